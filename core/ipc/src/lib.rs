@@ -16,7 +16,11 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::path::PathBuf;
+use std::{
+    io::{Error, ErrorKind},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
 
 use flume::{unbounded, Receiver, Sender};
 use hearth_schema::protocol::CapOperation;
@@ -25,38 +29,72 @@ use tokio::{
     net::UnixStream,
 };
 
-/// Returns the path of the Hearth IPC socket.
+/// Returns the candidate paths of the Hearth IPC socket in priority order.
+///
+/// If an instance name is given, either through `instance` or the
+/// HEARTH_INSTANCE environment variable, the only candidate is
+/// "$XDG_RUNTIME_DIR/hearth-<instance>.sock", so that a missing instance is
+/// never mistaken for another daemon.
+///
+/// Otherwise, the candidates are:
+/// 1. The HEARTH_SOCK environment variable, if set.
+/// 2. The legacy "$XDG_RUNTIME_DIR/hearth.sock".
 ///
-/// If the HEARTH_SOCK environment variable is set, then that is used for the
-/// path. Otherwise, "$XDG_RUNTIME_DIR/hearth.sock" is used. If XDG_RUNTIME_DIR
-/// is not set, then this function returns `None`.
-pub fn get_socket_path() -> Option<PathBuf> {
+/// Candidates that depend on XDG_RUNTIME_DIR are omitted if it is not set.
+pub fn get_socket_paths(instance: Option<&str>) -> Vec<PathBuf> {
+    let instance = instance
+        .map(ToString::to_string)
+        .or_else(|| std::env::var("HEARTH_INSTANCE").ok())
+        .filter(|instance| !instance.is_empty());
+
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from);
+
+    if let Some(instance) = instance {
+        return runtime_dir
+            .map(|dir| dir.join(format!("hearth-{}.sock", instance)))
+            .into_iter()
+            .collect();
+    }
+
+    let mut paths = Vec::new();
+
     if let Ok(path) = std::env::var("HEARTH_SOCK") {
-        match path.clone().try_into() {
-            Ok(path) => return Some(path),
-            Err(err) => {
-                tracing::error!("Failed to cast HEARTH_SOCK ({}) to path: {:?}", path, err);
-            }
-        }
+        paths.push(PathBuf::from(path));
     }
 
-    if let Ok(path) = std::env::var("XDG_RUNTIME_DIR") {
-        match TryInto::<PathBuf>::try_into(path.clone()) {
-            Ok(path) => {
-                let path = path.join("hearth.sock");
-                return Some(path);
-            }
-            Err(err) => {
-                tracing::error!(
-                    "Failed to cast XDG_RUNTIME_DIR ({}) to path: {:?}",
-                    path,
-                    err
-                );
-            }
-        }
+    if let Some(runtime_dir) = runtime_dir {
+        paths.push(runtime_dir.join("hearth.sock"));
     }
 
-    None
+    paths
+}
+
+/// Returns the path of the Hearth IPC socket for the given instance.
+///
+/// This is the highest-priority candidate of [get_socket_paths], and is where
+/// daemons should bind their listener. Returns `None` if there are no
+/// candidates.
+pub fn get_socket_path(instance: Option<&str>) -> Option<PathBuf> {
+    get_socket_paths(instance).into_iter().next()
+}
+
+/// Fails if the socket at the given path is owned by a different user.
+pub fn check_socket_owner(path: &Path) -> std::io::Result<()> {
+    let owner = std::fs::metadata(path)?.uid();
+
+    // SAFETY: getuid() is always successful and has no side effects
+    let uid = unsafe { libc::getuid() };
+
+    if owner != uid {
+        let msg = format!(
+            "Socket {:?} is owned by UID {}, not the current user ({})",
+            path, owner, uid
+        );
+
+        return Err(Error::new(ErrorKind::PermissionDenied, msg));
+    }
+
+    Ok(())
 }
 
 pub struct Connection {
@@ -107,20 +145,35 @@ impl Connection {
 }
 
 /// Connects to the Hearth daemon and returns a [Connection].
-pub async fn connect() -> std::io::Result<Connection> {
-    use std::io::{Error, ErrorKind};
-
-    let sock_path = match get_socket_path() {
-        Some(p) => p,
-        None => {
-            let kind = ErrorKind::NotFound;
-            let msg = "Failed to find a socket path";
-            tracing::error!(msg);
-            return Err(Error::new(kind, msg));
+///
+/// Tries each path from [get_socket_paths] in order and returns the first
+/// successful connection.
+pub async fn connect(instance: Option<&str>) -> std::io::Result<Connection> {
+    let mut last_err = None;
+
+    for path in get_socket_paths(instance) {
+        match connect_to(&path).await {
+            Ok(conn) => return Ok(conn),
+            Err(err) => {
+                tracing::debug!("Failed to connect to {:?}: {}", path, err);
+                last_err = Some(err);
+            }
         }
-    };
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        let msg = "Failed to find a socket path";
+        tracing::error!(msg);
+        Error::new(ErrorKind::NotFound, msg)
+    }))
+}
 
-    let stream = UnixStream::connect(&sock_path).await?;
+/// Connects to the Hearth daemon at a specific socket path.
+///
+/// Refuses to connect to sockets owned by a different user.
+pub async fn connect_to(path: &Path) -> std::io::Result<Connection> {
+    check_socket_owner(path)?;
+    let stream = UnixStream::connect(path).await?;
     let (rx, tx) = stream.into_split();
     Ok(Connection::new(rx, tx))
 }
@@ -17,10 +17,10 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use proc_macro2::{Literal, Span, TokenStream};
-use quote::quote;
+use quote::{quote, ToTokens};
 use syn::{
-    parse_macro_input, AttributeArgs, FnArg, Ident, ImplItem, ImplItemMethod, Meta, MetaNameValue,
-    NestedMeta, Pat, PatIdent, Type,
+    parse_macro_input, AttributeArgs, FnArg, GenericArgument, Ident, ImplItem, ImplItemMethod, Lit,
+    Meta, MetaNameValue, NestedMeta, Pat, PatIdent, Path, PathArguments, ReturnType, Type,
 };
 
 #[proc_macro_attribute]
@@ -34,27 +34,38 @@ pub fn impl_wasm_linker(
     let fn_items = impl_item.items;
     let impl_type = impl_item.self_ty;
 
-    let module = args
-        .into_iter()
-        .next()
-        .expect("Expected one attribute argument");
-    let module = match module {
-        NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => {
-            let path = path.get_ident().expect("Argument key must be ident");
-            assert_eq!(
-                path.to_string(),
-                "module",
-                "Only supported argument is 'module'"
-            );
-            lit
+    let mut module = None;
+    let mut signatures = None;
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(MetaNameValue { path, lit, .. })) => {
+                let key = path.get_ident().expect("Argument key must be ident");
+                match key.to_string().as_str() {
+                    "module" => module = Some(lit),
+                    "signatures" => signatures = Some(get_signatures_path(lit)),
+                    _ => panic!("Supported arguments are 'module' and 'signatures'"),
+                }
+            }
+            _ => panic!("Set the module with 'module = \"your module\"'"),
         }
-        _ => panic!("Set only the module with 'module = \"your module\""),
+    }
+
+    // ABIs with a shared definition take their module name from it
+    let module = match (module, signatures.as_ref()) {
+        (Some(module), None) => quote! { #module },
+        (None, Some(signatures)) => quote! { <Self as #signatures>::MODULE },
+        _ => panic!("Set exactly one of 'module' or 'signatures'"),
     };
 
     let mut items_within_impl = vec![];
     let mut link_wrapped_fns = vec![];
     let mut wasm_linker_fns = vec![];
+    let mut signature_stubs = vec![];
     for fn_item in fn_items {
+        if let ImplItem::Method(fn_method) = &fn_item {
+            signature_stubs.push(generate_signature_stub(fn_method));
+        }
+
         items_within_impl.push(quote! {
             #fn_item
         });
@@ -66,6 +77,16 @@ pub fn impl_wasm_linker(
         );
     }
 
+    // implementing the signatures trait fails to compile if any function is
+    // missing, extra, or has mismatched Wasm-level types
+    let signatures_impl = signatures.map(|signatures| {
+        quote! {
+            impl #signatures for #impl_type {
+                #(#signature_stubs)*
+            }
+        }
+    });
+
     quote! {
         impl #impl_type {
             const MODULE: &'static str = #module;
@@ -78,9 +99,77 @@ pub fn impl_wasm_linker(
                 #(#wasm_linker_fns)*
             }
         }
+        #signatures_impl
     }
     .into()
 }
+fn get_signatures_path(lit: Lit) -> Path {
+    match lit {
+        Lit::Str(str) => str.parse().expect("'signatures' must be a trait path"),
+        _ => panic!("Set the signatures with 'signatures = \"YourSignatures\"'"),
+    }
+}
+/// Generates a function with the Wasm-level signature of an ABI function:
+/// without the receiver or guest memory, and returning the result's value.
+fn generate_signature_stub(fn_method: &ImplItemMethod) -> TokenStream {
+    let fn_name = get_fn_name(fn_method);
+    let arg_types = remove_guest_memory_if_exists(get_fn_args(fn_method))
+        .into_iter()
+        .map(|arg| match arg {
+            FnArg::Receiver(_) => panic!(),
+            FnArg::Typed(typed) => typed.ty,
+        });
+
+    let return_type = match &fn_method.sig.output {
+        ReturnType::Default => None,
+        _ if returns_abi_result(fn_method) => Some(syn::parse_quote!(i32)),
+        ReturnType::Type(_, ty) => match get_result_value_type(ty) {
+            Type::Tuple(tuple) if tuple.elems.is_empty() => None,
+            ty => Some(ty),
+        },
+    };
+
+    let return_type = return_type.map(|ty| quote! { -> #ty });
+    quote! {
+        fn #fn_name(#(_: #arg_types),*) #return_type {
+            unreachable!("ABI signature stubs are never called")
+        }
+    }
+}
+fn get_result_value_type(ty: &Type) -> Type {
+    if let Type::Path(path) = ty {
+        if let Some(segment) = path.path.segments.last() {
+            if segment.ident == "Result" {
+                if let PathArguments::AngleBracketed(args) = &segment.arguments {
+                    if let Some(GenericArgument::Type(value)) = args.args.first() {
+                        return value.clone();
+                    }
+                }
+            }
+        }
+    }
+
+    ty.clone()
+}
+
+/// Tests if a function returns a recoverable `AbiResult` inside of its
+/// trapping `Result`. The recoverable result is encoded into an `i32`.
+fn returns_abi_result(fn_method: &ImplItemMethod) -> bool {
+    let ReturnType::Type(_, ty) = &fn_method.sig.output else {
+        return false;
+    };
+
+    match get_result_value_type(ty) {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident == "AbiResult")
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
 fn handle_fn_item(
     link_wrapped_fns: &mut Vec<TokenStream>,
     wasm_linker_fns: &mut Vec<TokenStream>,
@@ -116,19 +205,26 @@ fn generate_internal_function(fn_method: &ImplItemMethod, impl_type: &Ident) ->
     let fn_name = get_fn_name(fn_method);
     let internal_args = get_internal_args(fn_method);
     let internal_parameters = get_internal_parameters(fn_method);
-    let return_type = fn_method.sig.output.clone();
+    let (return_type, encode) = if returns_abi_result(fn_method) {
+        let return_type = quote! { -> Result<i32> };
+        let encode = quote! { .map(EncodeAbiResult::encode) };
+        (return_type, encode)
+    } else {
+        (fn_method.sig.output.to_token_stream(), quote! {})
+    };
+
     if is_async(fn_method) {
         quote! {
             async fn #fn_name <T: GetAbi<#impl_type> + Send>(#internal_args) #return_type {
                 let this = caller.data_mut().get_abi()?;
-                this.#fn_name(#internal_parameters).await
+                this.#fn_name(#internal_parameters).await #encode
             }
         }
     } else {
         quote! {
             fn #fn_name <T: GetAbi<#impl_type> + Send>(#internal_args) #return_type {
                 let this = caller.data_mut().get_abi()?;
-                this.#fn_name(#internal_parameters)
+                this.#fn_name(#internal_parameters) #encode
             }
         }
     }
@@ -23,10 +23,14 @@ use std::sync::Arc;
 use crate::lump::LumpStoreImpl;
 use anyhow::{anyhow, Context, Result};
 use async_trait::async_trait;
+use bytes::Bytes;
 use hearth_schema::LumpId;
 use serde::Deserialize;
-use tokio::sync::{Mutex, RwLock};
-use tracing::{debug, error};
+use tokio::sync::{broadcast, Mutex, RwLock};
+use tracing::{debug, error, info};
+
+/// The number of reload notifications buffered for slow subscribers.
+const RELOAD_CHANNEL_CAPACITY: usize = 64;
 
 #[async_trait]
 pub trait AssetLoader: Send + Sync + 'static {
@@ -57,20 +61,80 @@ impl<T: JsonAssetLoader> AssetLoader for T {
     }
 }
 
+/// A notification that an asset has been reloaded from a new lump.
+pub struct AssetReload<T> {
+    /// The lump the asset was previously loaded from.
+    pub old: LumpId,
+
+    /// The lump the asset was reloaded from.
+    pub new: LumpId,
+
+    /// The reloaded asset.
+    pub asset: Arc<T>,
+}
+
+impl<T> Clone for AssetReload<T> {
+    fn clone(&self) -> Self {
+        Self {
+            old: self.old,
+            new: self.new,
+            asset: self.asset.clone(),
+        }
+    }
+}
+
 /// Loads and caches assets loaded from a loader.
 pub struct AssetPool<T: AssetLoader> {
     loader: Mutex<T>,
     assets: RwLock<HashMap<LumpId, Arc<T::Asset>>>,
+    reloads: broadcast::Sender<AssetReload<T::Asset>>,
 }
 
 impl<T: AssetLoader> AssetPool<T> {
     pub fn new(loader: T) -> Self {
+        let (reloads, _) = broadcast::channel(RELOAD_CHANNEL_CAPACITY);
+
         Self {
             loader: Mutex::new(loader),
             assets: Default::default(),
+            reloads,
         }
     }
 
+    /// Loads an asset from a new lump and notifies subscribers that it
+    /// replaces the asset loaded from `old`.
+    ///
+    /// Once the new asset has loaded, the asset loaded from `old` is evicted
+    /// so that repeated reloads don't pile up. Existing users of it keep it
+    /// alive, and failed reloads leave it cached.
+    async fn reload_asset(
+        &self,
+        store: &AssetStore,
+        old: &LumpId,
+        new: &LumpId,
+        data: &[u8],
+    ) -> Result<Arc<T::Asset>> {
+        let loader = self.loader.lock().await;
+        let asset = Arc::new(loader.load_asset(store, data).await?);
+        drop(loader);
+
+        let mut assets = self.assets.write().await;
+        assets.insert(*new, asset.to_owned());
+        if old != new {
+            assets.remove(old);
+        }
+        drop(assets);
+
+        // it's fine if nobody is subscribed
+        let _ = self.reloads.send(AssetReload {
+            old: *old,
+            new: *new,
+            asset: asset.to_owned(),
+        });
+
+        Ok(asset)
+    }
+
     async fn load_asset(
         &self,
         store: &AssetStore,
@@ -94,8 +158,51 @@ impl<T: AssetLoader> AssetPool<T> {
     }
 }
 
+/// Type-erased access to an [AssetPool].
+#[async_trait]
+trait DynAssetPool: Send + Sync + 'static {
+    fn as_any(&self) -> &(dyn Any + Send + Sync);
+
+    fn loader_name(&self) -> &'static str;
+
+    async fn is_loaded(&self, lump: &LumpId) -> bool;
+
+    async fn reload_dyn(
+        &self,
+        store: &AssetStore,
+        old: &LumpId,
+        new: &LumpId,
+        data: &[u8],
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl<T: AssetLoader> DynAssetPool for AssetPool<T> {
+    fn as_any(&self) -> &(dyn Any + Send + Sync) {
+        self
+    }
+
+    fn loader_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+
+    async fn is_loaded(&self, lump: &LumpId) -> bool {
+        self.assets.read().await.contains_key(lump)
+    }
+
+    async fn reload_dyn(
+        &self,
+        store: &AssetStore,
+        old: &LumpId,
+        new: &LumpId,
+        data: &[u8],
+    ) -> Result<()> {
+        self.reload_asset(store, old, new, data).await.map(|_| ())
+    }
+}
+
 pub struct AssetStore {
-    pools: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+    pools: HashMap<TypeId, Box<dyn DynAssetPool>>,
     lump_store: Arc<LumpStoreImpl>,
 }
 
@@ -125,19 +232,183 @@ impl AssetStore {
         self.pools.contains_key(&TypeId::of::<T>())
     }
 
-    pub async fn load_asset<T: AssetLoader>(&self, lump: &LumpId) -> Result<Arc<T::Asset>> {
+    fn get_pool<T: AssetLoader>(&self) -> Result<&AssetPool<T>> {
         let type_name = std::any::type_name::<T>();
         let type_id = TypeId::of::<T>();
         let pool = self
             .pools
             .get(&type_id)
             .ok_or_else(|| anyhow!("Could not find asset loader '{:?}", type_name))?;
-        let pool: &AssetPool<T> = pool.downcast_ref().unwrap();
-        let data = self
-            .lump_store
+        Ok(pool.as_any().downcast_ref().unwrap())
+    }
+
+    async fn get_data(&self, lump: &LumpId) -> Result<Bytes> {
+        self.lump_store
             .get_lump(lump)
             .await
-            .ok_or_else(|| anyhow!("Failed to get lump {}", lump))?;
+            .ok_or_else(|| anyhow!("Failed to get lump {}", lump))
+    }
+
+    pub async fn load_asset<T: AssetLoader>(&self, lump: &LumpId) -> Result<Arc<T::Asset>> {
+        let pool = self.get_pool::<T>()?;
+        let data = self.get_data(lump).await?;
         pool.load_asset(self, lump, &data).await
     }
+
+    /// Reloads an asset from a new lump, re-running its loader and notifying
+    /// the loader's subscribers (see [Self::subscribe]).
+    ///
+    /// On success, the asset loaded from `old` is evicted from the cache. On
+    /// failure, it stays live.
+    pub async fn reload<T: AssetLoader>(
+        &self,
+        old: &LumpId,
+        new: &LumpId,
+    ) -> Result<Arc<T::Asset>> {
+        let pool = self.get_pool::<T>()?;
+        let data = self.get_data(new).await?;
+        pool.reload_asset(self, old, new, &data).await
+    }
+
+    /// Reloads the assets of every loader that has loaded an asset from
+    /// `old`. Returns the number of assets that were reloaded.
+    ///
+    /// Failed reloads are logged and leave the old asset live.
+    pub async fn reload_all(&self, old: &LumpId, new: &LumpId) -> usize {
+        let data = match self.get_data(new).await {
+            Ok(data) => data,
+            Err(err) => {
+                error!("Failed to reload assets from {}: {:?}", old, err);
+                return 0;
+            }
+        };
+
+        let mut reloaded = 0;
+        for pool in self.pools.values() {
+            if !pool.is_loaded(old).await {
+                continue;
+            }
+
+            let name = pool.loader_name();
+            match pool.reload_dyn(self, old, new, &data).await {
+                Ok(()) => {
+                    info!("Reloaded {} asset {} as {}", name, old, new);
+                    reloaded += 1;
+                }
+                Err(err) => error!("Failed to reload {} asset {}: {:?}", name, old, err),
+            }
+        }
+
+        reloaded
+    }
+
+    /// Forgets every asset that a loader has loaded, so that each one is
+    /// loaded again the next time that it's used.
+    ///
+    /// Current users of the forgotten assets keep them alive.
+    pub async fn clear<T: AssetLoader>(&self) -> Result<()> {
+        self.get_pool::<T>()?.assets.write().await.clear();
+        Ok(())
+    }
+
+    /// Subscribes to notifications of reloaded assets from a loader.
+    pub fn subscribe<T: AssetLoader>(&self) -> Result<broadcast::Receiver<AssetReload<T::Asset>>> {
+        Ok(self.get_pool::<T>()?.reloads.subscribe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Loads UTF-8 strings, failing on invalid UTF-8.
+    struct TextLoader;
+
+    #[async_trait]
+    impl AssetLoader for TextLoader {
+        type Asset = String;
+
+        async fn load_asset(&self, _store: &AssetStore, data: &[u8]) -> Result<String> {
+            Ok(std::str::from_utf8(data)?.to_string())
+        }
+    }
+
+    fn setup() -> (AssetStore, Arc<LumpStoreImpl>) {
+        let lump_store = Arc::new(LumpStoreImpl::new());
+        let mut store = AssetStore::new(lump_store.clone());
+        store.add_loader(TextLoader);
+        (store, lump_store)
+    }
+
+    #[tokio::test]
+    async fn reload_notifies() {
+        let (store, lumps) = setup();
+        let old = lumps.add_lump(Bytes::from_static(b"old")).await;
+        let new = lumps.add_lump(Bytes::from_static(b"new")).await;
+        let mut reloads = store.subscribe::<TextLoader>().unwrap();
+
+        store.load_asset::<TextLoader>(&old).await.unwrap();
+        let asset = store.reload::<TextLoader>(&old, &new).await.unwrap();
+        assert_eq!(asset.as_str(), "new");
+
+        let reload = reloads.recv().await.unwrap();
+        assert_eq!(reload.old, old);
+        assert_eq!(reload.new, new);
+        assert_eq!(reload.asset.as_str(), "new");
+    }
+
+    #[tokio::test]
+    async fn failed_reload_keeps_old() {
+        let (store, lumps) = setup();
+        let old = lumps.add_lump(Bytes::from_static(b"old")).await;
+        let bad = lumps.add_lump(Bytes::from_static(&[0xff, 0xfe])).await;
+        let mut reloads = store.subscribe::<TextLoader>().unwrap();
+
+        let asset = store.load_asset::<TextLoader>(&old).await.unwrap();
+        assert!(store.reload::<TextLoader>(&old, &bad).await.is_err());
+        assert!(reloads.try_recv().is_err());
+
+        let cached = store.load_asset::<TextLoader>(&old).await.unwrap();
+        assert!(Arc::ptr_eq(&asset, &cached));
+    }
+
+    #[tokio::test]
+    async fn reload_evicts_old() {
+        let (store, lumps) = setup();
+        let old = lumps.add_lump(Bytes::from_static(b"old")).await;
+        let new = lumps.add_lump(Bytes::from_static(b"new")).await;
+
+        store.load_asset::<TextLoader>(&old).await.unwrap();
+        let reloaded = store.reload::<TextLoader>(&old, &new).await.unwrap();
+
+        // only the new asset is cached
+        assert_eq!(store.reload_all(&old, &new).await, 0);
+        let cached = store.load_asset::<TextLoader>(&new).await.unwrap();
+        assert!(Arc::ptr_eq(&reloaded, &cached));
+    }
+
+    #[tokio::test]
+    async fn reload_all_only_loaded() {
+        let (store, lumps) = setup();
+        let old = lumps.add_lump(Bytes::from_static(b"old")).await;
+        let new = lumps.add_lump(Bytes::from_static(b"new")).await;
+
+        assert_eq!(store.reload_all(&old, &new).await, 0);
+
+        store.load_asset::<TextLoader>(&old).await.unwrap();
+        assert_eq!(store.reload_all(&old, &new).await, 1);
+    }
+
+    #[tokio::test]
+    async fn cleared_assets_load_again() {
+        let (store, lumps) = setup();
+        let lump = lumps.add_lump(Bytes::from_static(b"text")).await;
+
+        let asset = store.load_asset::<TextLoader>(&lump).await.unwrap();
+        store.clear::<TextLoader>().await.unwrap();
+
+        let loaded = store.load_asset::<TextLoader>(&lump).await.unwrap();
+        assert!(!Arc::ptr_eq(&asset, &loaded));
+        assert_eq!(loaded.as_str(), "text");
+    }
 }
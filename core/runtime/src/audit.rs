@@ -0,0 +1,384 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Auditing of process spawns, kills, and service registrations.
+//!
+//! Every [ProcessFactory](crate::process::ProcessFactory) records into an
+//! [AuditLog], which keeps a bounded history of recent events, forwards new
+//! events to subscribers, and optionally appends every event to a JSONL
+//! file. [AuditService] exposes the log to guests.
+
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use flue::Table;
+use flume::{Receiver, Sender, TrySendError};
+use hearth_schema::audit::*;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tracing::{debug, error, info, warn};
+
+use crate::process::ProcessMetadata;
+use crate::process_log::now_millis;
+use crate::utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner};
+
+/// Configuration for the audit log.
+///
+/// Loaded from the `audit` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// How many recent events to keep in memory.
+    pub history: usize,
+
+    /// If set, every audit event is appended to this JSONL file.
+    pub file: Option<PathBuf>,
+
+    /// If true, the audit service is registered as [AUDIT_SERVICE] so that
+    /// any process with the registry can use it. Otherwise, only the init
+    /// system is given a capability to it.
+    pub public: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            history: 1024,
+            file: None,
+            public: false,
+        }
+    }
+}
+
+/// How many events a subscriber to the [AuditService] may fall behind by
+/// before it's dropped.
+pub const SUBSCRIBER_QUEUE_LEN: usize = 256;
+
+/// How many events the audit log file may fall behind by before it stops
+/// being written.
+pub const FILE_QUEUE_LEN: usize = 16384;
+
+/// A bounded log of recent audit events and the subscribers to new ones.
+pub struct AuditLog {
+    inner: Mutex<AuditLogInner>,
+}
+
+struct AuditLogInner {
+    events: VecDeque<AuditEvent>,
+    capacity: usize,
+    next_id: u64,
+    subscribers: Vec<(AuditFilter, Sender<AuditEvent>)>,
+}
+
+impl Default for AuditLog {
+    fn default() -> Self {
+        Self::new(AuditConfig::default().history)
+    }
+}
+
+impl AuditLog {
+    /// Creates an empty log that keeps up to `capacity` recent events.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(AuditLogInner {
+                events: VecDeque::with_capacity(capacity),
+                capacity,
+                next_id: 0,
+                subscribers: Vec::new(),
+            }),
+        }
+    }
+
+    /// Timestamps an event, adds it to the history, and forwards it to every
+    /// matching subscriber.
+    pub fn record(&self, kind: AuditEventKind) {
+        let mut inner = self.inner.lock();
+
+        let event = AuditEvent {
+            id: inner.next_id,
+            timestamp: now_millis(),
+            kind,
+        };
+
+        inner.next_id += 1;
+        debug!("audit: {:?}", event);
+
+        inner.subscribers.retain(|(filter, subscriber)| {
+            if !filter.matches(&event) {
+                return true;
+            }
+
+            match subscriber.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("Dropping an audit subscriber that fell behind");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            }
+        });
+
+        if inner.capacity == 0 {
+            return;
+        }
+
+        if inner.events.len() == inner.capacity {
+            inner.events.pop_front();
+        }
+
+        inner.events.push_back(event);
+    }
+
+    /// Gets the buffered events matching a filter, oldest first.
+    ///
+    /// If `limit` is set, only the newest `limit` matching events are
+    /// returned.
+    pub fn query(&self, filter: &AuditFilter, limit: Option<usize>) -> Vec<AuditEvent> {
+        let inner = self.inner.lock();
+        let matching = inner.events.iter().filter(|event| filter.matches(event));
+        let mut events: Vec<_> = match limit {
+            Some(limit) => matching.rev().take(limit).cloned().collect(),
+            None => matching.rev().cloned().collect(),
+        };
+
+        events.reverse();
+        events
+    }
+
+    /// Returns a receiver for every event matching a filter from now on.
+    ///
+    /// The subscription is removed once the receiver is dropped, or once it
+    /// falls more than `capacity` events behind.
+    pub fn subscribe(&self, filter: AuditFilter, capacity: usize) -> Receiver<AuditEvent> {
+        let (tx, rx) = flume::bounded(capacity);
+        self.inner.lock().subscribers.push((filter, tx));
+        rx
+    }
+}
+
+/// Spawns a thread that appends every new event in an audit log to a JSONL
+/// file.
+///
+/// Returns an error if the file can't be opened. If the thread falls more
+/// than [FILE_QUEUE_LEN] events behind, it stops.
+pub fn spawn_file_sink(path: PathBuf, log: &AuditLog) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let mut file = BufWriter::new(file);
+    info!("Writing audit log to {:?}", path);
+
+    let events = log.subscribe(AuditFilter::default(), FILE_QUEUE_LEN);
+    std::thread::spawn(move || {
+        while let Ok(event) = events.recv() {
+            let mut line = serde_json::to_vec(&event).unwrap();
+            line.push(b'\n');
+
+            if let Err(err) = file.write_all(&line) {
+                error!("Failed to write audit log file: {:?}", err);
+                return;
+            }
+
+            // only flush when caught up so bursts of events are batched
+            if events.is_empty() {
+                if let Err(err) = file.flush() {
+                    error!("Failed to flush audit log file: {:?}", err);
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// A service that lets privileged processes subscribe to and query the
+/// audit log.
+pub struct AuditService;
+
+#[async_trait]
+impl RequestResponseProcess for AuditService {
+    type Request = AuditRequest;
+    type Response = AuditResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, AuditRequest>,
+    ) -> ResponseInfo<'a, AuditResponse> {
+        let log = request.runtime.process_factory.audit_log();
+
+        match &request.data {
+            AuditRequest::Query { filter, limit } => {
+                let limit = limit.map(|limit| limit as usize);
+                Ok(AuditSuccess::Events(log.query(filter, limit))).into()
+            }
+            AuditRequest::Subscribe(filter) => {
+                let Some(subscriber) = request.cap_args.first() else {
+                    return AuditError::MissingCapability.into();
+                };
+
+                let subscriber = subscriber.to_owned();
+                let post = request.runtime.post.clone();
+                let events = log.subscribe(filter.clone(), SUBSCRIBER_QUEUE_LEN);
+
+                tokio::spawn(async move {
+                    let table = Table::new(post);
+                    let subscriber = table.import_owned(subscriber).unwrap();
+                    let subscriber = table.wrap_handle(subscriber).unwrap();
+
+                    while let Ok(event) = events.recv_async().await {
+                        let data = serde_json::to_vec(&event).unwrap();
+                        if let Err(err) = subscriber.send(&data, &[]).await {
+                            debug!("Audit subscriber error: {:?}", err);
+                            break;
+                        }
+                    }
+                });
+
+                Ok(AuditSuccess::Subscribed).into()
+            }
+        }
+    }
+}
+
+impl ServiceRunner for AuditService {
+    const NAME: &'static str = AUDIT_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = crate::utils::cargo_process_metadata!();
+        meta.description =
+            Some("Records process spawns, kills, and service registrations.".to_string());
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spawn_event(pid: u64, requester: AuditActor) -> AuditEventKind {
+        AuditEventKind::Spawn {
+            pid,
+            name: None,
+            requester,
+            lump: None,
+        }
+    }
+
+    fn peer(user: &str) -> AuditActor {
+        AuditActor::Peer {
+            user: user.to_string(),
+            address: "127.0.0.1:1234".to_string(),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let log = AuditLog::new(2);
+        for pid in 0..3 {
+            log.record(AuditEventKind::Exit { pid });
+        }
+
+        let ids: Vec<_> = log
+            .query(&AuditFilter::default(), None)
+            .into_iter()
+            .map(|event| event.id)
+            .collect();
+
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[test]
+    fn query_by_peer() {
+        let log = AuditLog::new(16);
+        log.record(spawn_event(0, AuditActor::Host));
+        log.record(spawn_event(1, peer("alice")));
+        log.record(spawn_event(2, peer("bob")));
+
+        let filter = AuditFilter {
+            peer: Some("alice".to_string()),
+            ..Default::default()
+        };
+
+        let events = log.query(&filter, None);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, spawn_event(1, peer("alice")));
+    }
+
+    #[test]
+    fn query_by_time_and_limit() {
+        let log = AuditLog::new(16);
+        for pid in 0..4 {
+            log.record(AuditEventKind::Exit { pid });
+        }
+
+        let events = log.query(&AuditFilter::default(), None);
+        let since = events[0].timestamp;
+
+        let filter = AuditFilter {
+            since: Some(since),
+            until: Some(since),
+            ..Default::default()
+        };
+
+        assert!(log.query(&filter, None).is_empty());
+
+        let newest = log.query(&AuditFilter::default(), Some(2));
+        let ids: Vec<_> = newest.into_iter().map(|event| event.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+    }
+
+    #[test]
+    fn subscribers_are_filtered() {
+        let log = AuditLog::new(16);
+        let filter = AuditFilter {
+            peer: Some("alice".to_string()),
+            ..Default::default()
+        };
+
+        let events = log.subscribe(filter, 16);
+        log.record(spawn_event(0, peer("bob")));
+        log.record(spawn_event(1, peer("alice")));
+
+        assert_eq!(events.try_recv().unwrap().id, 1);
+        assert!(events.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropped_subscribers_are_removed() {
+        let log = AuditLog::new(16);
+        drop(log.subscribe(AuditFilter::default(), 16));
+        log.record(AuditEventKind::Exit { pid: 0 });
+        assert!(log.inner.lock().subscribers.is_empty());
+    }
+
+    #[test]
+    fn lagging_subscribers_are_dropped() {
+        let log = AuditLog::new(16);
+        let events = log.subscribe(AuditFilter::default(), 2);
+        for pid in 0..3 {
+            log.record(AuditEventKind::Exit { pid });
+        }
+
+        assert!(log.inner.lock().subscribers.is_empty());
+        assert_eq!(events.try_recv().unwrap().id, 0);
+        assert_eq!(events.try_recv().unwrap().id, 1);
+        assert!(events.try_recv().is_err());
+    }
+}
@@ -18,33 +18,78 @@
 
 //! Implements peer-to-peer capability exchange code for remote processes.
 //!
-//! Currently unimplemented.
+//! Each capability that the other side of a connection declares is imported
+//! as a local mailbox. Messages sent to that mailbox are forwarded over the
+//! connection. When the other side revokes the capability, or the connection
+//! is lost, the mailbox is closed, so the capability goes down for every
+//! local process holding it and monitors receive down signals.
 
-// TODO get rid of this when connections are implemented
-#![allow(unused)]
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
-use std::{collections::HashMap, sync::Arc};
-
-use flue::{CapabilityRef, Mailbox, MailboxGroup, OwnedCapability, PostOffice, Table};
+use anyhow::Context;
+use flue::{
+    CapabilityRef, Mailbox, MailboxGroup, OwnedCapability, OwnedTableSignal, Permissions,
+    PostOffice, Table,
+};
 use flume::{Receiver, Sender};
-use hearth_schema::protocol::{CapOperation, LocalCapOperation, RemoteCapOperation};
+use hearth_schema::audit::{AuditActor, AuditEventKind};
+use hearth_schema::protocol::{CapOperation, LocalCapOperation, RemoteCapOperation, UnlinkReason};
 use ouroboros::self_referencing;
 use parking_lot::Mutex;
-use tokio::sync::oneshot;
+use tokio::{sync::oneshot, task::JoinHandle};
+use tracing::{debug, warn};
+
+use crate::audit::AuditLog;
+use crate::utils::{shutdown_with, ShutdownOutcome};
 
 pub type RootCapSender = oneshot::Sender<OwnedCapability>;
 
+/// A local mailbox standing in for a capability on the other side of a
+/// connection.
 #[self_referencing]
-struct Import<'a> {
-    conn: &'a Connection,
+struct Import {
+    conn: Arc<Connection>,
 
-    group: MailboxGroup<'a>,
+    #[borrows(conn)]
+    #[covariant]
+    group: MailboxGroup<'this>,
 
     #[borrows(group)]
     #[covariant]
     mb: Mailbox<'this>,
 }
 
+impl Import {
+    /// Forwards every message sent to this import over the connection until
+    /// it's killed or revoked.
+    async fn forward(self, id: u32) {
+        let conn = self.borrow_conn().clone();
+
+        loop {
+            match self.borrow_mb().recv_owned().await {
+                Some(OwnedTableSignal::Message { data, caps }) => {
+                    let caps = caps.iter().map(|cap| conn.export(cap.to_owned())).collect();
+                    let data = data.to_vec();
+                    conn.send_remote_op(RemoteCapOperation::Send { id, data, caps });
+                }
+                // imports don't monitor anything
+                Some(OwnedTableSignal::Down { .. }) => {}
+                None => {
+                    // a local process killed this import
+                    conn.send_remote_op(RemoteCapOperation::Kill { id });
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// A running [Import] and a capability to it.
+struct ImportHandle {
+    cap: OwnedCapability,
+    task: JoinHandle<()>,
+}
+
 struct Export<'a> {
     cap: CapabilityRef<'a>,
     revoked: bool,
@@ -56,12 +101,19 @@ struct Exports<'a> {
 }
 
 /// A data structure implementing the capability exchange protocol.
-///
-/// Currently unimplemented.
 #[self_referencing]
 pub struct Connection {
     table: Table,
 
+    op_tx: Sender<CapOperation>,
+
+    imports: Mutex<HashMap<u32, ImportHandle>>,
+
+    on_root_cap: Mutex<Option<RootCapSender>>,
+
+    /// Where kills of exports are audited and who they're attributed to.
+    audit: Mutex<Option<(Arc<AuditLog>, AuditActor)>>,
+
     #[borrows(table)]
     #[not_covariant]
     exports: Exports<'this>,
@@ -73,19 +125,41 @@ impl Connection {
     /// `op_rx` is the channel receiver used to receive incoming [CapOperation]
     /// messages on this connection. `op_tx` is the channel sender used to send
     /// outgoing [CapOperation]s.
+    ///
+    /// Once `op_rx` is closed, every capability imported through this
+    /// connection is revoked.
     pub fn begin(
         post: Arc<PostOffice>,
         op_rx: Receiver<CapOperation>,
         op_tx: Sender<CapOperation>,
         on_root_cap: Option<RootCapSender>,
     ) -> Arc<Self> {
-        let conn = Connection::new(Table::new(post), |table| Exports {
-            table,
-            inner: Default::default(),
-        });
+        let conn = Connection::new(
+            Table::new(post),
+            op_tx,
+            Default::default(),
+            Mutex::new(on_root_cap),
+            Mutex::new(None),
+            |table| Exports {
+                table,
+                inner: Default::default(),
+            },
+        );
 
         let conn = Arc::new(conn);
 
+        tokio::spawn({
+            let conn = conn.clone();
+            async move {
+                while let Ok(op) = op_rx.recv_async().await {
+                    conn.on_op(op).await;
+                }
+
+                debug!("Connection closed; revoking its capabilities");
+                conn.close();
+            }
+        });
+
         conn
     }
 
@@ -116,29 +190,106 @@ impl Connection {
         })
     }
 
+    /// Records every kill of an export by the other side in `log`, attributed
+    /// to `requester`.
+    ///
+    /// Call this before exporting anything so that no kill is missed.
+    pub fn audit_kills(&self, log: Arc<AuditLog>, requester: AuditActor) {
+        *self.borrow_audit().lock() = Some((log, requester));
+    }
+
     /// Exports a capability as this side of the connection's root cap.
     pub fn export_root(&self, cap: OwnedCapability) {
         let id = self.export(cap);
         self.send_local_op(LocalCapOperation::SetRootCap { id });
     }
 
-    pub fn on_op(self: &Arc<Self>, op: CapOperation) {
+    /// Revokes every imported capability and every exported one.
+    ///
+    /// Exports are revoked with [UnlinkReason::ConnectionLost] so that the
+    /// other side, if it's still listening, drops its imports of them.
+    ///
+    /// Called automatically once the connection is lost.
+    pub fn close(&self) {
+        let reason = UnlinkReason::ConnectionLost;
+        let imports: Vec<_> = self.borrow_imports().lock().drain().collect();
+        for (id, import) in imports {
+            debug!("Revoking import {}: {:?}", id, reason);
+            import.task.abort();
+        }
+
+        let exports: Vec<_> = self.with_exports(|exports| {
+            let mut exports = exports.inner.lock();
+            let live = exports
+                .iter()
+                .filter(|(_, export)| !export.revoked)
+                .map(|(id, _)| *id)
+                .collect();
+
+            exports.clear();
+            live
+        });
+
+        for id in exports {
+            self.send_local_op(LocalCapOperation::RevokeCap { id, reason });
+        }
+    }
+
+    pub async fn on_op(self: &Arc<Self>, op: CapOperation) {
         match op {
             CapOperation::Local(op) => self.on_local_op(op),
-            CapOperation::Remote(op) => self.on_remote_op(op),
+            CapOperation::Remote(op) => self.on_remote_op(op).await,
         }
     }
 
     fn on_local_op(self: &Arc<Self>, op: LocalCapOperation) {
         use LocalCapOperation::*;
         match op {
-            DeclareCap { id, perms } => todo!(),
-            RevokeCap { id, reason } => todo!(),
-            SetRootCap { id } => todo!(),
+            DeclareCap { id, perms } => {
+                let import = Import::new(
+                    self.clone(),
+                    |conn| MailboxGroup::new(conn.borrow_table()),
+                    |group| group.create_mailbox().unwrap(),
+                );
+
+                let perms = Permissions::from_bits_truncate(perms.bits());
+                let cap = import.borrow_mb().export(perms).unwrap().to_owned();
+                let task = tokio::spawn(import.forward(id));
+                let handle = ImportHandle { cap, task };
+
+                if let Some(old) = self.borrow_imports().lock().insert(id, handle) {
+                    warn!("Remote capability {} was declared twice", id);
+                    old.task.abort();
+                }
+            }
+            RevokeCap { id, reason } => {
+                match self.borrow_imports().lock().remove(&id) {
+                    Some(import) => {
+                        debug!("Revoking import {}: {:?}", id, reason);
+                        import.task.abort();
+                    }
+                    None => warn!("Remote revoked undeclared capability {}", id),
+                }
+
+                self.send_remote_op(RemoteCapOperation::AcknowledgeRevocation { id });
+            }
+            SetRootCap { id } => {
+                let Some(cap) = self.get_import(id) else {
+                    warn!("Remote set undeclared capability {} as its root", id);
+                    return;
+                };
+
+                match self.borrow_on_root_cap().lock().take() {
+                    Some(tx) => {
+                        let _ = tx.send(cap);
+                    }
+                    None => debug!("Ignoring root cap from remote"),
+                }
+            }
         }
     }
 
-    fn on_remote_op(&self, op: RemoteCapOperation) {
+    async fn on_remote_op(self: &Arc<Self>, op: RemoteCapOperation) {
         use RemoteCapOperation::*;
         match op {
             AcknowledgeRevocation { id } => {
@@ -151,14 +302,129 @@ impl Connection {
                     }
                 });
             }
-            FreeCap { id } => todo!(),
-            Send { id, data, caps } => self.with_export(id, |cap| {}),
+            FreeCap { id } => {
+                let revoked = self.with_exports(|exports| {
+                    let mut exports = exports.inner.lock();
+                    match exports.get_mut(&id) {
+                        Some(export) if !export.revoked => {
+                            export.revoked = true;
+                            true
+                        }
+                        _ => false,
+                    }
+                });
+
+                if revoked {
+                    let reason = UnlinkReason::AccessRevoked;
+                    self.send_local_op(LocalCapOperation::RevokeCap { id, reason });
+                }
+            }
+            Send { id, data, caps } => {
+                let Some(target) = self.get_export(id) else {
+                    return;
+                };
+
+                // dropping only the unknown caps would shift the positions of
+                // the rest, so refuse the whole message instead
+                let Some(caps) = caps
+                    .iter()
+                    .map(|cap| self.get_import(*cap))
+                    .collect::<Option<Vec<_>>>()
+                else {
+                    debug!("Dropping message to export {} with undeclared caps", id);
+                    return;
+                };
+
+                let table = self.borrow_table();
+                let import = |cap: OwnedCapability| {
+                    let handle = table.import_owned(cap).unwrap();
+                    table.wrap_handle(handle).unwrap()
+                };
+
+                let target = import(target);
+                let caps: Vec<_> = caps.into_iter().map(import).collect();
+
+                let caps: Vec<_> = caps.iter().collect();
+                if let Err(err) = target.send(&data, &caps).await {
+                    debug!("Failed to send to export {}: {:?}", id, err);
+                }
+            }
             Kill { id } => self.with_export(id, |cap| {
-                let _ = cap.kill();
+                if cap.kill().is_ok() {
+                    self.audit_kill();
+                }
             }),
+            Shutdown { id, grace_ms } => {
+                let Some(target) = self.get_export(id) else {
+                    return;
+                };
+
+                if !target.get_permissions().contains(Permissions::KILL) {
+                    return;
+                }
+
+                // don't hold up other operations for the grace period
+                let grace = Duration::from_millis(grace_ms);
+                tokio::spawn(self.clone().shutdown_export(target, grace));
+            }
         }
     }
 
+    /// Asks an exported process to shut down, killing it if it hasn't exited
+    /// once `grace` has passed or if it can't be asked.
+    async fn shutdown_export(self: Arc<Self>, target: OwnedCapability, grace: Duration) {
+        let table = self.borrow_table();
+        let handle = table.import_owned(target).unwrap();
+        let target = table.wrap_handle(handle).unwrap();
+        let group = MailboxGroup::new(table);
+
+        let result = async {
+            let mailbox = group
+                .create_mailbox()
+                .context("failed to create monitoring mailbox")?;
+
+            shutdown_with(&mailbox, &target, grace).await
+        }
+        .await;
+
+        match result {
+            Ok(ShutdownOutcome::Exited) => debug!("Shut down export"),
+            Ok(ShutdownOutcome::Killed) => {
+                debug!("Killed export after its grace period");
+                self.audit_kill();
+            }
+            Err(err) => {
+                debug!("Killing export that failed to shut down: {:?}", err);
+                if target.kill().is_ok() {
+                    self.audit_kill();
+                }
+            }
+        }
+    }
+
+    /// Records a kill of an export, if this connection's kills are audited.
+    fn audit_kill(&self) {
+        if let Some((log, requester)) = self.borrow_audit().lock().as_ref() {
+            log.record(AuditEventKind::Kill {
+                requester: requester.clone(),
+                target: None,
+            });
+        }
+    }
+
+    /// Gets a capability to a live import.
+    fn get_import(&self, id: u32) -> Option<OwnedCapability> {
+        let imports = self.borrow_imports().lock();
+        imports.get(&id).map(|import| import.cap.clone())
+    }
+
+    /// Gets an unrevoked export.
+    fn get_export(&self, id: u32) -> Option<OwnedCapability> {
+        let mut cap = None;
+        self.with_export(id, |export| cap = Some(export.to_owned()));
+        cap
+    }
+
     fn with_export(&self, id: u32, mut cb: impl FnMut(&CapabilityRef<'_>)) {
         self.with_exports(|exports| {
             let inner = exports.inner.lock();
@@ -170,7 +436,201 @@ impl Connection {
         });
     }
 
-    fn send_local_op(&self, op: LocalCapOperation) {}
+    fn send_local_op(&self, op: LocalCapOperation) {
+        let _ = self.borrow_op_tx().send(CapOperation::Local(op));
+    }
+
+    fn send_remote_op(&self, op: RemoteCapOperation) {
+        let _ = self.borrow_op_tx().send(CapOperation::Remote(op));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flue::TableSignal;
+    use hearth_schema::shutdown::ShutdownRequest;
+
+    use crate::process::ProcessMetadata;
+    use crate::testing::{TestRuntime, TestRuntimeBuilder};
+
+    /// Connects two connections through a relay. Aborting the relay drops
+    /// the transport between them.
+    fn loopback(
+        runtime: &TestRuntime,
+        root: OwnedCapability,
+    ) -> (Arc<Connection>, OwnedCapability, JoinHandle<()>) {
+        let post = runtime.runtime().post.clone();
+        let (a_tx, from_a) = flume::unbounded();
+        let (b_tx, from_b) = flume::unbounded();
+        let (to_a, a_rx) = flume::unbounded();
+        let (to_b, b_rx) = flume::unbounded();
+
+        runtime.block_on(async move {
+            let relay = tokio::spawn(async move {
+                loop {
+                    tokio::select! {
+                        Ok(op) = from_a.recv_async() => { let _ = to_b.send(op); }
+                        Ok(op) = from_b.recv_async() => { let _ = to_a.send(op); }
+                        else => break,
+                    }
+                }
+            });
+
+            let (root_tx, root_rx) = oneshot::channel();
+            let a = Connection::begin(post.clone(), a_rx, a_tx, None);
+            let b = Connection::begin(post, b_rx, b_tx, Some(root_tx));
+            a.export_root(root);
+            (b, root_rx.await.unwrap(), relay)
+        })
+    }
+
+    fn import<'a>(runtime: &'a TestRuntime, cap: OwnedCapability) -> CapabilityRef<'a> {
+        let table = runtime.process().borrow_table();
+        let handle = table.import_owned(cap).unwrap();
+        table.wrap_handle(handle).unwrap()
+    }
 
-    fn send_remote_op(&self, op: RemoteCapOperation) {}
+    #[test]
+    fn forwards_messages() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let service = runtime.mailbox();
+        let root = service.capability(Permissions::SEND).to_owned();
+        let (_conn, remote, _relay) = loopback(&runtime, root);
+
+        runtime.send(&import(&runtime, remote), &"hello", &[]);
+        let (data, _) = service.recv_json::<String>();
+        assert_eq!(data, "hello");
+    }
+
+    #[test]
+    fn connection_loss_revokes_imports() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let service = runtime.mailbox();
+        let root = service.capability(Permissions::SEND | Permissions::MONITOR);
+        let (_conn, remote, relay) = loopback(&runtime, root.to_owned());
+
+        let monitor = runtime.mailbox();
+        monitor.monitor(&import(&runtime, remote));
+        relay.abort();
+        monitor.recv_down();
+    }
+
+    #[test]
+    fn revoked_imports_go_down() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let (op_tx, op_rx) = flume::unbounded();
+        let (remote_tx, remote_rx) = flume::unbounded();
+        let (root_tx, root_rx) = oneshot::channel();
+
+        let post = runtime.runtime().post.clone();
+        let _conn = runtime
+            .block_on(async move { Connection::begin(post, op_rx, remote_tx, Some(root_tx)) });
+
+        let perms = hearth_schema::Permissions::SEND | hearth_schema::Permissions::MONITOR;
+        let ops = [
+            LocalCapOperation::DeclareCap { id: 0, perms },
+            LocalCapOperation::SetRootCap { id: 0 },
+        ];
+
+        for op in ops {
+            op_tx.send(CapOperation::Local(op)).unwrap();
+        }
+
+        let root = runtime.block_on(root_rx).unwrap();
+        let monitor = runtime.mailbox();
+        monitor.monitor(&import(&runtime, root));
+
+        let reason = UnlinkReason::AccessRevoked;
+        let op = LocalCapOperation::RevokeCap { id: 0, reason };
+        op_tx.send(CapOperation::Local(op)).unwrap();
+        monitor.recv_down();
+
+        let ack = RemoteCapOperation::AcknowledgeRevocation { id: 0 };
+        assert_eq!(remote_rx.try_recv(), Ok(CapOperation::Remote(ack)));
+    }
+
+    #[test]
+    fn rejects_messages_with_undeclared_caps() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let service = runtime.mailbox();
+        let (op_tx, op_rx) = flume::unbounded();
+        let (remote_tx, _remote_rx) = flume::unbounded();
+        let post = runtime.runtime().post.clone();
+        let conn = runtime.block_on(async move { Connection::begin(post, op_rx, remote_tx, None) });
+        let id = conn.export(service.capability(Permissions::SEND).to_owned());
+
+        let sends = [("rejected", vec![42]), ("accepted", vec![])];
+        for (text, caps) in sends {
+            let data = serde_json::to_vec(text).unwrap();
+            let op = RemoteCapOperation::Send { id, data, caps };
+            op_tx.send(CapOperation::Remote(op)).unwrap();
+        }
+
+        let (data, caps) = service.recv_json::<String>();
+        assert_eq!(data, "accepted");
+        assert!(caps.is_empty());
+    }
+
+    #[test]
+    fn connection_loss_revokes_exports() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let service = runtime.mailbox();
+        let (op_tx, op_rx) = flume::unbounded();
+        let (remote_tx, remote_rx) = flume::unbounded();
+        let post = runtime.runtime().post.clone();
+        let conn = runtime.block_on(async move { Connection::begin(post, op_rx, remote_tx, None) });
+        let id = conn.export(service.capability(Permissions::SEND).to_owned());
+        drop(op_tx);
+
+        let revoke = runtime.block_on(async move {
+            // skip the declaration of the export
+            remote_rx.recv_async().await.unwrap();
+            remote_rx.recv_async().await.unwrap()
+        });
+
+        let reason = UnlinkReason::ConnectionLost;
+        let expected = LocalCapOperation::RevokeCap { id, reason };
+        assert_eq!(revoke, CapOperation::Local(expected));
+    }
+
+    #[test]
+    fn shutdown_waits_for_grace_period() {
+        const GRACE: Duration = Duration::from_secs(5);
+
+        let runtime = TestRuntimeBuilder::new().build();
+        let factory = &runtime.runtime().process_factory;
+        let process = factory.spawn(ProcessMetadata::default());
+        let target = process
+            .borrow_parent()
+            .export_to(Permissions::all(), runtime.process().borrow_table())
+            .unwrap();
+
+        let monitor = runtime.mailbox();
+        monitor.monitor(&target);
+
+        let (op_tx, op_rx) = flume::unbounded();
+        let (remote_tx, _remote_rx) = flume::unbounded();
+        let post = runtime.runtime().post.clone();
+        let conn = runtime.block_on(async move { Connection::begin(post, op_rx, remote_tx, None) });
+        let id = conn.export(target.to_owned());
+
+        let start = runtime.block_on(async { tokio::time::Instant::now() });
+        let grace_ms = GRACE.as_millis() as u64;
+        let op = RemoteCapOperation::Shutdown { id, grace_ms };
+        op_tx.send(CapOperation::Remote(op)).unwrap();
+
+        let request = runtime.block_on(process.borrow_parent().recv(|signal| match signal {
+            TableSignal::Message { data, .. } => ShutdownRequest::decode(data),
+            TableSignal::Down { .. } => None,
+        }));
+
+        assert_eq!(request, Some(Some(ShutdownRequest { grace: GRACE })));
+
+        // the process ignores the request, so it's killed once grace passes
+        monitor.recv_down();
+        let elapsed = runtime.block_on(async move { start.elapsed() });
+        assert!(elapsed >= GRACE, "killed after {:?}", elapsed);
+    }
 }
@@ -0,0 +1,445 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::ops::Deref;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use flue::{CapabilityHandle, OwnedCapability, Permissions, PostOffice, Table};
+use hearth_schema::audit::{AuditActor, AuditEventKind};
+use hearth_schema::group::*;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tracing::{debug, warn};
+
+use crate::audit::AuditLog;
+use crate::process::{Process, ProcessId, ProcessMetadata};
+use crate::registry::{RegistryView, ViewPolicy, ViewStore};
+use crate::utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner};
+
+/// Config for process groups, loaded from the `process_groups` table.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProcessGroupsConfig {
+    /// If true, the [ProcessGroupsService] is registered as
+    /// [PROCESS_GROUPS_SERVICE] so that any process with the registry can
+    /// list and kill groups.
+    pub service: bool,
+}
+
+/// A running member of a [ProcessGroup].
+struct Member {
+    pid: ProcessId,
+    name: Option<String>,
+
+    /// A capability to the member's parent mailbox with the kill permission,
+    /// in the group's table.
+    cap: CapabilityHandle,
+}
+
+/// A set of processes that are managed together, such as the processes of
+/// one application.
+///
+/// Processes spawned on behalf of a member join its group too, so a group
+/// follows an application as it spawns helpers. A group is owned by a
+/// [GroupHandle], and tearing it down kills every member.
+pub struct ProcessGroup {
+    id: GroupId,
+    parent: Option<GroupId>,
+    table: Table,
+    members: Mutex<Vec<Member>>,
+    registry: ViewStore,
+    torn_down: AtomicBool,
+
+    /// The audit log that kills of members are recorded in.
+    audit: Arc<AuditLog>,
+}
+
+impl ProcessGroup {
+    pub(crate) fn new(
+        id: GroupId,
+        parent: Option<GroupId>,
+        post: Arc<PostOffice>,
+        audit: Arc<AuditLog>,
+    ) -> Self {
+        Self {
+            id,
+            parent,
+            table: Table::new(post),
+            members: Default::default(),
+            registry: Default::default(),
+            torn_down: AtomicBool::new(false),
+            audit,
+        }
+    }
+
+    /// Gets this group's ID.
+    pub fn id(&self) -> GroupId {
+        self.id
+    }
+
+    /// Gets the ID of the group that this group was created in, if any.
+    pub fn parent(&self) -> Option<GroupId> {
+        self.parent
+    }
+
+    /// Tests if this group has been torn down.
+    pub fn is_torn_down(&self) -> bool {
+        self.torn_down.load(Ordering::Acquire)
+    }
+
+    /// Adds a newly-spawned process to this group.
+    ///
+    /// If this group has been torn down, the process is killed instead and
+    /// this returns false.
+    pub(crate) fn add(&self, process: &Process) -> bool {
+        let cap = process
+            .borrow_parent()
+            .export_to(Permissions::KILL, &self.table)
+            .unwrap()
+            .into_handle();
+
+        let info = process.borrow_info();
+        let mut members = self.members.lock();
+
+        if self.is_torn_down() {
+            drop(members);
+            debug!("killing PID {} spawned into torn-down group", info.pid);
+            self.kill(cap);
+            return false;
+        }
+
+        members.push(Member {
+            pid: info.pid,
+            name: info.meta.name.clone(),
+            cap,
+        });
+
+        true
+    }
+
+    /// Removes a member that has exited.
+    pub(crate) fn remove(&self, pid: ProcessId) {
+        let mut members = self.members.lock();
+        let Some(index) = members.iter().position(|member| member.pid == pid) else {
+            return;
+        };
+
+        let member = members.remove(index);
+        let _ = self.table.dec_ref(member.cap);
+    }
+
+    /// Lists this group's running members, oldest first.
+    pub fn members(&self) -> Vec<GroupMember> {
+        self.members
+            .lock()
+            .iter()
+            .map(|member| GroupMember {
+                pid: member.pid as u64,
+                name: member.name.clone(),
+            })
+            .collect()
+    }
+
+    /// Describes this group.
+    pub fn info(&self) -> GroupInfo {
+        GroupInfo {
+            id: self.id,
+            parent: self.parent,
+            members: self.members(),
+        }
+    }
+
+    /// Kills every member of this group on behalf of `requester`, newest
+    /// first, so that children are killed before the processes that spawned
+    /// them.
+    ///
+    /// Returns the PIDs of the killed members in the order they were killed.
+    /// New members may still join afterwards.
+    pub fn kill_all(&self, requester: AuditActor) -> Vec<ProcessId> {
+        let members = std::mem::take(&mut *self.members.lock());
+        self.kill_members(members, requester)
+    }
+
+    /// Kills every member of this group like [Self::kill_all], then clears
+    /// its registry and kills any process that's spawned into it later.
+    pub fn teardown(&self, requester: AuditActor) -> Vec<ProcessId> {
+        let members = {
+            let mut members = self.members.lock();
+            self.torn_down.store(true, Ordering::Release);
+            std::mem::take(&mut *members)
+        };
+
+        let killed = self.kill_members(members, requester);
+        self.registry.clear();
+        killed
+    }
+
+    /// Creates a view of `parent` that registers services into this group's
+    /// private namespace.
+    pub fn registry_view(&self, parent: OwnedCapability) -> RegistryView {
+        let policy = ViewPolicy::new(format!("group.{}.", self.id));
+        RegistryView::new(parent, policy, self.registry.clone())
+    }
+
+    fn kill_members(&self, members: Vec<Member>, requester: AuditActor) -> Vec<ProcessId> {
+        members
+            .into_iter()
+            .rev()
+            .map(|member| {
+                self.audit.record(AuditEventKind::Kill {
+                    requester: requester.clone(),
+                    target: Some(member.pid as u64),
+                });
+
+                self.kill(member.cap);
+                member.pid
+            })
+            .collect()
+    }
+
+    fn kill(&self, cap: CapabilityHandle) {
+        // fails if the process has already exited
+        if let Err(err) = self.table.kill(cap) {
+            debug!("failed to kill group member: {:?}", err);
+        }
+
+        let _ = self.table.dec_ref(cap);
+    }
+}
+
+/// The owner of a [ProcessGroup]. Dropping it tears the group down.
+pub struct GroupHandle {
+    group: Arc<ProcessGroup>,
+}
+
+impl GroupHandle {
+    pub(crate) fn new(group: Arc<ProcessGroup>) -> Self {
+        Self { group }
+    }
+
+    /// Gets a shared reference to the owned group.
+    pub fn group(&self) -> &Arc<ProcessGroup> {
+        &self.group
+    }
+}
+
+impl Deref for GroupHandle {
+    type Target = ProcessGroup;
+
+    fn deref(&self) -> &ProcessGroup {
+        &self.group
+    }
+}
+
+impl Drop for GroupHandle {
+    fn drop(&mut self) {
+        let killed = self.group.teardown(AuditActor::Host);
+        debug!(
+            "tearing down group {}, killed {} members",
+            self.group.id,
+            killed.len()
+        );
+    }
+}
+
+/// Lists and kills the host's process groups. Accepts [ProcessGroupsRequest].
+pub struct ProcessGroupsService;
+
+#[async_trait]
+impl RequestResponseProcess for ProcessGroupsService {
+    type Request = ProcessGroupsRequest;
+    type Response = ProcessGroupsResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, ProcessGroupsRequest>,
+    ) -> ResponseInfo<'a, ProcessGroupsResponse> {
+        let factory = &request.runtime.process_factory;
+
+        match &request.data {
+            ProcessGroupsRequest::List => {
+                let groups = factory.groups().iter().map(|group| group.info()).collect();
+                Ok(ProcessGroupsSuccess::Groups(groups)).into()
+            }
+            ProcessGroupsRequest::Kill { id } => {
+                let Some(group) = factory.group(*id) else {
+                    return ProcessGroupsError::NotFound.into();
+                };
+
+                warn!("tearing down process group {} by request", id);
+                let requester = request.process.borrow_info().audit_actor();
+                let killed = group.teardown(requester).len();
+                Ok(ProcessGroupsSuccess::Killed(killed)).into()
+            }
+        }
+    }
+}
+
+impl ServiceRunner for ProcessGroupsService {
+    const NAME: &'static str = PROCESS_GROUPS_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = crate::utils::cargo_process_metadata!();
+        meta.description = Some(
+            "Lists and kills the host's process groups. Accepts ProcessGroupsRequest.".to_string(),
+        );
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flue::CapabilityRef;
+    use hearth_schema::audit::AuditActor;
+
+    use crate::testing::{TestRuntime, TestRuntimeBuilder};
+    use crate::utils::{ProcessRunner, RunnerContext};
+
+    /// Spawns a child [Spawner] for every request.
+    struct Spawner;
+
+    #[async_trait]
+    impl RequestResponseProcess for Spawner {
+        type Request = ();
+        type Response = ();
+
+        async fn on_request<'a>(
+            &'a mut self,
+            request: &mut RequestInfo<'a, ()>,
+        ) -> ResponseInfo<'a, ()> {
+            let child = request.spawn(ProcessMetadata::default(), Spawner);
+
+            ResponseInfo {
+                data: (),
+                caps: vec![child],
+            }
+        }
+    }
+
+    /// Spawns a [Spawner] into a group.
+    fn spawn_into<'a>(runtime: &'a TestRuntime, group: &Arc<ProcessGroup>) -> CapabilityRef<'a> {
+        let inner = runtime.runtime().clone();
+
+        runtime.block_on(async {
+            let factory = &inner.process_factory;
+            let meta = ProcessMetadata::default();
+            let process = factory.spawn_in_group(meta, AuditActor::Host, None, group);
+            let cap = process
+                .borrow_parent()
+                .export_to(
+                    Permissions::SEND | Permissions::MONITOR,
+                    runtime.process().borrow_table(),
+                )
+                .unwrap();
+
+            let runtime = inner.clone();
+            tokio::spawn(async move {
+                Spawner.run("Spawner".to_string(), runtime, &process).await;
+            });
+
+            cap
+        })
+    }
+
+    /// Asks a [Spawner] to spawn a child.
+    fn spawn_child<'a>(runtime: &'a TestRuntime, spawner: &CapabilityRef<'_>) -> CapabilityRef<'a> {
+        let ((), mut caps) = runtime.request(spawner, &(), &[]);
+        caps.remove(0)
+    }
+
+    #[test]
+    fn nested_spawns_inherit_group() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let factory = &runtime.runtime().process_factory;
+        let group = factory.create_group(None);
+        let other = factory.create_group(Some(&group));
+
+        let parent = spawn_into(&runtime, group.group());
+        let child = spawn_child(&runtime, &parent);
+        let _grandchild = spawn_child(&runtime, &child);
+        let _outsider = spawn_into(&runtime, other.group());
+
+        assert_eq!(group.members().len(), 3);
+        assert_eq!(other.members().len(), 1);
+        assert_eq!(other.parent(), Some(group.id()));
+
+        let ids: Vec<_> = factory.groups().iter().map(|group| group.id()).collect();
+        assert_eq!(ids, [group.id(), other.id()]);
+
+        // dropping the owning handle tears the group down
+        let other_group = other.group().clone();
+        drop(other);
+        assert!(other_group.is_torn_down());
+        assert!(other_group.members().is_empty());
+        assert_eq!(factory.groups().len(), 1);
+    }
+
+    #[test]
+    fn teardown_kills_children_first() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let factory = &runtime.runtime().process_factory;
+        let handle = factory.create_group(None);
+        let group = handle.group().clone();
+
+        let parent = spawn_into(&runtime, &group);
+        let child = spawn_child(&runtime, &parent);
+        let _grandchild = spawn_child(&runtime, &child);
+
+        let spawned: Vec<_> = group
+            .members()
+            .iter()
+            .map(|member| member.pid as ProcessId)
+            .collect();
+
+        let mailbox = runtime.mailbox();
+        mailbox.monitor(&parent);
+
+        let killed = group.teardown(AuditActor::Host);
+        assert_eq!(killed, spawned.into_iter().rev().collect::<Vec<_>>());
+        mailbox.recv_down();
+
+        // each kill is audited with its target
+        let audited: Vec<_> = factory
+            .audit_log()
+            .query(&Default::default(), None)
+            .into_iter()
+            .filter_map(|event| match event.kind {
+                AuditEventKind::Kill { target, .. } => target,
+                _ => None,
+            })
+            .collect();
+
+        let killed: Vec<_> = killed.into_iter().map(|pid| pid as u64).collect();
+        assert_eq!(audited, killed);
+        assert!(group.members().is_empty());
+        assert!(factory.groups().is_empty());
+
+        // nothing may join a torn-down group
+        let meta = ProcessMetadata::default();
+        let late = runtime
+            .block_on(async { factory.spawn_in_group(meta, AuditActor::Host, None, &group) });
+        assert!(group.members().is_empty());
+
+        drop(late);
+        drop(handle);
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use async_trait::async_trait;
+use hearth_schema::process::*;
+use serde::Deserialize;
+
+use crate::process::{ProcessLogEvent, ProcessMetadata, ProcessRecord};
+use crate::utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner};
+
+/// Config for process inspection, loaded from the `process_inspector` table.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ProcessInspectorConfig {
+    /// If true, the [ProcessInspector] is registered as
+    /// [PROCESS_INSPECTOR_SERVICE] so that any process with the registry can
+    /// describe any running process.
+    pub service: bool,
+}
+
+/// Describes running processes. Accepts [ProcessInspectorRequest].
+pub struct ProcessInspector;
+
+#[async_trait]
+impl RequestResponseProcess for ProcessInspector {
+    type Request = ProcessInspectorRequest;
+    type Response = ProcessInspectorResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, ProcessInspectorRequest>,
+    ) -> ResponseInfo<'a, ProcessInspectorResponse> {
+        let ProcessInspectorRequest::GetProcessDetails { pid, log_tail } = request.data;
+        let runtime = &request.runtime;
+        let factory = &runtime.process_factory;
+
+        // the log history is dropped along with the record, so a missing
+        // log only means that the process exited between these lookups
+        let (Some(record), Some(log)) = (
+            factory.record(pid as usize),
+            factory.recent_log(pid as usize, log_tail as usize),
+        ) else {
+            return ProcessInspectorError::NotFound.into();
+        };
+
+        let lump_info = match record.lump.as_ref() {
+            Some(lump) => runtime.lump_store.get_lump_info(lump).await,
+            None => None,
+        };
+
+        let services = factory.services_of(pid as usize);
+
+        let ProcessRecord {
+            meta,
+            requester,
+            lump,
+            group,
+        } = record;
+
+        Ok(ProcessDetails {
+            pid,
+            metadata: describe(meta),
+            spawned_by: requester,
+            lump,
+            lump_info,
+            group,
+            services,
+            // the host doesn't track these yet
+            monitoring: None,
+            mailbox_depth: None,
+            resources: None,
+            log_tail: log.into_iter().map(log_entry).collect(),
+        })
+        .into()
+    }
+}
+
+impl ServiceRunner for ProcessInspector {
+    const NAME: &'static str = PROCESS_INSPECTOR_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = crate::utils::cargo_process_metadata!();
+        meta.description =
+            Some("Describes running processes. Accepts ProcessInspectorRequest.".to_string());
+        meta
+    }
+}
+
+/// Converts a process's metadata to its protocol description.
+fn describe(meta: ProcessMetadata) -> ProcessDescription {
+    ProcessDescription {
+        name: meta.name,
+        version: meta.version,
+        description: meta.description,
+        authors: meta.authors,
+        repository: meta.repository,
+        homepage: meta.homepage,
+        license: meta.license,
+    }
+}
+
+/// Converts a process log event to its protocol representation.
+fn log_entry(event: ProcessLogEvent) -> ProcessLogEntry {
+    ProcessLogEntry {
+        timestamp: event.timestamp,
+        level: event.level,
+        module: event.module,
+        content: event.content,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_schema::audit::{AuditActor, AuditEventKind, AuditFilter};
+    use hearth_schema::ProcessLogLevel;
+
+    use crate::testing::{TestRuntime, TestRuntimeBuilder};
+
+    fn inspector_runtime() -> TestRuntime {
+        let config = toml::from_str("[process_inspector]\nservice = true").unwrap();
+        TestRuntimeBuilder::with_config(config).build()
+    }
+
+    fn inspect(runtime: &TestRuntime, pid: u64, log_tail: u32) -> ProcessInspectorResponse {
+        let inspector = runtime.get_service(PROCESS_INSPECTOR_SERVICE).unwrap();
+        let request = ProcessInspectorRequest::GetProcessDetails { pid, log_tail };
+        runtime.request(&inspector, &request, &[]).0
+    }
+
+    #[test]
+    fn details_include_metadata_and_log_tail() {
+        let runtime = inspector_runtime();
+        let factory = &runtime.runtime().process_factory;
+
+        let mut meta = ProcessMetadata::default();
+        meta.name = Some("Subject".to_string());
+        let process = runtime.block_on(async { factory.spawn_for(meta, AuditActor::Host, None) });
+        let info = process.borrow_info();
+
+        for content in ["first", "second", "third"] {
+            let event = ProcessLogEvent::new(ProcessLogLevel::Info, "subject", content);
+            info.log_tx.send(event).unwrap();
+        }
+
+        runtime.settle();
+
+        let details = inspect(&runtime, info.pid as u64, 2).unwrap();
+        assert_eq!(details.metadata.name.as_deref(), Some("Subject"));
+        assert_eq!(details.spawned_by, AuditActor::Host);
+        assert_eq!(details.lump, None);
+        assert_eq!(details.group, None);
+        assert!(details.services.is_empty());
+
+        let contents: Vec<_> = details
+            .log_tail
+            .iter()
+            .map(|event| event.content.as_str())
+            .collect();
+        assert_eq!(contents, ["second", "third"]);
+    }
+
+    #[test]
+    fn details_list_registered_services() {
+        let runtime = inspector_runtime();
+        let events = runtime
+            .runtime()
+            .process_factory
+            .audit_log()
+            .query(&AuditFilter::default(), None);
+
+        let pid = events
+            .into_iter()
+            .find_map(|event| match event.kind {
+                AuditEventKind::Register { name, pid, .. } if name == PROCESS_INSPECTOR_SERVICE => {
+                    pid
+                }
+                _ => None,
+            })
+            .unwrap();
+
+        let details = inspect(&runtime, pid, 0).unwrap();
+        assert_eq!(details.services, [PROCESS_INSPECTOR_SERVICE]);
+        assert!(details.log_tail.is_empty());
+    }
+
+    #[test]
+    fn details_list_only_current_services() {
+        let runtime = inspector_runtime();
+        let factory = &runtime.runtime().process_factory;
+        let meta = ProcessMetadata::default();
+        let process = runtime.block_on(async { factory.spawn_for(meta, AuditActor::Host, None) });
+        let pid = process.borrow_info().pid;
+
+        factory.set_service("first".to_string(), pid);
+        factory.set_service("second".to_string(), pid);
+        factory.remove_service("first");
+
+        let details = inspect(&runtime, pid as u64, 0).unwrap();
+        assert_eq!(details.services, ["second"]);
+
+        // names are forgotten along with the process
+        drop(process);
+        runtime.settle();
+        assert!(factory.services_of(pid).is_empty());
+    }
+
+    #[test]
+    fn unknown_pids_are_not_found() {
+        let runtime = inspector_runtime();
+        let response = inspect(&runtime, u64::MAX, 8);
+        assert_eq!(response, Err(ProcessInspectorError::NotFound));
+    }
+}
@@ -16,11 +16,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
+use std::fs::OpenOptions;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
 pub use async_trait::async_trait;
-use tracing::{debug, error, info, Level};
-use tracing_subscriber::prelude::*;
+use serde::Deserialize;
+use tracing::{debug, error, info};
+use tracing_subscriber::{prelude::*, EnvFilter};
 
 pub use anyhow;
 pub use flue;
@@ -31,39 +34,151 @@ pub use tracing;
 /// Asset loading and storage.
 pub mod asset;
 
+/// Process spawn auditing.
+pub mod audit;
+
 /// Network connection.
 pub mod connection;
 
+/// Process groups for managing composed applications.
+pub mod group;
+
+/// Process inspection for debugging.
+pub mod inspect;
+
 /// Lump loading and storage.
 pub mod lump;
 
 /// Local process spawning and management.
 pub mod process;
 
+/// Process log history and persistence.
+pub mod process_log;
+
 /// The native registry implementation.
 pub mod registry;
 
 /// Peer runtime building and execution.
 pub mod runtime;
 
+/// Restart policies for long-running processes.
+pub mod supervisor;
+
+/// Recording of process message traffic.
+pub mod tap;
+
+/// A deterministic harness for testing runtimes and guests.
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
 /// Utilities for host-side runtime management.
 pub mod utils;
 
-/// Helper function to set up console logging with reasonable defaults.
-pub fn init_logging() {
-    let filter = tracing_subscriber::filter::Targets::new()
-        .with_target("wgpu", Level::INFO)
-        .with_target("wgpu_core", Level::WARN)
-        .with_target("wgpu_hal", Level::WARN)
-        .with_target("hearth", Level::DEBUG)
-        .with_default(Level::INFO);
+/// The environment variable that overrides [LoggingConfig::filter].
+pub const LOG_ENV_VAR: &str = "HEARTH_LOG";
+
+/// Configuration for [init_logging], loaded from the `logging` table of the
+/// config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Which logs to print, as comma-separated `target=level` directives.
+    ///
+    /// Overridden by the `HEARTH_LOG` environment variable.
+    pub filter: String,
+
+    /// If set, logs are additionally appended to this file as JSON lines.
+    pub json_file: Option<PathBuf>,
+
+    /// Whether to additionally log to the systemd journal.
+    ///
+    /// Requires hearth-runtime's `journald` feature.
+    pub journald: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            filter: "info,hearth=debug,wgpu=info,wgpu_core=warn,wgpu_hal=warn".to_string(),
+            json_file: None,
+            journald: false,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// Loads the logging config from a config file, falling back to the
+    /// default if it's missing or invalid.
+    ///
+    /// Logging isn't set up yet when this is called, so errors are printed
+    /// directly to stderr.
+    pub fn from_config_file(config_file: &toml::Table) -> Self {
+        let Some(value) = config_file.get("logging") else {
+            return Self::default();
+        };
+
+        Self::deserialize(value.to_owned()).unwrap_or_else(|err| {
+            eprintln!("Invalid logging config, using defaults: {}", err);
+            Self::default()
+        })
+    }
+}
+
+/// Helper function to set up console logging.
+///
+/// Runtime logs are grouped by tracing spans for each process and network
+/// connection, so they can be filtered with directives like
+/// `HEARTH_LOG="[process{pid=3}]=trace"`.
+pub fn init_logging(config: &LoggingConfig) {
+    let directives = std::env::var(LOG_ENV_VAR).unwrap_or_else(|_| config.filter.clone());
+    let filter = EnvFilter::try_new(&directives).unwrap_or_else(|err| {
+        eprintln!(
+            "Invalid log filter {:?}, using defaults: {}",
+            directives, err
+        );
+        EnvFilter::new(LoggingConfig::default().filter)
+    });
 
     let format = tracing_subscriber::fmt::layer().compact();
 
-    tracing_subscriber::registry()
+    let json = config.json_file.as_ref().and_then(|path| {
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Some(
+                tracing_subscriber::fmt::layer()
+                    .json()
+                    .with_writer(Mutex::new(file)),
+            ),
+            Err(err) => {
+                eprintln!("Failed to open JSON log file {:?}: {}", path, err);
+                None
+            }
+        }
+    });
+
+    #[cfg(feature = "journald")]
+    let journald = config
+        .journald
+        .then(|| {
+            tracing_journald::layer()
+                .map_err(|err| eprintln!("Failed to connect to journald: {}", err))
+                .ok()
+        })
+        .flatten();
+
+    #[cfg(not(feature = "journald"))]
+    if config.journald {
+        eprintln!("journald logging requires hearth-runtime's `journald` feature");
+    }
+
+    let registry = tracing_subscriber::registry()
         .with(filter)
         .with(format)
-        .init();
+        .with(json);
+
+    #[cfg(feature = "journald")]
+    let registry = registry.with(journald);
+
+    registry.init();
 }
 
 /// Helper function to wait for Ctrl+C with nice logging.
@@ -85,6 +200,16 @@ pub fn get_config_dir() -> PathBuf {
         .to_owned()
 }
 
+/// Gets the system directory for persistent Hearth data.
+///
+/// Panics if something fails for whatever reason.
+pub fn get_data_dir() -> PathBuf {
+    directories::ProjectDirs::from("rs", "hearth", "hearth")
+        .expect("Failed to get Hearth project directories")
+        .data_dir()
+        .to_owned()
+}
+
 /// Gets the default path of the main Hearth configuration file.
 ///
 /// Panics if something fails for whatever reason.
@@ -100,3 +225,32 @@ pub fn load_config(path: &Path) -> anyhow::Result<toml::Table> {
     toml::from_str(&config)
         .map_err(|err| anyhow::anyhow!("Failed to deserialize config: {:?}", err))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logging_config_from_table() {
+        let config_file: toml::Table = toml::from_str(
+            r#"
+            [logging]
+            filter = "warn"
+            json_file = "hearth.jsonl"
+            "#,
+        )
+        .unwrap();
+
+        let config = LoggingConfig::from_config_file(&config_file);
+        assert_eq!(config.filter, "warn");
+        assert_eq!(config.json_file, Some(PathBuf::from("hearth.jsonl")));
+        assert!(!config.journald);
+    }
+
+    #[test]
+    fn logging_config_defaults() {
+        let config = LoggingConfig::from_config_file(&Default::default());
+        assert_eq!(config.filter, LoggingConfig::default().filter);
+        assert!(EnvFilter::try_new(config.filter).is_ok());
+    }
+}
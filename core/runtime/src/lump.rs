@@ -18,16 +18,33 @@
 
 use std::collections::HashMap;
 
+use async_trait::async_trait;
 use bytes::{Buf, Bytes};
+use hearth_schema::lump::*;
 use hearth_schema::*;
 use tokio::sync::RwLock;
 use tracing::debug;
 
+use crate::process::ProcessMetadata;
+use crate::utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner};
+
 pub use bytes;
 
+/// Computes the [LumpId] of some data.
+pub fn lump_id(data: &[u8]) -> LumpId {
+    LumpId(
+        blake3::Hasher::new()
+            .update(data)
+            .finalize()
+            .as_bytes()
+            .to_owned(),
+    )
+}
+
 #[derive(Debug)]
 struct Lump {
     data: Bytes,
+    metadata: LumpMetadata,
 }
 
 #[derive(Debug, Default)]
@@ -42,22 +59,63 @@ impl LumpStoreImpl {
         }
     }
 
+    /// Adds a lump without metadata and returns its ID.
     pub async fn add_lump(&self, data: Bytes) -> LumpId {
-        let id = LumpId(
-            blake3::Hasher::new()
-                .update(data.chunk())
-                .finalize()
-                .as_bytes()
-                .to_owned(),
-        );
+        self.add_lump_with(data, LumpMetadata::default()).await
+    }
+
+    /// Adds a lump with metadata and returns its ID.
+    ///
+    /// The ID is always computed from the data here, so callers can't store
+    /// data under the wrong ID.
+    pub async fn add_lump_with(&self, data: Bytes, metadata: LumpMetadata) -> LumpId {
+        let id = lump_id(data.chunk());
+        self.insert(id, data, metadata).await;
+        id
+    }
+
+    /// Adds a lump that's expected to have a given ID, such as one that was
+    /// transferred from another peer.
+    ///
+    /// The data is rejected without being stored if its ID doesn't match.
+    pub async fn add_verified_lump(
+        &self,
+        expected: LumpId,
+        data: Bytes,
+        metadata: LumpMetadata,
+    ) -> Result<(), LumpMismatch> {
+        let actual = lump_id(data.chunk());
+        if actual != expected {
+            return Err(LumpMismatch { expected, actual });
+        }
 
+        self.insert(actual, data, metadata).await;
+        Ok(())
+    }
+
+    async fn insert(&self, id: LumpId, data: Bytes, metadata: LumpMetadata) {
         let mut store = self.store.write().await;
-        store.entry(id).or_insert_with(|| {
+        let lump = store.entry(id).or_insert_with(|| {
             debug!("Storing lump {}", id);
-            Lump { data }
+            Lump {
+                data,
+                metadata: LumpMetadata::default(),
+            }
         });
 
-        id
+        let stored = &mut lump.metadata;
+        if from_host(&metadata) && !from_host(stored) {
+            *stored = metadata;
+            return;
+        }
+
+        if stored.content_type.is_none() {
+            stored.content_type = metadata.content_type;
+        }
+
+        if stored.origin.is_none() {
+            stored.origin = metadata.origin;
+        }
     }
 
     pub async fn get_lump(&self, id: &LumpId) -> Option<Bytes> {
@@ -67,4 +125,145 @@ impl LumpStoreImpl {
             .get(id)
             .map(|lump| lump.data.clone())
     }
+
+    /// Describes a stored lump.
+    pub async fn get_lump_info(&self, id: &LumpId) -> Option<LumpInfo> {
+        self.store.read().await.get(id).map(|lump| LumpInfo {
+            size: lump.data.len() as u64,
+            metadata: lump.metadata.clone(),
+        })
+    }
+}
+
+/// Whether lump metadata was supplied by the host itself rather than by a
+/// guest, a client, or a peer.
+fn from_host(metadata: &LumpMetadata) -> bool {
+    matches!(
+        metadata.origin,
+        Some(LumpOrigin::Host | LumpOrigin::Filesystem)
+    )
+}
+
+/// Describes the lumps in the runtime's lump store. Accepts
+/// [LumpInfoRequest].
+pub struct LumpInfoService;
+
+#[async_trait]
+impl RequestResponseProcess for LumpInfoService {
+    type Request = LumpInfoRequest;
+    type Response = LumpInfoResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, LumpInfoRequest>,
+    ) -> ResponseInfo<'a, LumpInfoResponse> {
+        let lump = &request.data.lump;
+        request.runtime.lump_store.get_lump_info(lump).await.into()
+    }
+}
+
+impl ServiceRunner for LumpInfoService {
+    const NAME: &'static str = LUMP_INFO_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = crate::utils::cargo_process_metadata!();
+        meta.description =
+            Some("Describes the lumps in the lump store. Accepts LumpInfoRequest.".to_string());
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verified_lumps_must_match() {
+        let store = LumpStoreImpl::new();
+        let data = Bytes::from_static(b"hello");
+        let expected = lump_id(b"hello");
+
+        let bad = store
+            .add_verified_lump(expected, Bytes::from_static(b"hellp"), Default::default())
+            .await;
+
+        assert_eq!(
+            bad,
+            Err(LumpMismatch {
+                expected,
+                actual: lump_id(b"hellp"),
+            })
+        );
+
+        assert!(store.get_lump(&expected).await.is_none());
+        assert!(store.get_lump(&lump_id(b"hellp")).await.is_none());
+
+        let meta = LumpMetadata {
+            content_type: Some("text/plain".to_string()),
+            origin: Some(LumpOrigin::Remote),
+        };
+
+        store.add_verified_lump(expected, data, meta).await.unwrap();
+        assert_eq!(store.get_lump(&expected).await.unwrap(), &b"hello"[..]);
+    }
+
+    #[tokio::test]
+    async fn metadata_is_filled_in() {
+        let store = LumpStoreImpl::new();
+        let data = Bytes::from_static(b"\0asm");
+        let id = store.add_lump(data.clone()).await;
+
+        let info = store.get_lump_info(&id).await.unwrap();
+        assert_eq!(info.size, 4);
+        assert_eq!(info.metadata, LumpMetadata::default());
+
+        let meta = LumpMetadata {
+            content_type: Some("application/wasm".to_string()),
+            origin: Some(LumpOrigin::Upload),
+        };
+
+        assert_eq!(store.add_lump_with(data.clone(), meta.clone()).await, id);
+
+        // later additions don't overwrite what's already set
+        let other = LumpMetadata {
+            content_type: Some("image/png".to_string()),
+            origin: Some(LumpOrigin::Guest),
+        };
+
+        store.add_lump_with(data, other).await;
+        let info = store.get_lump_info(&id).await.unwrap();
+        assert_eq!(info.metadata, meta);
+    }
+
+    #[tokio::test]
+    async fn host_metadata_wins() {
+        let store = LumpStoreImpl::new();
+        let data = Bytes::from_static(b"\x89PNG");
+
+        let poisoned = LumpMetadata {
+            content_type: Some("application/wasm".to_string()),
+            origin: Some(LumpOrigin::Remote),
+        };
+
+        let id = store.add_lump_with(data.clone(), poisoned).await;
+
+        let host = LumpMetadata {
+            content_type: Some("image/png".to_string()),
+            origin: Some(LumpOrigin::Host),
+        };
+
+        store.add_lump_with(data.clone(), host.clone()).await;
+        let info = store.get_lump_info(&id).await.unwrap();
+        assert_eq!(info.metadata, host);
+
+        // untrusted additions can't replace it again
+        let upload = LumpMetadata {
+            content_type: Some("text/plain".to_string()),
+            origin: Some(LumpOrigin::Upload),
+        };
+
+        store.add_lump_with(data, upload).await;
+        let info = store.get_lump_info(&id).await.unwrap();
+        assert_eq!(info.metadata, host);
+    }
 }
@@ -18,13 +18,24 @@
 
 #![warn(missing_docs)]
 
-use std::sync::{atomic::AtomicUsize, Arc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::sync::{Arc, Weak};
 
 use flue::{Mailbox, MailboxGroup, PostOffice, Table};
-use flume::Sender;
-use hearth_schema::ProcessLogLevel;
+use flume::{Receiver, Sender};
+use hearth_schema::audit::{AuditActor, AuditEventKind};
+use hearth_schema::group::GroupId;
+use hearth_schema::{LumpId, ProcessLogLevel};
 use ouroboros::self_referencing;
-use tracing::debug;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tracing::{debug, error, info_span, Span};
+
+use crate::audit::AuditLog;
+use crate::group::{GroupHandle, ProcessGroup};
+use crate::process_log::{now_millis, LogHistory};
+use crate::tap::{MessageTap, MessageTapConfig};
 
 /// A local Hearth process. The main entrypoint for Hearth programming.
 #[self_referencing]
@@ -50,6 +61,18 @@ pub struct Process {
     pub parent: Mailbox<'this>,
 }
 
+impl Process {
+    /// Creates a tracing span for this process's execution.
+    ///
+    /// The span carries the process's PID and name so that the runtime's
+    /// logs can be filtered by process.
+    pub fn span(&self) -> Span {
+        let info = self.borrow_info();
+        let name = info.meta.name.as_deref().unwrap_or("<no name>");
+        info_span!("process", pid = info.pid, name)
+    }
+}
+
 /// The integer identifier for a local process.
 ///
 /// Hidden from most guest-side code, but is used host-side for human-readable
@@ -66,11 +89,48 @@ pub struct ProcessInfo {
 
     /// This process's [ProcessMetdata].
     pub meta: ProcessMetadata,
+
+    /// Records this process's messages while enabled.
+    pub tap: Arc<MessageTap>,
+
+    /// The group that this process is a member of, if any. Processes spawned
+    /// on behalf of this one join it too.
+    pub group: Option<Arc<ProcessGroup>>,
+
+    /// The audit log that this process's exit is recorded in.
+    audit: Arc<AuditLog>,
+}
+
+impl ProcessInfo {
+    /// Identifies this process as the cause of an audited event.
+    pub fn audit_actor(&self) -> AuditActor {
+        AuditActor::Process {
+            pid: self.pid as u64,
+            name: self.meta.name.clone(),
+        }
+    }
+
+    /// Records that this process killed another process, identified by its
+    /// PID if it's known.
+    pub fn audit_kill(&self, target: Option<ProcessId>) {
+        self.audit.record(AuditEventKind::Kill {
+            requester: self.audit_actor(),
+            target: target.map(|pid| pid as u64),
+        });
+    }
 }
 
 impl Drop for ProcessInfo {
     fn drop(&mut self) {
         debug!("despawning PID {}", self.pid);
+
+        if let Some(group) = self.group.as_ref() {
+            group.remove(self.pid);
+        }
+
+        self.audit.record(AuditEventKind::Exit {
+            pid: self.pid as u64,
+        });
     }
 }
 
@@ -81,6 +141,9 @@ pub struct ProcessMetadata {
     /// A short, human-readable identifier for this process's function.
     pub name: Option<String>,
 
+    /// The version of this process's software.
+    pub version: Option<String>,
+
     /// Longer documentation of this process's function.
     pub description: Option<String>,
 
@@ -97,10 +160,36 @@ pub struct ProcessMetadata {
     pub license: Option<String>,
 }
 
+/// What a [ProcessFactory] remembers about a running process.
+#[derive(Clone, Debug)]
+pub struct ProcessRecord {
+    /// The metadata that the process was spawned with.
+    pub meta: ProcessMetadata,
+
+    /// Who requested the process's spawn.
+    pub requester: AuditActor,
+
+    /// The lump that the process runs, if any.
+    pub lump: Option<LumpId>,
+
+    /// The ID of the group that the process was spawned into, if any.
+    pub group: Option<GroupId>,
+}
+
 /// A factory for making local instances of [Process].
 pub struct ProcessFactory {
     post: Arc<PostOffice>,
     pid_gen: AtomicUsize,
+    log_observers: Vec<Sender<(ProcessId, ProcessLogEvent)>>,
+    log_histories: Arc<Mutex<HashMap<ProcessId, LogHistory>>>,
+    log_history_len: usize,
+    audit: Arc<AuditLog>,
+    taps: Arc<Mutex<HashMap<ProcessId, Arc<MessageTap>>>>,
+    records: Arc<Mutex<HashMap<ProcessId, ProcessRecord>>>,
+    services: Arc<Mutex<HashMap<String, ProcessId>>>,
+    tap_config: MessageTapConfig,
+    group_gen: AtomicU64,
+    groups: Mutex<HashMap<GroupId, Weak<ProcessGroup>>>,
 }
 
 impl ProcessFactory {
@@ -109,11 +198,151 @@ impl ProcessFactory {
         Self {
             post,
             pid_gen: AtomicUsize::new(0),
+            log_observers: Vec::new(),
+            log_histories: Default::default(),
+            log_history_len: 256,
+            audit: Default::default(),
+            taps: Default::default(),
+            records: Default::default(),
+            services: Default::default(),
+            tap_config: Default::default(),
+            group_gen: AtomicU64::new(0),
+            groups: Default::default(),
         }
     }
 
+    /// Sets how many recent log events are kept for each process spawned
+    /// after this call.
+    pub fn set_log_history_len(&mut self, len: usize) {
+        self.log_history_len = len;
+    }
+
+    /// Replaces the audit log that processes spawned after this call are
+    /// recorded in.
+    pub fn set_audit_log(&mut self, audit: Arc<AuditLog>) {
+        self.audit = audit;
+    }
+
+    /// Gets the audit log that this factory records into.
+    pub fn audit_log(&self) -> &Arc<AuditLog> {
+        &self.audit
+    }
+
+    /// Replaces the message recording config for processes spawned after
+    /// this call.
+    pub fn set_tap_config(&mut self, config: MessageTapConfig) {
+        self.tap_config = config;
+    }
+
+    /// Gets the message recording config.
+    pub fn tap_config(&self) -> &MessageTapConfig {
+        &self.tap_config
+    }
+
+    /// Gets the message tap of a running process.
+    pub fn tap(&self, pid: ProcessId) -> Option<Arc<MessageTap>> {
+        self.taps.lock().get(&pid).cloned()
+    }
+
+    /// Gets the spawn record of a running process.
+    pub fn record(&self, pid: ProcessId) -> Option<ProcessRecord> {
+        self.records.lock().get(&pid).cloned()
+    }
+
+    /// Records that a running process is registered as a service under
+    /// `name`, replacing whichever process was registered under it before.
+    ///
+    /// The name is forgotten once the process exits.
+    pub fn set_service(&self, name: String, pid: ProcessId) {
+        self.services.lock().insert(name, pid);
+    }
+
+    /// Forgets which process is registered as a service under `name`.
+    pub fn remove_service(&self, name: &str) {
+        self.services.lock().remove(name);
+    }
+
+    /// Lists the names that a running process is currently registered under
+    /// as a service, in sorted order.
+    pub fn services_of(&self, pid: ProcessId) -> Vec<String> {
+        let mut names: Vec<_> = self
+            .services
+            .lock()
+            .iter()
+            .filter(|(_, owner)| **owner == pid)
+            .map(|(name, _)| name.clone())
+            .collect();
+
+        names.sort();
+        names
+    }
+
+    /// Gets up to `limit` of a running process's most recent log events,
+    /// oldest first, or `None` if the process is not running.
+    pub fn recent_log(&self, pid: ProcessId, limit: usize) -> Option<Vec<ProcessLogEvent>> {
+        self.log_histories
+            .lock()
+            .get(&pid)
+            .map(|history| history.recent(limit))
+    }
+
+    /// Creates a new process group, optionally nested in another group.
+    ///
+    /// The group is torn down when the returned handle is dropped.
+    pub fn create_group(&self, parent: Option<&ProcessGroup>) -> GroupHandle {
+        let id = self
+            .group_gen
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+
+        debug!("creating process group {}", id);
+
+        let parent = parent.map(|parent| parent.id());
+        let post = self.post.clone();
+        let group = Arc::new(ProcessGroup::new(id, parent, post, self.audit.clone()));
+        let mut groups = self.groups.lock();
+        groups.retain(|_, group| group.strong_count() > 0);
+        groups.insert(id, Arc::downgrade(&group));
+        GroupHandle::new(group)
+    }
+
+    /// Gets a process group by ID if it hasn't been torn down.
+    pub fn group(&self, id: GroupId) -> Option<Arc<ProcessGroup>> {
+        self.groups
+            .lock()
+            .get(&id)
+            .and_then(Weak::upgrade)
+            .filter(|group| !group.is_torn_down())
+    }
+
+    /// Lists every process group that hasn't been torn down, by ID.
+    pub fn groups(&self) -> Vec<Arc<ProcessGroup>> {
+        let mut groups: Vec<_> = self
+            .groups
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .filter(|group| !group.is_torn_down())
+            .collect();
+
+        groups.sort_by_key(|group| group.id());
+        groups
+    }
+
     /// Spawns a process with an existing [Table].
+    ///
+    /// The spawn is audited as requested by the host.
     pub fn spawn_with_table(&self, meta: ProcessMetadata, table: Table) -> Process {
+        self.spawn_audited(meta, table, AuditActor::Host, None, None)
+    }
+
+    fn spawn_audited(
+        &self,
+        meta: ProcessMetadata,
+        table: Table,
+        requester: AuditActor,
+        lump: Option<LumpId>,
+        group: Option<Arc<ProcessGroup>>,
+    ) -> Process {
         // this results in guessable PIDs, but access to PIDs and operations
         // consuming PIDs is limited to the debugging infrastructure, which
         // should not be given to untrusted processes.
@@ -124,32 +353,181 @@ impl ProcessFactory {
         debug!("spawning PID {}: {:?}", pid, meta);
 
         let (log_tx, log_rx) = flume::unbounded();
+        let observers = self.log_observers.clone();
+        let histories = self.log_histories.clone();
+        let history = LogHistory::new(self.log_history_len);
+        histories.lock().insert(pid, history);
+
+        let tap = Arc::new(MessageTap::new(pid, meta.name.clone()));
+        let taps = self.taps.clone();
+        taps.lock().insert(pid, tap.clone());
+        self.start_configured_tap(&tap, pid, &meta);
+
+        let record = ProcessRecord {
+            meta: meta.clone(),
+            requester: requester.clone(),
+            lump,
+            group: group.as_ref().map(|group| group.id()),
+        };
+
+        let records = self.records.clone();
+        records.lock().insert(pid, record);
+        let services = self.services.clone();
 
         tokio::spawn(async move {
             while let Ok(event) = log_rx.recv_async().await {
                 debug!("PID {} log: {:?}", pid, event);
+
+                for observer in observers.iter() {
+                    let _ = observer.send((pid, event.clone()));
+                }
+
+                if let Some(history) = histories.lock().get_mut(&pid) {
+                    history.push(event);
+                }
             }
+
+            // the process's log is closed, so it's gone
+            histories.lock().remove(&pid);
+            taps.lock().remove(&pid);
+            records.lock().remove(&pid);
+            services.lock().retain(|_, owner| *owner != pid);
+        });
+
+        self.audit.record(AuditEventKind::Spawn {
+            pid: pid as u64,
+            name: meta.name.clone(),
+            requester,
+            lump,
         });
 
-        let id = ProcessInfo { pid, log_tx, meta };
+        let id = ProcessInfo {
+            pid,
+            log_tx,
+            meta,
+            tap,
+            group: group.clone(),
+            audit: self.audit.clone(),
+        };
 
-        Process::new(
+        let process = Process::new(
             table,
             id,
             |table| MailboxGroup::new(table),
             |store| store.create_mailbox().unwrap(),
-        )
+        );
+
+        if let Some(group) = group {
+            group.add(&process);
+        }
+
+        process
+    }
+
+    /// Starts recording a new process if its name is configured to be
+    /// recorded.
+    fn start_configured_tap(&self, tap: &MessageTap, pid: ProcessId, meta: &ProcessMetadata) {
+        let config = &self.tap_config;
+        let Some(name) = meta.name.as_ref() else {
+            return;
+        };
+
+        if !config.processes.contains(name) {
+            return;
+        }
+
+        let path = config.recording_path(name, pid, now_millis());
+        if let Some(dir) = path.parent() {
+            if let Err(err) = std::fs::create_dir_all(dir) {
+                error!("Failed to create recording directory {:?}: {:?}", dir, err);
+                return;
+            }
+        }
+
+        if let Err(err) = tap.start(&path, config.limit, config.buffer) {
+            error!("Failed to record PID {}: {:?}", pid, err);
+        }
+    }
+
+    /// Forwards the log events of every process spawned after this call to
+    /// the returned receiver.
+    pub fn observe_logs(&mut self) -> Receiver<(ProcessId, ProcessLogEvent)> {
+        let (tx, rx) = flume::unbounded();
+        self.log_observers.push(tx);
+        rx
+    }
+
+    /// Follows the log of a running process.
+    ///
+    /// Returns the process's buffered recent log events and a receiver for
+    /// every event after them, or `None` if the process is not running.
+    pub fn follow_log(
+        &self,
+        pid: ProcessId,
+    ) -> Option<(Vec<ProcessLogEvent>, Receiver<ProcessLogEvent>)> {
+        self.log_histories
+            .lock()
+            .get_mut(&pid)
+            .map(|history| history.follow())
     }
 
     /// Spawns a process with a new table in this factory's [PostOffice].
+    ///
+    /// The spawn is audited as requested by the host.
     pub fn spawn(&self, meta: ProcessMetadata) -> Process {
         self.spawn_with_table(meta, Table::new(self.post.clone()))
     }
+
+    /// Spawns a process with a new table on behalf of another process or a
+    /// peer, recording who requested it and the lump it runs, if any.
+    pub fn spawn_for(
+        &self,
+        meta: ProcessMetadata,
+        requester: AuditActor,
+        lump: Option<LumpId>,
+    ) -> Process {
+        let table = Table::new(self.post.clone());
+        self.spawn_audited(meta, table, requester, lump, None)
+    }
+
+    /// Spawns a process on behalf of another process.
+    ///
+    /// The child joins the parent's group, if it has one.
+    pub fn spawn_child(
+        &self,
+        meta: ProcessMetadata,
+        parent: &Process,
+        lump: Option<LumpId>,
+    ) -> Process {
+        let info = parent.borrow_info();
+        let table = Table::new(self.post.clone());
+        let group = info.group.clone();
+        self.spawn_audited(meta, table, info.audit_actor(), lump, group)
+    }
+
+    /// Spawns a process into a group, recording who requested it and the
+    /// lump it runs, if any.
+    ///
+    /// If the group has been torn down, the process is killed immediately.
+    pub fn spawn_in_group(
+        &self,
+        meta: ProcessMetadata,
+        requester: AuditActor,
+        lump: Option<LumpId>,
+        group: &Arc<ProcessGroup>,
+    ) -> Process {
+        let table = Table::new(self.post.clone());
+        let group = Some(group.clone());
+        self.spawn_audited(meta, table, requester, lump, group)
+    }
 }
 
 /// Log event emitted by a process.
-#[derive(Clone, Debug, Hash)]
+#[derive(Clone, Debug, Hash, Serialize)]
 pub struct ProcessLogEvent {
+    /// When this event was logged, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+
     /// The level of this log event.
     pub level: ProcessLogLevel,
 
@@ -159,5 +537,20 @@ pub struct ProcessLogEvent {
     /// The main message body of the log event.
     pub content: String,
     // TODO optional source code location?
-    // TODO serializeable timestamp?
+}
+
+impl ProcessLogEvent {
+    /// Creates a new log event timestamped with the current time.
+    pub fn new(
+        level: ProcessLogLevel,
+        module: impl Into<String>,
+        content: impl Into<String>,
+    ) -> Self {
+        Self {
+            timestamp: now_millis(),
+            level,
+            module: module.into(),
+            content: content.into(),
+        }
+    }
 }
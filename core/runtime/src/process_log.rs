@@ -0,0 +1,311 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flume::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::process::{ProcessId, ProcessLogEvent};
+
+/// Configuration for process log history and persistence.
+///
+/// Loaded from the `process_log` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ProcessLogConfig {
+    /// How many recent log events to keep for each process.
+    pub history: usize,
+
+    /// If set, every process log event is appended to this JSONL file.
+    pub file: Option<PathBuf>,
+
+    /// The size in bytes at which the log file is rotated.
+    pub max_file_size: u64,
+
+    /// How many rotated log files to keep besides the current one.
+    pub max_files: usize,
+}
+
+impl Default for ProcessLogConfig {
+    fn default() -> Self {
+        Self {
+            history: 256,
+            file: None,
+            max_file_size: 16 * 1024 * 1024,
+            max_files: 4,
+        }
+    }
+}
+
+/// Gets the current time in milliseconds since the Unix epoch.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// A bounded buffer of a process's recent log events and the followers of
+/// its log.
+pub(crate) struct LogHistory {
+    events: VecDeque<ProcessLogEvent>,
+    capacity: usize,
+    followers: Vec<Sender<ProcessLogEvent>>,
+}
+
+impl LogHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            followers: Vec::new(),
+        }
+    }
+
+    /// Records an event, evicting the oldest one if the buffer is full, and
+    /// forwards it to all followers.
+    pub fn push(&mut self, event: ProcessLogEvent) {
+        self.followers
+            .retain(|follower| follower.send(event.clone()).is_ok());
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+
+        self.events.push_back(event);
+    }
+
+    /// Returns up to `limit` of the most recent buffered events, oldest first.
+    pub fn recent(&self, limit: usize) -> Vec<ProcessLogEvent> {
+        let skip = self.events.len().saturating_sub(limit);
+        self.events.iter().skip(skip).cloned().collect()
+    }
+
+    /// Returns the buffered events and a receiver for every event after them.
+    pub fn follow(&mut self) -> (Vec<ProcessLogEvent>, Receiver<ProcessLogEvent>) {
+        let (tx, rx) = flume::unbounded();
+        self.followers.push(tx);
+        (self.events.iter().cloned().collect(), rx)
+    }
+}
+
+/// A single line of a process log file.
+#[derive(Serialize)]
+struct LogLine<'a> {
+    pid: ProcessId,
+    event: &'a ProcessLogEvent,
+}
+
+/// Spawns a thread that appends every received log event to a JSONL file.
+///
+/// Returns an error if the file can't be opened.
+pub fn spawn_file_sink(
+    config: &ProcessLogConfig,
+    logs: Receiver<(ProcessId, ProcessLogEvent)>,
+) -> std::io::Result<()> {
+    let path = config.file.clone().expect("process log file path unset");
+    let mut file = RotatingFile::open(path, config.max_file_size, config.max_files)?;
+    info!("Writing process logs to {:?}", file.path);
+
+    std::thread::spawn(move || {
+        while let Ok((pid, event)) = logs.recv() {
+            let line = serde_json::to_string(&LogLine { pid, event: &event }).unwrap();
+
+            if let Err(err) = file.write_line(&line) {
+                error!("Failed to write process log file: {:?}", err);
+                return;
+            }
+
+            // only flush when caught up so bursts of logs are batched
+            if logs.is_empty() {
+                if let Err(err) = file.flush() {
+                    error!("Failed to flush process log file: {:?}", err);
+                    return;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// An append-only file that's rotated once it grows past a maximum size.
+///
+/// Rotated files are named after the original with a numeric suffix, where
+/// `.1` is the most recent.
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_size: u64, max_files: usize) -> std::io::Result<Self> {
+        let (file, size) = Self::open_file(&path)?;
+
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn open_file(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((BufWriter::new(file), size))
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        path.into()
+    }
+
+    fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.file.write_all(b"\n")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            // shift each rotated file up by one, dropping the oldest
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        (self.file, self.size) = Self::open_file(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_schema::ProcessLogLevel;
+
+    fn event(content: &str) -> ProcessLogEvent {
+        ProcessLogEvent::new(ProcessLogLevel::Info, "test", content)
+    }
+
+    fn contents(events: &[ProcessLogEvent]) -> Vec<&str> {
+        events.iter().map(|event| event.content.as_str()).collect()
+    }
+
+    #[test]
+    fn history_evicts_oldest() {
+        let mut history = LogHistory::new(2);
+        for content in ["a", "b", "c"] {
+            history.push(event(content));
+        }
+
+        let (events, _) = history.follow();
+        assert_eq!(contents(&events), ["b", "c"]);
+    }
+
+    #[test]
+    fn recent_returns_newest_events() {
+        let mut history = LogHistory::new(8);
+        for content in ["a", "b", "c"] {
+            history.push(event(content));
+        }
+
+        assert_eq!(contents(&history.recent(2)), ["b", "c"]);
+        assert_eq!(contents(&history.recent(8)), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn follow_delivers_history_then_new_events() {
+        let mut history = LogHistory::new(8);
+        history.push(event("before"));
+
+        let (events, rx) = history.follow();
+        history.push(event("after"));
+
+        assert_eq!(contents(&events), ["before"]);
+        assert_eq!(rx.try_recv().unwrap().content, "after");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropped_followers_are_removed() {
+        let mut history = LogHistory::new(0);
+        let (_, rx) = history.follow();
+        drop(rx);
+
+        history.push(event("nobody listening"));
+        assert!(history.followers.is_empty());
+    }
+
+    #[test]
+    fn file_rotates_by_size() {
+        let dir = std::env::temp_dir().join(format!("hearth-log-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("processes.jsonl");
+
+        let mut file = RotatingFile::open(path.clone(), 16, 2).unwrap();
+        for line in ["0123456789", "abcdefghij", "ABCDEFGHIJ", "9876543210"] {
+            file.write_line(line).unwrap();
+        }
+        file.flush().unwrap();
+
+        let read = |path: PathBuf| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(path.clone()), "9876543210\n");
+        assert_eq!(read(file.rotated_path(1)), "ABCDEFGHIJ\n");
+        assert_eq!(read(file.rotated_path(2)), "abcdefghij\n");
+        assert!(!file.rotated_path(3).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
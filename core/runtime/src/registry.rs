@@ -19,14 +19,24 @@
 use std::{
     collections::{hash_map, HashMap},
     sync::Arc,
+    time::Duration,
 };
 
 use async_trait::async_trait;
-use flue::{CapabilityHandle, Mailbox, Permissions, PostOffice, Table};
+use flue::{
+    CapabilityHandle, CapabilityRef, Mailbox, MailboxGroup, OwnedCapability, Permissions,
+    PostOffice, Table, TableSignal,
+};
+use hearth_schema::audit::{AuditActor, AuditEventKind};
 use hearth_schema::registry::*;
-use tracing::warn;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
 
-use crate::utils::{RequestInfo, RequestResponseProcess, ResponseInfo};
+use crate::process::Process;
+use crate::utils::{
+    recv_timeout, MessageInfo, RequestInfo, RequestResponseProcess, ResponseInfo, SinkProcess,
+};
 
 /// A builder to initialize the service entries in a [Registry], since they
 /// can't be modified once the registry has started.
@@ -108,6 +118,24 @@ impl RequestResponseProcess for Registry {
                     }
                 }
             }
+            Watch { name } => {
+                // this registry is immutable, so the watched service is
+                // either already here or will never arrive
+                let cap = self.services.get(name).map(|handle| {
+                    request.process.with_table(|table| {
+                        table.inc_ref(*handle).unwrap();
+                        table.wrap_handle(*handle).unwrap()
+                    })
+                });
+
+                ResponseInfo {
+                    data: RegistryResponse::Watch {
+                        name: name.clone(),
+                        present: cap.is_some(),
+                    },
+                    caps: cap.into_iter().collect(),
+                }
+            }
             Register { .. } => ResponseInfo {
                 data: RegistryResponse::Register(None),
                 caps: vec![],
@@ -121,3 +149,674 @@ impl RequestResponseProcess for Registry {
         }
     }
 }
+
+/// Chooses which names a [RegistryView] exposes and where its own services
+/// are registered.
+///
+/// Name patterns match a name exactly, or if they end with `*`, match every
+/// name starting with the rest of the pattern.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct ViewPolicy {
+    /// The namespace that services registered through the view are stored
+    /// under, such as `peer.alice.`.
+    pub prefix: String,
+
+    /// Patterns of names outside of the view's namespace to expose. If
+    /// unset, every name in the wrapped registry is exposed, but other
+    /// views' services are only exposed when explicitly allowed.
+    pub allow: Option<Vec<String>>,
+
+    /// Patterns of names to hide, even if they're allowed.
+    pub deny: Vec<String>,
+}
+
+impl ViewPolicy {
+    /// Creates a policy that registers services under `prefix` and exposes
+    /// every other name.
+    pub fn new(prefix: impl Into<String>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Tests if a name in the wrapped registry is visible.
+    pub fn exposes(&self, name: &str) -> bool {
+        !matches_any(&self.deny, name)
+            && self
+                .allow
+                .as_ref()
+                .map_or(true, |allow| matches_any(allow, name))
+    }
+
+    /// Tests if a service registered through another view is visible.
+    pub fn shares(&self, name: &str) -> bool {
+        !matches_any(&self.deny, name)
+            && self
+                .allow
+                .as_ref()
+                .map_or(false, |allow| matches_any(allow, name))
+    }
+
+    /// Gets the full name of a service in the view's namespace.
+    ///
+    /// Names that already start with the prefix are left as-is.
+    pub fn own_name(&self, name: &str) -> String {
+        if name.starts_with(&self.prefix) {
+            name.to_string()
+        } else {
+            format!("{}{}", self.prefix, name)
+        }
+    }
+}
+
+/// Tests if a name matches any of a [ViewPolicy]'s patterns.
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => pattern == name,
+        })
+}
+
+/// The services registered through a group of [RegistryView]s.
+///
+/// Views sharing a store can see each other's services if their policies
+/// explicitly allow the other views' namespaces.
+#[derive(Clone, Default)]
+pub struct ViewStore {
+    inner: Arc<Mutex<ViewStoreInner>>,
+}
+
+impl ViewStore {
+    /// Adds a service under its full name, such as `peer.alice.Service`, as
+    /// if it had been registered through a view with that namespace.
+    ///
+    /// Pending watchers of the name aren't notified, so this is meant for
+    /// setting up a store before its views are served.
+    pub fn insert(&self, name: String, service: OwnedCapability) {
+        let mut inner = self.inner.lock();
+        let registration = inner.next_registration;
+        inner.next_registration += 1;
+        inner.services.insert(name, (registration, service));
+    }
+
+    /// Removes every registered service and drops every pending watcher.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock();
+        inner.services.clear();
+        inner.watchers.clear();
+    }
+}
+
+#[derive(Default)]
+struct ViewStoreInner {
+    /// Registered services by their full names, with the number of the
+    /// registration that added them.
+    services: HashMap<String, (u64, OwnedCapability)>,
+
+    /// The number of the next registration.
+    next_registration: u64,
+
+    /// Watchers of services by full name, with the name that they asked for.
+    watchers: HashMap<String, Vec<(String, OwnedCapability)>>,
+}
+
+/// How long a [RegistryView] waits for its parent registry to list its
+/// services.
+const VIEW_LIST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A scoped, mutable view of another registry.
+///
+/// Services registered through the view are stored in a [ViewStore] under
+/// the view's namespace, so that views with different namespaces never
+/// collide and the wrapped registry is never modified. Lookups check the
+/// view's namespace first, then other names exposed by its [ViewPolicy] in
+/// the store and the wrapped registry.
+///
+/// Names hidden by the policy look missing rather than forbidden, so that
+/// users of the view can't probe for them.
+///
+/// Registrations are audited. They're attributed to the view's process
+/// unless the view is made for a known requester with
+/// [Self::with_requester].
+pub struct RegistryView {
+    parent: OwnedCapability,
+    policy: ViewPolicy,
+    store: ViewStore,
+    requester: Option<AuditActor>,
+}
+
+impl RegistryView {
+    /// Creates a view of the registry `parent` that registers services into
+    /// `store`.
+    pub fn new(parent: OwnedCapability, policy: ViewPolicy, store: ViewStore) -> Self {
+        Self {
+            parent,
+            policy,
+            store,
+            requester: None,
+        }
+    }
+
+    /// Attributes registrations through this view to `requester`.
+    pub fn with_requester(mut self, requester: AuditActor) -> Self {
+        self.requester = Some(requester);
+        self
+    }
+
+    /// Finds a service registered in the store that this view can see.
+    fn lookup(&self, name: &str) -> Option<OwnedCapability> {
+        let inner = self.store.inner.lock();
+
+        if let Some((_, service)) = inner.services.get(&self.policy.own_name(name)) {
+            return Some(service.clone());
+        }
+
+        if !self.policy.shares(name) {
+            return None;
+        }
+
+        inner.services.get(name).map(|(_, service)| service.clone())
+    }
+
+    /// Removes a registered service from the store once it goes down, unless
+    /// it has been replaced by then.
+    ///
+    /// Does nothing if the service can't be monitored.
+    fn remove_when_down(
+        &self,
+        post: Arc<PostOffice>,
+        name: String,
+        registration: u64,
+        service: &CapabilityRef<'_>,
+    ) {
+        if !service.get_permissions().contains(Permissions::MONITOR) {
+            return;
+        }
+
+        let service = service.to_owned();
+        let store = self.store.clone();
+        tokio::spawn(async move {
+            let table = Table::new(post);
+            let group = MailboxGroup::new(&table);
+            let mailbox = group.create_mailbox().unwrap();
+            let service = table.import_owned(service).unwrap();
+            let service = table.wrap_handle(service).unwrap();
+            service.monitor(&mailbox).unwrap();
+            drop(service);
+
+            // nothing else can reach this mailbox, so any signal is the down
+            mailbox.recv(|_| ()).await;
+
+            let mut inner = store.inner.lock();
+            if inner.services.get(&name).map(|(current, _)| *current) == Some(registration) {
+                debug!("Removing {:?} from registry views since it went down", name);
+                inner.services.remove(&name);
+            }
+        });
+    }
+
+    /// Lists the names of the services that this view can see in the
+    /// parent registry.
+    async fn list_parent(&self, table: &Table, process: &Process) -> Vec<String> {
+        let parent = table.import_owned(self.parent.clone()).unwrap();
+        let parent = table.wrap_handle(parent).unwrap();
+        let reply = process.borrow_group().create_mailbox().unwrap();
+        let reply_cap = reply.export(Permissions::SEND).unwrap();
+
+        if parent.get_permissions().contains(Permissions::MONITOR) {
+            parent.monitor(&reply).unwrap();
+        }
+
+        send_json(&parent, &RegistryRequest::List, &[&reply_cap]).await;
+
+        let on_recv = |signal: TableSignal<'_>| match signal {
+            TableSignal::Message { data, .. } => serde_json::from_slice(data).ok(),
+            TableSignal::Down { .. } => None,
+        };
+
+        match recv_timeout(&reply, VIEW_LIST_TIMEOUT, on_recv).await {
+            Ok(Some(Some(RegistryResponse::List(names)))) => names,
+            _ => {
+                debug!("Registry view failed to list its parent registry");
+                Vec::new()
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl SinkProcess for RegistryView {
+    type Message = RegistryRequest;
+
+    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, RegistryRequest>) {
+        let Some(reply) = message.caps.first() else {
+            debug!("Request to {:?} has no reply address", message.label);
+            return;
+        };
+
+        let table = message.process.borrow_table();
+        let import = |cap: OwnedCapability| {
+            let handle = table.import_owned(cap).unwrap();
+            table.wrap_handle(handle).unwrap()
+        };
+
+        use RegistryRequest::*;
+        match message.data {
+            Get { name } => {
+                if let Some(service) = self.lookup(&name) {
+                    let service = import(service);
+                    send_json(reply, &RegistryResponse::Get(true), &[&service]).await;
+                } else if self.policy.exposes(&name) {
+                    // the parent registry replies directly
+                    let parent = import(self.parent.clone());
+                    send_json(&parent, &Get { name }, &[reply]).await;
+                } else {
+                    send_json(reply, &RegistryResponse::Get(false), &[]).await;
+                }
+            }
+            Watch { name } => {
+                if let Some(service) = self.lookup(&name) {
+                    let response = RegistryResponse::Watch {
+                        name,
+                        present: true,
+                    };
+
+                    let service = import(service);
+                    send_json(reply, &response, &[&service]).await;
+                } else if self.policy.exposes(&name) {
+                    let parent = import(self.parent.clone());
+                    send_json(&parent, &Watch { name }, &[reply]).await;
+                } else {
+                    // wait for the name to be registered through this view
+                    let full_name = self.policy.own_name(&name);
+                    let watcher = (name, reply.to_owned());
+                    let mut inner = self.store.inner.lock();
+                    inner.watchers.entry(full_name).or_default().push(watcher);
+                }
+            }
+            Register { name } => {
+                let Some(service) = message.caps.get(1) else {
+                    warn!("attempted to register {:?} without a service", name);
+                    send_json(reply, &RegistryResponse::Register(None), &[]).await;
+                    return;
+                };
+
+                let name = self.policy.own_name(&name);
+                debug!("{:?} registered {:?}", message.label, name);
+
+                let requester = self
+                    .requester
+                    .clone()
+                    .unwrap_or_else(|| message.process.borrow_info().audit_actor());
+
+                let audit = message.runtime.process_factory.audit_log();
+                audit.record(AuditEventKind::Register {
+                    name: name.clone(),
+                    pid: None,
+                    requester,
+                });
+
+                let (replaced, watchers, registration) = {
+                    let mut inner = self.store.inner.lock();
+                    let registration = inner.next_registration;
+                    inner.next_registration += 1;
+
+                    let entry = (registration, service.to_owned());
+                    let replaced = inner.services.insert(name.clone(), entry);
+                    let watchers = inner.watchers.remove(&name).unwrap_or_default();
+                    (replaced.is_some(), watchers, registration)
+                };
+
+                // the replaced process no longer serves this name
+                let factory = &message.runtime.process_factory;
+                factory.remove_service(&name);
+
+                let post = message.runtime.post.clone();
+                self.remove_when_down(post, name.clone(), registration, service);
+
+                for (name, watcher) in watchers {
+                    let response = RegistryResponse::Watch {
+                        name,
+                        present: true,
+                    };
+
+                    send_json(&import(watcher), &response, &[service]).await;
+                }
+
+                let response = RegistryResponse::Register(Some(replaced));
+                send_json(reply, &response, &[]).await;
+            }
+            List => {
+                let mut names = self.list_parent(table, message.process).await;
+                names.retain(|name| self.policy.exposes(name));
+
+                // own services are listed by the names they were registered
+                // with, and other views' services by their full names
+                names.extend(self.store.inner.lock().services.keys().filter_map(|name| {
+                    match name.strip_prefix(&self.policy.prefix) {
+                        Some(own) if !self.policy.prefix.is_empty() => Some(own.to_string()),
+                        _ if self.policy.shares(name) => Some(name.clone()),
+                        _ => None,
+                    }
+                }));
+
+                names.sort();
+                names.dedup();
+                send_json(reply, &RegistryResponse::List(names), &[]).await;
+            }
+        }
+    }
+}
+
+/// Sends a JSON message, logging any errors.
+async fn send_json(cap: &CapabilityRef<'_>, data: &impl Serialize, caps: &[&CapabilityRef<'_>]) {
+    let data = serde_json::to_vec(data).unwrap();
+    if let Err(err) = cap.send(&data, caps).await {
+        debug!("Registry view send error: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::process::ProcessMetadata;
+    use crate::testing::{MemoryFs, TestRuntime, TestRuntimeBuilder};
+    use crate::utils::ProcessRunner;
+
+    #[test]
+    fn get_and_list_services() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryFs::new([("readme.txt", "")]));
+        let runtime = builder.build();
+
+        assert!(runtime.get_service("hearth.fs.Filesystem").is_some());
+        assert!(runtime.get_service("hearth.Missing").is_none());
+
+        let registry = runtime.registry();
+        let (response, _) = runtime.request(&registry, &RegistryRequest::List, &[]);
+        let RegistryResponse::List(names) = response else {
+            panic!("expected list response, got {:?}", response);
+        };
+
+        assert_eq!(names, ["hearth.fs.Filesystem"]);
+    }
+
+    #[test]
+    fn watch_responds_immediately() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryFs::new([("readme.txt", "")]));
+        let runtime = builder.build();
+        let registry = runtime.registry();
+
+        let request = RegistryRequest::Watch {
+            name: "hearth.fs.Filesystem".to_string(),
+        };
+
+        let (response, caps) = runtime.request(&registry, &request, &[]);
+        assert!(matches!(
+            response,
+            RegistryResponse::Watch { ref name, present: true } if name == "hearth.fs.Filesystem"
+        ));
+        assert_eq!(caps.len(), 1);
+
+        let request = RegistryRequest::Watch {
+            name: "hearth.Missing".to_string(),
+        };
+
+        let (response, caps) = runtime.request(&registry, &request, &[]);
+        assert!(matches!(
+            response,
+            RegistryResponse::Watch { present: false, .. }
+        ));
+        assert!(caps.is_empty());
+    }
+
+    #[test]
+    fn register_is_refused() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let registry = runtime.registry();
+
+        let request = RegistryRequest::Register {
+            name: "hearth.Test".to_string(),
+        };
+
+        let (response, _) = runtime.request(&registry, &request, &[&registry]);
+        assert!(matches!(response, RegistryResponse::Register(None)));
+    }
+
+    fn spawn_view<'a>(
+        runtime: &'a TestRuntime,
+        policy: ViewPolicy,
+        store: &ViewStore,
+    ) -> CapabilityRef<'a> {
+        let view = RegistryView::new(runtime.registry().to_owned(), policy, store.clone());
+        let inner = runtime.runtime().clone();
+
+        runtime.block_on(async {
+            let process = inner.process_factory.spawn(ProcessMetadata::default());
+            let cap = process
+                .borrow_parent()
+                .export_to(Permissions::SEND, runtime.process().borrow_table())
+                .unwrap();
+
+            tokio::spawn(async move {
+                view.run("RegistryView".to_string(), inner, &process).await;
+            });
+
+            cap
+        })
+    }
+
+    fn get(runtime: &TestRuntime, view: &CapabilityRef<'_>, name: &str) -> bool {
+        let request = RegistryRequest::Get {
+            name: name.to_string(),
+        };
+
+        let (response, caps) = runtime.request(view, &request, &[]);
+        match response {
+            RegistryResponse::Get(present) => {
+                assert_eq!(caps.len(), present as usize);
+                present
+            }
+            other => panic!("expected get response, got {:?}", other),
+        }
+    }
+
+    fn register(runtime: &TestRuntime, view: &CapabilityRef<'_>, name: &str) -> Option<bool> {
+        let service = runtime.mailbox();
+        let service = service.capability(Permissions::SEND);
+        let request = RegistryRequest::Register {
+            name: name.to_string(),
+        };
+
+        let (response, _) = runtime.request(view, &request, &[&service]);
+        let RegistryResponse::Register(result) = response else {
+            panic!("expected register response, got {:?}", response);
+        };
+
+        result
+    }
+
+    fn list(runtime: &TestRuntime, view: &CapabilityRef<'_>) -> Vec<String> {
+        let (response, _) = runtime.request(view, &RegistryRequest::List, &[]);
+        let RegistryResponse::List(names) = response else {
+            panic!("expected list response, got {:?}", response);
+        };
+
+        names
+    }
+
+    #[test]
+    fn views_do_not_collide() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryFs::new([("readme.txt", "")]));
+        let runtime = builder.build();
+
+        let store = ViewStore::default();
+        let alice = spawn_view(&runtime, ViewPolicy::new("peer.alice."), &store);
+        let bob = spawn_view(&runtime, ViewPolicy::new("peer.bob."), &store);
+
+        assert_eq!(register(&runtime, &alice, "Game"), Some(false));
+        assert_eq!(register(&runtime, &bob, "Game"), Some(false));
+        assert_eq!(register(&runtime, &alice, "Game"), Some(true));
+
+        assert!(get(&runtime, &alice, "Game"));
+        assert!(get(&runtime, &bob, "Game"));
+
+        // global services are still visible and are never overwritten
+        assert!(get(&runtime, &alice, "hearth.fs.Filesystem"));
+        assert_eq!(
+            register(&runtime, &alice, "hearth.fs.Filesystem"),
+            Some(false)
+        );
+        assert!(runtime.get_service("hearth.fs.Filesystem").is_some());
+        assert!(runtime.get_service("peer.alice.Game").is_none());
+
+        // each view only sees its own namespace
+        assert!(!get(&runtime, &bob, "peer.alice.Game"));
+        assert_eq!(list(&runtime, &alice), ["Game", "hearth.fs.Filesystem"]);
+    }
+
+    #[test]
+    fn shared_namespaces_are_visible() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let store = ViewStore::default();
+        let alice = spawn_view(&runtime, ViewPolicy::new("peer.alice."), &store);
+
+        let policy = ViewPolicy {
+            prefix: "peer.bob.".to_string(),
+            allow: Some(vec!["peer.alice.*".to_string()]),
+            deny: vec![],
+        };
+
+        let bob = spawn_view(&runtime, policy, &store);
+
+        register(&runtime, &alice, "Game");
+        assert!(get(&runtime, &bob, "peer.alice.Game"));
+        assert_eq!(list(&runtime, &bob), ["peer.alice.Game"]);
+    }
+
+    #[test]
+    fn registrations_are_audited() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let store = ViewStore::default();
+        let alice = spawn_view(&runtime, ViewPolicy::new("peer.alice."), &store);
+        register(&runtime, &alice, "Game");
+
+        let registered: Vec<_> = runtime
+            .runtime()
+            .process_factory
+            .audit_log()
+            .query(&Default::default(), None)
+            .into_iter()
+            .filter_map(|event| match event.kind {
+                // host services are registered with their PIDs
+                AuditEventKind::Register {
+                    name, pid: None, ..
+                } => Some(name),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(registered, ["peer.alice.Game"]);
+    }
+
+    #[test]
+    fn inserted_services_shadow_the_parent() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryFs::new([("readme.txt", "")]));
+        let runtime = builder.build();
+
+        let store = ViewStore::default();
+        let service = runtime.mailbox();
+        let service_cap = service.capability(Permissions::SEND).to_owned();
+        store.insert("ipc.hearth.fs.Filesystem".to_string(), service_cap);
+        let view = spawn_view(&runtime, ViewPolicy::new("ipc."), &store);
+
+        let request = RegistryRequest::Get {
+            name: "hearth.fs.Filesystem".to_string(),
+        };
+
+        let (response, caps) = runtime.request(&view, &request, &[]);
+        assert!(matches!(response, RegistryResponse::Get(true)));
+
+        // requests for the name reach the inserted service instead
+        runtime.send(&caps[0], &"hello", &[]);
+        let (message, _): (String, _) = service.recv_json();
+        assert_eq!(message, "hello");
+    }
+
+    #[test]
+    fn denied_names_are_not_found() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryFs::new([("readme.txt", "")]));
+        let runtime = builder.build();
+
+        let policy = ViewPolicy {
+            prefix: "peer.alice.".to_string(),
+            allow: None,
+            deny: vec!["hearth.fs.*".to_string()],
+        };
+
+        let view = spawn_view(&runtime, policy, &ViewStore::default());
+        assert!(!get(&runtime, &view, "hearth.fs.Filesystem"));
+        assert!(list(&runtime, &view).is_empty());
+    }
+
+    #[test]
+    fn watch_waits_for_own_registration() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let policy = ViewPolicy {
+            prefix: "peer.alice.".to_string(),
+            allow: Some(vec![]),
+            deny: vec![],
+        };
+
+        let view = spawn_view(&runtime, policy, &ViewStore::default());
+        let watcher = runtime.mailbox();
+        let watcher_cap = watcher.capability(Permissions::SEND);
+        let request = RegistryRequest::Watch {
+            name: "Game".to_string(),
+        };
+
+        runtime.send(&view, &request, &[&watcher_cap]);
+        register(&runtime, &view, "Game");
+
+        let (response, caps): (RegistryResponse, _) = watcher.recv_json();
+        assert!(matches!(
+            response,
+            RegistryResponse::Watch { ref name, present: true } if name == "Game"
+        ));
+        assert_eq!(caps.len(), 1);
+    }
+
+    #[test]
+    fn dead_services_are_removed() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let view = spawn_view(
+            &runtime,
+            ViewPolicy::new("peer.alice."),
+            &ViewStore::default(),
+        );
+
+        let service = runtime.mailbox();
+        let service_cap = service.capability(Permissions::SEND | Permissions::MONITOR);
+        let request = RegistryRequest::Register {
+            name: "Game".to_string(),
+        };
+
+        let (response, _) = runtime.request(&view, &request, &[&service_cap]);
+        assert!(matches!(response, RegistryResponse::Register(Some(false))));
+        assert!(get(&runtime, &view, "Game"));
+
+        drop(service_cap);
+        drop(service);
+        runtime.settle();
+        assert!(!get(&runtime, &view, "Game"));
+    }
+}
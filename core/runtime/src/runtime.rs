@@ -28,14 +28,27 @@ use std::sync::Arc;
 
 use async_trait::async_trait;
 use flue::PostOffice;
-use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
-use tracing::{debug, error, warn};
+use flume::Receiver;
+use hearth_schema::audit::{AuditActor, AuditEventKind, AUDIT_SERVICE};
+use hearth_schema::group::PROCESS_GROUPS_SERVICE;
+use hearth_schema::lump::LUMP_INFO_SERVICE;
+use hearth_schema::process::PROCESS_INSPECTOR_SERVICE;
+use hearth_schema::tap::TAP_SERVICE;
+use hearth_schema::PeerRole;
+use tokio::sync::oneshot;
+use tracing::{debug, error, warn, Instrument};
 
 use crate::asset::{AssetLoader, AssetStore};
-use crate::lump::LumpStoreImpl;
-use crate::process::{Process, ProcessFactory, ProcessMetadata};
+use crate::audit::{self, AuditConfig, AuditLog, AuditService};
+use crate::group::{ProcessGroupsConfig, ProcessGroupsService};
+use crate::inspect::{ProcessInspector, ProcessInspectorConfig};
+use crate::lump::{LumpInfoService, LumpStoreImpl};
+use crate::process::{Process, ProcessFactory, ProcessId, ProcessLogEvent, ProcessMetadata};
+use crate::process_log::{spawn_file_sink, ProcessLogConfig};
 use crate::registry::RegistryBuilder;
-use crate::utils::ProcessRunner;
+use crate::supervisor::{ChildSpec, RestartPolicy, Supervisor};
+use crate::tap::{MessageTapConfig, TapService};
+use crate::utils::{ProcessRunner, ServiceRunner};
 
 /// Interface trait for plugins to the Hearth runtime.
 ///
@@ -75,9 +88,7 @@ pub struct RuntimeBuilder {
     process_factory: ProcessFactory,
     registry_builder: RegistryBuilder,
     asset_store: AssetStore,
-    service_num: usize,
-    service_start_tx: UnboundedSender<String>,
-    service_start_rx: UnboundedReceiver<String>,
+    startup: StartupBarrier,
 }
 
 impl RuntimeBuilder {
@@ -85,12 +96,11 @@ impl RuntimeBuilder {
     pub fn new(config_file: toml::Table) -> Self {
         let lump_store = Arc::new(LumpStoreImpl::new());
         let asset_store = AssetStore::new(lump_store.clone());
-        let (service_start_tx, service_start_rx) = unbounded_channel();
         let post = PostOffice::new();
         let process_factory = ProcessFactory::new(post.clone());
         let registry_builder = RegistryBuilder::new(post.clone());
 
-        Self {
+        let mut builder = Self {
             config_file,
             plugins: Default::default(),
             plugin_order: Default::default(),
@@ -101,9 +111,140 @@ impl RuntimeBuilder {
             process_factory,
             registry_builder,
             asset_store,
-            service_num: 0,
-            service_start_tx,
-            service_start_rx,
+            startup: StartupBarrier::default(),
+        };
+
+        builder.configure_process_logs();
+        builder.configure_audit();
+        builder.configure_message_tap();
+        builder.configure_process_groups();
+        builder.configure_process_inspector();
+
+        // lumps are addressed by their contents, so describing them is safe
+        let mut meta = LumpInfoService::get_process_metadata();
+        meta.name = Some(LUMP_INFO_SERVICE.to_string());
+        builder.add_service(LUMP_INFO_SERVICE.to_string(), meta, LumpInfoService);
+
+        builder
+    }
+
+    /// Applies the `audit` config table to the process factory's audit log.
+    fn configure_audit(&mut self) {
+        let config = self
+            .load_config::<AuditConfig>("audit")
+            .unwrap_or_else(|err| {
+                debug!("Using default audit config: {}", err);
+                AuditConfig::default()
+            });
+
+        let log = Arc::new(AuditLog::new(config.history));
+
+        if let Some(path) = config.file.clone() {
+            if let Err(err) = audit::spawn_file_sink(path, &log) {
+                error!("Failed to open audit log file: {:?}", err);
+            }
+        }
+
+        self.process_factory.set_audit_log(log);
+
+        if config.public {
+            let mut meta = AuditService::get_process_metadata();
+            meta.name = Some(AUDIT_SERVICE.to_string());
+            self.add_service(AUDIT_SERVICE.to_string(), meta, AuditService);
+        }
+    }
+
+    /// Applies the `message_tap` config table to the process factory.
+    fn configure_message_tap(&mut self) {
+        let config = self
+            .load_config::<MessageTapConfig>("message_tap")
+            .unwrap_or_else(|err| {
+                debug!("Using default message tap config: {}", err);
+                MessageTapConfig::default()
+            });
+
+        let service = config.service;
+        self.process_factory.set_tap_config(config);
+
+        if service {
+            let mut meta = TapService::get_process_metadata();
+            meta.name = Some(TAP_SERVICE.to_string());
+            self.add_service(TAP_SERVICE.to_string(), meta, TapService);
+        }
+    }
+
+    /// Registers the process groups service if the `process_groups` config
+    /// table enables it.
+    fn configure_process_groups(&mut self) {
+        let config = self
+            .load_config::<ProcessGroupsConfig>("process_groups")
+            .unwrap_or_else(|err| {
+                debug!("Using default process groups config: {}", err);
+                ProcessGroupsConfig::default()
+            });
+
+        if config.service {
+            let mut meta = ProcessGroupsService::get_process_metadata();
+            meta.name = Some(PROCESS_GROUPS_SERVICE.to_string());
+            self.add_service(
+                PROCESS_GROUPS_SERVICE.to_string(),
+                meta,
+                ProcessGroupsService,
+            );
+        }
+    }
+
+    /// Registers the process inspector if the `process_inspector` config
+    /// table enables it.
+    fn configure_process_inspector(&mut self) {
+        let config = self
+            .load_config::<ProcessInspectorConfig>("process_inspector")
+            .unwrap_or_else(|err| {
+                debug!("Using default process inspector config: {}", err);
+                ProcessInspectorConfig::default()
+            });
+
+        if config.service {
+            let mut meta = ProcessInspector::get_process_metadata();
+            meta.name = Some(PROCESS_INSPECTOR_SERVICE.to_string());
+            self.add_service(
+                PROCESS_INSPECTOR_SERVICE.to_string(),
+                meta,
+                ProcessInspector,
+            );
+        }
+    }
+
+    /// Records the registration of a host service in the audit log and in
+    /// the process factory's services.
+    fn audit_registration(&self, name: &str, process: &Process) {
+        let pid = process.borrow_info().pid;
+        self.process_factory.set_service(name.to_string(), pid);
+        self.process_factory
+            .audit_log()
+            .record(AuditEventKind::Register {
+                name: name.to_string(),
+                pid: Some(pid as u64),
+                requester: AuditActor::Host,
+            });
+    }
+
+    /// Applies the `process_log` config table to the process factory.
+    fn configure_process_logs(&mut self) {
+        let config = self
+            .load_config::<ProcessLogConfig>("process_log")
+            .unwrap_or_else(|err| {
+                debug!("Using default process log config: {}", err);
+                ProcessLogConfig::default()
+            });
+
+        self.process_factory.set_log_history_len(config.history);
+
+        if config.file.is_some() {
+            let logs = self.process_factory.observe_logs();
+            if let Err(err) = spawn_file_sink(&config, logs) {
+                error!("Failed to open process log file: {:?}", err);
+            }
         }
     }
 
@@ -194,24 +335,101 @@ impl RuntimeBuilder {
             return self;
         }
 
-        let service_start_tx = self.service_start_tx.clone();
-        self.service_num += 1;
+        let started = self.startup.register(name.clone());
 
         let ctx = self.process_factory.spawn(meta);
         self.registry_builder.add(name.clone(), ctx.borrow_parent());
+        self.audit_registration(&name, &ctx);
         self.services.insert(name.clone());
 
+        self.add_runner(move |runtime| {
+            let span = ctx.span();
+            tokio::spawn(
+                async move {
+                    debug!("Spawning '{}' service", name);
+                    let _ = started.send(());
+                    process.run(name, runtime, &ctx).await;
+                }
+                .instrument(span),
+            );
+        });
+
+        self
+    }
+
+    /// Adds a service that is restarted by a supervisor when it exits or
+    /// panics.
+    ///
+    /// The factory is called to create a fresh process runner for each
+    /// restart. The service keeps its process across restarts, so its
+    /// registry entry stays valid.
+    pub fn add_supervised_service<R, F>(
+        &mut self,
+        name: String,
+        meta: ProcessMetadata,
+        policy: RestartPolicy,
+        factory: F,
+    ) -> &mut Self
+    where
+        R: ProcessRunner + 'static,
+        F: Fn() -> R + Send + Sync + 'static,
+    {
+        let mut supervisor = Supervisor::new(name.clone(), policy);
+        supervisor.add_child(ChildSpec::new(name, meta, factory));
+        self.add_supervisor(supervisor)
+    }
+
+    /// Adds a supervisor, registering each of its children as a service.
+    ///
+    /// Logs an error and skips any child whose service name is taken. Logs
+    /// an error if the supervisor gives up on its children.
+    pub fn add_supervisor(&mut self, mut supervisor: Supervisor) -> &mut Self {
+        let mut names = Vec::new();
+        supervisor.children.retain_mut(|child| {
+            if self.services.contains(&child.name) {
+                error!("Service name {} is taken", child.name);
+                return false;
+            }
+
+            let process = self.process_factory.spawn(child.meta.clone());
+            self.registry_builder
+                .add(child.name.clone(), process.borrow_parent());
+            self.audit_registration(&child.name, &process);
+            self.services.insert(child.name.clone());
+            names.push(child.name.clone());
+            child.process = Some(process);
+            true
+        });
+
+        let started: Vec<_> = names
+            .into_iter()
+            .map(|name| (self.startup.register(name.clone()), name))
+            .collect();
+
         self.add_runner(move |runtime| {
             tokio::spawn(async move {
-                debug!("Spawning '{}' service", name);
-                let _ = service_start_tx.send(name.clone());
-                process.run(name, runtime, &ctx).await;
+                for (started, name) in started {
+                    debug!("Spawning supervised '{}' service", name);
+                    let _ = started.send(());
+                }
+
+                if let Err(err) = supervisor.run(runtime).await {
+                    error!("Supervisor failed: {:?}", err);
+                }
             });
         });
 
         self
     }
 
+    /// Forwards the log events of every process spawned after this call to
+    /// the returned receiver, tagged with their [ProcessId].
+    ///
+    /// Process logs are still printed as usual.
+    pub fn observe_process_logs(&mut self) -> Receiver<(ProcessId, ProcessLogEvent)> {
+        self.process_factory.observe_logs()
+    }
+
     /// Adds a new asset loader.
     ///
     /// Logs an error event if the asset loader has already been added.
@@ -293,26 +511,57 @@ impl RuntimeBuilder {
             runner(runtime.clone());
         }
 
-        let service_num = self.service_num;
-        let mut service_rx = self.service_start_rx;
-        debug!("Waiting for {} services to start...", service_num);
-        for i in 0..service_num {
-            let name = service_rx.recv().await.expect(
-                "all instances of service_start_tx dropped while waiting for all services to start",
-            );
+        self.startup.wait().await;
+
+        runtime
+    }
+}
+
+/// Waits for every service added to a [RuntimeBuilder] to be spawned.
+///
+/// Services are awaited in the order that they were added, so that startup
+/// logs are the same from run to run.
+#[derive(Default)]
+struct StartupBarrier {
+    pending: Vec<(String, oneshot::Receiver<()>)>,
+}
+
+impl StartupBarrier {
+    /// Registers a service. Its runner must send on the returned sender once
+    /// the service has been spawned.
+    fn register(&mut self, name: String) -> oneshot::Sender<()> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.push((name, rx));
+        tx
+    }
+
+    /// Waits until every registered service has started.
+    ///
+    /// Panics if a service's runner was dropped without starting it.
+    async fn wait(self) {
+        let total = self.pending.len();
+        debug!("Waiting for {} services to start...", total);
+
+        for (idx, (name, started)) in self.pending.into_iter().enumerate() {
+            started
+                .await
+                .unwrap_or_else(|_| panic!("service {:?} was dropped before it started", name));
 
-            let left = service_num - i;
+            let left = total - idx - 1;
             debug!("Service {:?} started, {} left", name, left);
         }
 
         debug!("All services started");
-
-        runtime
     }
 }
 
 /// Configuration info for a runtime.
-pub struct RuntimeConfig {}
+#[derive(Clone, Debug, Default)]
+pub struct RuntimeConfig {
+    /// Whether this runtime is a client or a server, or `None` if it's
+    /// neither, like in tests.
+    pub role: Option<PeerRole>,
+}
 
 /// An instance of a single Hearth runtime.
 ///
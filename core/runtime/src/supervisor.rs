@@ -0,0 +1,422 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Supervision of long-running host-side processes.
+//!
+//! A [Supervisor] owns a set of child processes, each described by a
+//! [ChildSpec], and restarts them according to a [RestartPolicy] when they
+//! exit or panic. Children are restarted in place, on the same [Process], so
+//! capabilities to them (including their registry entries) stay valid across
+//! restarts.
+
+use std::collections::VecDeque;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::future::BoxFuture;
+use futures_util::FutureExt;
+use hearth_schema::ProcessLogLevel;
+use tokio::task::JoinSet;
+use tracing::{error, info, warn, Instrument};
+
+use crate::process::{Process, ProcessLogEvent, ProcessMetadata};
+use crate::runtime::{Plugin, Runtime, RuntimeBuilder};
+use crate::utils::{panic_message, ProcessRunner, ServiceRunner};
+
+/// Which children are restarted when one of them exits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RestartStrategy {
+    /// Only the child that exited is restarted.
+    OneForOne,
+
+    /// All children are stopped and restarted when any of them exits.
+    OneForAll,
+}
+
+/// How a [Supervisor] restarts its children.
+#[derive(Clone, Debug)]
+pub struct RestartPolicy {
+    /// Which children are restarted when one exits.
+    pub strategy: RestartStrategy,
+
+    /// The maximum number of restarts allowed within [Self::window]. When
+    /// exceeded, the supervisor stops all of its children and gives up.
+    pub max_restarts: usize,
+
+    /// The sliding window in which restarts are counted.
+    pub window: Duration,
+
+    /// The delay before the first restart within the window. Each further
+    /// restart within the window doubles the delay.
+    pub initial_backoff: Duration,
+
+    /// The maximum delay between restarts.
+    pub max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: RestartStrategy::OneForOne,
+            max_restarts: 3,
+            window: Duration::from_secs(5),
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Gets the delay before a restart, given how many restarts have
+    /// already happened within the window.
+    pub fn backoff(&self, recent_restarts: usize) -> Duration {
+        let exponent = recent_restarts.min(31) as u32;
+        self.initial_backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff)
+    }
+}
+
+/// The result of a single run of a child, by index. Panics are caught and
+/// turned into their messages.
+type RunResult = (usize, Result<(), String>);
+
+/// Starts a single run of a child process.
+type StartFn = dyn Fn(String, Arc<Runtime>, Arc<Process>) -> BoxFuture<'static, ()> + Send + Sync;
+
+/// A description of a supervised child process.
+pub struct ChildSpec {
+    pub(crate) name: String,
+    pub(crate) meta: ProcessMetadata,
+    pub(crate) process: Option<Process>,
+    start: Box<StartFn>,
+}
+
+impl ChildSpec {
+    /// Creates a child spec that creates a new process runner each time the
+    /// child is started.
+    ///
+    /// To supervise Wasm processes, use a factory that returns a runner which
+    /// spawns the Wasm module's lump with its arguments.
+    pub fn new<R, F>(name: String, meta: ProcessMetadata, factory: F) -> Self
+    where
+        R: ProcessRunner + 'static,
+        F: Fn() -> R + Send + Sync + 'static,
+    {
+        Self {
+            name,
+            meta,
+            process: None,
+            start: Box::new(move |label, runtime, process| {
+                let runner = factory();
+                async move { runner.run(label, runtime, &process).await }.boxed()
+            }),
+        }
+    }
+}
+
+/// A child that has been given a process to run on.
+struct Child {
+    name: String,
+    process: Arc<Process>,
+    start: Box<StartFn>,
+}
+
+impl Child {
+    /// Writes an event to this child's process log.
+    fn log(&self, level: ProcessLogLevel, content: String) {
+        let event = ProcessLogEvent::new(level, "supervisor", content);
+        let _ = self.process.borrow_info().log_tx.send(event);
+    }
+}
+
+/// Owns a set of child processes and restarts them when they exit.
+pub struct Supervisor {
+    name: String,
+    policy: RestartPolicy,
+    pub(crate) children: Vec<ChildSpec>,
+}
+
+impl Supervisor {
+    /// Creates an empty supervisor with a name used for logging.
+    pub fn new(name: String, policy: RestartPolicy) -> Self {
+        Self {
+            name,
+            policy,
+            children: Vec::new(),
+        }
+    }
+
+    /// Adds a child to this supervisor.
+    pub fn add_child(&mut self, child: ChildSpec) -> &mut Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Starts all children and supervises them.
+    ///
+    /// Returns an error when the restart limit is exceeded, after stopping
+    /// all children. Returns successfully if there are no children.
+    pub async fn run(self, runtime: Arc<Runtime>) -> anyhow::Result<()> {
+        let children: Vec<_> = self
+            .children
+            .into_iter()
+            .map(|spec| Child {
+                process: Arc::new(
+                    spec.process
+                        .unwrap_or_else(|| runtime.process_factory.spawn(spec.meta)),
+                ),
+                name: spec.name,
+                start: spec.start,
+            })
+            .collect();
+
+        let mut running = JoinSet::new();
+        let start = |running: &mut JoinSet<RunResult>, index: usize| {
+            let child: &Child = &children[index];
+            let run = (child.start)(child.name.clone(), runtime.clone(), child.process.clone());
+            let span = child.process.span();
+            running.spawn(
+                async move {
+                    let result = AssertUnwindSafe(run).catch_unwind().await;
+                    let result = result.map_err(|panic| panic_message(panic.as_ref()).to_string());
+                    (index, result)
+                }
+                .instrument(span),
+            );
+        };
+
+        for index in 0..children.len() {
+            start(&mut running, index);
+        }
+
+        let mut restarts: VecDeque<Instant> = VecDeque::new();
+        while let Some(joined) = running.join_next().await {
+            // children are only cancelled while restarting all of them
+            let Ok((index, result)) = joined else {
+                continue;
+            };
+
+            let child = &children[index];
+            let reason = match result {
+                Ok(()) => "exited".to_string(),
+                Err(msg) => format!("panicked: {}", msg),
+            };
+
+            let now = Instant::now();
+            while restarts
+                .front()
+                .map_or(false, |time| now.duration_since(*time) > self.policy.window)
+            {
+                restarts.pop_front();
+            }
+
+            if restarts.len() >= self.policy.max_restarts {
+                running.abort_all();
+                while running.join_next().await.is_some() {}
+
+                let msg = format!(
+                    "child {:?} {} after {} restarts within {:?}; giving up",
+                    child.name,
+                    reason,
+                    restarts.len(),
+                    self.policy.window
+                );
+
+                error!("Supervisor {:?}: {}", self.name, msg);
+                child.log(ProcessLogLevel::Error, msg.clone());
+                anyhow::bail!(msg);
+            }
+
+            let delay = self.policy.backoff(restarts.len());
+            restarts.push_back(now);
+
+            let msg = format!(
+                "child {:?} {}; restarting in {:?}",
+                child.name, reason, delay
+            );
+            warn!("Supervisor {:?}: {}", self.name, msg);
+            child.log(ProcessLogLevel::Warning, msg);
+
+            let restarted = match self.policy.strategy {
+                RestartStrategy::OneForOne => vec![index],
+                RestartStrategy::OneForAll => {
+                    running.abort_all();
+                    while running.join_next().await.is_some() {}
+                    (0..children.len()).collect()
+                }
+            };
+
+            tokio::time::sleep(delay).await;
+
+            for index in restarted {
+                info!(
+                    "Supervisor {:?}: restarting child {:?}",
+                    self.name, children[index].name
+                );
+                start(&mut running, index);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A plugin that adds a [ServiceRunner] as a supervised service.
+///
+/// The service is restarted from a clone of `runner` whenever it exits,
+/// including after too many consecutive panics. Restarts happen on the same
+/// process, so its registry entry and the capabilities held by its clients
+/// keep working. See [RuntimeBuilder::add_supervised_service].
+pub struct Supervised<T> {
+    /// The initial state of the service, cloned for every restart.
+    pub runner: T,
+
+    /// How the service is restarted.
+    pub policy: RestartPolicy,
+}
+
+impl<T> Supervised<T> {
+    /// Supervises a service with the default [RestartPolicy].
+    pub fn new(runner: T) -> Self {
+        Self {
+            runner,
+            policy: RestartPolicy::default(),
+        }
+    }
+}
+
+impl<T> Plugin for Supervised<T>
+where
+    T: ServiceRunner + Clone + Sync + 'static,
+{
+    fn finalize(self, builder: &mut RuntimeBuilder) {
+        let name = T::NAME.to_string();
+        let mut meta = T::get_process_metadata();
+        meta.name = Some(name.clone());
+
+        let runner = self.runner;
+        builder.add_supervised_service(name, meta, self.policy, move || runner.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+
+    use crate::runtime::{RuntimeBuilder, RuntimeConfig};
+
+    /// A runner that counts its runs, then either exits immediately or runs
+    /// forever.
+    struct Counted {
+        runs: Arc<AtomicUsize>,
+        exits: bool,
+    }
+
+    #[async_trait]
+    impl ProcessRunner for Counted {
+        async fn run(self, _label: String, _runtime: Arc<Runtime>, _ctx: &Process) {
+            self.runs.fetch_add(1, Ordering::SeqCst);
+
+            if !self.exits {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+
+    fn counted(name: &str, exits: bool) -> (ChildSpec, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let spec = ChildSpec::new(name.to_string(), ProcessMetadata::default(), {
+            let runs = runs.clone();
+            move || Counted {
+                runs: runs.clone(),
+                exits,
+            }
+        });
+
+        (spec, runs)
+    }
+
+    fn policy(strategy: RestartStrategy) -> RestartPolicy {
+        RestartPolicy {
+            strategy,
+            max_restarts: 3,
+            window: Duration::from_secs(60),
+            initial_backoff: Duration::ZERO,
+            max_backoff: Duration::ZERO,
+        }
+    }
+
+    async fn runtime() -> Arc<Runtime> {
+        RuntimeBuilder::new(Default::default())
+            .run(RuntimeConfig::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_restarts() {
+        let (spec, runs) = counted("exits", true);
+        let mut supervisor = Supervisor::new("test".into(), policy(RestartStrategy::OneForOne));
+        supervisor.add_child(spec);
+
+        let result = supervisor.run(runtime().await).await;
+        assert!(result.is_err());
+        assert_eq!(runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[tokio::test]
+    async fn one_for_one_leaves_siblings() {
+        let (exits, _) = counted("exits", true);
+        let (stays, stays_runs) = counted("stays", false);
+        let mut supervisor = Supervisor::new("test".into(), policy(RestartStrategy::OneForOne));
+        supervisor.add_child(exits).add_child(stays);
+
+        assert!(supervisor.run(runtime().await).await.is_err());
+        assert_eq!(stays_runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn one_for_all_restarts_siblings() {
+        let (exits, _) = counted("exits", true);
+        let (stays, stays_runs) = counted("stays", false);
+        let mut supervisor = Supervisor::new("test".into(), policy(RestartStrategy::OneForAll));
+        supervisor.add_child(exits).add_child(stays);
+
+        assert!(supervisor.run(runtime().await).await.is_err());
+        assert_eq!(stays_runs.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RestartPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(350),
+            ..Default::default()
+        };
+
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff(2), Duration::from_millis(350));
+        assert_eq!(policy.backoff(100), Duration::from_millis(350));
+    }
+}
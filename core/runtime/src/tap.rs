@@ -0,0 +1,556 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Recording of the messages that processes receive and send.
+//!
+//! Every process has a [MessageTap] in its
+//! [ProcessInfo](crate::process::ProcessInfo). While a tap is recording,
+//! guest ABIs report every message delivered to or sent by the process, and
+//! a background thread writes them to a JSONL file as [TapRecord]s.
+//! Capabilities are described by name instead of copied, so that a recording
+//! can be replayed against stub capabilities in the test harness.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+use async_trait::async_trait;
+use flue::{CapabilityHandle, Table};
+use flume::Sender;
+use hearth_schema::tap::*;
+use hearth_schema::{LumpId, Permissions};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tracing::{debug, error, info};
+
+use crate::process::{ProcessId, ProcessMetadata};
+use crate::process_log::now_millis;
+use crate::utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner};
+
+/// Configuration for message recording.
+///
+/// Loaded from the `message_tap` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct MessageTapConfig {
+    /// The names of processes to record from the moment they're spawned.
+    pub processes: Vec<String>,
+
+    /// The directory that recordings of [Self::processes] are written to,
+    /// as `<name>-<pid>-<timestamp>.jsonl`. Defaults to `recordings` in the Hearth data
+    /// directory.
+    pub dir: Option<PathBuf>,
+
+    /// The default maximum number of messages in one recording.
+    pub limit: Option<u64>,
+
+    /// How many messages may wait to be written before new ones are dropped.
+    pub buffer: usize,
+
+    /// If true, the message tap service is registered as [TAP_SERVICE] so
+    /// that any process with the registry can record other processes.
+    ///
+    /// Recordings contain every message a process handles and are written
+    /// to arbitrary host paths, so only enable this on trusted hosts.
+    pub service: bool,
+}
+
+impl Default for MessageTapConfig {
+    fn default() -> Self {
+        Self {
+            processes: Vec::new(),
+            dir: None,
+            limit: None,
+            buffer: 4096,
+            service: false,
+        }
+    }
+}
+
+impl MessageTapConfig {
+    /// Gets the path that a configured recording of a process is written to.
+    ///
+    /// PIDs are reused between runs, so the path also includes the time in
+    /// milliseconds since the Unix epoch.
+    pub fn recording_path(&self, name: &str, pid: ProcessId, timestamp: u64) -> PathBuf {
+        let dir = self
+            .dir
+            .clone()
+            .unwrap_or_else(|| crate::get_data_dir().join("recordings"));
+
+        let name: String = name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '_' })
+            .collect();
+
+        dir.join(format!("{}-{}-{}.jsonl", name, pid, timestamp))
+    }
+}
+
+/// Records the messages of a single process while enabled.
+pub struct MessageTap {
+    pid: ProcessId,
+    name: Option<String>,
+    recording: AtomicBool,
+    inner: Mutex<TapInner>,
+}
+
+#[derive(Default)]
+struct TapInner {
+    recorder: Option<Recorder>,
+
+    /// The mailboxes that capabilities in the process's table were made
+    /// from, by capability handle.
+    exports: HashMap<usize, u32>,
+
+    /// The process's [TapRecord::Spawn], written at the start of every
+    /// recording.
+    spawn: Option<TapRecord>,
+}
+
+struct Recorder {
+    tx: Sender<TapRecord>,
+    writer: JoinHandle<()>,
+    summary: TapSummary,
+    limit: Option<u64>,
+    buffer: usize,
+}
+
+impl Drop for MessageTap {
+    fn drop(&mut self) {
+        // taps are dropped along with their process, which may be on an
+        // async runtime thread, so let the writer finish in the background
+        // instead of joining it
+        let _ = self.finish();
+    }
+}
+
+impl MessageTap {
+    /// Creates a tap for a process that isn't recording.
+    pub fn new(pid: ProcessId, name: Option<String>) -> Self {
+        Self {
+            pid,
+            name,
+            recording: AtomicBool::new(false),
+            inner: Default::default(),
+        }
+    }
+
+    /// Tests if messages are currently being recorded.
+    ///
+    /// Cheap enough to check on every message.
+    pub fn is_recording(&self) -> bool {
+        self.recording.load(Ordering::Relaxed)
+    }
+
+    /// Starts recording into a new JSONL file at `path`.
+    ///
+    /// Fails if `path` already exists, so that recording can never clobber
+    /// an existing file. At most `limit` messages are recorded, and messages
+    /// are dropped while `buffer` of them are waiting to be written.
+    pub fn start(&self, path: &Path, limit: Option<u64>, buffer: usize) -> Result<(), TapError> {
+        let mut inner = self.inner.lock();
+        if inner.recorder.is_some() {
+            return Err(TapError::AlreadyRecording);
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|err| TapError::Io(err.to_string()))?;
+        info!("Recording messages of PID {} to {:?}", self.pid, path);
+
+        let (tx, rx) = flume::unbounded::<TapRecord>();
+        let path = path.to_owned();
+        let writer = std::thread::spawn(move || {
+            let mut file = BufWriter::new(file);
+
+            while let Ok(record) = rx.recv() {
+                let mut line = serde_json::to_vec(&record).unwrap();
+                line.push(b'\n');
+
+                if let Err(err) = file.write_all(&line) {
+                    error!("Failed to write recording {:?}: {:?}", path, err);
+                    return;
+                }
+
+                // only flush when caught up so bursts of messages are batched
+                if rx.is_empty() {
+                    if let Err(err) = file.flush() {
+                        error!("Failed to flush recording {:?}: {:?}", path, err);
+                        return;
+                    }
+                }
+            }
+        });
+
+        let _ = tx.send(TapRecord::Start {
+            pid: self.pid as u64,
+            name: self.name.clone(),
+            timestamp: now_millis(),
+        });
+
+        if let Some(spawn) = inner.spawn.clone() {
+            let _ = tx.send(spawn);
+        }
+
+        inner.recorder = Some(Recorder {
+            tx,
+            writer,
+            summary: TapSummary::default(),
+            limit,
+            buffer,
+        });
+
+        self.recording.store(limit != Some(0), Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Stops recording and waits for the recording file to be written.
+    ///
+    /// This blocks on file IO, so async callers should use a blocking task.
+    pub fn stop(&self) -> Result<TapSummary, TapError> {
+        let (summary, writer) = self.finish()?;
+
+        if writer.join().is_err() {
+            error!("Recording writer of PID {} panicked", self.pid);
+        }
+
+        info!("Stopped recording PID {}: {:?}", self.pid, summary);
+        Ok(summary)
+    }
+
+    /// Stops recording without waiting for the writer thread.
+    fn finish(&self) -> Result<(TapSummary, JoinHandle<()>), TapError> {
+        let recorder = self
+            .inner
+            .lock()
+            .recorder
+            .take()
+            .ok_or(TapError::NotRecording)?;
+
+        self.recording.store(false, Ordering::Relaxed);
+
+        let summary = recorder.summary;
+        let _ = recorder.tx.send(TapRecord::Stop {
+            timestamp: now_millis(),
+            summary: summary.clone(),
+        });
+
+        Ok((summary, recorder.writer))
+    }
+
+    /// Remembers that a capability in the process's table was made from one
+    /// of its mailboxes.
+    pub fn note_export(&self, cap: usize, mailbox: u32) {
+        self.inner.lock().exports.insert(cap, mailbox);
+    }
+
+    /// Describes a capability in the process's table.
+    pub fn describe(&self, table: &Table, cap: usize) -> TapCap {
+        describe(&self.inner.lock(), table, cap)
+    }
+
+    /// Records how a Wasm process was spawned.
+    ///
+    /// Unlike messages, this is kept even when not recording, so that every
+    /// recording of the process can start with it.
+    pub fn record_spawn(&self, table: &Table, lump: LumpId, initial_caps: &[usize]) {
+        let mut inner = self.inner.lock();
+
+        let record = TapRecord::Spawn {
+            lump,
+            initial_caps: describe_all(&inner, table, initial_caps),
+        };
+
+        if let Some(recorder) = inner.recorder.as_ref() {
+            let _ = recorder.tx.send(record.clone());
+        }
+
+        inner.spawn = Some(record);
+    }
+
+    /// Records a message received by one of the process's mailboxes.
+    pub fn record_inbound(&self, table: &Table, mailbox: u32, data: &[u8], caps: &[usize]) {
+        if !self.is_recording() {
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+        let record = TapRecord::Inbound {
+            timestamp: now_millis(),
+            mailbox,
+            data: data.to_vec(),
+            caps: describe_all(&inner, table, caps),
+        };
+
+        self.push(&mut inner, record);
+    }
+
+    /// Records a message sent by the process.
+    pub fn record_outbound(&self, table: &Table, target: usize, data: &[u8], caps: &[usize]) {
+        if !self.is_recording() {
+            return;
+        }
+
+        let mut inner = self.inner.lock();
+        let record = TapRecord::Outbound {
+            timestamp: now_millis(),
+            target: describe(&inner, table, target),
+            data: data.to_vec(),
+            caps: describe_all(&inner, table, caps),
+        };
+
+        self.push(&mut inner, record);
+    }
+
+    /// Queues a message record, dropping it if the buffer is full and
+    /// stopping recording at the limit.
+    fn push(&self, inner: &mut TapInner, record: TapRecord) {
+        let Some(recorder) = inner.recorder.as_mut() else {
+            return;
+        };
+
+        if recorder.summary.truncated {
+            return;
+        }
+
+        if recorder.tx.len() >= recorder.buffer || recorder.tx.send(record).is_err() {
+            recorder.summary.dropped += 1;
+            return;
+        }
+
+        recorder.summary.recorded += 1;
+
+        if Some(recorder.summary.recorded) == recorder.limit {
+            debug!("Recording of PID {} reached its limit", self.pid);
+            recorder.summary.truncated = true;
+            self.recording.store(false, Ordering::Relaxed);
+        }
+    }
+}
+
+fn describe(inner: &TapInner, table: &Table, cap: usize) -> TapCap {
+    let permissions = table
+        .get_permissions(CapabilityHandle(cap))
+        .map(|perms| Permissions::from_bits_truncate(perms.bits()))
+        .unwrap_or(Permissions::empty());
+
+    TapCap {
+        name: format!("cap{}", cap),
+        permissions,
+        mailbox: inner.exports.get(&cap).copied(),
+    }
+}
+
+fn describe_all(inner: &TapInner, table: &Table, caps: &[usize]) -> Vec<TapCap> {
+    caps.iter()
+        .map(|cap| describe(inner, table, *cap))
+        .collect()
+}
+
+/// Reads every record of a recording file.
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<TapRecord>> {
+    let file = File::open(path)
+        .map_err(|err| anyhow::anyhow!("Failed to open recording {:?}: {}", path, err))?;
+
+    let mut records = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record = serde_json::from_str(&line).map_err(|err| {
+            anyhow::anyhow!(
+                "Invalid record on line {} of {:?}: {}",
+                index + 1,
+                path,
+                err
+            )
+        })?;
+
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+/// A service that starts and stops recordings of running processes.
+pub struct TapService;
+
+#[async_trait]
+impl RequestResponseProcess for TapService {
+    type Request = TapRequest;
+    type Response = TapResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, TapRequest>,
+    ) -> ResponseInfo<'a, TapResponse> {
+        let factory = &request.runtime.process_factory;
+
+        match &request.data {
+            TapRequest::Start { pid, path, limit } => {
+                let Some(tap) = factory.tap(*pid as ProcessId) else {
+                    return TapError::NotFound.into();
+                };
+
+                let config = factory.tap_config();
+                let limit = limit.or(config.limit);
+                tap.start(path, limit, config.buffer)
+                    .map(|_| TapSuccess::Started)
+                    .into()
+            }
+            TapRequest::Stop { pid } => {
+                let Some(tap) = factory.tap(*pid as ProcessId) else {
+                    return TapError::NotFound.into();
+                };
+
+                match tokio::task::spawn_blocking(move || tap.stop()).await {
+                    Ok(result) => result.map(TapSuccess::Stopped).into(),
+                    Err(err) => {
+                        error!("Stopping recording of PID {} failed: {:?}", pid, err);
+                        TapError::Io(err.to_string()).into()
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl ServiceRunner for TapService {
+    const NAME: &'static str = TAP_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = crate::utils::cargo_process_metadata!();
+        meta.description =
+            Some("Records the messages of running processes. Accepts TapRequest.".to_string());
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flue::{MailboxGroup, PostOffice};
+
+    fn temp_path(name: &str) -> PathBuf {
+        let name = format!("hearth-tap-{}-{}.jsonl", name, std::process::id());
+        let path = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn existing_files_are_kept() {
+        let path = temp_path("existing");
+        std::fs::write(&path, "keep me").unwrap();
+
+        let tap = MessageTap::new(0, None);
+        let result = tap.start(&path, None, 16);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(matches!(result, Err(TapError::Io(_))));
+        assert!(!tap.is_recording());
+        assert_eq!(contents, "keep me");
+    }
+
+    #[test]
+    fn dropping_tap_finishes_recording() {
+        let table = Table::new(PostOffice::new());
+        let path = temp_path("drop");
+        let tap = MessageTap::new(0, None);
+        tap.start(&path, None, 16).unwrap();
+        tap.record_inbound(&table, 0, b"data", &[]);
+        drop(tap);
+
+        // the writer is detached, so wait for it to write the stop record
+        let mut records = Vec::new();
+        for _ in 0..500 {
+            records = read_recording(&path).unwrap_or_default();
+            if matches!(records.last(), Some(TapRecord::Stop { .. })) {
+                break;
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(matches!(records[2], TapRecord::Stop { .. }));
+    }
+
+    #[test]
+    fn recording_stops_at_limit() {
+        let table = Table::new(PostOffice::new());
+        let path = temp_path("limit");
+        let tap = MessageTap::new(7, Some("test".to_string()));
+
+        tap.start(&path, Some(2), 16).unwrap();
+        let again = tap.start(&path, None, 16);
+        assert!(matches!(again, Err(TapError::AlreadyRecording)));
+
+        for data in [b"one", b"two", b"thr"] {
+            tap.record_inbound(&table, 0, data, &[]);
+        }
+
+        assert!(!tap.is_recording());
+
+        let summary = tap.stop().unwrap();
+        assert_eq!(summary.recorded, 2);
+        assert_eq!(summary.dropped, 0);
+        assert!(summary.truncated);
+
+        let records = read_recording(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(records.len(), 4);
+        assert!(matches!(records[0], TapRecord::Start { pid: 7, .. }));
+        assert!(matches!(&records[2], TapRecord::Inbound { data, .. } if data == b"two"));
+        assert!(matches!(&records[3], TapRecord::Stop { summary: s, .. } if *s == summary));
+        assert!(matches!(tap.stop(), Err(TapError::NotRecording)));
+    }
+
+    #[test]
+    fn exported_mailboxes_are_described() {
+        let table = Table::new(PostOffice::new());
+        let group = MailboxGroup::new(&table);
+        let mailbox = group.create_mailbox().unwrap();
+        let perms = flue::Permissions::SEND | flue::Permissions::MONITOR;
+        let cap = mailbox.export(perms).unwrap().into_handle().0;
+
+        let tap = MessageTap::new(0, None);
+        let described = tap.describe(&table, cap);
+        assert_eq!(described.name, format!("cap{}", cap));
+        assert_eq!(
+            described.permissions,
+            Permissions::SEND | Permissions::MONITOR
+        );
+        assert_eq!(described.mailbox, None);
+
+        tap.note_export(cap, 3);
+        assert_eq!(tap.describe(&table, cap).mailbox, Some(3));
+    }
+}
@@ -0,0 +1,1076 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! A deterministic harness for testing runtimes, services, and guests.
+//!
+//! [TestRuntimeBuilder] wraps a [RuntimeBuilder] in a single-threaded Tokio
+//! runtime with paused time, so that timers fire instantly and in order and
+//! tests never have to sleep. The resulting [TestRuntime] exchanges messages
+//! with processes synchronously from plain `#[test]` functions and collects
+//! every process log for assertions.
+//!
+//! ```ignore
+//! let mut builder = TestRuntimeBuilder::new();
+//! builder.add_plugin(MemoryFs::new([("hello.txt", "hello!")]));
+//! let runtime = builder.build();
+//!
+//! let fs = runtime.get_service("hearth.fs.Filesystem").unwrap();
+//! let (response, _) = runtime.request::<_, fs::Response>(&fs, &request, &[]);
+//! ```
+//!
+//! [TestRuntime::replay] feeds a recording made by a process's
+//! [MessageTap](crate::tap::MessageTap) back into a fresh instance of its
+//! module and compares what it sends with what was recorded.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use flue::{CapabilityRef, Mailbox, Permissions, TableSignal};
+use flume::Receiver;
+use hearth_schema::fs::{self, FileInfo, RequestKind, Success};
+use hearth_schema::network::*;
+use hearth_schema::registry::{RegistryRequest, RegistryResponse};
+use hearth_schema::tap::{TapCap, TapRecord};
+use hearth_schema::wasm::{WasmSpawnInfo, WasmSpawnResponse};
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::process::{Process, ProcessId, ProcessLogEvent, ProcessMetadata};
+use crate::runtime::{Plugin, Runtime, RuntimeBuilder, RuntimeConfig};
+use crate::utils::*;
+
+/// How long to wait for a message before failing a test.
+///
+/// Time is paused in test runtimes, so this elapses as soon as every task is
+/// idle; it only bounds how far the clock is allowed to skip ahead.
+pub const RECV_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Builds a [TestRuntime].
+pub struct TestRuntimeBuilder {
+    builder: RuntimeBuilder,
+    logs: Receiver<(ProcessId, ProcessLogEvent)>,
+
+    // dropped last so that everything above may still spawn tasks on drop
+    tokio: tokio::runtime::Runtime,
+}
+
+impl Default for TestRuntimeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRuntimeBuilder {
+    /// Creates a new test runtime builder with an empty config file.
+    pub fn new() -> Self {
+        Self::with_config(Default::default())
+    }
+
+    /// Creates a new test runtime builder with the given config file.
+    pub fn with_config(config_file: toml::Table) -> Self {
+        let tokio = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .start_paused(true)
+            .build()
+            .expect("failed to create test Tokio runtime");
+
+        let mut builder = RuntimeBuilder::new(config_file);
+        let logs = builder.observe_process_logs();
+
+        Self {
+            builder,
+            logs,
+            tokio,
+        }
+    }
+
+    /// Adds a plugin to the runtime. See [RuntimeBuilder::add_plugin].
+    pub fn add_plugin<T: Plugin>(&mut self, plugin: T) -> &mut Self {
+        self.with_builder(|builder| {
+            builder.add_plugin(plugin);
+        })
+    }
+
+    /// Adds a service to the runtime. See [RuntimeBuilder::add_service].
+    pub fn add_service(&mut self, name: &str, process: impl ProcessRunner + 'static) -> &mut Self {
+        let meta = ProcessMetadata {
+            name: Some(name.to_string()),
+            ..Default::default()
+        };
+
+        self.with_builder(|builder| {
+            builder.add_service(name.to_string(), meta, process);
+        })
+    }
+
+    /// Accesses the inner [RuntimeBuilder] from within the test runtime.
+    pub fn with_builder(&mut self, cb: impl FnOnce(&mut RuntimeBuilder)) -> &mut Self {
+        // spawning processes spawns tasks, so enter the Tokio runtime first
+        let _guard = self.tokio.enter();
+        cb(&mut self.builder);
+        self
+    }
+
+    /// Starts the runtime, returning once all of its services have started.
+    pub fn build(self) -> TestRuntime {
+        let Self {
+            builder,
+            logs,
+            tokio,
+        } = self;
+
+        let runtime = tokio.block_on(builder.run(RuntimeConfig::default()));
+
+        let client = {
+            let _guard = tokio.enter();
+            runtime.process_factory.spawn(ProcessMetadata {
+                name: Some("Test".to_string()),
+                ..Default::default()
+            })
+        };
+
+        TestRuntime {
+            runtime,
+            client,
+            logs,
+            collected: Default::default(),
+            tokio,
+        }
+    }
+}
+
+/// A running runtime driven synchronously by a test.
+///
+/// The test acts as a host process of its own, which owns every capability
+/// handed out by this struct's methods.
+///
+/// All methods panic when something goes wrong so that the test fails with
+/// a useful message.
+pub struct TestRuntime {
+    runtime: Arc<Runtime>,
+    client: Process,
+    logs: Receiver<(ProcessId, ProcessLogEvent)>,
+    collected: Mutex<Vec<(ProcessId, ProcessLogEvent)>>,
+
+    // dropped last so that everything above may still spawn tasks on drop
+    tokio: tokio::runtime::Runtime,
+}
+
+impl TestRuntime {
+    /// Gets the inner [Runtime].
+    pub fn runtime(&self) -> &Arc<Runtime> {
+        &self.runtime
+    }
+
+    /// Gets the test's own process.
+    pub fn process(&self) -> &Process {
+        &self.client
+    }
+
+    /// Runs a future to completion on the test runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.tokio.block_on(future)
+    }
+
+    /// Runs every task until the runtime is idle.
+    ///
+    /// The paused clock only advances once no task can make progress, so
+    /// sleeping for any amount of time does exactly that.
+    pub fn settle(&self) {
+        self.block_on(tokio::time::sleep(Duration::from_millis(1)));
+    }
+
+    /// Creates a new mailbox in the test's process.
+    pub fn mailbox(&self) -> TestMailbox<'_> {
+        TestMailbox {
+            runtime: self,
+            mailbox: self.client.borrow_group().create_mailbox().unwrap(),
+        }
+    }
+
+    /// Gets a capability to the native registry.
+    pub fn registry(&self) -> CapabilityRef<'_> {
+        self.runtime
+            .registry
+            .borrow_parent()
+            .export_to(Permissions::SEND, self.client.borrow_table())
+            .unwrap()
+    }
+
+    /// Looks up a service in the native registry.
+    pub fn get_service(&self, name: &str) -> Option<CapabilityRef<'_>> {
+        let request = RegistryRequest::Get {
+            name: name.to_string(),
+        };
+
+        let (response, mut caps) = self.request(&self.registry(), &request, &[]);
+
+        match response {
+            RegistryResponse::Get(true) if !caps.is_empty() => Some(caps.remove(0)),
+            _ => None,
+        }
+    }
+
+    /// Sends a JSON message to a capability.
+    pub fn send<T: Serialize>(
+        &self,
+        cap: &CapabilityRef<'_>,
+        data: &T,
+        caps: &[&CapabilityRef<'_>],
+    ) {
+        let data = serde_json::to_vec(data).unwrap();
+        self.block_on(cap.send(&data, caps)).unwrap();
+    }
+
+    /// Sends a request to a request-response capability and waits for its
+    /// reply.
+    ///
+    /// A reply capability is passed as the request's first capability and
+    /// `args` follow it, matching [RequestResponseProcess].
+    pub fn request<Req, Res>(
+        &self,
+        cap: &CapabilityRef<'_>,
+        request: &Req,
+        args: &[&CapabilityRef<'_>],
+    ) -> (Res, Vec<CapabilityRef<'_>>)
+    where
+        Req: Serialize,
+        Res: DeserializeOwned,
+    {
+        let reply = self.mailbox();
+        let reply_cap = reply.capability(Permissions::SEND);
+
+        let mut caps = Vec::with_capacity(args.len() + 1);
+        caps.push(&reply_cap);
+        caps.extend_from_slice(args);
+        self.send(cap, request, &caps);
+
+        reply.recv_json()
+    }
+
+    /// Spawns a Wasm module as a new process.
+    ///
+    /// `module` may be a binary module or, for convenience, WebAssembly text.
+    /// The runtime must have a Wasm plugin that provides the
+    /// `hearth.wasm.WasmProcessSpawner` service. The new process is passed a
+    /// capability to the registry.
+    pub fn spawn_wasm(&self, module: impl Into<Bytes>) -> SpawnedWasm<'_> {
+        self.spawn_wasm_with(module, &[&self.registry()])
+    }
+
+    /// Spawns a Wasm module as a new process with the given initial
+    /// capabilities instead of the registry.
+    pub fn spawn_wasm_with(
+        &self,
+        module: impl Into<Bytes>,
+        caps: &[&CapabilityRef<'_>],
+    ) -> SpawnedWasm<'_> {
+        let lump = self.block_on(self.runtime.lump_store.add_lump(module.into()));
+
+        let spawner = self
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .expect("test runtime has no Wasm spawner");
+
+        let info = WasmSpawnInfo {
+            lump,
+            entrypoint: None,
+            limits: Default::default(),
+            keep_awake: false,
+            allow: None,
+        };
+
+        let (result, mut caps): (WasmSpawnResponse, _) = self.request(&spawner, &info, caps);
+
+        if let Err(err) = result {
+            panic!("failed to spawn Wasm module: {}", err);
+        }
+
+        assert_eq!(caps.len(), 2, "failed to spawn Wasm module");
+        let endpoint = caps.pop().unwrap();
+        let process = caps.pop().unwrap();
+        SpawnedWasm { process, endpoint }
+    }
+
+    /// Replays a recording of a Wasm process against a fresh instance of its
+    /// module.
+    ///
+    /// Every capability in the recording is replaced by a stub mailbox that
+    /// logs what's sent to it. The new process is spawned with stubs for its
+    /// recorded initial capabilities, and then each recorded inbound message
+    /// is delivered to it in order once the runtime is idle. Messages to the
+    /// process's own mailboxes other than its parent are delivered through
+    /// the capabilities it sent out while replaying, and skipped if it never
+    /// sent one.
+    ///
+    /// Afterwards, the messages received by each stub are compared in order
+    /// with the ones recorded for the same capability. Messages to the
+    /// process's own mailboxes aren't observable and are not compared.
+    pub fn replay(
+        &self,
+        module: impl Into<Bytes>,
+        records: &[TapRecord],
+        options: &ReplayOptions,
+    ) -> ReplayReport {
+        let mut replay = Replay {
+            runtime: self,
+            stubs: BTreeMap::new(),
+            mailboxes: HashMap::new(),
+            expected: HashMap::new(),
+            received: HashMap::new(),
+            report: ReplayReport::default(),
+        };
+
+        for record in records {
+            if let TapRecord::Outbound {
+                target, data, caps, ..
+            } = record
+            {
+                if target.mailbox.is_none() {
+                    let expected = replay.expected.entry(target.name.clone()).or_default();
+                    expected.push((data.as_slice(), caps.as_slice()));
+                }
+            }
+        }
+
+        let initial_caps = records
+            .iter()
+            .find_map(|record| match record {
+                TapRecord::Spawn { initial_caps, .. } => Some(initial_caps.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default();
+
+        let initial_caps: Vec<_> = initial_caps.iter().map(|cap| replay.stub(cap)).collect();
+        let initial_caps: Vec<_> = initial_caps.iter().collect();
+        let spawned = self.spawn_wasm_with(module, &initial_caps);
+
+        for record in records {
+            let TapRecord::Inbound {
+                mailbox,
+                data,
+                caps,
+                ..
+            } = record
+            else {
+                continue;
+            };
+
+            replay.collect();
+
+            let target = match *mailbox {
+                0 => &spawned.process,
+                mailbox => match replay.mailboxes.get(&mailbox) {
+                    Some(target) => target,
+                    None => {
+                        replay.report.skipped += 1;
+                        continue;
+                    }
+                },
+            };
+
+            let caps: Vec<_> = caps.iter().map(|cap| replay.arg(cap)).collect();
+            let caps: Vec<_> = caps.iter().collect();
+
+            match self.block_on(target.send(data, &caps)) {
+                Ok(()) => replay.report.delivered += 1,
+                Err(_) => replay.report.skipped += 1,
+            }
+        }
+
+        replay.collect();
+        replay.compare(options);
+        replay.report
+    }
+
+    /// Returns every process log event received so far.
+    pub fn logs(&self) -> Vec<(ProcessId, ProcessLogEvent)> {
+        // let pending log tasks forward their events first
+        self.settle();
+
+        let mut collected = self.collected.lock();
+        collected.extend(self.logs.try_iter());
+        collected.clone()
+    }
+
+    /// Panics if no process has logged a message containing `needle`.
+    pub fn assert_logged(&self, needle: &str) {
+        let logs = self.logs();
+
+        assert!(
+            logs.iter().any(|(_, event)| event.content.contains(needle)),
+            "no process logged {:?}; logs: {:#?}",
+            needle,
+            logs
+        );
+    }
+}
+
+/// A mailbox in a [TestRuntime]'s process.
+pub struct TestMailbox<'a> {
+    runtime: &'a TestRuntime,
+    mailbox: Mailbox<'a>,
+}
+
+impl<'a> TestMailbox<'a> {
+    /// Creates a capability to this mailbox.
+    pub fn capability(&self, perms: Permissions) -> CapabilityRef<'a> {
+        self.mailbox.export(perms).unwrap()
+    }
+
+    /// Monitors a capability with this mailbox.
+    pub fn monitor(&self, cap: &CapabilityRef<'_>) {
+        cap.monitor(&self.mailbox).unwrap();
+    }
+
+    /// Waits for a down signal.
+    ///
+    /// Panics on a timeout or a message.
+    pub fn recv_down(&self) {
+        self.recv_signal(|signal| match signal {
+            TableSignal::Down { .. } => Ok(()),
+            other => Err(format!("{:?}", other)),
+        })
+    }
+
+    /// Waits for a message and returns its raw data.
+    ///
+    /// Panics on a timeout or a down signal.
+    pub fn recv(&self) -> (Vec<u8>, Vec<CapabilityRef<'a>>) {
+        let (data, caps) = self.recv_signal(|signal| match signal {
+            TableSignal::Message { data, caps } => Ok((data.to_vec(), caps)),
+            other => Err(format!("{:?}", other)),
+        });
+
+        let table = self.runtime.client.borrow_table();
+        let caps = caps
+            .into_iter()
+            .map(|handle| table.wrap_handle(handle).unwrap())
+            .collect();
+
+        (data, caps)
+    }
+
+    /// Waits for a JSON message.
+    ///
+    /// Panics on a timeout, a down signal, or a malformed message.
+    pub fn recv_json<T: DeserializeOwned>(&self) -> (T, Vec<CapabilityRef<'a>>) {
+        let (data, caps) = self.recv();
+
+        let data = serde_json::from_slice(&data).unwrap_or_else(|err| {
+            panic!("failed to parse {}: {:?}", std::any::type_name::<T>(), err)
+        });
+
+        (data, caps)
+    }
+
+    /// Returns a message if one has already been received, without waiting.
+    ///
+    /// Down signals are skipped.
+    pub fn try_recv(&self) -> Option<(Vec<u8>, Vec<CapabilityRef<'a>>)> {
+        let table = self.runtime.client.borrow_table();
+
+        loop {
+            let signal = self
+                .mailbox
+                .try_recv(|signal| match signal {
+                    TableSignal::Message { data, caps } => Some((data.to_vec(), caps)),
+                    TableSignal::Down { .. } => None,
+                })
+                .expect("test process was killed")?;
+
+            if let Some((data, caps)) = signal {
+                let caps = caps
+                    .into_iter()
+                    .map(|handle| table.wrap_handle(handle).unwrap())
+                    .collect();
+
+                return Some((data, caps));
+            }
+        }
+    }
+
+    fn recv_signal<T>(&self, cb: impl FnOnce(TableSignal) -> Result<T, String>) -> T {
+        let recv = tokio::time::timeout(RECV_TIMEOUT, self.mailbox.recv(cb));
+
+        match self.runtime.block_on(recv) {
+            Ok(Some(Ok(value))) => value,
+            Ok(Some(Err(other))) => panic!("received unexpected signal: {}", other),
+            Ok(None) => panic!("test process was killed"),
+            Err(_) => panic!("timed out waiting for a signal"),
+        }
+    }
+}
+
+/// A Wasm process spawned by [TestRuntime::spawn_wasm].
+pub struct SpawnedWasm<'a> {
+    /// A capability to the process.
+    pub process: CapabilityRef<'a>,
+
+    /// A capability to the process's link endpoint.
+    pub endpoint: CapabilityRef<'a>,
+}
+
+/// Tolerances for comparing replayed messages with recorded ones.
+///
+/// Messages are compared as JSON if both parse as JSON, and byte-for-byte
+/// otherwise.
+#[derive(Clone, Debug)]
+pub struct ReplayOptions {
+    /// Object fields to ignore at any depth, such as timestamps.
+    pub ignore_fields: Vec<String>,
+
+    /// The largest allowed difference between two JSON numbers.
+    pub float_tolerance: f64,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        Self {
+            ignore_fields: vec!["timestamp".to_string()],
+            float_tolerance: 0.0,
+        }
+    }
+}
+
+impl ReplayOptions {
+    /// Tests if a replayed message matches a recorded one.
+    pub fn matches(&self, expected: &[u8], actual: &[u8]) -> bool {
+        match (
+            serde_json::from_slice::<Value>(expected),
+            serde_json::from_slice::<Value>(actual),
+        ) {
+            (Ok(expected), Ok(actual)) => self.values_match(&expected, &actual),
+            _ => expected == actual,
+        }
+    }
+
+    fn values_match(&self, expected: &Value, actual: &Value) -> bool {
+        match (expected, actual) {
+            (Value::Object(expected), Value::Object(actual)) => expected
+                .keys()
+                .chain(actual.keys())
+                .filter(|key| !self.ignore_fields.contains(key))
+                .all(|key| match (expected.get(key), actual.get(key)) {
+                    (Some(expected), Some(actual)) => self.values_match(expected, actual),
+                    _ => false,
+                }),
+            (Value::Array(expected), Value::Array(actual)) => {
+                expected.len() == actual.len()
+                    && expected
+                        .iter()
+                        .zip(actual.iter())
+                        .all(|(expected, actual)| self.values_match(expected, actual))
+            }
+            (Value::Number(expected), Value::Number(actual)) => {
+                match (expected.as_f64(), actual.as_f64()) {
+                    (Some(e), Some(a)) => (e - a).abs() <= self.float_tolerance,
+                    _ => expected == actual,
+                }
+            }
+            (expected, actual) => expected == actual,
+        }
+    }
+}
+
+/// A message sent to a stub capability during a replay.
+#[derive(Clone, Debug)]
+pub struct StubSend {
+    /// The recorded name of the capability that the stub stands in for.
+    pub stub: String,
+
+    /// The message's data.
+    pub data: Vec<u8>,
+
+    /// How many capabilities the message carried.
+    pub caps: usize,
+}
+
+/// A difference between a recording and its replay.
+#[derive(Clone, Debug)]
+pub struct ReplayMismatch {
+    /// The recorded name of the capability that the message was sent to.
+    pub stub: String,
+
+    /// The index of the message among those sent to the capability.
+    pub index: usize,
+
+    /// The recorded message, if one was recorded at this index.
+    pub expected: Option<Vec<u8>>,
+
+    /// The replayed message, if one was sent at this index.
+    pub actual: Option<Vec<u8>>,
+}
+
+impl Display for ReplayMismatch {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let show = |data: &Option<Vec<u8>>| match data {
+            Some(data) => format!("{:?}", String::from_utf8_lossy(data)),
+            None => "nothing".to_string(),
+        };
+
+        write!(
+            f,
+            "message {} to {}: expected {}, got {}",
+            self.index,
+            self.stub,
+            show(&self.expected),
+            show(&self.actual)
+        )
+    }
+}
+
+/// The outcome of [TestRuntime::replay].
+#[derive(Clone, Debug, Default)]
+pub struct ReplayReport {
+    /// How many recorded messages were delivered to the process.
+    pub delivered: usize,
+
+    /// How many recorded messages couldn't be delivered.
+    pub skipped: usize,
+
+    /// Every message sent to a stub, in the order they were collected.
+    pub sends: Vec<StubSend>,
+
+    /// Every difference between the recorded and replayed messages.
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayReport {
+    /// Tests if the replayed messages matched the recording.
+    pub fn is_match(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+
+    /// Panics if the replayed messages didn't match the recording.
+    pub fn assert_matches(&self) {
+        if self.is_match() {
+            return;
+        }
+
+        let mismatches: Vec<_> = self.mismatches.iter().map(|m| m.to_string()).collect();
+        panic!("replay did not match recording:\n{}", mismatches.join("\n"));
+    }
+}
+
+/// The state of a [TestRuntime::replay] in progress.
+struct Replay<'a, 'r> {
+    runtime: &'a TestRuntime,
+
+    /// Stub mailboxes by the recorded names of their capabilities.
+    stubs: BTreeMap<String, TestMailbox<'a>>,
+
+    /// Capabilities to the process's own mailboxes by their recorded handles.
+    mailboxes: HashMap<u32, CapabilityRef<'a>>,
+
+    /// Recorded messages to each capability, in order.
+    expected: HashMap<String, Vec<(&'r [u8], &'r [TapCap])>>,
+
+    /// Replayed messages to each stub, in order.
+    received: HashMap<String, Vec<Vec<u8>>>,
+
+    report: ReplayReport,
+}
+
+impl<'a, 'r> Replay<'a, 'r> {
+    /// Gets a capability to the stub for a recorded capability, creating the
+    /// stub if needed.
+    fn stub(&mut self, cap: &TapCap) -> CapabilityRef<'a> {
+        let runtime = self.runtime;
+        let stub = self
+            .stubs
+            .entry(cap.name.clone())
+            .or_insert_with(|| runtime.mailbox());
+
+        // stubs are never killed
+        let perms = Permissions::from_bits_truncate(cap.permissions.bits())
+            & (Permissions::SEND | Permissions::MONITOR);
+
+        stub.capability(perms)
+    }
+
+    /// Gets the capability to pass in place of a recorded one.
+    fn arg(&mut self, cap: &TapCap) -> CapabilityRef<'a> {
+        match cap.mailbox.and_then(|mailbox| self.mailboxes.get(&mailbox)) {
+            Some(own) => own.clone(),
+            None => self.stub(cap),
+        }
+    }
+
+    /// Lets the process run until idle and collects what it sent to stubs.
+    ///
+    /// Capabilities to the process's own mailboxes are learned from the
+    /// positions of the recorded ones.
+    fn collect(&mut self) {
+        self.runtime.settle();
+
+        for (name, stub) in self.stubs.iter() {
+            while let Some((data, caps)) = stub.try_recv() {
+                info!("stub {} received {} bytes", name, data.len());
+
+                let received = self.received.entry(name.clone()).or_default();
+                let index = received.len();
+
+                let recorded = self
+                    .expected
+                    .get(name)
+                    .and_then(|expected| expected.get(index));
+
+                if let Some((_, recorded_caps)) = recorded {
+                    for (recorded, cap) in recorded_caps.iter().zip(caps.iter()) {
+                        if let Some(mailbox) = recorded.mailbox {
+                            self.mailboxes.insert(mailbox, cap.clone());
+                        }
+                    }
+                }
+
+                self.report.sends.push(StubSend {
+                    stub: name.clone(),
+                    data: data.clone(),
+                    caps: caps.len(),
+                });
+
+                received.push(data);
+            }
+        }
+    }
+
+    /// Compares the replayed messages to each stub with the recorded ones.
+    fn compare(&mut self, options: &ReplayOptions) {
+        let mut names: Vec<_> = self.expected.keys().chain(self.received.keys()).collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            // only capabilities given to the process are observable
+            if !self.stubs.contains_key(name) {
+                continue;
+            }
+
+            let expected = self
+                .expected
+                .get(name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let actual = self
+                .received
+                .get(name)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            for index in 0..expected.len().max(actual.len()) {
+                let expected = expected.get(index).map(|(data, _)| *data);
+                let actual = actual.get(index).map(Vec::as_slice);
+
+                let matches = match (expected, actual) {
+                    (Some(expected), Some(actual)) => options.matches(expected, actual),
+                    _ => false,
+                };
+
+                if !matches {
+                    self.report.mismatches.push(ReplayMismatch {
+                        stub: name.clone(),
+                        index,
+                        expected: expected.map(<[u8]>::to_vec),
+                        actual: actual.map(<[u8]>::to_vec),
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// An in-memory stand-in for the native filesystem service.
+///
+/// Directories are implicit: a directory exists while any file is inside it.
+/// Watches and read-only capabilities are unsupported.
+pub struct MemoryFs {
+    files: HashMap<String, Bytes>,
+}
+
+impl MemoryFs {
+    /// Creates a filesystem with the given paths and contents.
+    pub fn new<P, D>(files: impl IntoIterator<Item = (P, D)>) -> Self
+    where
+        P: Into<String>,
+        D: Into<Bytes>,
+    {
+        Self {
+            files: files
+                .into_iter()
+                .map(|(path, data)| (path.into(), data.into()))
+                .collect(),
+        }
+    }
+
+    fn list(&self, dir: &str) -> fs::Response {
+        let prefix = match dir.trim_matches('/') {
+            "" => String::new(),
+            dir => format!("{}/", dir),
+        };
+
+        let mut names: Vec<_> = self
+            .files
+            .keys()
+            .filter_map(|path| path.strip_prefix(&prefix))
+            .map(|rest| rest.split('/').next().unwrap().to_string())
+            .collect();
+
+        if names.is_empty() && !prefix.is_empty() {
+            return Err(fs::Error::NotFound);
+        }
+
+        names.sort();
+        names.dedup();
+
+        Ok(Success::List(
+            names.into_iter().map(|name| FileInfo { name }).collect(),
+        ))
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for MemoryFs {
+    type Request = fs::Request;
+    type Response = fs::Response;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, fs::Request>,
+    ) -> ResponseInfo<'a, fs::Response> {
+        let target = request.data.target.trim_matches('/').to_string();
+        let lumps = &request.runtime.lump_store;
+
+        let data = match &request.data.kind {
+            RequestKind::Get => match self.files.get(&target) {
+                Some(data) => Ok(Success::Get(lumps.add_lump(data.clone()).await)),
+                None => Err(fs::Error::NotFound),
+            },
+            RequestKind::List => self.list(&target),
+            RequestKind::Write(lump) => match lumps.get_lump(lump).await {
+                Some(data) => {
+                    self.files.insert(target, data);
+                    Ok(Success::Done)
+                }
+                None => Err(fs::Error::LumpNotFound),
+            },
+            RequestKind::CreateDir => Ok(Success::Done),
+            RequestKind::Delete => match self.files.remove(&target) {
+                Some(_) => Ok(Success::Done),
+                None => Err(fs::Error::NotFound),
+            },
+            RequestKind::Rename { to } => match self.files.remove(&target) {
+                Some(data) => {
+                    self.files.insert(to.trim_matches('/').to_string(), data);
+                    Ok(Success::Done)
+                }
+                None => Err(fs::Error::NotFound),
+            },
+            RequestKind::ReadOnly | RequestKind::Watch { .. } => Err(fs::Error::InvalidRequest),
+        };
+
+        ResponseInfo { data, caps: vec![] }
+    }
+}
+
+impl ServiceRunner for MemoryFs {
+    const NAME: &'static str = "hearth.fs.Filesystem";
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = crate::utils::cargo_process_metadata!();
+        meta.description = Some("An in-memory test filesystem. Accepts FsRequest.".to_string());
+        meta
+    }
+}
+
+/// An in-memory stand-in for the network plugin's services.
+///
+/// Provides a connection status service that always reports the same status
+/// and an identities service with a fixed list of identities.
+pub struct MemoryNetwork {
+    /// The connection status to report.
+    pub status: ConnectionStatus,
+
+    /// The identities to list as connected.
+    pub identities: Vec<ConnectedIdentity>,
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self {
+            status: ConnectionStatus::Online,
+            identities: Vec::new(),
+        }
+    }
+}
+
+impl Plugin for MemoryNetwork {
+    fn finalize(self, builder: &mut RuntimeBuilder) {
+        builder.add_service(
+            CONNECTION_STATUS_SERVICE.to_string(),
+            ProcessMetadata {
+                name: Some(CONNECTION_STATUS_SERVICE.to_string()),
+                ..Default::default()
+            },
+            FixedStatus(self.status),
+        );
+
+        builder.add_service(
+            IDENTITIES_SERVICE.to_string(),
+            ProcessMetadata {
+                name: Some(IDENTITIES_SERVICE.to_string()),
+                ..Default::default()
+            },
+            FixedIdentities(self.identities),
+        );
+    }
+}
+
+struct FixedStatus(ConnectionStatus);
+
+#[async_trait]
+impl SinkProcess for FixedStatus {
+    type Message = ConnectionStatusRequest;
+
+    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>) {
+        let ConnectionStatusRequest::Subscribe = message.data else {
+            return;
+        };
+
+        let Some(subscriber) = message.caps.first() else {
+            return;
+        };
+
+        let data = serde_json::to_vec(&self.0).unwrap();
+        let _ = subscriber.send(&data, &[]).await;
+    }
+}
+
+struct FixedIdentities(Vec<ConnectedIdentity>);
+
+#[async_trait]
+impl RequestResponseProcess for FixedIdentities {
+    type Request = IdentitiesRequest;
+    type Response = Vec<ConnectedIdentity>;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        _request: &mut RequestInfo<'a, IdentitiesRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        ResponseInfo {
+            data: self.0.clone(),
+            caps: vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(runtime: &TestRuntime, target: &str) -> fs::Response {
+        let fs = runtime.get_service("hearth.fs.Filesystem").unwrap();
+        let request = fs::Request {
+            target: target.to_string(),
+            kind: RequestKind::Get,
+        };
+
+        runtime.request(&fs, &request, &[]).0
+    }
+
+    #[test]
+    fn memory_fs_get() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryFs::new([("dir/hello.txt", "hello!")]));
+        let runtime = builder.build();
+
+        let Ok(Success::Get(lump)) = get(&runtime, "/dir/hello.txt") else {
+            panic!("expected file");
+        };
+
+        let data = runtime.block_on(runtime.runtime().lump_store.get_lump(&lump));
+        assert_eq!(data.unwrap(), Bytes::from("hello!"));
+        assert!(matches!(get(&runtime, "missing"), Err(fs::Error::NotFound)));
+    }
+
+    #[test]
+    fn memory_fs_list() {
+        let files = [("a/one", ""), ("a/b/two", ""), ("three", "")];
+        let fs = MemoryFs::new(files);
+
+        let names = |dir| match fs.list(dir) {
+            Ok(Success::List(files)) => files.into_iter().map(|f| f.name).collect::<Vec<_>>(),
+            other => panic!("unexpected {:?}", other),
+        };
+
+        assert_eq!(names(""), ["a", "three"]);
+        assert_eq!(names("a"), ["b", "one"]);
+        assert!(fs.list("nope").is_err());
+    }
+
+    #[test]
+    fn memory_network_status() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(MemoryNetwork::default());
+        let runtime = builder.build();
+
+        let status = runtime.get_service(CONNECTION_STATUS_SERVICE).unwrap();
+        let mailbox = runtime.mailbox();
+        let subscriber = mailbox.capability(Permissions::SEND);
+        runtime.send(&status, &ConnectionStatusRequest::Subscribe, &[&subscriber]);
+
+        let (status, _) = mailbox.recv_json::<ConnectionStatus>();
+        assert_eq!(status, ConnectionStatus::Online);
+    }
+
+    #[test]
+    fn paused_time_skips_ahead() {
+        let runtime = TestRuntimeBuilder::new().build();
+
+        let start = runtime.block_on(async { tokio::time::Instant::now() });
+        runtime.block_on(tokio::time::sleep(Duration::from_secs(60 * 60)));
+        let elapsed = runtime.block_on(async { start.elapsed() });
+
+        assert!(elapsed >= Duration::from_secs(60 * 60));
+    }
+
+    #[test]
+    fn collects_process_logs() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let process =
+            runtime.block_on(async { runtime.runtime().process_factory.spawn(Default::default()) });
+
+        process
+            .borrow_info()
+            .log_tx
+            .send(ProcessLogEvent::new(
+                hearth_schema::ProcessLogLevel::Info,
+                "test",
+                "hello from the test",
+            ))
+            .unwrap();
+
+        runtime.assert_logged("hello from the test");
+    }
+}
@@ -16,16 +16,30 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{any::type_name, collections::HashMap, fmt::Debug, marker::PhantomData, sync::Arc};
+use std::{
+    any::{type_name, Any},
+    collections::HashMap,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    marker::PhantomData,
+    panic::AssertUnwindSafe,
+    sync::Arc,
+    time::Duration,
+};
 
+use anyhow::Context;
 use async_trait::async_trait;
-use flue::{CapabilityHandle, CapabilityRef, OwnedTableSignal, Permissions, PostOffice, Table};
+use flue::{
+    CapabilityHandle, CapabilityRef, Mailbox, OwnedTableSignal, Permissions, PostOffice, Table,
+    TableSignal,
+};
+use futures_util::FutureExt;
+use hearth_schema::{codec, shutdown::ShutdownRequest, ProcessLogLevel};
 use parking_lot::Mutex;
-use serde::{Deserialize, Serialize};
-use tracing::{debug, error, trace};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{debug, error, trace, Instrument};
 
 use crate::{
-    process::{Process, ProcessMetadata},
+    process::{Process, ProcessLogEvent, ProcessMetadata},
     runtime::{Plugin, Runtime, RuntimeBuilder},
 };
 
@@ -54,6 +68,7 @@ macro_rules! cargo_process_metadata {
         meta.authors = some_or_empty(env!("CARGO_PKG_AUTHORS"))
             .map(|authors| authors.split(':').map(ToString::to_string).collect());
 
+        meta.version = some_or_empty(env!("CARGO_PKG_VERSION"));
         meta.repository = some_or_empty(env!("CARGO_PKG_REPOSITORY"));
         meta.homepage = some_or_empty(env!("CARGO_PKG_HOMEPAGE"));
         meta.license = some_or_empty(env!("CARGO_PKG_LICENSE"));
@@ -75,6 +90,8 @@ pub trait RunnerContext<'a> {
     /// Spawns a child process, executes it using the given process runner,
     /// and returns a capability to its parent mailbox within this runners'
     /// table.
+    ///
+    /// The child joins this process's group, if it has one.
     fn spawn(
         &self,
         meta: ProcessMetadata,
@@ -82,7 +99,9 @@ pub trait RunnerContext<'a> {
     ) -> CapabilityRef<'a> {
         let label = meta.name.clone().unwrap_or("<no name>".to_string());
         let runtime = self.get_runtime().to_owned();
-        let child = runtime.process_factory.spawn(meta);
+        let child = runtime
+            .process_factory
+            .spawn_child(meta, self.get_process(), None);
         let perms = Permissions::all();
 
         let child_cap = child
@@ -90,9 +109,13 @@ pub trait RunnerContext<'a> {
             .export_to(perms, self.get_process().borrow_table())
             .unwrap();
 
-        tokio::spawn(async move {
-            runner.run(label, runtime, &child).await;
-        });
+        let span = child.span();
+        tokio::spawn(
+            async move {
+                runner.run(label, runtime, &child).await;
+            }
+            .instrument(span),
+        );
 
         child_cap
     }
@@ -197,16 +220,32 @@ pub trait ProcessRunner: Send {
     async fn run(mut self, label: String, runtime: Arc<Runtime>, ctx: &Process);
 }
 
-/// A trait for process runners that continuously receive JSON-formatted messages of a single type.
+/// A trait for process runners that continuously receive messages of a single type.
 ///
 /// This trait has a blanket implementation for [ProcessRunner] that loops and
 /// receives new messages of the given data type, and calls [Self::on_message]
-/// with a [RequestInfo].
+/// with a [RequestInfo]. Messages may be encoded in either format supported
+/// by [hearth_schema::codec].
+///
+/// Panics in [Self::on_message] or [Self::on_down] are caught and written to
+/// the process log so that a single bad message can't take down the whole
+/// process. After [Self::MAX_CONSECUTIVE_PANICS] panics in a row, the
+/// process exits instead. Plain services then go down, so clients holding
+/// capabilities to them are notified and can look them up again. Services
+/// added with [Supervised](crate::supervisor::Supervised) are restarted on
+/// the same process, so their capabilities keep working.
+///
+/// When an authorized [ShutdownRequest] arrives (see [shutdown]),
+/// [Self::on_shutdown] is called and then the process exits.
 #[async_trait]
 pub trait SinkProcess: Send {
     /// The deserializeable data type to be received.
     type Message: for<'a> Deserialize<'a> + Send + Debug;
 
+    /// How many messages or down signals in a row may panic before this
+    /// process exits.
+    const MAX_CONSECUTIVE_PANICS: usize = 3;
+
     /// A callback to call when messages are received by this process.
     async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>);
 
@@ -215,6 +254,12 @@ pub trait SinkProcess: Send {
     /// The capability passed is the capability in the down signal; a version
     /// of the monitored capability with no permissions.
     async fn on_down<'a>(&'a mut self, _cap: CapabilityRef<'a>) {}
+
+    /// A callback to call when this process is asked to shut down.
+    ///
+    /// The process exits once this returns. If this takes longer than the
+    /// request's grace period, the process may be killed first.
+    async fn on_shutdown(&mut self, _request: ShutdownRequest) {}
 }
 
 #[async_trait]
@@ -223,13 +268,41 @@ where
     T: SinkProcess,
 {
     async fn run(mut self, label: String, runtime: Arc<Runtime>, ctx: &Process) {
+        let mut panics = 0;
+
         loop {
+            if panics >= T::MAX_CONSECUTIVE_PANICS {
+                let content = format!("exiting after {} consecutive panics", panics);
+                error!("{:?} {}", label, content);
+                log_error(ctx, content);
+                break;
+            }
+
             let recv = ctx.borrow_parent().recv_owned().await;
 
             use OwnedTableSignal::*;
             match recv {
                 Some(Message { data, caps }) => {
-                    let data: T::Message = match serde_json::from_slice(&data) {
+                    if let Some(request) = ShutdownRequest::decode(&data) {
+                        if !is_shutdown_authorized(ctx, &caps) {
+                            debug!("{:?} ignored an unauthorized shutdown request", label);
+                            continue;
+                        }
+
+                        debug!("{:?} shutting down", label);
+                        let result = AssertUnwindSafe(self.on_shutdown(request))
+                            .catch_unwind()
+                            .await;
+
+                        if let Err(panic) = result {
+                            let msg = panic_message(panic.as_ref());
+                            error!("{:?} panicked while shutting down: {}", label, msg);
+                        }
+
+                        break;
+                    }
+
+                    let data: T::Message = match codec::decode(&data) {
                         Ok(request) => request,
                         Err(err) => {
                             // TODO make this a process log
@@ -240,19 +313,44 @@ where
 
                     trace!("{:?} received {:?}", label, data);
 
-                    self.on_message(MessageInfo {
+                    let result = AssertUnwindSafe(self.on_message(MessageInfo {
                         label: &label,
                         process: ctx,
                         runtime: &runtime,
                         data,
                         caps: &caps,
-                    })
+                    }))
+                    .catch_unwind()
                     .await;
 
+                    if let Err(panic) = result {
+                        let content = format!(
+                            "panicked while handling {}: {}",
+                            type_name::<T::Message>(),
+                            panic_message(panic.as_ref())
+                        );
+
+                        error!("{:?} {}", label, content);
+                        log_error(ctx, content);
+                        panics += 1;
+                        continue;
+                    }
+
+                    panics = 0;
                     trace!("{:?} finished processing message", label);
                 }
                 Some(Down { handle }) => {
-                    self.on_down(handle).await;
+                    let result = AssertUnwindSafe(self.on_down(handle)).catch_unwind().await;
+
+                    if let Err(panic) = result {
+                        let msg = panic_message(panic.as_ref());
+                        let content = format!("panicked while handling a down signal: {}", msg);
+                        error!("{:?} {}", label, content);
+                        log_error(ctx, content);
+                        panics += 1;
+                    } else {
+                        panics = 0;
+                    }
                 }
                 None => break, // killed; quit
             }
@@ -260,11 +358,31 @@ where
     }
 }
 
+/// Writes an error to a process's log.
+fn log_error(process: &Process, content: String) {
+    let event = ProcessLogEvent::new(ProcessLogLevel::Error, "runner", content);
+    let _ = process.borrow_info().log_tx.send(event);
+}
+
+/// Extracts the message from a caught panic's payload.
+pub(crate) fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(msg) = panic.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = panic.downcast_ref::<String>() {
+        msg
+    } else {
+        "<unknown panic>"
+    }
+}
+
 #[async_trait]
 pub trait RequestResponseProcess: Send {
     type Request: for<'a> Deserialize<'a> + Send + Debug;
     type Response: Serialize + Send + Debug;
 
+    /// See [SinkProcess::MAX_CONSECUTIVE_PANICS].
+    const MAX_CONSECUTIVE_PANICS: usize = 3;
+
     async fn on_request<'a>(
         &'a mut self,
         request: &mut RequestInfo<'a, Self::Request>,
@@ -275,6 +393,10 @@ pub trait RequestResponseProcess: Send {
     /// The capability passed is the capability in the down signal; a version
     /// of the monitored capability with no permissions.
     async fn on_down<'a>(&'a mut self, _cap: CapabilityRef<'a>) {}
+
+    /// A callback to call when this process is asked to shut down. See
+    /// [SinkProcess::on_shutdown].
+    async fn on_shutdown(&mut self, _request: ShutdownRequest) {}
 }
 
 #[async_trait]
@@ -284,6 +406,8 @@ where
 {
     type Message = T::Request;
 
+    const MAX_CONSECUTIVE_PANICS: usize = <T as RequestResponseProcess>::MAX_CONSECUTIVE_PANICS;
+
     async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>) {
         let Some(reply) = message.caps.first().cloned() else {
             debug!("Request to {:?} has no reply address", message.label);
@@ -313,6 +437,10 @@ where
         // clarify trait so we don't make this function recursive
         <T as RequestResponseProcess>::on_down(self, cap).await;
     }
+
+    async fn on_shutdown(&mut self, request: ShutdownRequest) {
+        <T as RequestResponseProcess>::on_shutdown(self, request).await;
+    }
 }
 
 pub trait ServiceRunner: ProcessRunner {
@@ -336,6 +464,271 @@ where
     }
 }
 
+/// The error returned when a [recv_timeout] deadline passes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout;
+
+impl Display for Timeout {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        write!(f, "timed out")
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// Waits for a mailbox to receive a signal, giving up after `timeout`.
+///
+/// Returns `Ok(None)` if the mailbox's process has been killed, like
+/// [Mailbox::recv].
+pub async fn recv_timeout<T>(
+    mailbox: &Mailbox<'_>,
+    timeout: Duration,
+    on_recv: impl FnOnce(TableSignal<'_>) -> T,
+) -> Result<Option<T>, Timeout> {
+    tokio::time::timeout(timeout, mailbox.recv(on_recv))
+        .await
+        .map_err(|_| Timeout)
+}
+
+/// Tests if a [ShutdownRequest] received by `process` with the given
+/// capabilities was sent by a holder of the kill permission.
+///
+/// An authorized request carries a capability to the receiving process's
+/// parent mailbox with the kill permission.
+pub fn is_shutdown_authorized(process: &Process, caps: &[CapabilityRef<'_>]) -> bool {
+    let Some(cap) = caps.first() else {
+        return false;
+    };
+
+    if !cap.get_permissions().contains(Permissions::KILL) {
+        return false;
+    }
+
+    // capabilities to the same mailbox with the same permissions share a
+    // handle, so compare permissionless versions of both
+    let (Ok(theirs), Ok(ours)) = (
+        cap.demote(Permissions::empty()),
+        process.borrow_parent().export(Permissions::empty()),
+    ) else {
+        return false;
+    };
+
+    let table = process.borrow_table();
+    let theirs = theirs.into_handle();
+    let ours = ours.into_handle();
+    let _ = table.dec_ref(theirs);
+    let _ = table.dec_ref(ours);
+    theirs == ours
+}
+
+/// How a process ended after a call to [shutdown].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownOutcome {
+    /// The process exited within its grace period.
+    Exited,
+
+    /// The process was killed after its grace period passed.
+    Killed,
+}
+
+/// Gracefully shuts down the process behind `cap`.
+///
+/// Sends it a [ShutdownRequest], then waits up to `grace` for it to exit
+/// before killing it. `cap` must have the send, monitor, and kill
+/// permissions and belong to `process`'s table.
+///
+/// If the process has to be killed, the kill is audited as done by
+/// `process`.
+pub async fn shutdown(
+    process: &Process,
+    cap: &CapabilityRef<'_>,
+    grace: Duration,
+) -> anyhow::Result<ShutdownOutcome> {
+    let mailbox = process
+        .borrow_group()
+        .create_mailbox()
+        .context("failed to create monitoring mailbox")?;
+
+    let outcome = shutdown_with(&mailbox, cap, grace).await?;
+    if outcome == ShutdownOutcome::Killed {
+        process.borrow_info().audit_kill(None);
+    }
+
+    Ok(outcome)
+}
+
+/// Gracefully shuts down the process behind `cap` like [shutdown], watching
+/// for it to go down with `mailbox`.
+///
+/// `mailbox` and `cap` must belong to the same table.
+pub(crate) async fn shutdown_with(
+    mailbox: &Mailbox<'_>,
+    cap: &CapabilityRef<'_>,
+    grace: Duration,
+) -> anyhow::Result<ShutdownOutcome> {
+    cap.monitor(mailbox).context("failed to monitor process")?;
+
+    let request = ShutdownRequest { grace };
+    cap.send(&request.encode(), &[cap])
+        .await
+        .context("failed to send shutdown request")?;
+
+    let on_recv = |signal: TableSignal<'_>| matches!(signal, TableSignal::Down { .. });
+    let deadline = tokio::time::Instant::now().checked_add(grace);
+
+    loop {
+        // a deadline too far away to represent is never reached
+        let remaining = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(tokio::time::Instant::now()),
+            None => grace,
+        };
+
+        match recv_timeout(mailbox, remaining, on_recv).await {
+            // the process went down
+            Ok(Some(true)) => return Ok(ShutdownOutcome::Exited),
+            // stray message; keep waiting
+            Ok(Some(false)) => continue,
+            Ok(None) => anyhow::bail!("shutting down process was killed"),
+            Err(Timeout) => break,
+        }
+    }
+
+    cap.kill().context("failed to kill process")?;
+    Ok(ShutdownOutcome::Killed)
+}
+
+/// The default deadline of a [RequestResponse] request.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An error from [RequestResponse::request].
+#[derive(Debug)]
+pub enum RequestError {
+    /// No response arrived before the deadline.
+    Timeout,
+
+    /// The callee went down before responding.
+    Down,
+
+    /// The requesting process was killed while waiting.
+    Killed,
+
+    /// The response couldn't be deserialized.
+    InvalidResponse(serde_json::Error),
+
+    /// The request couldn't be sent.
+    Send(anyhow::Error),
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            RequestError::Timeout => write!(f, "request timed out"),
+            RequestError::Down => write!(f, "callee went down before responding"),
+            RequestError::Killed => write!(f, "process was killed during request"),
+            RequestError::InvalidResponse(err) => write!(f, "invalid response: {}", err),
+            RequestError::Send(err) => write!(f, "failed to send request: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// A helper for sending JSON requests to a capability and awaiting typed
+/// responses with a deadline.
+///
+/// Each request is sent with a fresh reply capability as its first
+/// capability, the same convention as [RequestResponseProcess]. If the
+/// callee capability permits monitoring, the request fails early with
+/// [RequestError::Down] when the callee goes down.
+pub struct RequestResponse<'a, Request, Response> {
+    process: &'a Process,
+    cap: CapabilityRef<'a>,
+    timeout: Duration,
+    _phantom: PhantomData<fn(Request) -> Response>,
+}
+
+impl<'a, Request, Response> RequestResponse<'a, Request, Response>
+where
+    Request: Serialize,
+    Response: DeserializeOwned,
+{
+    /// Wraps a capability in `process`'s table with the default timeout.
+    pub fn new(process: &'a Process, cap: CapabilityRef<'a>) -> Self {
+        Self {
+            process,
+            cap,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Sets the deadline of each request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends a request and waits for its response.
+    ///
+    /// The response's capabilities are returned in this process's table.
+    pub async fn request(
+        &self,
+        request: &Request,
+        args: &[&CapabilityRef<'_>],
+    ) -> Result<(Response, Vec<CapabilityRef<'a>>), RequestError> {
+        let data = serde_json::to_vec(request).unwrap();
+
+        let reply = self
+            .process
+            .borrow_group()
+            .create_mailbox()
+            .context("failed to create reply mailbox")
+            .map_err(RequestError::Send)?;
+
+        let reply_cap = reply
+            .export(Permissions::SEND)
+            .context("failed to export reply capability")
+            .map_err(RequestError::Send)?;
+
+        if self.cap.get_permissions().contains(Permissions::MONITOR) {
+            self.cap
+                .monitor(&reply)
+                .context("failed to monitor callee")
+                .map_err(RequestError::Send)?;
+        }
+
+        let mut caps = Vec::with_capacity(args.len() + 1);
+        caps.push(&reply_cap);
+        caps.extend_from_slice(args);
+
+        self.cap
+            .send(&data, &caps)
+            .await
+            .context("failed to send request")
+            .map_err(RequestError::Send)?;
+
+        let on_recv = |signal: TableSignal<'_>| match signal {
+            TableSignal::Message { data, caps } => Some((data.to_vec(), caps)),
+            TableSignal::Down { .. } => None,
+        };
+
+        let (data, handles) = recv_timeout(&reply, self.timeout, on_recv)
+            .await
+            .map_err(|_| RequestError::Timeout)?
+            .ok_or(RequestError::Killed)?
+            .ok_or(RequestError::Down)?;
+
+        let table = self.process.borrow_table();
+        let caps = handles
+            .into_iter()
+            .map(|handle| table.wrap_handle(handle).unwrap())
+            .collect();
+
+        let response = serde_json::from_slice(&data).map_err(RequestError::InvalidResponse)?;
+        Ok((response, caps))
+    }
+}
+
 /// A shared utility struct for publishing event messages of type `T` to a
 /// dynamic list of subscribers.
 pub struct PubSub<T> {
@@ -444,3 +837,282 @@ impl<T: Serialize> PubSub<T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::sync::mpsc;
+
+    use crate::runtime::RuntimeConfig;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    enum TestCommand {
+        Panic,
+        SetTitle(String),
+    }
+
+    #[derive(Clone)]
+    struct PanickingSink {
+        titles: mpsc::UnboundedSender<String>,
+    }
+
+    #[async_trait]
+    impl SinkProcess for PanickingSink {
+        type Message = TestCommand;
+
+        async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, TestCommand>) {
+            match message.data {
+                TestCommand::Panic => panic!("unimplemented command"),
+                TestCommand::SetTitle(title) => self.titles.send(title).unwrap(),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sink_survives_panic() {
+        let runtime = RuntimeBuilder::new(Default::default())
+            .run(RuntimeConfig::default())
+            .await;
+
+        let (titles_tx, mut titles_rx) = mpsc::unbounded_channel();
+        let sink = PanickingSink { titles: titles_tx };
+
+        let process = runtime.process_factory.spawn(ProcessMetadata::default());
+        let sender = runtime.process_factory.spawn(ProcessMetadata::default());
+        let cap = process
+            .borrow_parent()
+            .export_to(Permissions::SEND, sender.borrow_table())
+            .unwrap();
+
+        let runner_runtime = runtime.clone();
+        tokio::spawn(async move {
+            sink.run("test".to_string(), runner_runtime, &process).await;
+        });
+
+        for command in [TestCommand::Panic, TestCommand::SetTitle("title".into())] {
+            let data = serde_json::to_vec(&command).unwrap();
+            cap.send(&data, &[]).await.unwrap();
+        }
+
+        assert_eq!(titles_rx.recv().await.unwrap(), "title");
+    }
+
+    impl ServiceRunner for PanickingSink {
+        const NAME: &'static str = "test.Panicking";
+
+        fn get_process_metadata() -> ProcessMetadata {
+            ProcessMetadata::default()
+        }
+    }
+
+    fn panic_then_set_title(runtime: &crate::testing::TestRuntime, panics: usize) {
+        let cap = runtime.get_service(PanickingSink::NAME).unwrap();
+        for _ in 0..panics {
+            runtime.send(&cap, &TestCommand::Panic, &[]);
+        }
+
+        runtime.send(&cap, &TestCommand::SetTitle("title".into()), &[]);
+    }
+
+    #[test]
+    fn sink_panics_are_logged() {
+        let (titles_tx, mut titles_rx) = mpsc::unbounded_channel();
+        let mut builder = crate::testing::TestRuntimeBuilder::new();
+        builder.add_plugin(PanickingSink { titles: titles_tx });
+        let runtime = builder.build();
+
+        panic_then_set_title(&runtime, 1);
+        assert_eq!(runtime.block_on(titles_rx.recv()).unwrap(), "title");
+        runtime.assert_logged("panicked while handling");
+        runtime.assert_logged(type_name::<TestCommand>());
+    }
+
+    #[test]
+    fn sink_exits_after_consecutive_panics() {
+        let (titles_tx, _titles_rx) = mpsc::unbounded_channel();
+        let mut builder = crate::testing::TestRuntimeBuilder::new();
+        builder.add_plugin(PanickingSink { titles: titles_tx });
+        let runtime = builder.build();
+
+        let cap = runtime.get_service(PanickingSink::NAME).unwrap();
+        let monitor = runtime.mailbox();
+        monitor.monitor(&cap);
+
+        for _ in 0..PanickingSink::MAX_CONSECUTIVE_PANICS {
+            runtime.send(&cap, &TestCommand::Panic, &[]);
+        }
+
+        monitor.recv_down();
+        runtime.assert_logged("exiting after 3 consecutive panics");
+    }
+
+    #[test]
+    fn supervised_sink_restarts_in_place() {
+        let (titles_tx, mut titles_rx) = mpsc::unbounded_channel();
+        let mut builder = crate::testing::TestRuntimeBuilder::new();
+        builder.add_plugin(crate::supervisor::Supervised::new(PanickingSink {
+            titles: titles_tx,
+        }));
+
+        let runtime = builder.build();
+
+        panic_then_set_title(&runtime, PanickingSink::MAX_CONSECUTIVE_PANICS);
+        assert_eq!(runtime.block_on(titles_rx.recv()).unwrap(), "title");
+    }
+
+    struct Echo;
+
+    #[async_trait]
+    impl RequestResponseProcess for Echo {
+        type Request = String;
+        type Response = String;
+
+        async fn on_request<'a>(
+            &'a mut self,
+            request: &mut RequestInfo<'a, String>,
+        ) -> ResponseInfo<'a, String> {
+            request.data.clone().into()
+        }
+    }
+
+    struct Silent;
+
+    #[async_trait]
+    impl SinkProcess for Silent {
+        type Message = String;
+
+        async fn on_message<'a>(&'a mut self, _message: MessageInfo<'a, String>) {}
+    }
+
+    /// Exits as soon as it receives anything.
+    struct Dying;
+
+    #[async_trait]
+    impl ProcessRunner for Dying {
+        async fn run(mut self, _label: String, _runtime: Arc<Runtime>, ctx: &Process) {
+            ctx.borrow_parent().recv(|_| ()).await;
+        }
+    }
+
+    fn request(service: &str) -> Result<String, RequestError> {
+        let mut builder = crate::testing::TestRuntimeBuilder::new();
+        builder
+            .add_service("Echo", Echo)
+            .add_service("Silent", Silent)
+            .add_service("Dying", Dying);
+        let runtime = builder.build();
+
+        let cap = runtime.get_service(service).unwrap();
+        let rr = RequestResponse::<String, String>::new(runtime.process(), cap)
+            .with_timeout(Duration::from_secs(5));
+
+        let result = runtime.block_on(rr.request(&"hello".to_string(), &[]));
+        result.map(|(response, _)| response)
+    }
+
+    #[test]
+    fn request_reply() {
+        assert_eq!(request("Echo").unwrap(), "hello");
+    }
+
+    #[test]
+    fn request_timeout() {
+        assert!(matches!(request("Silent"), Err(RequestError::Timeout)));
+    }
+
+    #[test]
+    fn request_callee_down() {
+        assert!(matches!(request("Dying"), Err(RequestError::Down)));
+    }
+
+    /// Records when it's asked to shut down.
+    struct Cooperative {
+        shut_down: mpsc::UnboundedSender<ShutdownRequest>,
+    }
+
+    #[async_trait]
+    impl SinkProcess for Cooperative {
+        type Message = String;
+
+        async fn on_message<'a>(&'a mut self, _message: MessageInfo<'a, String>) {}
+
+        async fn on_shutdown(&mut self, request: ShutdownRequest) {
+            self.shut_down.send(request).unwrap();
+        }
+    }
+
+    /// Ignores every signal, including shutdown requests.
+    struct Stubborn;
+
+    #[async_trait]
+    impl ProcessRunner for Stubborn {
+        async fn run(mut self, _label: String, _runtime: Arc<Runtime>, ctx: &Process) {
+            while ctx.borrow_parent().recv(|_| ()).await.is_some() {}
+        }
+    }
+
+    const GRACE: Duration = Duration::from_millis(200);
+
+    /// Spawns a runner and shuts it down, returning how it ended and how long
+    /// that took.
+    async fn shut_down(runner: impl ProcessRunner + 'static) -> (ShutdownOutcome, Duration) {
+        let runtime = RuntimeBuilder::new(Default::default())
+            .run(RuntimeConfig::default())
+            .await;
+
+        let process = runtime.process_factory.spawn(ProcessMetadata::default());
+        let parent = runtime.process_factory.spawn(ProcessMetadata::default());
+        let cap = process
+            .borrow_parent()
+            .export_to(Permissions::all(), parent.borrow_table())
+            .unwrap();
+
+        let runner_runtime = runtime.clone();
+        tokio::spawn(async move {
+            runner
+                .run("test".to_string(), runner_runtime, &process)
+                .await;
+        });
+
+        let start = tokio::time::Instant::now();
+        let outcome = shutdown(&parent, &cap, GRACE).await.unwrap();
+        (outcome, start.elapsed())
+    }
+
+    #[tokio::test]
+    async fn cooperative_shutdown() {
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let (outcome, elapsed) = shut_down(Cooperative { shut_down: tx }).await;
+        assert_eq!(outcome, ShutdownOutcome::Exited);
+        assert!(elapsed < GRACE, "took {:?}", elapsed);
+        assert_eq!(rx.try_recv().unwrap().grace, GRACE);
+    }
+
+    #[tokio::test]
+    async fn uncooperative_shutdown() {
+        let (outcome, elapsed) = shut_down(Stubborn).await;
+        assert_eq!(outcome, ShutdownOutcome::Killed);
+        assert!(elapsed >= GRACE, "took {:?}", elapsed);
+    }
+
+    #[test]
+    fn unauthorized_shutdown_is_ignored() {
+        let mut builder = crate::testing::TestRuntimeBuilder::new();
+        builder.add_service("Echo", Echo);
+        let runtime = builder.build();
+
+        // registry services can't be killed, so this has no kill permission
+        let cap = runtime.get_service("Echo").unwrap();
+        let request = ShutdownRequest { grace: GRACE };
+        runtime
+            .block_on(cap.send(&request.encode(), &[&cap]))
+            .unwrap();
+
+        let rr = RequestResponse::<String, String>::new(runtime.process(), cap)
+            .with_timeout(Duration::from_secs(5));
+
+        let result = runtime.block_on(rr.request(&"hello".to_string(), &[]));
+        assert_eq!(result.unwrap().0, "hello");
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Each ABI module is defined once here as a macro that passes the module's
+//! name and function signatures to a callback macro. The Wasm host and
+//! guests both expand the same definition, so the host can check at compile
+//! time that it implements exactly the functions that guests import.
+//!
+//! A definition is invoked as `lump_abi!(callback, args...)`, which expands
+//! to:
+//!
+//! ```text
+//! callback! {
+//!     [args...]
+//!     module = "hearth::lump";
+//!     /// docs
+//!     fn this_lump(id_ptr: u32);
+//!     ...
+//! }
+//! ```
+//!
+//! Signatures use the Wasm-level types that values are passed with, so
+//! pointers and handles are all `u32`.
+//!
+//! Functions that can fail in ways a guest can recover from return an `i32`
+//! instead. A negative return value is the negated code of a [HearthError],
+//! and anything else is the function's result, if it has one. Errors that
+//! guests can't recover from, like accessing memory out of bounds, still trap.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use serde::{Deserialize, Serialize};
+
+/// A recoverable error from a host ABI function.
+///
+/// Each variant has a stable numeric [code](Self::code) that's passed across
+/// the ABI. Messages aren't passed, so guests describe errors themselves with
+/// [Self::from_code].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HearthError {
+    /// A requested resource, like a lump, doesn't exist.
+    NotFound(String),
+
+    /// A handle doesn't refer to anything in the process's tables.
+    InvalidHandle(String),
+
+    /// The process isn't allowed to perform the operation.
+    PermissionDenied(String),
+
+    /// An argument is out of range or malformed.
+    InvalidArgument(String),
+
+    /// An error with a code that this build doesn't know about, such as one
+    /// added in a newer host.
+    Unknown(i32, String),
+}
+
+impl HearthError {
+    /// The code of [HearthError::NotFound].
+    pub const NOT_FOUND: i32 = 1;
+
+    /// The code of [HearthError::InvalidHandle].
+    pub const INVALID_HANDLE: i32 = 2;
+
+    /// The code of [HearthError::PermissionDenied].
+    pub const PERMISSION_DENIED: i32 = 3;
+
+    /// The code of [HearthError::InvalidArgument].
+    pub const INVALID_ARGUMENT: i32 = 4;
+
+    /// Gets this error's stable numeric code. Codes are always positive.
+    pub fn code(&self) -> i32 {
+        match self {
+            HearthError::NotFound(_) => Self::NOT_FOUND,
+            HearthError::InvalidHandle(_) => Self::INVALID_HANDLE,
+            HearthError::PermissionDenied(_) => Self::PERMISSION_DENIED,
+            HearthError::InvalidArgument(_) => Self::INVALID_ARGUMENT,
+            HearthError::Unknown(code, _) => *code,
+        }
+    }
+
+    /// Gets this error's message.
+    pub fn message(&self) -> &str {
+        match self {
+            HearthError::NotFound(message)
+            | HearthError::InvalidHandle(message)
+            | HearthError::PermissionDenied(message)
+            | HearthError::InvalidArgument(message)
+            | HearthError::Unknown(_, message) => message,
+        }
+    }
+
+    /// Creates an error from its code and a message.
+    pub fn from_code(code: i32, message: impl Into<String>) -> Self {
+        let message = message.into();
+        match code {
+            Self::NOT_FOUND => HearthError::NotFound(message),
+            Self::INVALID_HANDLE => HearthError::InvalidHandle(message),
+            Self::PERMISSION_DENIED => HearthError::PermissionDenied(message),
+            Self::INVALID_ARGUMENT => HearthError::InvalidArgument(message),
+            code => HearthError::Unknown(code, message),
+        }
+    }
+
+    /// Encodes the result of a fallible ABI function as its return value.
+    ///
+    /// Successful values must fit in an `i32` without becoming negative.
+    /// Larger values are encoded as [HearthError::InvalidArgument].
+    pub fn encode(result: Result<u32, HearthError>) -> i32 {
+        match result.map(i32::try_from) {
+            Ok(Ok(value)) => value,
+            Ok(Err(_)) => -Self::INVALID_ARGUMENT,
+            Err(err) => -err.code(),
+        }
+    }
+
+    /// Decodes the return value of a fallible ABI function into its value or
+    /// the code of its error.
+    pub fn decode(ret: i32) -> Result<u32, i32> {
+        match ret {
+            ret if ret < 0 => Err(ret.saturating_neg()),
+            ret => Ok(ret as u32),
+        }
+    }
+}
+
+impl Display for HearthError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            HearthError::NotFound(message) => write!(fmt, "not found: {}", message),
+            HearthError::InvalidHandle(message) => write!(fmt, "invalid handle: {}", message),
+            HearthError::PermissionDenied(message) => {
+                write!(fmt, "permission denied: {}", message)
+            }
+            HearthError::InvalidArgument(message) => {
+                write!(fmt, "invalid argument: {}", message)
+            }
+            HearthError::Unknown(code, message) => {
+                write!(fmt, "error code {}: {}", code, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for HearthError {}
+
+/// Passes the definition of the `hearth::lump` ABI module to a callback
+/// macro. See the [module-level documentation](self) for the format.
+#[macro_export]
+macro_rules! lump_abi {
+    ($callback:ident $(, $($args:tt)*)?) => {
+        $callback! {
+            [$($($args)*)?]
+            module = "hearth::lump";
+
+            /// Writes the lump ID of the running process's WebAssembly
+            /// module to a guest-side lump ID.
+            fn this_lump(id_ptr: u32);
+
+            /// Loads a lump by the guest-side lump ID at `id_ptr` and returns
+            /// its handle.
+            ///
+            /// Fails with `HearthError::NotFound` if the lump isn't in the
+            /// lump store.
+            fn load_by_id(id_ptr: u32) -> i32;
+
+            /// Creates a new lump from guest memory and returns its handle.
+            fn load(data_ptr: u32, data_len: u32) -> u32;
+
+            /// Writes the lump ID of a loaded lump to a guest-side lump ID.
+            ///
+            /// Fails with `HearthError::InvalidHandle` if the handle isn't a
+            /// loaded lump, as do the rest of the functions taking handles.
+            fn get_id(handle: u32, id_ptr: u32) -> i32;
+
+            /// Gets the length of a loaded lump's data in bytes.
+            fn get_len(handle: u32) -> i32;
+
+            /// Copies the data of a loaded lump into guest memory.
+            fn get_data(handle: u32, data_ptr: u32) -> i32;
+
+            /// Unloads a lump by handle.
+            fn free(handle: u32) -> i32;
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_round_trip() {
+        assert_eq!(HearthError::decode(HearthError::encode(Ok(0))), Ok(0));
+        assert_eq!(HearthError::decode(HearthError::encode(Ok(42))), Ok(42));
+
+        let err = HearthError::NotFound("lump".into());
+        let ret = HearthError::encode(Err(err));
+        assert_eq!(ret, -HearthError::NOT_FOUND);
+        assert_eq!(HearthError::decode(ret), Err(HearthError::NOT_FOUND));
+    }
+
+    #[test]
+    fn oversized_values_are_errors() {
+        let ret = HearthError::encode(Ok(u32::MAX));
+        assert_eq!(HearthError::decode(ret), Err(HearthError::INVALID_ARGUMENT));
+    }
+
+    #[test]
+    fn codes_are_stable() {
+        for code in 1..=4 {
+            assert_eq!(HearthError::from_code(code, "").code(), code);
+        }
+
+        let unknown = HearthError::from_code(100, "from the future");
+        assert_eq!(unknown, HearthError::Unknown(100, "from the future".into()));
+        assert_eq!(unknown.message(), "from the future");
+    }
+}
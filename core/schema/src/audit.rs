@@ -0,0 +1,198 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+use crate::LumpId;
+
+/// The name of the audit service, if it's registered publicly.
+///
+/// By default, a capability to the audit service is only given to the init
+/// system.
+pub const AUDIT_SERVICE: &str = "hearth.Audit";
+
+/// Who caused an audited event.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AuditActor {
+    /// The host itself, such as when starting built-in services.
+    Host,
+
+    /// A local process.
+    ///
+    /// Capabilities don't identify their senders, so requests to shared
+    /// services are attributed to the process that served them. Guests spawn
+    /// through their own spawner (see `hearth::group`'s `spawner`) so that
+    /// their spawns are attributed to them.
+    Process {
+        /// The process's PID.
+        pid: u64,
+
+        /// The process's name, if it has one.
+        name: Option<String>,
+    },
+
+    /// A user connected over the network.
+    Peer {
+        /// The user that the peer authenticated as.
+        user: String,
+
+        /// The peer's network address.
+        address: String,
+    },
+}
+
+/// What happened in an [AuditEvent].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AuditEventKind {
+    /// A process was spawned.
+    Spawn {
+        /// The PID of the new process.
+        pid: u64,
+
+        /// The name of the new process, if it has one.
+        name: Option<String>,
+
+        /// Who requested the spawn.
+        requester: AuditActor,
+
+        /// The lump that the process was spawned from, for guest processes.
+        lump: Option<LumpId>,
+    },
+
+    /// A process has exited, either by finishing or being killed.
+    Exit {
+        /// The PID of the exited process.
+        pid: u64,
+    },
+
+    /// A process was killed.
+    Kill {
+        /// Who killed the process.
+        requester: AuditActor,
+
+        /// The PID of the killed process, if it's known.
+        ///
+        /// Capabilities don't identify the process behind them, so kills
+        /// through one leave this unset. The killed process is then
+        /// identified by its following [AuditEventKind::Exit].
+        target: Option<u64>,
+    },
+
+    /// A service was registered in one of the host's registries.
+    Register {
+        /// The full name of the service.
+        name: String,
+
+        /// The PID of the service's process, if it's known.
+        pid: Option<u64>,
+
+        /// Who registered the service.
+        requester: AuditActor,
+    },
+}
+
+impl AuditEventKind {
+    /// Gets who caused this event, if it's known.
+    pub fn requester(&self) -> Option<&AuditActor> {
+        match self {
+            AuditEventKind::Spawn { requester, .. } => Some(requester),
+            AuditEventKind::Exit { .. } => None,
+            AuditEventKind::Kill { requester, .. } => Some(requester),
+            AuditEventKind::Register { requester, .. } => Some(requester),
+        }
+    }
+}
+
+/// A single entry in the audit log.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AuditEvent {
+    /// A sequence number, counting up from 0 for each event.
+    pub id: u64,
+
+    /// When the event happened, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+
+    /// What happened.
+    pub kind: AuditEventKind,
+}
+
+/// Selects audit events. Unset fields match every event.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AuditFilter {
+    /// Only match events caused by this user.
+    pub peer: Option<String>,
+
+    /// Only match events at or after this timestamp.
+    pub since: Option<u64>,
+
+    /// Only match events before this timestamp.
+    pub until: Option<u64>,
+}
+
+impl AuditFilter {
+    /// Tests if an event matches this filter.
+    pub fn matches(&self, event: &AuditEvent) -> bool {
+        if let Some(peer) = self.peer.as_ref() {
+            match event.kind.requester() {
+                Some(AuditActor::Peer { user, .. }) if user == peer => {}
+                _ => return false,
+            }
+        }
+
+        let after_since = self.since.map_or(true, |since| event.timestamp >= since);
+        let before_until = self.until.map_or(true, |until| event.timestamp < until);
+        after_since && before_until
+    }
+}
+
+/// A request to the audit service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AuditRequest {
+    /// Sends each new matching [AuditEvent] to the first capability argument
+    /// until it goes down.
+    Subscribe(AuditFilter),
+
+    /// Gets the recent events that match a filter, oldest first.
+    ///
+    /// Only a bounded number of recent events are kept in memory.
+    Query {
+        filter: AuditFilter,
+
+        /// The maximum number of events to return. The newest events are
+        /// returned if there are more.
+        limit: Option<u32>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AuditSuccess {
+    /// The subscription was added.
+    Subscribed,
+
+    /// The events matching a [AuditRequest::Query].
+    Events(Vec<AuditEvent>),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum AuditError {
+    /// The request was missing a required capability argument.
+    MissingCapability,
+}
+
+pub type AuditResponse = Result<AuditSuccess, AuditError>;
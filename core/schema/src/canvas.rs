@@ -18,7 +18,7 @@
 
 use glam::{Quat, Vec2, Vec3};
 use serde::{Deserialize, Serialize};
-use serde_with::{base64::Base64, serde_as};
+use serde_with::{base64::Base64, serde_as, Bytes, IfIsHumanReadable};
 
 /// A rectangular buffer of pixel data.
 #[serde_as]
@@ -30,18 +30,61 @@ pub struct Pixels {
     /// The height of the buffer, in pixels.
     pub height: u32,
 
-    /// The RGBA color data of the buffer.
+    /// The color data of the buffer, encoded in the canvas's [PixelFormat].
     ///
-    /// `width * height * 4` should match the length of `data`. Missing pixel
-    /// data will be initialized with `0xff` for all components. Excess data
-    /// is ignored.
-    #[serde_as(as = "Base64")]
+    /// `width * height * bytes_per_pixel` should match the length of `data`.
+    /// Missing pixel data will be initialized with `0xff` for all components.
+    /// Excess data is ignored.
+    ///
+    /// Encoded as base64 in JSON and as raw bytes in binary payloads.
+    #[serde_as(as = "IfIsHumanReadable<Base64, Bytes>")]
     pub data: Vec<u8>,
 }
 
+/// The encoding of the pixel data sent to a canvas.
+///
+/// Canvases always store RGBA pixels on the GPU. Other formats are expanded
+/// by the host, so guests can pick the most compact format for their content.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PixelFormat {
+    /// Four bytes per pixel, in red, green, blue, alpha order.
+    #[default]
+    Rgba8,
+
+    /// Three bytes per pixel, in red, green, blue order. Pixels are opaque.
+    Rgb8,
+
+    /// Four bytes per pixel, in blue, green, red, alpha order.
+    Bgra8,
+
+    /// One byte per pixel, indexing into an RGBA palette.
+    ///
+    /// The palette may have at most 256 entries. Indices past the end of the
+    /// palette are expanded to `0xff` for all components.
+    Paletted {
+        /// The RGBA colors of this palette.
+        palette: Vec<[u8; 4]>,
+    },
+}
+
+impl PixelFormat {
+    /// The maximum number of entries in a [PixelFormat::Paletted] palette.
+    pub const MAX_PALETTE_LEN: usize = 256;
+
+    /// Returns the number of bytes each pixel occupies in this format.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgba8 | PixelFormat::Bgra8 => 4,
+            PixelFormat::Rgb8 => 3,
+            PixelFormat::Paletted { .. } => 1,
+        }
+    }
+}
+
 /// A rectangular update to a target region of a canvas's pixel buffer.
 ///
-/// Out-of-bounds regions of blits are discarded.
+/// The blit's rectangle must lie entirely within the canvas. Out-of-bounds
+/// blits are rejected with [CanvasError::OutOfBounds].
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Blit {
     /// The X coordinate of this blit's origin in pixels.
@@ -89,6 +132,19 @@ pub enum CanvasUpdate {
     Blit(Blit),
 }
 
+/// An error from a [CanvasUpdate].
+///
+/// Canvas updates are not replied to unless they are sent with a capability.
+/// If they are, the first capability receives a [CanvasResponse].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CanvasError {
+    /// A blit's rectangle did not fit within the canvas.
+    OutOfBounds,
+}
+
+/// A type shorthand for the result of a [CanvasUpdate].
+pub type CanvasResponse = Result<(), CanvasError>;
+
 /// Configures the method of texture sampling to use for a canvas.
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
 pub enum CanvasSamplingMode {
@@ -115,6 +171,11 @@ pub enum FactoryRequest {
 
         /// The sampling method to use.
         sampling: CanvasSamplingMode,
+
+        /// The format of all pixel data sent to this canvas, including
+        /// `pixels`.
+        #[serde(default)]
+        format: PixelFormat,
     },
 }
 
@@ -130,6 +191,10 @@ pub enum FactorySuccess {
 pub enum FactoryError {
     /// The request has failed to parse.
     ParseError,
+
+    /// The requested [PixelFormat] is not supported, e.g. because its
+    /// palette is empty or too long.
+    UnsupportedFormat,
 }
 
 /// A type shorthand for [FactorySuccess] and [FactoryError].
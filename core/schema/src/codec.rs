@@ -0,0 +1,133 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+/// The first byte of a binary-encoded message payload.
+///
+/// Binary payloads are this tag followed by the value encoded with
+/// [bincode]. Valid JSON can never begin with a NUL byte, so payloads without
+/// the tag are decoded as JSON. This lets services accept both formats.
+pub const BINARY_TAG: u8 = 0x00;
+
+/// The encoding of a message payload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PayloadFormat {
+    /// Untagged JSON.
+    Json,
+
+    /// [BINARY_TAG] followed by bincode.
+    Binary,
+}
+
+impl PayloadFormat {
+    /// Detects the format of a payload from its first byte.
+    pub fn detect(data: &[u8]) -> Self {
+        match data.first() {
+            Some(&BINARY_TAG) => PayloadFormat::Binary,
+            _ => PayloadFormat::Json,
+        }
+    }
+}
+
+/// An error from [decode].
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The payload was untagged but isn't valid JSON.
+    Json(serde_json::Error),
+
+    /// The payload was tagged as binary but couldn't be decoded.
+    Binary(bincode::Error),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            DecodeError::Json(err) => write!(f, "invalid JSON payload: {}", err),
+            DecodeError::Binary(err) => write!(f, "invalid binary payload: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Encodes a value as a tagged binary payload.
+pub fn encode_binary<T: Serialize>(value: &T) -> Vec<u8> {
+    let mut data = vec![BINARY_TAG];
+    bincode::serialize_into(&mut data, value).expect("failed to encode binary payload");
+    data
+}
+
+/// Encodes a value as a JSON payload.
+pub fn encode_json<T: Serialize>(value: &T) -> Vec<u8> {
+    serde_json::to_vec(value).expect("failed to encode JSON payload")
+}
+
+/// Decodes a payload in either format.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T, DecodeError> {
+    match PayloadFormat::detect(data) {
+        PayloadFormat::Json => serde_json::from_slice(data).map_err(DecodeError::Json),
+        PayloadFormat::Binary => bincode::deserialize(&data[1..]).map_err(DecodeError::Binary),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::canvas::{CanvasUpdate, Pixels};
+
+    #[test]
+    fn decodes_both_formats() {
+        let value = vec![1u32, 2, 3];
+        assert_eq!(decode::<Vec<u32>>(&encode_json(&value)).unwrap(), value);
+        assert_eq!(decode::<Vec<u32>>(&encode_binary(&value)).unwrap(), value);
+        assert!(decode::<Vec<u32>>(&[BINARY_TAG, 0xff]).is_err());
+    }
+
+    #[test]
+    fn pixel_update_size() {
+        let side = 512;
+        let pixels = Pixels {
+            width: side,
+            height: side,
+            data: (0..side * side * 4).map(|i| i as u8).collect(),
+        };
+
+        let raw = pixels.data.len();
+        let update = CanvasUpdate::Resize(pixels.clone());
+
+        let json = encode_json(&update);
+        let _: CanvasUpdate = decode(&json).unwrap();
+
+        let binary = encode_binary(&update);
+        let decoded: CanvasUpdate = decode(&binary).unwrap();
+
+        let CanvasUpdate::Resize(decoded) = decoded else {
+            panic!("unexpected {:?}", decoded);
+        };
+
+        assert_eq!(decoded.data, pixels.data);
+        assert!(binary.len() >= raw, "binary must carry every pixel");
+        assert!(binary.len() < raw + 64, "binary overhead too large");
+        assert!(json.len() > raw * 4 / 3, "JSON should be base64");
+        assert!(json.len() > binary.len(), "binary should beat JSON");
+    }
+}
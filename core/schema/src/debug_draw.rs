@@ -16,7 +16,7 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use glam::Vec3;
+use glam::{Mat4, Vec3};
 use serde::{Deserialize, Serialize};
 
 use crate::Color;
@@ -36,12 +36,53 @@ pub struct DebugDrawMesh {
     pub indices: Vec<u32>,
 }
 
+/// A higher-level debug draw shape, expanded into lines by the host.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum DebugDrawShape {
+    /// A connected series of line segments through each point in order.
+    LineStrip { points: Vec<Vec3>, color: Color },
+
+    /// The edges of an axis-aligned box.
+    WireBox { min: Vec3, max: Vec3, color: Color },
+
+    /// Three great circles around a sphere, one on each axis plane.
+    WireSphere {
+        center: Vec3,
+        radius: f32,
+
+        /// The number of line segments in each circle.
+        segments: u32,
+
+        color: Color,
+    },
+
+    /// Red, green, and blue lines along the X, Y, and Z axes of a transform.
+    Axes {
+        transform: Mat4,
+
+        /// The length of each axis line.
+        size: f32,
+    },
+}
+
 /// An update to a debug draw mesh.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum DebugDrawUpdate {
-    /// Updates the contents of this debug draw mesh.
+    /// Replaces the contents of this debug draw mesh, including any added
+    /// shapes.
     Contents(DebugDrawMesh),
 
+    /// Adds a shape to the contents of this debug draw mesh.
+    Add {
+        shape: DebugDrawShape,
+
+        /// If set, the number of seconds after which the shape is removed.
+        ttl: Option<f32>,
+    },
+
+    /// Removes all contents of this debug draw mesh.
+    Clear,
+
     /// Sets whether to hide this mesh.
     Hide(bool),
 
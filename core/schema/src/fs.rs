@@ -20,6 +20,9 @@ use serde::{Deserialize, Serialize};
 
 use crate::LumpId;
 
+/// The name of the native filesystem service.
+pub const FILESYSTEM_SERVICE: &str = "hearth.fs.Filesystem";
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum Error {
     NotFound,
@@ -29,6 +32,16 @@ pub enum Error {
     DirectoryTraversal,
     InvalidTarget,
     InvalidRequest,
+
+    /// The capability used for the request only has read access.
+    ReadOnly,
+
+    /// The target of a create or rename already exists.
+    AlreadyExists,
+
+    /// The lump to write was not found.
+    LumpNotFound,
+
     Other(String),
 }
 
@@ -36,6 +49,38 @@ pub enum Error {
 pub enum RequestKind {
     Get,
     List,
+
+    /// Replaces the contents of a file with a lump, creating it if it doesn't
+    /// exist. Files are never left partially written.
+    Write(LumpId),
+
+    /// Creates a directory and any missing parent directories.
+    CreateDir,
+
+    /// Deletes a file or an empty directory.
+    Delete,
+
+    /// Moves a file or directory to a new path.
+    Rename {
+        /// The destination path, relative to the filesystem root.
+        to: String,
+    },
+
+    /// Creates a new read-only capability to this filesystem.
+    ///
+    /// The target is ignored. The new capability is returned as the first
+    /// capability of the response.
+    ReadOnly,
+
+    /// Watches a file or directory for changes.
+    ///
+    /// The first capability argument receives a [WatchEvent] message for
+    /// each change until it goes down. Rapid successive changes to the same
+    /// path are coalesced into one event.
+    Watch {
+        /// Whether to watch the contents of subdirectories too.
+        recursive: bool,
+    },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -54,6 +99,32 @@ pub struct FileInfo {
 pub enum Success {
     Get(LumpId),
     List(Vec<FileInfo>),
+
+    /// A write, create, delete, or rename succeeded.
+    Done,
+
+    /// A read-only capability was created.
+    ReadOnly,
+
+    /// A watch was started.
+    Watching,
+}
+
+/// The kind of a filesystem change.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum WatchEventKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change to a watched path, sent to watch subscribers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct WatchEvent {
+    pub kind: WatchEventKind,
+
+    /// The changed path, relative to the filesystem root.
+    pub path: String,
 }
 
 pub type Response = Result<Success, Error>;
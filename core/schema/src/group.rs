@@ -0,0 +1,134 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+use crate::wasm::WasmSpawnInfo;
+
+/// The name of the process groups service, if it's enabled.
+///
+/// The service can kill any group on the host, so it's only registered when
+/// the host's config enables it.
+pub const PROCESS_GROUPS_SERVICE: &str = "hearth.ProcessGroups";
+
+/// The identifier of a process group on a host.
+pub type GroupId = u64;
+
+/// A running process in a group.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GroupMember {
+    /// The PID of the process.
+    pub pid: u64,
+
+    /// The name of the process, if it has one.
+    pub name: Option<String>,
+}
+
+/// A request to a process group's capability.
+///
+/// Processes spawned into a group are members of it, and so are the
+/// processes that members spawn, unless they're spawned into another group.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum GroupRequest {
+    /// Spawns a Wasm process into this group. The capability arguments are
+    /// passed to the new process as its initial capabilities.
+    ///
+    /// On success, the first returned capability is to the new process and
+    /// the second is to its link endpoint, as with the Wasm spawner.
+    Spawn(WasmSpawnInfo),
+
+    /// Lists the group's running members, oldest first.
+    List,
+
+    /// Kills every member of the group, newest first, so that children are
+    /// killed before the processes that spawned them.
+    KillAll,
+
+    /// Gets the group's private registry as the first returned capability.
+    ///
+    /// Services registered through it are only visible to other users of
+    /// the group's registry. Other names are looked up in the host's
+    /// registry.
+    Registry,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum GroupSuccess {
+    /// The process was spawned into the group.
+    Spawned,
+
+    /// The group's running members.
+    Members(Vec<GroupMember>),
+
+    /// The number of members that were killed.
+    Killed(usize),
+
+    /// The group's registry was returned.
+    Registry,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum GroupError {
+    /// The process couldn't be spawned, with a description of the error.
+    SpawnFailed(String),
+
+    /// The group has been torn down, so nothing may join it anymore.
+    TornDown,
+}
+
+pub type GroupResponse = Result<GroupSuccess, GroupError>;
+
+/// A description of a process group.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct GroupInfo {
+    /// The group's ID.
+    pub id: GroupId,
+
+    /// The group that this group was created in, if any.
+    pub parent: Option<GroupId>,
+
+    /// The group's running members, oldest first.
+    pub members: Vec<GroupMember>,
+}
+
+/// A request to the process groups service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ProcessGroupsRequest {
+    /// Lists every live process group.
+    List,
+
+    /// Tears down a group by ID, killing all of its members.
+    Kill { id: GroupId },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ProcessGroupsSuccess {
+    /// Every live process group.
+    Groups(Vec<GroupInfo>),
+
+    /// The group was torn down, killing this many members.
+    Killed(usize),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ProcessGroupsError {
+    /// No live group has the given ID.
+    NotFound,
+}
+
+pub type ProcessGroupsResponse = Result<ProcessGroupsSuccess, ProcessGroupsError>;
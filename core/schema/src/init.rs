@@ -0,0 +1,114 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::PeerRole;
+
+/// The init system's configuration, loaded from the `init` table of the
+/// config file and sent to the init process as its first message.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct InitConfig {
+    /// The directory to find services in. Defaults to `init`.
+    pub path: Option<String>,
+
+    /// The services to start, by name.
+    ///
+    /// If unset, every service found in the init directory is started.
+    pub services: Option<HashMap<String, InitServiceConfig>>,
+
+    /// Paths of Wasm modules to compile ahead of time, before any services
+    /// are started, so that spawning them later is faster.
+    pub warm_up: Vec<String>,
+
+    /// The role of the runtime that init is running in. Services whose
+    /// `targets` don't include this role aren't started.
+    ///
+    /// This is always set by the host from the runtime's config.
+    pub role: Option<PeerRole>,
+}
+
+/// The configuration of a single service started by the init system.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(default)]
+pub struct InitServiceConfig {
+    /// If false, the service isn't started.
+    pub enabled: bool,
+
+    /// Arguments sent to the service in a [ServiceArgs] message.
+    ///
+    /// If these and the `config` table of the service's `service.toml` are
+    /// both tables, they're merged, with these arguments taking precedence.
+    pub args: serde_json::Value,
+
+    /// Environment variables sent to the service in a [ServiceArgs] message.
+    pub env: HashMap<String, String>,
+}
+
+impl Default for InitServiceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            args: serde_json::Value::Null,
+            env: HashMap::new(),
+        }
+    }
+}
+
+/// The first message the init system sends to each service that it starts,
+/// if the service's `service.toml` says that it expects one.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct ServiceArgs {
+    /// The service's arguments. See [InitServiceConfig::args].
+    pub args: serde_json::Value,
+
+    /// The service's environment variables. See [InitServiceConfig::env].
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn service_defaults() {
+        let config: InitConfig = serde_json::from_str(
+            r#"{ "services": { "terminal": {}, "daemon": { "enabled": false } } }"#,
+        )
+        .unwrap();
+
+        let services = config.services.unwrap();
+        assert_eq!(services["terminal"], InitServiceConfig::default());
+        assert!(!services["daemon"].enabled);
+        assert!(services["terminal"].env.is_empty());
+        assert_eq!(config.path, None);
+        assert!(config.warm_up.is_empty());
+        assert_eq!(config.role, None);
+    }
+
+    #[test]
+    fn role_names() {
+        let config: InitConfig = serde_json::from_str(r#"{ "role": "client" }"#).unwrap();
+        assert_eq!(config.role, Some(PeerRole::Client));
+        assert_eq!(PeerRole::Server.as_str(), "server");
+    }
+}
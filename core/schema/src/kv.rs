@@ -0,0 +1,104 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the key-value store service.
+pub const KV_STORE_SERVICE: &str = "hearth.KvStore";
+
+/// A request to the [KV_STORE_SERVICE].
+///
+/// Namespaces are identified by unguessable keys that the store picks, so
+/// that guests can only reach namespaces that they created or were given the
+/// key to. On success, the response has the namespace's key and a capability
+/// to the namespace as the first capability. Send [KvRequest] to it.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum KvFactoryRequest {
+    /// Creates a new, empty namespace.
+    ///
+    /// Anyone with the returned key can open the namespace again, so keep it
+    /// somewhere private, such as the service's own config.
+    CreateNamespace,
+
+    /// Opens an existing namespace by its key.
+    OpenNamespace { key: String },
+}
+
+/// A response from the [KV_STORE_SERVICE] with a namespace's key.
+pub type KvFactoryResponse = Result<String, KvError>;
+
+/// A request to a key-value namespace.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum KvRequest {
+    /// Gets the value of a key. Responds with [KvSuccess::Value].
+    Get { key: Vec<u8> },
+
+    /// Sets the value of a key. Responds with [KvSuccess::Done].
+    Set { key: Vec<u8>, value: Vec<u8> },
+
+    /// Deletes a key. Responds with [KvSuccess::Done], even if the key
+    /// didn't exist.
+    Delete { key: Vec<u8> },
+
+    /// Lists all keys beginning with a prefix in ascending order. Responds
+    /// with [KvSuccess::Keys].
+    List { prefix: Vec<u8> },
+
+    /// Atomically replaces a key's value if its current value matches
+    /// `expected`. `None` stands for a missing key in both fields, so this
+    /// can also create or delete keys.
+    ///
+    /// Responds with [KvSuccess::Done] or [KvError::CompareFailed].
+    CompareAndSwap {
+        key: Vec<u8>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    },
+}
+
+/// A successful response to a [KvRequest].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum KvSuccess {
+    /// The value of a key, or `None` if it doesn't exist.
+    Value(Option<Vec<u8>>),
+
+    /// The operation was completed.
+    Done,
+
+    /// A list of keys.
+    Keys(Vec<Vec<u8>>),
+}
+
+/// An error in a key-value store operation.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum KvError {
+    /// No namespace has the given key.
+    UnknownNamespace,
+
+    /// The value is larger than the store's configured maximum.
+    ValueTooLarge { size: usize, max: usize },
+
+    /// A compare-and-swap failed. Contains the key's current value.
+    CompareFailed { current: Option<Vec<u8>> },
+
+    /// The store failed to persist the change.
+    StorageError(String),
+}
+
+/// A response to a [KvRequest].
+pub type KvResponse = Result<KvSuccess, KvError>;
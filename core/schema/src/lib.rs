@@ -24,27 +24,66 @@ use std::{
 use bytemuck::{Pod, Zeroable};
 use serde::{Deserialize, Serialize};
 
+/// Wasm ABI module definitions shared by the host and guests.
+pub mod abi;
+
+/// Process spawn auditing protocol.
+pub mod audit;
+
 /// Canvas protocol.
 pub mod canvas;
 
+/// Message payload encodings.
+pub mod codec;
+
 /// Debug draw protocol
 pub mod debug_draw;
 
 /// Filesystem native service protocol.
 pub mod fs;
 
+/// Process group protocol.
+pub mod group;
+
+/// Init system configuration and service arguments.
+pub mod init;
+
+/// Key-value store protocol.
+pub mod kv;
+
+/// Lump metadata and verification.
+pub mod lump;
+
+/// Client network connection protocol.
+pub mod network;
+
+/// Process inspection protocol.
+pub mod process;
+
 /// Network/IPC protocol definitions.
 pub mod protocol;
 
+/// Publish-subscribe topic protocol.
+pub mod pubsub;
+
 /// Registry protocol.
 pub mod registry;
 
 /// Renderer protocol.
 pub mod renderer;
 
+/// Graceful process shutdown protocol.
+pub mod shutdown;
+
+/// Message recording protocol.
+pub mod tap;
+
 /// Terminal protocol.
 pub mod terminal;
 
+/// Timer and scheduled delivery protocol.
+pub mod timer;
+
 /// WebAssembly process protocols and utilities.
 pub mod wasm;
 
@@ -78,6 +117,30 @@ bitflags::bitflags! {
     }
 }
 
+/// Which side of a client-server connection a runtime is.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerRole {
+    Client,
+    Server,
+}
+
+impl PeerRole {
+    /// The lowercase name of this role, as used in service targets.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PeerRole::Client => "client",
+            PeerRole::Server => "server",
+        }
+    }
+}
+
+impl Display for PeerRole {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.write_str(self.as_str())
+    }
+}
+
 /// The severity level for a log message emitted by a process.
 #[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
 pub enum ProcessLogLevel {
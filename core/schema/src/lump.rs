@@ -0,0 +1,105 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use serde::{Deserialize, Serialize};
+
+use crate::LumpId;
+
+/// The name of the service that describes the lumps in the local lump store.
+pub const LUMP_INFO_SERVICE: &str = "hearth.LumpInfo";
+
+/// Where a lump was created.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LumpOrigin {
+    /// The host created the lump itself, such as a screenshot.
+    Host,
+
+    /// A guest process created the lump.
+    Guest,
+
+    /// The lump was uploaded through the lump uploader.
+    Upload,
+
+    /// The lump was fetched from a remote peer.
+    Remote,
+
+    /// The lump was read from the host's filesystem.
+    Filesystem,
+}
+
+/// Optional metadata stored alongside a lump.
+///
+/// Metadata isn't part of a lump's ID. If the same data is added more than
+/// once, fields that weren't set yet are filled in by later additions, and
+/// metadata supplied by the host replaces metadata from guests, uploads, and
+/// remote peers.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LumpMetadata {
+    /// The MIME type of the lump's data, such as `application/wasm`.
+    pub content_type: Option<String>,
+
+    /// Where the lump was created.
+    pub origin: Option<LumpOrigin>,
+}
+
+/// A description of a stored lump.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LumpInfo {
+    /// The size of the lump's data in bytes.
+    pub size: u64,
+
+    /// The lump's metadata.
+    pub metadata: LumpMetadata,
+}
+
+/// Lump data that doesn't hash to the ID that it was expected to have.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct LumpMismatch {
+    /// The ID that the data was expected to have.
+    pub expected: LumpId,
+
+    /// The ID of the data that was actually received.
+    pub actual: LumpId,
+}
+
+impl Display for LumpMismatch {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "expected lump {} but received {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for LumpMismatch {}
+
+/// A request to the lump info service.
+///
+/// The first capability is the reply address. The service replies with a
+/// [LumpInfoResponse].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LumpInfoRequest {
+    /// The lump to describe.
+    pub lump: LumpId,
+}
+
+/// A response to a [LumpInfoRequest]. `None` if the lump isn't stored.
+pub type LumpInfoResponse = Option<LumpInfo>;
@@ -0,0 +1,160 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the service that reports the client's connection status.
+pub const CONNECTION_STATUS_SERVICE: &str = "hearth.network.ConnectionStatus";
+
+/// The status of a client's connection to a server.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ConnectionStatus {
+    /// The client is attempting to connect to the server.
+    Connecting,
+
+    /// The client is connected to the server.
+    Online,
+
+    /// The client has lost its connection to the server and is not
+    /// connected.
+    Offline,
+}
+
+/// A message sent to the connection status service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ConnectionStatusRequest {
+    /// Subscribes to all [ConnectionStatus] changes using the first attached
+    /// capability. The current status is sent immediately upon subscription.
+    ///
+    /// If the capability has the monitor permission, it will be automatically
+    /// unsubscribed when down.
+    Subscribe,
+
+    /// Unsubscribes from connection status changes using the first attached
+    /// capability.
+    Unsubscribe,
+}
+
+/// The name of the service that lists the identities connected to a server.
+pub const IDENTITIES_SERVICE: &str = "hearth.network.Identities";
+
+/// A request to the identities service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum IdentitiesRequest {
+    /// Lists all connected identities. Returns a [Vec] of
+    /// [ConnectedIdentity].
+    List,
+}
+
+/// An authenticated user connected to a server.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConnectedIdentity {
+    /// The name of the user. Empty for anonymous password logins.
+    pub user: String,
+
+    /// The network address of the user's connection.
+    pub address: String,
+}
+
+/// A request sent to a server's root cap provider for the root capability to
+/// export to a newly-authenticated user.
+///
+/// The first capability is the reply cap. The provider replies with a
+/// message containing the user's root capability as the first capability,
+/// or with no capabilities to refuse the connection.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RootCapRequest {
+    /// The name of the authenticated user.
+    pub user: String,
+}
+
+/// The name of the service that reports traffic counters for a host's
+/// network connections.
+pub const NETWORK_STATS_SERVICE: &str = "hearth.network.NetworkStats";
+
+/// A request to the network stats service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum NetworkStatsRequest {
+    /// Takes a snapshot of every open connection's counters. Returns a [Vec]
+    /// of [PeerTraffic].
+    List,
+}
+
+/// The traffic counters of a connection to a peer.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct PeerTraffic {
+    /// The network address or name of the peer.
+    pub peer: String,
+
+    /// The connection's counters.
+    pub traffic: ConnectionTraffic,
+}
+
+/// A snapshot of a connection's traffic counters.
+///
+/// Byte counts include framing and keepalive pings and are measured before
+/// encryption, so they're the number of bytes encrypted or decrypted for
+/// encrypted transports.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ConnectionTraffic {
+    /// The number of milliseconds since the connection was opened.
+    pub age_ms: u64,
+
+    /// The number of milliseconds since an operation was last sent or
+    /// received, or `None` if none have been.
+    pub idle_ms: Option<u64>,
+
+    /// The number of operations received.
+    pub ops_in: u64,
+
+    /// The number of operations sent.
+    pub ops_out: u64,
+
+    /// The number of bytes received.
+    pub bytes_in: u64,
+
+    /// The number of bytes sent.
+    pub bytes_out: u64,
+
+    /// The number of received operations waiting to be processed.
+    pub queue_in: u64,
+
+    /// The number of operations waiting to be sent.
+    pub queue_out: u64,
+
+    /// The capabilities that the most operations were received for, busiest
+    /// first.
+    pub busiest_in: Vec<CapTraffic>,
+
+    /// The capabilities that the most operations were sent for, busiest
+    /// first.
+    pub busiest_out: Vec<CapTraffic>,
+}
+
+/// The traffic for a single capability on a connection.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct CapTraffic {
+    /// The ID of the capability in the connection's protocol.
+    pub id: u32,
+
+    /// The number of operations for the capability.
+    pub ops: u64,
+
+    /// The number of bytes of operations for the capability.
+    pub bytes: u64,
+}
@@ -0,0 +1,133 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditActor;
+use crate::group::GroupId;
+use crate::lump::LumpInfo;
+use crate::{LumpId, ProcessLogLevel};
+
+/// The name of the process inspector service, if it's enabled.
+///
+/// The service describes any process on the host, so it's only registered
+/// when the host's config enables it.
+pub const PROCESS_INSPECTOR_SERVICE: &str = "hearth.ProcessInspector";
+
+/// The metadata that a process was spawned with.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProcessDescription {
+    /// A short, human-readable identifier for the process's function.
+    pub name: Option<String>,
+
+    /// The version of the process's software.
+    pub version: Option<String>,
+
+    /// Longer documentation of the process's function.
+    pub description: Option<String>,
+
+    /// The authors of the process.
+    pub authors: Option<Vec<String>>,
+
+    /// A link to the process's source repository.
+    pub repository: Option<String>,
+
+    /// A link to the home page of the process.
+    pub homepage: Option<String>,
+
+    /// An SPDX license identifier of the process's software license.
+    pub license: Option<String>,
+}
+
+/// A log event emitted by a process.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProcessLogEntry {
+    /// When the event was logged, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+
+    /// The level of the event.
+    pub level: ProcessLogLevel,
+
+    /// The context of the event's location, such as a script module.
+    pub module: String,
+
+    /// The message body of the event.
+    pub content: String,
+}
+
+/// Everything that the host knows about a running process.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProcessDetails {
+    /// The process's PID.
+    pub pid: u64,
+
+    /// The metadata that the process was spawned with.
+    pub metadata: ProcessDescription,
+
+    /// Who requested the process's spawn.
+    pub spawned_by: AuditActor,
+
+    /// The lump that the process runs, for guest processes.
+    pub lump: Option<LumpId>,
+
+    /// The description of [Self::lump], or `None` if it isn't stored.
+    pub lump_info: Option<LumpInfo>,
+
+    /// The group that the process is a member of, if any.
+    pub group: Option<GroupId>,
+
+    /// The names that the process is currently registered under as a
+    /// service.
+    pub services: Vec<String>,
+
+    /// The PIDs of the processes that this process links to or monitors, or
+    /// `None` if the host doesn't track them.
+    #[serde(default)]
+    pub monitoring: Option<Vec<u64>>,
+
+    /// How many messages are waiting in the process's parent mailbox, or
+    /// `None` if the host doesn't track it.
+    #[serde(default)]
+    pub mailbox_depth: Option<u64>,
+
+    /// Resource usage by metric name, such as `memory_bytes`, or `None` if
+    /// the host doesn't measure it.
+    #[serde(default)]
+    pub resources: Option<BTreeMap<String, u64>>,
+
+    /// The process's most recent log events, oldest first.
+    pub log_tail: Vec<ProcessLogEntry>,
+}
+
+/// A request to the process inspector service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ProcessInspectorRequest {
+    /// Gets the [ProcessDetails] of a running process, including up to
+    /// `log_tail` of its most recent log events.
+    GetProcessDetails { pid: u64, log_tail: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ProcessInspectorError {
+    /// No running process has the requested PID.
+    NotFound,
+}
+
+pub type ProcessInspectorResponse = Result<ProcessDetails, ProcessInspectorError>;
@@ -31,6 +31,9 @@ pub enum UnlinkReason {
 
     /// Access to the process has been revoked.
     AccessRevoked,
+
+    /// The connection that the process was accessed through was lost.
+    ConnectionLost,
 }
 
 /// Types of messages relating to low-level capability operations between two peers.
@@ -104,4 +107,19 @@ pub enum RemoteCapOperation {
         /// Ignored if invalid or revoked.
         id: u32,
     },
+
+    /// Asks a remote capability's process to shut down, then kills it if it
+    /// hasn't exited after a grace period.
+    ///
+    /// Ignored if the capability does not have [Permissions::KILL] set. See
+    /// [crate::shutdown] for the message the process receives.
+    Shutdown {
+        /// The remote capability to shut down.
+        ///
+        /// Ignored if invalid or revoked.
+        id: u32,
+
+        /// The grace period in milliseconds.
+        grace_ms: u64,
+    },
 }
@@ -0,0 +1,69 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the publish-subscribe topic factory service.
+pub const PUBSUB_SERVICE: &str = "hearth.PubSub";
+
+/// A request to the [PUBSUB_SERVICE].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum PubSubRequest {
+    /// Creates a new topic.
+    ///
+    /// The first capability argument is the topic's owner and must have the
+    /// monitor permission. When the owner goes down, the topic is destroyed.
+    ///
+    /// On success, a capability to the new topic is returned as the first
+    /// capability of the response. Send [TopicRequest] to it.
+    CreateTopic,
+}
+
+/// An error from the [PUBSUB_SERVICE].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum PubSubError {
+    /// No owner capability was given.
+    MissingOwner,
+
+    /// The owner capability lacks the monitor permission.
+    OwnerNotMonitorable,
+}
+
+/// A response from the [PUBSUB_SERVICE].
+pub type PubSubResponse = Result<(), PubSubError>;
+
+/// A message sent to a topic.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TopicRequest {
+    /// Sends `payload` to every subscriber as the raw data of a message.
+    ///
+    /// Messages from the same publisher are delivered in the order that they
+    /// were published. Each subscriber has a bounded queue of undelivered
+    /// messages; when it's full, the oldest message is dropped.
+    Publish { payload: Vec<u8> },
+
+    /// Subscribes the first capability to this topic. It must have the send
+    /// permission.
+    ///
+    /// If the capability also has the monitor permission, it will be
+    /// automatically unsubscribed when down.
+    Subscribe,
+
+    /// Unsubscribes the first capability from this topic.
+    Unsubscribe,
+}
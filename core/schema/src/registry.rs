@@ -34,6 +34,14 @@ pub enum RegistryRequest {
     /// Requests a list of all of the registered services. Returns
     /// [RegistryReponse::List].
     List,
+
+    /// Waits until a service with the given name is registered, then returns
+    /// it with [RegistryResponse::Watch]. If the service is already present,
+    /// the response is sent immediately.
+    ///
+    /// Registries that can never gain the service, such as immutable ones,
+    /// respond immediately with `present: false`.
+    Watch { name: String },
 }
 
 /// A response to a [RegistryRequest].
@@ -54,4 +62,76 @@ pub enum RegistryResponse {
 
     /// Returns a list of the names of all services in this registry.
     List(Vec<String>),
+
+    /// Responds to a [RegistryRequest::Watch]. The name of the watched
+    /// service is included so that several watches may share a reply
+    /// mailbox. If `present` is true, the service is the first capability.
+    Watch { name: String, present: bool },
+}
+
+/// Gets the namespace that a network peer's services are registered under,
+/// which is `peer.<user>.`.
+///
+/// Backslashes and dots in the user name are escaped with a backslash, so
+/// that no user's namespace is inside of another's.
+pub fn peer_namespace(user: &str) -> String {
+    let mut namespace = String::from("peer.");
+
+    for c in user.chars() {
+        if c == '\\' || c == '.' {
+            namespace.push('\\');
+        }
+
+        namespace.push(c);
+    }
+
+    namespace.push('.');
+    namespace
+}
+
+/// Splits a service name in a peer's namespace into the peer's user name and
+/// the name that the peer registered it under.
+///
+/// Returns `None` if the name isn't in a peer's namespace. See
+/// [peer_namespace].
+pub fn split_peer_name(name: &str) -> Option<(String, &str)> {
+    let rest = name.strip_prefix("peer.")?;
+    let mut user = String::new();
+    let mut chars = rest.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' => user.push(chars.next()?.1),
+            '.' => return Some((user, &rest[index + 1..])),
+            c => user.push(c),
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peer_names_round_trip() {
+        for user in ["alice", "a.b", "back\\slash", "trailing."] {
+            let name = format!("{}Game", peer_namespace(user));
+            assert_eq!(split_peer_name(&name), Some((user.to_string(), "Game")));
+        }
+    }
+
+    #[test]
+    fn peer_namespaces_do_not_nest() {
+        assert_eq!(peer_namespace("alice"), "peer.alice.");
+        assert_eq!(peer_namespace("a.b"), "peer.a\\.b.");
+        assert!(!peer_namespace("a.b").starts_with(&peer_namespace("a")));
+    }
+
+    #[test]
+    fn other_names_are_not_peer_names() {
+        assert_eq!(split_peer_name("hearth.fs.Filesystem"), None);
+        assert_eq!(split_peer_name("peer.unterminated"), None);
+    }
 }
@@ -66,6 +66,19 @@ pub enum RendererRequest {
     ///
     /// Returns [RendererSuccess::Ok] with no capabilities when successful.
     SetAmbientLighting { ambient: Vec4 },
+
+    /// Updates the number of MSAA samples per pixel. Either 1 (disabled) or
+    /// 4. Takes effect on the next frame.
+    ///
+    /// Returns [RendererSuccess::Ok] with no capabilities when successful.
+    SetSampleCount { samples: u8 },
+
+    /// Updates the scale of the scene's render resolution relative to the
+    /// window's size. Clamped to between 0.25 and 2.0. Takes effect on the
+    /// next frame.
+    ///
+    /// Returns [RendererSuccess::Ok] with no capabilities when successful.
+    SetResolutionScale { scale: f32 },
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -80,10 +93,251 @@ pub enum RendererSuccess {
 pub enum RendererError {
     /// A lump involved in this operation was improperly formatted or not found.
     LumpError,
+
+    /// The requested sample count is not supported.
+    UnsupportedSampleCount,
 }
 
 pub type RendererResponse = Result<RendererSuccess, RendererError>;
 
+/// The name of the screenshot service.
+pub const SCREENSHOT_SERVICE: &str = "hearth.Screenshot";
+
+/// A request to the screenshot service to capture what the renderer is
+/// drawing.
+///
+/// Returns a [ScreenshotResponse] with the ID of a lump containing the
+/// captured image as a PNG.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ScreenshotRequest {
+    /// The size of the screenshot. If `None`, the window's current size is
+    /// used.
+    pub resolution: Option<UVec2>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ScreenshotError {
+    /// The renderer failed to capture a frame, or hasn't drawn one yet.
+    CaptureFailed,
+
+    /// The captured frame could not be encoded.
+    EncodingFailed,
+}
+
+pub type ScreenshotResponse = Result<LumpId, ScreenshotError>;
+
+/// The name of the render statistics service.
+pub const RENDER_STATS_SERVICE: &str = "hearth.RenderStats";
+
+/// A request to the render statistics service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum RenderStatsRequest {
+    /// Gets the timings of the most recent frames, oldest first.
+    ///
+    /// Returns a [RenderStatsResponse].
+    GetFrameTimings,
+}
+
+pub type RenderStatsResponse = Vec<FrameTimings>;
+
+/// A breakdown of the time it took to render a single frame. All times are
+/// in milliseconds.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct FrameTimings {
+    /// CPU time spent preparing the renderer's pending updates.
+    pub ready: f32,
+
+    /// CPU time spent building routine nodes and the render graph.
+    pub build: f32,
+
+    /// CPU time spent executing the render graph and submitting it to the
+    /// GPU.
+    pub execute: f32,
+
+    /// Total CPU time spent on this frame.
+    pub cpu_total: f32,
+
+    /// GPU time spent in each major pass, in order. `None` if the GPU
+    /// doesn't support timestamp queries.
+    pub gpu_passes: Option<Vec<PassTiming>>,
+
+    /// The total number of frames that had been dropped when this frame was
+    /// drawn, because the renderer fell behind and a newer frame replaced
+    /// them.
+    #[serde(default)]
+    pub dropped_frames: u64,
+}
+
+impl FrameTimings {
+    /// Returns the total GPU time of this frame, if known.
+    pub fn gpu_total(&self) -> Option<f32> {
+        let passes = self.gpu_passes.as_ref()?;
+        Some(passes.iter().map(|pass| pass.time).sum())
+    }
+}
+
+/// The GPU time spent in a single render pass.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PassTiming {
+    /// The name of the pass.
+    pub name: String,
+
+    /// The time spent in the pass, in milliseconds.
+    pub time: f32,
+}
+
+/// The name of the renderer info service.
+pub const RENDERER_INFO_SERVICE: &str = "hearth.RendererInfo";
+
+/// A request to the renderer info service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum RendererInfoRequest {
+    /// Gets the current [RendererInfo].
+    ///
+    /// Returns [RendererInfoSuccess::Info].
+    GetInfo,
+
+    /// Sends the current [RendererInfo] to the first capability argument,
+    /// then sends it again every time it changes, until the capability goes
+    /// down.
+    ///
+    /// Returns [RendererInfoSuccess::Subscribed].
+    Subscribe,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum RendererInfoSuccess {
+    /// The renderer's current info.
+    Info(RendererInfo),
+
+    /// The subscription was added.
+    Subscribed,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum RendererInfoError {
+    /// The request was missing a required capability argument.
+    MissingCapability,
+}
+
+pub type RendererInfoResponse = Result<RendererInfoSuccess, RendererInfoError>;
+
+/// What the renderer is running on and the limits that content for it must
+/// respect.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RendererInfo {
+    /// The GPU adapter in use.
+    pub adapter: AdapterInfo,
+
+    /// Device limits relevant to guests.
+    pub limits: RendererLimits,
+
+    /// Optional device features relevant to guests.
+    pub features: RendererFeatures,
+
+    /// The name of the output surface's texture format, such as
+    /// `Bgra8UnormSrgb`.
+    pub surface_format: String,
+
+    /// The current multisampling sample count. May change at runtime.
+    pub sample_count: u32,
+
+    /// The current scale of the scene's render resolution relative to the
+    /// output resolution. May change at runtime.
+    pub resolution_scale: f32,
+}
+
+/// Identifies a GPU adapter.
+///
+/// The amount of video memory is not reported because the graphics APIs
+/// don't expose it portably.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct AdapterInfo {
+    /// The adapter's name, as reported by its driver.
+    pub name: String,
+
+    /// The PCI vendor ID of the adapter, or 0 if unknown.
+    pub vendor: u32,
+
+    /// The PCI device ID of the adapter, or 0 if unknown.
+    pub device: u32,
+
+    /// What kind of device the adapter is.
+    pub device_type: AdapterType,
+
+    /// The graphics API that the adapter is used through.
+    pub backend: GraphicsBackend,
+}
+
+/// The kind of device a GPU adapter is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum AdapterType {
+    /// A GPU integrated with the CPU, sharing its memory.
+    Integrated,
+
+    /// A dedicated GPU with its own memory.
+    Discrete,
+
+    /// A GPU in a virtual machine.
+    Virtual,
+
+    /// A software renderer running on the CPU.
+    Cpu,
+
+    /// Anything else.
+    Other,
+}
+
+/// A graphics API.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GraphicsBackend {
+    Vulkan,
+    Metal,
+    Dx12,
+    Dx11,
+    Gl,
+    WebGpu,
+    Other,
+}
+
+/// Device limits that affect what guests can send to the renderer.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RendererLimits {
+    /// The largest width and height of a 2D texture, in pixels.
+    pub max_texture_dimension_2d: u32,
+
+    /// The largest width, height, and depth of a 3D texture, in pixels.
+    pub max_texture_dimension_3d: u32,
+
+    /// The largest number of layers in a texture array.
+    pub max_texture_array_layers: u32,
+
+    /// The largest storage buffer that can be bound at once, in bytes.
+    pub max_storage_buffer_binding_size: u32,
+
+    /// The largest uniform buffer that can be bound at once, in bytes.
+    pub max_uniform_buffer_binding_size: u32,
+}
+
+/// Optional device features that affect what guests can rely on.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RendererFeatures {
+    /// GPU timestamp queries, which GPU frame timings depend on.
+    pub timestamp_query: bool,
+
+    /// BCn compressed textures.
+    pub texture_compression_bc: bool,
+
+    /// ETC2 compressed textures.
+    pub texture_compression_etc2: bool,
+
+    /// ASTC compressed textures.
+    pub texture_compression_astc: bool,
+
+    /// Drawing polygons as wireframes.
+    pub polygon_mode_line: bool,
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct DirectionalLightState {
     pub color: Vec3,
@@ -0,0 +1,84 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+/// The first byte of an encoded [ShutdownRequest].
+///
+/// This can't begin a JSON payload or a [crate::codec::BINARY_TAG] payload,
+/// so processes can check for shutdown requests before decoding a message
+/// with their usual protocol.
+pub const SHUTDOWN_TAG: u8 = 0x01;
+
+/// A request for a process to shut down gracefully.
+///
+/// This is sent to a process's parent mailbox along with a single
+/// capability: the process itself, with the kill permission. Requiring that
+/// capability means that only holders of the kill permission can request a
+/// shutdown, and processes should ignore requests without it.
+///
+/// The process should clean up and exit within the grace period. Once it
+/// passes, the requester may kill it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ShutdownRequest {
+    /// How long the process has to exit before it may be killed.
+    pub grace: Duration,
+}
+
+impl ShutdownRequest {
+    /// Encodes this request as [SHUTDOWN_TAG] followed by the grace period in
+    /// little-endian milliseconds.
+    pub fn encode(&self) -> Vec<u8> {
+        let millis = self.grace.as_millis().min(u64::MAX as u128) as u64;
+        let mut data = vec![SHUTDOWN_TAG];
+        data.extend_from_slice(&millis.to_le_bytes());
+        data
+    }
+
+    /// Decodes a message payload as a shutdown request. Returns `None` if the
+    /// payload is any other kind of message.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (tag, millis) = data.split_first()?;
+
+        if *tag != SHUTDOWN_TAG {
+            return None;
+        }
+
+        let millis = u64::from_le_bytes(millis.try_into().ok()?);
+
+        Some(Self {
+            grace: Duration::from_millis(millis),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let request = ShutdownRequest {
+            grace: Duration::from_millis(2500),
+        };
+
+        assert_eq!(ShutdownRequest::decode(&request.encode()), Some(request));
+        assert_eq!(ShutdownRequest::decode(b"{\"Shutdown\":null}"), None);
+        assert_eq!(ShutdownRequest::decode(&[SHUTDOWN_TAG, 1, 2]), None);
+    }
+}
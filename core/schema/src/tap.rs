@@ -0,0 +1,176 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{LumpId, Permissions};
+
+/// The name of the message tap service, if it's enabled.
+///
+/// The service writes files on the host, so it's only registered when the
+/// host's config enables it.
+pub const TAP_SERVICE: &str = "hearth.MessageTap";
+
+/// A capability in a recorded message, described instead of copied.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct TapCap {
+    /// A name for the capability that's stable within one recording.
+    ///
+    /// Names are derived from the capability's handle in the recorded
+    /// process's table, so the same capability always has the same name
+    /// while the process holds it.
+    pub name: String,
+
+    /// The capability's permissions.
+    pub permissions: Permissions,
+
+    /// If the capability was made by the recorded process from one of its
+    /// own mailboxes, that mailbox's handle.
+    pub mailbox: Option<u32>,
+}
+
+/// A single line of a message recording.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TapRecord {
+    /// The recording started. Always the first record.
+    Start {
+        /// The PID of the recorded process.
+        pid: u64,
+
+        /// The name of the recorded process, if it has one.
+        name: Option<String>,
+
+        /// When recording started, in milliseconds since the Unix epoch.
+        timestamp: u64,
+    },
+
+    /// How the recorded process was spawned, for Wasm processes.
+    Spawn {
+        /// The lump of the process's module.
+        lump: LumpId,
+
+        /// The process's initial capabilities, in order.
+        initial_caps: Vec<TapCap>,
+    },
+
+    /// A message was received by one of the process's mailboxes.
+    Inbound {
+        /// When the message was received, in milliseconds since the Unix
+        /// epoch.
+        timestamp: u64,
+
+        /// The handle of the receiving mailbox. The parent mailbox is 0.
+        mailbox: u32,
+
+        /// The message's data.
+        data: Vec<u8>,
+
+        /// The message's capabilities.
+        caps: Vec<TapCap>,
+    },
+
+    /// The process sent a message.
+    Outbound {
+        /// When the message was sent, in milliseconds since the Unix epoch.
+        timestamp: u64,
+
+        /// The capability that the message was sent to.
+        target: TapCap,
+
+        /// The message's data.
+        data: Vec<u8>,
+
+        /// The message's capabilities.
+        caps: Vec<TapCap>,
+    },
+
+    /// The recording stopped. Missing if the host exited mid-recording.
+    Stop {
+        /// When recording stopped, in milliseconds since the Unix epoch.
+        timestamp: u64,
+
+        /// A summary of the recording.
+        summary: TapSummary,
+    },
+}
+
+/// A summary of a finished recording.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TapSummary {
+    /// How many messages were written to the recording.
+    pub recorded: u64,
+
+    /// How many messages were lost because the recording buffer was full.
+    pub dropped: u64,
+
+    /// Whether the recording stopped early because it reached its limit.
+    pub truncated: bool,
+}
+
+/// A request to the message tap service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TapRequest {
+    /// Starts recording every message delivered to and sent by a process.
+    Start {
+        /// The PID of the process to record.
+        pid: u64,
+
+        /// The host path of the JSONL file to write the recording to.
+        ///
+        /// Must not already exist. Existing files are never overwritten.
+        path: PathBuf,
+
+        /// The maximum number of messages to record, overriding the host's
+        /// configured default.
+        limit: Option<u64>,
+    },
+
+    /// Stops recording a process and closes its recording file.
+    Stop {
+        /// The PID of the recorded process.
+        pid: u64,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TapSuccess {
+    /// The recording has started.
+    Started,
+
+    /// The recording has stopped.
+    Stopped(TapSummary),
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TapError {
+    /// No process with the given PID is running.
+    NotFound,
+
+    /// The process is already being recorded.
+    AlreadyRecording,
+
+    /// The process is not being recorded.
+    NotRecording,
+
+    /// The recording file couldn't be created.
+    Io(String),
+}
+
+pub type TapResponse = Result<TapSuccess, TapError>;
@@ -21,12 +21,29 @@ use std::collections::HashMap;
 use glam::{Quat, Vec2, Vec3};
 use serde::{Deserialize, Serialize};
 
-use crate::Color;
+use crate::{window::Ime, Color, LumpId};
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FactoryError {
     /// The request has failed to parse.
     ParseError,
+
+    /// A font for [FactoryRequest::SetFont] or [SpawnShell::fonts] could not
+    /// be loaded, with a description of the error.
+    FontError(String),
+
+    /// The command for [FactoryRequest::SpawnShell], or the default shell
+    /// for [FactoryRequest::CreateTerminal], isn't in the host's allowlist
+    /// of spawnable commands.
+    CommandNotAllowed(String),
+
+    /// An environment variable for [FactoryRequest::SpawnShell] isn't one
+    /// that guests may set.
+    EnvNotAllowed(String),
+
+    /// The terminal's process could not be spawned, with a description of
+    /// the error.
+    SpawnFailed(String),
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -34,28 +51,407 @@ pub struct TerminalState {
     pub position: Vec3,
     pub orientation: Quat,
     pub half_size: Vec2,
+
+    /// The opacity of the default background color, from 0.0 (transparent)
+    /// to 1.0 (opaque).
     pub opacity: f32,
+
     pub padding: Vec2,
     pub units_per_em: f32,
     pub colors: HashMap<usize, Color>,
 }
 
+/// A terminal color theme.
+///
+/// Unset colors fall back to the terminal's default theme.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TerminalPalette {
+    /// The default text color.
+    pub foreground: Option<Color>,
+
+    /// The default background color.
+    pub background: Option<Color>,
+
+    /// The color of the cursor.
+    pub cursor: Option<Color>,
+
+    /// The 16 ANSI colors: the 8 normal colors (black, red, green, yellow,
+    /// blue, magenta, cyan, white) followed by their bright variants.
+    pub ansi: [Option<Color>; 16],
+}
+
+/// An outline drawn around a terminal's glyphs.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TerminalOutline {
+    /// The color of the outline.
+    pub color: Color,
+
+    /// The thickness of the outline in ems.
+    ///
+    /// Outlines are limited by the distance range of the glyph atlas, so
+    /// very thick outlines are clamped.
+    pub width: f32,
+}
+
+/// Decorations drawn around a terminal to make it easier to identify and
+/// grab.
+///
+/// Every part of the chrome is disabled by default.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct TerminalChrome {
+    /// The text drawn in the title bar. Titles wider than the terminal are
+    /// cut short with an ellipsis.
+    pub title: String,
+
+    /// Whether to draw a title bar with [Self::title] along the terminal's
+    /// top edge.
+    pub title_bar: bool,
+
+    /// Whether to draw a border around the terminal and its title bar.
+    pub border: bool,
+
+    /// Whether to draw a drop shadow behind the terminal.
+    pub shadow: bool,
+
+    /// The thickness of the border in ems.
+    pub border_width: f32,
+
+    /// The color of the border and title bar while the terminal has focus.
+    pub focused_color: Color,
+
+    /// The color of the border and title bar while the terminal doesn't
+    /// have focus, or while its focus is unknown.
+    pub unfocused_color: Color,
+
+    /// The color of the title text.
+    pub title_color: Color,
+
+    /// The color of the drop shadow. Usually translucent.
+    pub shadow_color: Color,
+
+    /// How far the drop shadow is offset from the terminal, in ems, with +Y
+    /// up.
+    pub shadow_offset: Vec2,
+}
+
+impl Default for TerminalChrome {
+    fn default() -> Self {
+        Self {
+            title: String::new(),
+            title_bar: false,
+            border: false,
+            shadow: false,
+            border_width: 0.15,
+            focused_color: Color(0xff5f87d7),
+            unfocused_color: Color(0xff4e4e4e),
+            title_color: Color(0xffffffff),
+            shadow_color: Color(0x80000000),
+            shadow_offset: Vec2::new(0.4, -0.4),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum TerminalUpdate {
     Quit,
     Input(String),
     State(TerminalState),
+
+    /// Replaces this terminal's color palette.
+    SetPalette(TerminalPalette),
+
+    /// Replaces this terminal's color palette with a built-in theme by name.
+    ///
+    /// Unknown theme names are ignored.
+    SetTheme(String),
+
+    /// Sets or clears the outline drawn around this terminal's glyphs.
+    SetOutline(Option<TerminalOutline>),
+
+    /// Sets this terminal's opacities, each from 0.0 (transparent) to 1.0
+    /// (opaque).
+    ///
+    /// `background` replaces [TerminalState::opacity] and applies to cells
+    /// with the default background color and the padding around them.
+    /// `glyphs` applies to text and its decorations.
+    SetOpacity {
+        background: f32,
+        glyphs: f32,
+    },
+
+    /// Sets how this terminal is depth-tested against the terminals and
+    /// scene geometry around it.
+    SetDepthMode(TerminalDepthMode),
+
+    /// Scrolls the view by a number of lines. Positive values scroll up into
+    /// the scrollback history and negative values scroll down.
+    ///
+    /// The view is clamped to the length of the history.
+    ScrollLines(i32),
+
+    /// Scrolls the view to the oldest line in the scrollback history.
+    ScrollToTop,
+
+    /// Scrolls the view back to the live terminal output.
+    ScrollToBottom,
+
+    /// Passes an input method editor event to this terminal.
+    ///
+    /// Preedit text is drawn underlined at the terminal's cursor and
+    /// committed text is written to the terminal's input.
+    Ime(Ime),
+
+    /// Sets whether this terminal has input focus.
+    ///
+    /// The host's IME candidate window follows the cursor of the focused
+    /// terminal. Once focus has been set, an unfocused terminal draws a block
+    /// cursor hollow.
+    SetFocused(bool),
+
+    /// Writes raw bytes to this terminal's input.
+    InputBytes(Vec<u8>),
+
+    /// Replaces the decorations drawn around this terminal.
+    SetChrome(TerminalChrome),
+
+    /// Sets the title drawn in this terminal's title bar, if it has one.
+    SetTitle(String),
+
+    /// Fixes this terminal's grid to a size in cells instead of fitting it
+    /// to [TerminalState]. Both the terminal and its process's
+    /// pseudoterminal are resized.
+    Resize {
+        cols: u16,
+        rows: u16,
+
+        /// How the terminal's quad fits the new grid.
+        #[serde(default)]
+        mode: TerminalSizingMode,
+    },
+
+    /// Moves the pointer over this terminal, or takes it off with `None`.
+    ///
+    /// The point is in the terminal's local space: the same units as
+    /// [TerminalState::half_size], centered on the terminal with +Y up. The
+    /// hotspot under the pointer is underlined.
+    SetPointer(Option<Vec2>),
+
+    /// Finds the cell at a point in the terminal's local space, as in
+    /// [TerminalUpdate::SetPointer].
+    ///
+    /// Replies to the first capability of the message with an
+    /// `Option<CellPosition>`, which is `None` if the point is outside of
+    /// the grid.
+    GetCellAt {
+        local_point: Vec2,
+    },
+
+    /// Finds URLs and file paths on the visible screen.
+    ///
+    /// Replies to the first capability of the message with a
+    /// `Vec<TerminalHotspot>`.
+    GetHotspots,
+
+    /// Extracts text from a region of the terminal.
+    ///
+    /// Replies to the first capability of the message with [TerminalText].
+    GetText {
+        /// The region to extract.
+        range: TextRange,
+    },
+}
+
+/// How a terminal with a fixed grid size fits its quad in world space.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TerminalSizingMode {
+    /// The quad keeps its [TerminalState::half_size] and cells are scaled
+    /// by changing [TerminalState::units_per_em] so that the grid fits.
+    #[default]
+    FixedSize,
+
+    /// Cells keep their size and [TerminalState::half_size] changes to fit
+    /// the grid.
+    Grow,
+}
+
+/// How a terminal interacts with the depth buffer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TerminalDepthMode {
+    /// The terminal doesn't write depth. Terminals in this mode are drawn
+    /// after opaque ones, sorted back to front by their distance from the
+    /// camera, so that they blend over whatever is behind them.
+    #[default]
+    Transparent,
+
+    /// The terminal's background writes depth, so it hides terminals and
+    /// scene geometry behind it regardless of draw order. Only suitable for
+    /// terminals with opaque backgrounds.
+    Opaque,
+}
+
+/// A region of a terminal's lines to extract text from.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TextRange {
+    /// The currently visible screen, including any scrollback it's scrolled
+    /// into.
+    Screen,
+
+    /// The entire scrollback history followed by the live screen.
+    Scrollback,
+
+    /// An explicit half-open range of lines, numbered from the oldest line
+    /// in the scrollback history.
+    ///
+    /// Out-of-range lines are clamped to the available lines.
+    Lines { start: u32, end: u32 },
+}
+
+/// The position of a cell in a terminal.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct CellPosition {
+    /// The cell's line, numbered from the oldest line in the scrollback
+    /// history like [TextRange::Lines].
+    pub line: u32,
+
+    /// The cell's column, from the left.
+    pub column: u32,
+}
+
+/// The kind of text a [TerminalHotspot] matched.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HotspotKind {
+    /// A URL with a scheme, like `https://example.com`.
+    Url,
+
+    /// An absolute or relative file path, like `~/notes.txt` or `./src`.
+    Path,
+}
+
+/// A clickable span of text in a terminal, in reply to
+/// [TerminalUpdate::GetHotspots].
+///
+/// Hotspots may wrap across lines. Opening them is left to the guest.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TerminalHotspot {
+    /// The first cell of the hotspot.
+    pub start: CellPosition,
+
+    /// The last cell of the hotspot, inclusive.
+    pub end: CellPosition,
+
+    /// The matched text, with wrapped lines joined.
+    pub text: String,
+
+    /// What kind of text was matched.
+    pub kind: HotspotKind,
+}
+
+/// Text extracted from a terminal in reply to [TerminalUpdate::GetText].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TerminalText {
+    /// The extracted lines, joined by newlines. Trailing whitespace is
+    /// trimmed and soft-wrapped lines are joined.
+    pub text: String,
+
+    /// Whether the text was cut short by the terminal's reply size limit.
+    pub truncated: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FactoryRequest {
     CreateTerminal(TerminalState),
+
+    /// Creates a terminal running a specific command.
+    ///
+    /// If the first capability of the request is given, it's sent a
+    /// [TerminalEvent::ProcessExited] when the command exits.
+    SpawnShell(SpawnShell),
+
+    /// Replaces the font of every terminal, including ones created later.
+    SetFont(TerminalFonts),
+
+    /// Gets counters describing the most recently rendered frame.
+    GetStats,
+}
+
+/// A command to run in a new terminal, for [FactoryRequest::SpawnShell].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SpawnShell {
+    /// The initial state of the terminal.
+    pub state: TerminalState,
+
+    /// The program to run. Must be allowed by the host's config.
+    pub command: String,
+
+    /// The program's arguments.
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// Environment variables set for the program.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+
+    /// The width of the terminal's grid in cells. If either this or `rows`
+    /// is zero, the grid is sized to fit `state`.
+    #[serde(default)]
+    pub cols: u16,
+
+    /// The height of the terminal's grid in cells.
+    #[serde(default)]
+    pub rows: u16,
+
+    /// Fonts for this terminal instead of the factory's current fonts.
+    ///
+    /// A later [FactoryRequest::SetFont] still replaces them.
+    #[serde(default)]
+    pub fonts: Option<TerminalFonts>,
+}
+
+/// An event sent by a terminal to its owner.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TerminalEvent {
+    /// The terminal's process has exited. The terminal stays open showing
+    /// an exit banner until it's told to quit.
+    ProcessExited,
+}
+
+/// Lumps containing the TrueType or OpenType font files for each terminal
+/// font style.
+///
+/// Styles that aren't given use the regular font. Variable fonts are not
+/// supported.
+///
+/// Each font is loaded once per lump and shared by every terminal that uses
+/// it, so reusing a lump is cheap.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TerminalFonts {
+    pub regular: LumpId,
+    pub italic: Option<LumpId>,
+    pub bold: Option<LumpId>,
+    pub bold_italic: Option<LumpId>,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FactorySuccess {
     /// The first returned capability is to the new terminal, which receives [TerminalUpdates][TerminalUpdate].
     Terminal,
+
+    /// The font was replaced.
+    FontReplaced,
+
+    /// The counters requested by [FactoryRequest::GetStats].
+    Stats(TerminalRenderStats),
+}
+
+/// Counters describing the most recently rendered frame of terminals.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TerminalRenderStats {
+    /// The number of terminals drawn.
+    pub terminals: u32,
+
+    /// The number of draw calls used to draw them.
+    pub draw_calls: u32,
 }
 
 pub type FactoryResponse = Result<FactorySuccess, FactoryError>;
@@ -0,0 +1,77 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::{Deserialize, Serialize};
+
+/// The name of the delayed message delivery service.
+pub const TIMER_SERVICE: &str = "hearth.Timer";
+
+/// The longest delay or period, in milliseconds, that a timer may have.
+pub const MAX_TIMER_MILLIS: u64 = 1 << 40;
+
+/// A request to the [TIMER_SERVICE].
+///
+/// The first capability of each request is the reply address and the second
+/// is the timer's target. When the target has the monitor permission, its
+/// timers are cancelled automatically when it goes down.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum TimerRequest {
+    /// Sends `payload` as the raw data of a message to the target once,
+    /// after `millis` milliseconds.
+    SendAfter { millis: u64, payload: Vec<u8> },
+
+    /// Sends `payload` as the raw data of a message to the target every
+    /// `period_millis` milliseconds until cancelled.
+    ///
+    /// Ticks are scheduled from the previous deadline so that they don't
+    /// drift. If delivery falls behind by whole periods, the missed ticks are
+    /// skipped instead of sent in a burst.
+    SendInterval {
+        period_millis: u64,
+        payload: Vec<u8>,
+    },
+
+    /// Cancels a timer.
+    ///
+    /// The target must be the same as the timer's, so that only holders of
+    /// the target can cancel its timers.
+    Cancel { id: u64 },
+}
+
+/// An error from the [TIMER_SERVICE].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum TimerError {
+    /// The request has no target capability.
+    MissingTarget,
+
+    /// The target capability lacks the send permission.
+    TargetNotSendable,
+
+    /// An interval's period is zero.
+    ZeroPeriod,
+
+    /// The delay or period is longer than [MAX_TIMER_MILLIS].
+    TooLong,
+
+    /// No timer with this ID exists for this target.
+    NotFound,
+}
+
+/// A response from the [TIMER_SERVICE]. Contains the ID of the new or
+/// cancelled timer.
+pub type TimerResponse = Result<u64, TimerError>;
@@ -16,13 +16,35 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
+use std::fmt::{Display, Formatter, Result as FmtResult};
+
+use crate::lump::{LumpMetadata, LumpMismatch};
 use crate::LumpId;
 use serde::{Deserialize, Serialize};
+use serde_with::{base64::Base64, serde_as};
+
+/// The name of the service that spawns Wasm processes on behalf of remote
+/// peers.
+pub const REMOTE_SPAWNER_SERVICE: &str = "hearth.wasm.RemoteSpawner";
+
+/// The name of the service that serves lumps from the local lump store in
+/// chunks.
+pub const LUMP_SOURCE_SERVICE: &str = "hearth.wasm.LumpSource";
+
+/// The name of the service that adds lumps to the local lump store from
+/// chunked uploads.
+pub const LUMP_UPLOADER_SERVICE: &str = "hearth.wasm.LumpUploader";
+
+/// The name of the service that migrates Wasm processes to other peers.
+pub const MIGRATOR_SERVICE: &str = "hearth.wasm.Migrator";
+
+pub const HIBERNATION_STATS_SERVICE: &str = "hearth.wasm.HibernationStats";
 
 /// A spawn message sent to the Wasm process spawner service.
 ///
-/// The service replies with a message containing the decimal representation of
-/// the new process's local process ID.
+/// The service replies with a message whose first capability is the new
+/// process and whose second capability is the process's link endpoint (see
+/// [LinkRequest]).
 #[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct WasmSpawnInfo {
     /// The [LumpId] of the Wasm module lump source.
@@ -31,4 +53,666 @@ pub struct WasmSpawnInfo {
     /// The identifier of the entrypoint to execute. If not specified, runs
     /// the exported "run" function.
     pub entrypoint: Option<u32>,
+
+    /// Resource limits to spawn the process with.
+    #[serde(default)]
+    pub limits: WasmLimits,
+
+    /// If set, the process is never asked to hibernate while idle. Only
+    /// guests whose [GuestMetadata::hibernates] is set are asked either way.
+    #[serde(default)]
+    pub keep_awake: bool,
+
+    /// The host ABI modules, such as `hearth::mailbox`, that the process may
+    /// import, or `None` for the host's configured baseline.
+    ///
+    /// `hearth::abi` is always allowed. The spawn fails if the module imports
+    /// from any other module, or if the requester isn't allowed one of these
+    /// modules itself.
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+}
+
+/// Resource limits for a Wasm process.
+///
+/// Unset limits use the host's configured defaults. Limits above the host's
+/// configured maximums are clamped to them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct WasmLimits {
+    /// The maximum size of each linear memory in bytes.
+    pub max_memory: Option<u64>,
+
+    /// The maximum number of elements in each table.
+    pub max_table_elements: Option<u32>,
+}
+
+/// An error from the Wasm process spawner.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum WasmSpawnError {
+    /// The process couldn't be spawned, with a description of the error.
+    Failed(String),
+
+    /// A process spawned for [WasmSpawnerRequest::SpawnService] couldn't be
+    /// registered, so it was killed. Has a description of the error.
+    Unregistered(String),
+}
+
+impl Display for WasmSpawnError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            WasmSpawnError::Failed(err) => write!(fmt, "{}", err),
+            WasmSpawnError::Unregistered(err) => write!(fmt, "{}", err),
+        }
+    }
+}
+
+/// A response to a [WasmSpawnInfo] or [WasmSpawnerRequest].
+pub type WasmSpawnResponse = Result<(), WasmSpawnError>;
+
+/// Any request to the Wasm process spawner service.
+///
+/// Plain [WasmSpawnInfo] messages are also valid requests, so existing
+/// clients don't need to wrap them.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum WasmSpawnerRequest {
+    /// Spawns a process. See [WasmSpawnInfo].
+    Spawn(WasmSpawnInfo),
+
+    /// Compiles and links a module ahead of time so that spawning it later
+    /// skips that work. The reply has no capabilities.
+    WarmUp { warm_up: LumpId },
+
+    /// Spawns a process like [WasmSpawnerRequest::Spawn], then registers it
+    /// under the name `service` in the registry given as the first
+    /// capability, or in the runtime's registry if there is none. The
+    /// process is killed if it can't be registered.
+    SpawnService {
+        spawn: WasmSpawnInfo,
+        service: String,
+    },
+}
+
+/// Messages between the runtime and an idle process about hibernating it.
+///
+/// These are only sent to guests that set [GuestMetadata::hibernates].
+///
+/// Hibernated processes keep their table and parent mailbox, but their
+/// instance is dropped and their module is instantiated again when their
+/// parent mailbox next receives a signal.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum HibernationMessage {
+    /// Sent to an idle process on its parent mailbox, with a capability to
+    /// reply to, before it's hibernated.
+    ///
+    /// The process may save its state to a lump and reply with
+    /// [HibernationMessage::Hibernated], or reply with
+    /// [HibernationMessage::Refused]. Processes that don't reply are never
+    /// asked to hibernate again.
+    Hibernate,
+
+    /// The process is ready to hibernate, with the lump that its state was
+    /// saved to, if any.
+    Hibernated(Option<LumpId>),
+
+    /// The process can't hibernate now. It's asked again once it has been
+    /// idle for another period.
+    Refused,
+
+    /// Delivered to a woken process on its parent mailbox before the signal
+    /// that woke it, with the lump that it saved its state to.
+    Wake(LumpId),
+}
+
+/// A request to the hibernation statistics service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum HibernationStatsRequest {
+    /// Returns the current [HibernationStats].
+    Get,
+}
+
+/// Counters describing the hibernation of idle Wasm processes.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct HibernationStats {
+    /// The number of processes that are currently hibernated.
+    pub hibernated: u64,
+
+    /// The total number of times that a hibernated process was woken.
+    pub wakes: u64,
+
+    /// The mean time it took to wake a process, in microseconds.
+    pub mean_wake_latency_us: u64,
+
+    /// The longest time it took to wake a process, in microseconds.
+    pub max_wake_latency_us: u64,
+}
+
+pub type HibernationStatsResponse = HibernationStats;
+
+/// The version of the host ABI that this crate describes.
+///
+/// This increases whenever the ABI changes. Guests record the version they
+/// were built against in their [GuestMetadata] and hosts refuse to run
+/// guests that need a newer ABI than they provide.
+///
+/// Version 2 added the `hearth::abi` module.
+///
+/// Version 3 made `hearth::lump` return [HearthError](crate::abi::HearthError)
+/// codes instead of trapping.
+///
+/// Version 4 added `hearth::group`'s `spawner`.
+pub const ABI_VERSION: u32 = 4;
+
+/// The name of the custom Wasm section that holds a module's encoded
+/// [GuestMetadata].
+pub const METADATA_SECTION: &str = "hearth_metadata";
+
+/// The version of the [GuestMetadata] encoding.
+///
+/// Format 1 has no flags byte. It's still decoded, with every flag unset.
+const METADATA_FORMAT: u8 = 2;
+
+/// The [GuestMetadata] flag for [GuestMetadata::hibernates].
+const METADATA_HIBERNATES: u8 = 1 << 0;
+
+/// Metadata that a guest module embeds in its [METADATA_SECTION] so that
+/// hosts can inspect it without instantiating the module.
+///
+/// The section is encoded as a format version byte, the little-endian
+/// `abi_version`, then the name, version, and description, each as a
+/// little-endian `u32` length followed by UTF-8 bytes. An empty description
+/// is encoded for `None`. The last byte holds the guest's flags.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GuestMetadata {
+    /// The name of the guest's crate.
+    pub name: String,
+
+    /// The version of the guest's crate.
+    pub version: String,
+
+    /// The [ABI_VERSION] that the guest was built against.
+    pub abi_version: u32,
+
+    /// A description of the guest.
+    pub description: Option<String>,
+
+    /// Whether the guest handles [HibernationMessage]s. Only guests that do
+    /// are asked to hibernate.
+    pub hibernates: bool,
+}
+
+impl GuestMetadata {
+    /// Decodes the contents of a [METADATA_SECTION]. Returns `None` if the
+    /// section is malformed or uses an unknown format.
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let (format, data) = data.split_first()?;
+
+        if *format != 1 && *format != METADATA_FORMAT {
+            return None;
+        }
+
+        let (abi_version, mut data) = split_u32(data)?;
+
+        let mut next_field = || -> Option<String> {
+            let (len, rest) = split_u32(data)?;
+            let len = len as usize;
+
+            if rest.len() < len {
+                return None;
+            }
+
+            let (field, rest) = rest.split_at(len);
+            data = rest;
+            String::from_utf8(field.to_vec()).ok()
+        };
+
+        let name = next_field()?;
+        let version = next_field()?;
+        let description = Some(next_field()?).filter(|description| !description.is_empty());
+
+        let flags = match *format {
+            1 => 0,
+            _ => *data.first()?,
+        };
+
+        Some(Self {
+            name,
+            version,
+            abi_version,
+            description,
+            hibernates: flags & METADATA_HIBERNATES != 0,
+        })
+    }
+}
+
+fn split_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+
+    let (int, rest) = data.split_at(4);
+    Some((u32::from_le_bytes(int.try_into().unwrap()), rest))
+}
+
+/// Returns the encoded length of a [METADATA_SECTION] with the given name,
+/// version, and description.
+pub const fn encoded_metadata_len(fields: [&str; 3]) -> usize {
+    let mut len = 1 + 4 + 1;
+    let mut index = 0;
+
+    while index < fields.len() {
+        len += 4 + fields[index].len();
+        index += 1;
+    }
+
+    len
+}
+
+/// Encodes a [METADATA_SECTION] at compile time. `N` must be the result of
+/// [encoded_metadata_len] with the same fields.
+pub const fn encode_metadata<const N: usize>(
+    abi_version: u32,
+    fields: [&str; 3],
+    hibernates: bool,
+) -> [u8; N] {
+    let mut out = [0u8; N];
+    out[0] = METADATA_FORMAT;
+    let mut cursor = 1;
+
+    let abi_version = abi_version.to_le_bytes();
+    let mut index = 0;
+    while index < 4 {
+        out[cursor] = abi_version[index];
+        cursor += 1;
+        index += 1;
+    }
+
+    let mut field = 0;
+    while field < fields.len() {
+        let bytes = fields[field].as_bytes();
+        let len = (bytes.len() as u32).to_le_bytes();
+
+        let mut index = 0;
+        while index < 4 {
+            out[cursor] = len[index];
+            cursor += 1;
+            index += 1;
+        }
+
+        let mut index = 0;
+        while index < bytes.len() {
+            out[cursor] = bytes[index];
+            cursor += 1;
+            index += 1;
+        }
+
+        field += 1;
+    }
+
+    if hibernates {
+        out[cursor] = METADATA_HIBERNATES;
+    }
+
+    out
+}
+
+/// A spawn message sent to the remote spawner service.
+///
+/// The first capability is the reply address. The second is a lump source
+/// (see [LumpChunkRequest]) that the module lump is fetched from if the
+/// spawning peer doesn't have it yet. The rest of the capabilities are passed
+/// on to the new process.
+///
+/// The service replies with a [RemoteSpawnResponse]. On success, the reply
+/// also contains a capability to the new process followed by its link
+/// endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RemoteSpawnInfo {
+    /// If set, the request is forwarded to the remote spawner registered
+    /// under this name in the receiving peer's registry, with the receiving
+    /// peer's own lump source attached. Peers are addressed this way so that
+    /// a local client can spawn processes on a remote peer through its own
+    /// runtime.
+    pub peer: Option<String>,
+
+    /// The Wasm module and entrypoint to spawn.
+    pub spawn: WasmSpawnInfo,
+
+    /// Other lumps that the new process needs, such as saved state. Missing
+    /// lumps are fetched from the lump source along with the module.
+    #[serde(default)]
+    pub lumps: Vec<LumpId>,
+}
+
+/// An error from the remote spawner.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RemoteSpawnError {
+    /// A lump fetched from the lump source didn't match its ID, so it was
+    /// discarded.
+    LumpMismatch(LumpMismatch),
+
+    /// The process couldn't be spawned, with a description of the error.
+    Failed(String),
+}
+
+impl Display for RemoteSpawnError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            RemoteSpawnError::LumpMismatch(mismatch) => write!(fmt, "{}", mismatch),
+            RemoteSpawnError::Failed(err) => write!(fmt, "{}", err),
+        }
+    }
+}
+
+/// A response to a [RemoteSpawnInfo] request.
+pub type RemoteSpawnResponse = Result<(), RemoteSpawnError>;
+
+/// A request to the migrator service to move a running Wasm process to
+/// another peer.
+///
+/// The first capability is the reply address. The second is the process to
+/// migrate, which must permit killing and be local to the migrator. The
+/// rest of the capabilities are passed on to the new process.
+///
+/// Migration is cooperative, so the process must handle
+/// [MigrationMessage]s. The migrator:
+///
+/// 1. asks the process for a snapshot of its state, after which the process
+///    holds back any other messages it receives,
+/// 2. spawns the module on the destination peer, transferring the state
+///    lump along with it,
+/// 3. restores the new process from the snapshot,
+/// 4. has the original process forward its held-back and later messages to
+///    the new process,
+/// 5. registers the new process under [Self::services] and kills the
+///    original process.
+///
+/// If a step before forwarding fails, the new process is killed and the
+/// original process resumes.
+///
+/// The migrator replies with a [MigrateResponse]. On success, the reply also
+/// contains a capability to the new process followed by its link endpoint.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MigrateRequest {
+    /// The name of the destination peer's remote spawner in the migrator's
+    /// registry. See [RemoteSpawnInfo::peer].
+    pub peer: String,
+
+    /// The module and entrypoint that the process is running.
+    pub spawn: WasmSpawnInfo,
+
+    /// Names to register the new process under. If set, the first capability
+    /// passed on to the new process must be the registry to register it in.
+    #[serde(default)]
+    pub services: Vec<String>,
+}
+
+/// A response to a [MigrateRequest].
+pub type MigrateResponse = Result<(), String>;
+
+/// A message between the migrator and a migrating process, sent to the
+/// process's parent mailbox. See [MigrateRequest].
+///
+/// Every message sent to a process has a reply address as its first
+/// capability. The second capability is the process itself with the kill
+/// permission, proving that the sender may move it or overwrite its state.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MigrationMessage {
+    /// Asks a process to save its state to a lump and reply with
+    /// [MigrationMessage::State], then hold back other messages until it's
+    /// told to forward or resume.
+    Snapshot,
+
+    /// The lump that a process's state was saved to.
+    State(LumpId),
+
+    /// Asks a newly-spawned process to load its state from a lump and reply
+    /// with [MigrationMessage::Ready]. Only accepted as a process's first
+    /// message.
+    Restore(LumpId),
+
+    /// A process has restored its state and is ready for messages.
+    Ready,
+
+    /// Asks a process to forward every held-back and later message to the
+    /// third capability, then reply with [MigrationMessage::Forwarded] once
+    /// the held-back messages have been sent.
+    Forward,
+
+    /// A process has forwarded its held-back messages.
+    Forwarded,
+
+    /// Tells a process that migration was aborted and to handle its
+    /// held-back messages itself. Has no reply.
+    Resume,
+
+    /// A process failed to save or restore its state.
+    Failed(String),
+}
+
+/// The maximum length of a lump chunk, both in [LumpChunkRequest]s and in
+/// [LumpUploadRequest::Chunk]s.
+///
+/// Lumps are transferred in chunks of this size so that large transfers are
+/// interleaved with other traffic on the connection instead of stalling it.
+pub const LUMP_CHUNK_SIZE: u32 = 64 * 1024;
+
+/// The maximum size of a lump transferred in chunks.
+pub const MAX_LUMP_SIZE: u64 = 64 * 1024 * 1024;
+
+/// A request to a lump source for a chunk of a lump.
+///
+/// The first capability is the reply address. The lump source replies with
+/// a [LumpChunkResponse].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LumpChunkRequest {
+    /// The lump to read from.
+    pub lump: LumpId,
+
+    /// The byte offset of the chunk within the lump.
+    pub offset: u64,
+
+    /// The maximum length of the chunk. Lump sources may return shorter
+    /// chunks.
+    pub len: u32,
+}
+
+/// A chunk of a lump.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct LumpChunk {
+    /// The total size of the lump in bytes.
+    pub size: u64,
+
+    /// The data of this chunk. Empty if the offset is past the end of the
+    /// lump.
+    #[serde_as(as = "Base64")]
+    pub data: Vec<u8>,
+
+    /// The lump's metadata.
+    #[serde(default)]
+    pub metadata: LumpMetadata,
+}
+
+/// A response to a [LumpChunkRequest].
+pub type LumpChunkResponse = Result<LumpChunk, String>;
+
+/// A request to the lump uploader service.
+///
+/// The first capability is the reply address. The uploader replies with a
+/// [LumpUploadResponse]. Large lumps are uploaded over several requests so
+/// that no single message has to hold the whole lump.
+#[serde_as]
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum LumpUploadRequest {
+    /// Starts uploading a lump of `size` bytes, which may be at most
+    /// [MAX_LUMP_SIZE]. Replies with [LumpUploadSuccess::Started].
+    ///
+    /// If `expected` is set, finishing the upload fails with
+    /// [LumpUploadError::Mismatch] unless the data has that ID. `metadata`
+    /// is stored with the lump.
+    Begin {
+        size: u64,
+        #[serde(default)]
+        expected: Option<LumpId>,
+        #[serde(default)]
+        metadata: LumpMetadata,
+    },
+
+    /// Appends data to an upload. `offset` must be the number of bytes
+    /// uploaded so far, so chunks have to be sent in order, and chunks may
+    /// be at most [LUMP_CHUNK_SIZE] bytes long. Replies with
+    /// [LumpUploadSuccess::Received].
+    Chunk {
+        upload: u64,
+        offset: u64,
+        #[serde_as(as = "Base64")]
+        data: Vec<u8>,
+    },
+
+    /// Adds a fully-uploaded lump to the lump store. Replies with
+    /// [LumpUploadSuccess::Finished].
+    Finish { upload: u64 },
+}
+
+/// A successful reply to a [LumpUploadRequest].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LumpUploadSuccess {
+    /// An upload was started with this ID.
+    Started { upload: u64 },
+
+    /// A chunk was appended. `received` is the upload's size so far.
+    Received { received: u64 },
+
+    /// The upload was added to the lump store as this lump.
+    Finished(LumpId),
+}
+
+/// An error from the lump uploader.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LumpUploadError {
+    /// The request was invalid, with a description of the problem.
+    Invalid(String),
+
+    /// The uploaded data didn't have the expected ID, so it was discarded.
+    Mismatch(LumpMismatch),
+}
+
+impl Display for LumpUploadError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            LumpUploadError::Invalid(err) => write!(fmt, "{}", err),
+            LumpUploadError::Mismatch(mismatch) => write!(fmt, "{}", mismatch),
+        }
+    }
+}
+
+/// A response to a [LumpUploadRequest].
+pub type LumpUploadResponse = Result<LumpUploadSuccess, LumpUploadError>;
+
+/// Why a Wasm process exited.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum ExitReason {
+    /// The process returned from its entrypoint.
+    Finished,
+
+    /// The process trapped or failed to run, with a description of the error.
+    Trapped(String),
+
+    /// The process was killed.
+    Killed,
+
+    /// The process trapped after being refused memory or table space by one
+    /// of its [WasmLimits], with a description of the limit.
+    LimitExceeded(String),
+}
+
+/// The message sent to a linked mailbox when the process it's linked to
+/// exits.
+///
+/// If the process had already exited when the link was made, this is sent
+/// immediately.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProcessDown {
+    /// The local process ID of the process that exited.
+    pub pid: u64,
+
+    /// Why the process exited.
+    pub reason: ExitReason,
+}
+
+/// A request sent to a process's link endpoint.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum LinkRequest {
+    /// Sends a [ProcessDown] to the first capability when the process exits.
+    ///
+    /// `id` identifies this link to [LinkRequest::Unlink]. Linking again with
+    /// the same ID replaces the previous link.
+    Link { id: u64 },
+
+    /// Removes a link by its ID.
+    Unlink { id: u64 },
+
+    /// Replies to the first capability with the process's PID as a `u64`.
+    GetPid,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata_round_trip() {
+        const FIELDS: [&str; 3] = ["hearth-test", "0.1.0", "A test module."];
+        const LEN: usize = encoded_metadata_len(FIELDS);
+        const ENCODED: [u8; LEN] = encode_metadata(ABI_VERSION, FIELDS, true);
+
+        let decoded = GuestMetadata::decode(&ENCODED).unwrap();
+        assert_eq!(decoded.name, "hearth-test");
+        assert_eq!(decoded.version, "0.1.0");
+        assert_eq!(decoded.abi_version, ABI_VERSION);
+        assert_eq!(decoded.description.as_deref(), Some("A test module."));
+        assert!(decoded.hibernates);
+
+        const BARE: [u8; encoded_metadata_len(["bare", "1.0.0", ""])] =
+            encode_metadata(7, ["bare", "1.0.0", ""], false);
+
+        let decoded = GuestMetadata::decode(&BARE).unwrap();
+        assert_eq!(decoded.abi_version, 7);
+        assert_eq!(decoded.description, None);
+        assert!(!decoded.hibernates);
+
+        // format 1 predates flags
+        let mut old = BARE[..BARE.len() - 1].to_vec();
+        old[0] = 1;
+        assert_eq!(GuestMetadata::decode(&old), Some(decoded));
+
+        assert_eq!(GuestMetadata::decode(&ENCODED[..LEN - 1]), None);
+    }
+
+    #[test]
+    fn spawner_requests_accept_spawn_info() {
+        let info = WasmSpawnInfo {
+            lump: LumpId([0; 32]),
+            entrypoint: Some(4),
+            limits: Default::default(),
+            keep_awake: false,
+            allow: None,
+        };
+
+        let data = serde_json::to_vec(&info).unwrap();
+        let request: WasmSpawnerRequest = serde_json::from_slice(&data).unwrap();
+        assert!(matches!(
+            request,
+            WasmSpawnerRequest::Spawn(WasmSpawnInfo {
+                entrypoint: Some(4),
+                ..
+            })
+        ));
+
+        let warm_up = WasmSpawnerRequest::WarmUp { warm_up: info.lump };
+        let data = serde_json::to_vec(&warm_up).unwrap();
+        let request: WasmSpawnerRequest = serde_json::from_slice(&data).unwrap();
+        assert!(matches!(request, WasmSpawnerRequest::WarmUp { .. }));
+    }
 }
@@ -24,7 +24,9 @@
 //! there is a more appropriate way to reuse these type definitions, please
 //! open an issue and let us know!
 
-use glam::{DVec2, Mat4, UVec2};
+use std::collections::BTreeMap;
+
+use glam::{DVec2, Mat4, UVec2, Vec2};
 use serde::{Deserialize, Serialize};
 
 /// The name of the service that provides the main client window.
@@ -38,7 +40,6 @@ pub const SERVICE_NAME: &str = "hearth.Window";
 ///
 /// If something is missing, wrong, or otherwise broken, please open an issue.
 // TODO file dropping/hovering?
-// TODO IME support?
 // TODO touchpad support?
 // TODO touch support?
 // TODO port DeviceId?
@@ -82,6 +83,52 @@ pub enum WindowEvent {
 
     /// Raw, unfiltered physical motion from a mouse device in unspecified units.
     MouseMotion(DVec2),
+
+    /// An input method editor event, for composing text that can't be typed
+    /// with single key presses.
+    Ime(Ime),
+}
+
+impl WindowEvent {
+    /// Whether this is a keyboard or text input event, which the focus
+    /// service only routes to the focused target.
+    pub fn is_keyboard_input(&self) -> bool {
+        matches!(
+            self,
+            WindowEvent::ReceivedCharacter(_)
+                | WindowEvent::KeyboardInput { .. }
+                | WindowEvent::ModifiersChanged(_)
+                | WindowEvent::Ime(_)
+        )
+    }
+}
+
+/// Describes an input method editor (IME) event.
+///
+/// Text composed with an IME is sent as [Ime::Commit] instead of as
+/// [WindowEvent::ReceivedCharacter], so text input should handle both.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub enum Ime {
+    /// The IME has been enabled. Preedit and commit events follow.
+    Enabled,
+
+    /// The text being composed has changed. An empty `text` means that the
+    /// composition has been cleared.
+    Preedit {
+        /// The text being composed, which has not been committed yet.
+        text: String,
+
+        /// The byte range of the cursor or selection within `text`, or
+        /// `None` if the cursor should be hidden.
+        cursor: Option<(usize, usize)>,
+    },
+
+    /// Text has been composed and should be inserted. Clears the preedit
+    /// text.
+    Commit(String),
+
+    /// The IME has been disabled. Clears the preedit text.
+    Disabled,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -105,6 +152,13 @@ pub enum WindowCommand {
     /// Sets the visibility of the cursor.
     SetCursorVisible(bool),
 
+    /// Sets the vertical sync mode of the window's surface.
+    SetVsync(VsyncMode),
+
+    /// Requests that the window redraws soon because its contents have
+    /// changed. Windows only redraw periodically otherwise.
+    Redraw,
+
     /// Updates the window's rendering camera.
     SetCamera {
         /// Vertical field of view in degrees.
@@ -116,6 +170,414 @@ pub enum WindowCommand {
         /// The camera's view matrix.
         view: Mat4,
     },
+
+    /// Sets the position of the IME candidate window, in physical display
+    /// units from the top-left corner of the window.
+    ///
+    /// This should be kept on the cursor of the text being edited.
+    SetImePosition(DVec2),
+
+    /// Moves the window's rendering camera toward a target, easing it in
+    /// every rendered frame instead of snapping to it.
+    ///
+    /// Sending [WindowCommand::SetCamera] snaps to the new camera instead.
+    SetCameraTarget {
+        /// Vertical field of view in degrees.
+        vfov: f32,
+
+        /// Near plane distance. All projection uses an infinite far plane.
+        near: f32,
+
+        /// The camera's view matrix.
+        view: Mat4,
+
+        /// How the camera eases toward the target.
+        smoothing: CameraSmoothing,
+    },
+}
+
+/// How a camera moves toward its target.
+///
+/// Positions and fields of view are interpolated directly, while rotations
+/// are interpolated along the shortest arc.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub enum CameraSmoothing {
+    /// The camera snaps to the target.
+    #[default]
+    None,
+
+    /// The camera covers half of its remaining distance to the target every
+    /// `half_life` seconds.
+    Exponential { half_life: f32 },
+
+    /// The camera follows a critically damped spring, which keeps its
+    /// momentum when the target moves but never overshoots a still target.
+    /// Higher `frequency` values, in radians per second, are stiffer.
+    Spring { frequency: f32 },
+}
+
+/// The name of the service that creates camera sources for the main window.
+pub const CAMERA_SERVICE: &str = "hearth.Camera";
+
+/// A request to the camera service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum CameraRequest {
+    /// Creates a new camera source.
+    ///
+    /// The window is drawn with the camera of the highest-priority source
+    /// that has been set. Sources created later win ties. The window's own
+    /// camera, set with [WindowCommand::SetCamera], has a priority of zero.
+    ///
+    /// Returns [CameraSuccess::Source] with a capability to the source, which
+    /// accepts [CameraCommand]. The source hands control back to the next
+    /// source once it's released or killed.
+    CreateSource { priority: u32 },
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum CameraSuccess {
+    /// A camera source was created.
+    Source,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum CameraError {
+    /// The window is no longer available.
+    Unavailable,
+}
+
+pub type CameraResponse = Result<CameraSuccess, CameraError>;
+
+/// A message to a camera source created by [CameraRequest::CreateSource].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum CameraCommand {
+    /// Snaps this source's camera. Same as [WindowCommand::SetCamera].
+    Set { vfov: f32, near: f32, view: Mat4 },
+
+    /// Eases this source's camera toward a target. Same as
+    /// [WindowCommand::SetCameraTarget].
+    SetTarget {
+        vfov: f32,
+        near: f32,
+        view: Mat4,
+        smoothing: CameraSmoothing,
+    },
+
+    /// Removes this source.
+    Release,
+}
+
+/// The vertical sync mode of a window, which controls how rendered frames are
+/// presented to the display.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VsyncMode {
+    /// Frames are presented in sync with the display's refresh rate. Never
+    /// tears.
+    #[default]
+    On,
+
+    /// Frames are presented immediately. May tear.
+    #[serde(alias = "immediate")]
+    Off,
+
+    /// Frames are presented in sync with the display, but rendering doesn't
+    /// wait for the display, replacing queued frames instead. Falls back to
+    /// [VsyncMode::On] where unsupported.
+    Mailbox,
+}
+
+/// The name of the service that provides access to the host clipboard.
+pub const CLIPBOARD_SERVICE: &str = "hearth.Clipboard";
+
+/// A request to the clipboard service. The first capability is the reply
+/// address, which receives a [ClipboardResponse].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ClipboardRequest {
+    /// Gets the text contents of the clipboard.
+    ///
+    /// Text longer than the service's configured limit is truncated.
+    GetText,
+
+    /// Sets the text contents of the clipboard.
+    ///
+    /// Text longer than the service's configured limit is truncated.
+    SetText(String),
+}
+
+/// A successful reply to a [ClipboardRequest].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ClipboardSuccess {
+    /// The text contents of the clipboard.
+    GetText(String),
+
+    /// The clipboard's text has been set.
+    SetText,
+}
+
+/// An error in handling a [ClipboardRequest].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum ClipboardError {
+    /// The clipboard is empty or does not contain text.
+    Empty,
+
+    /// The host does not have a usable clipboard.
+    Unavailable,
+
+    /// Any other clipboard error.
+    Other(String),
+}
+
+/// A reply to a [ClipboardRequest].
+pub type ClipboardResponse = Result<ClipboardSuccess, ClipboardError>;
+
+/// The name of the service that maps physical inputs to named actions.
+pub const INPUT_MAP_SERVICE: &str = "hearth.InputMap";
+
+/// A request to the input map service. The first capability is the reply
+/// address, which receives an [InputMapResponse].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum InputMapRequest {
+    /// Subscribes the first capability argument to [InputMapEvent].
+    ///
+    /// If the capability has the monitor permission, it will be automatically
+    /// unsubscribed when down.
+    Subscribe,
+
+    /// Unsubscribes the first capability argument from [InputMapEvent].
+    Unsubscribe,
+
+    /// Gets the bindings of every action.
+    GetBindings,
+
+    /// Replaces the bindings of an action and saves them to the config file.
+    ///
+    /// An empty list of bindings removes the action.
+    Rebind {
+        /// The name of the action.
+        action: String,
+
+        /// The action's new bindings.
+        bindings: Vec<InputBinding>,
+    },
+}
+
+/// A successful reply to an [InputMapRequest].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum InputMapSuccess {
+    /// The request was handled.
+    Ok,
+
+    /// The bindings of every action, by action name.
+    Bindings(BTreeMap<String, Vec<InputBinding>>),
+}
+
+/// An error in handling an [InputMapRequest].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum InputMapError {
+    /// The request requires a capability argument and none was given.
+    MissingCapability,
+
+    /// The bindings were changed but could not be saved to the config file.
+    SaveFailed(String),
+}
+
+/// A reply to an [InputMapRequest].
+pub type InputMapResponse = Result<InputMapSuccess, InputMapError>;
+
+/// An event sent to subscribers of the input map service.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum InputMapEvent {
+    /// One of an action's bindings has been pressed while none of the others
+    /// were held.
+    ActionStarted(String),
+
+    /// The last held binding of an action has been released.
+    ActionEnded(String),
+
+    /// An axis bound to an action has moved.
+    AxisChanged {
+        /// The name of the action.
+        action: String,
+
+        /// The new value of the axis, from -1.0 to 1.0.
+        value: f32,
+    },
+}
+
+/// A physical input that triggers an action.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+pub enum InputBinding {
+    /// A keyboard key, regardless of held modifiers.
+    Key(VirtualKeyCode),
+
+    /// A keyboard key pressed while holding at least the given modifiers.
+    Chord {
+        modifiers: ModifiersState,
+        key: VirtualKeyCode,
+    },
+
+    /// A mouse button.
+    Mouse(MouseButton),
+
+    /// A gamepad button on any connected gamepad.
+    GamepadButton(GamepadButton),
+
+    /// A gamepad axis on any connected gamepad. Only sends
+    /// [InputMapEvent::AxisChanged].
+    GamepadAxis(GamepadAxis),
+}
+
+/// A button on a gamepad, named by its position on the controller.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GamepadButton {
+    /// The bottom face button.
+    South,
+    /// The right face button.
+    East,
+    /// The top face button.
+    North,
+    /// The left face button.
+    West,
+    C,
+    Z,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    /// The button in the middle of the controller, often with a logo.
+    Mode,
+    /// Pressing in the left stick.
+    LeftThumb,
+    /// Pressing in the right stick.
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+/// An analog axis on a gamepad.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq, Deserialize, Serialize)]
+pub enum GamepadAxis {
+    LeftStickX,
+    LeftStickY,
+    LeftZ,
+    RightStickX,
+    RightStickY,
+    RightZ,
+    DPadX,
+    DPadY,
+}
+
+/// The name of the service that routes keyboard input to a single focused
+/// target, such as a terminal.
+pub const FOCUS_SERVICE: &str = "hearth.Focus";
+
+/// A world-space rectangle that focuses its target when clicked.
+///
+/// The rectangle spans from `-half_size` to `half_size` on the XY plane of
+/// `transform`, facing either way along its Z axis.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct FocusBounds {
+    /// The rectangle's transform in world space.
+    pub transform: Mat4,
+
+    /// Half of the rectangle's width and height, before `transform`.
+    pub half_size: Vec2,
+}
+
+/// A request to the focus service. The first capability is the reply
+/// address, which receives a [FocusResponse].
+///
+/// At most one target is focused at a time. Clicking a target's bounds with
+/// the left mouse button focuses it, and clicking outside of every target
+/// clears the focus. Whenever the focus moves, the previous target is sent
+/// [FocusEvent::Lost] before the new one is sent [FocusEvent::Gained].
+///
+/// While a target is focused, keyboard input is only sent to it. While
+/// nothing is focused, keyboard input goes to the window's subscribers.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum FocusRequest {
+    /// Registers the second capability as a focusable target, which
+    /// receives [FocusEvent].
+    ///
+    /// Returns [FocusSuccess::Registered] with the target's ID. The first
+    /// returned capability controls the target and accepts
+    /// [FocusTargetRequest], so only its holder can focus, move, or
+    /// unregister the target. Killing it unregisters the target.
+    ///
+    /// Targets without bounds can only be focused with
+    /// [FocusTargetRequest::Focus]. If the target capability has the monitor
+    /// permission, the target is unregistered when it goes down.
+    Register { bounds: Option<FocusBounds> },
+
+    /// Gets the ID of the focused target, if any.
+    GetFocus,
+}
+
+/// A request to a focus target's controller, returned by
+/// [FocusRequest::Register]. The first capability is the reply address,
+/// which receives a [FocusResponse].
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum FocusTargetRequest {
+    /// Moves or removes the bounds of the target.
+    SetBounds(Option<FocusBounds>),
+
+    /// Focuses the target, taking the focus from any other target.
+    Focus,
+
+    /// Clears the focus if the target has it.
+    Blur,
+
+    /// Unregisters the target. If it's focused, it's sent [FocusEvent::Lost]
+    /// first and nothing is focused afterwards.
+    Unregister,
+}
+
+/// A successful reply to a [FocusRequest] or [FocusTargetRequest].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FocusSuccess {
+    /// The request was handled.
+    Ok,
+
+    /// A target was registered with this ID.
+    Registered(u32),
+
+    /// The ID of the focused target, if any.
+    Focus(Option<u32>),
+}
+
+/// An error in handling a [FocusRequest] or [FocusTargetRequest].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub enum FocusError {
+    /// The request requires a capability argument and none was given.
+    MissingCapability,
+
+    /// No target is registered with this ID. Targets are gone once they're
+    /// unregistered.
+    UnknownTarget(u32),
+}
+
+/// A reply to a [FocusRequest] or [FocusTargetRequest].
+pub type FocusResponse = Result<FocusSuccess, FocusError>;
+
+/// A message sent to a focusable target.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub enum FocusEvent {
+    /// This target has gained focus.
+    Gained,
+
+    /// This target has lost focus. Always follows [FocusEvent::Gained],
+    /// including when the target is unregistered or goes down while
+    /// focused.
+    Lost,
+
+    /// A window event for which [WindowEvent::is_keyboard_input] is true,
+    /// sent while this target is focused.
+    Input(WindowEvent),
 }
 
 /// Describes a keyboard input event.
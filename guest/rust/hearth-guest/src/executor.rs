@@ -0,0 +1,232 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! A minimal single-threaded executor for futures over mailbox receives.
+//!
+//! [Mailbox::recv_async] returns a plain [Future], so receives on several
+//! mailboxes can be composed with [select] or any other executor-agnostic
+//! combinator. [run_async] drives a future to completion, blocking on the
+//! host until one of the mailboxes that the future is waiting on receives a
+//! signal whenever it can't make progress.
+//!
+//! Signals that arrive for a mailbox after the future waiting on them has
+//! been dropped are kept, and are returned by the next receive on that
+//! mailbox, blocking or not.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use crate::{poll_handles, Mailbox, Signal};
+
+thread_local! {
+    static REACTOR: RefCell<Reactor> = RefCell::new(Reactor::default());
+}
+
+/// The executor's state for every mailbox being waited on.
+#[derive(Default)]
+struct Reactor {
+    slots: HashMap<u32, Slot>,
+    next_waiter: u64,
+}
+
+/// The executor's state for a single mailbox.
+#[derive(Default)]
+struct Slot {
+    /// Signals received from the host that no future has taken yet.
+    signals: VecDeque<Signal>,
+
+    /// The wakers of the futures waiting on this mailbox, by waiter ID.
+    wakers: HashMap<u64, Waker>,
+}
+
+/// Takes the oldest signal that the executor has received for a mailbox.
+pub(crate) fn take_buffered(handle: u32) -> Option<Signal> {
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        reactor.slots.get_mut(&handle)?.signals.pop_front()
+    })
+}
+
+/// Forgets the state of a destroyed mailbox.
+pub(crate) fn forget(handle: u32) {
+    REACTOR.with(|reactor| reactor.borrow_mut().slots.remove(&handle));
+}
+
+fn next_waiter() -> u64 {
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        reactor.next_waiter += 1;
+        reactor.next_waiter
+    })
+}
+
+fn add_waker(handle: u32, waiter: u64, waker: &Waker) {
+    REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        let slot = reactor.slots.entry(handle).or_default();
+        slot.wakers.insert(waiter, waker.clone());
+    });
+}
+
+fn remove_waker(handle: u32, waiter: u64) {
+    REACTOR.with(|reactor| {
+        if let Some(slot) = reactor.borrow_mut().slots.get_mut(&handle) {
+            slot.wakers.remove(&waiter);
+        }
+    });
+}
+
+/// Blocks until any mailbox with a waiting future receives a signal, then
+/// buffers the signal and wakes the mailbox's futures.
+fn park() {
+    let handles: Vec<u32> = REACTOR.with(|reactor| {
+        reactor
+            .borrow()
+            .slots
+            .iter()
+            .filter(|(_, slot)| !slot.wakers.is_empty())
+            .map(|(handle, _)| *handle)
+            .collect()
+    });
+
+    if handles.is_empty() {
+        panic!("future passed to run_async is pending but isn't waiting on any mailbox");
+    }
+
+    let (index, signal) = poll_handles(&handles);
+
+    let wakers = REACTOR.with(|reactor| {
+        let mut reactor = reactor.borrow_mut();
+        let slot = reactor.slots.entry(handles[index]).or_default();
+        slot.signals.push_back(signal);
+        std::mem::take(&mut slot.wakers)
+    });
+
+    for waker in wakers.into_values() {
+        waker.wake();
+    }
+}
+
+/// Wakes [run_async] by flagging its future to be polled again.
+struct Flag(AtomicBool);
+
+impl Wake for Flag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Runs a future to completion on this process's thread.
+///
+/// When the future can't make progress, this blocks until one of the
+/// mailboxes it's waiting on receives a signal. Panics if the future is
+/// pending without waiting on any mailbox, since it could never be woken.
+pub fn run_async<F: Future>(fut: F) -> F::Output {
+    let mut fut = pin!(fut);
+    let flag = Arc::new(Flag(AtomicBool::new(false)));
+    let waker = Waker::from(flag.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if let Poll::Ready(output) = fut.as_mut().poll(&mut cx) {
+            return output;
+        }
+
+        // skip blocking if the future woke itself while being polled
+        if !flag.0.swap(false, Ordering::Relaxed) {
+            park();
+        }
+    }
+}
+
+/// A future for the next [Signal] received by a mailbox. Created by
+/// [Mailbox::recv_async].
+///
+/// Dropping this before it completes doesn't lose any signals.
+pub struct Recv<'a> {
+    mailbox: &'a Mailbox,
+    waiter: u64,
+}
+
+impl<'a> Recv<'a> {
+    pub(crate) fn new(mailbox: &'a Mailbox) -> Self {
+        Self {
+            mailbox,
+            waiter: next_waiter(),
+        }
+    }
+}
+
+impl Future for Recv<'_> {
+    type Output = Signal;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Signal> {
+        match self.mailbox.try_recv() {
+            Some(signal) => {
+                remove_waker(self.mailbox.0, self.waiter);
+                Poll::Ready(signal)
+            }
+            None => {
+                add_waker(self.mailbox.0, self.waiter, cx.waker());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Recv<'_> {
+    fn drop(&mut self) {
+        remove_waker(self.mailbox.0, self.waiter);
+    }
+}
+
+/// The output of [select].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Either<A, B> {
+    /// The first future completed first.
+    Left(A),
+
+    /// The second future completed first.
+    Right(B),
+}
+
+/// Waits for the first of two futures to complete, dropping the other.
+///
+/// If both are ready at once, the first one wins.
+pub async fn select<A: Future, B: Future>(a: A, b: B) -> Either<A::Output, B::Output> {
+    let mut a = pin!(a);
+    let mut b = pin!(b);
+
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = a.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(output));
+        }
+
+        if let Poll::Ready(output) = b.as_mut().poll(cx) {
+            return Poll::Ready(Either::Right(output));
+        }
+
+        Poll::Pending
+    })
+    .await
+}
@@ -21,11 +21,15 @@
 #![warn(missing_docs)]
 
 use std::borrow::Borrow;
+use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
+pub use hearth_schema::abi::HearthError;
 pub use hearth_schema::*;
 
+pub mod executor;
+
 /// Internal helper function to turn a string into a pointer and length.
 fn abi_string(str: &str) -> (u32, u32) {
     let bytes = str.as_bytes();
@@ -34,6 +38,12 @@ fn abi_string(str: &str) -> (u32, u32) {
     (ptr, len)
 }
 
+/// Internal helper function to decode the return value of a fallible ABI
+/// function, describing its error with `context`.
+fn abi_result(ret: i32, context: impl FnOnce() -> String) -> Result<u32, HearthError> {
+    HearthError::decode(ret).map_err(|code| HearthError::from_code(code, context()))
+}
+
 /// Fetches the lump ID of the module used to spawn the current process.
 pub fn this_lump() -> LumpId {
     // load lump ID from the host
@@ -97,21 +107,51 @@ impl Capability {
         self.send(&msg.into_bytes(), caps);
     }
 
+    /// Sends a type, serialized in the compact binary format, to this
+    /// capability.
+    ///
+    /// Usually smaller than JSON for bulky data like pixel buffers. Only
+    /// receivers that decode payloads with [codec::decode] accept it. That
+    /// includes host services built on the runtime's sink and
+    /// request-response helpers, but not every process that accepts JSON.
+    pub fn send_bin(&self, data: &impl Serialize, caps: &[&Capability]) {
+        self.send(&codec::encode_binary(data), caps);
+    }
+
     /// Kills this capability.
     pub fn kill(&self) {
         unsafe { abi::table::kill(self.0) }
     }
 
     /// Demotes this capability to a capability with fewer permissions.
+    ///
+    /// Use this to hand out attenuated capabilities, for example to child
+    /// processes. The host traps this process if `new_perms` isn't a subset of
+    /// this capability's permissions; use [Self::try_demote] to check first.
     pub fn demote(&self, new_perms: Permissions) -> Capability {
         let handle = unsafe { abi::table::demote(self.0, new_perms.bits()) };
         Capability(handle)
     }
 
+    /// Demotes this capability, or returns `None` if `new_perms` isn't a
+    /// subset of this capability's permissions.
+    pub fn try_demote(&self, new_perms: Permissions) -> Option<Capability> {
+        if self.can(new_perms) {
+            Some(self.demote(new_perms))
+        } else {
+            None
+        }
+    }
+
     /// Gets the permission flags for this capability.
     pub fn get_flags(&self) -> Permissions {
         Permissions::from_bits_retain(unsafe { abi::table::get_permissions(self.0) })
     }
+
+    /// Returns true if this capability has all of the given permissions.
+    pub fn can(&self, perms: Permissions) -> bool {
+        self.get_flags().contains(perms)
+    }
 }
 
 /// A signal.
@@ -150,6 +190,16 @@ impl Signal {
     }
 }
 
+/// Waits for one of many mailboxes to receive a signal, by handle.
+fn poll_handles(handles: &[u32]) -> (usize, Signal) {
+    let ptr = handles.as_ptr() as u32;
+    let len = handles.len() as u32;
+    let result = unsafe { abi::mailbox::poll(ptr, len) };
+    let index = (result >> 32) as usize;
+    let signal = unsafe { Signal::from_handle(result as u32) };
+    (index, signal)
+}
+
 /// An un-closeable mailbox that receives signals from the parent of this process.
 pub static PARENT: Mailbox = Mailbox(0);
 
@@ -166,6 +216,7 @@ impl Drop for Mailbox {
     fn drop(&mut self) {
         // free this mailbox handle from the host API
         unsafe { abi::mailbox::destroy(self.0) }
+        executor::forget(self.0);
     }
 }
 
@@ -190,8 +241,26 @@ impl Mailbox {
         unsafe { abi::mailbox::monitor(self.0, subject.0) }
     }
 
+    /// Link this mailbox to a process's link endpoint.
+    ///
+    /// When the process exits, this mailbox will receive a JSON-encoded
+    /// [wasm::ProcessDown] message describing why. If the process has
+    /// already exited, the message is sent immediately.
+    pub fn link(&self, endpoint: &Capability) {
+        unsafe { abi::mailbox::link(self.0, endpoint.0) }
+    }
+
+    /// Removes a link previously made with [Mailbox::link].
+    pub fn unlink(&self, endpoint: &Capability) {
+        unsafe { abi::mailbox::unlink(self.0, endpoint.0) }
+    }
+
     /// Wait for this mailbox to receive a [Signal].
     pub fn recv(&self) -> Signal {
+        if let Some(signal) = executor::take_buffered(self.0) {
+            return signal;
+        }
+
         unsafe {
             let handle = abi::mailbox::recv(self.0);
             Signal::from_handle(handle)
@@ -200,6 +269,10 @@ impl Mailbox {
 
     /// Check if this mailbox has received any signals without waiting.
     pub fn try_recv(&self) -> Option<Signal> {
+        if let Some(signal) = executor::take_buffered(self.0) {
+            return Some(signal);
+        }
+
         unsafe {
             let handle = abi::mailbox::try_recv(self.0);
 
@@ -211,15 +284,43 @@ impl Mailbox {
         }
     }
 
+    /// Waits for this mailbox to receive a [Signal], giving up after the
+    /// given timeout.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Signal, Timeout> {
+        if let Some(signal) = executor::take_buffered(self.0) {
+            return Ok(signal);
+        }
+
+        let millis = timeout.as_millis().min(u64::MAX as u128) as u64;
+
+        unsafe {
+            let handle = abi::mailbox::recv_timeout(self.0, millis);
+
+            if handle == u32::MAX {
+                Err(Timeout)
+            } else {
+                Ok(Signal::from_handle(handle))
+            }
+        }
+    }
+
     /// Waits for one of many mailboxes to receive a signal.
     pub fn poll(mailboxes: &[&Self]) -> (usize, Signal) {
+        for (index, mb) in mailboxes.iter().enumerate() {
+            if let Some(signal) = executor::take_buffered(mb.0) {
+                return (index, signal);
+            }
+        }
+
         let handles: Vec<_> = mailboxes.iter().map(|mb| mb.0).collect();
-        let ptr = handles.as_ptr() as u32;
-        let len = handles.len() as u32;
-        let result = unsafe { abi::mailbox::poll(ptr, len) };
-        let index = (result >> 32) as usize;
-        let signal = unsafe { Signal::from_handle(result as u32) };
-        (index, signal)
+        poll_handles(&handles)
+    }
+
+    /// Returns a future for the next [Signal] this mailbox receives.
+    ///
+    /// Drive it with [executor::run_async].
+    pub fn recv_async(&self) -> executor::Recv<'_> {
+        executor::Recv::new(self)
     }
 
     /// Receives a JSON message. Panics if the next signal isn't a message or
@@ -237,8 +338,28 @@ impl Mailbox {
         let data = serde_json::from_slice(&msg.data).unwrap();
         (data, msg.caps)
     }
+
+    /// Receives a message in either JSON or binary format. Panics if the next
+    /// signal isn't a message or if decoding fails.
+    pub fn recv_bin<T>(&self) -> (T, Vec<Capability>)
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        let signal = self.recv();
+
+        let Signal::Message(msg) = signal else {
+            panic!("expected message, received {:?}", signal);
+        };
+
+        let data = codec::decode(&msg.data).unwrap();
+        (data, msg.caps)
+    }
 }
 
+/// The error returned when [Mailbox::recv_timeout] times out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout;
+
 /// A message that has been received from another process.
 #[derive(Clone, Debug)]
 pub struct Message {
@@ -264,6 +385,37 @@ impl Message {
 
         Self { data, caps }
     }
+
+    /// Returns this message as a [shutdown::ShutdownRequest] if it is one
+    /// and it was sent by a holder of the kill permission on this process.
+    ///
+    /// Guests that want to shut down gracefully should check messages to
+    /// [PARENT] with this, then clean up and exit within the grace period.
+    pub fn as_shutdown(&self) -> Option<shutdown::ShutdownRequest> {
+        let request = shutdown::ShutdownRequest::decode(&self.data)?;
+        self.proves_kill(0).then_some(request)
+    }
+
+    /// Tests if the capability at `index` is this process's own [PARENT]
+    /// with the kill permission, proving that the sender may kill it.
+    pub fn proves_kill(&self, index: usize) -> bool {
+        let Some(cap) = self.caps.get(index) else {
+            return false;
+        };
+
+        if !cap.can(Permissions::KILL) {
+            return false;
+        }
+
+        // capabilities to the same mailbox with the same permissions share a
+        // handle, so compare permissionless versions of both
+        let Some(theirs) = cap.try_demote(Permissions::empty()) else {
+            return false;
+        };
+
+        let ours = PARENT.make_capability(Permissions::empty());
+        theirs.0 == ours.0
+    }
 }
 
 /// A loaded lump.
@@ -271,7 +423,8 @@ pub struct Lump(u32);
 
 impl Drop for Lump {
     fn drop(&mut self) {
-        unsafe { abi::lump::free(self.0) }
+        // the handle is owned by this lump, so freeing it can't fail
+        unsafe { abi::lump::free(self.0) };
     }
 }
 
@@ -287,34 +440,45 @@ impl Lump {
     }
 
     /// Loads a lump from the ID of an already existing lump.
-    pub fn load_by_id(id: &LumpId) -> Self {
-        unsafe {
-            let handle = abi::lump::load_by_id(id as *const LumpId as u32);
-            Self(handle)
-        }
+    ///
+    /// Fails with [HearthError::NotFound] if the host doesn't have the
+    /// lump.
+    pub fn load_by_id(id: &LumpId) -> Result<Self, HearthError> {
+        let ret = unsafe { abi::lump::load_by_id(id as *const LumpId as u32) };
+        let handle = abi_result(ret, || format!("couldn't load lump {}", id))?;
+        Ok(Self(handle))
     }
 
     /// Gets the ID of this lump.
     pub fn get_id(&self) -> LumpId {
-        unsafe {
-            let id = LumpId(Default::default());
-            let id_ptr = &id as *const LumpId as u32;
-            abi::lump::get_id(self.0, id_ptr);
-            id
-        }
+        let id = LumpId(Default::default());
+        let id_ptr = &id as *const LumpId as u32;
+        let ret = unsafe { abi::lump::get_id(self.0, id_ptr) };
+        self.expect_loaded(ret);
+        id
     }
 
     /// Retrieves the data stored in this lump.
     pub fn get_data(&self) -> Vec<u8> {
+        let len = self.expect_loaded(unsafe { abi::lump::get_len(self.0) }) as usize;
+
         #[allow(clippy::uninit_vec)]
         unsafe {
-            let len = abi::lump::get_len(self.0) as usize;
             let mut data = Vec::with_capacity(len);
             data.set_len(len);
-            abi::lump::get_data(self.0, data.as_ptr() as u32);
+            self.expect_loaded(abi::lump::get_data(self.0, data.as_ptr() as u32));
             data
         }
     }
+
+    /// Decodes the result of a lump function on this lump's handle. This lump
+    /// owns its handle, so the host always recognizes it.
+    fn expect_loaded(&self, ret: i32) -> u32 {
+        match abi_result(ret, || format!("lump handle {}", self.0)) {
+            Ok(value) => value,
+            Err(err) => panic!("loaded lump rejected by host: {}", err),
+        }
+    }
 }
 
 /// Log a message.
@@ -325,8 +489,83 @@ pub fn log(level: ProcessLogLevel, module: &str, content: &str) {
     unsafe { abi::log::log(level, module_ptr, module_len, content_ptr, content_len) }
 }
 
+/// Returns the version of the host's ABI.
+pub fn abi_version() -> u32 {
+    unsafe { abi::query::version() }
+}
+
+/// Tests if the host provides an optional feature, such as an ABI module by
+/// its name (`"hearth::mailbox"`).
+///
+/// Guests should check for optional features before using them and fall
+/// back to other behavior when they're missing.
+pub fn has_feature(name: &str) -> bool {
+    let (ptr, len) = abi_string(name);
+    unsafe { abi::query::has_feature(ptr, len) != 0 }
+}
+
+/// Creates a new process group and returns a capability to its controller,
+/// which accepts [group::GroupRequest].
+///
+/// The group is nested in this process's group, if it has one. Killing the
+/// controller or exiting this process tears the group down, killing all of its
+/// members.
+///
+/// Requires the `"hearth::group"` feature.
+pub fn create_group() -> Capability {
+    unsafe { Capability::from_handle(abi::group::create()) }
+}
+
+/// Returns a capability to a controller of this process's group, or `None` if
+/// this process isn't in a group.
+///
+/// The controller accepts [group::GroupRequest] but can't tear the group down.
+///
+/// Requires the `"hearth::group"` feature.
+pub fn current_group() -> Option<Capability> {
+    match unsafe { abi::group::current() } {
+        u32::MAX => None,
+        handle => Some(unsafe { Capability::from_handle(handle) }),
+    }
+}
+
+/// Returns a capability to a Wasm process spawner bound to this process.
+///
+/// The spawner accepts the same requests as the
+/// `hearth.wasm.WasmProcessSpawner` service, but spawns children under this
+/// process's ABI policy and records this process as the requester in the
+/// audit log.
+///
+/// Requires the `"hearth::group"` feature.
+pub fn spawner() -> Capability {
+    unsafe { Capability::from_handle(abi::group::spawner()) }
+}
+
 #[allow(clashing_extern_declarations)]
 mod abi {
+    /// Declares the host function imports of an ABI module definition from
+    /// [hearth_schema::abi].
+    macro_rules! import_abi {
+        (
+            []
+            module = $module:literal;
+            $($(#[$meta:meta])* fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*
+        ) => {
+            #[link(wasm_import_module = $module)]
+            extern "C" {
+                $($(#[$meta])* pub fn $name($($arg: $ty),*) $(-> $ret)?;)*
+            }
+        };
+    }
+
+    pub mod query {
+        #[link(wasm_import_module = "hearth::abi")]
+        extern "C" {
+            pub fn version() -> u32;
+            pub fn has_feature(ptr: u32, len: u32) -> u32;
+        }
+    }
+
     pub mod log {
         #[link(wasm_import_module = "hearth::log")]
         extern "C" {
@@ -341,16 +580,7 @@ mod abi {
     }
 
     pub mod lump {
-        #[link(wasm_import_module = "hearth::lump")]
-        extern "C" {
-            pub fn this_lump(ptr: u32);
-            pub fn load_by_id(id_ptr: u32) -> u32;
-            pub fn load(ptr: u32, len: u32) -> u32;
-            pub fn get_id(handle: u32, id_ptr: u32);
-            pub fn get_len(handle: u32) -> u32;
-            pub fn get_data(handle: u32, ptr: u32);
-            pub fn free(handle: u32);
-        }
+        hearth_schema::lump_abi!(import_abi);
     }
 
     pub mod table {
@@ -372,8 +602,11 @@ mod abi {
             pub fn destroy(handle: u32);
             pub fn make_capability(handle: u32, perms: u32) -> u32;
             pub fn monitor(mailbox: u32, cap: u32);
+            pub fn link(mailbox: u32, endpoint: u32);
+            pub fn unlink(mailbox: u32, endpoint: u32);
             pub fn recv(handle: u32) -> u32;
             pub fn try_recv(handle: u32) -> u32;
+            pub fn recv_timeout(handle: u32, timeout_ms: u64) -> u32;
             pub fn poll(handles_ptr: u32, handles_len: u32) -> u64;
             pub fn destroy_signal(handle: u32);
             pub fn get_signal_kind(handle: u32) -> u32;
@@ -384,6 +617,15 @@ mod abi {
             pub fn get_message_caps(handle: u32, dst_ptr: u32);
         }
     }
+
+    pub mod group {
+        #[link(wasm_import_module = "hearth::group")]
+        extern "C" {
+            pub fn create() -> u32;
+            pub fn current() -> u32;
+            pub fn spawner() -> u32;
+        }
+    }
 }
 
 /// Exports this WebAssembly module's process metadata using the calling Cargo
@@ -400,10 +642,40 @@ mod abi {
 /// - `homepage`: a link to the package's homepage.
 /// - `license`: an SPDX license identifier for the package's source code.
 ///
+/// The name, version, and description are also embedded in the module's
+/// [wasm::METADATA_SECTION] along with the [wasm::ABI_VERSION] this crate was
+/// built against, so that hosts can read them before instantiating the module.
+///
+/// Use `export_metadata!(hibernates)` instead if the guest handles
+/// [wasm::HibernationMessage]s, so that the host may hibernate it when it's
+/// idle. Other guests are never asked to hibernate.
+///
 /// See [Cargo's documentation](https://doc.rust-lang.org/cargo/reference/manifest.html#the-package-section) for more info.
 #[macro_export]
 macro_rules! export_metadata {
     () => {
+        $crate::export_metadata!(@hibernates false);
+    };
+    (hibernates) => {
+        $crate::export_metadata!(@hibernates true);
+    };
+    (@hibernates $hibernates:literal) => {
+        const _HEARTH_METADATA_FIELDS: [&str; 3] = [
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION"),
+            env!("CARGO_PKG_DESCRIPTION"),
+        ];
+
+        // must match wasm::METADATA_SECTION
+        #[used]
+        #[link_section = "hearth_metadata"]
+        static _HEARTH_METADATA: [u8; $crate::wasm::encoded_metadata_len(_HEARTH_METADATA_FIELDS)] =
+            $crate::wasm::encode_metadata(
+                $crate::wasm::ABI_VERSION,
+                _HEARTH_METADATA_FIELDS,
+                $hibernates,
+            );
+
         #[no_mangle]
         extern "C" fn _hearth_metadata() {
             // define the ABI functions in the function since we only use them here
@@ -483,6 +755,16 @@ extern "C" fn _hearth_init() {
         let log_message = format!("panicked at '{msg}', {location}");
         log(ProcessLogLevel::Error, "panic", &log_message);
     }));
+
+    // fail early and readably instead of on the first missing host call
+    let host_version = abi_version();
+    if host_version < wasm::ABI_VERSION {
+        panic!(
+            "this guest requires host ABI version {}, but the host provides version {}",
+            wasm::ABI_VERSION,
+            host_version
+        );
+    }
 }
 
 #[no_mangle]
@@ -26,6 +26,9 @@ fn spawn(spawner: &Process, cb: fn()) -> Process {
         &WasmSpawnInfo {
             lump: hearth_guest::this_lump(),
             entrypoint: Some(unsafe { std::mem::transmute::<fn(), usize>(cb) } as u32),
+            limits: Default::default(),
+            keep_awake: false,
+            allow: None,
         },
     );
 
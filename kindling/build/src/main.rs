@@ -18,13 +18,62 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    path::Path,
-    process::{Command, Stdio},
+    path::{Path, PathBuf},
+    process::{Command, ExitCode, Stdio},
+    sync::mpsc::channel,
+    time::Duration,
 };
 
-use cargo_metadata::Package;
+use cargo_metadata::{Metadata, Package};
+use clap::Parser;
+use notify::{RecursiveMode, Watcher};
+
+mod postprocess;
+
+/// The name of the init system's package, which is always built to `init.wasm`.
+const INIT_PACKAGE: &str = "kindling-init";
+
+/// How long to wait for more source changes before rebuilding in watch mode.
+const DEBOUNCE_WINDOW: Duration = Duration::from_millis(200);
+
+/// Builds the kindling services into a Hearth init directory.
+#[derive(Parser, Debug)]
+pub struct Args {
+    /// Only build the package or service with this name.
+    #[clap(short, long)]
+    pub package: Option<String>,
+
+    /// Print the discovered services and their output paths without building.
+    #[clap(long)]
+    pub list: bool,
+
+    /// Rebuild whenever the kindling sources change.
+    #[clap(long)]
+    pub watch: bool,
+
+    /// Stop at the first package that fails to build.
+    #[clap(long)]
+    pub fail_fast: bool,
+
+    /// Don't run wasm-opt on built modules, even if it's installed.
+    #[clap(long)]
+    pub no_wasm_opt: bool,
+}
+
+/// A wasm module to build and the path to copy it to.
+struct Target<'a> {
+    package: &'a Package,
+
+    /// The directory to create the output in, if it's a service.
+    service_dir: Option<PathBuf>,
+
+    /// The path of the built module.
+    module_path: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
 
-fn main() {
     let metadata = cargo_metadata::MetadataCommand::new()
         .current_dir(get_kindling_dir())
         .exec()
@@ -32,10 +81,127 @@ fn main() {
 
     let target_path = metadata.target_directory.as_std_path().to_owned();
     let root_path = target_path.join("kindling-root");
+    let targets = get_targets(&metadata, &root_path);
+
+    let targets: Vec<_> = match args.package.as_ref() {
+        None => targets,
+        Some(name) => {
+            let targets: Vec<_> = targets
+                .into_iter()
+                .filter(|target| {
+                    target.package.name == *name
+                        || get_service_name(target.package) == Some(name.as_str())
+                })
+                .collect();
+
+            if targets.is_empty() {
+                eprintln!("no package or service named {:?}", name);
+                return ExitCode::FAILURE;
+            }
+
+            targets
+        }
+    };
+
+    if args.list {
+        for target in targets.iter() {
+            let name = get_service_name(target.package).unwrap_or("(init)");
+            println!(
+                "{}\t{}\t{}",
+                target.package.name,
+                name,
+                target.module_path.display()
+            );
+        }
 
-    let is_clean = touch_dir(&root_path);
+        return ExitCode::SUCCESS;
+    }
+
+    let succeeded = build_all(&root_path, &targets, &args);
+
+    if !args.watch {
+        return if succeeded {
+            ExitCode::SUCCESS
+        } else {
+            ExitCode::FAILURE
+        };
+    }
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::recommended_watcher(move |event| {
+        let _ = tx.send(event);
+    })
+    .expect("failed to create source watcher");
+
+    watcher
+        .watch(Path::new(&get_kindling_dir()), RecursiveMode::Recursive)
+        .expect("failed to watch kindling sources");
 
-    build_wasm("kindling-init", &root_path.join("init.wasm"), is_clean);
+    eprintln!("watching for changes");
+
+    while let Ok(event) = rx.recv() {
+        // ignore our own build outputs
+        let is_source = |event: &notify::Event| {
+            event
+                .paths
+                .iter()
+                .any(|path| !path.starts_with(&target_path))
+        };
+
+        let mut changed = matches!(event, Ok(ref event) if is_source(event));
+
+        // collect the rest of a burst of changes into one rebuild
+        while let Ok(event) = rx.recv_timeout(DEBOUNCE_WINDOW) {
+            changed |= matches!(event, Ok(ref event) if is_source(event));
+        }
+
+        if changed {
+            eprintln!("sources changed; rebuilding");
+            build_all(&root_path, &targets, &args);
+        }
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// Builds every target, reporting all failures at the end.
+///
+/// Returns true if every target built successfully.
+fn build_all(root_path: &Path, targets: &[Target], args: &Args) -> bool {
+    let is_clean = touch_dir(root_path);
+    let mut failures = Vec::new();
+
+    for target in targets.iter() {
+        let result = match target.service_dir.as_ref() {
+            Some(service_dir) => build_service(service_dir, target, is_clean, args),
+            None => build_wasm(&target.package.name, &target.module_path, is_clean, args),
+        };
+
+        if let Err(err) = result {
+            eprintln!("failed to build {:?}: {}", target.package.name, err);
+            failures.push((target.package.name.as_str(), err));
+
+            if args.fail_fast {
+                break;
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        return true;
+    }
+
+    eprintln!("{} package(s) failed to build:", failures.len());
+    for (package, err) in failures {
+        eprintln!("  {}: {}", package, err);
+    }
+
+    false
+}
+
+/// Finds the init system and every service in the kindling workspace.
+fn get_targets<'a>(metadata: &'a Metadata, root_path: &Path) -> Vec<Target<'a>> {
+    let mut targets = Vec::new();
 
     for package_id in metadata.workspace_members.iter() {
         let package = &metadata[package_id];
@@ -53,15 +219,35 @@ fn main() {
             continue;
         }
 
-        build_service(&root_path, package);
+        if package.name == INIT_PACKAGE {
+            targets.push(Target {
+                package,
+                service_dir: None,
+                module_path: root_path.join("init.wasm"),
+            });
+        } else if let Some(name) = get_service_name(package) {
+            let service_dir = root_path.join("init").join(name);
+
+            targets.push(Target {
+                package,
+                module_path: service_dir.join("service.wasm"),
+                service_dir: Some(service_dir),
+            });
+        }
     }
+
+    targets
 }
 
 /// Returns true if the directory is freshly created.
 fn touch_dir(path: &Path) -> bool {
     eprintln!("touching directory {:?}", path);
-    // TODO ignore already exists error, catch everything else
-    let _ = std::fs::create_dir_all(path);
+
+    if path.is_dir() {
+        return false;
+    }
+
+    std::fs::create_dir_all(path).expect("failed to create directory");
     true
 }
 
@@ -74,37 +260,91 @@ fn get_kindling_dir() -> String {
         + "kindling/"
 }
 
-fn build_service(root_path: &Path, package: &Package) {
-    if let Some(service) = package.metadata.get("service") {
-        let name = service.get("name").unwrap().as_str().unwrap();
-        let service_path = root_path.join("init").join(name);
-        let is_clean = touch_dir(&service_path);
-        let module_path = service_path.join("service.wasm");
-        build_wasm(&package.name, &module_path, is_clean);
+fn get_service_name(package: &Package) -> Option<&str> {
+    let service = package.metadata.get("service")?;
+    Some(service.get("name")?.as_str().unwrap())
+}
+
+fn build_service(
+    service_path: &Path,
+    target: &Target,
+    force_copy: bool,
+    args: &Args,
+) -> Result<(), String> {
+    let package = target.package;
+    let service = package.metadata.get("service").unwrap();
+    let is_clean = touch_dir(service_path) || force_copy;
+    build_wasm(&package.name, &target.module_path, is_clean, args)?;
 
-        let mut config = toml::Table::new();
+    let mut config = toml::Table::new();
 
-        if let Some(description) = package.description.clone() {
-            config.insert("description".into(), description.into());
-        }
+    if let Some(description) = package.description.clone() {
+        config.insert("description".into(), description.into());
+    }
 
-        let targets: Vec<String> = service
-            .get("targets")
-            .map(|targets| targets.as_array().unwrap().clone())
-            .unwrap_or_default()
-            .into_iter()
-            .map(|target| target.as_str().unwrap().to_string())
-            .collect();
+    config.insert("targets".into(), get_strings(service, "targets").into());
+    config.insert("depends".into(), get_strings(service, "depends").into());
 
-        config.insert("targets".into(), targets.into());
+    if let Some(timeout) = service.get("depends_timeout") {
+        let timeout = timeout.as_f64().expect("depends_timeout must be a number");
+        config.insert("depends_timeout".into(), timeout.into());
+    }
 
-        let config = toml::to_string_pretty(&config).unwrap();
-        let config_path = service_path.join("service.toml");
-        std::fs::write(config_path, config.as_bytes()).unwrap();
+    if let Some(on_timeout) = service.get("on_timeout") {
+        let on_timeout = on_timeout.as_str().expect("on_timeout must be a string");
+        config.insert("on_timeout".into(), on_timeout.into());
     }
+
+    if let Some(writes_files) = service.get("writes_files") {
+        let writes_files = writes_files
+            .as_bool()
+            .expect("writes_files must be a boolean");
+        config.insert("writes_files".into(), writes_files.into());
+    }
+
+    if let Some(args) = service.get("args") {
+        let args = args.as_bool().expect("args must be a boolean");
+        config.insert("args".into(), args.into());
+    }
+
+    // the default deployment config, which deployments may edit
+    if let Some(service_config) = service.get("config") {
+        let service_config = toml::Value::try_from(service_config)
+            .map_err(|err| format!("invalid service config: {}", err))?;
+        config.insert("config".into(), service_config);
+    }
+
+    let config = toml::to_string_pretty(&config).unwrap();
+    let config_path = service_path.join("service.toml");
+    write_if_changed(config.as_bytes(), &config_path, is_clean)?;
+    Ok(())
+}
+
+/// Gets a list of strings from a service's metadata, defaulting to empty.
+fn get_strings(service: &serde_json::Value, key: &str) -> Vec<String> {
+    service
+        .get(key)
+        .map(|values| values.as_array().unwrap().clone())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|value| value.as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Writes `data` to `path` unless the file already has those contents or
+/// `force` is set.
+///
+/// Returns true if the file was written.
+fn write_if_changed(data: &[u8], path: &Path, force: bool) -> Result<bool, String> {
+    if !force && matches!(std::fs::read(path), Ok(old) if old == data) {
+        return Ok(false);
+    }
+
+    std::fs::write(path, data).map_err(|err| format!("failed to write {:?}: {}", path, err))?;
+    Ok(true)
 }
 
-fn build_wasm(package: &str, path: &Path, force_copy: bool) {
+fn build_wasm(package: &str, path: &Path, force_copy: bool, args: &Args) -> Result<(), String> {
     let mut command = Command::new(get_cargo());
     command
         .current_dir(get_kindling_dir())
@@ -124,19 +364,56 @@ fn build_wasm(package: &str, path: &Path, force_copy: bool) {
     let reader = std::io::BufReader::new(child.stdout.take().unwrap());
     for message in cargo_metadata::Message::parse_stream(reader) {
         use cargo_metadata::Message;
-        if let Message::CompilerArtifact(artifact) = message.unwrap() {
+        let message = message.map_err(|err| format!("failed to read cargo output: {}", err))?;
+        if let Message::CompilerArtifact(artifact) = message {
             for file in artifact.filenames {
                 if file.as_str().ends_with(".wasm") {
-                    if artifact.fresh && !force_copy {
+                    let data = std::fs::read(&file)
+                        .map_err(|err| format!("failed to read {:?}: {}", file, err))?;
+
+                    postprocess::validate(&data)
+                        .map_err(|err| format!("{:?} failed validation: {}", file, err))?;
+
+                    // cargo doesn't know whether our copy is stale, so
+                    // compare contents even if the artifact is fresh
+                    let debug_path = path.with_extension("debug.wasm");
+                    if !write_if_changed(&data, &debug_path, force_copy)? && path.exists() {
+                        eprintln!("{:?} is up to date", path);
                         continue;
                     }
 
-                    eprintln!("copying {:?} to {:?}", file, path);
-                    std::fs::copy(file, path).unwrap();
+                    let scratch_dir = path.parent().unwrap();
+                    let optimized = if args.no_wasm_opt {
+                        None
+                    } else {
+                        let optimized = postprocess::optimize(&data, scratch_dir)?;
+
+                        if optimized.is_none() {
+                            eprintln!("wasm-opt is not installed; skipping optimization");
+                        }
+
+                        optimized
+                    };
+
+                    let shipped = postprocess::strip(optimized.as_deref().unwrap_or(&data));
+                    write_if_changed(&shipped, path, true)?;
+
+                    eprintln!(
+                        "{}: {} bytes -> {} bytes ({:?})",
+                        package,
+                        data.len(),
+                        shipped.len(),
+                        path
+                    );
                 }
             }
         }
     }
 
-    child.wait().unwrap();
+    let status = child.wait().expect("failed to wait on cargo");
+    if !status.success() {
+        return Err(format!("cargo exited with {}", status));
+    }
+
+    Ok(())
 }
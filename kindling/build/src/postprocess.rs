@@ -0,0 +1,170 @@
+// Copyright (c) 2023 the Hearth contributors.
+// Copyright (c) 2023 Marceline Cramer
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Validation and size optimization of built wasm modules.
+
+use std::{io::ErrorKind, path::Path, process::Command};
+
+use hearth_schema::wasm::METADATA_SECTION;
+use wasmparser::{ExternalKind, Parser, Payload, Validator};
+
+/// The prefix of every import module that the Hearth host provides.
+const HOST_NAMESPACE: &str = "hearth::";
+
+/// The exports that every module must have, with their kinds.
+const REQUIRED_EXPORTS: &[(&str, ExternalKind)] = &[
+    ("memory", ExternalKind::Memory),
+    ("run", ExternalKind::Func),
+];
+
+/// The ID of custom sections in the wasm binary format.
+const CUSTOM_SECTION_ID: u8 = 0;
+
+/// Checks that a module is valid, exports what the host expects, and only
+/// imports from the host's ABI.
+pub fn validate(data: &[u8]) -> Result<(), String> {
+    Validator::new()
+        .validate_all(data)
+        .map_err(|err| format!("invalid module: {}", err))?;
+
+    let mut exports = Vec::new();
+
+    for payload in Parser::new(0).parse_all(data) {
+        match payload.map_err(|err| err.to_string())? {
+            Payload::ImportSection(reader) => {
+                for import in reader {
+                    let import = import.map_err(|err| err.to_string())?;
+                    if !import.module.starts_with(HOST_NAMESPACE) {
+                        return Err(format!(
+                            "disallowed import {:?} from module {:?}",
+                            import.name, import.module
+                        ));
+                    }
+                }
+            }
+            Payload::ExportSection(reader) => {
+                for export in reader {
+                    let export = export.map_err(|err| err.to_string())?;
+                    exports.push((export.name.to_string(), export.kind));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for (name, kind) in REQUIRED_EXPORTS.iter() {
+        let found = exports.iter().find(|(export, _)| export == name);
+        match found {
+            None => return Err(format!("missing export {:?}", name)),
+            Some((_, found)) if found != kind => {
+                return Err(format!(
+                    "export {:?} is a {:?}, expected a {:?}",
+                    name, found, kind
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs `wasm-opt -Os` on a module.
+///
+/// Returns `Ok(None)` if wasm-opt isn't installed.
+pub fn optimize(data: &[u8], scratch_dir: &Path) -> Result<Option<Vec<u8>>, String> {
+    let input = scratch_dir.join("wasm-opt-input.wasm");
+    let output = scratch_dir.join("wasm-opt-output.wasm");
+    std::fs::write(&input, data).map_err(|err| format!("failed to write {:?}: {}", input, err))?;
+
+    let result = Command::new("wasm-opt")
+        .arg("-Os")
+        .arg(&input)
+        .arg("-o")
+        .arg(&output)
+        .status();
+
+    let status = match result {
+        Ok(status) => status,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("failed to run wasm-opt: {}", err)),
+    };
+
+    if !status.success() {
+        return Err(format!("wasm-opt exited with {}", status));
+    }
+
+    let optimized =
+        std::fs::read(&output).map_err(|err| format!("failed to read {:?}: {}", output, err))?;
+
+    let _ = std::fs::remove_file(input);
+    let _ = std::fs::remove_file(output);
+
+    Ok(Some(optimized))
+}
+
+/// Removes every custom section from a module except for its
+/// [METADATA_SECTION], which the host reads.
+///
+/// Assumes that the module has already been validated.
+pub fn strip(data: &[u8]) -> Vec<u8> {
+    // keep the magic number and version
+    let mut stripped = data[..8].to_vec();
+    let mut offset = 8;
+
+    while offset < data.len() {
+        let start = offset;
+        let id = data[offset];
+        offset += 1;
+
+        let (size, len) = read_leb128(&data[offset..]);
+        offset += len;
+        let contents = &data[offset..offset + size as usize];
+        offset += size as usize;
+
+        if id != CUSTOM_SECTION_ID || custom_section_name(contents) == METADATA_SECTION.as_bytes() {
+            stripped.extend_from_slice(&data[start..offset]);
+        }
+    }
+
+    stripped
+}
+
+/// Gets the name of a custom section from its contents.
+fn custom_section_name(contents: &[u8]) -> &[u8] {
+    let (len, offset) = read_leb128(contents);
+    &contents[offset..offset + len as usize]
+}
+
+/// Reads an unsigned LEB128 integer, returning it and its encoded length.
+fn read_leb128(data: &[u8]) -> (u32, usize) {
+    let mut value = 0;
+    let mut shift = 0;
+
+    for (index, byte) in data.iter().enumerate() {
+        value |= ((byte & 0x7f) as u32) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            return (value, index + 1);
+        }
+    }
+
+    panic!("unterminated LEB128 integer");
+}
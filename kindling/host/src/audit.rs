@@ -0,0 +1,73 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use hearth_guest::audit::*;
+
+/// A wrapper around the audit service capability.
+pub struct AuditLog {
+    service: RequestResponse<AuditRequest, AuditResponse>,
+}
+
+impl AuditLog {
+    /// Wraps an audit service capability.
+    ///
+    /// The init system receives this capability as its second initial
+    /// capability, after the registry.
+    pub fn from_capability(cap: Capability) -> Self {
+        Self {
+            service: RequestResponse::new(cap),
+        }
+    }
+
+    /// Gets the audit service from the registry, if it's registered
+    /// publicly.
+    pub fn from_registry() -> Option<Self> {
+        registry::REGISTRY
+            .get_service(AUDIT_SERVICE)
+            .map(Self::from_capability)
+    }
+
+    /// Gets the recent events matching a filter, oldest first.
+    ///
+    /// If `limit` is set, only the newest `limit` events are returned.
+    pub fn query(&self, filter: AuditFilter, limit: Option<u32>) -> Vec<AuditEvent> {
+        let (resp, _) = self
+            .service
+            .request(AuditRequest::Query { filter, limit }, &[]);
+
+        match resp {
+            Ok(AuditSuccess::Events(events)) => events,
+            other => panic!("unexpected audit response: {:?}", other),
+        }
+    }
+
+    /// Subscribes a mailbox to new events matching a filter. Receive
+    /// [AuditEvent]s with [Mailbox::recv_json].
+    ///
+    /// The subscription ends once the mailbox is dropped.
+    pub fn subscribe(&self, filter: AuditFilter, mailbox: &Mailbox) -> Result<(), AuditError> {
+        let cap = mailbox.make_capability(Permissions::SEND);
+        let (resp, _) = self
+            .service
+            .request(AuditRequest::Subscribe(filter), &[&cap]);
+
+        resp.map(|_| ())
+    }
+}
@@ -33,36 +33,58 @@ pub struct Canvas {
 }
 
 impl Canvas {
-    /// Creates a new Canvas.
+    /// Creates a new Canvas with RGBA pixel data.
     ///
-    /// Panics if the factory responds with an error.
+    /// Panics if the factory responds with an error or doesn't respond.
     pub fn new(position: Position, pixels: Pixels, sampling: CanvasSamplingMode) -> Self {
-        let resp = CANVAS_FACTORY.request(
-            FactoryRequest::CreateCanvas {
-                position,
-                pixels,
-                sampling,
-            },
-            &[],
-        );
-        let _ = resp.0.unwrap();
+        Self::with_format(position, pixels, sampling, PixelFormat::Rgba8)
+    }
+
+    /// Creates a new Canvas whose pixel data is sent in the given format.
+    ///
+    /// Panics if the factory responds with an error or doesn't respond.
+    pub fn with_format(
+        position: Position,
+        pixels: Pixels,
+        sampling: CanvasSamplingMode,
+        format: PixelFormat,
+    ) -> Self {
+        let request = FactoryRequest::CreateCanvas {
+            position,
+            pixels,
+            sampling,
+            format,
+        };
+
+        let (result, caps) = CANVAS_FACTORY
+            .try_request(request, &[], DEFAULT_REQUEST_TIMEOUT)
+            .expect("canvas factory did not respond");
+
+        let _ = result.unwrap();
         Canvas {
-            cap: resp.1.get(0).unwrap().clone(),
+            cap: caps.get(0).unwrap().clone(),
         }
     }
 
     /// Update this canvas with a new buffer of pixels to draw.
     pub fn update(&self, buffer: Pixels) {
-        self.cap.send_json(&CanvasUpdate::Resize(buffer), &[]);
+        self.cap.send_bin(&CanvasUpdate::Resize(buffer), &[]);
     }
 
     /// Move this canvas to a new position in 3D space.
     pub fn relocate(&self, position: Position) {
-        self.cap.send_json(&CanvasUpdate::Relocate(position), &[])
+        self.cap.send_bin(&CanvasUpdate::Relocate(position), &[])
     }
 
     /// Blit a recatangular buffer to a part of this canvas.
     pub fn blit(&self, blit: Blit) {
-        self.cap.send_json(&CanvasUpdate::Blit(blit), &[])
+        self.cap.send_bin(&CanvasUpdate::Blit(blit), &[])
+    }
+
+    /// Blit a rectangular buffer to a part of this canvas and wait for the
+    /// canvas to accept it.
+    pub fn try_blit(&self, blit: Blit) -> CanvasResponse {
+        let canvas = RequestResponse::<CanvasUpdate, CanvasResponse>::new(self.cap.clone());
+        canvas.request(CanvasUpdate::Blit(blit), &[]).0
     }
 }
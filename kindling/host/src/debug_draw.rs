@@ -33,7 +33,7 @@ pub struct DebugDraw {
 
 impl Drop for DebugDraw {
     fn drop(&mut self) {
-        self.cap.send_json(&DebugDrawUpdate::Destroy, &[]);
+        self.cap.send_bin(&DebugDrawUpdate::Destroy, &[]);
     }
 }
 
@@ -60,16 +60,28 @@ impl DebugDraw {
 
     /// Hide this debug draw mesh.
     pub fn hide(&self) {
-        self.cap.send_json(&DebugDrawUpdate::Hide(true), &[]);
+        self.cap.send_bin(&DebugDrawUpdate::Hide(true), &[]);
     }
 
     /// Show this debug draw mesh.
     pub fn show(&self) {
-        self.cap.send_json(&DebugDrawUpdate::Hide(false), &[]);
+        self.cap.send_bin(&DebugDrawUpdate::Hide(false), &[]);
     }
 
     /// Update the contents of this debug draw mesh.
     pub fn update(&self, mesh: DebugDrawMesh) {
-        self.cap.send_json(&DebugDrawUpdate::Contents(mesh), &[]);
+        self.cap.send_bin(&DebugDrawUpdate::Contents(mesh), &[]);
+    }
+
+    /// Add a shape to this debug draw mesh.
+    ///
+    /// If `ttl` is set, the shape is removed after that many seconds.
+    pub fn add(&self, shape: DebugDrawShape, ttl: Option<f32>) {
+        self.cap.send_bin(&DebugDrawUpdate::Add { shape, ttl }, &[]);
+    }
+
+    /// Remove all contents of this debug draw mesh.
+    pub fn clear(&self) {
+        self.cap.send_bin(&DebugDrawUpdate::Clear, &[]);
     }
 }
@@ -23,7 +23,7 @@ use hearth_guest::{fs::*, Lump, LumpId};
 
 lazy_static::lazy_static! {
     static ref FILESYSTEM: RequestResponse<Request, Response> = {
-        RequestResponse::new(registry::REGISTRY.get_service("hearth.fs.Filesystem").unwrap())
+        RequestResponse::new(registry::REGISTRY.get_service(FILESYSTEM_SERVICE).unwrap())
     };
 }
 
@@ -47,7 +47,7 @@ pub fn get_file(path: &str) -> Result<LumpId, Error> {
 /// Read the bytes of a file into a `Vec<u8>`.
 pub fn read_file(path: &str) -> Result<Vec<u8>, Error> {
     let lump = get_file(path)?;
-    let lump = Lump::load_by_id(&lump);
+    let lump = Lump::load_by_id(&lump).map_err(|err| Error::Other(err.to_string()))?;
     Ok(lump.get_data())
 }
 
@@ -67,3 +67,85 @@ pub fn list_files(path: &str) -> Result<Vec<FileInfo>, Error> {
         _ => panic!("expected Success::List, got {:?}", success),
     }
 }
+
+/// Send a request that modifies the filesystem and expects [Success::Done].
+fn modify(path: &str, kind: RequestKind) -> Result<(), Error> {
+    let request = Request {
+        target: path.to_string(),
+        kind,
+    };
+
+    let success = FILESYSTEM.request(request, &[]).0?;
+    match success {
+        Success::Done => Ok(()),
+        _ => panic!("expected Success::Done, got {:?}", success),
+    }
+}
+
+/// Write bytes to a file, replacing it if it exists.
+pub fn write_file(path: &str, data: &[u8]) -> Result<(), Error> {
+    let lump = Lump::load(data);
+    modify(path, RequestKind::Write(lump.get_id()))
+}
+
+/// Create a directory and any of its missing parents.
+pub fn create_dir(path: &str) -> Result<(), Error> {
+    modify(path, RequestKind::CreateDir)
+}
+
+/// Delete a file or an empty directory.
+pub fn delete(path: &str) -> Result<(), Error> {
+    modify(path, RequestKind::Delete)
+}
+
+/// Move a file or directory to a new path.
+pub fn rename(from: &str, to: &str) -> Result<(), Error> {
+    modify(from, RequestKind::Rename { to: to.to_string() })
+}
+
+/// Get a read-only capability to the filesystem to hand out to other
+/// processes.
+pub fn get_read_only() -> Result<Capability, Error> {
+    let request = Request {
+        target: String::new(),
+        kind: RequestKind::ReadOnly,
+    };
+
+    let (success, caps) = FILESYSTEM.request(request, &[]);
+    match success? {
+        Success::ReadOnly => Ok(caps.into_iter().next().unwrap()),
+        success => panic!("expected Success::ReadOnly, got {:?}", success),
+    }
+}
+
+/// A subscription to changes to a file or directory.
+///
+/// Watching stops when this is dropped.
+pub struct Watcher {
+    mailbox: Mailbox,
+}
+
+impl Watcher {
+    /// Start watching a path. If `recursive` is set, changes inside of
+    /// subdirectories are watched too.
+    pub fn new(path: &str, recursive: bool) -> Result<Self, Error> {
+        let mailbox = Mailbox::new();
+        let subscriber = mailbox.make_capability(Permissions::SEND);
+
+        let request = Request {
+            target: path.to_string(),
+            kind: RequestKind::Watch { recursive },
+        };
+
+        let success = FILESYSTEM.request(request, &[&subscriber]).0?;
+        match success {
+            Success::Watching => Ok(Self { mailbox }),
+            _ => panic!("expected Success::Watching, got {:?}", success),
+        }
+    }
+
+    /// Wait for the next change.
+    pub fn recv(&self) -> WatchEvent {
+        self.mailbox.recv_json().0
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use hearth_guest::kv::*;
+
+lazy_static::lazy_static! {
+    static ref KV_STORE: RequestResponse<KvFactoryRequest, KvFactoryResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(KV_STORE_SERVICE).unwrap())
+    };
+}
+
+/// A wrapper around a namespace of the host's persistent key-value store.
+pub struct KvStore(RequestResponse<KvRequest, KvResponse>);
+
+impl KvStore {
+    /// Creates a new namespace of the key-value store. Returns it along with
+    /// the key to open it again with.
+    pub fn create() -> Result<(Self, String), KvError> {
+        Self::request_namespace(KvFactoryRequest::CreateNamespace)
+    }
+
+    /// Opens an existing namespace of the key-value store by its key.
+    pub fn open(key: &str) -> Result<Self, KvError> {
+        let request = KvFactoryRequest::OpenNamespace {
+            key: key.to_string(),
+        };
+
+        Self::request_namespace(request).map(|(store, _)| store)
+    }
+
+    fn request_namespace(request: KvFactoryRequest) -> Result<(Self, String), KvError> {
+        let (result, caps) = KV_STORE.request(request, &[]);
+        let key = result?;
+        let store = Self(RequestResponse::new(caps.get(0).unwrap().clone()));
+        Ok((store, key))
+    }
+
+    /// Gets the value of a key.
+    pub fn get(&self, key: impl Into<Vec<u8>>) -> Result<Option<Vec<u8>>, KvError> {
+        match self.request(KvRequest::Get { key: key.into() })? {
+            KvSuccess::Value(value) => Ok(value),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    /// Sets the value of a key.
+    pub fn set(&self, key: impl Into<Vec<u8>>, value: impl Into<Vec<u8>>) -> Result<(), KvError> {
+        self.request(KvRequest::Set {
+            key: key.into(),
+            value: value.into(),
+        })
+        .map(drop)
+    }
+
+    /// Deletes a key.
+    pub fn delete(&self, key: impl Into<Vec<u8>>) -> Result<(), KvError> {
+        self.request(KvRequest::Delete { key: key.into() })
+            .map(drop)
+    }
+
+    /// Lists all keys beginning with a prefix.
+    pub fn list(&self, prefix: impl Into<Vec<u8>>) -> Result<Vec<Vec<u8>>, KvError> {
+        match self.request(KvRequest::List {
+            prefix: prefix.into(),
+        })? {
+            KvSuccess::Keys(keys) => Ok(keys),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    /// Replaces a key's value if it currently equals `expected`.
+    ///
+    /// `None` stands for a missing key. On mismatch, returns
+    /// [KvError::CompareFailed] with the current value.
+    pub fn compare_and_swap(
+        &self,
+        key: impl Into<Vec<u8>>,
+        expected: Option<Vec<u8>>,
+        new: Option<Vec<u8>>,
+    ) -> Result<(), KvError> {
+        self.request(KvRequest::CompareAndSwap {
+            key: key.into(),
+            expected,
+            new,
+        })
+        .map(drop)
+    }
+
+    fn request(&self, request: KvRequest) -> KvResponse {
+        self.0.request(request, &[]).0
+    }
+}
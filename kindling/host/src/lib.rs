@@ -17,15 +17,20 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use std::marker::PhantomData;
+use std::time::Duration;
 
-use hearth_guest::{Capability, Mailbox, Permissions};
+use hearth_guest::{Capability, Mailbox, Permissions, Signal};
 use serde::{Deserialize, Serialize};
 
 pub use glam;
 
+pub mod audit;
 pub mod canvas;
 pub mod debug_draw;
 pub mod fs;
+pub mod kv;
+pub mod migrate;
+pub mod pubsub;
 pub mod registry;
 pub mod terminal;
 pub mod time;
@@ -40,20 +45,38 @@ pub mod window;
 /// use kindling_host::prelude::*;
 /// ```
 pub mod prelude {
+    pub use hearth_guest::has_feature;
+
     pub use crate::{
         canvas::Canvas,
         debug_draw::DebugDraw,
         fs::{get_file, list_files, read_file},
         glam,
+        kv::KvStore,
+        pubsub::Topic,
         registry::REGISTRY,
         terminal::Terminal,
-        time::{sleep, Stopwatch, Timer},
-        wasm::{spawn_fn, spawn_mod},
-        window::MAIN_WINDOW,
+        time::{sleep, sleep_async, ScheduledMessage, Stopwatch, Timer},
+        wasm::{spawn_fn, spawn_fn_linked, spawn_mod},
+        window::{get_clipboard, set_clipboard, Focusable, MAIN_WINDOW},
         RequestResponse, {debug, error, info, log, trace, warning},
     };
 }
 
+/// The timeout this crate's wrappers use when waiting for a service to
+/// respond.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An error from [RequestResponse::try_request].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestError {
+    /// No response arrived before the timeout.
+    Timeout,
+
+    /// The service went down before responding.
+    Down,
+}
+
 /// A helper struct for request-response capabilities.
 pub struct RequestResponse<Request, Response> {
     cap: Capability,
@@ -97,6 +120,33 @@ where
 
         reply.recv_json()
     }
+
+    /// Perform a request on this capability, waiting at most `timeout` for
+    /// the response.
+    ///
+    /// Panics if the response can't be deserialized.
+    pub fn try_request(
+        &self,
+        request: Request,
+        args: &[&Capability],
+        timeout: Duration,
+    ) -> Result<(Response, Vec<Capability>), RequestError> {
+        let reply = Mailbox::new();
+        let reply_cap = reply.make_capability(Permissions::SEND);
+        reply.monitor(&self.cap);
+
+        let mut caps = Vec::with_capacity(args.len() + 1);
+        caps.push(&reply_cap);
+        caps.extend_from_slice(args);
+
+        self.cap.send_json(&request, caps.as_slice());
+
+        match reply.recv_timeout(timeout) {
+            Ok(Signal::Message(msg)) => Ok((serde_json::from_slice(&msg.data).unwrap(), msg.caps)),
+            Ok(Signal::Down { .. }) => Err(RequestError::Down),
+            Err(_) => Err(RequestError::Timeout),
+        }
+    }
 }
 
 /// Takes a `ProcessLogLevel` and a format string and prints it to the terminal.
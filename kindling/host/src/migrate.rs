@@ -0,0 +1,253 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use std::collections::VecDeque;
+
+use hearth_guest::{wasm::*, Lump, LumpId, Message, Timeout, PARENT};
+use serde::de::DeserializeOwned;
+
+lazy_static::lazy_static! {
+    static ref MIGRATOR: RequestResponse<MigrateRequest, MigrateResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(MIGRATOR_SERVICE).unwrap())
+    };
+}
+
+/// Moves a [Migratable] process to another peer.
+///
+/// `process` must have the kill permission. `args` are passed on to the new
+/// process; if `services` is not empty, the first of them must be the
+/// registry to re-register the new process in. Returns a capability to the
+/// new process.
+pub fn migrate(
+    process: &Capability,
+    peer: &str,
+    spawn: WasmSpawnInfo,
+    services: Vec<String>,
+    args: &[&Capability],
+) -> Result<Capability, String> {
+    let request = MigrateRequest {
+        peer: peer.to_string(),
+        spawn,
+        services,
+    };
+
+    let mut caps = vec![process];
+    caps.extend_from_slice(args);
+    let (resp, caps) = MIGRATOR.request(request, &caps);
+    resp?;
+    Ok(caps.into_iter().next().unwrap())
+}
+
+/// What a [Migratable] does with incoming messages.
+enum Mode {
+    /// Messages are returned to the process.
+    Running,
+
+    /// A snapshot was taken, so messages are held back until the migration
+    /// finishes or is aborted.
+    Held,
+
+    /// The process has moved, so messages are forwarded to its new instance.
+    Forwarding(Capability),
+}
+
+/// Process state that can be moved to another peer by the migrator service.
+///
+/// Wraps the state of a process and receives its messages from [PARENT],
+/// handling [MigrationMessage]s along the way. Everything the process needs
+/// to carry on elsewhere must be kept in the state, since nothing else
+/// survives a migration.
+///
+/// While a migration is in progress, [Migratable::recv] and
+/// [Migratable::recv_timeout] block until it finishes, so the state can't
+/// change after it's been saved. If the migration succeeds, messages are
+/// forwarded to the new instance until this one is killed.
+pub struct Migratable<T> {
+    state: T,
+    mode: Mode,
+    held: VecDeque<Message>,
+    started: bool,
+}
+
+impl<T: Serialize + DeserializeOwned> Migratable<T> {
+    /// Wraps the initial state of a process.
+    ///
+    /// If this process is the new instance of a migration, the state is
+    /// replaced by the saved state when its first message is received.
+    pub fn new(state: T) -> Self {
+        Self {
+            state,
+            mode: Mode::Running,
+            held: VecDeque::new(),
+            started: false,
+        }
+    }
+
+    /// Gets the current state.
+    pub fn state(&self) -> &T {
+        &self.state
+    }
+
+    /// Mutably gets the current state.
+    pub fn state_mut(&mut self) -> &mut T {
+        &mut self.state
+    }
+
+    /// Waits for the next message to this process.
+    pub fn recv(&mut self) -> Message {
+        loop {
+            if let Ok(Some(msg)) = self.next(None) {
+                return msg;
+            }
+        }
+    }
+
+    /// Waits for the next message to this process, or returns `None` if
+    /// none arrives within `timeout`.
+    ///
+    /// Handling a migration message restarts the timeout.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Message> {
+        loop {
+            match self.next(Some(timeout)) {
+                Ok(Some(msg)) => return Some(msg),
+                Ok(None) => continue,
+                Err(Timeout) => return None,
+            }
+        }
+    }
+
+    /// Receives and handles one signal. Returns a message if it's for the
+    /// process, or `None` if it was handled here.
+    ///
+    /// The timeout only applies while running.
+    fn next(&mut self, timeout: Option<Duration>) -> Result<Option<Message>, Timeout> {
+        if matches!(self.mode, Mode::Running) {
+            if let Some(msg) = self.held.pop_front() {
+                return Ok(Some(msg));
+            }
+        }
+
+        let signal = match (&self.mode, timeout) {
+            (Mode::Running, Some(timeout)) => PARENT.recv_timeout(timeout)?,
+            _ => PARENT.recv(),
+        };
+
+        let Signal::Message(msg) = signal else {
+            return Ok(None);
+        };
+
+        let first = !self.started;
+        self.started = true;
+
+        let Ok(control) = serde_json::from_slice::<MigrationMessage>(&msg.data) else {
+            return Ok(self.dispatch(msg));
+        };
+
+        let Some(reply) = msg.caps.first() else {
+            crate::warning!("ignoring migration message without a reply address");
+            return Ok(None);
+        };
+
+        if !msg.proves_kill(1) {
+            crate::warning!("ignoring unauthorized migration message: {:?}", control);
+            return Ok(None);
+        }
+
+        if let MigrationMessage::Restore(lump) = control {
+            let response = if first {
+                self.restore(&lump)
+            } else {
+                MigrationMessage::Failed("process has already started".to_string())
+            };
+
+            reply.send_json(&response, &[]);
+            return Ok(None);
+        }
+
+        match (control, &self.mode) {
+            (MigrationMessage::Snapshot, Mode::Running) => {
+                let data = serde_json::to_vec(&self.state).unwrap();
+                let lump = Lump::load(&data);
+                self.mode = Mode::Held;
+                reply.send_json(&MigrationMessage::State(lump.get_id()), &[]);
+            }
+            (MigrationMessage::Forward, Mode::Held) => {
+                let Some(target) = msg.caps.get(2) else {
+                    let err = "missing the new process".to_string();
+                    reply.send_json(&MigrationMessage::Failed(err), &[]);
+                    return Ok(None);
+                };
+
+                for held in self.held.drain(..) {
+                    forward(target, &held);
+                }
+
+                self.mode = Mode::Forwarding(target.clone());
+                reply.send_json(&MigrationMessage::Forwarded, &[]);
+            }
+            (MigrationMessage::Resume, Mode::Held) => {
+                self.mode = Mode::Running;
+            }
+            (control, _) => {
+                let err = format!("unexpected migration message: {:?}", control);
+                reply.send_json(&MigrationMessage::Failed(err), &[]);
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns, holds, or forwards a message depending on the mode.
+    fn dispatch(&mut self, msg: Message) -> Option<Message> {
+        match &self.mode {
+            Mode::Running => Some(msg),
+            Mode::Held => {
+                self.held.push_back(msg);
+                None
+            }
+            Mode::Forwarding(target) => {
+                forward(target, &msg);
+                None
+            }
+        }
+    }
+
+    /// Replaces the state with a saved one.
+    fn restore(&mut self, lump: &LumpId) -> MigrationMessage {
+        let data = match Lump::load_by_id(lump) {
+            Ok(lump) => lump.get_data(),
+            Err(err) => return MigrationMessage::Failed(format!("missing saved state: {}", err)),
+        };
+
+        match serde_json::from_slice(&data) {
+            Ok(state) => {
+                self.state = state;
+                MigrationMessage::Ready
+            }
+            Err(err) => MigrationMessage::Failed(format!("invalid saved state: {}", err)),
+        }
+    }
+}
+
+/// Sends a copy of a message to another process.
+fn forward(target: &Capability, msg: &Message) {
+    let caps: Vec<&Capability> = msg.caps.iter().collect();
+    target.send(&msg.data, &caps);
+}
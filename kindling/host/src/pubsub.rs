@@ -0,0 +1,91 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use super::*;
+
+use hearth_guest::pubsub::*;
+
+lazy_static::lazy_static! {
+    static ref PUBSUB_FACTORY: RequestResponse<PubSubRequest, PubSubResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(PUBSUB_SERVICE).unwrap())
+    };
+}
+
+/// A wrapper around a pub-sub topic capability.
+pub struct Topic {
+    cap: Capability,
+
+    /// The mailbox that owns this topic, if it was created by this process.
+    /// The topic is destroyed when this is dropped.
+    _owner: Option<Mailbox>,
+}
+
+impl Default for Topic {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Topic {
+    /// Creates a new topic owned by this wrapper.
+    ///
+    /// The topic is destroyed when this wrapper is dropped. Share it with
+    /// other processes by sending them [Self::capability].
+    pub fn new() -> Self {
+        let owner = Mailbox::new();
+        let owner_cap = owner.make_capability(Permissions::MONITOR);
+        let (result, caps) = PUBSUB_FACTORY.request(PubSubRequest::CreateTopic, &[&owner_cap]);
+        result.unwrap();
+
+        Self {
+            cap: caps.get(0).unwrap().clone(),
+            _owner: Some(owner),
+        }
+    }
+
+    /// Wraps a topic capability received from another process.
+    pub fn from_capability(cap: Capability) -> Self {
+        Self { cap, _owner: None }
+    }
+
+    /// Gets the underlying topic capability.
+    pub fn capability(&self) -> &Capability {
+        &self.cap
+    }
+
+    /// Publishes a JSON-encoded message to every subscriber.
+    pub fn publish<T: Serialize>(&self, message: &T) {
+        let payload = serde_json::to_vec(message).unwrap();
+        self.cap.send_json(&TopicRequest::Publish { payload }, &[]);
+    }
+
+    /// Subscribes a mailbox to this topic. Receive messages with
+    /// [Mailbox::recv_json].
+    ///
+    /// The mailbox is automatically unsubscribed when it's dropped.
+    pub fn subscribe(&self, mailbox: &Mailbox) {
+        let cap = mailbox.make_capability(Permissions::SEND | Permissions::MONITOR);
+        self.cap.send_json(&TopicRequest::Subscribe, &[&cap]);
+    }
+
+    /// Unsubscribes a mailbox from this topic.
+    pub fn unsubscribe(&self, mailbox: &Mailbox) {
+        let cap = mailbox.make_capability(Permissions::SEND | Permissions::MONITOR);
+        self.cap.send_json(&TopicRequest::Unsubscribe, &[&cap]);
+    }
+}
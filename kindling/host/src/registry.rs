@@ -42,6 +42,37 @@ impl Registry {
             None
         }
     }
+
+    /// Registers a service under the given name.
+    ///
+    /// Returns `Some(true)` if an old service was replaced, `Some(false)` if
+    /// the name was free, and `None` if this registry is read-only.
+    pub fn register(&self, name: &str, service: &Capability) -> Option<bool> {
+        let request = registry::RegistryRequest::Register {
+            name: name.to_string(),
+        };
+
+        let (data, _) = self.request(request, &[service]);
+
+        let registry::RegistryResponse::Register(result) = data else {
+            panic!("failed to register service {:?}", name);
+        };
+
+        result
+    }
+
+    /// Sends a [registry::RegistryRequest::Watch] for the given name, with
+    /// the response going to `reply` instead of being waited on.
+    ///
+    /// The response names the service, so one reply capability can be shared
+    /// by any number of watches.
+    pub fn watch(&self, name: &str, reply: &Capability) {
+        let request = registry::RegistryRequest::Watch {
+            name: name.to_string(),
+        };
+
+        self.as_ref().send_json(&request, &[reply]);
+    }
 }
 
 /// A capability to the registry that this process has base access to.
@@ -18,7 +18,8 @@
 
 use super::*;
 
-use hearth_guest::terminal::*;
+use glam::{Mat4, Vec2};
+use hearth_guest::{terminal::*, window::*};
 
 lazy_static::lazy_static! {
     static ref TERMINAL_FACTORY: RequestResponse<FactoryRequest, FactoryResponse> = {
@@ -26,6 +27,23 @@ lazy_static::lazy_static! {
     };
 }
 
+/// Replace the font of every terminal, including ones created later.
+///
+/// Styles missing from `fonts` use the regular font.
+pub fn set_font(fonts: TerminalFonts) -> Result<(), FactoryError> {
+    let (resp, _) = TERMINAL_FACTORY.request(FactoryRequest::SetFont(fonts), &[]);
+    resp.map(|_| ())
+}
+
+/// Get counters describing the most recently rendered frame of terminals.
+pub fn get_stats() -> TerminalRenderStats {
+    let (resp, _) = TERMINAL_FACTORY.request(FactoryRequest::GetStats, &[]);
+    match resp {
+        Ok(FactorySuccess::Stats(stats)) => stats,
+        other => panic!("unexpected terminal factory response: {:?}", other),
+    }
+}
+
 /// A wrapper around the Terminal Capability.
 pub struct Terminal {
     cap: Capability,
@@ -49,13 +67,196 @@ impl Terminal {
         }
     }
 
+    /// Creates a new terminal running a specific command.
+    ///
+    /// If `on_exit` is given, it's sent a [TerminalEvent::ProcessExited]
+    /// when the command exits.
+    pub fn spawn_shell(
+        spawn: SpawnShell,
+        on_exit: Option<&Capability>,
+    ) -> Result<Self, FactoryError> {
+        let caps: Vec<&Capability> = on_exit.into_iter().collect();
+        let (resp, caps) = TERMINAL_FACTORY.request(FactoryRequest::SpawnShell(spawn), &caps);
+        resp?;
+        Ok(Terminal {
+            cap: caps.get(0).unwrap().clone(),
+        })
+    }
+
     /// Send input to this terminal.
     pub fn input(&self, input: String) {
         self.cap.send_json(&TerminalUpdate::Input(input), &[])
     }
 
+    /// Send raw bytes to this terminal's input.
+    pub fn input_bytes(&self, bytes: Vec<u8>) {
+        self.cap.send_json(&TerminalUpdate::InputBytes(bytes), &[])
+    }
+
+    /// Fix this terminal's grid to a size in cells. `mode` chooses whether
+    /// the terminal keeps its size in the world or grows to fit.
+    pub fn resize(&self, cols: u16, rows: u16, mode: TerminalSizingMode) {
+        self.cap
+            .send_json(&TerminalUpdate::Resize { cols, rows, mode }, &[])
+    }
+
+    /// Pass an input method editor event to this terminal. Committed text
+    /// is sent as input and preedit text is drawn at the cursor.
+    pub fn ime(&self, ime: hearth_guest::window::Ime) {
+        self.cap.send_json(&TerminalUpdate::Ime(ime), &[])
+    }
+
+    /// Set whether this terminal has input focus, which moves the IME
+    /// candidate window to its cursor.
+    pub fn set_focused(&self, focused: bool) {
+        self.cap
+            .send_json(&TerminalUpdate::SetFocused(focused), &[])
+    }
+
+    /// Handles an event from a [Focusable](crate::window::Focusable)
+    /// registered for this terminal.
+    ///
+    /// Focus changes update the terminal's cursor and IME placement. While
+    /// it's focused, typed text, editing keys, and IME events are written to
+    /// the terminal.
+    pub fn on_focus_event(&self, event: FocusEvent) {
+        match event {
+            FocusEvent::Gained => self.set_focused(true),
+            FocusEvent::Lost => self.set_focused(false),
+            FocusEvent::Input(WindowEvent::ReceivedCharacter(c)) => match c {
+                // backspace and delete are sent as editing keys
+                '\u{7f}' | '\u{8}' => {}
+                c => self.input(c.to_string()),
+            },
+            FocusEvent::Input(WindowEvent::KeyboardInput { input, .. }) => {
+                if input.state == ElementState::Pressed {
+                    let key = input.virtual_keycode.and_then(key_input);
+                    if let Some(key) = key {
+                        self.input(key.to_string());
+                    }
+                }
+            }
+            FocusEvent::Input(WindowEvent::Ime(ime)) => self.ime(ime),
+            FocusEvent::Input(_) => {}
+        }
+    }
+
     /// Update the state of this terminal.
     pub fn update(&self, state: TerminalState) {
         self.cap.send_json(&TerminalUpdate::State(state), &[])
     }
+
+    /// Replace this terminal's color palette.
+    pub fn set_palette(&self, palette: TerminalPalette) {
+        self.cap
+            .send_json(&TerminalUpdate::SetPalette(palette), &[])
+    }
+
+    /// Scroll this terminal's view up (positive) or down (negative) through
+    /// its scrollback history.
+    pub fn scroll(&self, lines: i32) {
+        self.cap.send_json(&TerminalUpdate::ScrollLines(lines), &[])
+    }
+
+    /// Scroll this terminal's view to the top of its scrollback history.
+    pub fn scroll_to_top(&self) {
+        self.cap.send_json(&TerminalUpdate::ScrollToTop, &[])
+    }
+
+    /// Scroll this terminal's view back to its live output.
+    pub fn scroll_to_bottom(&self) {
+        self.cap.send_json(&TerminalUpdate::ScrollToBottom, &[])
+    }
+
+    /// Extract the text of a range of this terminal's lines.
+    pub fn get_text(&self, range: TextRange) -> TerminalText {
+        let terminal = RequestResponse::<TerminalUpdate, TerminalText>::new(self.cap.clone());
+        terminal.request(TerminalUpdate::GetText { range }, &[]).0
+    }
+
+    /// Move the pointer over this terminal, in its local space, or take it
+    /// off. The URL or path under the pointer is underlined.
+    pub fn set_pointer(&self, pointer: Option<Vec2>) {
+        self.cap
+            .send_json(&TerminalUpdate::SetPointer(pointer), &[])
+    }
+
+    /// Find the cell at a point in this terminal's local space.
+    pub fn get_cell_at(&self, local_point: Vec2) -> Option<CellPosition> {
+        let terminal =
+            RequestResponse::<TerminalUpdate, Option<CellPosition>>::new(self.cap.clone());
+        terminal
+            .request(TerminalUpdate::GetCellAt { local_point }, &[])
+            .0
+    }
+
+    /// Find the URLs and paths on this terminal's visible screen.
+    pub fn get_hotspots(&self) -> Vec<TerminalHotspot> {
+        let terminal =
+            RequestResponse::<TerminalUpdate, Vec<TerminalHotspot>>::new(self.cap.clone());
+        terminal.request(TerminalUpdate::GetHotspots, &[]).0
+    }
+
+    /// Switch this terminal to a built-in theme by name.
+    pub fn set_theme(&self, name: &str) {
+        self.cap
+            .send_json(&TerminalUpdate::SetTheme(name.to_string()), &[])
+    }
+
+    /// Set or clear the outline drawn around this terminal's glyphs.
+    pub fn set_outline(&self, outline: Option<TerminalOutline>) {
+        self.cap
+            .send_json(&TerminalUpdate::SetOutline(outline), &[])
+    }
+
+    /// Replace the title bar, border, and shadow drawn around this terminal.
+    pub fn set_chrome(&self, chrome: TerminalChrome) {
+        self.cap.send_json(&TerminalUpdate::SetChrome(chrome), &[])
+    }
+
+    /// Set the title drawn in this terminal's title bar.
+    pub fn set_title(&self, title: impl Into<String>) {
+        self.cap
+            .send_json(&TerminalUpdate::SetTitle(title.into()), &[])
+    }
+
+    /// Set the opacity of this terminal's background and glyphs, from 0.0
+    /// (transparent) to 1.0 (opaque).
+    pub fn set_opacity(&self, background: f32, glyphs: f32) {
+        self.cap
+            .send_json(&TerminalUpdate::SetOpacity { background, glyphs }, &[])
+    }
+
+    /// Set how this terminal is depth-tested against its surroundings.
+    pub fn set_depth_mode(&self, depth_mode: TerminalDepthMode) {
+        self.cap
+            .send_json(&TerminalUpdate::SetDepthMode(depth_mode), &[])
+    }
+}
+
+/// Gets the bounds that focus a terminal with the given state when clicked.
+pub fn focus_bounds(state: &TerminalState) -> FocusBounds {
+    FocusBounds {
+        transform: Mat4::from_rotation_translation(state.orientation, state.position),
+        half_size: state.half_size,
+    }
+}
+
+/// Gets the terminal input for an editing key, if it has one.
+fn key_input(key: VirtualKeyCode) -> Option<&'static str> {
+    use VirtualKeyCode::*;
+    match key {
+        Back => Some("\x7f"),
+        Up => Some("\x1b[A"),
+        Down => Some("\x1b[B"),
+        Right => Some("\x1b[C"),
+        Left => Some("\x1b[D"),
+        Home => Some("\x1b[1~"),
+        Insert => Some("\x1b[2~"),
+        Delete => Some("\x1b[3~"),
+        End => Some("\x1b[4~"),
+        PageUp => Some("\x1b[5~"),
+        PageDown => Some("\x1b[6~"),
+        _ => None,
+    }
 }
@@ -18,6 +18,8 @@
 
 use super::*;
 
+use hearth_guest::timer::*;
+
 lazy_static::lazy_static! {
     static ref SLEEP_SERVICE: Capability = {
         registry::REGISTRY.get_service("hearth.Sleep").unwrap()
@@ -27,6 +29,10 @@ lazy_static::lazy_static! {
         RequestResponse::new(registry::REGISTRY.get_service("hearth.TimerFactory").unwrap())
     };
 
+    static ref SCHEDULER: RequestResponse<TimerRequest, TimerResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(TIMER_SERVICE).unwrap())
+    };
+
     static ref STOPWATCH_FACTORY: RequestResponse<(), ()> = {
         RequestResponse::new(registry::REGISTRY.get_service("hearth.StopwatchFactory").unwrap())
     };
@@ -43,6 +49,19 @@ pub fn sleep(duration: f32) {
     let _ = reply.recv();
 }
 
+/// Asynchronously sleeps for the given time in seconds.
+///
+/// Drive this with [hearth_guest::executor::run_async].
+pub async fn sleep_async(duration: f32) {
+    let reply = Mailbox::new();
+    let reply_cap = reply.make_capability(Permissions::SEND);
+    reply.monitor(&SLEEP_SERVICE);
+
+    SLEEP_SERVICE.send_json(&duration, &[&reply_cap]);
+
+    let _ = reply.recv_async().await;
+}
+
 pub struct Timer(RequestResponse<f32, ()>);
 
 impl Default for Timer {
@@ -84,3 +103,51 @@ impl Stopwatch {
         self.0.request((), &[]).0
     }
 }
+
+/// A message scheduled for delivery by the timer service.
+///
+/// The delivery is cancelled when this is dropped.
+pub struct ScheduledMessage {
+    id: u64,
+    target: Capability,
+}
+
+impl Drop for ScheduledMessage {
+    fn drop(&mut self) {
+        let _ = SCHEDULER.request(TimerRequest::Cancel { id: self.id }, &[&self.target]);
+    }
+}
+
+impl ScheduledMessage {
+    /// Sends a JSON-encoded message to `target` once, after the given time
+    /// in milliseconds.
+    pub fn send_after<T: Serialize>(millis: u64, message: &T, target: &Capability) -> Self {
+        let payload = serde_json::to_vec(message).unwrap();
+        Self::schedule(TimerRequest::SendAfter { millis, payload }, target)
+    }
+
+    /// Sends a JSON-encoded message to `target` repeatedly with the given
+    /// period in milliseconds.
+    pub fn send_interval<T: Serialize>(
+        period_millis: u64,
+        message: &T,
+        target: &Capability,
+    ) -> Self {
+        let payload = serde_json::to_vec(message).unwrap();
+        let request = TimerRequest::SendInterval {
+            period_millis,
+            payload,
+        };
+
+        Self::schedule(request, target)
+    }
+
+    fn schedule(request: TimerRequest, target: &Capability) -> Self {
+        let (result, _) = SCHEDULER.request(request, &[target]);
+
+        Self {
+            id: result.unwrap(),
+            target: target.clone(),
+        }
+    }
+}
@@ -18,14 +18,33 @@
 
 use super::*;
 
-use hearth_guest::{wasm::*, LumpId};
+use std::collections::HashMap;
+
+use hearth_guest::{init::ServiceArgs, wasm::*, LumpId, PARENT};
+use serde::de::DeserializeOwned;
 
 lazy_static::lazy_static! {
-    static ref WASM_SPAWNER: RequestResponse<wasm::WasmSpawnInfo, ()> = {
-        RequestResponse::new(registry::REGISTRY.get_service("hearth.wasm.WasmProcessSpawner").unwrap())
+    static ref WASM_SPAWNER: RequestResponse<wasm::WasmSpawnInfo, wasm::WasmSpawnResponse> = {
+        RequestResponse::new(get_spawner())
+    };
+
+    static ref WASM_WARMER: RequestResponse<wasm::WasmSpawnerRequest, wasm::WasmSpawnResponse> = {
+        RequestResponse::new(get_spawner())
     };
 }
 
+/// Gets a spawner bound to this process if the host provides one, so that
+/// spawns are attributed to it, or the registry's shared spawner otherwise.
+fn get_spawner() -> Capability {
+    if hearth_guest::has_feature("hearth::group") {
+        hearth_guest::spawner()
+    } else {
+        registry::REGISTRY
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap()
+    }
+}
+
 /// Spawns a child process for the given function.
 ///
 /// Takes an optional capability to a registry. If provided, the service will
@@ -35,15 +54,15 @@ pub fn spawn_fn(cb: fn(), registry: Option<Capability>) -> Capability {
     // directly transmute a Rust function pointer to a Wasm function index
     let entrypoint = cb as usize as u32;
 
-    let ((), caps) = WASM_SPAWNER.request(
-        wasm::WasmSpawnInfo {
-            lump: hearth_guest::this_lump(),
-            entrypoint: Some(entrypoint),
-        },
-        &[registry.as_ref().unwrap_or(registry::REGISTRY.as_ref())],
-    );
+    let info = wasm::WasmSpawnInfo {
+        lump: hearth_guest::this_lump(),
+        entrypoint: Some(entrypoint),
+        limits: Default::default(),
+        keep_awake: false,
+        allow: None,
+    };
 
-    caps.get(0).cloned().unwrap()
+    spawn(info, registry).0
 }
 
 /// Spawn an entire Wasm module from a given lump.
@@ -52,12 +71,70 @@ pub fn spawn_fn(cb: fn(), registry: Option<Capability>) -> Capability {
 /// be added to the given registry, otherwise it will be added to the default
 /// registry.
 pub fn spawn_mod(lump: LumpId, registry: Option<Capability>) -> Capability {
-    let ((), caps) = WASM_SPAWNER.request(
-        wasm::WasmSpawnInfo {
-            lump,
-            entrypoint: None,
-        },
-        &[registry.as_ref().unwrap_or(registry::REGISTRY.as_ref())],
-    );
-    caps.get(0).cloned().unwrap()
+    let info = wasm::WasmSpawnInfo {
+        lump,
+        entrypoint: None,
+        limits: Default::default(),
+        keep_awake: false,
+        allow: None,
+    };
+
+    spawn(info, registry).0
+}
+
+/// Spawns a child process for the given function and returns a capability to
+/// it along with a capability to its link endpoint.
+///
+/// Pass the endpoint to [Mailbox::link] to be notified when the child exits.
+pub fn spawn_fn_linked(cb: fn(), registry: Option<Capability>) -> (Capability, Capability) {
+    let entrypoint = cb as usize as u32;
+
+    let info = wasm::WasmSpawnInfo {
+        lump: hearth_guest::this_lump(),
+        entrypoint: Some(entrypoint),
+        limits: Default::default(),
+        keep_awake: false,
+        allow: None,
+    };
+
+    spawn(info, registry)
+}
+
+/// Compiles and links a Wasm module ahead of time so that spawning it later
+/// is faster.
+///
+/// Useful for modules that are spawned often, like per-request workers.
+/// Returns the spawner's error if the module can't be loaded.
+pub fn warm_up(lump: LumpId) -> Result<(), String> {
+    let request = wasm::WasmSpawnerRequest::WarmUp { warm_up: lump };
+    WASM_WARMER.request(request, &[]).0
+}
+
+/// Receives the arguments and environment variables that the init system
+/// started this service with.
+///
+/// Only services started by the init system whose `service.toml` expects
+/// arguments receive them, which are always their first message, so this
+/// must be called before anything else is received from [PARENT]. Services
+/// with no configured arguments receive `null`.
+pub fn recv_service_args<T: DeserializeOwned>(
+) -> Result<(T, HashMap<String, String>), serde_json::Error> {
+    let (ServiceArgs { args, env }, _) = PARENT.recv_json::<ServiceArgs>();
+    Ok((serde_json::from_value(args)?, env))
+}
+
+/// Spawns a process, returning it and its link endpoint. Panics with the
+/// spawner's error if spawning fails.
+fn spawn(info: wasm::WasmSpawnInfo, registry: Option<Capability>) -> (Capability, Capability) {
+    let registry = registry.as_ref().unwrap_or(registry::REGISTRY.as_ref());
+    let (result, caps) = WASM_SPAWNER.request(info, &[registry]);
+
+    if let Err(err) = result {
+        panic!("failed to spawn Wasm process: {}", err);
+    }
+
+    let mut caps = caps.into_iter();
+    let process = caps.next().unwrap();
+    let endpoint = caps.next().unwrap();
+    (process, endpoint)
 }
@@ -16,9 +16,14 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use super::{glam::Mat4, *};
+use std::collections::BTreeMap;
 
-use hearth_guest::window::*;
+use super::{
+    glam::{DVec2, Mat4},
+    *,
+};
+
+use hearth_guest::{window::*, Signal};
 
 lazy_static::lazy_static! {
     /// The main client window.
@@ -29,6 +34,62 @@ lazy_static::lazy_static! {
     };
 }
 
+lazy_static::lazy_static! {
+    static ref CAMERA: RequestResponse<CameraRequest, CameraResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(CAMERA_SERVICE).unwrap())
+    };
+}
+
+lazy_static::lazy_static! {
+    static ref INPUT_MAP: RequestResponse<InputMapRequest, InputMapResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(INPUT_MAP_SERVICE).unwrap())
+    };
+}
+
+lazy_static::lazy_static! {
+    static ref FOCUS: RequestResponse<FocusRequest, FocusResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(FOCUS_SERVICE).unwrap())
+    };
+}
+
+lazy_static::lazy_static! {
+    static ref CLIPBOARD: RequestResponse<ClipboardRequest, ClipboardResponse> = {
+        RequestResponse::new(registry::REGISTRY.get_service(CLIPBOARD_SERVICE).unwrap())
+    };
+}
+
+/// Gets the text contents of the host clipboard.
+///
+/// Panics if the clipboard service doesn't respond.
+pub fn get_clipboard() -> Result<String, ClipboardError> {
+    let success = CLIPBOARD
+        .try_request(ClipboardRequest::GetText, &[], DEFAULT_REQUEST_TIMEOUT)
+        .expect("clipboard service did not respond")
+        .0?;
+    match success {
+        ClipboardSuccess::GetText(text) => Ok(text),
+        _ => panic!("expected ClipboardSuccess::GetText, got {:?}", success),
+    }
+}
+
+/// Sets the text contents of the host clipboard.
+///
+/// Panics if the clipboard service doesn't respond.
+pub fn set_clipboard(text: String) -> Result<(), ClipboardError> {
+    let success = CLIPBOARD
+        .try_request(
+            ClipboardRequest::SetText(text),
+            &[],
+            DEFAULT_REQUEST_TIMEOUT,
+        )
+        .expect("clipboard service did not respond")
+        .0?;
+    match success {
+        ClipboardSuccess::SetText => Ok(()),
+        _ => panic!("expected ClipboardSuccess::SetText, got {:?}", success),
+    }
+}
+
 /// Instance of a desktop window.
 pub struct Window {
     cap: Capability,
@@ -45,6 +106,16 @@ impl Window {
         mailbox
     }
 
+    /// Subscribe to this window's events and iterate over them.
+    ///
+    /// The iterator blocks until the next event and ends if the window
+    /// service goes down.
+    pub fn events(&self) -> WindowEvents {
+        let mailbox = self.subscribe();
+        mailbox.monitor(&self.cap);
+        WindowEvents { mailbox }
+    }
+
     /// Sets the title of this window.
     pub fn set_title(&self, title: String) {
         self.cap.send_json(&WindowCommand::SetTitle(title), &[]);
@@ -67,6 +138,23 @@ impl Window {
             .send_json(&WindowCommand::SetCursorVisible(false), &[]);
     }
 
+    /// Sets the window's vertical sync mode.
+    pub fn set_vsync(&self, mode: VsyncMode) {
+        self.cap.send_json(&WindowCommand::SetVsync(mode), &[]);
+    }
+
+    /// Moves the IME candidate window to a position in physical display
+    /// units from the top-left corner of this window.
+    pub fn set_ime_position(&self, position: DVec2) {
+        self.cap
+            .send_json(&WindowCommand::SetImePosition(position), &[]);
+    }
+
+    /// Requests that the window redraws soon because its contents changed.
+    pub fn request_redraw(&self) {
+        self.cap.send_json(&WindowCommand::Redraw, &[]);
+    }
+
     /// Update the window's rending camera
     ///
     /// `vfov` - The vertical field of view, in degrees.
@@ -76,4 +164,291 @@ impl Window {
         self.cap
             .send_json(&WindowCommand::SetCamera { vfov, near, view }, &[]);
     }
+
+    /// Eases the window's rendering camera toward a target every frame
+    /// instead of snapping to it. See [Window::set_camera] for the
+    /// parameters.
+    pub fn set_camera_target(&self, vfov: f32, near: f32, view: Mat4, smoothing: CameraSmoothing) {
+        self.cap.send_json(
+            &WindowCommand::SetCameraTarget {
+                vfov,
+                near,
+                view,
+                smoothing,
+            },
+            &[],
+        );
+    }
+}
+
+/// A camera source that overrides the window's camera while it has the
+/// highest priority. Control goes back to the next source when this is
+/// dropped.
+pub struct CameraSource {
+    cap: Capability,
+}
+
+impl Drop for CameraSource {
+    fn drop(&mut self) {
+        self.cap.send_json(&CameraCommand::Release, &[]);
+    }
+}
+
+impl CameraSource {
+    /// Creates a new camera source with the given priority. The window's own
+    /// camera has a priority of zero.
+    ///
+    /// Panics if the camera service doesn't respond.
+    pub fn new(priority: u32) -> Result<Self, CameraError> {
+        let request = CameraRequest::CreateSource { priority };
+        let (resp, caps) = CAMERA
+            .try_request(request, &[], DEFAULT_REQUEST_TIMEOUT)
+            .expect("camera service did not respond");
+        resp?;
+
+        Ok(Self {
+            cap: caps.get(0).unwrap().clone(),
+        })
+    }
+
+    /// Snaps this source's camera.
+    pub fn set(&self, vfov: f32, near: f32, view: Mat4) {
+        self.cap
+            .send_json(&CameraCommand::Set { vfov, near, view }, &[]);
+    }
+
+    /// Eases this source's camera toward a target every frame.
+    pub fn set_target(&self, vfov: f32, near: f32, view: Mat4, smoothing: CameraSmoothing) {
+        self.cap.send_json(
+            &CameraCommand::SetTarget {
+                vfov,
+                near,
+                view,
+                smoothing,
+            },
+            &[],
+        );
+    }
+}
+
+/// Subscribes to the input map's action events and iterates over them.
+///
+/// The iterator blocks until the next event and ends if the input map
+/// service goes down. Panics if the input map service doesn't respond to the
+/// subscription.
+pub fn input_actions() -> Result<InputActions, InputMapError> {
+    let mailbox = Mailbox::new();
+    let sub = mailbox.make_capability(Permissions::SEND | Permissions::MONITOR);
+    request_input_map(InputMapRequest::Subscribe, &[&sub])?;
+    mailbox.monitor(INPUT_MAP.as_ref());
+    Ok(InputActions { mailbox })
+}
+
+/// Gets the bindings of every input action, by action name.
+///
+/// Panics if the input map service doesn't respond.
+pub fn get_input_bindings() -> Result<BTreeMap<String, Vec<InputBinding>>, InputMapError> {
+    let success = request_input_map(InputMapRequest::GetBindings, &[])?;
+    match success {
+        InputMapSuccess::Bindings(bindings) => Ok(bindings),
+        _ => panic!("expected InputMapSuccess::Bindings, got {:?}", success),
+    }
+}
+
+/// Replaces the bindings of an input action and saves them to the host's
+/// config file. An empty list of bindings removes the action.
+///
+/// Panics if the input map service doesn't respond.
+pub fn rebind_input(action: String, bindings: Vec<InputBinding>) -> Result<(), InputMapError> {
+    let request = InputMapRequest::Rebind { action, bindings };
+    request_input_map(request, &[])?;
+    Ok(())
+}
+
+/// Performs a request on the input map service.
+///
+/// Panics if the input map service doesn't respond.
+fn request_input_map(request: InputMapRequest, args: &[&Capability]) -> InputMapResponse {
+    INPUT_MAP
+        .try_request(request, args, DEFAULT_REQUEST_TIMEOUT)
+        .expect("input map service did not respond")
+        .0
+}
+
+/// An iterator over input action events. Created by [input_actions].
+pub struct InputActions {
+    mailbox: Mailbox,
+}
+
+impl InputActions {
+    /// Gets the next event if one has already been received, without
+    /// waiting.
+    pub fn try_next(&mut self) -> Option<InputMapEvent> {
+        let signal = self.mailbox.try_recv()?;
+        Self::parse(signal)
+    }
+
+    /// Gets the mailbox that this iterator receives events on, for use with
+    /// polling multiple mailboxes.
+    pub fn mailbox(&self) -> &Mailbox {
+        &self.mailbox
+    }
+
+    fn parse(signal: Signal) -> Option<InputMapEvent> {
+        match signal {
+            Signal::Message(msg) => match serde_json::from_slice(&msg.data) {
+                Ok(event) => Some(event),
+                Err(err) => panic!("invalid input map event: {:?}", err),
+            },
+            Signal::Down { .. } => None,
+        }
+    }
+}
+
+impl Iterator for InputActions {
+    type Item = InputMapEvent;
+
+    fn next(&mut self) -> Option<InputMapEvent> {
+        Self::parse(self.mailbox.recv())
+    }
+}
+
+/// A target of the focus service, which receives keyboard input from the
+/// window while it's focused.
+///
+/// Iterating over this blocks until the next [FocusEvent]. The target is
+/// unregistered once this is dropped.
+pub struct Focusable {
+    id: u32,
+    mailbox: Mailbox,
+    controller: RequestResponse<FocusTargetRequest, FocusResponse>,
+}
+
+impl Drop for Focusable {
+    fn drop(&mut self) {
+        self.controller.as_ref().kill();
+    }
+}
+
+impl Focusable {
+    /// Registers a new focusable target. If `bounds` are given, clicking
+    /// inside of them focuses this target.
+    ///
+    /// Panics if the focus service doesn't respond.
+    pub fn new(bounds: Option<FocusBounds>) -> Result<Self, FocusError> {
+        let mailbox = Mailbox::new();
+        let cap = mailbox.make_capability(Permissions::SEND | Permissions::MONITOR);
+        let request = FocusRequest::Register { bounds };
+        let (result, caps) = FOCUS
+            .try_request(request, &[&cap], DEFAULT_REQUEST_TIMEOUT)
+            .expect("focus service did not respond");
+        match result? {
+            FocusSuccess::Registered(id) => Ok(Self {
+                id,
+                mailbox,
+                controller: RequestResponse::new(caps.into_iter().next().unwrap()),
+            }),
+            success => panic!("expected FocusSuccess::Registered, got {:?}", success),
+        }
+    }
+
+    /// Gets this target's ID in the focus service.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Moves or removes the bounds that focus this target when clicked.
+    pub fn set_bounds(&self, bounds: Option<FocusBounds>) -> Result<(), FocusError> {
+        self.request(FocusTargetRequest::SetBounds(bounds))
+    }
+
+    /// Focuses this target, taking the focus from any other target.
+    pub fn focus(&self) -> Result<(), FocusError> {
+        self.request(FocusTargetRequest::Focus)
+    }
+
+    /// Clears the focus if this target has it.
+    pub fn blur(&self) -> Result<(), FocusError> {
+        self.request(FocusTargetRequest::Blur)
+    }
+
+    /// Gets the next event if one has already been received, without
+    /// waiting.
+    pub fn try_next(&mut self) -> Option<FocusEvent> {
+        let signal = self.mailbox.try_recv()?;
+        Self::parse(signal)
+    }
+
+    /// Gets the mailbox that this target receives events on, for use with
+    /// polling multiple mailboxes.
+    pub fn mailbox(&self) -> &Mailbox {
+        &self.mailbox
+    }
+
+    /// Parses a signal received on this target's mailbox, such as one
+    /// returned by [Mailbox::poll].
+    pub fn parse(signal: Signal) -> Option<FocusEvent> {
+        match signal {
+            Signal::Message(msg) => match serde_json::from_slice(&msg.data) {
+                Ok(event) => Some(event),
+                Err(err) => panic!("invalid focus event: {:?}", err),
+            },
+            Signal::Down { .. } => None,
+        }
+    }
+
+    fn request(&self, request: FocusTargetRequest) -> Result<(), FocusError> {
+        self.controller
+            .try_request(request, &[], DEFAULT_REQUEST_TIMEOUT)
+            .expect("focus service did not respond")
+            .0?;
+
+        Ok(())
+    }
+}
+
+impl Iterator for Focusable {
+    type Item = FocusEvent;
+
+    fn next(&mut self) -> Option<FocusEvent> {
+        Self::parse(self.mailbox.recv())
+    }
+}
+
+/// An iterator over a window's events. Created by [Window::events].
+pub struct WindowEvents {
+    mailbox: Mailbox,
+}
+
+impl WindowEvents {
+    /// Gets the next event if one has already been received, without
+    /// waiting.
+    pub fn try_next(&mut self) -> Option<WindowEvent> {
+        let signal = self.mailbox.try_recv()?;
+        Self::parse(signal)
+    }
+
+    /// Gets the mailbox that this iterator receives events on, for use with
+    /// polling multiple mailboxes.
+    pub fn mailbox(&self) -> &Mailbox {
+        &self.mailbox
+    }
+
+    fn parse(signal: Signal) -> Option<WindowEvent> {
+        match signal {
+            Signal::Message(msg) => match serde_json::from_slice(&msg.data) {
+                Ok(event) => Some(event),
+                Err(err) => panic!("invalid window event: {:?}", err),
+            },
+            Signal::Down { .. } => None,
+        }
+    }
+}
+
+impl Iterator for WindowEvents {
+    type Item = WindowEvent;
+
+    fn next(&mut self) -> Option<WindowEvent> {
+        Self::parse(self.mailbox.recv())
+    }
 }
@@ -16,17 +16,393 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use kindling_host::prelude::*;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use hearth_guest::{
+    fs::FILESYSTEM_SERVICE,
+    init::{InitConfig, InitServiceConfig, ServiceArgs},
+    registry::RegistryResponse,
+    Capability, Mailbox, Permissions, Signal, PARENT,
+};
+use kindling_host::{prelude::*, registry::Registry, wasm::warm_up};
+
+use service::{find_cycles, OnTimeout, Service};
+
+pub mod registry;
+pub mod service;
 
 hearth_guest::export_metadata!();
 
 #[no_mangle]
 pub extern "C" fn run() {
     info!("Hello world!");
-    let search_dir = "init";
-    for file in list_files(search_dir).unwrap() {
-        info!("file: {}", file.name);
-        let lump = get_file(&format!("init/{}/service.wasm", file.name)).unwrap();
-        spawn_mod(lump, None);
+
+    // the host sends the init config before anything else
+    let (config, _) = PARENT.recv_json::<InitConfig>();
+    let search_dir = config.path.as_deref().unwrap_or("init");
+
+    for path in config.warm_up.iter() {
+        warm_up_module(path);
+    }
+
+    // services spawned by init register here once they're ready
+    let registry = Registry::new(spawn_fn(registry::serve, None));
+
+    let found: Vec<String> = list_files(search_dir)
+        .unwrap()
+        .into_iter()
+        .map(|file| file.name)
+        .collect();
+
+    // names of services that will never be registered
+    let mut unavailable = HashSet::new();
+
+    for name in missing_services(&config, &found) {
+        warning!("configured service {:?} was not found", name);
+    }
+
+    let mut pending = Vec::new();
+    for (name, selected) in select_services(&config, found) {
+        let service_config = match selected {
+            Ok(service_config) => service_config,
+            Err(Skip::Disabled) => {
+                info!("skipping disabled service {:?}", name);
+                continue;
+            }
+            Err(Skip::Unconfigured) => {
+                info!("skipping unconfigured service {:?}", name);
+                continue;
+            }
+        };
+
+        info!("found service: {}", name);
+
+        let mut service = match Service::load(search_dir, name.clone()) {
+            Ok(service) => service,
+            Err(err) => {
+                error!("not starting {:?}: {}", name, err);
+                unavailable.insert(name);
+                continue;
+            }
+        };
+
+        if !service.config.targets_role(config.role) {
+            let role = config.role.unwrap();
+            info!(
+                "skipping {:?}, which doesn't target the {} role",
+                name, role
+            );
+            unavailable.insert(name);
+            continue;
+        }
+
+        service.args = service_config.args;
+        service.env = service_config.env;
+        pending.push(service);
+    }
+
+    for cycle in find_cycles(&pending) {
+        error!("dependency cycle between services: {}", cycle.join(" -> "));
+        unavailable.extend(cycle);
+    }
+
+    pending.retain(|service| {
+        let in_cycle = unavailable.contains(&service.name);
+
+        if in_cycle {
+            error!("not starting {:?} due to a dependency cycle", service.name);
+        }
+
+        !in_cycle
+    });
+
+    let watches = Mailbox::new();
+    let watch_cap = watches.make_capability(Permissions::SEND);
+    let mut watched = HashSet::new();
+
+    for dep in pending
+        .iter()
+        .flat_map(|service| service.config.depends.iter())
+    {
+        if watched.insert(dep.as_str()) {
+            registry.watch(dep, &watch_cap);
+        }
+    }
+
+    let clock = Stopwatch::new();
+    let mut elapsed = 0.0;
+    let mut present = HashSet::new();
+
+    loop {
+        let failures = unavailable.len();
+
+        pending.retain(|service| {
+            let missing: Vec<&String> = service
+                .config
+                .depends
+                .iter()
+                .filter(|dep| !present.contains(*dep))
+                .collect();
+
+            if missing.is_empty() {
+                start(service, &registry, &mut unavailable);
+                return false;
+            }
+
+            let failed = missing.iter().find(|dep| unavailable.contains(**dep));
+
+            if let Some(dep) = failed {
+                warning!("{:?} depends on unavailable {:?}", service.name, dep);
+            } else if elapsed >= service.config.depends_timeout {
+                warning!(
+                    "{:?} timed out waiting for dependencies: {:?}",
+                    service.name,
+                    missing
+                );
+            } else {
+                return true;
+            }
+
+            match service.config.on_timeout {
+                OnTimeout::Start => start(service, &registry, &mut unavailable),
+                OnTimeout::Fail => {
+                    error!("not starting {:?}", service.name);
+                    unavailable.insert(service.name.clone());
+                }
+            }
+
+            false
+        });
+
+        if pending.is_empty() {
+            break;
+        }
+
+        // new failures may affect services that were already checked
+        if unavailable.len() != failures {
+            continue;
+        }
+
+        let next_deadline = pending
+            .iter()
+            .map(|service| service.config.depends_timeout)
+            .fold(f32::INFINITY, f32::min);
+
+        let wait = Duration::from_secs_f32((next_deadline - elapsed).max(0.0));
+
+        if let Ok(Signal::Message(msg)) = watches.recv_timeout(wait) {
+            match serde_json::from_slice(&msg.data) {
+                Ok(RegistryResponse::Watch {
+                    name,
+                    present: true,
+                }) => {
+                    debug!("dependency {:?} is ready", name);
+                    present.insert(name);
+                }
+                Ok(RegistryResponse::Watch { name, .. }) => {
+                    unavailable.insert(name);
+                }
+                _ => warning!("received invalid watch response"),
+            }
+        }
+
+        elapsed += clock.lap();
+    }
+}
+
+/// Why a service found on disk isn't started.
+#[derive(Debug, PartialEq, Eq)]
+enum Skip {
+    /// The service is disabled in the config.
+    Disabled,
+
+    /// The config has a services table that doesn't list the service.
+    Unconfigured,
+}
+
+/// Decides whether to start each of the services found on disk and, if so,
+/// what to start it with.
+///
+/// Without a services table in the config, every found service is started
+/// with the default config. Otherwise, only found services that are listed
+/// and enabled are started.
+fn select_services(
+    config: &InitConfig,
+    found: Vec<String>,
+) -> Vec<(String, Result<InitServiceConfig, Skip>)> {
+    let Some(services) = config.services.as_ref() else {
+        return found
+            .into_iter()
+            .map(|name| (name, Ok(InitServiceConfig::default())))
+            .collect();
+    };
+
+    found
+        .into_iter()
+        .map(|name| {
+            let selected = match services.get(&name) {
+                Some(service) if service.enabled => Ok(service.clone()),
+                Some(_) => Err(Skip::Disabled),
+                None => Err(Skip::Unconfigured),
+            };
+
+            (name, selected)
+        })
+        .collect()
+}
+
+/// Lists the services in the config's services table that weren't found on
+/// disk, in sorted order.
+fn missing_services<'a>(config: &'a InitConfig, found: &[String]) -> Vec<&'a str> {
+    let mut missing: Vec<&str> = config
+        .services
+        .iter()
+        .flat_map(|services| services.keys())
+        .filter(|name| !found.contains(*name))
+        .map(String::as_str)
+        .collect();
+
+    missing.sort();
+    missing
+}
+
+/// Compiles and links a frequently-spawned module ahead of time.
+fn warm_up_module(path: &str) {
+    let lump = match get_file(path) {
+        Ok(lump) => lump,
+        Err(err) => {
+            warning!("failed to load {:?} to warm up: {:?}", path, err);
+            return;
+        }
+    };
+
+    match warm_up(lump) {
+        Ok(()) => debug!("warmed up {:?}", path),
+        Err(err) => warning!("failed to warm up {:?}: {}", path, err),
+    }
+}
+
+/// Spawns a service with init's registry and registers it there under its
+/// name, recording it as unavailable if its module can't be found.
+fn start(service: &Service, registry: &Registry, unavailable: &mut HashSet<String>) {
+    info!("starting {:?}", service.name);
+
+    let lump = match get_file(&service.module) {
+        Ok(lump) => lump,
+        Err(err) => {
+            error!("failed to load {:?}: {:?}", service.module, err);
+            unavailable.insert(service.name.clone());
+            return;
+        }
+    };
+
+    let process = spawn_mod(lump, Some(service_registry(service, registry)));
+
+    if service.config.expects_args() {
+        let args = ServiceArgs {
+            args: service.start_args(),
+            env: service.env.clone(),
+        };
+
+        process.send_json(&args, &[]);
+    } else if !service.args.is_null() || !service.env.is_empty() {
+        warning!(
+            "{:?} doesn't expect arguments, so its args and env are ignored",
+            service.name
+        );
+    }
+
+    // this wakes up services that depend on this one; other services must
+    // not be able to kill it through the registry
+    let service_cap = process.demote(Permissions::SEND | Permissions::MONITOR);
+    if registry.register(&service.name, &service_cap).is_none() {
+        warning!("init's registry refused to register {:?}", service.name);
+    }
+}
+
+/// Gets the registry to start a service with.
+///
+/// Init's registry only hands out read-only access to the filesystem, so
+/// services that write files are given a registry in front of it that hands
+/// out read-write access instead.
+fn service_registry(service: &Service, registry: &Registry) -> Capability {
+    let registry = registry.as_ref().clone();
+
+    if !service.config.writes_files {
+        return registry;
+    }
+
+    let Some(fs) = REGISTRY.get_service(FILESYSTEM_SERVICE) else {
+        warning!("{:?} writes files, but there's no filesystem", service.name);
+        return registry;
+    };
+
+    let writable = spawn_fn(registry::serve_writable, Some(registry));
+    writable.send(&[], &[&fs]);
+    writable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    fn found() -> Vec<String> {
+        vec!["terminal".to_string(), "daemon".to_string()]
+    }
+
+    #[test]
+    fn selects_every_service_without_a_table() {
+        let config = InitConfig::default();
+        let selected = select_services(&config, found());
+        let default = InitServiceConfig::default();
+        assert_eq!(
+            selected,
+            [
+                ("terminal".to_string(), Ok(default.clone())),
+                ("daemon".to_string(), Ok(default)),
+            ]
+        );
+
+        assert!(missing_services(&config, &found()).is_empty());
+    }
+
+    #[test]
+    fn selects_enabled_services_from_the_table() {
+        let terminal = InitServiceConfig {
+            args: serde_json::json!({ "rows": 24 }),
+            env: HashMap::from([("TERM".to_string(), "hearth".to_string())]),
+            ..Default::default()
+        };
+
+        let disabled = InitServiceConfig {
+            enabled: false,
+            ..Default::default()
+        };
+
+        let config = InitConfig {
+            services: Some(HashMap::from([
+                ("terminal".to_string(), terminal.clone()),
+                ("daemon".to_string(), disabled.clone()),
+                ("missing".to_string(), InitServiceConfig::default()),
+            ])),
+            ..Default::default()
+        };
+
+        let mut found = found();
+        found.push("unlisted".to_string());
+
+        assert_eq!(
+            select_services(&config, found.clone()),
+            [
+                ("terminal".to_string(), Ok(terminal)),
+                ("daemon".to_string(), Err(Skip::Disabled)),
+                ("unlisted".to_string(), Err(Skip::Unconfigured)),
+            ]
+        );
+
+        assert_eq!(missing_services(&config, &found), ["missing"]);
     }
 }
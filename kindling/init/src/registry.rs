@@ -0,0 +1,184 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use hearth_guest::{fs::FILESYSTEM_SERVICE, registry::*, Capability, Signal, PARENT};
+use kindling_host::{fs::get_read_only, prelude::*};
+
+/// The entrypoint of the registry that init hands to the services it spawns.
+///
+/// Services register themselves here once they're ready to be used, which is
+/// what init's dependency ordering waits on. Lookups of names that haven't
+/// been registered here fall through to the parent registry, except that the
+/// filesystem is only handed out with read-only access.
+pub fn serve() {
+    let mut registry = InitRegistry::default();
+
+    loop {
+        let Signal::Message(msg) = PARENT.recv() else {
+            continue;
+        };
+
+        let Ok(request) = serde_json::from_slice::<RegistryRequest>(&msg.data) else {
+            warning!("received invalid registry request");
+            continue;
+        };
+
+        let mut caps = msg.caps.into_iter();
+
+        let Some(reply) = caps.next() else {
+            warning!("received registry request with no reply capability");
+            continue;
+        };
+
+        registry.on_request(request, reply, caps.next());
+    }
+}
+
+#[derive(Default)]
+struct InitRegistry {
+    /// The services that have been registered with this registry.
+    services: HashMap<String, Capability>,
+
+    /// Reply capabilities of watchers waiting on a service, by name.
+    watchers: HashMap<String, Vec<Capability>>,
+
+    /// The read-only filesystem handed out in place of the real one, once
+    /// it's been requested.
+    read_only_fs: Option<Capability>,
+}
+
+impl InitRegistry {
+    fn on_request(&mut self, request: RegistryRequest, reply: Capability, arg: Option<Capability>) {
+        match request {
+            RegistryRequest::Get { name } => match self.get(&name) {
+                Some(service) => reply.send_json(&RegistryResponse::Get(true), &[&service]),
+                None => reply.send_json(&RegistryResponse::Get(false), &[]),
+            },
+            RegistryRequest::Register { name } => {
+                let Some(service) = arg else {
+                    warning!("attempted to register {:?} without a service", name);
+                    reply.send_json(&RegistryResponse::Register(None), &[]);
+                    return;
+                };
+
+                debug!("registered {:?}", name);
+
+                for watcher in self.watchers.remove(&name).unwrap_or_default() {
+                    let response = RegistryResponse::Watch {
+                        name: name.clone(),
+                        present: true,
+                    };
+
+                    watcher.send_json(&response, &[&service]);
+                }
+
+                let replaced = self.services.insert(name, service).is_some();
+                reply.send_json(&RegistryResponse::Register(Some(replaced)), &[]);
+            }
+            RegistryRequest::List => {
+                let mut names: Vec<String> = self.services.keys().cloned().collect();
+
+                let (parent, _) = REGISTRY.request(RegistryRequest::List, &[]);
+                if let RegistryResponse::List(parent) = parent {
+                    names.extend(parent);
+                }
+
+                names.sort();
+                names.dedup();
+                reply.send_json(&RegistryResponse::List(names), &[]);
+            }
+            RegistryRequest::Watch { name } => match self.get(&name) {
+                Some(service) => {
+                    let response = RegistryResponse::Watch {
+                        name,
+                        present: true,
+                    };
+
+                    reply.send_json(&response, &[&service]);
+                }
+                None => self.watchers.entry(name).or_default().push(reply),
+            },
+        }
+    }
+
+    fn get(&mut self, name: &str) -> Option<Capability> {
+        if let Some(service) = self.services.get(name) {
+            return Some(service.clone());
+        }
+
+        if name != FILESYSTEM_SERVICE {
+            return REGISTRY.get_service(name);
+        }
+
+        if self.read_only_fs.is_none() {
+            match get_read_only() {
+                Ok(fs) => self.read_only_fs = Some(fs),
+                Err(err) => warning!("failed to get a read-only filesystem: {:?}", err),
+            }
+        }
+
+        self.read_only_fs.clone()
+    }
+}
+
+/// The entrypoint of the registry that init hands to services that may
+/// write files.
+///
+/// Its own registry must be init's registry, and its first message must
+/// carry the read-write filesystem. Lookups of the filesystem are answered
+/// with it, and every other request is forwarded to init's registry.
+pub fn serve_writable() {
+    let Signal::Message(first) = PARENT.recv() else {
+        error!("writable registry was not given a filesystem");
+        return;
+    };
+
+    let Some(fs) = first.caps.into_iter().next() else {
+        error!("writable registry was not given a filesystem");
+        return;
+    };
+
+    loop {
+        let Signal::Message(msg) = PARENT.recv() else {
+            continue;
+        };
+
+        let request = serde_json::from_slice::<RegistryRequest>(&msg.data);
+        let reply = msg.caps.first();
+
+        match (request, reply) {
+            (Ok(RegistryRequest::Get { name }), Some(reply)) if name == FILESYSTEM_SERVICE => {
+                reply.send_json(&RegistryResponse::Get(true), &[&fs]);
+            }
+            (Ok(RegistryRequest::Watch { name }), Some(reply)) if name == FILESYSTEM_SERVICE => {
+                let response = RegistryResponse::Watch {
+                    name,
+                    present: true,
+                };
+
+                reply.send_json(&response, &[&fs]);
+            }
+            _ => {
+                let caps: Vec<&Capability> = msg.caps.iter().collect();
+                REGISTRY.as_ref().send(&msg.data, &caps);
+            }
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, HashSet};
+
+use hearth_guest::PeerRole;
+use kindling_host::prelude::*;
+use serde::Deserialize;
+
+/// The default number of seconds to wait for a service's dependencies.
+pub const DEFAULT_DEPENDS_TIMEOUT: f32 = 10.0;
+
+/// What to do with a service whose dependencies don't appear in time.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnTimeout {
+    /// Start the service anyway.
+    #[default]
+    Start,
+
+    /// Don't start the service.
+    Fail,
+}
+
+/// The contents of a service's `service.toml`.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ServiceConfig {
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// The roles (`client` or `server`) that this service runs on. Empty
+    /// targets run on every role. Services with any other target fail to
+    /// load.
+    #[serde(default)]
+    pub targets: Vec<String>,
+
+    /// Registry names of the services that must be registered before this
+    /// service is started.
+    ///
+    /// Init registers each service that it starts under its name, so these
+    /// may name other init services as well as host services.
+    #[serde(default)]
+    pub depends: Vec<String>,
+
+    /// How long to wait for `depends` in seconds.
+    #[serde(default = "default_depends_timeout")]
+    pub depends_timeout: f32,
+
+    #[serde(default)]
+    pub on_timeout: OnTimeout,
+
+    /// Deployment-specific configuration sent to the service when it's
+    /// started. See [Service::start_args].
+    #[serde(default)]
+    pub config: Option<toml::Table>,
+
+    /// If true, the service is given read-write access to the filesystem.
+    /// Otherwise, it can only read files.
+    #[serde(default)]
+    pub writes_files: bool,
+
+    /// Whether the service expects a [ServiceArgs] message when it's
+    /// started. Defaults to whether it has a `config` table.
+    ///
+    /// [ServiceArgs]: hearth_guest::init::ServiceArgs
+    #[serde(default)]
+    pub args: Option<bool>,
+}
+
+impl ServiceConfig {
+    /// Checks that every target names a known role.
+    pub fn check_targets(&self) -> Result<(), String> {
+        let roles = [PeerRole::Client, PeerRole::Server];
+
+        for target in self.targets.iter() {
+            if !roles.iter().any(|role| role.as_str() == target) {
+                return Err(format!(
+                    "unknown target role {:?} (expected \"client\" or \"server\")",
+                    target
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tests if this service should run on a runtime with the given role.
+    ///
+    /// Runtimes with no role run every service.
+    pub fn targets_role(&self, role: Option<PeerRole>) -> bool {
+        match role {
+            Some(role) if !self.targets.is_empty() => {
+                self.targets.iter().any(|target| target == role.as_str())
+            }
+            _ => true,
+        }
+    }
+
+    /// Tests if this service expects a [ServiceArgs] message when it's
+    /// started.
+    ///
+    /// [ServiceArgs]: hearth_guest::init::ServiceArgs
+    pub fn expects_args(&self) -> bool {
+        self.args.unwrap_or(self.config.is_some())
+    }
+}
+
+fn default_depends_timeout() -> f32 {
+    DEFAULT_DEPENDS_TIMEOUT
+}
+
+/// A service found in the init directory.
+pub struct Service {
+    /// The name of this service's directory, which is also its registry name.
+    pub name: String,
+
+    /// The path to this service's module.
+    pub module: String,
+
+    pub config: ServiceConfig,
+
+    /// The arguments to send to this service when it's started.
+    pub args: serde_json::Value,
+
+    /// The environment variables to send to this service when it's started.
+    pub env: HashMap<String, String>,
+}
+
+impl Service {
+    /// Loads a service from its directory in the init directory.
+    ///
+    /// Fails with a description of the error if the service has a
+    /// `service.toml` that can't be parsed or that targets an unknown role.
+    pub fn load(search_dir: &str, name: String) -> Result<Self, String> {
+        let config_path = format!("{}/{}/service.toml", search_dir, name);
+
+        let config = match read_file(&config_path) {
+            Err(err) => {
+                debug!("{:?} has no service.toml ({:?})", name, err);
+                ServiceConfig::default()
+            }
+            Ok(data) => String::from_utf8(data)
+                .map_err(|err| err.to_string())
+                .and_then(|src| toml::from_str(&src).map_err(|err| err.to_string()))
+                .map_err(|err| format!("failed to parse {:?}: {}", config_path, err))?,
+        };
+
+        config
+            .check_targets()
+            .map_err(|err| format!("invalid {:?}: {}", config_path, err))?;
+
+        Ok(Self {
+            module: format!("{}/{}/service.wasm", search_dir, name),
+            name,
+            config,
+            args: serde_json::Value::Null,
+            env: HashMap::new(),
+        })
+    }
+
+    /// Gets the arguments to start this service with: the `config` table of
+    /// its `service.toml`, merged with its arguments from the init config.
+    pub fn start_args(&self) -> serde_json::Value {
+        let config = self
+            .config
+            .config
+            .as_ref()
+            .map(|config| serde_json::to_value(config).unwrap());
+
+        merge_args(config, self.args.clone())
+    }
+}
+
+/// Merges a service's `service.toml` config with its init config arguments.
+///
+/// If both are objects, their fields are combined with the arguments taking
+/// precedence. Otherwise, the arguments are used unless they're null.
+pub fn merge_args(config: Option<serde_json::Value>, args: serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+
+    match (config, args) {
+        (Some(Value::Object(mut config)), Value::Object(args)) => {
+            config.extend(args);
+            Value::Object(config)
+        }
+        (Some(config), Value::Null) => config,
+        (_, args) => args,
+    }
+}
+
+/// Finds every dependency cycle between the given services.
+///
+/// Each cycle is returned as the path of service names that forms it, with
+/// the first name repeated at the end. Dependencies on names that aren't in
+/// `services` can't form cycles and are ignored.
+pub fn find_cycles(services: &[Service]) -> Vec<Vec<String>> {
+    let graph: HashMap<&str, Vec<&str>> = services
+        .iter()
+        .map(|service| {
+            let depends = service.config.depends.iter().map(String::as_str).collect();
+            (service.name.as_str(), depends)
+        })
+        .collect();
+
+    let mut cycles = Vec::new();
+    let mut done = HashSet::new();
+    let mut stack = Vec::new();
+
+    for service in services {
+        visit(&graph, &service.name, &mut stack, &mut done, &mut cycles);
+    }
+
+    cycles
+}
+
+fn visit<'a>(
+    graph: &HashMap<&'a str, Vec<&'a str>>,
+    name: &'a str,
+    stack: &mut Vec<&'a str>,
+    done: &mut HashSet<&'a str>,
+    cycles: &mut Vec<Vec<String>>,
+) {
+    if done.contains(name) {
+        return;
+    }
+
+    if let Some(start) = stack.iter().position(|visiting| *visiting == name) {
+        let mut cycle: Vec<String> = stack[start..].iter().map(ToString::to_string).collect();
+        cycle.push(name.to_string());
+        cycles.push(cycle);
+        return;
+    }
+
+    let Some(depends) = graph.get(name) else {
+        return;
+    };
+
+    stack.push(name);
+
+    for dep in depends {
+        visit(graph, dep, stack, done, cycles);
+    }
+
+    stack.pop();
+    done.insert(name);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(name: &str, depends: &[&str]) -> Service {
+        Service {
+            name: name.to_string(),
+            module: format!("init/{}/service.wasm", name),
+            config: ServiceConfig {
+                depends: depends.iter().map(ToString::to_string).collect(),
+                ..Default::default()
+            },
+            args: serde_json::Value::Null,
+            env: HashMap::new(),
+        }
+    }
+
+    fn targets(targets: &[&str]) -> ServiceConfig {
+        ServiceConfig {
+            targets: targets.iter().map(ToString::to_string).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn untargeted_services_run_everywhere() {
+        let config = targets(&[]);
+        assert!(config.targets_role(None));
+        assert!(config.targets_role(Some(PeerRole::Client)));
+        assert!(config.targets_role(Some(PeerRole::Server)));
+    }
+
+    #[test]
+    fn targeted_services_run_on_their_roles() {
+        let config = targets(&["server"]);
+        assert!(config.targets_role(None));
+        assert!(!config.targets_role(Some(PeerRole::Client)));
+        assert!(config.targets_role(Some(PeerRole::Server)));
+
+        let config = targets(&["client", "server"]);
+        assert!(config.targets_role(Some(PeerRole::Client)));
+        assert!(config.targets_role(Some(PeerRole::Server)));
+    }
+
+    #[test]
+    fn unknown_targets_are_rejected() {
+        assert_eq!(targets(&["client", "server"]).check_targets(), Ok(()));
+
+        let err = targets(&["client", "sever"]).check_targets().unwrap_err();
+        assert!(err.contains("\"sever\""), "{}", err);
+    }
+
+    #[test]
+    fn args_override_config_fields() {
+        use serde_json::json;
+
+        let config = json!({ "greeting": "hello", "volume": 3 });
+        let args = json!({ "greeting": "hi" });
+        let merged = merge_args(Some(config.clone()), args);
+        assert_eq!(merged, json!({ "greeting": "hi", "volume": 3 }));
+
+        assert_eq!(merge_args(Some(config.clone()), json!(null)), config);
+        assert_eq!(merge_args(Some(config), json!([1, 2])), json!([1, 2]));
+        assert_eq!(merge_args(None, json!(null)), json!(null));
+        assert_eq!(merge_args(None, json!({ "a": 1 })), json!({ "a": 1 }));
+    }
+
+    #[test]
+    fn acyclic_services_have_no_cycles() {
+        let services = [
+            service("a", &["b", "c"]),
+            service("b", &["c"]),
+            service("c", &["hearth.canvas.CanvasFactory"]),
+        ];
+
+        assert!(find_cycles(&services).is_empty());
+    }
+
+    #[test]
+    fn finds_self_dependencies() {
+        let services = [service("a", &["a"])];
+        assert_eq!(find_cycles(&services), [["a", "a"]]);
+    }
+
+    #[test]
+    fn finds_each_cycle_once() {
+        let services = [
+            service("a", &["b"]),
+            service("b", &["c"]),
+            service("c", &["a"]),
+            service("d", &["a", "e"]),
+            service("e", &["d"]),
+        ];
+
+        let cycles = find_cycles(&services);
+        assert_eq!(cycles, [vec!["a", "b", "c", "a"], vec!["d", "e", "d"]]);
+    }
+}
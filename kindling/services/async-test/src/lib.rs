@@ -0,0 +1,67 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use hearth_guest::{
+    executor::{run_async, select, Either},
+    Mailbox, Permissions, Signal, PARENT,
+};
+use kindling_host::prelude::*;
+
+hearth_guest::export_metadata!();
+
+#[no_mangle]
+pub extern "C" fn run() {
+    let ticks = Mailbox::new();
+    let ticks_cap = ticks.make_capability(Permissions::SEND);
+    let _interval = ScheduledMessage::send_interval(100, &(), &ticks_cap);
+
+    let messages = Mailbox::new();
+    let messages_cap = messages.make_capability(Permissions::SEND);
+    let child = spawn_fn(child, None);
+    child.send(&[], &[&messages_cap]);
+
+    let count = run_async(async {
+        let mut count = 0;
+
+        loop {
+            match select(ticks.recv_async(), messages.recv_async()).await {
+                Either::Left(_) => count += 1,
+                Either::Right(_) => return count,
+            }
+        }
+    });
+
+    assert!(count > 0, "message arrived before any timer ticks");
+    info!("received message after {} ticks", count);
+
+    // a sleep works as a timeout on another receive
+    match run_async(select(sleep_async(0.25), messages.recv_async())) {
+        Either::Left(()) => info!("timed out waiting for a second message"),
+        Either::Right(signal) => panic!("unexpected signal: {:?}", signal),
+    }
+}
+
+/// Replies to the parent after a delay.
+fn child() {
+    let Signal::Message(msg) = PARENT.recv() else {
+        panic!("expected a message");
+    };
+
+    sleep(0.5);
+    msg.caps[0].send(&[], &[]);
+}
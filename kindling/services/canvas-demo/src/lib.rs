@@ -0,0 +1,121 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use hearth_guest::canvas::*;
+use kindling_host::prelude::*;
+
+hearth_guest::export_metadata!();
+
+/// The width and height of the canvas in pixels.
+const CANVAS_SIZE: u32 = 64;
+
+/// The width and height of the sprite in pixels.
+const SPRITE_SIZE: u32 = 8;
+
+/// The palette index of the background.
+const BG: u8 = 0;
+
+/// The palette index of the sprite's outline.
+const OUTLINE: u8 = 1;
+
+/// The palette index of the sprite's fill.
+const FILL: u8 = 2;
+
+/// Creates a buffer of paletted pixels filled with a single index.
+fn fill(width: u32, height: u32, index: u8) -> Pixels {
+    Pixels {
+        width,
+        height,
+        data: vec![index; (width * height) as usize],
+    }
+}
+
+/// Creates the sprite's pixels: a filled square with an outline.
+fn sprite() -> Pixels {
+    let mut pixels = fill(SPRITE_SIZE, SPRITE_SIZE, FILL);
+
+    for y in 0..SPRITE_SIZE {
+        for x in 0..SPRITE_SIZE {
+            if x == 0 || y == 0 || x == SPRITE_SIZE - 1 || y == SPRITE_SIZE - 1 {
+                pixels.data[(y * SPRITE_SIZE + x) as usize] = OUTLINE;
+            }
+        }
+    }
+
+    pixels
+}
+
+#[no_mangle]
+pub extern "C" fn run() {
+    let format = PixelFormat::Paletted {
+        palette: vec![
+            [0x19, 0x17, 0x24, 0xff],
+            [0xeb, 0xbc, 0xba, 0xff],
+            [0xc4, 0xa7, 0xe7, 0xff],
+        ],
+    };
+
+    let canvas = Canvas::with_format(
+        Position {
+            origin: (0.0, 0.0, -1.0).into(),
+            orientation: Default::default(),
+            half_size: (1.0, 1.0).into(),
+        },
+        fill(CANVAS_SIZE, CANVAS_SIZE, BG),
+        CanvasSamplingMode::Nearest,
+        format,
+    );
+
+    let sprite = sprite();
+    let eraser = fill(SPRITE_SIZE, SPRITE_SIZE, BG);
+    let max = (CANVAS_SIZE - SPRITE_SIZE) as i32;
+    let (mut x, mut y) = (0i32, max / 3);
+    let (mut dx, mut dy) = (1i32, 1i32);
+
+    let timer = Timer::new();
+
+    loop {
+        // erase the sprite at its old position
+        canvas.blit(Blit {
+            x: x as u32,
+            y: y as u32,
+            pixels: eraser.clone(),
+        });
+
+        // bounce off of the canvas's edges
+        if !(0..=max).contains(&(x + dx)) {
+            dx = -dx;
+        }
+
+        if !(0..=max).contains(&(y + dy)) {
+            dy = -dy;
+        }
+
+        x += dx;
+        y += dy;
+
+        // draw the sprite at its new position
+        canvas.blit(Blit {
+            x: x as u32,
+            y: y as u32,
+            pixels: sprite.clone(),
+        });
+
+        timer.tick(1.0 / 30.0);
+    }
+}
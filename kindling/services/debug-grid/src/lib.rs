@@ -17,36 +17,46 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use hearth_guest::{debug_draw::*, Color};
-use kindling_host::prelude::{glam::vec3, DebugDraw};
+use kindling_host::prelude::{
+    glam::{vec3, Mat4},
+    DebugDraw,
+};
 
 #[no_mangle]
 pub extern "C" fn run() {
     let size = 15;
     let color = Color::from_rgb(0x6a, 0xf5, 0xfc);
     let grid_to_pos = |x: i32, y: i32| vec3(x as f32 * 5.0, -8.0, y as f32 * 5.0);
-    let vertex = |x, y, color| -> DebugDrawVertex {
-        DebugDrawVertex {
-            position: grid_to_pos(x, y),
-            color,
-        }
-    };
-
-    let mut vertices = Vec::new();
+    let dd = DebugDraw::new();
 
     for x in -size..=size {
-        vertices.push(vertex(x, -size, color));
-        vertices.push(vertex(x, size, color));
+        dd.add(
+            DebugDrawShape::LineStrip {
+                points: vec![grid_to_pos(x, -size), grid_to_pos(x, size)],
+                color,
+            },
+            None,
+        );
     }
 
     for y in -size..=size {
-        vertices.push(vertex(-size, y, color));
-        vertices.push(vertex(size, y, color));
+        dd.add(
+            DebugDrawShape::LineStrip {
+                points: vec![grid_to_pos(-size, y), grid_to_pos(size, y)],
+                color,
+            },
+            None,
+        );
     }
 
-    let dd = DebugDraw::new();
-    dd.update(DebugDrawMesh {
-        indices: (0..vertices.len() as u32).collect(),
-        vertices,
-    });
+    // mark the grid's origin and orientation
+    dd.add(
+        DebugDrawShape::Axes {
+            transform: Mat4::from_translation(grid_to_pos(0, 0)),
+            size: 5.0,
+        },
+        None,
+    );
+
     std::mem::forget(dd);
 }
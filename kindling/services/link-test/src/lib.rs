@@ -0,0 +1,37 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use hearth_guest::{wasm::ProcessDown, Mailbox};
+use kindling_host::prelude::*;
+
+hearth_guest::export_metadata!();
+
+#[no_mangle]
+pub extern "C" fn run() {
+    let (_child, endpoint) = spawn_fn_linked(child, None);
+
+    let mailbox = Mailbox::new();
+    mailbox.link(&endpoint);
+
+    let (down, _caps) = mailbox.recv_json::<ProcessDown>();
+    info!("child process {} exited: {:?}", down.pid, down.reason);
+}
+
+fn child() {
+    panic!("trapping on purpose");
+}
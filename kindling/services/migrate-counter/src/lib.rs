@@ -0,0 +1,44 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+use kindling_host::migrate::Migratable;
+use kindling_host::prelude::*;
+
+hearth_guest::export_metadata!();
+
+/// Counts up once a second. Migrate it with the migrator service and the new
+/// instance picks up where this one left off.
+#[no_mangle]
+pub extern "C" fn run() {
+    let mut counter = Migratable::new(0u64);
+
+    loop {
+        if let Some(msg) = counter.recv_timeout(Duration::from_secs(1)) {
+            if msg.as_shutdown().is_some() {
+                return;
+            }
+
+            continue;
+        }
+
+        *counter.state_mut() += 1;
+        info!("count: {}", counter.state());
+    }
+}
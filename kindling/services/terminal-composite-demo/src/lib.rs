@@ -0,0 +1,70 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use hearth_guest::{renderer::*, terminal::TerminalState, Lump};
+use kindling_host::prelude::{
+    glam::{vec3, Mat4, Vec3},
+    *,
+};
+
+hearth_guest::export_metadata!();
+
+type Renderer = RequestResponse<RendererRequest, RendererResponse>;
+
+/// The color of every face of the sky, as RGBA.
+const SKY_COLOR: [u8; 4] = [0x40, 0x80, 0xc0, 0xff];
+
+#[no_mangle]
+pub extern "C" fn run() {
+    // fill the scene with a single flat color so that any pixels the
+    // terminal pass clears stand out
+    let data = SKY_COLOR.repeat(6);
+    let texture = Lump::load(
+        &serde_json::to_vec(&TextureData {
+            label: None,
+            size: (1, 1).into(),
+            data,
+        })
+        .unwrap(),
+    )
+    .get_id();
+
+    let renderer = Renderer::new(REGISTRY.get_service("hearth.Renderer").unwrap());
+    let (result, _) = renderer.request(RendererRequest::SetSkybox { texture }, &[]);
+    result.unwrap();
+
+    // a terminal covering only the middle of the view. everything around it
+    // should stay the color of the sky.
+    let term = Terminal::new(TerminalState {
+        position: vec3(0.0, 0.0, -2.0),
+        orientation: Default::default(),
+        half_size: (0.6, 0.4).into(),
+        opacity: 1.0,
+        padding: Default::default(),
+        units_per_em: 0.05,
+        colors: Default::default(),
+    });
+
+    sleep(0.5);
+    term.input("echo the sky should surround this terminal\n".into());
+
+    // forget the terminal so that it doesn't drop when this function exits
+    std::mem::forget(term);
+
+    MAIN_WINDOW.set_camera(70.0, 0.01, Mat4::look_at_rh(Vec3::ZERO, -Vec3::Z, Vec3::Y));
+}
@@ -18,10 +18,13 @@
 
 use std::collections::HashMap;
 
-use hearth_guest::{terminal::TerminalState, Color};
-use kindling_host::prelude::{
-    glam::{vec3, Mat4, Vec3},
-    *,
+use hearth_guest::{terminal::TerminalState, Color, Mailbox};
+use kindling_host::{
+    prelude::{
+        glam::{vec3, Mat4, Vec3},
+        *,
+    },
+    terminal::focus_bounds,
 };
 
 hearth_guest::export_metadata!();
@@ -36,27 +39,31 @@ pub extern "C" fn run() {
         (1, 1, Palette::pretty_in_pink()),
     ];
 
-    // spawn each terminal using the terminal factory and a select palette
-    let terms = terminal_configs.into_iter().map(|(x, y, palette)| {
-        Terminal::new(TerminalState {
-            position: (x as f32 * 2.8 - 1.4, y as f32 * 2.8 - 1.4, 0.0).into(),
-            orientation: Default::default(),
-            half_size: (1.25, 1.25).into(),
-            opacity: 1.0,
-            padding: Default::default(),
-            units_per_em: 0.06,
-            colors: palette.to_ansi(),
+    // spawn each terminal using the terminal factory and a select palette,
+    // and make it focusable by clicking on it
+    let terms: Vec<_> = terminal_configs
+        .into_iter()
+        .map(|(x, y, palette)| {
+            let state = TerminalState {
+                position: (x as f32 * 2.8 - 1.4, y as f32 * 2.8 - 1.4, 0.0).into(),
+                orientation: Default::default(),
+                half_size: (1.25, 1.25).into(),
+                opacity: 1.0,
+                padding: Default::default(),
+                units_per_em: 0.06,
+                colors: palette.to_ansi(),
+            };
+
+            let focus = Focusable::new(Some(focus_bounds(&state))).unwrap();
+            (Terminal::new(state), focus)
         })
-    });
+        .collect();
 
     sleep(0.5);
 
     // enter and execute the pipes command in each terminal
-    for term in terms {
+    for (term, _focus) in terms.iter() {
         term.input("pipes\n".into());
-
-        // forget the terminals so that they dont drop when this function exits
-        std::mem::forget(term);
     }
 
     MAIN_WINDOW.set_camera(
@@ -64,6 +71,23 @@ pub extern "C" fn run() {
         0.01,
         Mat4::look_at_rh(vec3(0.3, 0.3, 3.0), Vec3::ZERO, Vec3::Y),
     );
+
+    // wait for a few frames to be drawn, then report how they were batched
+    sleep(0.5);
+    let stats = kindling_host::terminal::get_stats();
+    info!(
+        "Drew {} terminals in {} draw calls",
+        stats.terminals, stats.draw_calls
+    );
+
+    // type into whichever terminal was clicked last
+    let mailboxes: Vec<_> = terms.iter().map(|(_term, focus)| focus.mailbox()).collect();
+    loop {
+        let (index, signal) = Mailbox::poll(&mailboxes);
+        if let Some(event) = Focusable::parse(signal) {
+            terms[index].0.on_focus_event(event);
+        }
+    }
 }
 
 /// Helper struct for containing and identifying terminal colors.
@@ -0,0 +1,76 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use hearth_guest::{
+    terminal::{TerminalOutline, TerminalState},
+    Color,
+};
+use kindling_host::prelude::{
+    glam::{vec3, Mat4, Vec3},
+    *,
+};
+
+hearth_guest::export_metadata!();
+
+/// The distance of each terminal from the camera.
+const DISTANCES: [f32; 4] = [1.5, 4.0, 10.0, 25.0];
+
+#[no_mangle]
+pub extern "C" fn run() {
+    // spread the terminals out to the side as they recede so that none of
+    // them are hidden behind a closer one
+    let terms: Vec<_> = DISTANCES
+        .iter()
+        .enumerate()
+        .map(|(index, distance)| {
+            let x = index as f32 * distance * 0.4;
+            Terminal::new(TerminalState {
+                position: vec3(x, 0.0, -distance),
+                orientation: Default::default(),
+                half_size: (1.0, 0.6).into(),
+                opacity: 0.8,
+                padding: Default::default(),
+                units_per_em: 0.05,
+                colors: Default::default(),
+            })
+        })
+        .collect();
+
+    // outline every other terminal to compare against plain text
+    for term in terms.iter().skip(1).step_by(2) {
+        term.set_outline(Some(TerminalOutline {
+            color: Color(0xff000000),
+            width: 0.04,
+        }));
+    }
+
+    sleep(0.5);
+
+    for term in terms {
+        term.input("ls -la /\n".into());
+
+        // forget the terminals so that they dont drop when this function exits
+        std::mem::forget(term);
+    }
+
+    MAIN_WINDOW.set_camera(
+        70.0,
+        0.01,
+        Mat4::look_at_rh(Vec3::ZERO, vec3(0.2, 0.0, -1.0), Vec3::Y),
+    );
+}
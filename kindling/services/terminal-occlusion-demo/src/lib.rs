@@ -0,0 +1,88 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use hearth_guest::terminal::{TerminalDepthMode, TerminalState};
+use kindling_host::prelude::{
+    glam::{vec3, Mat4, Vec3},
+    *,
+};
+
+hearth_guest::export_metadata!();
+
+/// How long each arrangement is shown before the terminals swap places.
+const SWAP_INTERVAL: f32 = 2.0;
+
+/// Creates a terminal at a position in a given depth mode.
+fn spawn(x: f32, y: f32, z: f32, depth_mode: TerminalDepthMode) -> (Terminal, TerminalState) {
+    let state = TerminalState {
+        position: vec3(x, y, z),
+        orientation: Default::default(),
+        half_size: (1.0, 0.6).into(),
+        opacity: match depth_mode {
+            TerminalDepthMode::Opaque => 1.0,
+            TerminalDepthMode::Transparent => 0.7,
+        },
+        padding: Default::default(),
+        units_per_em: 0.06,
+        colors: Default::default(),
+    };
+
+    let term = Terminal::new(state.clone());
+    term.set_depth_mode(depth_mode);
+    (term, state)
+}
+
+#[no_mangle]
+pub extern "C" fn run() {
+    // two overlapping pairs of terminals, transparent ones on the left and
+    // opaque ones on the right. the first terminal of each pair starts out
+    // in front, and they swap places periodically, so each pair shows both
+    // a newer terminal behind an older one and the other way around.
+    let mut pairs: Vec<_> = [-1.2, 1.2]
+        .into_iter()
+        .zip([TerminalDepthMode::Transparent, TerminalDepthMode::Opaque])
+        .map(|(x, depth_mode)| {
+            let front = spawn(x - 0.3, 0.2, -0.5, depth_mode);
+            let back = spawn(x + 0.3, -0.2, -1.0, depth_mode);
+            [front, back]
+        })
+        .collect();
+
+    sleep(0.5);
+
+    for [(front, _), (back, _)] in pairs.iter() {
+        front.input("echo front\n".into());
+        back.input("echo back\n".into());
+    }
+
+    MAIN_WINDOW.set_camera(
+        70.0,
+        0.01,
+        Mat4::look_at_rh(vec3(0.0, 0.0, 2.5), vec3(0.0, 0.0, -0.75), Vec3::Y),
+    );
+
+    loop {
+        sleep(SWAP_INTERVAL);
+
+        for [(a, a_state), (b, b_state)] in pairs.iter_mut() {
+            std::mem::swap(&mut a_state.position.z, &mut b_state.position.z);
+            a.update(a_state.clone());
+            b.update(b_state.clone());
+        }
+    }
+}
@@ -0,0 +1,614 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Camera sources and per-frame camera smoothing.
+
+use std::ops::{Add, Mul, Sub};
+
+use glam::{Mat4, Quat, Vec2, Vec3};
+use hearth_rend3::rend3::types::{Camera, CameraProjection};
+use hearth_runtime::{
+    async_trait, cargo_process_metadata,
+    hearth_schema::window::*,
+    process::ProcessMetadata,
+    utils::{
+        MessageInfo, RequestInfo, RequestResponseProcess, ResponseInfo, RunnerContext,
+        ServiceRunner, SinkProcess,
+    },
+};
+use winit::event_loop::EventLoopProxy;
+
+use crate::window::WindowRxMessage;
+
+/// The ID of the window's own camera source, set by
+/// [WindowCommand::SetCamera] and [WindowCommand::SetCameraTarget].
+pub const DEFAULT_SOURCE: u32 = 0;
+
+/// Distances under which a smoothed camera is considered to have reached
+/// its target.
+const SETTLE_EPSILON: f32 = 1e-4;
+
+/// The longest time in seconds that the camera moves by in one frame, so
+/// that a long stall doesn't make it jump straight to its target.
+const MAX_STEP: f32 = 0.1;
+
+/// A camera's lens and its transform in world space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    /// Vertical field of view in degrees.
+    pub vfov: f32,
+
+    /// Near plane distance.
+    pub near: f32,
+
+    /// The camera's position.
+    pub position: Vec3,
+
+    /// The camera's rotation.
+    pub rotation: Quat,
+}
+
+impl Default for CameraPose {
+    fn default() -> Self {
+        Self::new(60.0, 0.1, Mat4::IDENTITY)
+    }
+}
+
+impl CameraPose {
+    /// Creates a pose by decomposing a view matrix into the position and
+    /// rotation of the camera. Any scale in the view matrix is discarded.
+    ///
+    /// A view matrix that can't be inverted yields a camera at the origin.
+    pub fn new(vfov: f32, near: f32, view: Mat4) -> Self {
+        let (_scale, rotation, position) = view.inverse().to_scale_rotation_translation();
+        let rotation = rotation.normalize();
+
+        if !position.is_finite() || !rotation.is_finite() {
+            return Self {
+                vfov,
+                near,
+                position: Vec3::ZERO,
+                rotation: Quat::IDENTITY,
+            };
+        }
+
+        Self {
+            vfov,
+            near,
+            position,
+            rotation,
+        }
+    }
+
+    /// Recomposes this pose's view matrix.
+    pub fn view(&self) -> Mat4 {
+        Mat4::from_rotation_translation(self.rotation, self.position).inverse()
+    }
+
+    /// Converts this pose to a rend3 camera.
+    pub fn to_camera(&self) -> Camera {
+        Camera {
+            projection: CameraProjection::Perspective {
+                vfov: self.vfov,
+                near: self.near,
+            },
+            view: self.view(),
+        }
+    }
+
+    /// Casts a world-space ray from the camera through a point on screen,
+    /// given in normalized device coordinates, for a viewport with the given
+    /// aspect ratio. Returns the ray's origin and normalized direction.
+    pub fn ray(&self, ndc: Vec2, aspect: f32) -> (Vec3, Vec3) {
+        let half_height = (self.vfov.to_radians() / 2.0).tan();
+        let local = Vec3::new(ndc.x * half_height * aspect, ndc.y * half_height, -1.0);
+        (self.position, (self.rotation * local).normalize())
+    }
+
+    /// Packs the field of view and near plane for interpolation.
+    fn lens(&self) -> Vec2 {
+        Vec2::new(self.vfov, self.near)
+    }
+
+    fn set_lens(&mut self, lens: Vec2) {
+        self.vfov = lens.x;
+        self.near = lens.y;
+    }
+}
+
+/// A camera pose and how a camera should move toward it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraTarget {
+    pub pose: CameraPose,
+    pub smoothing: CameraSmoothing,
+}
+
+impl CameraTarget {
+    /// Creates a target that the camera snaps to.
+    pub fn snap(pose: CameraPose) -> Self {
+        Self {
+            pose,
+            smoothing: CameraSmoothing::None,
+        }
+    }
+}
+
+/// Advances a critically damped spring with its rest position at zero by
+/// `dt` seconds. Returns the new offset and velocity.
+fn spring<T>(offset: T, velocity: T, frequency: f32, dt: f32) -> (T, T)
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T>,
+{
+    // closed-form solution, so it's stable for any time step
+    let decay = (-frequency * dt).exp();
+    let temp = (velocity + offset * frequency) * dt;
+    let offset = (offset + temp) * decay;
+    let velocity = (velocity - temp * frequency) * decay;
+    (offset, velocity)
+}
+
+/// Computes the rotation from `target` to `current` as a scaled axis.
+fn rotation_offset(current: Quat, target: Quat) -> Vec3 {
+    let offset = current * target.inverse();
+
+    // take the shortest arc
+    let offset = if offset.w < 0.0 { -offset } else { offset };
+    offset.to_scaled_axis()
+}
+
+/// A camera that moves toward a target every frame.
+#[derive(Clone, Debug, Default)]
+struct SmoothCamera {
+    current: CameraPose,
+    velocity: Vec3,
+    angular_velocity: Vec3,
+    lens_velocity: Vec2,
+}
+
+impl SmoothCamera {
+    /// Moves the camera toward a target by `dt` seconds. Returns true if it
+    /// hasn't reached the target yet.
+    fn step(&mut self, target: &CameraTarget, dt: f32) -> bool {
+        let goal = &target.pose;
+        let current = &mut self.current;
+
+        match target.smoothing {
+            CameraSmoothing::Exponential { half_life } if half_life > 0.0 => {
+                let t = 1.0 - 0.5f32.powf(dt / half_life);
+                current.position = current.position.lerp(goal.position, t);
+                current.rotation = current.rotation.slerp(goal.rotation, t).normalize();
+                current.set_lens(current.lens().lerp(goal.lens(), t));
+                self.velocity = Vec3::ZERO;
+                self.angular_velocity = Vec3::ZERO;
+                self.lens_velocity = Vec2::ZERO;
+            }
+            CameraSmoothing::Spring { frequency } if frequency > 0.0 => {
+                let offset = current.position - goal.position;
+                let (offset, velocity) = spring(offset, self.velocity, frequency, dt);
+                current.position = goal.position + offset;
+                self.velocity = velocity;
+
+                let offset = rotation_offset(current.rotation, goal.rotation);
+                let (offset, velocity) = spring(offset, self.angular_velocity, frequency, dt);
+                current.rotation = (Quat::from_scaled_axis(offset) * goal.rotation).normalize();
+                self.angular_velocity = velocity;
+
+                let offset = current.lens() - goal.lens();
+                let (offset, velocity) = spring(offset, self.lens_velocity, frequency, dt);
+                current.set_lens(goal.lens() + offset);
+                self.lens_velocity = velocity;
+            }
+            _ => {}
+        }
+
+        let settled = current.position.distance(goal.position) < SETTLE_EPSILON
+            && rotation_offset(current.rotation, goal.rotation).length() < SETTLE_EPSILON
+            && current.lens().distance(goal.lens()) < SETTLE_EPSILON
+            && self.velocity.length() < SETTLE_EPSILON
+            && self.angular_velocity.length() < SETTLE_EPSILON
+            && self.lens_velocity.length() < SETTLE_EPSILON;
+
+        // snapping also covers CameraSmoothing::None and invalid parameters
+        if settled || !is_smoothed(target.smoothing) {
+            *self = Self {
+                current: *goal,
+                ..Default::default()
+            };
+
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Whether a smoothing mode has usable parameters.
+fn is_smoothed(smoothing: CameraSmoothing) -> bool {
+    match smoothing {
+        CameraSmoothing::None => false,
+        CameraSmoothing::Exponential { half_life } => half_life > 0.0,
+        CameraSmoothing::Spring { frequency } => frequency > 0.0,
+    }
+}
+
+/// A source of camera targets with a priority.
+#[derive(Debug)]
+struct CameraSource {
+    id: u32,
+    priority: u32,
+    target: Option<CameraTarget>,
+}
+
+/// The camera sources of a window and the smoothed camera they drive.
+///
+/// The window is drawn with the target of the highest-priority source that
+/// has one. Later sources win ties.
+#[derive(Debug)]
+pub struct CameraSources {
+    sources: Vec<CameraSource>,
+    camera: SmoothCamera,
+    moving: bool,
+}
+
+impl Default for CameraSources {
+    fn default() -> Self {
+        Self {
+            sources: vec![CameraSource {
+                id: DEFAULT_SOURCE,
+                priority: 0,
+                target: None,
+            }],
+            camera: SmoothCamera::default(),
+            moving: false,
+        }
+    }
+}
+
+impl CameraSources {
+    /// Adds a new source without a target.
+    pub fn add(&mut self, id: u32, priority: u32) {
+        self.sources.push(CameraSource {
+            id,
+            priority,
+            target: None,
+        });
+    }
+
+    /// Removes a source. The window's own source can't be removed.
+    pub fn remove(&mut self, id: u32) {
+        if id != DEFAULT_SOURCE {
+            self.sources.retain(|source| source.id != id);
+        }
+    }
+
+    /// Sets the target of a source. Unknown sources are ignored.
+    pub fn set(&mut self, id: u32, target: CameraTarget) {
+        if let Some(source) = self.sources.iter_mut().find(|source| source.id == id) {
+            source.target = Some(target);
+        }
+    }
+
+    /// Gets the target of the active source, if any source has a target.
+    fn active(&self) -> Option<&CameraTarget> {
+        self.sources
+            .iter()
+            .filter_map(|source| Some((source.priority, source.target.as_ref()?)))
+            .max_by_key(|(priority, _)| *priority)
+            .map(|(_, target)| target)
+    }
+
+    /// Moves the camera toward the active target by `dt` seconds and
+    /// returns the camera to draw with.
+    ///
+    /// `dt` is clamped to [MAX_STEP]. A negative or NaN `dt` doesn't move the
+    /// camera.
+    pub fn update(&mut self, dt: f32) -> Camera {
+        // f32::max() discards NaN
+        let dt = dt.max(0.0).min(MAX_STEP);
+
+        self.moving = match self.active().copied() {
+            Some(target) => self.camera.step(&target, dt),
+            None => false,
+        };
+
+        self.camera.current.to_camera()
+    }
+
+    /// Gets the pose that the camera was last drawn with.
+    pub fn pose(&self) -> &CameraPose {
+        &self.camera.current
+    }
+
+    /// Whether the camera is still moving toward its target, in which case
+    /// the window should keep drawing frames.
+    pub fn is_moving(&self) -> bool {
+        self.moving
+    }
+}
+
+/// A service that creates camera sources for the window. Accepts
+/// [CameraRequest].
+pub struct CameraService {
+    incoming: EventLoopProxy<WindowRxMessage>,
+    next_id: u32,
+}
+
+impl CameraService {
+    pub fn new(incoming: EventLoopProxy<WindowRxMessage>) -> Self {
+        Self {
+            incoming,
+            next_id: DEFAULT_SOURCE + 1,
+        }
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for CameraService {
+    type Request = CameraRequest;
+    type Response = CameraResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, CameraRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let CameraRequest::CreateSource { priority } = request.data;
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let message = WindowRxMessage::AddCameraSource { id, priority };
+        if self.incoming.send_event(message).is_err() {
+            let response: CameraResponse = Err(CameraError::Unavailable);
+            return response.into();
+        }
+
+        let mut meta = cargo_process_metadata!();
+        meta.name = Some("CameraSource".to_string());
+        meta.description = Some("A window camera source. Accepts CameraCommand.".to_string());
+
+        let sink = CameraSourceSink {
+            incoming: self.incoming.clone(),
+            id,
+        };
+
+        let child = request.spawn(meta, sink);
+
+        ResponseInfo {
+            data: Ok(CameraSuccess::Source),
+            caps: vec![child],
+        }
+    }
+}
+
+impl ServiceRunner for CameraService {
+    const NAME: &'static str = CAMERA_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Creates camera sources for the window. Accepts CameraRequest.".to_string());
+        meta
+    }
+}
+
+/// A single camera source. Accepts [CameraCommand].
+struct CameraSourceSink {
+    incoming: EventLoopProxy<WindowRxMessage>,
+    id: u32,
+}
+
+impl Drop for CameraSourceSink {
+    fn drop(&mut self) {
+        let _ = self
+            .incoming
+            .send_event(WindowRxMessage::RemoveCameraSource(self.id));
+    }
+}
+
+#[async_trait]
+impl SinkProcess for CameraSourceSink {
+    type Message = CameraCommand;
+
+    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, CameraCommand>) {
+        let target = match message.data {
+            CameraCommand::Set { vfov, near, view } => {
+                CameraTarget::snap(CameraPose::new(vfov, near, view))
+            }
+            CameraCommand::SetTarget {
+                vfov,
+                near,
+                view,
+                smoothing,
+            } => CameraTarget {
+                pose: CameraPose::new(vfov, near, view),
+                smoothing,
+            },
+            CameraCommand::Release => {
+                let message = WindowRxMessage::RemoveCameraSource(self.id);
+                let _ = self.incoming.send_event(message);
+                return;
+            }
+        };
+
+        let message = WindowRxMessage::SetCamera {
+            source: self.id,
+            target,
+        };
+
+        let _ = self.incoming.send_event(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose_at(position: Vec3, yaw: f32) -> CameraPose {
+        let transform = Mat4::from_rotation_translation(Quat::from_rotation_y(yaw), position);
+        CameraPose::new(60.0, 0.1, transform.inverse())
+    }
+
+    #[test]
+    fn pose_round_trips_view() {
+        let view = Mat4::look_at_rh(Vec3::new(1.0, 2.0, 3.0), Vec3::ZERO, Vec3::Y);
+        let pose = CameraPose::new(60.0, 0.1, view);
+        assert!(pose.view().abs_diff_eq(view, 1e-5));
+        assert!(pose.position.abs_diff_eq(Vec3::new(1.0, 2.0, 3.0), 1e-5));
+    }
+
+    #[test]
+    fn singular_view_is_finite() {
+        let pose = CameraPose::new(60.0, 0.1, Mat4::ZERO);
+        assert_eq!(pose.position, Vec3::ZERO);
+        assert_eq!(pose.rotation, Quat::IDENTITY);
+        assert!(pose.view().is_finite());
+    }
+
+    #[test]
+    fn ray_through_screen() {
+        let view = Mat4::look_at_rh(Vec3::Z, Vec3::ZERO, Vec3::Y);
+        let pose = CameraPose::new(90.0, 0.1, view);
+
+        let (origin, direction) = pose.ray(Vec2::ZERO, 2.0);
+        assert!(origin.abs_diff_eq(Vec3::Z, 1e-5));
+        assert!(direction.abs_diff_eq(-Vec3::Z, 1e-5));
+
+        // the top-right corner is 45 degrees up and wider by the aspect
+        let (_, direction) = pose.ray(Vec2::ONE, 2.0);
+        let expected = Vec3::new(2.0, 1.0, -1.0).normalize();
+        assert!(direction.abs_diff_eq(expected, 1e-5));
+    }
+
+    #[test]
+    fn exponential_halves_distance() {
+        let mut camera = SmoothCamera {
+            current: pose_at(Vec3::ZERO, 0.0),
+            ..Default::default()
+        };
+
+        let target = CameraTarget {
+            pose: pose_at(Vec3::X * 2.0, 0.0),
+            smoothing: CameraSmoothing::Exponential { half_life: 0.5 },
+        };
+
+        assert!(camera.step(&target, 0.5));
+        assert!((camera.current.position.x - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn rotation_is_slerped() {
+        let mut camera = SmoothCamera {
+            current: pose_at(Vec3::ZERO, 0.0),
+            ..Default::default()
+        };
+
+        let target = CameraTarget {
+            pose: pose_at(Vec3::ZERO, 2.5),
+            smoothing: CameraSmoothing::Exponential { half_life: 1.0 },
+        };
+
+        camera.step(&target, 1.0);
+
+        // halfway along the arc, not a lerped matrix that shrinks and skews
+        let expected = Quat::from_rotation_y(1.25);
+        let angle = camera.current.rotation.angle_between(expected);
+        assert!(angle < 1e-3, "off by {} radians", angle);
+
+        let view = camera.current.view();
+        assert!((view.determinant() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn spring_settles_without_overshoot() {
+        let mut camera = SmoothCamera {
+            current: pose_at(Vec3::ZERO, 0.0),
+            ..Default::default()
+        };
+
+        let target = CameraTarget {
+            pose: pose_at(Vec3::X, 1.0),
+            smoothing: CameraSmoothing::Spring { frequency: 10.0 },
+        };
+
+        let mut last = 0.0;
+        let mut frames = 0;
+        while camera.step(&target, 1.0 / 60.0) {
+            let x = camera.current.position.x;
+            assert!(x <= 1.0 && x >= last, "overshot at {}", x);
+            last = x;
+            frames += 1;
+            assert!(frames < 600, "spring never settled");
+        }
+
+        assert_eq!(camera.current, target.pose);
+    }
+
+    #[test]
+    fn priority_overrides_and_hands_back() {
+        let mut sources = CameraSources::default();
+        let default = pose_at(Vec3::ZERO, 0.0);
+        let cinematic = pose_at(Vec3::Y, 0.0);
+
+        sources.set(DEFAULT_SOURCE, CameraTarget::snap(default));
+        sources.add(1, 10);
+        assert_eq!(sources.update(0.1).view, default.view());
+
+        sources.set(1, CameraTarget::snap(cinematic));
+        assert_eq!(sources.update(0.1).view, cinematic.view());
+
+        sources.remove(1);
+        assert_eq!(sources.update(0.1).view, default.view());
+
+        // the window's own source is never removed
+        sources.remove(DEFAULT_SOURCE);
+        assert_eq!(sources.update(0.1).view, default.view());
+    }
+
+    #[test]
+    fn later_sources_win_ties() {
+        let mut sources = CameraSources::default();
+        sources.add(1, 5);
+        sources.add(2, 5);
+        sources.set(2, CameraTarget::snap(pose_at(Vec3::Z, 0.0)));
+        sources.set(1, CameraTarget::snap(pose_at(Vec3::X, 0.0)));
+        let camera = sources.update(0.1);
+        assert_eq!(camera.view, pose_at(Vec3::Z, 0.0).view());
+    }
+
+    #[test]
+    fn stalls_are_clamped() {
+        let mut sources = CameraSources::default();
+        sources.set(
+            DEFAULT_SOURCE,
+            CameraTarget {
+                pose: pose_at(Vec3::X, 0.0),
+                smoothing: CameraSmoothing::Exponential { half_life: 0.1 },
+            },
+        );
+
+        // a ten second stall only moves the camera by one step
+        sources.update(10.0);
+        assert!(sources.is_moving());
+        assert!((sources.pose().position.x - 0.5).abs() < 1e-4);
+
+        let before = *sources.pose();
+        sources.update(f32::NAN);
+        sources.update(-1.0);
+        assert_eq!(*sources.pose(), before);
+    }
+}
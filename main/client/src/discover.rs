@@ -0,0 +1,125 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Interactive discovery of servers on the local network.
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::time::Duration;
+
+use hearth_network::discovery::{browse, DiscoveredServer};
+use tracing::{error, info, warn};
+
+/// How long to browse the local network for servers.
+pub const BROWSE_DURATION: Duration = Duration::from_secs(3);
+
+/// Browses for servers and picks one to connect to, returning its address.
+///
+/// If only one compatible server is found, it's chosen automatically.
+/// Otherwise, the user is asked to choose one if standard input is a
+/// terminal.
+pub async fn choose_server() -> Option<String> {
+    info!("Browsing the local network for servers");
+    let found = tokio::task::spawn_blocking(|| browse(BROWSE_DURATION)).await;
+    let servers = match found {
+        Ok(Ok(servers)) => servers,
+        Ok(Err(err)) => {
+            error!("Failed to browse for servers: {}", err);
+            return None;
+        }
+        Err(err) => {
+            error!("Server browsing panicked: {}", err);
+            return None;
+        }
+    };
+
+    let (compatible, incompatible): (Vec<_>, Vec<_>) = servers
+        .into_iter()
+        .filter(|server| !server.addrs.is_empty())
+        .partition(DiscoveredServer::is_compatible);
+
+    for server in incompatible {
+        warn!(
+            "Ignoring {:?}: unsupported protocol version {:?}",
+            server.name, server.protocol_version
+        );
+    }
+
+    let server = match compatible.len() {
+        0 => {
+            error!("No servers found on the local network");
+            return None;
+        }
+        1 => compatible.into_iter().next()?,
+        _ if std::io::stdin().is_terminal() => {
+            let mut compatible = compatible;
+            let chosen = tokio::task::spawn_blocking(move || {
+                let index = prompt(&compatible)?;
+                Some(compatible.swap_remove(index))
+            });
+
+            chosen.await.ok().flatten()?
+        }
+        _ => {
+            print_servers(&compatible);
+            error!("Multiple servers found; choose one with --server");
+            return None;
+        }
+    };
+
+    let addr = server.addrs[0];
+    info!("Connecting to {:?} at {}", server.name, addr);
+    Some(addr.to_string())
+}
+
+/// Prints a numbered list of servers.
+fn print_servers(servers: &[DiscoveredServer]) {
+    println!("{:<4}{:<32}{:<48}PASSWORD", "#", "NAME", "ADDRESS");
+    for (index, server) in servers.iter().enumerate() {
+        let password = if server.password_required {
+            "yes"
+        } else {
+            "no"
+        };
+        let addr = server.addrs[0].to_string();
+        println!(
+            "{:<4}{:<32}{:<48}{}",
+            index + 1,
+            server.name,
+            addr,
+            password
+        );
+    }
+}
+
+/// Asks the user to choose a server by number until they give a valid one.
+/// Returns `None` if standard input is closed.
+fn prompt(servers: &[DiscoveredServer]) -> Option<usize> {
+    print_servers(servers);
+
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("Connect to server [1-{}]: ", servers.len());
+        let _ = std::io::stdout().flush();
+
+        let line = lines.next()?.ok()?;
+        match line.trim().parse::<usize>() {
+            Ok(choice) if (1..=servers.len()).contains(&choice) => return Some(choice - 1),
+            _ => println!("Please enter a number from 1 to {}", servers.len()),
+        }
+    }
+}
@@ -0,0 +1,601 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Keyboard focus for interactive surfaces in the world.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use glam::Vec3;
+use hearth_runtime::{
+    async_trait, cargo_process_metadata,
+    flue::{CapabilityRef, MailboxGroup, OwnedCapability, Permissions, PostOffice, Table},
+    hearth_schema::window::*,
+    process::ProcessMetadata,
+    utils::{RequestInfo, RequestResponseProcess, ResponseInfo, RunnerContext, ServiceRunner},
+};
+use tokio::sync::{mpsc, Mutex};
+use tracing::debug;
+
+/// Input from the window that the focus service acts on.
+#[derive(Clone, Debug)]
+pub enum FocusInput {
+    /// The left mouse button was pressed with the cursor over this
+    /// world-space ray.
+    Click { origin: Vec3, direction: Vec3 },
+
+    /// A keyboard or text input event for the focused target.
+    Event(WindowEvent),
+}
+
+/// A registered focusable target.
+struct FocusTarget<T> {
+    target: T,
+    bounds: Option<FocusBounds>,
+}
+
+/// Tracks focusable targets and which one of them is focused.
+///
+/// Every change in focus returns the [FocusEvent]s to send to the targets
+/// involved, in the order they must be sent.
+pub struct FocusManager<T> {
+    targets: BTreeMap<u32, FocusTarget<T>>,
+    focused: Option<u32>,
+    next_id: u32,
+}
+
+impl<T> Default for FocusManager<T> {
+    fn default() -> Self {
+        Self {
+            targets: BTreeMap::new(),
+            focused: None,
+            next_id: 0,
+        }
+    }
+}
+
+impl<T: Clone> FocusManager<T> {
+    /// Adds a target and returns its ID. IDs are never reused.
+    pub fn register(&mut self, target: T, bounds: Option<FocusBounds>) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.targets.insert(id, FocusTarget { target, bounds });
+        id
+    }
+
+    /// Moves or removes the bounds of a target.
+    pub fn set_bounds(&mut self, id: u32, bounds: Option<FocusBounds>) -> Result<(), FocusError> {
+        let target = self
+            .targets
+            .get_mut(&id)
+            .ok_or(FocusError::UnknownTarget(id))?;
+
+        target.bounds = bounds;
+        Ok(())
+    }
+
+    /// Removes a target. If it was focused, the focus is cleared and the
+    /// target is returned with [FocusEvent::Lost].
+    pub fn unregister(&mut self, id: u32) -> Result<Vec<(T, FocusEvent)>, FocusError> {
+        // take the lost event before the target is gone
+        let events = if self.focused == Some(id) {
+            self.set_focus(None)?
+        } else {
+            vec![]
+        };
+
+        self.targets
+            .remove(&id)
+            .ok_or(FocusError::UnknownTarget(id))?;
+
+        Ok(events)
+    }
+
+    /// Gets the ID of the focused target, if any.
+    pub fn focused(&self) -> Option<u32> {
+        self.focused
+    }
+
+    /// Focuses a target, or clears the focus if `None`.
+    ///
+    /// The previously focused target, if any, is sent [FocusEvent::Lost]
+    /// before the new one is sent [FocusEvent::Gained]. Refocusing the
+    /// focused target does nothing.
+    pub fn set_focus(&mut self, id: Option<u32>) -> Result<Vec<(T, FocusEvent)>, FocusError> {
+        if let Some(id) = id {
+            if !self.targets.contains_key(&id) {
+                return Err(FocusError::UnknownTarget(id));
+            }
+        }
+
+        if id == self.focused {
+            return Ok(vec![]);
+        }
+
+        let mut events = Vec::with_capacity(2);
+
+        if let Some(old) = self.focused.and_then(|old| self.targets.get(&old)) {
+            events.push((old.target.clone(), FocusEvent::Lost));
+        }
+
+        if let Some(new) = id.and_then(|id| self.targets.get(&id)) {
+            events.push((new.target.clone(), FocusEvent::Gained));
+        }
+
+        self.focused = id;
+        Ok(events)
+    }
+
+    /// Finds the nearest target whose bounds are hit by a ray.
+    pub fn pick(&self, origin: Vec3, direction: Vec3) -> Option<u32> {
+        self.targets
+            .iter()
+            .filter_map(|(id, target)| {
+                let distance = intersect(target.bounds.as_ref()?, origin, direction)?;
+                Some((*id, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(id, _)| id)
+    }
+
+    /// Clears the focus if a target has it.
+    pub fn blur(&mut self, id: u32) -> Result<Vec<(T, FocusEvent)>, FocusError> {
+        if !self.targets.contains_key(&id) {
+            return Err(FocusError::UnknownTarget(id));
+        }
+
+        if self.focused == Some(id) {
+            self.set_focus(None)
+        } else {
+            Ok(vec![])
+        }
+    }
+
+    /// Focuses the target under a click, or clears the focus if the click
+    /// missed every target.
+    pub fn click(&mut self, origin: Vec3, direction: Vec3) -> Vec<(T, FocusEvent)> {
+        let id = self.pick(origin, direction);
+        self.set_focus(id).unwrap_or_default()
+    }
+
+    /// Routes a window event to the focused target if it's keyboard input.
+    pub fn route(&self, event: WindowEvent) -> Option<(T, FocusEvent)> {
+        if !event.is_keyboard_input() {
+            return None;
+        }
+
+        let target = self.targets.get(&self.focused?)?;
+        Some((target.target.clone(), FocusEvent::Input(event)))
+    }
+}
+
+/// Intersects a ray with a rectangle. Returns the distance to the hit in
+/// units of the ray's direction.
+fn intersect(bounds: &FocusBounds, origin: Vec3, direction: Vec3) -> Option<f32> {
+    // the ray's parameter is the same in the rectangle's local space
+    let inverse = bounds.transform.inverse();
+    let origin = inverse.transform_point3(origin);
+    let direction = inverse.transform_vector3(direction);
+
+    if direction.z.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let distance = -origin.z / direction.z;
+    let hit = origin + direction * distance;
+    let inside = hit.x.abs() <= bounds.half_size.x && hit.y.abs() <= bounds.half_size.y;
+    (inside && distance >= 0.0).then_some(distance)
+}
+
+/// The focus service's targets and the table to send to them with.
+struct FocusState {
+    table: Table,
+    manager: FocusManager<OwnedCapability>,
+}
+
+impl FocusState {
+    /// Sends focus events to their targets in order.
+    ///
+    /// Failing to reach a target doesn't stop the rest of the events, so a
+    /// target that is going down never holds up the next target's
+    /// [FocusEvent::Gained].
+    async fn send(&self, events: Vec<(OwnedCapability, FocusEvent)>) {
+        for (target, event) in events {
+            let data = serde_json::to_vec(&event).unwrap();
+            let target = self.table.import_owned(target).unwrap();
+            let target = self.table.wrap_handle(target).unwrap();
+            if let Err(err) = target.send(&data, &[]).await {
+                debug!("Failed to send {:?} to focus target: {:?}", event, err);
+            }
+        }
+    }
+}
+
+/// A service that routes keyboard input to a single focused target.
+/// Accepts [FocusRequest].
+///
+/// The state is shared with a task that handles clicks and keyboard input
+/// from the window, and with the controllers of each target. Every change in
+/// focus happens under its lock, including sending the resulting events, so
+/// that no target misses a [FocusEvent::Lost] or receives events out of
+/// order.
+pub struct FocusService {
+    post: Arc<PostOffice>,
+    state: Arc<Mutex<FocusState>>,
+}
+
+impl FocusService {
+    /// Creates the service and starts handling window input from a channel.
+    ///
+    /// Keyboard input that arrives while nothing is focused is passed on to
+    /// `unfocused_tx`.
+    pub fn new(
+        post: Arc<PostOffice>,
+        mut input_rx: mpsc::UnboundedReceiver<FocusInput>,
+        unfocused_tx: mpsc::UnboundedSender<WindowEvent>,
+    ) -> Self {
+        let state = Arc::new(Mutex::new(FocusState {
+            table: Table::new(post.clone()),
+            manager: FocusManager::default(),
+        }));
+
+        tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Some(input) = input_rx.recv().await {
+                    let mut state = state.lock().await;
+                    let events = match input {
+                        FocusInput::Click { origin, direction } => {
+                            state.manager.click(origin, direction)
+                        }
+                        FocusInput::Event(event) => match state.manager.route(event.clone()) {
+                            Some(routed) => vec![routed],
+                            None => {
+                                let _ = unfocused_tx.send(event);
+                                continue;
+                            }
+                        },
+                    };
+
+                    state.send(events).await;
+                }
+            }
+        });
+
+        Self { post, state }
+    }
+
+    /// Unregisters a target once it goes down, sending it
+    /// [FocusEvent::Lost] if it was focused, and kills its controller.
+    fn unregister_when_down(
+        &self,
+        id: u32,
+        target: &CapabilityRef<'_>,
+        controller: &CapabilityRef<'_>,
+    ) {
+        let target = target.to_owned();
+        let controller = controller.to_owned();
+        let post = self.post.clone();
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let table = Table::new(post);
+            let group = MailboxGroup::new(&table);
+            let mailbox = group.create_mailbox().unwrap();
+            let target = table.import_owned(target).unwrap();
+            let target = table.wrap_handle(target).unwrap();
+            target.monitor(&mailbox).unwrap();
+            drop(target);
+
+            // nothing else can reach this mailbox, so any signal is the down
+            mailbox.recv(|_| ()).await;
+
+            let mut state = state.lock().await;
+            if let Ok(events) = state.manager.unregister(id) {
+                debug!("Unregistering focus target {} since it went down", id);
+                state.send(events).await;
+            }
+
+            // fails if the controller has already been killed
+            let controller = table.import_owned(controller).unwrap();
+            let _ = table.kill(controller);
+        });
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for FocusService {
+    type Request = FocusRequest;
+    type Response = FocusResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, FocusRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match &request.data {
+            FocusRequest::Register { bounds } => {
+                let Some(target) = request.cap_args.first() else {
+                    return FocusError::MissingCapability.into();
+                };
+
+                let id = {
+                    let mut state = self.state.lock().await;
+                    state.manager.register(target.to_owned(), *bounds)
+                };
+
+                let mut meta = cargo_process_metadata!();
+                meta.name = Some(format!("FocusTarget {}", id));
+                meta.description =
+                    Some("Controls a focus target. Accepts FocusTargetRequest.".to_string());
+
+                let controller = FocusTargetController {
+                    id,
+                    state: self.state.clone(),
+                };
+
+                let controller = request.spawn(meta, controller);
+
+                if target.get_permissions().contains(Permissions::MONITOR) {
+                    self.unregister_when_down(id, target, &controller);
+                }
+
+                ResponseInfo {
+                    data: Ok(FocusSuccess::Registered(id)),
+                    caps: vec![controller],
+                }
+            }
+            FocusRequest::GetFocus => {
+                let state = self.state.lock().await;
+                Ok(FocusSuccess::Focus(state.manager.focused())).into()
+            }
+        }
+    }
+}
+
+/// Controls a single focus target. Accepts [FocusTargetRequest].
+///
+/// Only the holder of a target's controller can change it, since capabilities
+/// don't identify who sent a request. The target is unregistered when its
+/// controller is killed.
+struct FocusTargetController {
+    id: u32,
+    state: Arc<Mutex<FocusState>>,
+}
+
+impl Drop for FocusTargetController {
+    fn drop(&mut self) {
+        let id = self.id;
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let mut state = state.lock().await;
+
+            // fails if the target has already been unregistered
+            if let Ok(events) = state.manager.unregister(id) {
+                state.send(events).await;
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for FocusTargetController {
+    type Request = FocusTargetRequest;
+    type Response = FocusResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, FocusTargetRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let mut state = self.state.lock().await;
+        let manager = &mut state.manager;
+
+        let events = match &request.data {
+            FocusTargetRequest::SetBounds(bounds) => {
+                let result = manager.set_bounds(self.id, *bounds);
+                return result.map(|_| FocusSuccess::Ok).into();
+            }
+            FocusTargetRequest::Focus => manager.set_focus(Some(self.id)),
+            FocusTargetRequest::Blur => manager.blur(self.id),
+            FocusTargetRequest::Unregister => manager.unregister(self.id),
+        };
+
+        // send focus events before replying so that they arrive first
+        match events {
+            Ok(events) => {
+                state.send(events).await;
+                Ok(FocusSuccess::Ok).into()
+            }
+            Err(err) => err.into(),
+        }
+    }
+}
+
+impl ServiceRunner for FocusService {
+    const NAME: &'static str = FOCUS_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Routes keyboard input to a single focused target. Accepts FocusRequest.".to_string(),
+        );
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::{vec2, Mat4};
+
+    /// A mock focusable target.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    enum Mock {
+        Left,
+        Right,
+    }
+
+    /// Summarizes sent events for comparison.
+    fn summarize(events: Vec<(Mock, FocusEvent)>) -> Vec<(Mock, &'static str)> {
+        events
+            .into_iter()
+            .map(|(target, event)| {
+                let kind = match event {
+                    FocusEvent::Gained => "gained",
+                    FocusEvent::Lost => "lost",
+                    FocusEvent::Input(_) => "input",
+                };
+
+                (target, kind)
+            })
+            .collect()
+    }
+
+    /// Creates unit-sized bounds facing +Z at an X offset.
+    fn bounds_at(x: f32) -> Option<FocusBounds> {
+        Some(FocusBounds {
+            transform: Mat4::from_translation(Vec3::new(x, 0.0, 0.0)),
+            half_size: vec2(0.5, 0.5),
+        })
+    }
+
+    /// Clicks straight down -Z from an X offset.
+    fn click_at(manager: &mut FocusManager<Mock>, x: f32) -> Vec<(Mock, &'static str)> {
+        let events = manager.click(Vec3::new(x, 0.0, 5.0), -Vec3::Z);
+        summarize(events)
+    }
+
+    #[test]
+    fn two_targets_exchange_focus() {
+        use Mock::*;
+
+        let mut manager = FocusManager::default();
+        let left = manager.register(Left, bounds_at(-2.0));
+        let right = manager.register(Right, bounds_at(2.0));
+
+        assert_eq!(click_at(&mut manager, -2.0), vec![(Left, "gained")]);
+        assert_eq!(manager.focused(), Some(left));
+
+        // the old target always loses focus before the new one gains it
+        let events = manager.set_focus(Some(right)).unwrap();
+        assert_eq!(summarize(events), vec![(Left, "lost"), (Right, "gained")]);
+
+        assert_eq!(
+            click_at(&mut manager, -2.0),
+            vec![(Right, "lost"), (Left, "gained")]
+        );
+
+        // clicking the focused target again changes nothing
+        assert!(click_at(&mut manager, -2.0).is_empty());
+
+        // clicking empty space clears the focus
+        assert_eq!(click_at(&mut manager, 0.0), vec![(Left, "lost")]);
+        assert_eq!(manager.focused(), None);
+    }
+
+    #[test]
+    fn keyboard_input_goes_to_focused_target() {
+        use Mock::*;
+
+        let mut manager = FocusManager::default();
+        let _left = manager.register(Left, None);
+        let right = manager.register(Right, None);
+
+        let key = WindowEvent::ReceivedCharacter('a');
+        assert!(manager.route(key.clone()).is_none());
+
+        manager.set_focus(Some(right)).unwrap();
+        let routed = manager.route(key).map(|(target, _)| target);
+        assert_eq!(routed, Some(Right));
+
+        // pointer events aren't routed by focus
+        let click = WindowEvent::CursorLeft {};
+        assert!(manager.route(click).is_none());
+    }
+
+    #[test]
+    fn ime_goes_to_focused_target() {
+        use Mock::*;
+
+        let mut manager = FocusManager::default();
+        let left = manager.register(Left, None);
+        let _right = manager.register(Right, None);
+
+        let commit = WindowEvent::Ime(Ime::Commit("日本".to_string()));
+        assert!(manager.route(commit.clone()).is_none());
+
+        manager.set_focus(Some(left)).unwrap();
+        match manager.route(commit) {
+            Some((Left, FocusEvent::Input(WindowEvent::Ime(Ime::Commit(text))))) => {
+                assert_eq!(text, "日本")
+            }
+            other => panic!("IME event was misrouted: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn removed_target_loses_focus() {
+        use Mock::*;
+
+        let mut manager = FocusManager::default();
+        let left = manager.register(Left, bounds_at(0.0));
+        let right = manager.register(Right, None);
+
+        manager.set_focus(Some(left)).unwrap();
+        let events = manager.unregister(left).unwrap();
+        assert_eq!(summarize(events), vec![(Left, "lost")]);
+        assert_eq!(manager.focused(), None);
+
+        // removing an unfocused target sends nothing
+        assert!(manager.unregister(right).unwrap().is_empty());
+        assert_eq!(
+            manager.set_focus(Some(left)).unwrap_err(),
+            FocusError::UnknownTarget(left)
+        );
+    }
+
+    #[test]
+    fn blur_only_clears_own_focus() {
+        use Mock::*;
+
+        let mut manager = FocusManager::default();
+        let left = manager.register(Left, None);
+        let right = manager.register(Right, None);
+
+        manager.set_focus(Some(left)).unwrap();
+        assert!(manager.blur(right).unwrap().is_empty());
+        assert_eq!(manager.focused(), Some(left));
+
+        let events = manager.blur(left).unwrap();
+        assert_eq!(summarize(events), vec![(Left, "lost")]);
+        assert_eq!(manager.focused(), None);
+    }
+
+    #[test]
+    fn nearest_target_is_picked() {
+        let mut manager = FocusManager::default();
+        let far = FocusBounds {
+            transform: Mat4::from_translation(Vec3::new(0.0, 0.0, -1.0)),
+            half_size: vec2(1.0, 1.0),
+        };
+
+        manager.register(Mock::Left, Some(far));
+        let near = manager.register(Mock::Right, bounds_at(0.0));
+
+        assert_eq!(manager.pick(Vec3::Z, -Vec3::Z), Some(near));
+        assert_eq!(manager.pick(Vec3::Z, Vec3::Z), None);
+    }
+}
@@ -0,0 +1,695 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Mapping of physical inputs to named actions.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use hearth_runtime::{
+    anyhow, async_trait, cargo_process_metadata,
+    flue::{CapabilityRef, Permissions},
+    hearth_schema::window::*,
+    process::ProcessMetadata,
+    runtime::{Plugin, RuntimeBuilder},
+    utils::{PubSub, RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner},
+};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+/// A physical input event, before it's mapped to actions.
+#[derive(Clone, Debug)]
+pub enum RawInput {
+    /// A keyboard key has changed state.
+    Key {
+        key: VirtualKeyCode,
+        state: ElementState,
+    },
+
+    /// The held keyboard modifiers have changed.
+    Modifiers(ModifiersState),
+
+    /// A mouse button has changed state.
+    Mouse {
+        button: MouseButton,
+        state: ElementState,
+    },
+
+    /// A gamepad button has changed state.
+    GamepadButton {
+        button: GamepadButton,
+        state: ElementState,
+    },
+
+    /// A gamepad axis has moved.
+    GamepadAxis { axis: GamepadAxis, value: f32 },
+
+    /// The window has lost focus, so every held input is released.
+    Unfocused,
+}
+
+/// Configuration for the input map.
+///
+/// Loaded from the `input` table of the config file.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// The bindings of each action, by action name.
+    pub actions: BTreeMap<String, Vec<InputBinding>>,
+}
+
+/// The bindings of every action and which of them are held.
+#[derive(Debug, Default)]
+pub struct InputMap {
+    /// The bindings of each action.
+    bindings: BTreeMap<String, Vec<InputBinding>>,
+
+    /// The currently held keyboard modifiers.
+    modifiers: ModifiersState,
+
+    /// The held bindings of each started action.
+    held: HashMap<String, HashSet<InputBinding>>,
+}
+
+impl InputMap {
+    /// Creates an input map with the given bindings.
+    pub fn new(bindings: BTreeMap<String, Vec<InputBinding>>) -> Self {
+        Self {
+            bindings,
+            ..Default::default()
+        }
+    }
+
+    /// Gets the bindings of every action.
+    pub fn bindings(&self) -> &BTreeMap<String, Vec<InputBinding>> {
+        &self.bindings
+    }
+
+    /// Replaces the bindings of an action. An action with no bindings is
+    /// removed. Ends the action if it was started.
+    pub fn rebind(&mut self, action: String, bindings: Vec<InputBinding>) -> Vec<InputMapEvent> {
+        let mut events = Vec::new();
+        if self.held.remove(&action).is_some() {
+            events.push(InputMapEvent::ActionEnded(action.clone()));
+        }
+
+        if bindings.is_empty() {
+            self.bindings.remove(&action);
+        } else {
+            self.bindings.insert(action, bindings);
+        }
+
+        events
+    }
+
+    /// Maps a raw input to the action events that it causes.
+    pub fn on_input(&mut self, input: RawInput) -> Vec<InputMapEvent> {
+        use ElementState::*;
+        match input {
+            RawInput::Key { key, state } => self.on_key(key, state),
+            RawInput::Modifiers(modifiers) => {
+                self.modifiers = modifiers;
+                vec![]
+            }
+            RawInput::Mouse { button, state } => {
+                self.on_button(InputBinding::Mouse(button), state == Pressed)
+            }
+            RawInput::GamepadButton { button, state } => {
+                self.on_button(InputBinding::GamepadButton(button), state == Pressed)
+            }
+            RawInput::GamepadAxis { axis, value } => self
+                .actions_for(|binding| *binding == InputBinding::GamepadAxis(axis))
+                .into_iter()
+                .map(|action| InputMapEvent::AxisChanged { action, value })
+                .collect(),
+            RawInput::Unfocused => {
+                let mut ended: Vec<_> = self.held.drain().map(|(action, _)| action).collect();
+                ended.sort();
+                ended.into_iter().map(InputMapEvent::ActionEnded).collect()
+            }
+        }
+    }
+
+    fn on_key(&mut self, key: VirtualKeyCode, state: ElementState) -> Vec<InputMapEvent> {
+        if state == ElementState::Released {
+            // release chords by their key, whatever the modifiers are now
+            return self.release(|binding| match binding {
+                InputBinding::Key(bound) => *bound == key,
+                InputBinding::Chord { key: bound, .. } => *bound == key,
+                _ => false,
+            });
+        }
+
+        let modifiers = self.modifiers;
+        self.press(|binding| match binding {
+            InputBinding::Key(bound) => *bound == key,
+            InputBinding::Chord {
+                modifiers: bound_modifiers,
+                key: bound,
+            } => *bound == key && modifiers.contains(*bound_modifiers),
+            _ => false,
+        })
+    }
+
+    fn on_button(&mut self, button: InputBinding, pressed: bool) -> Vec<InputMapEvent> {
+        if pressed {
+            self.press(|binding| *binding == button)
+        } else {
+            self.release(|binding| *binding == button)
+        }
+    }
+
+    /// Holds every binding that matches a pressed input and starts the
+    /// actions that weren't started yet. Key repeats are ignored.
+    fn press(&mut self, matches: impl Fn(&InputBinding) -> bool) -> Vec<InputMapEvent> {
+        let mut events = Vec::new();
+        for (action, bindings) in self.bindings.iter() {
+            for binding in bindings.iter().filter(|binding| matches(binding)) {
+                let held = self.held.entry(action.clone()).or_default();
+                if held.is_empty() {
+                    events.push(InputMapEvent::ActionStarted(action.clone()));
+                }
+
+                held.insert(*binding);
+            }
+        }
+
+        events
+    }
+
+    /// Releases every held binding that matches a released input and ends
+    /// the actions with no held bindings left.
+    fn release(&mut self, matches: impl Fn(&InputBinding) -> bool) -> Vec<InputMapEvent> {
+        let mut ended = Vec::new();
+        for (action, held) in self.held.iter_mut() {
+            held.retain(|binding| !matches(binding));
+            if held.is_empty() {
+                ended.push(action.clone());
+            }
+        }
+
+        for action in ended.iter() {
+            self.held.remove(action);
+        }
+
+        // keep event order deterministic
+        ended.sort();
+        ended.into_iter().map(InputMapEvent::ActionEnded).collect()
+    }
+
+    fn actions_for(&self, matches: impl Fn(&InputBinding) -> bool) -> Vec<String> {
+        self.bindings
+            .iter()
+            .filter(|(_, bindings)| bindings.iter().any(&matches))
+            .map(|(action, _)| action.clone())
+            .collect()
+    }
+}
+
+/// A plugin that maps the window's and gamepads' inputs to actions and
+/// provides the input map service.
+pub struct InputMapPlugin {
+    input_tx: mpsc::UnboundedSender<RawInput>,
+    input_rx: mpsc::UnboundedReceiver<RawInput>,
+    config_path: Option<PathBuf>,
+}
+
+impl InputMapPlugin {
+    /// Creates an input map plugin that receives inputs from the given
+    /// channel.
+    pub fn new(
+        input_tx: mpsc::UnboundedSender<RawInput>,
+        input_rx: mpsc::UnboundedReceiver<RawInput>,
+    ) -> Self {
+        Self {
+            input_tx,
+            input_rx,
+            config_path: None,
+        }
+    }
+
+    /// Saves rebound actions to the config file at the given path.
+    pub fn with_config_path(mut self, path: PathBuf) -> Self {
+        self.config_path = Some(path);
+        self
+    }
+}
+
+impl Plugin for InputMapPlugin {
+    fn finalize(mut self, builder: &mut RuntimeBuilder) {
+        let config: InputConfig = builder.load_config("input").unwrap_or_else(|err| {
+            debug!("Using default input config: {}", err);
+            InputConfig::default()
+        });
+
+        let map = Arc::new(Mutex::new(InputMap::new(config.actions)));
+        let pubsub = Arc::new(PubSub::new(builder.get_post()));
+
+        #[cfg(feature = "gamepad")]
+        spawn_gamepad_thread(self.input_tx.clone());
+
+        // the sender only exists to be cloned to input sources
+        drop(self.input_tx);
+
+        tokio::spawn({
+            let map = map.clone();
+            let pubsub = pubsub.clone();
+            async move {
+                while let Some(input) = self.input_rx.recv().await {
+                    let events = map.lock().on_input(input);
+                    for event in events {
+                        pubsub.notify(&event).await;
+                    }
+                }
+            }
+        });
+
+        builder.add_plugin(InputMapService {
+            map,
+            pubsub,
+            config_path: self.config_path,
+        });
+    }
+}
+
+/// Writes the bindings of every action to the `input` table of a config
+/// file.
+///
+/// The file is replaced atomically so that a crash mid-write can't leave a
+/// truncated config behind.
+async fn save_bindings(
+    path: &PathBuf,
+    bindings: &BTreeMap<String, Vec<InputBinding>>,
+) -> anyhow::Result<()> {
+    let text = match tokio::fs::read_to_string(path).await {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(err) => return Err(err.into()),
+    };
+
+    let text = update_bindings(&text, bindings)?;
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    tokio::fs::write(&tmp_path, text).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Replaces `input.actions` in the text of a config file, leaving the rest
+/// of the file (including its comments and formatting) untouched.
+fn update_bindings(
+    text: &str,
+    bindings: &BTreeMap<String, Vec<InputBinding>>,
+) -> anyhow::Result<String> {
+    let mut doc: toml_edit::Document = text.parse()?;
+
+    let config = InputConfig {
+        actions: bindings.clone(),
+    };
+
+    let mut actions: toml_edit::Document = toml::to_string(&config)?.parse()?;
+    let actions = actions.remove("actions").unwrap_or_else(toml_edit::table);
+
+    let input = doc.entry("input").or_insert(toml_edit::table());
+    let Some(input) = input.as_table_mut() else {
+        anyhow::bail!("input config is not a table");
+    };
+
+    input.insert("actions", actions);
+    Ok(doc.to_string())
+}
+
+/// A service that maps physical inputs to named actions.
+pub struct InputMapService {
+    map: Arc<Mutex<InputMap>>,
+    pubsub: Arc<PubSub<InputMapEvent>>,
+    config_path: Option<PathBuf>,
+}
+
+#[async_trait]
+impl RequestResponseProcess for InputMapService {
+    type Request = InputMapRequest;
+    type Response = InputMapResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, InputMapRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        use InputMapRequest::*;
+        let response = match request.data.clone() {
+            Subscribe => {
+                let Some(sub) = request.cap_args.first() else {
+                    return InputMapError::MissingCapability.into();
+                };
+
+                if sub.get_permissions().contains(Permissions::MONITOR) {
+                    sub.monitor(request.process.borrow_parent()).unwrap();
+                }
+
+                self.pubsub.subscribe(sub.clone());
+                Ok(InputMapSuccess::Ok)
+            }
+            Unsubscribe => {
+                let Some(sub) = request.cap_args.first() else {
+                    return InputMapError::MissingCapability.into();
+                };
+
+                self.pubsub.unsubscribe(sub.clone());
+                Ok(InputMapSuccess::Ok)
+            }
+            GetBindings => Ok(InputMapSuccess::Bindings(
+                self.map.lock().bindings().clone(),
+            )),
+            Rebind { action, bindings } => {
+                let (events, bindings) = {
+                    let mut map = self.map.lock();
+                    let events = map.rebind(action, bindings);
+                    (events, map.bindings().clone())
+                };
+
+                for event in events {
+                    self.pubsub.notify(&event).await;
+                }
+
+                match self.config_path.as_ref() {
+                    Some(path) => save_bindings(path, &bindings).await.map_err(|err| {
+                        warn!("failed to save input bindings: {err:?}");
+                        InputMapError::SaveFailed(err.to_string())
+                    }),
+                    None => Ok(()),
+                }
+                .map(|_| InputMapSuccess::Ok)
+            }
+        };
+
+        response.into()
+    }
+
+    async fn on_down<'a>(&'a mut self, cap: CapabilityRef<'a>) {
+        self.pubsub.unsubscribe(cap);
+    }
+}
+
+impl ServiceRunner for InputMapService {
+    const NAME: &'static str = INPUT_MAP_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Maps physical inputs to named actions. Accepts InputMapRequest.".to_string());
+        meta
+    }
+}
+
+/// Polls gamepad events on a dedicated thread, since gilrs is not async.
+#[cfg(feature = "gamepad")]
+fn spawn_gamepad_thread(input_tx: mpsc::UnboundedSender<RawInput>) {
+    std::thread::spawn(move || {
+        let mut gilrs = match gilrs::Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(err) => {
+                warn!("gamepad input is unavailable: {err:?}");
+                return;
+            }
+        };
+
+        loop {
+            while let Some(gilrs::Event { event, .. }) = gilrs.next_event() {
+                use gilrs::EventType;
+                let input = match event {
+                    EventType::ButtonPressed(button, _) => {
+                        conv_gamepad_button(button).map(|button| RawInput::GamepadButton {
+                            button,
+                            state: ElementState::Pressed,
+                        })
+                    }
+                    EventType::ButtonReleased(button, _) => {
+                        conv_gamepad_button(button).map(|button| RawInput::GamepadButton {
+                            button,
+                            state: ElementState::Released,
+                        })
+                    }
+                    EventType::AxisChanged(axis, value, _) => {
+                        conv_gamepad_axis(axis).map(|axis| RawInput::GamepadAxis { axis, value })
+                    }
+                    _ => None,
+                };
+
+                if let Some(input) = input {
+                    if input_tx.send(input).is_err() {
+                        return;
+                    }
+                }
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+    });
+}
+
+#[cfg(feature = "gamepad")]
+fn conv_gamepad_button(button: gilrs::Button) -> Option<GamepadButton> {
+    use gilrs::Button as Gilrs;
+    use GamepadButton as Schema;
+    Some(match button {
+        Gilrs::South => Schema::South,
+        Gilrs::East => Schema::East,
+        Gilrs::North => Schema::North,
+        Gilrs::West => Schema::West,
+        Gilrs::C => Schema::C,
+        Gilrs::Z => Schema::Z,
+        Gilrs::LeftTrigger => Schema::LeftTrigger,
+        Gilrs::LeftTrigger2 => Schema::LeftTrigger2,
+        Gilrs::RightTrigger => Schema::RightTrigger,
+        Gilrs::RightTrigger2 => Schema::RightTrigger2,
+        Gilrs::Select => Schema::Select,
+        Gilrs::Start => Schema::Start,
+        Gilrs::Mode => Schema::Mode,
+        Gilrs::LeftThumb => Schema::LeftThumb,
+        Gilrs::RightThumb => Schema::RightThumb,
+        Gilrs::DPadUp => Schema::DPadUp,
+        Gilrs::DPadDown => Schema::DPadDown,
+        Gilrs::DPadLeft => Schema::DPadLeft,
+        Gilrs::DPadRight => Schema::DPadRight,
+        Gilrs::Unknown => return None,
+    })
+}
+
+#[cfg(feature = "gamepad")]
+fn conv_gamepad_axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+    use gilrs::Axis as Gilrs;
+    use GamepadAxis as Schema;
+    Some(match axis {
+        Gilrs::LeftStickX => Schema::LeftStickX,
+        Gilrs::LeftStickY => Schema::LeftStickY,
+        Gilrs::LeftZ => Schema::LeftZ,
+        Gilrs::RightStickX => Schema::RightStickX,
+        Gilrs::RightStickY => Schema::RightStickY,
+        Gilrs::RightZ => Schema::RightZ,
+        Gilrs::DPadX => Schema::DPadX,
+        Gilrs::DPadY => Schema::DPadY,
+        Gilrs::Unknown => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(actions: &[(&str, InputBinding)]) -> InputMap {
+        let mut bindings: BTreeMap<String, Vec<InputBinding>> = BTreeMap::new();
+        for (action, binding) in actions {
+            bindings
+                .entry(action.to_string())
+                .or_default()
+                .push(*binding);
+        }
+
+        InputMap::new(bindings)
+    }
+
+    fn key(key: VirtualKeyCode, state: ElementState) -> RawInput {
+        RawInput::Key { key, state }
+    }
+
+    fn started(action: &str) -> InputMapEvent {
+        InputMapEvent::ActionStarted(action.to_string())
+    }
+
+    fn ended(action: &str) -> InputMapEvent {
+        InputMapEvent::ActionEnded(action.to_string())
+    }
+
+    #[test]
+    fn key_starts_and_ends_action() {
+        use ElementState::*;
+        use VirtualKeyCode::*;
+        let mut map = map(&[("move_forward", InputBinding::Key(W))]);
+        assert_eq!(map.on_input(key(W, Pressed)), vec![started("move_forward")]);
+
+        // key repeats don't restart the action
+        assert_eq!(map.on_input(key(W, Pressed)), vec![]);
+        assert_eq!(map.on_input(key(W, Released)), vec![ended("move_forward")]);
+    }
+
+    #[test]
+    fn action_ends_with_last_binding() {
+        use ElementState::*;
+        use VirtualKeyCode::*;
+        let mut map = map(&[
+            ("move_forward", InputBinding::Key(W)),
+            ("move_forward", InputBinding::Key(Up)),
+        ]);
+
+        assert_eq!(map.on_input(key(W, Pressed)), vec![started("move_forward")]);
+        assert_eq!(map.on_input(key(Up, Pressed)), vec![]);
+        assert_eq!(map.on_input(key(W, Released)), vec![]);
+        assert_eq!(map.on_input(key(Up, Released)), vec![ended("move_forward")]);
+    }
+
+    #[test]
+    fn chord_requires_modifiers() {
+        use ElementState::*;
+        use VirtualKeyCode::*;
+        let mut map = map(&[(
+            "toggle_terminal",
+            InputBinding::Chord {
+                modifiers: ModifiersState::CTRL,
+                key: Grave,
+            },
+        )]);
+
+        assert_eq!(map.on_input(key(Grave, Pressed)), vec![]);
+        assert_eq!(map.on_input(key(Grave, Released)), vec![]);
+
+        map.on_input(RawInput::Modifiers(ModifiersState::CTRL));
+        assert_eq!(
+            map.on_input(key(Grave, Pressed)),
+            vec![started("toggle_terminal")]
+        );
+
+        // releasing the modifier first still ends the chord on key release
+        map.on_input(RawInput::Modifiers(ModifiersState::empty()));
+        assert_eq!(
+            map.on_input(key(Grave, Released)),
+            vec![ended("toggle_terminal")]
+        );
+    }
+
+    #[test]
+    fn axes_and_unfocus() {
+        let mut map = map(&[
+            (
+                "look_x",
+                InputBinding::GamepadAxis(GamepadAxis::RightStickX),
+            ),
+            ("jump", InputBinding::GamepadButton(GamepadButton::South)),
+        ]);
+
+        let events = map.on_input(RawInput::GamepadAxis {
+            axis: GamepadAxis::RightStickX,
+            value: 0.5,
+        });
+
+        assert_eq!(
+            events,
+            vec![InputMapEvent::AxisChanged {
+                action: "look_x".to_string(),
+                value: 0.5
+            }]
+        );
+
+        let events = map.on_input(RawInput::GamepadButton {
+            button: GamepadButton::South,
+            state: ElementState::Pressed,
+        });
+
+        assert_eq!(events, vec![started("jump")]);
+        assert_eq!(map.on_input(RawInput::Unfocused), vec![ended("jump")]);
+    }
+
+    #[test]
+    fn rebind_ends_started_action() {
+        use ElementState::*;
+        use VirtualKeyCode::*;
+        let mut map = map(&[("jump", InputBinding::Key(Space))]);
+        map.on_input(key(Space, Pressed));
+        let events = map.rebind("jump".to_string(), vec![InputBinding::Key(J)]);
+        assert_eq!(events, vec![ended("jump")]);
+        assert_eq!(map.on_input(key(Space, Released)), vec![]);
+        assert_eq!(map.on_input(key(J, Pressed)), vec![started("jump")]);
+    }
+
+    #[test]
+    fn config_round_trips_through_toml() {
+        let config: InputConfig = toml::from_str(
+            r#"
+            [actions]
+            move_forward = [{ Key = "W" }, { GamepadAxis = "LeftStickY" }]
+            toggle_terminal = [{ Chord = { modifiers = "CTRL", key = "Grave" } }]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.actions["toggle_terminal"],
+            vec![InputBinding::Chord {
+                modifiers: ModifiersState::CTRL,
+                key: VirtualKeyCode::Grave,
+            }]
+        );
+
+        let text = toml::to_string(&config).unwrap();
+        let parsed: InputConfig = toml::from_str(&text).unwrap();
+        assert_eq!(parsed.actions, config.actions);
+    }
+
+    #[test]
+    fn saving_keeps_rest_of_config() {
+        let text = r#"
+# the window title
+title = "Hearth"
+
+[input.actions]
+jump = [{ Key = "Space" }]
+
+[network]
+port = 9000 # default
+"#;
+
+        let bindings = BTreeMap::from([(
+            "jump".to_string(),
+            vec![InputBinding::Key(VirtualKeyCode::J)],
+        )]);
+
+        let text = update_bindings(text, &bindings).unwrap();
+        assert!(text.contains("# the window title"));
+        assert!(text.contains("port = 9000 # default"));
+
+        let config: toml::Table = toml::from_str(&text).unwrap();
+        let input: InputConfig = config["input"].clone().try_into().unwrap();
+        assert_eq!(input.actions, bindings);
+    }
+}
@@ -17,34 +17,75 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    net::{SocketAddr, ToSocketAddrs},
+    fmt::{Display, Formatter},
+    net::SocketAddr,
     path::PathBuf,
-    str::FromStr,
     sync::Arc,
+    time::Duration,
 };
 
 use clap::Parser;
-use hearth_network::{auth::login, connection::Connection};
+use hearth_network::{
+    auth::{login, AuthenticationError, SessionKey},
+    connection::{ConfigError, Connection, ConnectionConfig},
+    stats::{NetworkStatsService, TrackedConnections},
+    tls::{self, ClientTls, Transport},
+    websocket,
+};
 use hearth_rend3::Rend3Plugin;
 use hearth_runtime::{
-    flue::OwnedCapability,
+    async_trait, cargo_process_metadata,
+    flue::{CapabilityRef, OwnedCapability, Permissions},
+    hearth_schema::{
+        network::{ConnectionStatus, ConnectionStatusRequest, CONNECTION_STATUS_SERVICE},
+        PeerRole,
+    },
+    process::ProcessMetadata,
     runtime::{Plugin, Runtime, RuntimeBuilder, RuntimeConfig},
+    utils::{MessageInfo, PubSub, ServiceRunner, SinkProcess},
+    LoggingConfig,
+};
+use resolve::{ServerAddress, SystemResolver};
+use tokio::{
+    net::TcpStream,
+    sync::{oneshot, watch},
 };
-use tokio::{net::TcpStream, sync::oneshot};
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, info_span, warn, Instrument};
 use window::WindowPlugin;
 
-use crate::window::WindowCtx;
+use crate::{input::InputMapPlugin, window::WindowCtx};
 
+mod camera;
+#[cfg(feature = "discovery")]
+mod discover;
+mod focus;
+mod input;
+mod resolve;
 mod window;
 
 /// Client program to the Hearth virtual space server.
 #[derive(Parser, Debug)]
 pub struct Args {
-    /// IP address and port of the server to connect to.
+    /// Address of the server to connect to.
+    ///
+    /// If no port is given, the port is looked up from the host's
+    /// `_hearth._tcp` SRV record, or the default port is used.
+    ///
+    /// A `ws://` or `wss://` URL connects to the server's WebSocket listener
+    /// instead.
     #[clap(short, long)]
     pub server: Option<String>,
 
+    /// Browse the local network for a server to connect to instead of
+    /// using --server.
+    #[cfg(feature = "discovery")]
+    #[clap(long, conflicts_with = "server")]
+    pub discover: bool,
+
+    /// User name to authenticate to the server as. Defaults to empty.
+    #[clap(short, long, default_value = "")]
+    pub user: String,
+
     /// Password to use to authenticate to the server. Defaults to empty.
     #[clap(short, long, default_value = "")]
     pub password: String,
@@ -60,11 +101,40 @@ pub struct Args {
     /// A path to the guest-side filesystem root.
     #[clap(short, long)]
     pub root: PathBuf,
+
+    /// Reload assets loaded from files in the filesystem root when they
+    /// change on disk.
+    #[clap(long)]
+    pub hot_reload: bool,
+
+    /// Give up instead of reconnecting when the server connection fails.
+    #[clap(long)]
+    pub no_retry: bool,
+
+    /// Require TLS when connecting to the server.
+    #[clap(long)]
+    pub tls: bool,
+
+    /// A PEM certificate to trust as the server's instead of the web root
+    /// certificates. Implies --tls.
+    #[clap(long)]
+    pub tls_cert: Option<PathBuf>,
+
+    /// The name of this IPC daemon instance, used to find a socket path that
+    /// doesn't collide with other daemons on this machine.
+    #[clap(long)]
+    pub instance: Option<String>,
 }
 
 fn main() {
     let args = Args::parse();
-    hearth_runtime::init_logging();
+
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(hearth_runtime::get_config_path);
+    let config_file = hearth_runtime::load_config(&config_path).unwrap();
+    hearth_runtime::init_logging(&LoggingConfig::from_config_file(&config_file));
 
     // winit requires that running its event loop takes over the calling thread,
     // so we need to manually create a Tokio runtime so that we can use this
@@ -77,8 +147,10 @@ fn main() {
     let (window, mut window_offer) = runtime.block_on(WindowCtx::new());
     let mut join_main = runtime.spawn(async_main(
         args,
+        config_file,
         window_offer.rend3_plugin,
         window_offer.window_plugin,
+        window_offer.input_plugin.with_config_path(config_path),
     ));
 
     runtime.spawn(async move {
@@ -103,31 +175,102 @@ fn main() {
     window.run();
 }
 
-async fn async_main(args: Args, rend3_plugin: Rend3Plugin, window_plugin: WindowPlugin) {
-    let config_path = args.config.unwrap_or_else(hearth_runtime::get_config_path);
-
-    let config_file = hearth_runtime::load_config(&config_path).unwrap();
-
+async fn async_main(
+    args: Args,
+    config_file: toml::Table,
+    rend3_plugin: Rend3Plugin,
+    window_plugin: WindowPlugin,
+    input_plugin: InputMapPlugin,
+) {
     let mut builder = RuntimeBuilder::new(config_file);
     builder.add_plugin(hearth_time::TimePlugin);
+    builder.add_plugin(hearth_pubsub::PubSubPlugin::default());
     builder.add_plugin(hearth_wasm::WasmPlugin::default());
     builder.add_plugin(hearth_init::InitPlugin::new(args.init));
-    builder.add_plugin(hearth_fs::FsPlugin::new(args.root));
+
+    let mut fs = hearth_fs::FsPlugin::new(args.root);
+    if args.hot_reload {
+        fs = fs.with_hot_reload();
+    }
+
+    builder.add_plugin(fs);
+
+    let info = rend3_plugin.info();
+    info!(
+        "Rendering with {} ({:?}, {:?}) to {}; max texture size {}",
+        info.adapter.name,
+        info.adapter.backend,
+        info.adapter.device_type,
+        info.surface_format,
+        info.limits.max_texture_dimension_2d,
+    );
+
     builder.add_plugin(rend3_plugin);
     builder.add_plugin(hearth_renderer::RendererPlugin::default());
+
+    // the terminal's IME candidate window follows its cursor
+    let terminal = hearth_terminal::TerminalPlugin::default()
+        .with_ime_positions(window_plugin.ime_position_sender());
+
     builder.add_plugin(window_plugin);
+    builder.add_plugin(input_plugin);
     builder.add_plugin(hearth_debug_draw::DebugDrawPlugin::default());
     builder.add_plugin(hearth_canvas::CanvasPlugin);
-    builder.add_plugin(hearth_terminal::TerminalPlugin::default());
-    builder.add_plugin(hearth_daemon::DaemonPlugin::default());
+    builder.add_plugin(terminal);
+    builder.add_plugin(hearth_daemon::DaemonPlugin {
+        instance: args.instance,
+    });
+
+    #[cfg(feature = "discovery")]
+    let server = match args.discover {
+        true => discover::choose_server().await,
+        false => args.server,
+    };
 
-    if let (Some(server), password) = (args.server, args.password) {
-        builder.add_plugin(ClientPlugin { server, password });
+    #[cfg(not(feature = "discovery"))]
+    let server = args.server;
+
+    if let (Some(server), password) = (server, args.password) {
+        let config: ConnectionConfig = builder.load_config("network").unwrap_or_else(|err| {
+            debug!("Using default network config: {}", err);
+            ConnectionConfig::default()
+        });
+
+        let config = match config.validate() {
+            Ok(()) => config,
+            Err(err) => {
+                warn!("Using default network config: {}", err);
+                ConnectionConfig::default()
+            }
+        };
+
+        let tls = match args.tls_cert {
+            Some(path) => match ClientTls::with_pinned_file(&path) {
+                Ok(tls) => Some(tls),
+                Err(err) => {
+                    error!("Failed to load pinned TLS certificate {:?}: {}", path, err);
+                    return;
+                }
+            },
+            None if args.tls => Some(ClientTls::with_web_roots()),
+            None => None,
+        };
+
+        builder.add_plugin(ClientPlugin {
+            server,
+            user: args.user,
+            password,
+            retry: !args.no_retry,
+            config,
+            tls: tls.map(Arc::new),
+        });
     } else {
         info!("Running in serverless mode");
     }
 
-    let config = RuntimeConfig {};
+    let config = RuntimeConfig {
+        role: Some(PeerRole::Client),
+    };
 
     let _runtime = builder.run(config).await;
 
@@ -135,10 +278,71 @@ async fn async_main(args: Args, rend3_plugin: Rend3Plugin, window_plugin: Window
     info!("Ctrl+C hit; quitting client");
 }
 
+/// The initial delay between reconnection attempts.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// The maximum delay between reconnection attempts.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How long to wait for a TCP connection to a single address.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// An error that occurred while connecting to a server.
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The server address could not be resolved.
+    Resolve(String),
+
+    /// An IO error occurred while connecting.
+    Io(std::io::Error),
+
+    /// Every resolved address failed to connect.
+    Unreachable(Vec<(SocketAddr, ConnectError)>),
+
+    /// The server rejected our credentials.
+    Auth(AuthenticationError),
+
+    /// The server never sent its root cap.
+    RootCap,
+
+    /// The network config is invalid.
+    Config(ConfigError),
+}
+
+impl Display for ConnectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectError::Resolve(msg) => write!(f, "failed to resolve server: {}", msg),
+            ConnectError::Io(err) => write!(f, "IO error: {}", err),
+            ConnectError::Unreachable(attempts) => {
+                write!(f, "all addresses failed")?;
+                for (addr, err) in attempts.iter() {
+                    write!(f, "; {}: {}", addr, err)?;
+                }
+
+                Ok(())
+            }
+            ConnectError::Auth(err) => write!(f, "authentication error: {:?}", err),
+            ConnectError::RootCap => write!(f, "server's root cap was never received"),
+            ConnectError::Config(err) => write!(f, "{}", err),
+        }
+    }
+}
+
 /// The plugin that implements the client side of a network connection.
 pub struct ClientPlugin {
     pub server: String,
+    pub user: String,
     pub password: String,
+
+    /// Whether to reconnect when the connection fails or is lost.
+    pub retry: bool,
+
+    /// The configuration of the network connection.
+    pub config: ConnectionConfig,
+
+    /// If set, TLS is required for the server connection.
+    pub tls: Option<Arc<ClientTls>>,
 }
 
 impl Plugin for ClientPlugin {
@@ -150,58 +354,145 @@ impl Plugin for ClientPlugin {
         let (network_root_tx, network_root_rx) = oneshot::channel();
         init.add_hook("hearth.init.Client".into(), network_root_tx);
 
+        let (status_tx, status_rx) = watch::channel(ConnectionStatus::Offline);
+        let pubsub = Arc::new(PubSub::new(builder.get_post()));
+
+        tokio::spawn({
+            let pubsub = pubsub.clone();
+            let mut status_rx = status_rx.clone();
+            async move {
+                while status_rx.changed().await.is_ok() {
+                    let status = *status_rx.borrow_and_update();
+                    pubsub.notify(&status).await;
+                }
+            }
+        });
+
+        builder.add_plugin(ConnectionStatusService {
+            status: status_rx,
+            pubsub,
+        });
+
+        let connections = TrackedConnections::default();
+        builder.add_plugin(NetworkStatsService {
+            connections: connections.clone(),
+        });
+
         builder.add_runner(move |runtime| {
-            tokio::spawn(self.connect(network_root_rx, runtime));
+            tokio::spawn(self.run(network_root_rx, runtime, status_tx, connections));
         });
     }
 }
 
 impl ClientPlugin {
-    pub async fn connect(
+    /// Maintains a connection to the server, reconnecting with exponential
+    /// backoff if retrying is enabled.
+    pub async fn run(
         self,
         on_network_root: oneshot::Receiver<OwnedCapability>,
         runtime: Arc<Runtime>,
+        status: watch::Sender<ConnectionStatus>,
+        connections: TrackedConnections,
     ) {
         info!("Waiting for network root cap hook");
         let network_root = on_network_root.await.unwrap();
+        let mut backoff = INITIAL_BACKOFF;
 
-        info!("Resolving {}", self.server);
-        let server = match SocketAddr::from_str(&self.server) {
-            Err(_) => {
-                info!(
-                    "Failed to parse \'{}\' to SocketAddr, attempting DNS resolution",
-                    self.server
-                );
-                match self.server.to_socket_addrs() {
-                    Err(err) => {
-                        error!("Failed to resolve IP: {:?}", err);
-                        return;
-                    }
-                    Ok(addrs) => match addrs.last() {
-                        None => return,
-                        Some(addr) => addr,
-                    },
+        loop {
+            status.send_replace(ConnectionStatus::Connecting);
+
+            let span = info_span!("connection", server = %self.server);
+            let connected = self
+                .connect(network_root.clone(), &runtime, &connections)
+                .instrument(span)
+                .await;
+
+            match connected {
+                Ok((_conn, closed)) => {
+                    info!("Successfully connected!");
+                    status.send_replace(ConnectionStatus::Online);
+                    backoff = INITIAL_BACKOFF;
+                    let _ = closed.await;
+                    warn!("Lost connection to server");
+                }
+                Err(ConnectError::Auth(err)) => {
+                    error!("Failed to authenticate with server: {:?}", err);
+                    status.send_replace(ConnectionStatus::Offline);
+                    return;
+                }
+                Err(ConnectError::Config(err)) => {
+                    error!("Failed to connect to server: {}", err);
+                    status.send_replace(ConnectionStatus::Offline);
+                    return;
+                }
+                Err(err) => {
+                    error!("Failed to connect to server: {}", err);
                 }
             }
-            Ok(addr) => addr,
-        };
 
-        info!("Connecting to server at {:?}", server);
-        let mut socket = match TcpStream::connect(server).await {
-            Ok(s) => s,
-            Err(err) => {
-                error!("Failed to connect to server: {:?}", err);
+            status.send_replace(ConnectionStatus::Offline);
+
+            if !self.retry {
                 return;
             }
-        };
 
-        info!("Authenticating");
-        let session_key = match login(&mut socket, self.password.as_bytes()).await {
-            Ok(key) => key,
-            Err(err) => {
-                error!("Failed to authenticate with server: {:?}", err);
-                return;
+            // add up to 50% jitter so that clients don't reconnect in lockstep
+            let jitter = backoff.mul_f64(rand::random::<f64>() * 0.5);
+            let delay = backoff + jitter;
+            info!("Reconnecting in {:?}", delay);
+            tokio::time::sleep(delay).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Performs a single connection attempt.
+    ///
+    /// Every resolved address of the server is tried in order until one
+    /// completes the transport negotiation and authentication.
+    ///
+    /// On success, returns the new connection and a receiver that resolves
+    /// when the connection is closed.
+    pub async fn connect(
+        &self,
+        network_root: OwnedCapability,
+        runtime: &Arc<Runtime>,
+        connections: &TrackedConnections,
+    ) -> Result<
+        (
+            Arc<hearth_runtime::connection::Connection>,
+            oneshot::Receiver<()>,
+        ),
+        ConnectError,
+    > {
+        info!("Resolving {}", self.server);
+        let server = ServerAddress::parse(&self.server).map_err(ConnectError::Resolve)?;
+        let candidates = resolve::resolve(&server, &SystemResolver)
+            .await
+            .map_err(ConnectError::Resolve)?;
+
+        debug!("Resolved {} to {:?}", self.server, candidates);
+
+        let host = server.host();
+        let mut attempts = Vec::new();
+        let mut established = None;
+        for addr in candidates {
+            let span = info_span!("handshake", %addr);
+            match self.handshake(addr, &server, &host).instrument(span).await {
+                Ok(result) => {
+                    info!("Connected to server at {}", addr);
+                    established = Some(result);
+                    break;
+                }
+                Err(ConnectError::Auth(err)) => return Err(ConnectError::Auth(err)),
+                Err(err) => {
+                    warn!("Failed to connect to {}: {}", addr, err);
+                    attempts.push((addr, err));
+                }
             }
+        }
+
+        let Some((socket, session_key)) = established else {
+            return Err(ConnectError::Unreachable(attempts));
         };
 
         use hearth_network::encryption::{AsyncDecryptor, AsyncEncryptor, Key};
@@ -211,10 +502,13 @@ impl ClientPlugin {
         let (server_rx, server_tx) = tokio::io::split(socket);
         let server_rx = AsyncDecryptor::new(&server_key, server_rx);
         let server_tx = AsyncEncryptor::new(&client_key, server_tx);
-        let conn = Connection::new(server_rx, server_tx);
+        let conn = Connection::with_config(server_rx, server_tx, self.config.clone())
+            .map_err(ConnectError::Config)?;
+        connections.track(self.server.clone(), &conn.stats);
 
         info!("Beginning connection");
         let (root_cap_tx, root_cap) = tokio::sync::oneshot::channel();
+        let closed = conn.closed;
         let conn = hearth_runtime::connection::Connection::begin(
             runtime.post.clone(),
             conn.op_rx,
@@ -226,14 +520,122 @@ impl ClientPlugin {
         conn.export_root(network_root);
 
         info!("Waiting for server's root cap...");
-        let _root_cap = match root_cap.await {
-            Ok(cap) => cap,
-            Err(err) => {
-                eprintln!("Server's root cap was never received: {:?}", err);
-                return;
+        let _root_cap = root_cap.await.map_err(|_| ConnectError::RootCap)?;
+
+        Ok((conn, closed))
+    }
+
+    /// Connects to a single address of the server and authenticates.
+    async fn handshake(
+        &self,
+        addr: SocketAddr,
+        server: &ServerAddress,
+        host: &str,
+    ) -> Result<(Box<dyn Transport>, SessionKey), ConnectError> {
+        info!("Connecting to server at {}", addr);
+        let socket = match tokio::time::timeout(CONNECT_TIMEOUT, TcpStream::connect(addr)).await {
+            Ok(result) => result.map_err(ConnectError::Io)?,
+            Err(_) => {
+                let kind = std::io::ErrorKind::TimedOut;
+                return Err(ConnectError::Io(kind.into()));
             }
         };
 
-        info!("Successfully connected!");
+        info!("Negotiating transport");
+        let negotiated = match server {
+            ServerAddress::WebSocket { url, secure, .. } => {
+                let web_roots;
+                let tls = match (*secure, self.tls.as_deref()) {
+                    (true, Some(tls)) => Some(tls),
+                    (true, None) => {
+                        web_roots = ClientTls::with_web_roots();
+                        Some(&web_roots)
+                    }
+                    (false, Some(_)) => {
+                        let msg = "TLS is required but the server URL is not wss://";
+                        let err = std::io::Error::new(std::io::ErrorKind::PermissionDenied, msg);
+                        return Err(ConnectError::Io(err));
+                    }
+                    (false, None) => None,
+                };
+
+                // TLS for WebSockets is handled underneath the WebSocket layer
+                match websocket::connect(socket, url, host, tls).await {
+                    Ok(socket) => tls::connect(socket, host, None).await,
+                    Err(err) => Err(err),
+                }
+            }
+            _ => tls::connect(socket, host, self.tls.as_deref()).await,
+        };
+
+        let mut socket = negotiated.map_err(ConnectError::Io)?;
+
+        info!("Authenticating");
+        match login(&mut socket, &self.user, self.password.as_bytes()).await {
+            Ok(key) => Ok((socket, key)),
+            Err(AuthenticationError::IoError(err)) => Err(ConnectError::Io(err)),
+            Err(err) => Err(ConnectError::Auth(err)),
+        }
+    }
+}
+
+/// A service that reports the status of the client's connection to the
+/// server.
+pub struct ConnectionStatusService {
+    status: watch::Receiver<ConnectionStatus>,
+    pubsub: Arc<PubSub<ConnectionStatus>>,
+}
+
+#[async_trait]
+impl SinkProcess for ConnectionStatusService {
+    type Message = ConnectionStatusRequest;
+
+    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, ConnectionStatusRequest>) {
+        use ConnectionStatusRequest::*;
+        match message.data {
+            Subscribe => {
+                let Some(sub) = message.caps.get(0) else {
+                    warn!("Subscribe message is missing capability");
+                    return;
+                };
+
+                if sub.get_permissions().contains(Permissions::MONITOR) {
+                    sub.monitor(message.process.borrow_parent()).unwrap();
+                }
+
+                self.pubsub.subscribe(sub.clone());
+
+                let status = *self.status.borrow();
+                let data = serde_json::to_vec(&status).unwrap();
+                if let Err(err) = sub.send(&data, &[]).await {
+                    debug!("failed to send connection status: {:?}", err);
+                }
+            }
+            Unsubscribe => {
+                let Some(sub) = message.caps.get(0) else {
+                    warn!("Unsubscribe message is missing capability");
+                    return;
+                };
+
+                self.pubsub.unsubscribe(sub.clone());
+            }
+        }
+    }
+
+    async fn on_down<'a>(&'a mut self, cap: CapabilityRef<'a>) {
+        self.pubsub.unsubscribe(cap);
+    }
+}
+
+impl ServiceRunner for ConnectionStatusService {
+    const NAME: &'static str = CONNECTION_STATUS_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Reports the client's connection status. Accepts ConnectionStatusRequest.".to_string(),
+        );
+
+        meta
     }
 }
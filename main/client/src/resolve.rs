@@ -0,0 +1,482 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Resolution of `--server` strings to candidate socket addresses.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+use hearth_runtime::async_trait;
+use tracing::debug;
+
+/// The port used for servers given without one and without an SRV record.
+pub const DEFAULT_PORT: u16 = 4114;
+
+/// The SRV service and protocol labels looked up for port-less host names.
+pub const SRV_PREFIX: &str = "_hearth._tcp";
+
+/// A parsed `--server` string.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServerAddress {
+    /// A literal IP address, with or without a port.
+    Ip { ip: IpAddr, port: Option<u16> },
+
+    /// A host name to resolve, with or without a port.
+    Host { host: String, port: Option<u16> },
+
+    /// A `ws://` or `wss://` URL of a WebSocket listener.
+    ///
+    /// `addr` is the URL's host and port. Without a port, it defaults to the
+    /// standard HTTP or HTTPS port instead of being looked up by SRV record.
+    WebSocket {
+        url: String,
+        secure: bool,
+        addr: Box<ServerAddress>,
+    },
+}
+
+impl ServerAddress {
+    /// Parses a server string.
+    ///
+    /// Accepts `host`, `host:port`, IP addresses with or without a port,
+    /// bracketed IPv6 addresses with or without a port, and `ws://` or
+    /// `wss://` URLs with any of the above as their host.
+    pub fn parse(server: &str) -> Result<Self, String> {
+        let ws = server
+            .strip_prefix("ws://")
+            .map(|rest| (false, rest))
+            .or_else(|| server.strip_prefix("wss://").map(|rest| (true, rest)));
+
+        if let Some((secure, rest)) = ws {
+            let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+            if authority.contains('@') {
+                return Err(format!("user info is not supported in {:?}", server));
+            }
+
+            return Ok(ServerAddress::WebSocket {
+                url: server.to_string(),
+                secure,
+                addr: Box::new(Self::parse(authority)?),
+            });
+        }
+
+        if let Ok(addr) = server.parse::<SocketAddr>() {
+            return Ok(ServerAddress::Ip {
+                ip: addr.ip(),
+                port: Some(addr.port()),
+            });
+        }
+
+        let unbracketed = server.trim_start_matches('[').trim_end_matches(']');
+        if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+            return Ok(ServerAddress::Ip { ip, port: None });
+        }
+
+        let (host, port) = match server.rsplit_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse()
+                    .map_err(|_| format!("invalid port in {:?}", server))?;
+                (host, Some(port))
+            }
+            None => (server, None),
+        };
+
+        if host.is_empty() {
+            return Err(format!("missing host in {:?}", server));
+        }
+
+        Ok(ServerAddress::Host {
+            host: host.to_string(),
+            port,
+        })
+    }
+
+    /// Gets the host name or IP address, without the port.
+    pub fn host(&self) -> String {
+        match self {
+            ServerAddress::Ip { ip, .. } => ip.to_string(),
+            ServerAddress::Host { host, .. } => host.clone(),
+            ServerAddress::WebSocket { addr, .. } => addr.host(),
+        }
+    }
+
+    /// Fills in the port if none was given.
+    fn or_port(&self, default: u16) -> Self {
+        match self.clone() {
+            ServerAddress::Ip { ip, port } => ServerAddress::Ip {
+                ip,
+                port: Some(port.unwrap_or(default)),
+            },
+            ServerAddress::Host { host, port } => ServerAddress::Host {
+                host,
+                port: Some(port.unwrap_or(default)),
+            },
+            other => other,
+        }
+    }
+}
+
+/// A source of DNS lookups.
+#[async_trait]
+pub trait Resolver {
+    /// Looks up the targets of an SRV record as host and port pairs.
+    async fn lookup_srv(&self, name: &str) -> io::Result<Vec<SrvTarget>>;
+
+    /// Looks up the addresses of a host.
+    async fn lookup_host(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+/// A single target of an SRV record.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub host: String,
+    pub port: u16,
+}
+
+/// A [Resolver] using the system's DNS configuration.
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn lookup_srv(&self, name: &str) -> io::Result<Vec<SrvTarget>> {
+        let to_io = |err| io::Error::new(io::ErrorKind::Other, err);
+        let resolver =
+            trust_dns_resolver::TokioAsyncResolver::tokio_from_system_conf().map_err(to_io)?;
+        let lookup = resolver.srv_lookup(name).await.map_err(to_io)?;
+
+        Ok(lookup
+            .iter()
+            .map(|srv| SrvTarget {
+                priority: srv.priority(),
+                weight: srv.weight(),
+                host: srv.target().to_utf8().trim_end_matches('.').to_string(),
+                port: srv.port(),
+            })
+            .collect())
+    }
+
+    async fn lookup_host(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        Ok(tokio::net::lookup_host((host, port)).await?.collect())
+    }
+}
+
+/// Resolves a server address to an ordered list of candidate addresses to
+/// try connecting to.
+///
+/// Host names given without a port are first looked up as SRV records under
+/// [SRV_PREFIX], falling back to [DEFAULT_PORT] if there are none.
+pub async fn resolve(
+    server: &ServerAddress,
+    resolver: &(impl Resolver + Sync),
+) -> Result<Vec<SocketAddr>, String> {
+    let ws_target;
+    let server = match server {
+        ServerAddress::WebSocket { secure, addr, .. } => {
+            ws_target = addr.or_port(if *secure { 443 } else { 80 });
+            &ws_target
+        }
+        other => other,
+    };
+
+    let addrs = match server {
+        ServerAddress::Ip { ip, port } => vec![SocketAddr::new(*ip, port.unwrap_or(DEFAULT_PORT))],
+        ServerAddress::WebSocket { .. } => return Err("nested WebSocket URL".to_string()),
+        ServerAddress::Host {
+            host,
+            port: Some(port),
+        } => resolver
+            .lookup_host(host, *port)
+            .await
+            .map_err(|err| err.to_string())?,
+        ServerAddress::Host { host, port: None } => {
+            let mut addrs = Vec::new();
+            let srv_name = format!("{}.{}", SRV_PREFIX, host);
+            match resolver.lookup_srv(&srv_name).await {
+                Ok(mut targets) => {
+                    // a single "." target means the service is unavailable
+                    targets.retain(|target| !target.host.is_empty());
+
+                    // lower priorities first, then heavier weights
+                    targets.sort_by_key(|target| (target.priority, u16::MAX - target.weight));
+
+                    for target in targets {
+                        match resolver.lookup_host(&target.host, target.port).await {
+                            Ok(found) => addrs.extend(found),
+                            Err(err) => {
+                                debug!("failed to resolve SRV target {:?}: {}", target, err)
+                            }
+                        }
+                    }
+                }
+                Err(err) => debug!("no SRV record for {}: {}", srv_name, err),
+            }
+
+            if addrs.is_empty() {
+                addrs = resolver
+                    .lookup_host(host, DEFAULT_PORT)
+                    .await
+                    .map_err(|err| err.to_string())?;
+            }
+
+            addrs
+        }
+    };
+
+    let addrs = interleave_families(addrs);
+
+    if addrs.is_empty() {
+        return Err("no addresses found".to_string());
+    }
+
+    Ok(addrs)
+}
+
+/// Removes duplicate addresses and alternates between IPv6 and IPv4 while
+/// keeping the resolver's order within each family, starting with the family
+/// of the first address. This way, one broken address family only delays
+/// every other attempt.
+pub fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let mut deduped = Vec::with_capacity(addrs.len());
+    for addr in addrs {
+        if !deduped.contains(&addr) {
+            deduped.push(addr);
+        }
+    }
+
+    let Some(first) = deduped.first() else {
+        return deduped;
+    };
+
+    let first_is_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = deduped
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_v6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => break,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    interleaved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct MockResolver {
+        srv: HashMap<String, Vec<SrvTarget>>,
+        hosts: HashMap<String, Vec<IpAddr>>,
+    }
+
+    #[async_trait]
+    impl Resolver for MockResolver {
+        async fn lookup_srv(&self, name: &str) -> io::Result<Vec<SrvTarget>> {
+            self.srv
+                .get(name)
+                .cloned()
+                .ok_or_else(|| io::ErrorKind::NotFound.into())
+        }
+
+        async fn lookup_host(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            let ips = self.hosts.get(host).ok_or(io::ErrorKind::NotFound)?;
+            Ok(ips.iter().map(|ip| SocketAddr::new(*ip, port)).collect())
+        }
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_addresses() {
+        let host = |host: &str, port| ServerAddress::Host {
+            host: host.to_string(),
+            port,
+        };
+
+        let ip_addr = |s: &str, port| ServerAddress::Ip { ip: ip(s), port };
+
+        let parse = |s| ServerAddress::parse(s).unwrap();
+        assert_eq!(parse("example.com"), host("example.com", None));
+        assert_eq!(parse("example.com:1234"), host("example.com", Some(1234)));
+        assert_eq!(parse("127.0.0.1"), ip_addr("127.0.0.1", None));
+        assert_eq!(parse("127.0.0.1:1234"), ip_addr("127.0.0.1", Some(1234)));
+        assert_eq!(parse("::1"), ip_addr("::1", None));
+        assert_eq!(parse("[::1]"), ip_addr("::1", None));
+        assert_eq!(parse("[::1]:1234"), ip_addr("::1", Some(1234)));
+        assert!(ServerAddress::parse("example.com:http").is_err());
+
+        let ws = |url: &str, secure, addr| ServerAddress::WebSocket {
+            url: url.to_string(),
+            secure,
+            addr: Box::new(addr),
+        };
+
+        let url = "ws://example.com/hearth";
+        assert_eq!(parse(url), ws(url, false, host("example.com", None)));
+        let url = "wss://[::1]:1234";
+        assert_eq!(parse(url), ws(url, true, ip_addr("::1", Some(1234))));
+        assert!(ServerAddress::parse("ws://user@example.com").is_err());
+        assert!(ServerAddress::parse("wss://").is_err());
+    }
+
+    #[test]
+    fn interleave() {
+        let addrs = vec![
+            addr("[::1]:1"),
+            addr("[::2]:1"),
+            addr("[::3]:1"),
+            addr("1.1.1.1:1"),
+            addr("[::1]:1"),
+            addr("2.2.2.2:1"),
+        ];
+
+        let expected = vec![
+            addr("[::1]:1"),
+            addr("1.1.1.1:1"),
+            addr("[::2]:1"),
+            addr("2.2.2.2:1"),
+            addr("[::3]:1"),
+        ];
+
+        assert_eq!(interleave_families(addrs), expected);
+    }
+
+    #[tokio::test]
+    async fn explicit_port() {
+        let mut resolver = MockResolver::default();
+        resolver
+            .hosts
+            .insert("example.com".into(), vec![ip("::1"), ip("10.0.0.1")]);
+        resolver.srv.insert(
+            "_hearth._tcp.example.com".into(),
+            vec![SrvTarget {
+                priority: 0,
+                weight: 0,
+                host: "other.com".into(),
+                port: 9999,
+            }],
+        );
+
+        let server = ServerAddress::parse("example.com:1234").unwrap();
+        let addrs = resolve(&server, &resolver).await.unwrap();
+        assert_eq!(addrs, vec![addr("[::1]:1234"), addr("10.0.0.1:1234")]);
+    }
+
+    #[tokio::test]
+    async fn default_port_without_srv() {
+        let mut resolver = MockResolver::default();
+        resolver
+            .hosts
+            .insert("example.com".into(), vec![ip("10.0.0.1")]);
+
+        let server = ServerAddress::parse("example.com").unwrap();
+        let addrs = resolve(&server, &resolver).await.unwrap();
+        assert_eq!(addrs, vec![SocketAddr::new(ip("10.0.0.1"), DEFAULT_PORT)]);
+    }
+
+    #[tokio::test]
+    async fn websocket_default_port() {
+        let mut resolver = MockResolver::default();
+        resolver
+            .hosts
+            .insert("example.com".into(), vec![ip("10.0.0.1")]);
+        resolver.srv.insert(
+            "_hearth._tcp.example.com".into(),
+            vec![SrvTarget {
+                priority: 0,
+                weight: 0,
+                host: "other.com".into(),
+                port: 9999,
+            }],
+        );
+
+        let server = ServerAddress::parse("wss://example.com/").unwrap();
+        let addrs = resolve(&server, &resolver).await.unwrap();
+        assert_eq!(addrs, vec![addr("10.0.0.1:443")]);
+    }
+
+    #[tokio::test]
+    async fn srv_ordering() {
+        let target = |priority, weight, host: &str, port| SrvTarget {
+            priority,
+            weight,
+            host: host.into(),
+            port,
+        };
+
+        let mut resolver = MockResolver::default();
+        resolver.srv.insert(
+            "_hearth._tcp.example.com".into(),
+            vec![
+                target(10, 0, "backup.example.com", 3000),
+                target(0, 1, "light.example.com", 2000),
+                target(0, 5, "heavy.example.com", 1000),
+                target(0, 5, "missing.example.com", 1000),
+            ],
+        );
+
+        resolver
+            .hosts
+            .insert("heavy.example.com".into(), vec![ip("10.0.0.1")]);
+        resolver
+            .hosts
+            .insert("light.example.com".into(), vec![ip("10.0.0.2")]);
+        resolver
+            .hosts
+            .insert("backup.example.com".into(), vec![ip("10.0.0.3")]);
+        resolver
+            .hosts
+            .insert("example.com".into(), vec![ip("10.0.0.4")]);
+
+        let server = ServerAddress::parse("example.com").unwrap();
+        let addrs = resolve(&server, &resolver).await.unwrap();
+        let expected = vec![
+            addr("10.0.0.1:1000"),
+            addr("10.0.0.2:2000"),
+            addr("10.0.0.3:3000"),
+        ];
+
+        assert_eq!(addrs, expected);
+    }
+
+    #[tokio::test]
+    async fn unresolvable() {
+        let resolver = MockResolver::default();
+        let server = ServerAddress::parse("example.com").unwrap();
+        assert!(resolve(&server, &resolver).await.is_err());
+    }
+}
@@ -16,15 +16,15 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{sync::Arc, time::Instant};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use glam::{dvec2, uvec2, Mat4};
+use glam::{dvec2, uvec2, vec2, DVec2};
 use hearth_rend3::{
-    rend3::{
-        self,
-        types::{Camera, CameraProjection},
-    },
-    wgpu, FrameRequest, Rend3Plugin,
+    rend3::{self, types::SampleCount},
+    wgpu, FrameOutcome, FrameRequest, Rend3Command, Rend3Plugin, RenderDevice,
 };
 use hearth_runtime::{
     async_trait, cargo_process_metadata,
@@ -32,19 +32,29 @@ use hearth_runtime::{
     hearth_schema::window::*,
     process::ProcessMetadata,
     runtime::{Plugin, RuntimeBuilder},
-    utils::{MessageInfo, PubSub, ServiceRunner, SinkProcess},
+    utils::{
+        MessageInfo, PubSub, RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner,
+        SinkProcess,
+    },
 };
 use rend3::InstanceAdapterDevice;
-use tokio::sync::{mpsc, oneshot};
-use tracing::warn;
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot, watch};
+use tracing::{info, warn};
 use winit::{
     event::{DeviceEvent, Event, WindowEvent as WinitWindowEvent},
     event_loop::{ControlFlow, EventLoop, EventLoopBuilder, EventLoopProxy},
     window::{Window as WinitWindow, WindowBuilder},
 };
 
+use crate::{
+    camera::{CameraPose, CameraService, CameraSources, CameraTarget, DEFAULT_SOURCE},
+    focus::{FocusInput, FocusService},
+    input::{InputMapPlugin, RawInput},
+};
+
 /// A message sent from the rest of the program to a window.
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum WindowRxMessage {
     /// Update the title.
     SetTitle(String),
@@ -55,21 +65,44 @@ pub enum WindowRxMessage {
     /// Set the cursor visibility.
     SetCursorVisible(bool),
 
-    /// Update the renderer camera.
-    SetCamera {
-        /// Vertical field of view in degrees.
-        vfov: f32,
+    /// Set the vertical sync mode.
+    SetVsync(VsyncMode),
 
-        /// Near plane distance. All projection uses an infinite far plane.
-        near: f32,
+    /// Set the position of the IME candidate window.
+    SetImePosition(DVec2),
 
-        /// The camera's view matrix.
-        view: Mat4,
-    },
+    /// Update the target of a camera source.
+    SetCamera { source: u32, target: CameraTarget },
+
+    /// Add a camera source with a priority.
+    AddCameraSource { id: u32, priority: u32 },
+
+    /// Remove a camera source, handing control to the next source.
+    RemoveCameraSource(u32),
 
     /// Broadcast the current state of the window to all event subscribers.
     BroadcastState,
 
+    /// Apply the renderer configuration.
+    Configure(RendererConfig),
+
+    /// Mark the window's contents as changed so that it redraws soon.
+    Redraw,
+
+    /// The last requested frame has finished rendering.
+    FrameComplete,
+
+    /// The last requested frame was skipped because the GPU device was lost
+    /// and the renderer has moved to a new device.
+    DeviceReset,
+
+    /// Perform a clipboard request on the window thread, which some platforms
+    /// require for clipboard access.
+    Clipboard {
+        request: ClipboardRequest,
+        reply: oneshot::Sender<ClipboardResponse>,
+    },
+
     /// The window is requested to quit.
     Quit,
 }
@@ -94,6 +127,9 @@ pub struct WindowOffer {
 
     /// The [WindowPlugin] for this window.
     pub window_plugin: WindowPlugin,
+
+    /// The [InputMapPlugin] that maps this window's inputs to actions.
+    pub input_plugin: InputMapPlugin,
 }
 
 /// A single running desktop window.
@@ -113,17 +149,63 @@ struct Window {
     /// This window's wgpu surface configuration.
     config: wgpu::SurfaceConfiguration,
 
+    /// Whether the surface needs to be reconfigured before the next frame.
+    config_dirty: bool,
+
     /// Sender of frame requests to the rend3 renderer.
-    frame_request_tx: mpsc::UnboundedSender<FrameRequest>,
+    frame_request_tx: mpsc::Sender<FrameRequest>,
 
-    /// This window's current camera in the rend3 world..
-    camera: Camera,
+    /// Follows the renderer's device so that the surface can be recreated
+    /// after a device reset.
+    device_rx: watch::Receiver<RenderDevice>,
+
+    /// This window's camera sources and the smoothed camera they drive.
+    cameras: CameraSources,
 
     /// Outgoing window events.
     events_tx: mpsc::UnboundedSender<WindowEvent>,
 
+    /// Outgoing raw inputs to the input map.
+    input_tx: mpsc::UnboundedSender<RawInput>,
+
+    /// Outgoing clicks and keyboard input to the focus service.
+    focus_tx: mpsc::UnboundedSender<FocusInput>,
+
     /// Tracks the last redraw to this window.
     last_redraw: Instant,
+
+    /// Whether the window's contents have changed since the last redraw.
+    dirty: bool,
+
+    /// Whether a frame has been requested and hasn't finished rendering yet.
+    frame_in_flight: bool,
+
+    /// The minimum time between frames, if frame rate is capped.
+    min_frame_interval: Option<Duration>,
+
+    /// The maximum time between frames, if animations are redrawn
+    /// continuously.
+    max_frame_interval: Option<Duration>,
+
+    /// A sender to this window's own event loop, used to signal frame
+    /// completion.
+    proxy: EventLoopProxy<WindowRxMessage>,
+
+    /// A handle to the Tokio runtime, used to wait for frame completion
+    /// without blocking the event loop.
+    tokio: tokio::runtime::Handle,
+
+    /// The latest cursor position since the last redraw, if it has moved.
+    ///
+    /// Cursor movement is coalesced into one event per frame so that
+    /// high-polling-rate mice don't flood subscribers.
+    pending_cursor: Option<DVec2>,
+
+    /// The last known cursor position, if the cursor is over the window.
+    cursor: Option<DVec2>,
+
+    /// The host clipboard. Initialized on first use.
+    clipboard: Option<arboard::Clipboard>,
 }
 
 impl Window {
@@ -134,9 +216,12 @@ impl Window {
             .build(event_loop)
             .unwrap();
 
+        // deliver composed text as Ime events instead of raw characters
+        window.set_ime_allowed(true);
+
         let size = window.inner_size();
         let swapchain_format = wgpu::TextureFormat::Bgra8UnormSrgb;
-        let iad = rend3::create_iad(None, None, None, None).await.unwrap();
+        let iad = hearth_rend3::create_iad(None).await.unwrap();
         let surface = unsafe { iad.instance.create_surface(&window) };
         let surface = Arc::new(surface);
 
@@ -152,7 +237,12 @@ impl Window {
         let (outgoing_tx, outgoing_rx) = mpsc::unbounded_channel();
         let rend3_plugin = Rend3Plugin::new(iad.to_owned(), swapchain_format);
         let frame_request_tx = rend3_plugin.frame_request_tx.clone();
+        let device_rx = rend3_plugin.subscribe_device();
         let (events_tx, events_rx) = mpsc::unbounded_channel();
+        let (input_tx, input_rx) = mpsc::unbounded_channel();
+        let input_plugin = InputMapPlugin::new(input_tx.clone(), input_rx);
+        let (focus_tx, focus_rx) = mpsc::unbounded_channel();
+        let unfocused_tx = events_tx.clone();
 
         let window = Self {
             outgoing_tx,
@@ -160,15 +250,31 @@ impl Window {
             iad,
             surface,
             config,
-            camera: Camera::default(),
+            config_dirty: false,
+            cameras: CameraSources::default(),
             frame_request_tx,
+            device_rx,
             events_tx,
+            input_tx,
+            focus_tx,
             last_redraw: Instant::now(),
+            dirty: true,
+            frame_in_flight: false,
+            min_frame_interval: None,
+            max_frame_interval: None,
+            proxy: event_loop.create_proxy(),
+            tokio: tokio::runtime::Handle::current(),
+            pending_cursor: None,
+            cursor: None,
+            clipboard: None,
         };
 
         let window_plugin = WindowPlugin {
             incoming: event_loop.create_proxy(),
             events_rx,
+            focus_rx,
+            unfocused_tx,
+            rend3_command_tx: rend3_plugin.command_tx.clone(),
         };
 
         let offer = WindowOffer {
@@ -176,6 +282,7 @@ impl Window {
             outgoing: outgoing_rx,
             rend3_plugin,
             window_plugin,
+            input_plugin,
         };
 
         (window, offer)
@@ -184,11 +291,81 @@ impl Window {
     pub fn on_resize(&mut self, size: winit::dpi::PhysicalSize<u32>) {
         self.config.width = size.width;
         self.config.height = size.height;
-        self.surface.configure(&self.iad.device, &self.config);
-        self.window.request_redraw();
+        self.config_dirty = true;
+        self.dirty = true;
+    }
+
+    /// Applies a renderer configuration to this window.
+    pub fn configure(&mut self, config: RendererConfig) {
+        let interval = |fps: f64| (fps > 0.0).then(|| Duration::from_secs_f64(1.0 / fps));
+        self.min_frame_interval = interval(config.max_fps);
+        self.max_frame_interval = interval(config.min_fps);
+        self.set_vsync(config.vsync);
+    }
+
+    /// Decides when the next frame should be drawn, requesting a redraw if
+    /// it's due, and returns the control flow to wait with.
+    pub fn pace(&mut self) -> ControlFlow {
+        // wait for the in-flight frame to complete first
+        if self.frame_in_flight {
+            return ControlFlow::Wait;
+        }
+
+        let due = if self.dirty {
+            let earliest = self.min_frame_interval.map(|min| self.last_redraw + min);
+            Some(earliest.unwrap_or(self.last_redraw))
+        } else {
+            self.max_frame_interval.map(|max| {
+                let latest = self.last_redraw + max;
+                let earliest = self.min_frame_interval.map(|min| self.last_redraw + min);
+                latest.max(earliest.unwrap_or(latest))
+            })
+        };
+
+        match due {
+            Some(due) if due <= Instant::now() => {
+                self.window.request_redraw();
+                ControlFlow::Wait
+            }
+            Some(due) => ControlFlow::WaitUntil(due),
+            None => ControlFlow::Wait,
+        }
+    }
+
+    /// Sets the surface's present mode for the given vsync mode.
+    ///
+    /// The surface is reconfigured before the next frame is requested so
+    /// that it's never reconfigured while a frame is in flight.
+    pub fn set_vsync(&mut self, mode: VsyncMode) {
+        // wgpu 0.12 can't list the present modes that a surface supports, but
+        // it falls back to FIFO (always supported) for unsupported modes
+        let present_mode = match mode {
+            VsyncMode::On => wgpu::PresentMode::Fifo,
+            VsyncMode::Off => wgpu::PresentMode::Immediate,
+            VsyncMode::Mailbox => wgpu::PresentMode::Mailbox,
+        };
+
+        info!("Using {:?} present mode for vsync {:?}", present_mode, mode);
+        self.config.present_mode = present_mode;
+        self.config_dirty = true;
+        self.dirty = true;
     }
 
     pub fn on_draw(&mut self) {
+        // never reconfigure the surface or queue frames while one is in
+        // flight; draw again once it completes instead
+        if self.frame_in_flight {
+            self.dirty = true;
+            return;
+        }
+
+        self.flush_cursor();
+
+        if self.config_dirty {
+            self.surface.configure(&self.iad.device, &self.config);
+            self.config_dirty = false;
+        }
+
         // notify redraw event
         let now = Instant::now();
         let elapsed = now.duration_since(self.last_redraw);
@@ -206,18 +383,50 @@ impl Window {
 
         let request = FrameRequest {
             output_frame,
-            camera: self.camera,
+            camera: self.cameras.update(dt),
             resolution,
             on_complete,
         };
 
-        if self.frame_request_tx.send(request).is_err() {
-            tracing::warn!("failed to request frame");
-        } else {
-            let _ = on_complete_rx.blocking_recv();
+        match self.frame_request_tx.try_send(request) {
+            Ok(()) => {}
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                // the renderer is behind, so try again on the next redraw
+                self.dirty = true;
+                return;
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                tracing::warn!("failed to request frame");
+                return;
+            }
         }
 
-        self.window.request_redraw();
+        // keep drawing until the camera reaches its target
+        self.dirty = self.cameras.is_moving();
+
+        self.frame_in_flight = true;
+        let proxy = self.proxy.clone();
+        self.tokio.spawn(async move {
+            let message = match on_complete_rx.await {
+                Ok(FrameOutcome::DeviceReset) => WindowRxMessage::DeviceReset,
+                _ => WindowRxMessage::FrameComplete,
+            };
+
+            let _ = proxy.send_event(message);
+        });
+    }
+
+    /// Recreates this window's surface on the renderer's new device after
+    /// the old device was lost, then redraws.
+    pub fn on_device_reset(&mut self) {
+        self.iad = self.device_rx.borrow_and_update().iad.clone();
+        let surface = unsafe { self.iad.instance.create_surface(&self.window) };
+        self.surface = Arc::new(surface);
+        self.surface.configure(&self.iad.device, &self.config);
+        self.config_dirty = false;
+        self.frame_in_flight = false;
+        self.dirty = true;
+        info!("Recreated the window surface after a GPU device reset");
     }
 
     pub fn on_event(&mut self, event: &WinitWindowEvent) -> bool {
@@ -230,6 +439,10 @@ impl Window {
                 self.notify_event(WindowEvent::ReceivedCharacter(*c));
             }
             WinitWindowEvent::Focused(focus) => {
+                if !*focus {
+                    self.send_input(RawInput::Unfocused);
+                }
+
                 self.notify_event(WindowEvent::Focused(*focus));
             }
             WinitWindowEvent::KeyboardInput {
@@ -237,42 +450,74 @@ impl Window {
                 is_synthetic,
                 ..
             } => {
+                let state = conv_element_state(input.state);
+                let virtual_keycode = input.virtual_keycode.map(conv_keycode);
+
+                // synthetic presses come from keys held while focusing
+                if let (Some(key), false) = (virtual_keycode, *is_synthetic) {
+                    self.send_input(RawInput::Key {
+                        key,
+                        state: state.clone(),
+                    });
+                }
+
                 self.notify_event(WindowEvent::KeyboardInput {
                     input: KeyboardInput {
                         scancode: input.scancode,
-                        state: conv_element_state(input.state),
-                        virtual_keycode: input.virtual_keycode.map(conv_keycode),
+                        state,
+                        virtual_keycode,
                     },
                     is_synthetic: *is_synthetic,
                 });
             }
             WinitWindowEvent::ModifiersChanged(modifiers) => {
-                self.notify_event(WindowEvent::ModifiersChanged(
-                    ModifiersState::from_bits(modifiers.bits()).unwrap(),
-                ));
+                let modifiers = ModifiersState::from_bits(modifiers.bits()).unwrap();
+                self.send_input(RawInput::Modifiers(modifiers));
+                self.notify_event(WindowEvent::ModifiersChanged(modifiers));
             }
             WinitWindowEvent::CursorMoved { position, .. } => {
-                self.notify_event(WindowEvent::CursorMoved {
-                    position: dvec2(position.x, position.y),
-                });
+                let position = dvec2(position.x, position.y);
+                self.pending_cursor = Some(position);
+                self.cursor = Some(position);
+
+                // the coalesced position is only sent on the next redraw
+                self.dirty = true;
             }
             WinitWindowEvent::CursorEntered { .. } => {
                 self.notify_event(WindowEvent::CursorEntered {});
             }
             WinitWindowEvent::CursorLeft { .. } => {
+                self.flush_cursor();
+                self.cursor = None;
                 self.notify_event(WindowEvent::CursorLeft {});
             }
             WinitWindowEvent::MouseWheel { delta, phase, .. } => {
+                self.flush_cursor();
                 self.notify_event(WindowEvent::MouseWheel {
                     delta: conv_scroll_delta(*delta),
                     phase: conv_touch_phase(*phase),
                 });
             }
             WinitWindowEvent::MouseInput { state, button, .. } => {
-                self.notify_event(WindowEvent::MouseInput {
-                    state: conv_element_state(*state),
-                    button: conv_mouse_button(*button),
+                // clicks apply to the latest cursor position
+                self.flush_cursor();
+
+                let state = conv_element_state(*state);
+                let button = conv_mouse_button(*button);
+
+                if let (ElementState::Pressed, MouseButton::Left) = (&state, &button) {
+                    self.send_focus_click();
+                }
+
+                self.send_input(RawInput::Mouse {
+                    button,
+                    state: state.clone(),
                 });
+
+                self.notify_event(WindowEvent::MouseInput { state, button });
+            }
+            WinitWindowEvent::Ime(ime) => {
+                self.notify_event(WindowEvent::Ime(conv_ime(ime.clone())));
             }
             WinitWindowEvent::CloseRequested => {
                 self.outgoing_tx.send(WindowTxMessage::Quit).unwrap();
@@ -295,8 +540,73 @@ impl Window {
         false
     }
 
+    pub fn on_clipboard(&mut self, request: ClipboardRequest) -> ClipboardResponse {
+        if self.clipboard.is_none() {
+            match arboard::Clipboard::new() {
+                Ok(clipboard) => self.clipboard = Some(clipboard),
+                Err(err) => {
+                    warn!("failed to open clipboard: {err:?}");
+                    return Err(ClipboardError::Unavailable);
+                }
+            }
+        }
+
+        let clipboard = self.clipboard.as_mut().unwrap();
+
+        let result = match request {
+            ClipboardRequest::GetText => clipboard.get_text().map(ClipboardSuccess::GetText),
+            ClipboardRequest::SetText(text) => {
+                clipboard.set_text(text).map(|_| ClipboardSuccess::SetText)
+            }
+        };
+
+        use arboard::Error;
+        result.map_err(|err| match err {
+            Error::ContentNotAvailable => ClipboardError::Empty,
+            Error::ClipboardNotSupported => ClipboardError::Unavailable,
+            err => ClipboardError::Other(err.to_string()),
+        })
+    }
+
+    /// Sends the coalesced cursor position, if the cursor has moved.
+    pub fn flush_cursor(&mut self) {
+        if let Some(position) = self.pending_cursor.take() {
+            self.notify_event(WindowEvent::CursorMoved { position });
+        }
+    }
+
+    /// Sends a focus click along the ray under the cursor, if it's over the
+    /// window.
+    pub fn send_focus_click(&self) {
+        let Some(cursor) = self.cursor else {
+            return;
+        };
+
+        let size = vec2(self.config.width as f32, self.config.height as f32);
+        if size.min_element() <= 0.0 {
+            return;
+        }
+
+        let position = cursor.as_vec2() / size;
+        let ndc = vec2(position.x * 2.0 - 1.0, 1.0 - position.y * 2.0);
+        let (origin, direction) = self.cameras.pose().ray(ndc, size.x / size.y);
+        let _ = self.focus_tx.send(FocusInput::Click { origin, direction });
+    }
+
+    /// Sends an event to the window's subscribers.
+    ///
+    /// Keyboard input goes through the focus service instead, which only
+    /// passes it on to subscribers while nothing is focused.
     pub fn notify_event(&self, event: WindowEvent) {
-        let _ = self.events_tx.send(event);
+        if event.is_keyboard_input() {
+            let _ = self.focus_tx.send(FocusInput::Event(event));
+        } else {
+            let _ = self.events_tx.send(event);
+        }
+    }
+
+    pub fn send_input(&self, input: RawInput) {
+        let _ = self.input_tx.send(input);
     }
 
     pub fn broadcast_state(&self) {
@@ -340,7 +650,9 @@ impl WindowCtx {
                     }
                 }
                 Event::MainEventsCleared => {
-                    window.window.request_redraw();
+                    if !control_flow.exit_requested() {
+                        *control_flow = window.pace();
+                    }
                 }
                 Event::RedrawRequested(_) => {
                     window.on_draw();
@@ -370,13 +682,30 @@ impl WindowCtx {
                     WindowRxMessage::SetCursorVisible(visible) => {
                         window.window.set_cursor_visible(visible)
                     }
-                    WindowRxMessage::SetCamera { vfov, near, view } => {
-                        window.camera = Camera {
-                            projection: CameraProjection::Perspective { vfov, near },
-                            view,
-                        }
+                    WindowRxMessage::SetVsync(mode) => window.set_vsync(mode),
+                    WindowRxMessage::SetImePosition(position) => {
+                        let position = winit::dpi::PhysicalPosition::new(position.x, position.y);
+                        window.window.set_ime_position(position);
+                    }
+                    WindowRxMessage::SetCamera { source, target } => {
+                        window.cameras.set(source, target);
+                        window.dirty = true;
+                    }
+                    WindowRxMessage::AddCameraSource { id, priority } => {
+                        window.cameras.add(id, priority);
+                    }
+                    WindowRxMessage::RemoveCameraSource(id) => {
+                        window.cameras.remove(id);
+                        window.dirty = true;
                     }
                     WindowRxMessage::BroadcastState => window.broadcast_state(),
+                    WindowRxMessage::Configure(config) => window.configure(config),
+                    WindowRxMessage::Redraw => window.dirty = true,
+                    WindowRxMessage::FrameComplete => window.frame_in_flight = false,
+                    WindowRxMessage::DeviceReset => window.on_device_reset(),
+                    WindowRxMessage::Clipboard { request, reply } => {
+                        let _ = reply.send(window.on_clipboard(request));
+                    }
                     WindowRxMessage::Quit => control_flow.set_exit(),
                 },
                 _ => (),
@@ -389,6 +718,33 @@ impl WindowCtx {
 pub struct WindowPlugin {
     incoming: EventLoopProxy<WindowRxMessage>,
     events_rx: mpsc::UnboundedReceiver<WindowEvent>,
+    focus_rx: mpsc::UnboundedReceiver<FocusInput>,
+
+    /// Passes keyboard input on to window subscribers while nothing is
+    /// focused.
+    unfocused_tx: mpsc::UnboundedSender<WindowEvent>,
+
+    rend3_command_tx: mpsc::UnboundedSender<Rend3Command>,
+}
+
+impl WindowPlugin {
+    /// Creates a sender of IME candidate window positions, in physical
+    /// display units from the top-left corner of the window, for native
+    /// plugins that edit text.
+    pub fn ime_position_sender(&self) -> mpsc::UnboundedSender<glam::Vec2> {
+        let (tx, mut rx) = mpsc::unbounded_channel::<glam::Vec2>();
+        let incoming = self.incoming.clone();
+        tokio::spawn(async move {
+            while let Some(position) = rx.recv().await {
+                let message = WindowRxMessage::SetImePosition(position.as_dvec2());
+                if incoming.send_event(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        tx
+    }
 }
 
 impl Plugin for WindowPlugin {
@@ -404,6 +760,49 @@ impl Plugin for WindowPlugin {
             }
         });
 
+        let renderer_config: RendererConfig =
+            builder.load_config("renderer").unwrap_or_else(|err| {
+                tracing::debug!("Using default renderer config: {}", err);
+                RendererConfig::default()
+            });
+
+        match SampleCount::try_from(renderer_config.msaa) {
+            Ok(samples) => {
+                let _ = self
+                    .rend3_command_tx
+                    .send(Rend3Command::SetSampleCount(samples));
+            }
+            Err(samples) => warn!("Unsupported MSAA sample count {}", samples),
+        }
+
+        let _ = self.rend3_command_tx.send(Rend3Command::SetResolutionScale(
+            renderer_config.resolution_scale,
+        ));
+
+        let budget = renderer_config.frame_budget_ms;
+        let budget = (budget > 0.0).then(|| Duration::from_secs_f64(budget / 1000.0));
+        let _ = self
+            .rend3_command_tx
+            .send(Rend3Command::SetFrameBudget(budget));
+
+        self.incoming
+            .send_event(WindowRxMessage::Configure(renderer_config))
+            .unwrap();
+
+        let clipboard_config = builder.load_config("clipboard").unwrap_or_else(|err| {
+            tracing::debug!("Using default clipboard config: {}", err);
+            ClipboardConfig::default()
+        });
+
+        builder.add_plugin(ClipboardService {
+            incoming: self.incoming.clone(),
+            config: clipboard_config,
+        });
+
+        builder.add_plugin(CameraService::new(self.incoming.clone()));
+        let focus = FocusService::new(builder.get_post(), self.focus_rx, self.unfocused_tx);
+        builder.add_plugin(focus);
+
         builder.add_plugin(WindowService {
             incoming: self.incoming,
             pubsub,
@@ -411,6 +810,135 @@ impl Plugin for WindowPlugin {
     }
 }
 
+/// Configuration for the window's renderer.
+///
+/// Loaded from the `renderer` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RendererConfig {
+    /// The vertical sync mode to start with.
+    pub vsync: VsyncMode,
+
+    /// The minimum frame rate to redraw at, even if nothing has visibly
+    /// changed, so that animations driven by redraw events keep running. If
+    /// zero, the window only redraws when its contents change.
+    pub min_fps: f64,
+
+    /// The maximum frame rate to draw at. Uncapped if zero.
+    pub max_fps: f64,
+
+    /// The number of MSAA samples per pixel. Either 1 (disabled) or 4.
+    pub msaa: u8,
+
+    /// The scale of the scene's render resolution relative to the window's
+    /// size. Lower values trade sharpness for performance.
+    pub resolution_scale: f32,
+
+    /// The time in milliseconds a frame may take on either the CPU or the
+    /// GPU before a warning with its timings is logged. Disabled if zero.
+    pub frame_budget_ms: f64,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        Self {
+            vsync: VsyncMode::default(),
+            min_fps: 60.0,
+            max_fps: 0.0,
+            msaa: 1,
+            resolution_scale: 1.0,
+            frame_budget_ms: 50.0,
+        }
+    }
+}
+
+/// Configuration for the clipboard service.
+///
+/// Loaded from the `clipboard` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// The maximum length in bytes of clipboard text passed to or from guests.
+    /// Longer text is truncated.
+    pub max_size: usize,
+}
+
+impl Default for ClipboardConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Truncates a string to at most `max_size` bytes without splitting a
+/// character. Returns true if the string was truncated.
+fn truncate_text(text: &mut String, max_size: usize) -> bool {
+    if text.len() <= max_size {
+        return false;
+    }
+
+    let mut end = max_size;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    text.truncate(end);
+    true
+}
+
+/// A service that provides access to the host clipboard.
+pub struct ClipboardService {
+    incoming: EventLoopProxy<WindowRxMessage>,
+    config: ClipboardConfig,
+}
+
+#[async_trait]
+impl RequestResponseProcess for ClipboardService {
+    type Request = ClipboardRequest;
+    type Response = ClipboardResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, ClipboardRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let max_size = self.config.max_size;
+        let mut request = request.data.clone();
+        if let ClipboardRequest::SetText(text) = &mut request {
+            if truncate_text(text, max_size) {
+                warn!("truncating clipboard text to {max_size} bytes");
+            }
+        }
+
+        let (reply, response) = oneshot::channel();
+        let message = WindowRxMessage::Clipboard { request, reply };
+        if self.incoming.send_event(message).is_err() {
+            let response: ClipboardResponse = Err(ClipboardError::Unavailable);
+            return response.into();
+        }
+
+        let mut response = response.await.unwrap_or(Err(ClipboardError::Unavailable));
+        if let Ok(ClipboardSuccess::GetText(text)) = &mut response {
+            if truncate_text(text, max_size) {
+                warn!("truncating clipboard text to {max_size} bytes");
+            }
+        }
+
+        response.into()
+    }
+}
+
+impl ServiceRunner for ClipboardService {
+    const NAME: &'static str = CLIPBOARD_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("The host clipboard service. Accepts ClipboardRequest.".to_string());
+        meta
+    }
+}
+
 /// A service that implements the windowing protocol using winit.
 pub struct WindowService {
     incoming: EventLoopProxy<WindowRxMessage>,
@@ -453,7 +981,25 @@ impl SinkProcess for WindowService {
             SetTitle(title) => send(WindowRxMessage::SetTitle(title)),
             SetCursorGrab(grab) => send(WindowRxMessage::SetCursorGrab(grab)),
             SetCursorVisible(visible) => send(WindowRxMessage::SetCursorVisible(visible)),
-            SetCamera { vfov, near, view } => send(WindowRxMessage::SetCamera { vfov, near, view }),
+            SetVsync(mode) => send(WindowRxMessage::SetVsync(mode)),
+            SetImePosition(position) => send(WindowRxMessage::SetImePosition(position)),
+            Redraw => send(WindowRxMessage::Redraw),
+            SetCamera { vfov, near, view } => send(WindowRxMessage::SetCamera {
+                source: DEFAULT_SOURCE,
+                target: CameraTarget::snap(CameraPose::new(vfov, near, view)),
+            }),
+            SetCameraTarget {
+                vfov,
+                near,
+                view,
+                smoothing,
+            } => send(WindowRxMessage::SetCamera {
+                source: DEFAULT_SOURCE,
+                target: CameraTarget {
+                    pose: CameraPose::new(vfov, near, view),
+                    smoothing,
+                },
+            }),
         }
     }
 
@@ -512,6 +1058,17 @@ fn conv_mouse_button(button: winit::event::MouseButton) -> MouseButton {
     }
 }
 
+fn conv_ime(ime: winit::event::Ime) -> Ime {
+    use winit::event::Ime as Winit;
+    use Ime as Schema;
+    match ime {
+        Winit::Enabled => Schema::Enabled,
+        Winit::Preedit(text, cursor) => Schema::Preedit { text, cursor },
+        Winit::Commit(text) => Schema::Commit(text),
+        Winit::Disabled => Schema::Disabled,
+    }
+}
+
 fn conv_keycode(code: winit::event::VirtualKeyCode) -> VirtualKeyCode {
     use winit::event::VirtualKeyCode as Winit;
     use VirtualKeyCode as Schema;
@@ -681,3 +1238,38 @@ fn conv_keycode(code: winit::event::VirtualKeyCode) -> VirtualKeyCode {
         Winit::Cut => Schema::Cut,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ime_events_convert() {
+        use winit::event::Ime as Winit;
+
+        let preedit = Winit::Preedit("にほ".to_string(), Some((3, 6)));
+        assert_eq!(
+            conv_ime(preedit),
+            Ime::Preedit {
+                text: "にほ".to_string(),
+                cursor: Some((3, 6)),
+            }
+        );
+
+        let commit = Winit::Commit("日本".to_string());
+        assert_eq!(conv_ime(commit), Ime::Commit("日本".to_string()));
+        assert_eq!(conv_ime(Winit::Enabled), Ime::Enabled);
+        assert_eq!(conv_ime(Winit::Disabled), Ime::Disabled);
+    }
+
+    #[test]
+    fn truncate_on_char_boundary() {
+        let mut text = "héllo".to_string();
+        assert!(truncate_text(&mut text, 2));
+        assert_eq!(text, "h");
+
+        let mut text = "héllo".to_string();
+        assert!(!truncate_text(&mut text, 6));
+        assert_eq!(text, "héllo");
+    }
+}
@@ -0,0 +1,327 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, time::Duration};
+
+use hearth_ipc::Connection;
+use hearth_schema::{
+    process::{
+        ProcessInspectorError, ProcessInspectorRequest, ProcessInspectorResponse,
+        PROCESS_INSPECTOR_SERVICE,
+    },
+    protocol::{CapOperation, LocalCapOperation, RemoteCapOperation, UnlinkReason},
+    registry::{split_peer_name, RegistryRequest, RegistryResponse},
+    Permissions,
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{CommandError, CommandResult, ToCommandError, EX_PROTOCOL, EX_USAGE};
+
+/// Exit code for when a requested service is not available.
+pub const EX_UNAVAILABLE: u8 = 69;
+
+/// Exit code for when the daemon refuses an operation.
+pub const EX_NOPERM: u8 = 77;
+
+/// A minimal client-side implementation of the capability exchange protocol
+/// for talking to a Hearth daemon's root registry.
+pub struct DaemonClient {
+    conn: Connection,
+    root: u32,
+    remote_perms: HashMap<u32, Permissions>,
+    next_local: u32,
+}
+
+impl DaemonClient {
+    /// Begins the protocol on an IPC connection and waits for the daemon's
+    /// root cap.
+    pub async fn new(conn: Connection) -> CommandResult<Self> {
+        let mut client = Self {
+            conn,
+            root: 0,
+            remote_perms: HashMap::new(),
+            next_local: 0,
+        };
+
+        loop {
+            if let CapOperation::Local(LocalCapOperation::SetRootCap { id }) =
+                client.next_op().await?
+            {
+                client.root = id;
+                break;
+            }
+        }
+
+        Ok(client)
+    }
+
+    /// Looks up a service in the daemon's root registry by name.
+    ///
+    /// Returns the remote ID of the service's capability.
+    pub async fn get_service(&mut self, name: &str) -> CommandResult<u32> {
+        let request = RegistryRequest::Get {
+            name: name.to_string(),
+        };
+
+        let (response, caps) = self.request(self.root, &request).await?;
+
+        match (response, caps.first()) {
+            (RegistryResponse::Get(true), Some(cap)) => Ok(*cap),
+            (RegistryResponse::Get(false), _) => Err(CommandError {
+                message: format!("service {:?} is not registered", name),
+                exit_code: EX_UNAVAILABLE,
+            }),
+            (response, _) => Err(CommandError {
+                message: format!("unexpected registry response: {:?}", response),
+                exit_code: EX_PROTOCOL,
+            }),
+        }
+    }
+
+    /// Lists the names of every service in the daemon's root registry.
+    pub async fn list_services(&mut self) -> CommandResult<Vec<String>> {
+        let (response, _caps) = self.request(self.root, &RegistryRequest::List).await?;
+
+        match response {
+            RegistryResponse::List(names) => Ok(names),
+            response => Err(CommandError {
+                message: format!("unexpected registry response: {:?}", response),
+                exit_code: EX_PROTOCOL,
+            }),
+        }
+    }
+
+    /// Resolves a service name that may be registered by a peer.
+    ///
+    /// Names registered by the daemon itself are used as-is. Otherwise, the
+    /// name is matched against services that peers registered in their
+    /// `peer.<user>.` namespaces, and fails if more than one peer has it.
+    ///
+    /// Returns the full name and the remote ID of the service's capability.
+    pub async fn resolve_service(&mut self, name: &str) -> CommandResult<(String, u32)> {
+        let names = self.list_services().await?;
+        if names.iter().any(|registered| registered == name) {
+            let cap = self.get_service(name).await?;
+            return Ok((name.to_string(), cap));
+        }
+
+        let matches: Vec<String> = names
+            .into_iter()
+            .filter(|registered| peer_service_name(registered) == Some(name))
+            .collect();
+
+        match matches.as_slice() {
+            [] => Err(CommandError {
+                message: format!("service {:?} is not registered", name),
+                exit_code: EX_UNAVAILABLE,
+            }),
+            [full_name] => {
+                let cap = self.get_service(full_name).await?;
+                Ok((full_name.clone(), cap))
+            }
+            _ => Err(CommandError {
+                message: format!(
+                    "service {:?} is ambiguous between peers: {}",
+                    name,
+                    matches.join(", ")
+                ),
+                exit_code: EX_USAGE,
+            }),
+        }
+    }
+
+    /// Resolves a PID to one of the services that its process is registered
+    /// as, using the daemon's process inspector.
+    ///
+    /// Processes are only addressable over IPC through the registry, so this
+    /// fails if the process isn't registered under any name.
+    ///
+    /// Returns the full name and the remote ID of the service's capability.
+    pub async fn resolve_pid(&mut self, pid: u64) -> CommandResult<(String, u32)> {
+        let inspector = self
+            .get_service(PROCESS_INSPECTOR_SERVICE)
+            .await
+            .map_err(|err| CommandError {
+                message: format!("cannot resolve PIDs: {}", err.message),
+                exit_code: err.exit_code,
+            })?;
+
+        let request = ProcessInspectorRequest::GetProcessDetails { pid, log_tail: 0 };
+        let (response, _caps): (ProcessInspectorResponse, _) =
+            self.request(inspector, &request).await?;
+
+        let details = response.map_err(|err| match err {
+            ProcessInspectorError::NotFound => CommandError {
+                message: format!("no process with PID {}", pid),
+                exit_code: EX_USAGE,
+            },
+        })?;
+
+        let Some(name) = details.services.first() else {
+            return Err(CommandError {
+                message: format!(
+                    "PID {} is not registered as a service, so it isn't addressable over IPC",
+                    pid
+                ),
+                exit_code: EX_UNAVAILABLE,
+            });
+        };
+
+        self.resolve_service(name).await
+    }
+
+    /// Kills a remote capability.
+    ///
+    /// Fails if the daemon did not grant the kill permission on the cap.
+    pub fn kill(&self, id: u32) -> CommandResult<()> {
+        self.check_kill(id)?;
+        self.send_op(CapOperation::Remote(RemoteCapOperation::Kill { id }))
+    }
+
+    /// Asks a remote capability's process to shut down, letting the daemon
+    /// kill it once the grace period has passed.
+    ///
+    /// Fails if the daemon did not grant the kill permission on the cap.
+    pub fn shutdown(&self, id: u32, grace: Duration) -> CommandResult<()> {
+        self.check_kill(id)?;
+        let grace_ms = grace.as_millis().try_into().unwrap_or(u64::MAX);
+        self.send_op(CapOperation::Remote(RemoteCapOperation::Shutdown {
+            id,
+            grace_ms,
+        }))
+    }
+
+    /// Fails if a remote capability does not have the kill permission.
+    fn check_kill(&self, id: u32) -> CommandResult<()> {
+        let perms = self.get_permissions(id)?;
+
+        if !perms.contains(Permissions::KILL) {
+            return Err(CommandError {
+                message: "daemon did not grant kill permission for this capability".into(),
+                exit_code: EX_NOPERM,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Retrieves the permissions of a remote capability.
+    pub fn get_permissions(&self, id: u32) -> CommandResult<Permissions> {
+        self.remote_perms.get(&id).copied().to_command_error(
+            format!("remote capability {} is not declared", id),
+            EX_PROTOCOL,
+        )
+    }
+
+    /// Sends a JSON request to a remote capability and waits for its JSON
+    /// response.
+    ///
+    /// Returns the response and the remote IDs of the capabilities attached
+    /// to the response.
+    pub async fn request<Req: Serialize, Resp: DeserializeOwned>(
+        &mut self,
+        id: u32,
+        request: &Req,
+    ) -> CommandResult<(Resp, Vec<u32>)> {
+        let reply = self.next_local;
+        self.next_local += 1;
+
+        self.send_op(CapOperation::Local(LocalCapOperation::DeclareCap {
+            id: reply,
+            perms: Permissions::SEND,
+        }))?;
+
+        let data = serde_json::to_vec(request).to_command_error("encoding request", EX_PROTOCOL)?;
+
+        self.send_op(CapOperation::Remote(RemoteCapOperation::Send {
+            id,
+            data,
+            caps: vec![reply],
+        }))?;
+
+        let result = loop {
+            match self.next_op().await? {
+                CapOperation::Remote(RemoteCapOperation::Send { id, data, caps })
+                    if id == reply =>
+                {
+                    break serde_json::from_slice(&data)
+                        .to_command_error("decoding response", EX_PROTOCOL)
+                        .map(|response| (response, caps));
+                }
+                CapOperation::Local(LocalCapOperation::RevokeCap { id: revoked, .. })
+                    if revoked == id =>
+                {
+                    break Err(CommandError {
+                        message: "capability was revoked while awaiting response".into(),
+                        exit_code: EX_PROTOCOL,
+                    });
+                }
+                _ => {}
+            }
+        };
+
+        self.send_op(CapOperation::Local(LocalCapOperation::RevokeCap {
+            id: reply,
+            reason: UnlinkReason::AccessRevoked,
+        }))?;
+
+        result
+    }
+
+    /// Sends a single operation to the daemon.
+    fn send_op(&self, op: CapOperation) -> CommandResult<()> {
+        self.conn
+            .op_tx
+            .send(op)
+            .to_command_error("sending to Hearth daemon", EX_PROTOCOL)
+    }
+
+    /// Waits for the next operation from the daemon, handling bookkeeping of
+    /// declared and revoked remote capabilities.
+    async fn next_op(&mut self) -> CommandResult<CapOperation> {
+        let op = self
+            .conn
+            .op_rx
+            .recv_async()
+            .await
+            .to_command_error("receiving from Hearth daemon", EX_PROTOCOL)?;
+
+        match &op {
+            CapOperation::Local(LocalCapOperation::DeclareCap { id, perms }) => {
+                self.remote_perms.insert(*id, *perms);
+            }
+            CapOperation::Local(LocalCapOperation::RevokeCap { id, .. }) => {
+                self.remote_perms.remove(id);
+                let id = *id;
+                self.send_op(CapOperation::Remote(
+                    RemoteCapOperation::AcknowledgeRevocation { id },
+                ))?;
+            }
+            _ => {}
+        }
+
+        Ok(op)
+    }
+}
+
+/// Strips the `peer.<user>.` namespace from a service name registered by a
+/// peer, or returns `None` if the name isn't in a peer's namespace.
+fn peer_service_name(name: &str) -> Option<&str> {
+    let (_user, name) = split_peer_name(name)?;
+    Some(name)
+}
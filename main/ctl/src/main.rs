@@ -16,11 +16,48 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::HashMap, fmt::Display, process::ExitCode};
+use std::{collections::HashMap, fmt::Display, path::PathBuf, process::ExitCode, time::Duration};
 
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
+use daemon::{DaemonClient, EX_UNAVAILABLE};
 use hearth_ipc::Connection;
+use hearth_schema::audit::AuditActor;
+use hearth_schema::group::{
+    ProcessGroupsError, ProcessGroupsRequest, ProcessGroupsResponse, ProcessGroupsSuccess,
+    PROCESS_GROUPS_SERVICE,
+};
+use hearth_schema::network::{
+    ConnectedIdentity, ConnectionTraffic, IdentitiesRequest, NetworkStatsRequest, PeerTraffic,
+    IDENTITIES_SERVICE, NETWORK_STATS_SERVICE,
+};
+use hearth_schema::process::{
+    ProcessDetails, ProcessInspectorError, ProcessInspectorRequest, ProcessInspectorResponse,
+    PROCESS_INSPECTOR_SERVICE,
+};
+use hearth_schema::tap::{TapError, TapRequest, TapResponse, TapSuccess, TapSummary, TAP_SERVICE};
+use hearth_schema::wasm::{
+    LinkRequest, RemoteSpawnError, RemoteSpawnInfo, RemoteSpawnResponse, WasmSpawnError,
+    WasmSpawnInfo, WasmSpawnResponse, WasmSpawnerRequest, REMOTE_SPAWNER_SERVICE,
+};
+use hearth_schema::{LumpId, Permissions};
+use output::OutputFormat;
+use serde::Serialize;
 
+/// Client-side helpers for talking to the daemon over IPC.
+pub mod daemon;
+
+/// Output formatting for command results.
+pub mod output;
+
+/// Chunked uploads of local files to the daemon's lump store.
+pub mod upload;
+
+pub const EX_USAGE: u8 = 64;
+pub const EX_DATAERR: u8 = 65;
+pub const EX_NOINPUT: u8 = 66;
+pub const EX_SOFTWARE: u8 = 70;
+pub const EX_CANTCREAT: u8 = 73;
+pub const EX_IOERR: u8 = 74;
 pub const EX_PROTOCOL: u8 = 76;
 
 pub struct DaemonOffer {}
@@ -66,27 +103,745 @@ pub type CommandResult<T> = Result<T, CommandError>;
 /// Command-line interface (CLI) for interacting with a Hearth daemon over IPC.
 #[derive(Debug, Parser)]
 pub struct Args {
+    #[clap(flatten)]
+    pub daemon: DaemonArgs,
+
+    /// The format to print results in. Errors always go to stderr.
+    #[clap(long, global = true, value_enum, default_value = "table")]
+    pub output: OutputFormat,
+
     #[clap(subcommand)]
     pub command: Commands,
 }
 
+/// Options for locating the daemon to connect to.
+#[derive(Debug, clap::Args)]
+pub struct DaemonArgs {
+    /// The path of the daemon's IPC socket. Overrides --instance.
+    #[clap(long, global = true)]
+    pub socket: Option<PathBuf>,
+
+    /// The name of the daemon instance to connect to. Only that instance's
+    /// socket is tried, even if HEARTH_SOCK is set.
+    #[clap(long, global = true)]
+    pub instance: Option<String>,
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Commands {
     /// A dummy command.
     Dummy,
+
+    /// Kills a service by name, or a process group by ID.
+    ///
+    /// Killing groups requires the daemon's `process_groups.service` config
+    /// option. Exits with 69 if it's disabled and 64 if the group doesn't
+    /// exist.
+    Kill(KillArgs),
+
+    /// Lists the users connected to the daemon's server.
+    Identities,
+
+    /// Prints traffic counters for the daemon's network connections.
+    ///
+    /// Exits with 69 if the daemon isn't a networked client or server.
+    Net(NetArgs),
+
+    /// Prints everything the daemon knows about one running process.
+    ///
+    /// Requires the daemon to enable the process inspector with
+    /// `process_inspector.service` in its config. Exits with 69 if it
+    /// doesn't and 64 if no process has the PID.
+    Inspect(InspectArgs),
+
+    /// Spawns a Wasm process from a lump or a local file.
+    ///
+    /// Exits with 66 if the file can't be read, 74 if uploading it fails, 65
+    /// if the daemon stores it under an unexpected lump ID, 70 if spawning
+    /// fails, and 73 if the process can't be registered.
+    SpawnWasm(SpawnWasmArgs),
+
+    /// Records the messages of a running process to a file until
+    /// interrupted.
+    ///
+    /// Requires the daemon to enable the message tap service with
+    /// `message_tap.service` in its config. Exits with 69 if it doesn't, 73
+    /// if the file can't be created, and 70 if the process can't be
+    /// recorded.
+    Record(RecordArgs),
+
+    /// Replays a recording against a local Wasm module and compares the
+    /// messages it sends with the recorded ones.
+    ///
+    /// A developer command that doesn't use the daemon. Exits with 66 if a
+    /// file can't be read and 65 if the replay doesn't match.
+    #[cfg(feature = "replay")]
+    Replay(ReplayArgs),
+
+    /// Lists the servers advertised on the local network.
+    #[cfg(feature = "discovery")]
+    Discover(DiscoverArgs),
+
+    /// Prints a shell completion script for hearth-ctl.
+    Completions(CompletionsArgs),
 }
 
 impl Commands {
-    pub async fn run(self) -> CommandResult<()> {
+    pub async fn run(self, daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+        match self {
+            Commands::Dummy => Ok(()),
+            Commands::Kill(args) => args.run(daemon, output).await,
+            Commands::Identities => list_identities(daemon, output).await,
+            Commands::Net(args) => args.run(daemon, output).await,
+            Commands::Inspect(args) => args.run(daemon, output).await,
+            Commands::SpawnWasm(args) => args.run(daemon, output).await,
+            Commands::Record(args) => args.run(daemon, output).await,
+            #[cfg(feature = "replay")]
+            Commands::Replay(args) => args.run(output).await,
+            #[cfg(feature = "discovery")]
+            Commands::Discover(args) => args.run(output).await,
+            Commands::Completions(args) => {
+                args.run();
+                Ok(())
+            }
+        }
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct CompletionsArgs {
+    /// The shell to generate completions for.
+    #[clap(value_enum)]
+    pub shell: clap_complete::Shell,
+}
+
+impl CompletionsArgs {
+    pub fn run(self) {
+        let mut command = Args::command();
+        let name = command.get_name().to_string();
+        clap_complete::generate(self.shell, &mut command, name, &mut std::io::stdout());
+    }
+}
+
+/// The result of a kill command.
+#[derive(Debug, Serialize)]
+pub struct KillOutput {
+    /// The name of the targeted service.
+    pub target: String,
+
+    /// The permissions of the daemon's capability to the target.
+    pub permissions: Permissions,
+
+    /// False if this was a dry run.
+    pub killed: bool,
+
+    /// The grace period given to the target in seconds, or `None` if it was
+    /// killed immediately.
+    pub grace: Option<f32>,
+}
+
+/// The result of a kill command targeting a process group.
+#[derive(Debug, Serialize)]
+pub struct GroupKillOutput {
+    /// The ID of the targeted group.
+    pub group: u64,
+
+    /// The number of members that were killed, or that would have been
+    /// killed in a dry run.
+    pub members: usize,
+
+    /// False if this was a dry run.
+    pub killed: bool,
+}
+
+/// The result of a spawn-wasm command.
+#[derive(Debug, Serialize)]
+pub struct SpawnOutput {
+    /// The lump that was spawned.
+    pub lump: LumpId,
+
+    /// The peer that the process was spawned on, if not the daemon.
+    pub peer: Option<String>,
+
+    /// The PID of the new process on the peer that it was spawned on.
+    pub pid: u64,
+
+    /// The name that the process was registered under, if any.
+    pub service: Option<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct KillArgs {
+    /// The PID or service name of the process to kill.
+    ///
+    /// Service names are looked up in the daemon's root registry, and names
+    /// registered by a single peer can be given without their `peer.<user>.`
+    /// prefix. PIDs are resolved to a service that the process is registered
+    /// as through the daemon's process inspector.
+    #[clap(required_unless_present = "group")]
+    pub target: Option<String>,
+
+    /// Tear down the process group with this ID instead, killing all of its
+    /// members.
+    #[clap(long, conflicts_with = "target")]
+    pub group: Option<u64>,
+
+    /// Print what would be killed without killing anything.
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Seconds to let the target shut down before it's killed.
+    #[clap(long, default_value_t = 5.0, conflicts_with = "now")]
+    pub grace: f32,
+
+    /// Kill the target immediately without asking it to shut down.
+    #[clap(long)]
+    pub now: bool,
+}
+
+impl KillArgs {
+    pub async fn run(self, daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+        let target = match (self.target.clone(), self.group) {
+            (_, Some(group)) => return self.kill_group(daemon, output, group).await,
+            (Some(target), None) => target,
+            (None, None) => unreachable!("clap requires a target or a group"),
+        };
+
+        let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+        let (target, cap) = match target.parse::<u64>() {
+            Ok(pid) => daemon.resolve_pid(pid).await?,
+            Err(_) => daemon.resolve_service(&target).await?,
+        };
+        let permissions = daemon.get_permissions(cap)?;
+
+        let grace = if self.now {
+            None
+        } else {
+            let grace = Duration::try_from_secs_f32(self.grace).map_err(|_| CommandError {
+                message: format!("invalid grace period: {}", self.grace),
+                exit_code: EX_USAGE,
+            })?;
+
+            Some(grace)
+        };
+
+        if !self.dry_run {
+            match grace {
+                Some(grace) => daemon.shutdown(cap, grace)?,
+                None => daemon.kill(cap)?,
+            }
+        }
+
+        let result = KillOutput {
+            target,
+            permissions,
+            killed: !self.dry_run,
+            grace: grace.map(|grace| grace.as_secs_f32()),
+        };
+
+        output.print(&result, |result| {
+            if !result.killed {
+                println!(
+                    "would kill {} (permissions: {:?})",
+                    result.target, result.permissions
+                );
+            } else if let Some(grace) = result.grace {
+                println!("shutting down {} ({}s grace)", result.target, grace);
+            } else {
+                println!("killed {}", result.target);
+            }
+        })
+    }
+
+    /// Tears down a process group through the daemon's process groups
+    /// service.
+    ///
+    /// Groups are always killed immediately, so the grace period is ignored.
+    async fn kill_group(
+        self,
+        daemon: DaemonArgs,
+        output: OutputFormat,
+        id: u64,
+    ) -> CommandResult<()> {
+        let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+        let service = daemon.get_service(PROCESS_GROUPS_SERVICE).await?;
+
+        let members = if self.dry_run {
+            let request = ProcessGroupsRequest::List;
+            let (response, _caps): (ProcessGroupsResponse, _) =
+                daemon.request(service, &request).await?;
+
+            let groups = match groups_result(id, response)? {
+                ProcessGroupsSuccess::Groups(groups) => groups,
+                other => return Err(unexpected_groups_response(other)),
+            };
+
+            let group = groups.into_iter().find(|group| group.id == id);
+            let group = group.ok_or_else(|| groups_error(id, ProcessGroupsError::NotFound))?;
+            group.members.len()
+        } else {
+            let request = ProcessGroupsRequest::Kill { id };
+            let (response, _caps): (ProcessGroupsResponse, _) =
+                daemon.request(service, &request).await?;
+
+            match groups_result(id, response)? {
+                ProcessGroupsSuccess::Killed(killed) => killed,
+                other => return Err(unexpected_groups_response(other)),
+            }
+        };
+
+        let result = GroupKillOutput {
+            group: id,
+            members,
+            killed: !self.dry_run,
+        };
+
+        output.print(&result, |result| {
+            if result.killed {
+                println!("killed group {} ({} members)", result.group, result.members);
+            } else {
+                println!(
+                    "would kill group {} ({} members)",
+                    result.group, result.members
+                );
+            }
+        })
+    }
+}
+
+/// Converts a process groups service response to a [CommandError].
+fn groups_result(id: u64, response: ProcessGroupsResponse) -> CommandResult<ProcessGroupsSuccess> {
+    response.map_err(|err| groups_error(id, err))
+}
+
+/// Converts a process groups service error to a [CommandError].
+fn groups_error(id: u64, err: ProcessGroupsError) -> CommandError {
+    match err {
+        ProcessGroupsError::NotFound => CommandError {
+            message: format!("no process group with ID {}", id),
+            exit_code: EX_USAGE,
+        },
+    }
+}
+
+fn unexpected_groups_response(response: ProcessGroupsSuccess) -> CommandError {
+    CommandError {
+        message: format!("unexpected process groups response: {:?}", response),
+        exit_code: EX_PROTOCOL,
+    }
+}
+
+/// Where to get the Wasm module to spawn from.
+#[derive(Clone, Debug)]
+pub enum ModuleSource {
+    /// A lump already in the daemon's lump store.
+    Lump(LumpId),
+
+    /// A local file to upload first.
+    File(PathBuf),
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SpawnWasmArgs {
+    /// The hexadecimal ID of the Wasm module lump to spawn, or the path of a
+    /// local Wasm module to upload and spawn.
+    #[clap(value_parser = parse_module_source)]
+    pub module: ModuleSource,
+
+    /// The index of the entrypoint to run instead of the default one.
+    #[clap(long)]
+    pub entrypoint: Option<u32>,
+
+    /// Spawn on a remote peer instead of the daemon itself.
+    ///
+    /// The peer is the name of its remote spawner in the daemon's registry.
+    /// If the peer doesn't have the lump, it's transferred from the daemon.
+    #[clap(long)]
+    pub peer: Option<String>,
+
+    /// Register the new process in the daemon's registry under this name.
+    ///
+    /// Services registered this way are only visible to IPC clients.
+    #[clap(long, conflicts_with = "peer")]
+    pub service: Option<String>,
+
+    /// Never hibernate the new process while it's idle.
+    #[clap(long)]
+    pub keep_awake: bool,
+
+    /// Only let the new process import this host ABI module, such as
+    /// `hearth::mailbox`. May be given more than once. The daemon's
+    /// configured baseline is used if none are given, and it can't be
+    /// exceeded.
+    #[clap(long = "allow", value_name = "MODULE")]
+    pub allow: Vec<String>,
+}
+
+impl SpawnWasmArgs {
+    pub async fn run(self, daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+        let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+
+        let lump = match &self.module {
+            ModuleSource::Lump(lump) => *lump,
+            ModuleSource::File(path) => {
+                let data = std::fs::read(path)
+                    .to_command_error(format!("reading {}", path.display()), EX_NOINPUT)?;
+
+                upload::upload_lump(&mut daemon, &data, "application/wasm").await?
+            }
+        };
+
+        let spawn = WasmSpawnInfo {
+            lump,
+            entrypoint: self.entrypoint,
+            limits: Default::default(),
+            keep_awake: self.keep_awake,
+            allow: (!self.allow.is_empty()).then(|| self.allow.clone()),
+        };
+
+        let caps = match self.peer.as_ref() {
+            None => {
+                let spawner = daemon.get_service("hearth.wasm.WasmProcessSpawner").await?;
+
+                let request = match self.service.clone() {
+                    None => WasmSpawnerRequest::Spawn(spawn),
+                    Some(service) => WasmSpawnerRequest::SpawnService { spawn, service },
+                };
+
+                let (response, caps): (WasmSpawnResponse, _) =
+                    daemon.request(spawner, &request).await?;
+
+                // the spawner kills processes that it fails to register
+                match response {
+                    Err(WasmSpawnError::Unregistered(err)) => {
+                        return Err(CommandError {
+                            message: format!("failed to register process: {}", err),
+                            exit_code: EX_CANTCREAT,
+                        });
+                    }
+                    response => {
+                        response.to_command_error("failed to spawn process", EX_SOFTWARE)?
+                    }
+                }
+
+                caps
+            }
+            Some(peer) => {
+                let spawner = daemon.get_service(REMOTE_SPAWNER_SERVICE).await?;
+                let request = RemoteSpawnInfo {
+                    peer: Some(peer.clone()),
+                    spawn,
+                    lumps: vec![],
+                };
+
+                let (response, caps): (RemoteSpawnResponse, _) =
+                    daemon.request(spawner, &request).await?;
+
+                match response {
+                    Err(err @ RemoteSpawnError::LumpMismatch(_)) => {
+                        return Err(CommandError {
+                            message: format!("peer received a corrupted module: {}", err),
+                            exit_code: EX_DATAERR,
+                        });
+                    }
+                    response => {
+                        response.to_command_error("failed to spawn process", EX_SOFTWARE)?
+                    }
+                }
+
+                caps
+            }
+        };
+
+        let endpoint = *caps
+            .get(1)
+            .to_command_error("spawner did not return a link endpoint", EX_PROTOCOL)?;
+
+        let (pid, _caps): (u64, _) = daemon.request(endpoint, &LinkRequest::GetPid).await?;
+
+        let result = SpawnOutput {
+            lump,
+            peer: self.peer,
+            pid,
+            service: self.service,
+        };
+
+        output.print(&result, |result| {
+            print!("spawned {} as PID {}", result.lump, result.pid);
+
+            if let Some(peer) = result.peer.as_ref() {
+                print!(" on {}", peer);
+            }
+
+            match result.service.as_ref() {
+                Some(service) => println!(" registered as {}", service),
+                None => println!(),
+            }
+        })
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct RecordArgs {
+    /// The PID of the process to record.
+    pub pid: u64,
+
+    /// The file to write the recording to. The daemon writes it, so it must
+    /// be writable by the daemon. It must not already exist.
+    #[clap(long)]
+    pub out: PathBuf,
+
+    /// The maximum number of messages to record.
+    #[clap(long)]
+    pub limit: Option<u64>,
+
+    /// Seconds to record for instead of waiting for an interrupt.
+    #[clap(long)]
+    pub duration: Option<f32>,
+}
+
+/// The result of a record command.
+#[derive(Debug, Serialize)]
+pub struct RecordOutput {
+    /// The PID of the recorded process.
+    pub pid: u64,
+
+    /// The path of the recording.
+    pub path: PathBuf,
+
+    /// A summary of the recording.
+    pub summary: TapSummary,
+}
+
+impl RecordArgs {
+    pub async fn run(self, daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+        let duration = self
+            .duration
+            .map(Duration::try_from_secs_f32)
+            .transpose()
+            .to_command_error("invalid duration", EX_USAGE)?;
+
+        // the daemon may have a different working directory
+        let path = std::env::current_dir()
+            .to_command_error("getting the working directory", EX_IOERR)?
+            .join(&self.out);
+
+        let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+        let tap = daemon.get_service(TAP_SERVICE).await?;
+
+        let request = TapRequest::Start {
+            pid: self.pid,
+            path: path.clone(),
+            limit: self.limit,
+        };
+
+        let (response, _caps): (TapResponse, _) = daemon.request(tap, &request).await?;
+        tap_result(self.pid, response)?;
+
+        if let OutputFormat::Table = output {
+            eprintln!("recording PID {} to {}", self.pid, path.display());
+        }
+
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+
+        let request = TapRequest::Stop { pid: self.pid };
+        let (response, _caps): (TapResponse, _) = daemon.request(tap, &request).await?;
+
+        let summary = match tap_result(self.pid, response)? {
+            TapSuccess::Stopped(summary) => summary,
+            other => {
+                return Err(CommandError {
+                    message: format!("unexpected message tap response: {:?}", other),
+                    exit_code: EX_PROTOCOL,
+                })
+            }
+        };
+
+        let result = RecordOutput {
+            pid: self.pid,
+            path,
+            summary,
+        };
+
+        output.print(&result, |result| {
+            print!(
+                "recorded {} messages of PID {} to {}",
+                result.summary.recorded,
+                result.pid,
+                result.path.display()
+            );
+
+            if result.summary.dropped > 0 {
+                print!(" ({} dropped)", result.summary.dropped);
+            }
+
+            if result.summary.truncated {
+                print!(" (limit reached)");
+            }
+
+            println!();
+        })
+    }
+}
+
+/// Converts a message tap service error to a [CommandError].
+fn tap_result(pid: u64, response: TapResponse) -> CommandResult<TapSuccess> {
+    let (message, exit_code) = match response {
+        Ok(success) => return Ok(success),
+        Err(TapError::NotFound) => (format!("no process with PID {}", pid), EX_USAGE),
+        Err(TapError::AlreadyRecording) => (
+            format!("PID {} is already being recorded", pid),
+            EX_SOFTWARE,
+        ),
+        Err(TapError::NotRecording) => (format!("PID {} is not being recorded", pid), EX_SOFTWARE),
+        Err(TapError::Io(err)) => (format!("creating recording: {}", err), EX_CANTCREAT),
+    };
+
+    Err(CommandError { message, exit_code })
+}
+
+#[cfg(feature = "replay")]
+#[derive(Debug, clap::Args)]
+pub struct ReplayArgs {
+    /// The recording to replay, as written by the record command.
+    pub recording: PathBuf,
+
+    /// The Wasm module to replay the recording against.
+    #[clap(long)]
+    pub module: PathBuf,
+
+    /// A JSON object field to ignore when comparing messages. May be given
+    /// more than once.
+    #[clap(long = "ignore-field", default_value = "timestamp")]
+    pub ignore_fields: Vec<String>,
+
+    /// The largest allowed difference between numbers in messages.
+    #[clap(long, default_value_t = 0.0)]
+    pub float_tolerance: f64,
+}
+
+/// The result of a replay command.
+#[cfg(feature = "replay")]
+#[derive(Debug, Serialize)]
+pub struct ReplayOutput {
+    /// How many recorded messages were delivered to the module.
+    pub delivered: usize,
+
+    /// How many recorded messages couldn't be delivered.
+    pub skipped: usize,
+
+    /// How many messages the module sent to stub capabilities.
+    pub sent: usize,
+
+    /// Every difference between the recorded and replayed messages.
+    pub mismatches: Vec<String>,
+}
+
+#[cfg(feature = "replay")]
+impl ReplayArgs {
+    pub async fn run(self, output: OutputFormat) -> CommandResult<()> {
+        use hearth_runtime::testing::{ReplayOptions, TestRuntimeBuilder};
+        use hearth_schema::tap::TapRecord;
+
+        let records = hearth_runtime::tap::read_recording(&self.recording)
+            .to_command_error("reading recording", EX_NOINPUT)?;
+
+        let module = std::fs::read(&self.module)
+            .to_command_error(format!("reading {}", self.module.display()), EX_NOINPUT)?;
+
+        let recorded = records.iter().find_map(|record| match record {
+            TapRecord::Spawn { lump, .. } => Some(*lump),
+            _ => None,
+        });
+
+        let lump = upload::lump_id(&module);
+        if matches!(recorded, Some(recorded) if recorded != lump) {
+            eprintln!("WARNING: the recording was made with a different module");
+        }
+
+        let options = ReplayOptions {
+            ignore_fields: self.ignore_fields,
+            float_tolerance: self.float_tolerance,
+        };
+
+        // the test runtime runs its own Tokio runtime, so keep it off of ours
+        let replay = std::thread::spawn(move || {
+            let mut builder = TestRuntimeBuilder::new();
+            builder.add_plugin(hearth_wasm::WasmPlugin::default());
+            let runtime = builder.build();
+            let report = runtime.replay(module, &records, &options);
+
+            ReplayOutput {
+                delivered: report.delivered,
+                skipped: report.skipped,
+                sent: report.sends.len(),
+                mismatches: report.mismatches.iter().map(|m| m.to_string()).collect(),
+            }
+        });
+
+        let result = replay
+            .join()
+            .ok()
+            .to_command_error("replay failed", EX_SOFTWARE)?;
+
+        let matched = result.mismatches.is_empty();
+
+        output.print(&result, |result| {
+            println!(
+                "delivered {} messages ({} skipped), module sent {}",
+                result.delivered, result.skipped, result.sent
+            );
+
+            for mismatch in result.mismatches.iter() {
+                println!("mismatch: {}", mismatch);
+            }
+        })?;
+
+        if !matched {
+            return Err(CommandError {
+                message: "replay did not match the recording".into(),
+                exit_code: EX_DATAERR,
+            });
+        }
+
         Ok(())
     }
 }
 
+/// Parses a [ModuleSource], treating anything that isn't a lump ID as a
+/// file path.
+fn parse_module_source(src: &str) -> Result<ModuleSource, String> {
+    match parse_lump_id(src) {
+        Ok(lump) => Ok(ModuleSource::Lump(lump)),
+        Err(_) => Ok(ModuleSource::File(PathBuf::from(src))),
+    }
+}
+
+/// Parses a [LumpId] from its hexadecimal representation.
+fn parse_lump_id(src: &str) -> Result<LumpId, String> {
+    if src.len() != 64 || !src.is_ascii() {
+        return Err("lump IDs are 64 hexadecimal digits".into());
+    }
+
+    let mut id = [0u8; 32];
+    for (byte, digits) in id.iter_mut().zip(src.as_bytes().chunks(2)) {
+        let digits = std::str::from_utf8(digits).unwrap();
+        *byte = u8::from_str_radix(digits, 16).map_err(|err| err.to_string())?;
+    }
+
+    Ok(LumpId(id))
+}
+
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> ExitCode {
     let args = Args::parse();
 
-    match args.command.run().await {
+    match args.command.run(args.daemon, args.output).await {
         Ok(_) => 0,
         Err(e) => {
             eprintln!("ERROR: {}", e.message);
@@ -96,10 +851,387 @@ async fn main() -> ExitCode {
     .into()
 }
 
-async fn get_daemon() -> CommandResult<Connection> {
-    hearth_ipc::connect()
-        .await
-        .to_command_error("connecting to Hearth daemon", EX_PROTOCOL)
+#[cfg(feature = "discovery")]
+#[derive(Debug, clap::Args)]
+pub struct DiscoverArgs {
+    /// How many seconds to browse for servers.
+    #[clap(long, default_value = "3")]
+    pub timeout: f64,
+}
+
+#[cfg(feature = "discovery")]
+impl DiscoverArgs {
+    pub async fn run(self, output: OutputFormat) -> CommandResult<()> {
+        let duration = std::time::Duration::try_from_secs_f64(self.timeout)
+            .to_command_error("invalid timeout", EX_USAGE)?;
+
+        let servers =
+            tokio::task::spawn_blocking(move || hearth_network::discovery::browse(duration))
+                .await
+                .to_command_error("browsing task failed", EX_SOFTWARE)?
+                .to_command_error("browsing for servers", EX_SOFTWARE)?;
+
+        output.print(&servers, |servers| {
+            println!(
+                "{:<32} {:<8} {:<8} ADDRESSES",
+                "NAME", "VERSION", "PASSWORD"
+            );
+            for server in servers {
+                let version = server
+                    .protocol_version
+                    .map(|version| version.to_string())
+                    .unwrap_or_else(|| "?".to_string());
+                let password = if server.password_required {
+                    "yes"
+                } else {
+                    "no"
+                };
+                let addrs: Vec<_> = server.addrs.iter().map(|addr| addr.to_string()).collect();
+                println!(
+                    "{:<32} {:<8} {:<8} {}",
+                    server.name,
+                    version,
+                    password,
+                    addrs.join(", ")
+                );
+            }
+        })
+    }
+}
+
+async fn list_identities(daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+    let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+    let service = daemon.get_service(IDENTITIES_SERVICE).await?;
+    let (identities, _caps): (Vec<ConnectedIdentity>, _) =
+        daemon.request(service, &IdentitiesRequest::List).await?;
+
+    output.print(&identities, |identities| {
+        println!("{:<24} USER", "ADDRESS");
+        for identity in identities {
+            println!("{:<24} {}", identity.address, identity.user);
+        }
+    })
+}
+
+#[derive(Debug, clap::Args)]
+pub struct InspectArgs {
+    /// The PID of the process to inspect.
+    pub pid: u64,
+
+    /// How many of the process's most recent log events to print.
+    #[clap(long, default_value_t = 20)]
+    pub log_tail: u32,
+}
+
+/// What `inspect` prints about a process.
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    pub pid: u64,
+
+    /// The process's details, or `None` if the daemon has no process
+    /// inspector.
+    pub details: Option<ProcessDetails>,
+}
+
+impl InspectArgs {
+    pub async fn run(self, daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+        let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+
+        // the inspector is opt-in, so report everything as unavailable
+        // instead of failing when it's missing
+        let service = match daemon.get_service(PROCESS_INSPECTOR_SERVICE).await {
+            Ok(service) => service,
+            Err(err) if err.exit_code == EX_UNAVAILABLE => {
+                let inspection = Inspection {
+                    pid: self.pid,
+                    details: None,
+                };
+
+                return output.print(&inspection, print_inspection);
+            }
+            Err(err) => return Err(err),
+        };
+
+        let request = ProcessInspectorRequest::GetProcessDetails {
+            pid: self.pid,
+            log_tail: self.log_tail,
+        };
+
+        let (response, _caps): (ProcessInspectorResponse, _) =
+            daemon.request(service, &request).await?;
+
+        let details = response.map_err(|err| match err {
+            ProcessInspectorError::NotFound => CommandError {
+                message: format!("no process with PID {}", self.pid),
+                exit_code: EX_USAGE,
+            },
+        })?;
+
+        let inspection = Inspection {
+            pid: self.pid,
+            details: Some(details),
+        };
+
+        output.print(&inspection, print_inspection)
+    }
+}
+
+/// The labels of every line that `inspect` prints after the PID.
+const INSPECTION_LABELS: &[&str] = &[
+    "NAME",
+    "VERSION",
+    "DESCRIPTION",
+    "AUTHORS",
+    "REPOSITORY",
+    "HOMEPAGE",
+    "LICENSE",
+    "SPAWNED BY",
+    "LUMP",
+    "GROUP",
+    "SERVICES",
+    "MONITORING",
+    "MAILBOX",
+    "RESOURCES",
+    "LOG",
+];
+
+/// Formats an optional field, marking missing values as unavailable.
+fn or_unavailable(value: Option<impl Display>) -> String {
+    value
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| "unavailable".to_string())
+}
+
+/// Prints a process's details as labeled sections, or marks every section
+/// as unavailable if there are no details.
+fn print_inspection(inspection: &Inspection) {
+    let Some(details) = inspection.details.as_ref() else {
+        println!("PID          {}", inspection.pid);
+        for label in INSPECTION_LABELS {
+            println!("{:<12} unavailable", label);
+        }
+
+        return;
+    };
+
+    let meta = &details.metadata;
+    println!("PID          {}", details.pid);
+    println!("NAME         {}", or_unavailable(meta.name.as_ref()));
+    println!("VERSION      {}", or_unavailable(meta.version.as_ref()));
+    println!("DESCRIPTION  {}", or_unavailable(meta.description.as_ref()));
+    println!(
+        "AUTHORS      {}",
+        or_unavailable(meta.authors.as_ref().map(|authors| authors.join(", ")))
+    );
+    println!("REPOSITORY   {}", or_unavailable(meta.repository.as_ref()));
+    println!("HOMEPAGE     {}", or_unavailable(meta.homepage.as_ref()));
+    println!("LICENSE      {}", or_unavailable(meta.license.as_ref()));
+
+    let spawned_by = match &details.spawned_by {
+        AuditActor::Host => "host".to_string(),
+        AuditActor::Process { pid, name: None } => format!("PID {}", pid),
+        AuditActor::Process {
+            pid,
+            name: Some(name),
+        } => format!("PID {} ({})", pid, name),
+        AuditActor::Peer { user, address } => format!("{} at {}", user, address),
+    };
+
+    println!("SPAWNED BY   {}", spawned_by);
+
+    match (details.lump.as_ref(), details.lump_info.as_ref()) {
+        (None, _) => println!("LUMP         none"),
+        (Some(lump), None) => println!("LUMP         {} (info unavailable)", lump),
+        (Some(lump), Some(info)) => {
+            let content_type = info.metadata.content_type.as_deref();
+            println!(
+                "LUMP         {} ({} bytes, {})",
+                lump,
+                info.size,
+                content_type.unwrap_or("unknown type")
+            );
+        }
+    }
+
+    match details.group {
+        Some(group) => println!("GROUP        {}", group),
+        None => println!("GROUP        none"),
+    }
+
+    if details.services.is_empty() {
+        println!("SERVICES     none");
+    } else {
+        println!("SERVICES     {}", details.services.join(", "));
+    }
+
+    let monitoring = details
+        .monitoring
+        .as_ref()
+        .map(|pids| match pids.as_slice() {
+            [] => "none".to_string(),
+            pids => {
+                let pids: Vec<_> = pids.iter().map(ToString::to_string).collect();
+                pids.join(", ")
+            }
+        });
+
+    let resources = details.resources.as_ref().map(|resources| {
+        let metrics: Vec<_> = resources
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, value))
+            .collect();
+
+        metrics.join(", ")
+    });
+
+    println!("MONITORING   {}", or_unavailable(monitoring));
+    println!("MAILBOX      {}", or_unavailable(details.mailbox_depth));
+    println!("RESOURCES    {}", or_unavailable(resources));
+    println!();
+
+    if details.log_tail.is_empty() {
+        println!("no recent log events");
+        return;
+    }
+
+    println!(
+        "{:<14} {:<8} {:<24} CONTENT",
+        "TIMESTAMP", "LEVEL", "MODULE"
+    );
+    for event in details.log_tail.iter() {
+        // padding isn't applied to Debug output, so format the level first
+        let level = format!("{:?}", event.level);
+        println!(
+            "{:<14} {:<8} {:<24} {}",
+            event.timestamp, level, event.module, event.content
+        );
+    }
+}
+
+#[derive(Debug, clap::Args)]
+pub struct NetArgs {
+    /// Refresh the counters every second until interrupted.
+    ///
+    /// Table output shows per-second rates since the previous refresh. JSON
+    /// output prints one snapshot per line.
+    #[clap(long)]
+    pub watch: bool,
+}
+
+impl NetArgs {
+    pub async fn run(self, daemon: DaemonArgs, output: OutputFormat) -> CommandResult<()> {
+        let mut daemon = DaemonClient::new(get_daemon(&daemon).await?).await?;
+        let service = daemon.get_service(NETWORK_STATS_SERVICE).await?;
+
+        if !self.watch {
+            let (peers, _caps): (Vec<PeerTraffic>, _) =
+                daemon.request(service, &NetworkStatsRequest::List).await?;
+
+            return output.print(&peers, |peers| print_traffic(peers, None));
+        }
+
+        let mut ticks = tokio::time::interval(Duration::from_secs(1));
+        let mut previous: Option<Vec<PeerTraffic>> = None;
+
+        loop {
+            tokio::select! {
+                _ = ticks.tick() => {}
+                _ = tokio::signal::ctrl_c() => return Ok(()),
+            }
+
+            let (peers, _caps): (Vec<PeerTraffic>, _) =
+                daemon.request(service, &NetworkStatsRequest::List).await?;
+
+            match output {
+                OutputFormat::Table => {
+                    // clear the screen and move the cursor to the top left
+                    print!("\x1b[2J\x1b[H");
+                    print_traffic(&peers, previous.as_deref());
+                }
+                OutputFormat::Json => {
+                    let json = serde_json::to_string(&peers)
+                        .to_command_error("serializing output", EX_SOFTWARE)?;
+                    println!("{}", json);
+                }
+            }
+
+            previous = Some(peers);
+        }
+    }
+}
+
+/// Prints a table of connection traffic.
+///
+/// If a previous snapshot is given, operation and byte counts are printed as
+/// per-second rates since then instead of totals.
+fn print_traffic(peers: &[PeerTraffic], previous: Option<&[PeerTraffic]>) {
+    let unit = if previous.is_some() { "/S" } else { "" };
+    println!(
+        "{:<24} {:>12} {:>12} {:>14} {:>14} {:>9} {:>9} {:>10}",
+        "PEER",
+        format!("OPS IN{}", unit),
+        format!("OPS OUT{}", unit),
+        format!("BYTES IN{}", unit),
+        format!("BYTES OUT{}", unit),
+        "QUEUE IN",
+        "QUEUE OUT",
+        "IDLE"
+    );
+
+    for peer in peers {
+        let traffic = &peer.traffic;
+        let last = previous
+            .and_then(|previous| previous.iter().find(|last| last.peer == peer.peer))
+            .map(|last| &last.traffic);
+
+        // per-second rates since the last snapshot, or totals without one
+        let rate = |now: u64, then: fn(&ConnectionTraffic) -> u64| match last {
+            Some(last) if traffic.age_ms > last.age_ms => {
+                let secs = (traffic.age_ms - last.age_ms) as f64 / 1000.0;
+                format!("{:.1}", now.saturating_sub(then(last)) as f64 / secs)
+            }
+            _ => now.to_string(),
+        };
+
+        let idle = match traffic.idle_ms {
+            Some(idle) => format!("{:.1}s", idle as f64 / 1000.0),
+            None => "-".to_string(),
+        };
+
+        println!(
+            "{:<24} {:>12} {:>12} {:>14} {:>14} {:>9} {:>9} {:>10}",
+            peer.peer,
+            rate(traffic.ops_in, |last| last.ops_in),
+            rate(traffic.ops_out, |last| last.ops_out),
+            rate(traffic.bytes_in, |last| last.bytes_in),
+            rate(traffic.bytes_out, |last| last.bytes_out),
+            traffic.queue_in,
+            traffic.queue_out,
+            idle,
+        );
+
+        for (direction, caps) in [("in", &traffic.busiest_in), ("out", &traffic.busiest_out)] {
+            if caps.is_empty() {
+                continue;
+            }
+
+            let caps: Vec<_> = caps
+                .iter()
+                .map(|cap| format!("#{} ({} ops, {} bytes)", cap.id, cap.ops, cap.bytes))
+                .collect();
+
+            println!("  busiest {}: {}", direction, caps.join(", "));
+        }
+    }
+}
+
+async fn get_daemon(args: &DaemonArgs) -> CommandResult<Connection> {
+    let result = match args.socket.as_ref() {
+        Some(path) => hearth_ipc::connect_to(path).await,
+        None => hearth_ipc::connect(args.instance.as_deref()).await,
+    };
+
+    result.to_command_error("connecting to Hearth daemon", EX_PROTOCOL)
 }
 
 fn hash_map_to_ordered_vec<K: Copy + Ord, V>(map: HashMap<K, V>) -> Vec<(K, V)> {
@@ -0,0 +1,50 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+
+use crate::{CommandResult, ToCommandError, EX_SOFTWARE};
+
+/// The format that commands print their results in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable, fixed-width text.
+    Table,
+
+    /// A single JSON document, for scripting.
+    Json,
+}
+
+impl OutputFormat {
+    /// Prints a command's result.
+    ///
+    /// JSON output is the serialized `value` and nothing else. Table output
+    /// is whatever `table` prints.
+    pub fn print<T: Serialize>(self, value: &T, table: impl FnOnce(&T)) -> CommandResult<()> {
+        match self {
+            OutputFormat::Table => table(value),
+            OutputFormat::Json => {
+                let json = serde_json::to_string_pretty(value)
+                    .to_command_error("serializing output", EX_SOFTWARE)?;
+                println!("{}", json);
+            }
+        }
+
+        Ok(())
+    }
+}
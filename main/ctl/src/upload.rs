@@ -0,0 +1,187 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::io::{IsTerminal, Write};
+
+use hearth_schema::lump::{LumpMetadata, LumpOrigin};
+use hearth_schema::wasm::{
+    LumpUploadError, LumpUploadRequest, LumpUploadResponse, LumpUploadSuccess, LUMP_CHUNK_SIZE,
+    LUMP_UPLOADER_SERVICE, MAX_LUMP_SIZE,
+};
+use hearth_schema::LumpId;
+
+use crate::daemon::DaemonClient;
+use crate::{CommandError, CommandResult, ToCommandError, EX_DATAERR, EX_IOERR, EX_PROTOCOL};
+
+/// Computes the ID that a lump store gives some data.
+pub fn lump_id(data: &[u8]) -> LumpId {
+    LumpId(*blake3::hash(data).as_bytes())
+}
+
+/// Uploads data to the daemon's lump store in chunks, tagged with a MIME
+/// content type.
+///
+/// Draws a progress bar on stderr if it's a terminal. The daemon verifies
+/// the data against the ID that it's expected to have and rejects it if it
+/// was corrupted on the way.
+pub async fn upload_lump(
+    daemon: &mut DaemonClient,
+    data: &[u8],
+    content_type: &str,
+) -> CommandResult<LumpId> {
+    if data.len() as u64 > MAX_LUMP_SIZE {
+        return Err(CommandError {
+            message: format!(
+                "{} bytes is too large to upload; lumps may be at most {} bytes",
+                data.len(),
+                MAX_LUMP_SIZE
+            ),
+            exit_code: EX_DATAERR,
+        });
+    }
+
+    let expected = lump_id(data);
+    let uploader = daemon.get_service(LUMP_UPLOADER_SERVICE).await?;
+
+    let begin = LumpUploadRequest::Begin {
+        size: data.len() as u64,
+        expected: Some(expected),
+        metadata: LumpMetadata {
+            content_type: Some(content_type.to_string()),
+            origin: Some(LumpOrigin::Upload),
+        },
+    };
+
+    let upload = match request(daemon, uploader, &begin).await? {
+        LumpUploadSuccess::Started { upload } => upload,
+        other => return Err(unexpected(other)),
+    };
+
+    let mut progress = Progress::new(data.len() as u64);
+
+    let chunk_size = LUMP_CHUNK_SIZE as usize;
+    for (index, chunk) in data.chunks(chunk_size).enumerate() {
+        let chunk = LumpUploadRequest::Chunk {
+            upload,
+            offset: (index * chunk_size) as u64,
+            data: chunk.to_vec(),
+        };
+
+        match request(daemon, uploader, &chunk).await? {
+            LumpUploadSuccess::Received { received } => progress.update(received),
+            other => return Err(unexpected(other)),
+        }
+    }
+
+    let lump = match request(daemon, uploader, &LumpUploadRequest::Finish { upload }).await? {
+        LumpUploadSuccess::Finished(lump) => lump,
+        other => return Err(unexpected(other)),
+    };
+
+    drop(progress);
+
+    if lump != expected {
+        return Err(CommandError {
+            message: format!("daemon stored lump {} but {} was uploaded", lump, expected),
+            exit_code: EX_DATAERR,
+        });
+    }
+
+    Ok(lump)
+}
+
+/// Sends one request to the lump uploader.
+async fn request(
+    daemon: &mut DaemonClient,
+    uploader: u32,
+    request: &LumpUploadRequest,
+) -> CommandResult<LumpUploadSuccess> {
+    let (response, _caps): (LumpUploadResponse, _) = daemon.request(uploader, request).await?;
+
+    match response {
+        Err(err @ LumpUploadError::Mismatch(_)) => Err(CommandError {
+            message: format!("uploaded lump was corrupted: {}", err),
+            exit_code: EX_DATAERR,
+        }),
+        response => response.to_command_error("uploading lump", EX_IOERR),
+    }
+}
+
+fn unexpected(response: LumpUploadSuccess) -> CommandError {
+    CommandError {
+        message: format!("unexpected upload response: {:?}", response),
+        exit_code: EX_PROTOCOL,
+    }
+}
+
+/// An upload progress bar on stderr. Does nothing if stderr isn't a
+/// terminal.
+struct Progress {
+    total: u64,
+    enabled: bool,
+    drawn: bool,
+}
+
+impl Progress {
+    /// The width of the bar in characters.
+    const WIDTH: usize = 40;
+
+    fn new(total: u64) -> Self {
+        Self {
+            total,
+            enabled: std::io::stderr().is_terminal(),
+            drawn: false,
+        }
+    }
+
+    /// Redraws the bar with the number of bytes uploaded so far.
+    fn update(&mut self, done: u64) {
+        if !self.enabled {
+            return;
+        }
+
+        let fraction = match self.total {
+            0 => 1.0,
+            total => done as f64 / total as f64,
+        };
+
+        let filled = ((fraction * Self::WIDTH as f64) as usize).min(Self::WIDTH);
+        let mut stderr = std::io::stderr().lock();
+        let _ = write!(
+            stderr,
+            "\r[{}{}] {:>3}% {}/{} KiB",
+            "#".repeat(filled),
+            " ".repeat(Self::WIDTH - filled),
+            (fraction * 100.0) as u32,
+            done / 1024,
+            self.total / 1024
+        );
+
+        let _ = stderr.flush();
+        self.drawn = true;
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        // end the bar's line so that later output starts on a new one
+        if self.drawn {
+            eprintln!();
+        }
+    }
+}
@@ -0,0 +1,79 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Advertisement of the server on the local network.
+
+use hearth_network::discovery::Advertisement;
+use hearth_runtime::runtime::RuntimeBuilder;
+use serde::Deserialize;
+use tracing::{debug, error, info};
+
+/// Configuration for LAN discovery, loaded from the `discovery` table of the
+/// config file.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct DiscoveryConfig {
+    /// Whether to advertise the server over mDNS.
+    pub enabled: bool,
+
+    /// The name that clients see when browsing for servers.
+    pub name: String,
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            name: "Hearth server".to_string(),
+        }
+    }
+}
+
+/// Loads the discovery config, falling back to the default if it's missing.
+pub fn load_config(builder: &RuntimeBuilder) -> DiscoveryConfig {
+    builder.load_config("discovery").unwrap_or_else(|err| {
+        debug!("Using default discovery config: {}", err);
+        DiscoveryConfig::default()
+    })
+}
+
+/// Advertises the server if discovery is enabled. The server is advertised
+/// for as long as the returned advertisement is kept alive.
+pub fn advertise(
+    config: &DiscoveryConfig,
+    port: u16,
+    password_required: bool,
+) -> Option<Advertisement> {
+    if !config.enabled {
+        return None;
+    }
+
+    match Advertisement::new(&config.name, port, password_required) {
+        Ok(advertisement) => {
+            info!(
+                "Advertising server as {:?} on the local network",
+                config.name
+            );
+            Some(advertisement)
+        }
+        Err(err) => {
+            error!("Failed to advertise server: {}", err);
+            None
+        }
+    }
+}
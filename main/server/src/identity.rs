@@ -0,0 +1,169 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+
+use hearth_runtime::{
+    anyhow::{bail, Context, Result},
+    async_trait, cargo_process_metadata,
+    flue::{OwnedCapability, Permissions, TableSignal},
+    process::ProcessMetadata,
+    runtime::Runtime,
+    utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner},
+};
+use hearth_schema::audit::AuditActor;
+use hearth_schema::network::{
+    ConnectedIdentity, IdentitiesRequest, RootCapRequest, IDENTITIES_SERVICE,
+};
+use parking_lot::Mutex;
+use tokio::time::timeout;
+
+/// A shared map of connected client addresses to their user names.
+pub type ConnectedIdentities = Arc<Mutex<HashMap<SocketAddr, String>>>;
+
+/// A service that lists the identities connected to this server.
+pub struct IdentityService {
+    pub connected: ConnectedIdentities,
+}
+
+#[async_trait]
+impl RequestResponseProcess for IdentityService {
+    type Request = IdentitiesRequest;
+    type Response = Vec<ConnectedIdentity>;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, Self::Request>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let IdentitiesRequest::List = request.data;
+
+        let mut identities: Vec<_> = self
+            .connected
+            .lock()
+            .iter()
+            .map(|(addr, user)| ConnectedIdentity {
+                user: user.to_owned(),
+                address: addr.to_string(),
+            })
+            .collect();
+
+        identities.sort_by(|a, b| a.address.cmp(&b.address));
+        identities.into()
+    }
+}
+
+impl ServiceRunner for IdentityService {
+    const NAME: &'static str = IDENTITIES_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Lists the users connected to this server. Accepts IdentitiesRequest.".into());
+        meta
+    }
+}
+
+/// How long a root cap provider may take to answer before the user is refused.
+const ROOT_CAP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Asks a root cap provider for the root cap to export to a user.
+///
+/// The request's process is audited as spawned by the user at `addr`.
+///
+/// Fails if the provider refused the user, went down, or didn't answer in
+/// time.
+pub async fn request_user_root(
+    runtime: &Runtime,
+    provider: &OwnedCapability,
+    user: &str,
+    addr: SocketAddr,
+) -> Result<OwnedCapability> {
+    let request = request_root(runtime, provider, user, addr);
+    match timeout(ROOT_CAP_TIMEOUT, request).await {
+        Ok(result) => result,
+        Err(_) => bail!("root cap provider didn't answer in {:?}", ROOT_CAP_TIMEOUT),
+    }
+}
+
+/// Sends a [RootCapRequest] and waits for its reply with no time limit.
+async fn request_root(
+    runtime: &Runtime,
+    provider: &OwnedCapability,
+    user: &str,
+    addr: SocketAddr,
+) -> Result<OwnedCapability> {
+    let mut meta = cargo_process_metadata!();
+    meta.name = Some("root cap provider request".to_string());
+
+    let requester = AuditActor::Peer {
+        user: user.to_string(),
+        address: addr.to_string(),
+    };
+
+    let process = runtime.process_factory.spawn_for(meta, requester, None);
+    let table = process.borrow_table();
+
+    let reply = process
+        .borrow_group()
+        .create_mailbox()
+        .context("failed to create reply mailbox")?;
+
+    let reply_cap = reply
+        .export(Permissions::SEND)
+        .context("failed to export reply capability")?;
+
+    let provider = table
+        .import_owned(provider.clone())
+        .context("failed to import root cap provider")?;
+
+    let provider = table
+        .wrap_handle(provider)
+        .context("failed to wrap root cap provider")?;
+
+    if provider.get_permissions().contains(Permissions::MONITOR) {
+        provider
+            .monitor(&reply)
+            .context("failed to monitor root cap provider")?;
+    }
+
+    let request = RootCapRequest {
+        user: user.to_string(),
+    };
+
+    let data = serde_json::to_vec(&request)?;
+    provider
+        .send(&data, &[&reply_cap])
+        .await
+        .context("failed to send root cap request")?;
+
+    let on_recv = |signal: TableSignal<'_>| match signal {
+        TableSignal::Message { caps, .. } => Ok(caps.first().copied()),
+        TableSignal::Down { .. } => Err(()),
+    };
+
+    let root = match reply.recv(on_recv).await {
+        None => bail!("reply mailbox was closed"),
+        Some(Err(())) => bail!("root cap provider went down"),
+        Some(Ok(None)) => bail!("root cap provider refused the user"),
+        Some(Ok(Some(root))) => root,
+    };
+
+    table
+        .get_owned(root)
+        .context("failed to take ownership of the root cap")
+}
@@ -17,18 +17,37 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use clap::Parser;
-use hearth_network::auth::ServerAuthenticator;
+use glam::UVec2;
+use hearth_network::auth::{ServerAuthenticator, UserFile};
+use hearth_network::connection::{Connection as NetworkConnection, ConnectionConfig};
+use hearth_network::stats::{NetworkStatsService, TrackedConnections};
+use hearth_network::tls::{self, ServerTls, ServerTlsConfig};
+use hearth_network::websocket;
+use hearth_rend3::{wgpu::TextureFormat, Rend3Plugin};
+use hearth_runtime::cargo_process_metadata;
 use hearth_runtime::connection::Connection;
-use hearth_runtime::flue::{OwnedCapability, PostOffice};
+use hearth_runtime::flue::{OwnedCapability, Permissions};
+use hearth_runtime::registry::{RegistryView, ViewPolicy, ViewStore};
 use hearth_runtime::runtime::Runtime;
 use hearth_runtime::runtime::{RuntimeBuilder, RuntimeConfig};
+use hearth_runtime::utils::ProcessRunner;
+use hearth_runtime::LoggingConfig;
+use hearth_schema::{audit::AuditActor, registry::peer_namespace, PeerRole};
+use serde::Deserialize;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::oneshot;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
+
+use identity::{ConnectedIdentities, IdentityService};
+
+#[cfg(feature = "discovery")]
+mod discovery;
+mod identity;
 
 /// The Hearth virtual space server program.
 #[derive(Parser, Debug)]
@@ -37,10 +56,29 @@ pub struct Args {
     #[clap(short, long)]
     pub bind: Option<SocketAddr>,
 
+    /// IP address and port to listen for WebSocket connections on.
+    ///
+    /// WebSocket clients speak the same protocol as TCP clients, inside of
+    /// binary WebSocket messages. If TLS is configured, WebSocket clients
+    /// must connect with `wss://`.
+    #[clap(long)]
+    pub bind_ws: Option<SocketAddr>,
+
     /// Password to use to authenticate with clients. Defaults to empty.
+    ///
+    /// Ignored for logins if --users is given.
     #[clap(short, long, default_value = "")]
     pub password: String,
 
+    /// A user file to authenticate clients with per-user passwords.
+    #[clap(short, long)]
+    pub users: Option<PathBuf>,
+
+    /// Adds a user with --password to the --users file, creating it if
+    /// needed, then exits.
+    #[clap(long, requires = "users")]
+    pub add_user: Option<String>,
+
     /// A configuration file to use if not the default one.
     #[clap(short, long)]
     pub config: Option<PathBuf>,
@@ -49,60 +87,242 @@ pub struct Args {
     #[clap(short, long)]
     pub init: PathBuf,
 
+    /// Give each user the root cap returned by the init system's
+    /// `hearth.init.ServerRootProvider` hook instead of the default network
+    /// root. Clients aren't accepted until the hook has been called.
+    #[clap(long)]
+    pub root_provider: bool,
+
     /// A path to the guest-side filesystem root.
     #[clap(short, long)]
     pub root: PathBuf,
+
+    /// Reload assets loaded from files in the filesystem root when they
+    /// change on disk.
+    #[clap(long)]
+    pub hot_reload: bool,
+
+    /// The name of this IPC daemon instance, used to find a socket path that
+    /// doesn't collide with other daemons on this machine.
+    #[clap(long, default_value = "server")]
+    pub instance: String,
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
-    hearth_runtime::init_logging();
 
-    let authenticator = ServerAuthenticator::from_password(args.password.as_bytes()).unwrap();
-    let authenticator = Arc::new(authenticator);
+    let config_path = args
+        .config
+        .clone()
+        .unwrap_or_else(hearth_runtime::get_config_path);
+    let config_file = hearth_runtime::load_config(&config_path);
+    let logging = config_file
+        .as_ref()
+        .map(LoggingConfig::from_config_file)
+        .unwrap_or_default();
+    hearth_runtime::init_logging(&logging);
+
+    if let (Some(user), Some(users)) = (args.add_user.as_ref(), args.users.as_ref()) {
+        if let Err(err) = add_user(users, user, &args.password) {
+            error!("Failed to add user: {}", err);
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    let authenticator = match args.users.as_ref() {
+        Some(path) => load_users(path).unwrap(),
+        None => ServerAuthenticator::from_password(args.password.as_bytes()).unwrap(),
+    };
 
     debug!("Initializing runtime");
-    let config = RuntimeConfig {};
+    let config = RuntimeConfig {
+        role: Some(PeerRole::Server),
+    };
+
+    let config_file = config_file.unwrap();
 
-    let config_path = args.config.unwrap_or_else(hearth_runtime::get_config_path);
-    let config_file = hearth_runtime::load_config(&config_path).unwrap();
+    let tls = match load_tls(&config_file) {
+        Ok(tls) => tls,
+        Err(err) => {
+            error!("Failed to load TLS: {}", err);
+            std::process::exit(1);
+        }
+    };
 
     let (network_root_tx, network_root_rx) = oneshot::channel();
     let mut init = hearth_init::InitPlugin::new(args.init);
     init.add_hook("hearth.init.Server".into(), network_root_tx);
 
+    let root_provider_rx = args.root_provider.then(|| {
+        let (root_provider_tx, root_provider_rx) = oneshot::channel();
+        init.add_hook("hearth.init.ServerRootProvider".into(), root_provider_tx);
+        root_provider_rx
+    });
+
+    let identities = ConnectedIdentities::default();
+    let connections = TrackedConnections::default();
+
     let mut builder = RuntimeBuilder::new(config_file);
     builder.add_plugin(hearth_time::TimePlugin);
+    builder.add_plugin(hearth_pubsub::PubSubPlugin::default());
+    builder.add_plugin(hearth_kv::KvPlugin);
     builder.add_plugin(hearth_wasm::WasmPlugin::default());
-    builder.add_plugin(hearth_fs::FsPlugin::new(args.root));
+
+    let mut fs = hearth_fs::FsPlugin::new(args.root);
+    if args.hot_reload {
+        fs = fs.with_hot_reload();
+    }
+
+    builder.add_plugin(fs);
     builder.add_plugin(init);
-    builder.add_plugin(hearth_daemon::DaemonPlugin::default());
+    builder.add_plugin(hearth_daemon::DaemonPlugin {
+        instance: Some(args.instance),
+    });
+    builder.add_plugin(IdentityService {
+        connected: identities.clone(),
+    });
+    builder.add_plugin(NetworkStatsService {
+        connections: connections.clone(),
+    });
+    add_headless_renderer(&mut builder).await;
+    let network_config = load_network_config(&builder);
+    let peer_registry = load_peer_registry_config(&builder);
+    #[cfg(feature = "discovery")]
+    let discovery_config = discovery::load_config(&builder);
     let runtime = builder.run(config).await;
 
-    if let Some(addr) = args.bind {
+    let listeners: Vec<_> = [
+        args.bind.map(|addr| (addr, ListenerKind::Tcp)),
+        args.bind_ws.map(|addr| (addr, ListenerKind::WebSocket)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if !listeners.is_empty() {
+        let ctx = NetworkContext {
+            runtime,
+            authenticator,
+            config: network_config,
+            tls,
+            root_provider: None,
+            identities,
+            connections,
+            next_connection_id: AtomicU64::new(0),
+            peer_registry,
+            peer_services: ViewStore::default(),
+        };
+
         tokio::spawn(async move {
-            bind(network_root_rx, addr, runtime.clone(), authenticator).await;
+            bind(network_root_rx, root_provider_rx, listeners, ctx).await;
         });
     } else {
         info!("Server running in headless mode");
     }
 
+    #[cfg(feature = "discovery")]
+    let _advertisement = args.bind.and_then(|addr| {
+        let password_required = args.users.is_some() || !args.password.is_empty();
+        discovery::advertise(&discovery_config, addr.port(), password_required)
+    });
+
     hearth_runtime::wait_for_interrupt().await;
 
     info!("Interrupt received; exiting server");
 }
 
+/// Shared state for accepting client connections.
+struct NetworkContext {
+    runtime: Arc<Runtime>,
+    authenticator: ServerAuthenticator,
+    config: ConnectionConfig,
+    tls: Option<ServerTls>,
+
+    /// A provider for per-user root caps. If unset, all users receive the
+    /// default network root.
+    root_provider: Option<OwnedCapability>,
+
+    identities: ConnectedIdentities,
+
+    /// The connections whose traffic counters are reported.
+    connections: TrackedConnections,
+
+    /// The ID to give the next accepted connection, for its tracing span.
+    next_connection_id: AtomicU64,
+
+    /// How users' views of the network root are scoped.
+    peer_registry: PeerRegistryConfig,
+
+    /// The services that users have registered through their views.
+    peer_services: ViewStore,
+}
+
+/// Configuration for scoping each user's view of the network root.
+///
+/// Loaded from the `peer_registry` table of the config file.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+struct PeerRegistryConfig {
+    /// If true, users are given a [RegistryView] of the network root, so
+    /// that they register services into their own `peer.<user>.` namespace.
+    /// See [peer_namespace].
+    /// Ignored if a root provider hook gives out per-user root caps.
+    enabled: bool,
+
+    /// Patterns of names that users may look up. See [ViewPolicy::allow].
+    allow: Option<Vec<String>>,
+
+    /// Patterns of names that users may never look up.
+    deny: Vec<String>,
+}
+
+/// The kinds of transports that clients can connect over.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ListenerKind {
+    /// Raw TCP connections.
+    Tcp,
+
+    /// WebSocket connections, for clients that can't open TCP sockets.
+    WebSocket,
+}
+
 async fn bind(
     on_network_root: oneshot::Receiver<OwnedCapability>,
-    addr: SocketAddr,
-    runtime: Arc<Runtime>,
-    authenticator: Arc<ServerAuthenticator>,
+    on_root_provider: Option<oneshot::Receiver<OwnedCapability>>,
+    listeners: Vec<(SocketAddr, ListenerKind)>,
+    mut ctx: NetworkContext,
 ) {
     info!("Waiting for network root cap hook");
     let network_root = on_network_root.await.unwrap();
 
-    info!("Binding to {:?}", addr);
+    // every login has to see the provider, so wait for it before listening
+    if let Some(on_root_provider) = on_root_provider {
+        info!("Waiting for root provider hook");
+        ctx.root_provider = Some(on_root_provider.await.unwrap());
+        info!("Using per-user root caps from the root provider hook");
+    }
+
+    let ctx = Arc::new(ctx);
+
+    for (addr, kind) in listeners {
+        let ctx = ctx.clone();
+        let network_root = network_root.clone();
+        tokio::spawn(async move {
+            listen(addr, kind, ctx, network_root).await;
+        });
+    }
+}
+
+async fn listen(
+    addr: SocketAddr,
+    kind: ListenerKind,
+    ctx: Arc<NetworkContext>,
+    network_root: OwnedCapability,
+) {
+    info!("Binding {:?} listener to {:?}", kind, addr);
     let listener = match TcpListener::bind(addr).await {
         Ok(l) => l,
         Err(err) => {
@@ -122,45 +342,107 @@ async fn bind(
         };
 
         info!("Connection from {:?}", addr);
-        let post = runtime.post.clone();
-        let authenticator = authenticator.clone();
+        let id = ctx.next_connection_id.fetch_add(1, Ordering::Relaxed);
+        let span = info_span!("connection", id, %addr, user = tracing::field::Empty);
+        let ctx = ctx.clone();
         let network_root = network_root.clone();
-        tokio::task::spawn(async move {
-            on_accept(post, authenticator, socket, addr, network_root).await;
-        });
+        tokio::task::spawn(
+            async move {
+                on_accept(&ctx, socket, kind, addr, network_root).await;
+            }
+            .instrument(span),
+        );
     }
 }
 
 async fn on_accept(
-    post: Arc<PostOffice>,
-    authenticator: Arc<ServerAuthenticator>,
-    mut client: TcpStream,
+    ctx: &NetworkContext,
+    client: TcpStream,
+    kind: ListenerKind,
     addr: SocketAddr,
     network_root: OwnedCapability,
 ) {
+    info!("Negotiating transport with client {:?}", addr);
+    let negotiated = match kind {
+        ListenerKind::Tcp => tls::accept(client, ctx.tls.as_ref()).await,
+        // TLS for WebSockets is handled underneath the WebSocket layer
+        ListenerKind::WebSocket => match websocket::accept(client, ctx.tls.as_ref()).await {
+            Ok(client) => tls::accept(client, None).await,
+            Err(err) => Err(err),
+        },
+    };
+
+    let mut client = match negotiated {
+        Ok(client) => client,
+        Err(err) => {
+            error!("Transport negotiation error: {:?}", err);
+            return;
+        }
+    };
+
     info!("Authenticating with client {:?}", addr);
-    let session_key = match authenticator.login(&mut client).await {
-        Ok(key) => key,
+    let authenticated = match ctx.authenticator.login(&mut client).await {
+        Ok(authenticated) => authenticated,
         Err(err) => {
             error!("Authentication error: {:?}", err);
             return;
         }
     };
 
-    info!("Successfully authenticated");
+    let user = authenticated.user;
+    info!("Successfully authenticated as {:?}", user);
+    Span::current().record("user", user.as_str());
+
+    let mut peer_view = None;
+    let network_root = match ctx.root_provider.as_ref() {
+        None if ctx.peer_registry.enabled => {
+            let (view, task) = spawn_peer_view(ctx, network_root, &user, addr);
+            peer_view = Some(task);
+            view
+        }
+        None => network_root,
+        Some(provider) => {
+            match identity::request_user_root(&ctx.runtime, provider, &user, addr).await {
+                Ok(root) => root,
+                Err(err) => {
+                    warn!("No root cap for user {:?}: {:#}", user, err);
+                    return;
+                }
+            }
+        }
+    };
+
     use hearth_network::encryption::{AsyncDecryptor, AsyncEncryptor, Key};
+    let session_key = authenticated.session_key;
     let client_key = Key::from_client_session(&session_key);
     let server_key = Key::from_server_session(&session_key);
 
     let (client_rx, client_tx) = tokio::io::split(client);
     let client_rx = AsyncDecryptor::new(&client_key, client_rx);
     let client_tx = AsyncEncryptor::new(&server_key, client_tx);
-    let conn = hearth_network::connection::Connection::new(client_rx, client_tx);
+    let conn = match NetworkConnection::with_config(client_rx, client_tx, ctx.config.clone()) {
+        Ok(conn) => conn,
+        Err(err) => {
+            error!("Failed to start connection: {}", err);
+            return;
+        }
+    };
+
+    ctx.connections.track(addr.to_string(), &conn.stats);
+    let closed = conn.closed;
 
     let (root_cap_tx, client_root) = tokio::sync::oneshot::channel();
 
     info!("Beginning connection");
+    let post = ctx.runtime.post.clone();
     let conn = Connection::begin(post, conn.op_rx, conn.op_tx, Some(root_cap_tx));
+    let audit = ctx.runtime.process_factory.audit_log().clone();
+    let requester = AuditActor::Peer {
+        user: user.clone(),
+        address: addr.to_string(),
+    };
+
+    conn.audit_kills(audit, requester);
 
     info!("Sending the client our root cap");
     conn.export_root(network_root);
@@ -175,4 +457,228 @@ async fn on_accept(
     };
 
     info!("Client sent a root cap!");
+    ctx.identities.lock().insert(addr, user);
+
+    let _ = closed.await;
+    ctx.identities.lock().remove(&addr);
+
+    if let Some(view) = peer_view {
+        view.abort();
+    }
+
+    info!("Client {:?} disconnected", addr);
+}
+
+/// Loads a user file into an authenticator.
+fn load_users(path: &Path) -> Result<ServerAuthenticator, String> {
+    let src = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read user file {:?}: {}", path, err))?;
+
+    let file: UserFile = toml::from_str(&src)
+        .map_err(|err| format!("failed to parse user file {:?}: {}", path, err))?;
+
+    ServerAuthenticator::from_user_file(&file)
+        .map_err(|err| format!("invalid user file {:?}: {:?}", path, err))
+}
+
+/// Adds a user to a user file, creating the file if it doesn't exist.
+///
+/// The file holds the server's OPAQUE setup, so only its owner may read it.
+fn add_user(path: &Path, user: &str, password: &str) -> Result<(), String> {
+    let mut authenticator = if path.exists() {
+        load_users(path)?
+    } else {
+        ServerAuthenticator::new()
+    };
+
+    authenticator
+        .add_user(user, password.as_bytes())
+        .map_err(|err| format!("failed to add user {:?}: {:?}", user, err))?;
+
+    let file = toml::to_string(&authenticator.to_user_file())
+        .map_err(|err| format!("failed to serialize user file: {}", err))?;
+
+    write_private(path, file.as_bytes())
+        .map_err(|err| format!("failed to write user file {:?}: {}", path, err))?;
+
+    info!("Added user {:?} to {:?}", user, path);
+    Ok(())
+}
+
+/// Writes a file that only its owner may read or write.
+fn write_private(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    let mut file = {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        options.mode(0o600);
+        let file = options.open(path)?;
+
+        // an existing file keeps its old mode when it's opened
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+        file
+    };
+
+    #[cfg(not(unix))]
+    let mut file = options.open(path)?;
+
+    file.write_all(data)
+}
+
+/// Loads TLS from the config file, if configured.
+///
+/// Fails if the `tls` table is present but invalid or if its certificate or
+/// key fail to load, so that a broken TLS setup never falls back to
+/// plaintext.
+fn load_tls(config_file: &toml::Table) -> Result<Option<ServerTls>, String> {
+    let Some(table) = config_file.get("tls") else {
+        info!("TLS is disabled: no 'tls' table in config file");
+        return Ok(None);
+    };
+
+    let config = ServerTlsConfig::deserialize(table.to_owned())
+        .map_err(|err| format!("invalid TLS config: {}", err))?;
+
+    ServerTls::from_config(&config)
+        .map(Some)
+        .map_err(|err| format!("failed to load TLS certificate or key: {}", err))
+}
+
+/// Configuration for the server's optional headless renderer.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+struct HeadlessRendererConfig {
+    /// Whether to run a renderer at all.
+    enabled: bool,
+
+    /// The width of the offscreen frame in pixels.
+    width: u32,
+
+    /// The height of the offscreen frame in pixels.
+    height: u32,
+}
+
+impl Default for HeadlessRendererConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            width: 1280,
+            height: 720,
+        }
+    }
+}
+
+/// Adds a headless renderer and the services that depend on it, if enabled
+/// in the config.
+///
+/// Logs an error and skips the renderer if it fails to initialize, such as
+/// on machines without a usable graphics adapter.
+async fn add_headless_renderer(builder: &mut RuntimeBuilder) {
+    let config: HeadlessRendererConfig =
+        builder
+            .load_config("headless_renderer")
+            .unwrap_or_else(|err| {
+                debug!("Using default headless renderer config: {}", err);
+                HeadlessRendererConfig::default()
+            });
+
+    if !config.enabled {
+        return;
+    }
+
+    if config.width == 0 || config.height == 0 {
+        error!("Headless renderer resolution must be non-zero; skipping it");
+        return;
+    }
+
+    let resolution = UVec2::new(config.width, config.height);
+    let format = TextureFormat::Rgba8UnormSrgb;
+    let rend3 = match Rend3Plugin::new_headless(resolution, format).await {
+        Ok(rend3) => rend3,
+        Err(err) => {
+            error!(
+                "Failed to initialize headless renderer; skipping it: {}",
+                err
+            );
+            return;
+        }
+    };
+
+    info!(
+        "Running headless renderer at {}x{}",
+        resolution.x, resolution.y
+    );
+    builder.add_plugin(rend3);
+    builder.add_plugin(hearth_renderer::RendererPlugin::default());
+    builder.add_plugin(hearth_debug_draw::DebugDrawPlugin::default());
+    builder.add_plugin(hearth_canvas::CanvasPlugin);
+    builder.add_plugin(hearth_terminal::TerminalPlugin::default());
+}
+
+/// Spawns a registry view of the network root for a user.
+///
+/// Returns a capability to the view and its task, which should be aborted
+/// once the user disconnects.
+fn spawn_peer_view(
+    ctx: &NetworkContext,
+    network_root: OwnedCapability,
+    user: &str,
+    addr: SocketAddr,
+) -> (OwnedCapability, tokio::task::JoinHandle<()>) {
+    let policy = ViewPolicy {
+        prefix: peer_namespace(user),
+        allow: ctx.peer_registry.allow.clone(),
+        deny: ctx.peer_registry.deny.clone(),
+    };
+
+    let requester = AuditActor::Peer {
+        user: user.to_string(),
+        address: addr.to_string(),
+    };
+
+    let view = RegistryView::new(network_root, policy, ctx.peer_services.clone())
+        .with_requester(requester.clone());
+
+    let mut meta = cargo_process_metadata!();
+    meta.name = Some(format!("registry view for {}", user));
+
+    let process = ctx.runtime.process_factory.spawn_for(meta, requester, None);
+    let perms = Permissions::SEND | Permissions::MONITOR;
+    let cap = process.borrow_parent().export(perms).unwrap().to_owned();
+
+    let runtime = ctx.runtime.clone();
+    let label = format!("registry view for {}", user);
+    let task = tokio::spawn(async move {
+        view.run(label, runtime, &process).await;
+    });
+
+    (cap, task)
+}
+
+fn load_peer_registry_config(builder: &RuntimeBuilder) -> PeerRegistryConfig {
+    builder.load_config("peer_registry").unwrap_or_else(|err| {
+        debug!("Using default peer registry config: {}", err);
+        PeerRegistryConfig::default()
+    })
+}
+
+/// Loads the network connection config, falling back to the defaults.
+fn load_network_config(builder: &RuntimeBuilder) -> ConnectionConfig {
+    let config: ConnectionConfig = builder.load_config("network").unwrap_or_else(|err| {
+        debug!("Using default network config: {}", err);
+        ConnectionConfig::default()
+    });
+
+    match config.validate() {
+        Ok(()) => config,
+        Err(err) => {
+            warn!("Using default network config: {}", err);
+            ConnectionConfig::default()
+        }
+    }
 }
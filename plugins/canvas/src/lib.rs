@@ -23,10 +23,14 @@ use flume::{Receiver, Sender};
 use hearth_rend3::{
     rend3::{
         graph::{DepthHandle, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets},
-        types::glam::{vec2, Mat4, Vec4},
+        types::{
+            glam::{vec2, Mat4, Vec4},
+            SampleCount,
+        },
+        Renderer,
     },
     wgpu::{util::DeviceExt, *},
-    Node, Rend3Plugin, Routine, RoutineInfo,
+    Node, Rend3Plugin, Routine, RoutineInfo, HDR_FORMAT,
 };
 use hearth_runtime::{
     async_trait, cargo_process_metadata,
@@ -75,8 +79,11 @@ pub struct CanvasDraw {
     position: Position,
     ubo: Buffer,
     sampling_mode: CanvasSamplingMode,
-    width: u32,
-    height: u32,
+
+    /// A CPU-side copy of the canvas's pixels, used to restore its texture
+    /// after a device reset.
+    pixels: Pixels,
+
     texture: Texture,
     bind_group: BindGroup,
 }
@@ -89,31 +96,37 @@ impl CanvasDraw {
         sampler: &Sampler,
         sampling_mode: CanvasSamplingMode,
         position: Position,
-        pixels: Pixels,
+        mut pixels: Pixels,
     ) -> Self {
-        let ubo = device.create_buffer(&BufferDescriptor {
-            label: Some("canvas uniform"),
-            size: std::mem::size_of::<CanvasUniform>() as BufferAddress,
-            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
-            mapped_at_creation: false,
-        });
-
-        let width = pixels.width;
-        let height = pixels.height;
-        let texture = Self::create_texture(device, queue, pixels);
+        let ubo = Self::create_ubo(device);
+        Self::correct_len(&mut pixels);
+        let texture = Self::create_texture(device, queue, &pixels);
         let bind_group = Self::create_bind_group(device, bgl, &ubo, &texture, sampler);
 
         Self {
             position,
             ubo,
-            width,
-            height,
+            pixels,
             texture,
             sampling_mode,
             bind_group,
         }
     }
 
+    /// Recreates this canvas's GPU objects on a new device, restoring its
+    /// texture from the CPU-side copy of its pixels.
+    pub fn recreate(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        bgl: &BindGroupLayout,
+        sampler: &Sampler,
+    ) {
+        self.ubo = Self::create_ubo(device);
+        self.texture = Self::create_texture(device, queue, &self.pixels);
+        self.bind_group = Self::create_bind_group(device, bgl, &self.ubo, &self.texture, sampler);
+    }
+
     /// Resizes the canvas pixel buffer and recreates GPU objects.
     ///
     /// Does not reallocate any GPU objects if the size of the new pixel buffer
@@ -125,20 +138,20 @@ impl CanvasDraw {
         &mut self,
         device: &Device,
         queue: &Queue,
-        pixels: Pixels,
+        mut pixels: Pixels,
         bgl: &BindGroupLayout,
         sampler: &Sampler,
     ) {
         // don't allocate a new texture if the size is the same. just blit.
-        if self.width == pixels.width && self.height == pixels.height {
+        if self.pixels.width == pixels.width && self.pixels.height == pixels.height {
             let blit = Blit { x: 0, y: 0, pixels };
             self.blit(queue, blit);
             return;
         }
 
-        self.width = pixels.width;
-        self.height = pixels.height;
-        self.texture = Self::create_texture(device, queue, pixels);
+        Self::correct_len(&mut pixels);
+        self.texture = Self::create_texture(device, queue, &pixels);
+        self.pixels = pixels;
         self.bind_group = Self::create_bind_group(device, bgl, &self.ubo, &self.texture, sampler);
     }
 
@@ -167,7 +180,8 @@ impl CanvasDraw {
             -Vec4::ONE
         } else {
             // pass the texture size and add padding
-            Vec4::new(self.width as f32, self.height as f32, 0.0, 0.0)
+            let (width, height) = (self.pixels.width, self.pixels.height);
+            Vec4::new(width as f32, height as f32, 0.0, 0.0)
         };
 
         let ubo = CanvasUniform { mvp, texture_size };
@@ -177,10 +191,14 @@ impl CanvasDraw {
 
     /// Implements the [Blit] operation: copies a pixel buffer to a target
     /// destination region of this canvas.
-    pub fn blit(&self, queue: &Queue, mut blit: Blit) {
+    ///
+    /// Only the blit's region of the texture is uploaded. Blits are expected
+    /// to be validated with [validate_blit] beforehand, but any out-of-bounds
+    /// regions are clipped here as well.
+    pub fn blit(&mut self, queue: &Queue, mut blit: Blit) {
         // available width and height
-        let aw = self.width.saturating_sub(blit.x);
-        let ah = self.height.saturating_sub(blit.y);
+        let aw = self.pixels.width.saturating_sub(blit.x);
+        let ah = self.pixels.height.saturating_sub(blit.y);
 
         // consumed width and height
         let width = blit.pixels.width.min(aw);
@@ -192,9 +210,18 @@ impl CanvasDraw {
         }
 
         // correct the pixel data length
-        blit.pixels
-            .data
-            .resize((blit.pixels.width * blit.pixels.height) as usize * 4, 0xff);
+        Self::correct_len(&mut blit.pixels);
+
+        // keep the CPU-side copy in sync, one row of the region at a time
+        let src_stride = blit.pixels.width as usize * 4;
+        let dst_stride = self.pixels.width as usize * 4;
+        let row_len = width as usize * 4;
+        for row in 0..height as usize {
+            let src = row * src_stride;
+            let dst = (blit.y as usize + row) * dst_stride + blit.x as usize * 4;
+            self.pixels.data[dst..(dst + row_len)]
+                .copy_from_slice(&blit.pixels.data[src..(src + row_len)]);
+        }
 
         queue.write_texture(
             ImageCopyTexture {
@@ -221,13 +248,26 @@ impl CanvasDraw {
         );
     }
 
-    /// Helper function to recreate the canvas's texture object with the given pixels.
-    fn create_texture(device: &Device, queue: &Queue, mut pixels: Pixels) -> Texture {
-        // correct the pixel data length
+    /// Pads or truncates a pixel buffer's data to exactly fit its size.
+    fn correct_len(pixels: &mut Pixels) {
         pixels
             .data
             .resize((pixels.width * pixels.height) as usize * 4, 0xff);
+    }
+
+    /// Helper function to create the canvas's uniform buffer.
+    fn create_ubo(device: &Device) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("canvas uniform"),
+            size: std::mem::size_of::<CanvasUniform>() as BufferAddress,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        })
+    }
 
+    /// Helper function to recreate the canvas's texture object with the given
+    /// pixels, which must already be the correct length.
+    fn create_texture(device: &Device, queue: &Queue, pixels: &Pixels) -> Texture {
         device.create_texture_with_data(
             queue,
             &TextureDescriptor {
@@ -284,7 +324,10 @@ pub struct CanvasRoutine {
     ops_rx: Receiver<CanvasOperation>,
     device: Arc<Device>,
     queue: Arc<Queue>,
+    shader: ShaderModule,
+    layout: PipelineLayout,
     bgl: BindGroupLayout,
+    sample_count: SampleCount,
     pipeline: RenderPipeline,
     sampler: Sampler,
     draws: HashMap<CanvasId, CanvasDraw>,
@@ -292,8 +335,18 @@ pub struct CanvasRoutine {
 
 impl CanvasRoutine {
     fn new(rend3: &mut Rend3Plugin, ops_rx: Receiver<CanvasOperation>) -> Self {
-        let device = rend3.iad.device.as_ref();
+        let device = rend3.iad.device.to_owned();
+        let queue = rend3.iad.queue.to_owned();
+        Self::create(device, queue, rend3.sample_count, ops_rx)
+    }
 
+    /// Creates the routine's GPU state on a device, without any canvases.
+    fn create(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        sample_count: SampleCount,
+        ops_rx: Receiver<CanvasOperation>,
+    ) -> Self {
         let shader = device.create_shader_module(&include_wgsl!("shaders.wgsl"));
 
         let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
@@ -334,11 +387,43 @@ impl CanvasRoutine {
             push_constant_ranges: &[],
         });
 
-        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        let pipeline = Self::create_pipeline(&device, &shader, &layout, sample_count);
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            ops_rx,
+            device,
+            queue,
+            shader,
+            layout,
+            bgl,
+            sample_count,
+            pipeline,
+            sampler,
+            draws: HashMap::new(),
+        }
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        shader: &ShaderModule,
+        layout: &PipelineLayout,
+        sample_count: SampleCount,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
             label: Some("canvas pipeline"),
-            layout: Some(&layout),
+            layout: Some(layout),
             vertex: VertexState {
-                module: &shader,
+                module: shader,
                 entry_point: "vs_main",
                 buffers: &[],
             },
@@ -353,38 +438,21 @@ impl CanvasRoutine {
                 stencil: Default::default(),
                 bias: Default::default(),
             }),
-            multisample: Default::default(),
+            multisample: MultisampleState {
+                count: sample_count as u32,
+                ..Default::default()
+            },
             fragment: Some(FragmentState {
-                module: &shader,
+                module: shader,
                 entry_point: "fs_main",
                 targets: &[ColorTargetState {
-                    format: rend3.surface_format,
+                    format: HDR_FORMAT,
                     blend: None,
                     write_mask: ColorWrites::COLOR,
                 }],
             }),
             multiview: None,
-        });
-
-        let sampler = device.create_sampler(&SamplerDescriptor {
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: FilterMode::Linear,
-            ..Default::default()
-        });
-
-        Self {
-            ops_rx,
-            device: rend3.iad.device.to_owned(),
-            queue: rend3.iad.queue.to_owned(),
-            bgl,
-            pipeline,
-            sampler,
-            draws: HashMap::new(),
-        }
+        })
     }
 }
 
@@ -431,6 +499,25 @@ impl Routine for CanvasRoutine {
 
         Box::new(CanvasNode { routine: self })
     }
+
+    fn set_sample_count(&mut self, sample_count: SampleCount) {
+        self.sample_count = sample_count;
+        self.pipeline =
+            Self::create_pipeline(&self.device, &self.shader, &self.layout, sample_count);
+    }
+
+    fn on_device_reset(&mut self, renderer: &Arc<Renderer>) {
+        let device = renderer.device.to_owned();
+        let queue = renderer.queue.to_owned();
+        let ops_rx = self.ops_rx.clone();
+        let draws = std::mem::take(&mut self.draws);
+        *self = Self::create(device, queue, self.sample_count, ops_rx);
+
+        for (id, mut draw) in draws {
+            draw.recreate(&self.device, &self.queue, &self.bgl, &self.sampler);
+            self.draws.insert(id, draw);
+        }
+    }
 }
 
 /// The canvas rend3 render node.
@@ -440,18 +527,22 @@ pub struct CanvasNode<'a> {
 
 impl<'a> Node<'a> for CanvasNode<'a> {
     fn draw<'graph>(&'graph self, info: &mut RoutineInfo<'_, 'graph>) {
-        let output = info.graph.add_surface_texture();
+        // draw into the scene's HDR target so that canvases are tonemapped
+        // with the rest of the scene instead of being drawn over by it
+        let output = info.state.color;
+        let resolve = info.state.resolve;
         let depth = info.state.depth;
 
         let mut builder = info.graph.add_node("canvas");
         let output_handle = builder.add_render_target_output(output);
+        let resolve_handle = resolve.map(|resolve| builder.add_render_target_output(resolve));
         let depth_handle = builder.add_render_target_output(depth);
 
         let rpass_handle = builder.add_renderpass(RenderPassTargets {
             targets: vec![RenderPassTarget {
                 color: output_handle,
                 clear: Color::BLACK,
-                resolve: None,
+                resolve: resolve_handle,
             }],
             depth_stencil: Some(RenderPassDepthTarget {
                 target: DepthHandle::RenderTarget(depth_handle),
@@ -480,6 +571,48 @@ impl<'a> Node<'a> for CanvasNode<'a> {
     }
 }
 
+/// Checks that a blit's rectangle lies entirely within a canvas of the given
+/// size.
+pub fn validate_blit(width: u32, height: u32, blit: &Blit) -> Result<(), CanvasError> {
+    let right = blit.x.checked_add(blit.pixels.width);
+    let bottom = blit.y.checked_add(blit.pixels.height);
+
+    match (right, bottom) {
+        (Some(right), Some(bottom)) if right <= width && bottom <= height => Ok(()),
+        _ => Err(CanvasError::OutOfBounds),
+    }
+}
+
+/// Expands pixels encoded in the given [PixelFormat] to RGBA.
+///
+/// Missing pixel data is padded with `0xff` and excess data is truncated, so
+/// the result always holds exactly `width * height * 4` bytes.
+pub fn expand_pixels(format: &PixelFormat, mut pixels: Pixels) -> Pixels {
+    let len = pixels.width as usize * pixels.height as usize;
+    pixels.data.resize(len * format.bytes_per_pixel(), 0xff);
+
+    pixels.data = match format {
+        PixelFormat::Rgba8 => pixels.data,
+        PixelFormat::Rgb8 => pixels
+            .data
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 0xff])
+            .collect(),
+        PixelFormat::Bgra8 => pixels
+            .data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect(),
+        PixelFormat::Paletted { palette } => pixels
+            .data
+            .iter()
+            .flat_map(|index| palette.get(*index as usize).copied().unwrap_or([0xff; 4]))
+            .collect(),
+    };
+
+    pixels
+}
+
 /// A canvas process. Processes [CanvasUpdate].
 pub struct CanvasInstance {
     /// This canvas's ID.
@@ -487,6 +620,15 @@ pub struct CanvasInstance {
 
     /// A sender to the canvas routine.
     ops_tx: Sender<CanvasOperation>,
+
+    /// The format of incoming pixel data.
+    format: PixelFormat,
+
+    /// The current width of the canvas, used to validate blits.
+    width: u32,
+
+    /// The current height of the canvas, used to validate blits.
+    height: u32,
 }
 
 impl Drop for CanvasInstance {
@@ -500,9 +642,41 @@ impl SinkProcess for CanvasInstance {
     type Message = CanvasUpdate;
 
     async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>) {
+        let result = self.on_update(message.data);
+
+        // updates are fire-and-forget unless a reply capability is attached
+        if let Some(reply) = message.caps.first() {
+            let data = serde_json::to_vec(&result).unwrap();
+            let _ = reply.send(&data, &[]).await;
+        }
+    }
+}
+
+impl CanvasInstance {
+    /// Validates and expands an update, then forwards it to the routine.
+    fn on_update(&mut self, update: CanvasUpdate) -> CanvasResponse {
+        let update = match update {
+            CanvasUpdate::Relocate(position) => CanvasUpdate::Relocate(position),
+            CanvasUpdate::Resize(pixels) => {
+                self.width = pixels.width;
+                self.height = pixels.height;
+                CanvasUpdate::Resize(expand_pixels(&self.format, pixels))
+            }
+            CanvasUpdate::Blit(blit) => {
+                validate_blit(self.width, self.height, &blit)?;
+
+                CanvasUpdate::Blit(Blit {
+                    pixels: expand_pixels(&self.format, blit.pixels),
+                    ..blit
+                })
+            }
+        };
+
         let _ = self
             .ops_tx
-            .send((self.id, CanvasOperationKind::Update(message.data)));
+            .send((self.id, CanvasOperationKind::Update(update)));
+
+        Ok(())
     }
 }
 
@@ -529,7 +703,15 @@ impl RequestResponseProcess for CanvasFactory {
                 position,
                 pixels,
                 sampling,
+                format,
             } => {
+                // reject palettes that can't be indexed by a single byte
+                if let PixelFormat::Paletted { palette } = format {
+                    if palette.is_empty() || palette.len() > PixelFormat::MAX_PALETTE_LEN {
+                        return FactoryError::UnsupportedFormat.into();
+                    }
+                }
+
                 // allocate a new ID
                 let id = self.next_id;
                 self.next_id += 1;
@@ -539,7 +721,7 @@ impl RequestResponseProcess for CanvasFactory {
                     id,
                     CanvasOperationKind::Create {
                         position: position.to_owned(),
-                        pixels: pixels.to_owned(),
+                        pixels: expand_pixels(format, pixels.to_owned()),
                         sampling: sampling.to_owned(),
                     },
                 ));
@@ -548,6 +730,9 @@ impl RequestResponseProcess for CanvasFactory {
                 let instance = CanvasInstance {
                     id,
                     ops_tx: self.ops_tx.clone(),
+                    format: format.to_owned(),
+                    width: pixels.width,
+                    height: pixels.height,
                 };
 
                 // initialize the instance's metadata
@@ -556,7 +741,11 @@ impl RequestResponseProcess for CanvasFactory {
                 meta.description = Some("An instance of a canvas.".to_string());
 
                 // spawn the instance child process
-                let child = request.runtime.process_factory.spawn(meta);
+                let requester = request.process.borrow_info().audit_actor();
+                let child = request
+                    .runtime
+                    .process_factory
+                    .spawn_for(meta, requester, None);
 
                 // retrieve the child's parent cap
                 let perms = Permissions::SEND | Permissions::KILL;
@@ -613,3 +802,83 @@ impl Plugin for CanvasPlugin {
         builder.add_plugin(CanvasFactory { next_id: 0, ops_tx });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blit(x: u32, y: u32, width: u32, height: u32) -> Blit {
+        Blit {
+            x,
+            y,
+            pixels: Pixels {
+                width,
+                height,
+                data: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn blit_in_bounds() {
+        assert_eq!(validate_blit(16, 8, &blit(0, 0, 16, 8)), Ok(()));
+        assert_eq!(validate_blit(16, 8, &blit(4, 2, 12, 6)), Ok(()));
+        assert_eq!(validate_blit(16, 8, &blit(16, 8, 0, 0)), Ok(()));
+    }
+
+    #[test]
+    fn blit_out_of_bounds() {
+        let err = Err(CanvasError::OutOfBounds);
+        assert_eq!(validate_blit(16, 8, &blit(1, 0, 16, 8)), err);
+        assert_eq!(validate_blit(16, 8, &blit(0, 1, 16, 8)), err);
+        assert_eq!(validate_blit(16, 8, &blit(17, 0, 0, 0)), err);
+        assert_eq!(validate_blit(16, 8, &blit(u32::MAX, 0, 2, 1)), err);
+        assert_eq!(validate_blit(16, 8, &blit(0, u32::MAX, 1, 2)), err);
+    }
+
+    fn expand(format: PixelFormat, data: Vec<u8>) -> Vec<u8> {
+        let pixels = Pixels {
+            width: 2,
+            height: 1,
+            data,
+        };
+
+        expand_pixels(&format, pixels).data
+    }
+
+    #[test]
+    fn expand_rgba8() {
+        assert_eq!(
+            expand(PixelFormat::Rgba8, vec![1, 2, 3]),
+            [1, 2, 3, 255, 255, 255, 255, 255]
+        );
+    }
+
+    #[test]
+    fn expand_rgb8() {
+        assert_eq!(
+            expand(PixelFormat::Rgb8, vec![1, 2, 3, 4, 5, 6]),
+            [1, 2, 3, 255, 4, 5, 6, 255]
+        );
+    }
+
+    #[test]
+    fn expand_bgra8() {
+        assert_eq!(
+            expand(PixelFormat::Bgra8, vec![1, 2, 3, 4, 5, 6, 7, 8]),
+            [3, 2, 1, 4, 7, 6, 5, 8]
+        );
+    }
+
+    #[test]
+    fn expand_paletted() {
+        let format = PixelFormat::Paletted {
+            palette: vec![[10, 20, 30, 40]],
+        };
+
+        assert_eq!(
+            expand(format, vec![0, 1]),
+            [10, 20, 30, 40, 255, 255, 255, 255]
+        );
+    }
+}
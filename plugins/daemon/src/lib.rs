@@ -18,23 +18,38 @@
 
 use std::{
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
 };
 
 use hearth_init::InitPlugin;
 use hearth_ipc::get_socket_path;
 use hearth_runtime::{
+    async_trait, cargo_process_metadata,
     connection::Connection,
-    flue::OwnedCapability,
+    flue::{CapabilityRef, OwnedCapability, Permissions},
+    hearth_schema::{
+        audit::AuditActor,
+        registry::{RegistryRequest, RegistryResponse},
+        wasm::{WasmSpawnError, WasmSpawnResponse, WasmSpawnerRequest},
+    },
+    process::Process,
+    registry::{RegistryView, ViewPolicy, ViewStore},
     runtime::{Plugin, Runtime, RuntimeBuilder},
     tokio::{
         self,
         net::{UnixListener, UnixStream},
         sync::oneshot,
     },
+    utils::{MessageInfo, ProcessRunner, RequestResponse, SinkProcess},
 };
 
+/// The namespace that services registered by IPC clients are stored under.
+pub const IPC_SERVICE_PREFIX: &str = "ipc.";
+
+/// The name of the Wasm process spawner service.
+const WASM_SPAWNER_SERVICE: &str = "hearth.wasm.WasmProcessSpawner";
+
 pub struct Listener {
     pub uds: UnixListener,
     pub path: PathBuf,
@@ -64,10 +79,14 @@ impl DerefMut for Listener {
 }
 
 impl Listener {
-    pub async fn new() -> std::io::Result<Self> {
+    /// Binds a new listener to the socket path of the given daemon instance.
+    ///
+    /// Leftover sockets from crashed daemons are replaced. The socket is only
+    /// accessible by the current user.
+    pub async fn new(instance: Option<&str>) -> std::io::Result<Self> {
         use std::io::{Error, ErrorKind};
 
-        let sock_path = match get_socket_path() {
+        let sock_path = match get_socket_path(instance) {
             Some(p) => p,
             None => {
                 let kind = ErrorKind::NotFound;
@@ -95,7 +114,7 @@ impl Listener {
         }
 
         tracing::info!("Making socket at: {:?}", sock_path);
-        let uds = UnixListener::bind(&sock_path)?;
+        let uds = bind_private(&sock_path)?;
         let path = sock_path.to_path_buf();
         Ok(Self { uds, path })
     }
@@ -118,8 +137,27 @@ impl Listener {
     }
 }
 
+/// Binds a Unix socket that only the current user can connect to.
+///
+/// The socket is created with its final permissions by narrowing the umask
+/// during the bind, so other users can't connect in the moment before a
+/// chmod would have run.
+fn bind_private(path: &Path) -> std::io::Result<UnixListener> {
+    // SAFETY: umask() always succeeds and only swaps the file mode mask
+    let old_mask = unsafe { libc::umask(0o177) };
+    let result = UnixListener::bind(path);
+    unsafe { libc::umask(old_mask) };
+    result
+}
+
 #[derive(Default)]
-pub struct DaemonPlugin {}
+pub struct DaemonPlugin {
+    /// The name of this daemon instance, used to pick a socket path that
+    /// doesn't collide with other daemons run by the same user.
+    ///
+    /// See [hearth_ipc::get_socket_paths] for details.
+    pub instance: Option<String>,
+}
 
 impl Plugin for DaemonPlugin {
     fn finalize(mut self, builder: &mut RuntimeBuilder) {
@@ -142,9 +180,11 @@ impl Plugin for DaemonPlugin {
                     }
                 };
 
+                let root_cap = spawn_ipc_root(&runtime, root_cap);
+
                 tracing::info!("Listening on IPC daemon...");
 
-                let listener = match Listener::new().await {
+                let listener = match Listener::new(self.instance.as_deref()).await {
                     Ok(l) => l,
                     Err(err) => {
                         tracing::warn!("error while listening on IPC daemon: {}", err);
@@ -172,8 +212,159 @@ impl DaemonPlugin {
     ) {
         tracing::info!("Beginning IPC connection");
         let conn = Connection::begin(runtime.post.clone(), transport.op_rx, transport.op_tx, None);
+        let audit = runtime.process_factory.audit_log().clone();
+        conn.audit_kills(audit, AuditActor::Host);
 
         tracing::info!("Sending the IPC client our root cap");
         conn.export_root(root_cap);
     }
 }
+
+/// Wraps the daemon's root registry in a view that IPC clients can register
+/// services in and returns a capability to the view.
+///
+/// The view shadows the Wasm process spawner with an [IpcSpawner] so that
+/// IPC clients can spawn services into it.
+fn spawn_ipc_root(runtime: &Arc<Runtime>, root: OwnedCapability) -> OwnedCapability {
+    let store = ViewStore::default();
+    let policy = ViewPolicy::new(IPC_SERVICE_PREFIX);
+    let view =
+        RegistryView::new(root.clone(), policy, store.clone()).with_requester(AuditActor::Host);
+
+    let view = spawn_runner(runtime, "IPC registry view", view);
+
+    let spawner = IpcSpawner {
+        root,
+        registry: view.clone(),
+    };
+
+    let spawner = spawn_runner(runtime, "IPC Wasm process spawner", spawner);
+    let name = format!("{}{}", IPC_SERVICE_PREFIX, WASM_SPAWNER_SERVICE);
+    store.insert(name, spawner);
+    view
+}
+
+/// Spawns a host process running `runner` and returns a capability to it.
+fn spawn_runner(
+    runtime: &Arc<Runtime>,
+    name: &str,
+    runner: impl ProcessRunner + 'static,
+) -> OwnedCapability {
+    let mut meta = cargo_process_metadata!();
+    meta.name = Some(name.to_string());
+
+    let process = runtime.process_factory.spawn(meta);
+    let perms = Permissions::SEND | Permissions::MONITOR;
+    let cap = process.borrow_parent().export(perms).unwrap().to_owned();
+
+    let runtime = runtime.clone();
+    let label = name.to_string();
+    tokio::spawn(async move {
+        runner.run(label, runtime, &process).await;
+    });
+
+    cap
+}
+
+/// Forwards requests from IPC clients to the Wasm process spawner. Accepts
+/// [WasmSpawnerRequest].
+///
+/// IPC clients can only attach capabilities that they host themselves, so
+/// they can't give the spawner a registry to register
+/// [WasmSpawnerRequest::SpawnService] processes in. This attaches the IPC
+/// registry view to such requests that don't have one.
+struct IpcSpawner {
+    /// The daemon's root registry, which the spawner is looked up in.
+    root: OwnedCapability,
+
+    /// The registry view given to IPC clients.
+    registry: OwnedCapability,
+}
+
+#[async_trait]
+impl SinkProcess for IpcSpawner {
+    type Message = WasmSpawnerRequest;
+
+    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, WasmSpawnerRequest>) {
+        let Some(reply) = message.caps.first() else {
+            tracing::debug!("Request to {:?} has no reply address", message.label);
+            return;
+        };
+
+        let table = message.process.borrow_table();
+        let import = |cap: &OwnedCapability| {
+            let handle = table.import_owned(cap.clone()).unwrap();
+            table.wrap_handle(handle).unwrap()
+        };
+
+        let root = import(&self.root);
+        let spawner = match get_service(message.process, root, WASM_SPAWNER_SERVICE).await {
+            Ok(spawner) => spawner,
+            Err(err) => {
+                let response: WasmSpawnResponse = Err(WasmSpawnError::Failed(err));
+                send(reply, serde_json::to_vec(&response).unwrap(), &[]).await;
+                return;
+            }
+        };
+
+        let registry = import(&self.registry);
+        let mut caps: Vec<_> = message.caps.iter().collect();
+        if let WasmSpawnerRequest::SpawnService { .. } = message.data {
+            if caps.len() == 1 {
+                caps.push(&registry);
+            }
+        }
+
+        // the spawner replies directly
+        send(&spawner, serde_json::to_vec(&message.data).unwrap(), &caps).await;
+    }
+}
+
+/// Looks up a service by name in a registry.
+async fn get_service<'a>(
+    process: &'a Process,
+    registry: CapabilityRef<'a>,
+    name: &str,
+) -> Result<CapabilityRef<'a>, String> {
+    let registry = RequestResponse::<RegistryRequest, RegistryResponse>::new(process, registry);
+
+    let request = RegistryRequest::Get {
+        name: name.to_string(),
+    };
+
+    match registry.request(&request, &[]).await {
+        Ok((RegistryResponse::Get(true), mut caps)) if !caps.is_empty() => Ok(caps.remove(0)),
+        Ok(_) => Err(format!("service {:?} is unavailable", name)),
+        Err(err) => Err(format!("looking up {:?}: {}", name, err)),
+    }
+}
+
+/// Sends a message, logging any errors.
+async fn send(cap: &CapabilityRef<'_>, data: Vec<u8>, caps: &[&CapabilityRef<'_>]) {
+    if let Err(err) = cap.send(&data, caps).await {
+        tracing::debug!("IPC spawner send error: {:?}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn sockets_are_private() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let path = std::env::temp_dir().join(format!("hearth-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let listener = bind_private(&path).unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
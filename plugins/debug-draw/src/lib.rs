@@ -16,16 +16,24 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use bytemuck::{Pod, Zeroable};
 use flume::{unbounded, Receiver, Sender};
 use glam::Vec3;
 use hearth_rend3::{
-    rend3::graph::{DepthHandle, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets},
+    rend3::{
+        graph::{DepthHandle, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets},
+        types::SampleCount,
+        Renderer,
+    },
     utils::DynamicMesh,
     wgpu::*,
-    Node, Rend3Plugin, Routine, RoutineInfo,
+    Node, Rend3Plugin, Routine, RoutineInfo, HDR_FORMAT,
 };
 use hearth_runtime::{
     async_trait, cargo_process_metadata,
@@ -36,6 +44,11 @@ use hearth_runtime::{
 use hearth_schema::debug_draw::*;
 use itertools::Itertools;
 
+use shapes::{append_mesh, expand_shape};
+
+/// Expansion of high-level debug draw shapes into lines.
+pub mod shapes;
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct CameraUniform {
@@ -69,16 +82,107 @@ impl Vertex {
     };
 }
 
+/// A shape added to a debug draw, with an optional expiration time.
+struct AddedShape {
+    mesh: DebugDrawMesh,
+    expires: Option<Instant>,
+}
+
 struct DebugDraw {
     mesh: DynamicMesh<Vertex>,
     hide: bool,
+
+    /// The contents most recently set with [DebugDrawUpdate::Contents].
+    contents: Option<DebugDrawMesh>,
+
+    /// Shapes added on top of the contents.
+    shapes: Vec<AddedShape>,
+
+    /// Whether the GPU mesh needs to be rebuilt.
+    dirty: bool,
+}
+
+impl DebugDraw {
+    /// Applies a single update, other than destruction.
+    fn apply(&mut self, update: DebugDrawUpdate, now: Instant) {
+        use DebugDrawUpdate::*;
+        match update {
+            Contents(mesh) => {
+                self.contents = Some(mesh);
+                self.shapes.clear();
+                self.dirty = true;
+            }
+            Add { shape, ttl } => {
+                let expires = match ttl.map(Duration::try_from_secs_f32) {
+                    None => None,
+                    Some(Ok(ttl)) => Some(now + ttl),
+                    Some(Err(_)) => return, // ignore invalid lifetimes
+                };
+
+                self.shapes.push(AddedShape {
+                    mesh: expand_shape(&shape),
+                    expires,
+                });
+
+                self.dirty = true;
+            }
+            Clear => {
+                self.contents = None;
+                self.shapes.clear();
+                self.dirty = true;
+            }
+            Hide(hide) => self.hide = hide,
+            Destroy => {}
+        }
+    }
+
+    /// Removes expired shapes and uploads the mesh if it has changed.
+    fn flush(&mut self, device: &Device, queue: &Queue, now: Instant) {
+        let shape_num = self.shapes.len();
+        self.shapes
+            .retain(|shape| shape.expires.map_or(true, |expires| expires > now));
+        self.dirty |= self.shapes.len() != shape_num;
+
+        if !self.dirty {
+            return;
+        }
+
+        self.dirty = false;
+
+        let mut lines = DebugDrawMesh {
+            vertices: vec![],
+            indices: vec![],
+        };
+
+        if let Some(contents) = self.contents.as_ref() {
+            append_mesh(&mut lines, contents);
+        }
+
+        for shape in self.shapes.iter() {
+            append_mesh(&mut lines, &shape.mesh);
+        }
+
+        let vertices: Vec<_> = lines
+            .vertices
+            .into_iter()
+            .map(|v| Vertex {
+                position: v.position,
+                color: v.color.0,
+            })
+            .collect();
+
+        self.mesh.update(device, queue, &vertices, &lines.indices);
+    }
 }
 
 pub struct DebugDrawRoutine {
     device: Arc<Device>,
     queue: Arc<Queue>,
+    shader: ShaderModule,
+    layout: PipelineLayout,
     camera_bind_group: BindGroup,
     camera_buffer: Buffer,
+    sample_count: SampleCount,
     pipeline: RenderPipeline,
     draws: HashMap<usize, DebugDraw>,
     update_rx: Receiver<(usize, DebugDrawUpdate)>,
@@ -88,148 +192,107 @@ impl Routine for DebugDrawRoutine {
     fn build_node(&mut self) -> Box<dyn Node + '_> {
         // vec of updates received in order by each ID
         let updates = self.update_rx.drain().into_group_map();
+        let now = Instant::now();
 
-        for (id, mut updates) in updates {
-            // only write the latest property from the update queue
-            let mut new_contents = None;
-            let mut new_hide = None;
-
-            // whether a destroy message has been received
-            let mut destroy = false;
-
-            // read from update queue in reverse order to fetch latest properties
-            while let Some(update) = updates.pop() {
-                use DebugDrawUpdate::*;
-                match update {
-                    Contents(mesh) if new_contents.is_none() => {
-                        new_contents = Some(mesh);
-                    }
-                    Hide(hide) if new_hide.is_none() => {
-                        new_hide = Some(hide);
-                    }
-                    Destroy => {
-                        destroy = true;
-                        break;
-                    }
-                    _ => {}
-                }
-            }
-
+        for (id, updates) in updates {
             // if the draw has been destroyed, remove it and discard updates
-            if destroy {
+            if updates
+                .iter()
+                .any(|update| matches!(update, DebugDrawUpdate::Destroy))
+            {
                 self.draws.remove(&id);
                 continue;
             }
 
             // retrieve the draw by ID or init it if it doesn't exist yet
             let draw = self.draws.entry(id).or_insert_with(|| DebugDraw {
-                mesh: DynamicMesh::new(self.device.as_ref(), Some(format!("debug draw #{id}"))),
+                mesh: Self::create_mesh(&self.device, id),
                 hide: false,
+                contents: None,
+                shapes: vec![],
+                dirty: false,
             });
 
-            if let Some(mesh) = new_contents {
-                let vertices: Vec<_> = mesh
-                    .vertices
-                    .into_iter()
-                    .map(|v| Vertex {
-                        position: v.position,
-                        color: v.color.0,
-                    })
-                    .collect();
-
-                draw.mesh.update(
-                    self.device.as_ref(),
-                    self.queue.as_ref(),
-                    &vertices,
-                    &mesh.indices,
-                );
+            for update in updates {
+                draw.apply(update, now);
             }
+        }
 
-            if let Some(hide) = new_hide {
-                draw.hide = hide;
-            }
+        for draw in self.draws.values_mut() {
+            draw.flush(&self.device, &self.queue, now);
         }
 
         Box::new(DebugDrawNode { routine: self })
     }
+
+    fn set_sample_count(&mut self, sample_count: SampleCount) {
+        self.sample_count = sample_count;
+        self.pipeline =
+            Self::create_pipeline(&self.device, &self.shader, &self.layout, sample_count);
+    }
+
+    fn on_device_reset(&mut self, renderer: &Arc<Renderer>) {
+        let device = renderer.device.to_owned();
+        let queue = renderer.queue.to_owned();
+        let update_rx = self.update_rx.clone();
+        let draws = std::mem::take(&mut self.draws);
+        *self = Self::create(device, queue, self.sample_count, update_rx);
+
+        // shapes are kept on the CPU, so meshes are rebuilt on the next flush
+        for (id, mut draw) in draws {
+            draw.mesh = Self::create_mesh(&self.device, id);
+            draw.dirty = true;
+            self.draws.insert(id, draw);
+        }
+    }
 }
 
 impl DebugDrawRoutine {
     pub fn new(rend3: &Rend3Plugin, update_rx: Receiver<(usize, DebugDrawUpdate)>) -> Self {
-        let shader = rend3
-            .iad
-            .device
-            .create_shader_module(&include_wgsl!("shaders.wgsl"));
-
-        let bgl = rend3
-            .iad
-            .device
-            .create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("debug draw bind group layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::VERTEX,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
-
-        let layout = rend3
-            .iad
-            .device
-            .create_pipeline_layout(&PipelineLayoutDescriptor {
-                label: Some("debug draw pipeline layout"),
-                bind_group_layouts: &[&bgl],
-                push_constant_ranges: &[],
-            });
+        let device = rend3.iad.device.to_owned();
+        let queue = rend3.iad.queue.to_owned();
+        Self::create(device, queue, rend3.sample_count, update_rx)
+    }
 
-        let pipeline = rend3
-            .iad
-            .device
-            .create_render_pipeline(&RenderPipelineDescriptor {
-                label: Some("debug draw pipeline"),
-                layout: Some(&layout),
-                vertex: VertexState {
-                    module: &shader,
-                    entry_point: "vs_main",
-                    buffers: &[Vertex::LAYOUT],
-                },
-                primitive: PrimitiveState {
-                    topology: PrimitiveTopology::LineList,
-                    ..Default::default()
+    /// Creates the routine's GPU state on a device, without any draws.
+    fn create(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        sample_count: SampleCount,
+        update_rx: Receiver<(usize, DebugDrawUpdate)>,
+    ) -> Self {
+        let shader = device.create_shader_module(&include_wgsl!("shaders.wgsl"));
+
+        let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("debug draw bind group layout"),
+            entries: &[BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
                 },
-                depth_stencil: Some(DepthStencilState {
-                    format: TextureFormat::Depth32Float,
-                    depth_write_enabled: true,
-                    depth_compare: CompareFunction::GreaterEqual,
-                    stencil: Default::default(),
-                    bias: Default::default(),
-                }),
-                multisample: MultisampleState::default(),
-                fragment: Some(FragmentState {
-                    module: &shader,
-                    entry_point: "fs_main",
-                    targets: &[ColorTargetState {
-                        format: rend3.surface_format,
-                        blend: None,
-                        write_mask: ColorWrites::COLOR,
-                    }],
-                }),
-                multiview: None,
-            });
+                count: None,
+            }],
+        });
 
-        let camera_buffer = rend3.iad.device.create_buffer(&BufferDescriptor {
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("debug draw pipeline layout"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = Self::create_pipeline(&device, &shader, &layout, sample_count);
+
+        let camera_buffer = device.create_buffer(&BufferDescriptor {
             label: Some("debug draw camera buffer"),
             size: std::mem::size_of::<CameraUniform>() as BufferAddress,
             usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
             mapped_at_creation: false,
         });
 
-        let camera_bind_group = rend3.iad.device.create_bind_group(&BindGroupDescriptor {
+        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
             label: Some("debug draw bind group"),
             layout: &bgl,
             entries: &[BindGroupEntry {
@@ -239,15 +302,64 @@ impl DebugDrawRoutine {
         });
 
         Self {
-            device: rend3.iad.device.to_owned(),
-            queue: rend3.iad.queue.to_owned(),
+            device,
+            queue,
+            shader,
+            layout,
             camera_buffer,
             camera_bind_group,
+            sample_count,
             pipeline,
             draws: HashMap::new(),
             update_rx,
         }
     }
+
+    fn create_mesh(device: &Device, id: usize) -> DynamicMesh<Vertex> {
+        DynamicMesh::new(device, Some(format!("debug draw #{id}")))
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        shader: &ShaderModule,
+        layout: &PipelineLayout,
+        sample_count: SampleCount,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("debug draw pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[Vertex::LAYOUT],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::GreaterEqual,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count as u32,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: None,
+                    write_mask: ColorWrites::COLOR,
+                }],
+            }),
+            multiview: None,
+        })
+    }
 }
 
 struct DebugDrawNode<'a> {
@@ -256,18 +368,20 @@ struct DebugDrawNode<'a> {
 
 impl<'a> Node<'a> for DebugDrawNode<'a> {
     fn draw<'graph>(&'graph self, info: &mut RoutineInfo<'_, 'graph>) {
-        let output = info.graph.add_surface_texture();
+        let output = info.state.color;
+        let resolve = info.state.resolve;
         let depth = info.state.depth;
 
         let mut builder = info.graph.add_node("debug draw");
         let output_handle = builder.add_render_target_output(output);
+        let resolve_handle = resolve.map(|resolve| builder.add_render_target_output(resolve));
         let depth_handle = builder.add_render_target_output(depth);
 
         let rpass_handle = builder.add_renderpass(RenderPassTargets {
             targets: vec![RenderPassTarget {
                 color: output_handle,
                 clear: Color::BLACK,
-                resolve: None,
+                resolve: resolve_handle,
             }],
             depth_stencil: Some(RenderPassDepthTarget {
                 target: DepthHandle::RenderTarget(depth_handle),
@@ -294,7 +408,7 @@ impl<'a> Node<'a> for DebugDrawNode<'a> {
                 rpass.set_bind_group(0, &routine.camera_bind_group, &[]);
 
                 for draw in routine.draws.values() {
-                    if draw.hide {
+                    if draw.hide || draw.mesh.is_empty() {
                         continue;
                     }
 
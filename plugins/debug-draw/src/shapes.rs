@@ -0,0 +1,234 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Expansion of [DebugDrawShape] into line list meshes.
+
+use std::f32::consts::TAU;
+
+use glam::{Vec3, Vec3Swizzles};
+use hearth_schema::{debug_draw::*, Color};
+
+/// The minimum number of segments in a wire sphere's circles.
+pub const MIN_SPHERE_SEGMENTS: u32 = 3;
+
+/// The maximum number of segments in a wire sphere's circles.
+pub const MAX_SPHERE_SEGMENTS: u32 = 256;
+
+/// Expands a shape into a line list mesh.
+pub fn expand_shape(shape: &DebugDrawShape) -> DebugDrawMesh {
+    use DebugDrawShape::*;
+    match shape {
+        LineStrip { points, color } => line_strip(points, *color),
+        WireBox { min, max, color } => wire_box(*min, *max, *color),
+        WireSphere {
+            center,
+            radius,
+            segments,
+            color,
+        } => wire_sphere(*center, *radius, *segments, *color),
+        Axes { transform, size } => {
+            let origin = transform.transform_point3(Vec3::ZERO);
+            let axes = [
+                (Vec3::X, Color::from_rgb(0xff, 0x00, 0x00)),
+                (Vec3::Y, Color::from_rgb(0x00, 0xff, 0x00)),
+                (Vec3::Z, Color::from_rgb(0x00, 0x00, 0xff)),
+            ];
+
+            let mut mesh = empty_mesh();
+            for (axis, color) in axes {
+                let end = transform.transform_point3(axis * *size);
+                push_line(&mut mesh, origin, end, color);
+            }
+
+            mesh
+        }
+    }
+}
+
+/// Appends one mesh's lines to another.
+pub fn append_mesh(dst: &mut DebugDrawMesh, src: &DebugDrawMesh) {
+    let offset = dst.vertices.len() as u32;
+    dst.vertices.extend_from_slice(&src.vertices);
+    dst.indices
+        .extend(src.indices.iter().map(|index| index + offset));
+}
+
+fn empty_mesh() -> DebugDrawMesh {
+    DebugDrawMesh {
+        vertices: vec![],
+        indices: vec![],
+    }
+}
+
+fn vertex(position: Vec3, color: Color) -> DebugDrawVertex {
+    DebugDrawVertex { position, color }
+}
+
+fn push_line(mesh: &mut DebugDrawMesh, start: Vec3, end: Vec3, color: Color) {
+    let index = mesh.vertices.len() as u32;
+    mesh.vertices.push(vertex(start, color));
+    mesh.vertices.push(vertex(end, color));
+    mesh.indices.extend_from_slice(&[index, index + 1]);
+}
+
+fn line_strip(points: &[Vec3], color: Color) -> DebugDrawMesh {
+    let vertices: Vec<_> = points.iter().map(|point| vertex(*point, color)).collect();
+    let segments = vertices.len().saturating_sub(1) as u32;
+    let indices = (0..segments).flat_map(|i| [i, i + 1]).collect();
+    DebugDrawMesh { vertices, indices }
+}
+
+fn wire_box(min: Vec3, max: Vec3, color: Color) -> DebugDrawMesh {
+    // corner N takes its X from bit 0, Y from bit 1, and Z from bit 2
+    let vertices = (0..8)
+        .map(|corner| {
+            let pick = |bit: u32, min: f32, max: f32| if corner & bit == 0 { min } else { max };
+            let position = Vec3::new(
+                pick(1, min.x, max.x),
+                pick(2, min.y, max.y),
+                pick(4, min.z, max.z),
+            );
+
+            vertex(position, color)
+        })
+        .collect();
+
+    // connect each pair of corners that differ by a single bit
+    let mut indices = Vec::with_capacity(24);
+    for corner in 0..8u32 {
+        for bit in [1, 2, 4] {
+            if corner & bit == 0 {
+                indices.extend_from_slice(&[corner, corner | bit]);
+            }
+        }
+    }
+
+    DebugDrawMesh { vertices, indices }
+}
+
+fn wire_sphere(center: Vec3, radius: f32, segments: u32, color: Color) -> DebugDrawMesh {
+    let segments = segments.clamp(MIN_SPHERE_SEGMENTS, MAX_SPHERE_SEGMENTS);
+    let mut mesh = empty_mesh();
+
+    // circles on the XY, YZ, and XZ planes
+    for circle in 0..3 {
+        let base = mesh.vertices.len() as u32;
+
+        for i in 0..segments {
+            let angle = i as f32 / segments as f32 * TAU;
+            let point = Vec3::new(angle.cos(), angle.sin(), 0.0);
+            let point = match circle {
+                0 => point,
+                1 => point.zxy(),
+                _ => point.xzy(),
+            };
+
+            mesh.vertices.push(vertex(center + point * radius, color));
+            mesh.indices
+                .extend_from_slice(&[base + i, base + (i + 1) % segments]);
+        }
+    }
+
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color(0xffffffff);
+
+    fn line_positions(mesh: &DebugDrawMesh) -> Vec<(Vec3, Vec3)> {
+        mesh.indices
+            .chunks_exact(2)
+            .map(|line| {
+                let start = mesh.vertices[line[0] as usize].position;
+                let end = mesh.vertices[line[1] as usize].position;
+                (start, end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn line_strip_segments() {
+        let points = vec![Vec3::ZERO, Vec3::X, Vec3::Y];
+        let mesh = line_strip(&points, WHITE);
+        assert_eq!(mesh.indices, vec![0, 1, 1, 2]);
+        assert!(line_strip(&[Vec3::ZERO], WHITE).indices.is_empty());
+        assert!(line_strip(&[], WHITE).indices.is_empty());
+    }
+
+    #[test]
+    fn wire_box_edges() {
+        let min = Vec3::new(-1.0, -2.0, -3.0);
+        let max = Vec3::new(1.0, 2.0, 3.0);
+        let mesh = wire_box(min, max, WHITE);
+        assert_eq!(mesh.vertices.len(), 8);
+        assert_eq!(mesh.indices.len(), 24);
+
+        // every edge is parallel to an axis and spans the box
+        for (start, end) in line_positions(&mesh) {
+            let delta = (end - start).abs();
+            let size = max - min;
+            let axes = [delta.x == size.x, delta.y == size.y, delta.z == size.z];
+            assert_eq!(axes.iter().filter(|axis| **axis).count(), 1);
+            assert_eq!(delta.x + delta.y + delta.z, delta.max_element());
+        }
+    }
+
+    #[test]
+    fn wire_sphere_points_on_surface() {
+        let center = Vec3::new(1.0, 2.0, 3.0);
+        let mesh = wire_sphere(center, 2.0, 16, WHITE);
+        assert_eq!(mesh.vertices.len(), 48);
+        assert_eq!(mesh.indices.len(), 96);
+
+        for vertex in mesh.vertices.iter() {
+            let distance = vertex.position.distance(center);
+            assert!((distance - 2.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn wire_sphere_clamps_segments() {
+        let mesh = wire_sphere(Vec3::ZERO, 1.0, 0, WHITE);
+        assert_eq!(mesh.vertices.len(), 3 * MIN_SPHERE_SEGMENTS as usize);
+    }
+
+    #[test]
+    fn axes_follow_transform() {
+        let transform = glam::Mat4::from_translation(Vec3::new(0.0, 5.0, 0.0));
+        let shape = DebugDrawShape::Axes {
+            transform,
+            size: 2.0,
+        };
+
+        let lines = line_positions(&expand_shape(&shape));
+        let origin = Vec3::new(0.0, 5.0, 0.0);
+        assert_eq!(lines[0], (origin, Vec3::new(2.0, 5.0, 0.0)));
+        assert_eq!(lines[1], (origin, Vec3::new(0.0, 7.0, 0.0)));
+        assert_eq!(lines[2], (origin, Vec3::new(0.0, 5.0, 2.0)));
+    }
+
+    #[test]
+    fn append_offsets_indices() {
+        let mut mesh = line_strip(&[Vec3::ZERO, Vec3::X], WHITE);
+        append_mesh(&mut mesh, &line_strip(&[Vec3::Y, Vec3::Z], WHITE));
+        assert_eq!(mesh.indices, vec![0, 1, 2, 3]);
+    }
+}
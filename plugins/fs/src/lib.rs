@@ -19,11 +19,39 @@
 use hearth_runtime::{
     async_trait, cargo_process_metadata, hearth_schema::fs::*, process::ProcessMetadata, utils::*,
 };
-use std::fs::{read, read_dir};
-use std::path::{Component, PathBuf};
+use std::fs::{
+    create_dir, create_dir_all, hard_link, read, read_dir, remove_dir, remove_file, rename,
+    symlink_metadata, write,
+};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use hearth_runtime::hearth_schema::lump::{LumpMetadata, LumpOrigin};
+use hearth_runtime::tracing::warn;
+use reload::HotReload;
+use watch::PathWatcher;
+
+/// The metadata of lumps read from files.
+pub(crate) const FILESYSTEM: LumpMetadata = LumpMetadata {
+    content_type: None,
+    origin: Some(LumpOrigin::Filesystem),
+};
+
+pub mod reload;
+pub mod watch;
+
+/// A counter for naming temporary files uniquely within this process.
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
 
 pub struct FsPlugin {
     root: PathBuf,
+
+    /// Whether requests through this capability may modify the filesystem.
+    writable: bool,
+
+    /// Hot reloading of assets loaded from files, if enabled.
+    hot_reload: Option<Arc<HotReload>>,
 }
 
 #[async_trait]
@@ -35,6 +63,27 @@ impl RequestResponseProcess for FsPlugin {
         &'a mut self,
         request: &mut RequestInfo<'a, Request>,
     ) -> ResponseInfo<'a, Response> {
+        if let RequestKind::ReadOnly = request.data.kind {
+            let mut meta = cargo_process_metadata!();
+            meta.name = Some("ReadOnlyFilesystem".to_string());
+            meta.description =
+                Some("Read-only native filesystem access. Accepts FsRequest.".to_string());
+
+            let child = request.spawn(
+                meta,
+                Self {
+                    root: self.root.clone(),
+                    writable: false,
+                    hot_reload: self.hot_reload.clone(),
+                },
+            );
+
+            return ResponseInfo {
+                data: Ok(Success::ReadOnly),
+                caps: vec![child],
+            };
+        }
+
         ResponseInfo {
             data: self.handle_request(request).await,
             caps: vec![],
@@ -43,7 +92,7 @@ impl RequestResponseProcess for FsPlugin {
 }
 
 impl ServiceRunner for FsPlugin {
-    const NAME: &'static str = "hearth.fs.Filesystem";
+    const NAME: &'static str = FILESYSTEM_SERVICE;
 
     fn get_process_metadata() -> ProcessMetadata {
         let mut meta = cargo_process_metadata!();
@@ -54,46 +103,66 @@ impl ServiceRunner for FsPlugin {
 }
 
 impl FsPlugin {
+    /// Creates a filesystem service with read and write access to `root`.
     pub fn new(root: PathBuf) -> Self {
-        Self { root }
+        Self {
+            root,
+            writable: true,
+            hot_reload: None,
+        }
+    }
+
+    /// Creates a filesystem service that can only read from `root`.
+    pub fn read_only(root: PathBuf) -> Self {
+        Self {
+            root,
+            writable: false,
+            hot_reload: None,
+        }
+    }
+
+    /// Enables reloading the assets loaded from files that guests read when
+    /// those files change.
+    pub fn with_hot_reload(mut self) -> Self {
+        match HotReload::new(&self.root) {
+            Ok(hot_reload) => self.hot_reload = Some(hot_reload),
+            Err(err) => warn!("Failed to enable hot reloading: {:?}", err),
+        }
+
+        self
     }
 
     async fn handle_request<'a>(&'a mut self, request: &mut RequestInfo<'a, Request>) -> Response {
-        let target = PathBuf::try_from(&request.data.target).map_err(|_| Error::InvalidTarget)?;
+        let path = resolve_path(&self.root, &request.data.target)?;
+        check_confined(&self.root, &path)?;
 
-        let mut path = self.root.to_path_buf();
-        for component in target.components() {
-            match component {
-                Component::Normal(normal) => path.push(normal),
-                _ => return Err(Error::DirectoryTraversal),
-            }
+        let modifies = !matches!(
+            request.data.kind,
+            RequestKind::Get | RequestKind::List | RequestKind::Watch { .. }
+        );
+        if modifies && !self.writable {
+            return Err(Error::ReadOnly);
         }
 
-        let to_response_error = |err: std::io::Error| -> Error {
-            use std::io::ErrorKind::*;
-            match err.kind() {
-                NotFound => Error::NotFound,
-                PermissionDenied => Error::PermissionDenied,
-                e => Error::Other(e.to_string()),
-            }
-        };
+        // the root itself may not be replaced, deleted, or moved
+        if modifies && path == self.root {
+            return Err(Error::InvalidTarget);
+        }
 
-        match request.data.kind {
+        match &request.data.kind {
             RequestKind::Get => {
-                let contents = match read(path) {
-                    Ok(contents) => contents,
-                    Err(e) => return Err(to_response_error(e)),
-                };
-
-                let lump = request.runtime.lump_store.add_lump(contents.into()).await;
+                let contents = read(&path).map_err(to_response_error)?;
+                let lumps = &request.runtime.lump_store;
+                let lump = lumps.add_lump_with(contents.into(), FILESYSTEM).await;
 
+                if let Some(hot_reload) = self.hot_reload.as_ref() {
+                    let relative = watch::to_relative(path.strip_prefix(&self.root).unwrap());
+                    hot_reload.record(request.runtime, relative, lump);
+                }
                 Ok(Success::Get(lump))
             }
             RequestKind::List => {
-                let dirs = match read_dir(path) {
-                    Ok(dirs) => dirs,
-                    Err(e) => return Err(to_response_error(e)),
-                };
+                let dirs = read_dir(path).map_err(to_response_error)?;
 
                 let dirs: Vec<_> = dirs
                     .into_iter()
@@ -108,6 +177,319 @@ impl FsPlugin {
 
                 Ok(Success::List(dirs))
             }
+            RequestKind::Write(lump) => {
+                let data = request
+                    .runtime
+                    .lump_store
+                    .get_lump(lump)
+                    .await
+                    .ok_or(Error::LumpNotFound)?;
+
+                write_atomic(&path, &data).map_err(to_response_error)?;
+                Ok(Success::Done)
+            }
+            RequestKind::CreateDir => {
+                create_dir_all(path).map_err(to_response_error)?;
+                Ok(Success::Done)
+            }
+            RequestKind::Delete => {
+                if path.is_dir() {
+                    remove_dir(path).map_err(to_response_error)?;
+                } else {
+                    remove_file(path).map_err(to_response_error)?;
+                }
+
+                Ok(Success::Done)
+            }
+            RequestKind::Rename { to } => {
+                let to = resolve_path(&self.root, to)?;
+                check_confined(&self.root, &to)?;
+
+                if to == self.root {
+                    return Err(Error::InvalidTarget);
+                }
+
+                rename_no_replace(&path, &to).map_err(to_response_error)?;
+                Ok(Success::Done)
+            }
+            RequestKind::Watch { recursive } => {
+                let subscriber = request.cap_args.first().ok_or(Error::InvalidRequest)?;
+                let root = self.root.canonicalize().map_err(to_response_error)?;
+                let path = path.canonicalize().map_err(to_response_error)?;
+
+                // symlinks may lead outside of the root
+                if !path.starts_with(&root) {
+                    return Err(Error::DirectoryTraversal);
+                }
+
+                let mut meta = cargo_process_metadata!();
+                meta.name = Some("PathWatcher".to_string());
+                meta.description =
+                    Some("Sends filesystem changes to a watch subscriber.".to_string());
+
+                let watcher = PathWatcher {
+                    root,
+                    path,
+                    recursive: *recursive,
+                };
+
+                let watcher = request.spawn(meta, watcher);
+                watcher
+                    .send(&[], &[subscriber])
+                    .await
+                    .map_err(|err| Error::Other(format!("{:?}", err)))?;
+
+                Ok(Success::Watching)
+            }
+            RequestKind::ReadOnly => Err(Error::InvalidRequest),
+        }
+    }
+}
+
+/// Resolves a guest path against the filesystem root.
+///
+/// Only plain path components are allowed, so absolute paths, `..`, and `.`
+/// are all rejected.
+pub fn resolve_path(root: &Path, target: &str) -> Result<PathBuf, Error> {
+    let target = PathBuf::try_from(target).map_err(|_| Error::InvalidTarget)?;
+
+    let mut path = root.to_path_buf();
+    for component in target.components() {
+        match component {
+            Component::Normal(normal) => path.push(normal),
+            _ => return Err(Error::DirectoryTraversal),
+        }
+    }
+
+    Ok(path)
+}
+
+/// Checks that a resolved path doesn't lead outside of the root through
+/// symlinks.
+///
+/// The closest entry on the path that exists, which may be the path itself,
+/// must canonicalize to somewhere inside of the canonical root. Symlinks
+/// that can't be resolved are rejected too, since writing through a
+/// dangling symlink would create its target.
+pub fn check_confined(root: &Path, path: &Path) -> Result<(), Error> {
+    let root = root.canonicalize().map_err(to_response_error)?;
+
+    let Some(existing) = path
+        .ancestors()
+        .find(|ancestor| symlink_metadata(ancestor).is_ok())
+    else {
+        return Err(Error::NotFound);
+    };
+
+    let existing = existing
+        .canonicalize()
+        .map_err(|_| Error::DirectoryTraversal)?;
+
+    if existing.starts_with(&root) {
+        Ok(())
+    } else {
+        Err(Error::DirectoryTraversal)
+    }
+}
+
+/// Renames a file or directory, failing with [std::io::ErrorKind::AlreadyExists]
+/// instead of replacing the destination.
+///
+/// Checking for the destination before renaming would race with other
+/// writers, so the destination is claimed atomically first. Files are hard
+/// linked to it and then unlinked from their old path. Directories are
+/// renamed over an empty directory created in their place, which fails if
+/// anything was put inside of it in the meantime.
+pub fn rename_no_replace(from: &Path, to: &Path) -> std::io::Result<()> {
+    if symlink_metadata(from)?.is_dir() {
+        create_dir(to)?;
+
+        if let Err(err) = rename(from, to) {
+            let _ = remove_dir(to);
+            return Err(err);
+        }
+    } else {
+        hard_link(from, to)?;
+        remove_file(from)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a file by writing to a temporary file in the same directory and
+/// renaming it over the destination, so that readers and concurrent writers
+/// never observe a partially-written file.
+pub fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+
+    let temp_name = format!(
+        ".{}.{}.{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+
+    let temp = path.with_file_name(temp_name);
+    write(&temp, data)?;
+
+    if let Err(err) = rename(&temp, path) {
+        let _ = remove_file(&temp);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn to_response_error(err: std::io::Error) -> Error {
+    use std::io::ErrorKind::*;
+    match err.kind() {
+        NotFound => Error::NotFound,
+        PermissionDenied => Error::PermissionDenied,
+        AlreadyExists => Error::AlreadyExists,
+        e => Error::Other(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+    use std::thread;
+
+    /// Creates an empty, uniquely-named directory to test in.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hearth-fs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resolve_nested() {
+        let root = Path::new("/srv/root");
+        let path = resolve_path(root, "a/b/c.txt").unwrap();
+        assert_eq!(path, root.join("a").join("b").join("c.txt"));
+    }
+
+    #[test]
+    fn resolve_rejects_traversal() {
+        let root = Path::new("/srv/root");
+        for target in ["..", "../etc/passwd", "a/../../b", "./a", "/etc/passwd"] {
+            assert!(
+                matches!(resolve_path(root, target), Err(Error::DirectoryTraversal)),
+                "{:?} was not rejected",
+                target
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_cannot_escape_root() {
+        let dir = test_dir("symlinks");
+        let root = dir.join("root");
+        let outside = dir.join("outside");
+        create_dir_all(&root).unwrap();
+        create_dir_all(&outside).unwrap();
+        write(outside.join("secret.txt"), b"secret").unwrap();
+
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.join("missing"), root.join("dangling")).unwrap();
+        write(root.join("inside.txt"), b"inside").unwrap();
+
+        for target in ["escape", "escape/secret.txt", "escape/new.txt", "dangling"] {
+            let path = resolve_path(&root, target).unwrap();
+            assert!(
+                matches!(check_confined(&root, &path), Err(Error::DirectoryTraversal)),
+                "{:?} was not rejected",
+                target
+            );
+        }
+
+        for target in ["inside.txt", "new.txt", "new/nested.txt"] {
+            let path = resolve_path(&root, target).unwrap();
+            assert!(
+                check_confined(&root, &path).is_ok(),
+                "{:?} was rejected",
+                target
+            );
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rename_does_not_replace() {
+        let dir = test_dir("rename");
+        let (a, b, c) = (dir.join("a.txt"), dir.join("b.txt"), dir.join("c.txt"));
+        write(&a, b"a").unwrap();
+        write(&b, b"b").unwrap();
+
+        let err = rename_no_replace(&a, &b).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert_eq!(read(&a).unwrap(), b"a");
+        assert_eq!(read(&b).unwrap(), b"b");
+
+        rename_no_replace(&a, &c).unwrap();
+        assert!(!a.exists());
+        assert_eq!(read(&c).unwrap(), b"a");
+
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        create_dir_all(from.join("nested")).unwrap();
+        rename_no_replace(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert!(to.join("nested").is_dir());
+
+        let err = rename_no_replace(&to, &c).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(to.is_dir());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn write_replaces_contents() {
+        let dir = test_dir("replace");
+        let path = dir.join("file.txt");
+
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+        assert_eq!(read(&path).unwrap(), b"second");
+
+        // no temporary files are left behind
+        assert_eq!(read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn concurrent_writers() {
+        let dir = test_dir("concurrent");
+        let path = Arc::new(dir.join("file.bin"));
+
+        let writers: Vec<_> = (0..8u8)
+            .map(|index| {
+                let path = path.clone();
+                thread::spawn(move || {
+                    let data = vec![index; 64 * 1024];
+                    for _ in 0..16 {
+                        write_atomic(&path, &data).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
         }
+
+        // the file must contain exactly one writer's contents
+        let contents = read(path.as_ref()).unwrap();
+        assert_eq!(contents.len(), 64 * 1024);
+        assert!(contents.iter().all(|byte| *byte == contents[0]));
+        assert_eq!(read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
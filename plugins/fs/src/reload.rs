@@ -0,0 +1,152 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hot reloading of assets loaded from files in the filesystem root.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use hearth_runtime::{
+    hearth_schema::{fs::WatchEventKind, LumpId},
+    runtime::Runtime,
+    tokio::{self, sync::mpsc::unbounded_channel},
+    tracing::{debug, info, warn},
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::watch::{push_events, DEBOUNCE_WINDOW};
+use crate::FILESYSTEM;
+
+/// Tracks the files that guests have read and reloads the assets loaded
+/// from them when they change on disk.
+pub struct HotReload {
+    /// The canonicalized filesystem root.
+    root: PathBuf,
+
+    /// The latest lump read from each file, keyed by root-relative path.
+    files: Mutex<HashMap<String, LumpId>>,
+
+    /// The root's watcher, started when the first file is recorded.
+    watcher: Mutex<Option<RecommendedWatcher>>,
+}
+
+impl HotReload {
+    pub fn new(root: &Path) -> std::io::Result<Arc<Self>> {
+        Ok(Arc::new(Self {
+            root: root.canonicalize()?,
+            files: Default::default(),
+            watcher: Default::default(),
+        }))
+    }
+
+    /// Records that a file was read into a lump.
+    pub fn record(self: &Arc<Self>, runtime: &Arc<Runtime>, path: String, lump: LumpId) {
+        self.files.lock().unwrap().insert(path, lump);
+
+        let mut watcher = self.watcher.lock().unwrap();
+        if watcher.is_none() {
+            *watcher = self.watch(runtime.to_owned());
+        }
+    }
+
+    /// Starts watching the root for changes to recorded files.
+    fn watch(self: &Arc<Self>, runtime: Arc<Runtime>) -> Option<RecommendedWatcher> {
+        let (tx, mut rx) = unbounded_channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        });
+
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                warn!("Failed to create hot reload watcher: {:?}", err);
+                return None;
+            }
+        };
+
+        if let Err(err) = watcher.watch(&self.root, RecursiveMode::Recursive) {
+            warn!(
+                "Failed to watch {:?} for hot reloading: {:?}",
+                self.root, err
+            );
+            return None;
+        }
+
+        // the watcher is owned by self, so only hold a weak reference to
+        // let the watcher and this task stop when self is dropped
+        let reload = Arc::downgrade(self);
+        let root = self.root.clone();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                // wait for writes to the file to finish
+                tokio::time::sleep(DEBOUNCE_WINDOW).await;
+
+                let mut events = Vec::new();
+                push_events(&mut events, &root, event);
+                while let Ok(event) = rx.try_recv() {
+                    push_events(&mut events, &root, event);
+                }
+
+                let Some(reload) = reload.upgrade() else {
+                    break;
+                };
+
+                for event in events {
+                    if event.kind != WatchEventKind::Removed {
+                        reload.on_change(&runtime, &event.path).await;
+                    }
+                }
+            }
+        });
+
+        Some(watcher)
+    }
+
+    /// Reads a changed file into a new lump and reloads the assets loaded
+    /// from its previous lump.
+    async fn on_change(&self, runtime: &Runtime, path: &str) {
+        let Some(old) = self.files.lock().unwrap().get(path).copied() else {
+            return;
+        };
+
+        let data = match tokio::fs::read(self.root.join(path)).await {
+            Ok(data) => data,
+            Err(err) => {
+                warn!("Failed to read changed file {:?}: {:?}", path, err);
+                return;
+            }
+        };
+
+        let new = runtime
+            .lump_store
+            .add_lump_with(data.into(), FILESYSTEM)
+            .await;
+        if new == old {
+            return;
+        }
+
+        debug!("{:?} changed from {} to {}", path, old, new);
+        self.files.lock().unwrap().insert(path.to_string(), new);
+
+        let reloaded = runtime.asset_store.reload_all(&old, &new).await;
+        if reloaded > 0 {
+            info!("Reloaded {} assets from {:?}", reloaded, path);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Change notifications for watched paths.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hearth_runtime::{
+    async_trait,
+    flue::TableSignal,
+    hearth_schema::fs::{WatchEvent, WatchEventKind},
+    process::Process,
+    runtime::Runtime,
+    tokio::{self, sync::mpsc::unbounded_channel},
+    tracing::{debug, warn},
+    utils::ProcessRunner,
+};
+use notify::event::{ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+/// How long to wait for more changes after the first before sending events.
+pub const DEBOUNCE_WINDOW: Duration = Duration::from_millis(50);
+
+/// A process that watches a path and sends each change to a subscriber as a
+/// [WatchEvent].
+///
+/// The subscriber is the first capability of the first message this process
+/// receives. Watching stops once the subscriber goes down.
+pub struct PathWatcher {
+    /// The canonicalized filesystem root.
+    pub root: PathBuf,
+
+    /// The canonicalized path to watch.
+    pub path: PathBuf,
+
+    /// Whether to watch subdirectories too.
+    pub recursive: bool,
+}
+
+#[async_trait]
+impl ProcessRunner for PathWatcher {
+    async fn run(self, label: String, _runtime: Arc<Runtime>, ctx: &Process) {
+        let on_subscribe = |signal: TableSignal<'_>| match signal {
+            TableSignal::Message { caps, .. } => caps.first().copied(),
+            _ => None,
+        };
+
+        let Some(Some(subscriber)) = ctx.borrow_parent().recv(on_subscribe).await else {
+            warn!("{:?} was not given a subscriber", label);
+            return;
+        };
+
+        let table = ctx.borrow_table();
+        if let Err(err) = table.monitor(subscriber, ctx.borrow_parent()) {
+            warn!("{:?} failed to monitor its subscriber: {:?}", label, err);
+            return;
+        }
+
+        let subscriber = table.wrap_handle(subscriber).unwrap();
+
+        let (tx, mut rx) = unbounded_channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = tx.send(event);
+        });
+
+        let mut watcher = match watcher {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                warn!("{:?} failed to create watcher: {:?}", label, err);
+                return;
+            }
+        };
+
+        let mode = match self.recursive {
+            true => RecursiveMode::Recursive,
+            false => RecursiveMode::NonRecursive,
+        };
+
+        if let Err(err) = watcher.watch(&self.path, mode) {
+            warn!("{:?} failed to watch {:?}: {:?}", label, self.path, err);
+            return;
+        }
+
+        let is_down = |signal: TableSignal<'_>| matches!(signal, TableSignal::Down { .. });
+
+        loop {
+            tokio::select! {
+                signal = ctx.borrow_parent().recv(is_down) => {
+                    match signal {
+                        Some(false) => continue,
+                        Some(true) => debug!("{:?} subscriber is down", label),
+                        None => {} // killed
+                    }
+
+                    break;
+                }
+                event = rx.recv() => {
+                    let Some(event) = event else {
+                        break;
+                    };
+
+                    // give rapid successive changes a chance to arrive
+                    tokio::time::sleep(DEBOUNCE_WINDOW).await;
+
+                    let mut events = Vec::new();
+                    push_events(&mut events, &self.root, event);
+                    while let Ok(event) = rx.try_recv() {
+                        push_events(&mut events, &self.root, event);
+                    }
+
+                    for event in events {
+                        let data = serde_json::to_vec(&event).unwrap();
+                        if subscriber.send(&data, &[]).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Converts a notify event into [WatchEvent]s and appends them to a list of
+/// pending events.
+///
+/// Changes that duplicate a pending event are dropped, including
+/// modifications to newly-created paths. Paths outside of the root are
+/// skipped.
+pub fn push_events(events: &mut Vec<WatchEvent>, root: &Path, event: notify::Result<Event>) {
+    let event = match event {
+        Ok(event) => event,
+        Err(err) => {
+            debug!("watch error: {:?}", err);
+            return;
+        }
+    };
+
+    use WatchEventKind::*;
+    let kind_of = |index: usize| match event.kind {
+        EventKind::Create(_) => Some(Created),
+        EventKind::Modify(ModifyKind::Name(RenameMode::From)) => Some(Removed),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To)) => Some(Created),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if index == 0 => Some(Removed),
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => Some(Created),
+        EventKind::Modify(_) => Some(Modified),
+        EventKind::Remove(_) => Some(Removed),
+        _ => None,
+    };
+
+    for (index, path) in event.paths.iter().enumerate() {
+        let Some(kind) = kind_of(index) else {
+            continue;
+        };
+
+        let Ok(relative) = path.strip_prefix(root) else {
+            continue;
+        };
+
+        let path = to_relative(relative);
+
+        let duplicate = events.iter().any(|pending| {
+            pending.path == path
+                && (pending.kind == kind || (pending.kind == Created && kind == Modified))
+        });
+
+        if !duplicate {
+            events.push(WatchEvent { kind, path });
+        }
+    }
+}
+
+/// Formats a path relative to the filesystem root as a guest path.
+pub fn to_relative(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use notify::event::{CreateKind, DataChange, RemoveKind};
+
+    fn event(kind: EventKind, paths: &[&str]) -> notify::Result<Event> {
+        let event = paths.iter().fold(Event::new(kind), |event, path| {
+            event.add_path((*path).into())
+        });
+
+        Ok(event)
+    }
+
+    fn watch_event(kind: WatchEventKind, path: &str) -> WatchEvent {
+        WatchEvent {
+            kind,
+            path: path.to_string(),
+        }
+    }
+
+    #[test]
+    fn relative_paths() {
+        let mut events = Vec::new();
+        let kind = EventKind::Create(CreateKind::File);
+        push_events(
+            &mut events,
+            Path::new("/root"),
+            event(kind, &["/root/a/b.txt"]),
+        );
+        assert_eq!(
+            events,
+            vec![watch_event(WatchEventKind::Created, "a/b.txt")]
+        );
+    }
+
+    #[test]
+    fn outside_root_skipped() {
+        let mut events = Vec::new();
+        let kind = EventKind::Remove(RemoveKind::File);
+        push_events(
+            &mut events,
+            Path::new("/root"),
+            event(kind, &["/other/a.txt"]),
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn rename_both() {
+        let mut events = Vec::new();
+        let kind = EventKind::Modify(ModifyKind::Name(RenameMode::Both));
+        let paths = ["/root/old.txt", "/root/new.txt"];
+        push_events(&mut events, Path::new("/root"), event(kind, &paths));
+
+        assert_eq!(
+            events,
+            vec![
+                watch_event(WatchEventKind::Removed, "old.txt"),
+                watch_event(WatchEventKind::Created, "new.txt"),
+            ]
+        );
+    }
+
+    #[test]
+    fn coalesce_duplicates() {
+        let root = Path::new("/root");
+        let create = EventKind::Create(CreateKind::File);
+        let modify = EventKind::Modify(ModifyKind::Data(DataChange::Content));
+
+        let mut events = Vec::new();
+        push_events(&mut events, root, event(create, &["/root/a.txt"]));
+        push_events(&mut events, root, event(modify, &["/root/a.txt"]));
+        push_events(&mut events, root, event(modify, &["/root/b.txt"]));
+        push_events(&mut events, root, event(modify, &["/root/b.txt"]));
+
+        assert_eq!(
+            events,
+            vec![
+                watch_event(WatchEventKind::Created, "a.txt"),
+                watch_event(WatchEventKind::Modified, "b.txt"),
+            ]
+        );
+    }
+}
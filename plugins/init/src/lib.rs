@@ -19,13 +19,20 @@
 use std::{path::PathBuf, sync::Arc};
 
 use hearth_runtime::{
-    async_trait, cargo_process_metadata,
+    async_trait,
+    audit::AuditService,
+    cargo_process_metadata,
     flue::{OwnedCapability, Permissions, TableSignal},
-    hearth_schema::{registry::RegistryRequest, wasm::WasmSpawnInfo},
+    hearth_schema::{
+        audit::AUDIT_SERVICE,
+        init::InitConfig,
+        registry::RegistryRequest,
+        wasm::{WasmSpawnInfo, WasmSpawnResponse},
+    },
     process::{Process, ProcessMetadata},
     runtime::{Plugin, Runtime, RuntimeBuilder},
     tokio::{spawn, sync::oneshot::Sender},
-    utils::ProcessRunner,
+    utils::{ProcessRunner, ServiceRunner},
 };
 use tracing::{debug, warn};
 
@@ -78,7 +85,15 @@ impl Plugin for InitPlugin {
             builder.add_service(hook.service.clone(), meta, hook);
         }
 
+        let mut config: InitConfig = builder.load_config("init").unwrap_or_else(|err| {
+            debug!("Using default init config: {:?}", err);
+            InitConfig::default()
+        });
+
         builder.add_runner(move |runtime| {
+            // services are filtered by role, which only the runtime knows
+            config.role = runtime.config.role;
+
             spawn(async move {
                 debug!("Loading init system module");
                 let wasm_data = std::fs::read(self.init_path.clone()).unwrap();
@@ -87,6 +102,10 @@ impl Plugin for InitPlugin {
                 let spawn_info = WasmSpawnInfo {
                     lump: wasm_lump,
                     entrypoint: None,
+                    limits: Default::default(),
+                    // the init system keeps its services' state in memory
+                    keep_awake: true,
+                    allow: None,
                 };
 
                 debug!("Running init system");
@@ -126,13 +145,55 @@ impl Plugin for InitPlugin {
 
                 let spawner = parent.borrow_table().wrap_handle(spawner).unwrap();
 
+                // the init system is the only process given the audit log
+                // unless it's registered publicly
+                let mut meta = AuditService::get_process_metadata();
+                meta.name = Some(AUDIT_SERVICE.to_string());
+                let audit_process = runtime.process_factory.spawn(meta);
+                let audit = audit_process
+                    .borrow_parent()
+                    .export_to(Permissions::SEND, parent.borrow_table())
+                    .unwrap();
+
+                spawn({
+                    let runtime = runtime.clone();
+                    async move {
+                        AuditService
+                            .run(AUDIT_SERVICE.to_string(), runtime, &audit_process)
+                            .await;
+                    }
+                });
+
                 spawner
                     .send(
                         &serde_json::to_vec(&spawn_info).unwrap(),
-                        &[&response_cap, &registry],
+                        &[&response_cap, &registry, &audit],
                     )
                     .await
                     .unwrap();
+
+                let init = response
+                    .recv(|signal| {
+                        let TableSignal::Message { data, caps } = signal else {
+                            panic!("expected message, got {:?}", signal);
+                        };
+
+                        let response: WasmSpawnResponse = serde_json::from_slice(data).unwrap();
+
+                        if let Err(err) = response {
+                            panic!("failed to spawn init system: {}", err);
+                        }
+
+                        caps[0]
+                    })
+                    .await
+                    .unwrap();
+
+                // the init system's first message is its config
+                let init = parent.borrow_table().wrap_handle(init).unwrap();
+                init.send(&serde_json::to_vec(&config).unwrap(), &[])
+                    .await
+                    .unwrap();
             });
         });
     }
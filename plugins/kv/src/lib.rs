@@ -0,0 +1,416 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use hearth_runtime::{
+    async_trait, cargo_process_metadata,
+    hearth_schema::kv::*,
+    process::ProcessMetadata,
+    runtime::{Plugin, RuntimeBuilder},
+    tracing::{debug, error, info, warn},
+    utils::{RequestInfo, RequestResponseProcess, ResponseInfo, RunnerContext, ServiceRunner},
+};
+use serde::Deserialize;
+
+use store::{Flush, Store};
+
+/// The log-structured storage backing the key-value store.
+pub mod store;
+
+/// Configuration for the key-value store.
+///
+/// Loaded from the `kv` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct KvConfig {
+    /// The path to the store's log file. Defaults to `kv.log` in Hearth's
+    /// data directory.
+    pub path: Option<PathBuf>,
+
+    /// The largest value in bytes that may be stored.
+    pub max_value_size: usize,
+}
+
+impl Default for KvConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_value_size: 64 * 1024,
+        }
+    }
+}
+
+/// A plugin that provides a persistent key-value store to guests.
+///
+/// Adds the [KvStoreFactory] service.
+#[derive(Default)]
+pub struct KvPlugin;
+
+impl Plugin for KvPlugin {
+    fn build(&mut self, builder: &mut RuntimeBuilder) {
+        let config: KvConfig = builder.load_config("kv").unwrap_or_else(|err| {
+            debug!("Using default key-value store config: {}", err);
+            KvConfig::default()
+        });
+
+        let path = config
+            .path
+            .clone()
+            .unwrap_or_else(|| hearth_runtime::get_data_dir().join("kv.log"));
+
+        let store = match Store::open(path.clone()) {
+            Ok(store) => {
+                info!("Opened key-value store at {:?}", path);
+                store
+            }
+            Err(err) => {
+                error!("Failed to open key-value store at {:?}: {:?}", path, err);
+                warn!("Keeping the key-value store in memory only");
+                Store::in_memory()
+            }
+        };
+
+        builder.add_plugin(KvStoreFactory {
+            store: Arc::new(Mutex::new(store)),
+            max_value_size: config.max_value_size,
+        });
+    }
+}
+
+/// Creates and opens [KvNamespace] processes on request.
+pub struct KvStoreFactory {
+    store: Arc<Mutex<Store>>,
+    max_value_size: usize,
+}
+
+#[async_trait]
+impl RequestResponseProcess for KvStoreFactory {
+    type Request = KvFactoryRequest;
+    type Response = KvFactoryResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, Self::Request>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let key = match &request.data {
+            KvFactoryRequest::CreateNamespace => {
+                // unguessable, so that namespaces can only be opened by whoever
+                // created them or was given the key
+                let key = format!("{:032x}", rand::random::<u128>());
+                let flush = self.store.lock().unwrap().create_namespace(&key);
+
+                if let Err(err) = flush.wait().await {
+                    return KvError::StorageError(err.to_string()).into();
+                }
+
+                key
+            }
+            KvFactoryRequest::OpenNamespace { key } => {
+                if !self.store.lock().unwrap().has_namespace(key) {
+                    return KvError::UnknownNamespace.into();
+                }
+
+                key.clone()
+            }
+        };
+
+        let mut meta = cargo_process_metadata!();
+        meta.name = Some("KvNamespace".to_string());
+
+        let child = request.spawn(
+            meta,
+            KvNamespace {
+                namespace: key.clone(),
+                store: self.store.clone(),
+                max_value_size: self.max_value_size,
+            },
+        );
+
+        ResponseInfo {
+            data: Ok(key),
+            caps: vec![child],
+        }
+    }
+}
+
+impl ServiceRunner for KvStoreFactory {
+    const NAME: &'static str = KV_STORE_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        cargo_process_metadata!()
+    }
+}
+
+/// Serves [KvRequest] on a single namespace of the store.
+pub struct KvNamespace {
+    namespace: String,
+    store: Arc<Mutex<Store>>,
+    max_value_size: usize,
+}
+
+impl KvNamespace {
+    fn check_size(&self, value: &[u8]) -> Result<(), KvError> {
+        if value.len() > self.max_value_size {
+            Err(KvError::ValueTooLarge {
+                size: value.len(),
+                max: self.max_value_size,
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn handle(&self, request: &KvRequest) -> KvResponse {
+        let (success, flush) = self.apply(request)?;
+
+        if let Some(flush) = flush {
+            // the store's lock is released before waiting for the write
+            flush
+                .wait()
+                .await
+                .map_err(|err| KvError::StorageError(err.to_string()))?;
+        }
+
+        Ok(success)
+    }
+
+    /// Performs a request in memory. Returns the write to wait for if it
+    /// changed the store.
+    fn apply(&self, request: &KvRequest) -> Result<(KvSuccess, Option<Flush>), KvError> {
+        let ns = self.namespace.as_str();
+        let mut store = self.store.lock().unwrap();
+
+        use KvRequest::*;
+        let flush = match request {
+            Get { key } => {
+                let value = store.get(ns, key).map(<[u8]>::to_vec);
+                return Ok((KvSuccess::Value(value), None));
+            }
+            List { prefix } => return Ok((KvSuccess::Keys(store.list(ns, prefix)), None)),
+            Set { key, value } => {
+                self.check_size(value)?;
+                store.set(ns, key, value)
+            }
+            Delete { key } => store.delete(ns, key),
+            CompareAndSwap { key, expected, new } => {
+                let current = store.get(ns, key);
+                if current != expected.as_deref() {
+                    let current = current.map(<[u8]>::to_vec);
+                    return Err(KvError::CompareFailed { current });
+                }
+
+                match new {
+                    Some(new) => {
+                        self.check_size(new)?;
+                        store.set(ns, key, new)
+                    }
+                    None => store.delete(ns, key),
+                }
+            }
+        };
+
+        Ok((KvSuccess::Done, Some(flush)))
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for KvNamespace {
+    type Request = KvRequest;
+    type Response = KvResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, Self::Request>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        self.handle(&request.data).await.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_runtime::{
+        flue::CapabilityRef,
+        testing::{TestRuntime, TestRuntimeBuilder},
+    };
+
+    fn runtime(path: &std::path::Path, max_value_size: usize) -> TestRuntime {
+        let mut kv = toml::Table::new();
+        kv.insert("path".into(), path.to_str().unwrap().into());
+        kv.insert("max_value_size".into(), (max_value_size as i64).into());
+        let mut config = toml::Table::new();
+        config.insert("kv".into(), kv.into());
+
+        let mut builder = TestRuntimeBuilder::with_config(config);
+        builder.add_plugin(KvPlugin);
+        builder.build()
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hearth-kv-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("store.log")
+    }
+
+    fn factory_request<'a>(
+        runtime: &'a TestRuntime,
+        request: KvFactoryRequest,
+    ) -> (KvFactoryResponse, Vec<CapabilityRef<'a>>) {
+        let factory = runtime.get_service(KV_STORE_SERVICE).unwrap();
+        runtime.request(&factory, &request, &[])
+    }
+
+    fn create(runtime: &TestRuntime) -> (CapabilityRef<'_>, String) {
+        let (result, mut caps) = factory_request(runtime, KvFactoryRequest::CreateNamespace);
+        (caps.remove(0), result.unwrap())
+    }
+
+    fn open<'a>(runtime: &'a TestRuntime, key: &str) -> CapabilityRef<'a> {
+        let request = KvFactoryRequest::OpenNamespace {
+            key: key.to_string(),
+        };
+
+        let (result, mut caps) = factory_request(runtime, request);
+        assert_eq!(result.as_deref(), Ok(key));
+        caps.remove(0)
+    }
+
+    fn request(runtime: &TestRuntime, ns: &CapabilityRef, request: KvRequest) -> KvResponse {
+        runtime.request(ns, &request, &[]).0
+    }
+
+    fn set(key: &str, value: &str) -> KvRequest {
+        KvRequest::Set {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    fn get(key: &str) -> KvRequest {
+        KvRequest::Get { key: key.into() }
+    }
+
+    fn value(value: &str) -> KvResponse {
+        Ok(KvSuccess::Value(Some(value.into())))
+    }
+
+    #[test]
+    fn persists_across_restart() {
+        let path = temp_path("restart");
+
+        let first = runtime(&path, 1024);
+        let (ns, key) = create(&first);
+        assert_eq!(
+            request(&first, &ns, set("theme", "dark")),
+            Ok(KvSuccess::Done)
+        );
+        drop(ns);
+        drop(first);
+
+        let second = runtime(&path, 1024);
+        let ns = open(&second, &key);
+        assert_eq!(request(&second, &ns, get("theme")), value("dark"));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn namespaces_are_isolated() {
+        let path = temp_path("isolated");
+        let runtime = runtime(&path, 1024);
+
+        let (a, a_key) = create(&runtime);
+        let (b, b_key) = create(&runtime);
+        assert_ne!(a_key, b_key);
+        request(&runtime, &a, set("key", "from a")).unwrap();
+
+        assert_eq!(
+            request(&runtime, &b, get("key")),
+            Ok(KvSuccess::Value(None))
+        );
+        let list = KvRequest::List { prefix: vec![] };
+        assert_eq!(request(&runtime, &b, list), Ok(KvSuccess::Keys(vec![])));
+        assert_eq!(request(&runtime, &a, get("key")), value("from a"));
+
+        // namespaces can only be opened by their keys
+        let a = open(&runtime, &a_key);
+        assert_eq!(request(&runtime, &a, get("key")), value("from a"));
+
+        let request = KvFactoryRequest::OpenNamespace {
+            key: "a".to_string(),
+        };
+
+        let (result, caps) = factory_request(&runtime, request);
+        assert_eq!(result, Err(KvError::UnknownNamespace));
+        assert!(caps.is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejects_large_values() {
+        let path = temp_path("large");
+        let runtime = runtime(&path, 4);
+        let (ns, _) = create(&runtime);
+
+        let result = request(&runtime, &ns, set("key", "too long"));
+        assert_eq!(result, Err(KvError::ValueTooLarge { size: 8, max: 4 }));
+        assert_eq!(
+            request(&runtime, &ns, get("key")),
+            Ok(KvSuccess::Value(None))
+        );
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn compare_and_swap() {
+        let path = temp_path("cas");
+        let runtime = runtime(&path, 1024);
+        let (ns, _) = create(&runtime);
+
+        let cas = |expected: Option<&str>, new: Option<&str>| KvRequest::CompareAndSwap {
+            key: "lock".into(),
+            expected: expected.map(Into::into),
+            new: new.map(Into::into),
+        };
+
+        assert_eq!(
+            request(&runtime, &ns, cas(None, Some("a"))),
+            Ok(KvSuccess::Done)
+        );
+
+        let failed = request(&runtime, &ns, cas(None, Some("b")));
+        let current = Some("a".into());
+        assert_eq!(failed, Err(KvError::CompareFailed { current }));
+
+        assert_eq!(
+            request(&runtime, &ns, cas(Some("a"), None)),
+            Ok(KvSuccess::Done)
+        );
+        assert_eq!(
+            request(&runtime, &ns, get("lock")),
+            Ok(KvSuccess::Value(None))
+        );
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,578 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! A log-structured, in-memory indexed key-value store.
+//!
+//! Every change is appended to a single log file as a binary record and the
+//! whole log is replayed into memory on open. Once the log is mostly made of
+//! overwritten records, it's compacted by rewriting only the live entries.
+//!
+//! Changes are applied in memory right away and queued, in order, for a
+//! dedicated thread that writes the log, so that the store never does file
+//! I/O itself. Each change returns a [Flush] to wait for it to be written.
+
+use std::collections::{hash_map::Entry, BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, ErrorKind, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+use hearth_runtime::tokio::sync::oneshot;
+use hearth_runtime::tracing::warn;
+
+/// The size of a record's header: an opcode and three little-endian `u32`
+/// lengths for the namespace, key, and value.
+const HEADER_SIZE: usize = 13;
+
+/// The minimum log size in bytes before compaction is considered.
+const COMPACT_MIN_SIZE: u64 = 1024 * 1024;
+
+const OP_SET: u8 = 1;
+const OP_DELETE: u8 = 2;
+const OP_CREATE: u8 = 3;
+
+/// A namespaced key-value store, optionally persisted to a log file.
+#[derive(Default)]
+pub struct Store {
+    namespaces: HashMap<String, BTreeMap<Vec<u8>, Vec<u8>>>,
+    log: Option<LogWriter>,
+
+    /// The size the log will be once every queued write is done.
+    log_size: u64,
+
+    /// The size the log would be if it only contained live entries.
+    live_size: u64,
+}
+
+impl Store {
+    /// Creates a store that isn't persisted.
+    pub fn in_memory() -> Self {
+        Self::default()
+    }
+
+    /// Opens the store persisted at the given path, creating it if needed.
+    ///
+    /// A torn record at the end of the log, left by a crash mid-write, is
+    /// discarded. Fails without changing the log if any other part of it is
+    /// corrupt, so that no data is lost.
+    pub fn open(path: PathBuf) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&path)?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        let mut store = Self::default();
+        let mut offset = 0;
+        loop {
+            match Record::decode(&data[offset..]) {
+                Decoded::Record(record, len) => {
+                    store.apply(record);
+                    offset += len;
+                }
+                Decoded::End => break,
+                Decoded::Torn => {
+                    warn!(
+                        "Discarding a torn record of {} bytes at the end of {:?}",
+                        data.len() - offset,
+                        path
+                    );
+
+                    file.set_len(offset as u64)?;
+                    break;
+                }
+                Decoded::Invalid => {
+                    let msg = format!("corrupt record at byte {} of {:?}", offset, path);
+                    return Err(std::io::Error::new(ErrorKind::InvalidData, msg));
+                }
+            }
+        }
+
+        store.log_size = offset as u64;
+        store.log = Some(LogWriter::spawn(Log { path, file })?);
+        Ok(store)
+    }
+
+    /// Tests if a namespace exists.
+    pub fn has_namespace(&self, namespace: &str) -> bool {
+        self.namespaces.contains_key(namespace)
+    }
+
+    /// Creates an empty namespace. Does nothing if it already exists.
+    pub fn create_namespace(&mut self, namespace: &str) -> Flush {
+        if self.has_namespace(namespace) {
+            return Flush::done();
+        }
+
+        self.write(Record {
+            op: OP_CREATE,
+            namespace: namespace.as_bytes(),
+            key: &[],
+            value: &[],
+        })
+    }
+
+    /// Gets the value of a key.
+    pub fn get(&self, namespace: &str, key: &[u8]) -> Option<&[u8]> {
+        self.namespaces
+            .get(namespace)
+            .and_then(|entries| entries.get(key))
+            .map(Vec::as_slice)
+    }
+
+    /// Lists the keys in a namespace beginning with a prefix, in order.
+    pub fn list(&self, namespace: &str, prefix: &[u8]) -> Vec<Vec<u8>> {
+        let Some(entries) = self.namespaces.get(namespace) else {
+            return Vec::new();
+        };
+
+        entries
+            .range(prefix.to_vec()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(prefix))
+            .cloned()
+            .collect()
+    }
+
+    /// Sets the value of a key.
+    pub fn set(&mut self, namespace: &str, key: &[u8], value: &[u8]) -> Flush {
+        self.write(Record {
+            op: OP_SET,
+            namespace: namespace.as_bytes(),
+            key,
+            value,
+        })
+    }
+
+    /// Deletes a key. Does nothing if the key doesn't exist.
+    pub fn delete(&mut self, namespace: &str, key: &[u8]) -> Flush {
+        if self.get(namespace, key).is_none() {
+            return Flush::done();
+        }
+
+        self.write(Record {
+            op: OP_DELETE,
+            namespace: namespace.as_bytes(),
+            key,
+            value: &[],
+        })
+    }
+
+    /// Applies a record in memory, then queues it to be persisted.
+    fn write(&mut self, record: Record) -> Flush {
+        let Some(log) = self.log.as_ref() else {
+            self.apply(record);
+            return Flush::done();
+        };
+
+        let mut data = Vec::new();
+        record.encode(&mut data);
+        self.log_size += data.len() as u64;
+        let flush = log.queue(LogOp::Append(data));
+
+        self.apply(record);
+        self.maybe_compact().unwrap_or(flush)
+    }
+
+    fn apply(&mut self, record: Record) {
+        let Ok(namespace) = std::str::from_utf8(record.namespace) else {
+            return;
+        };
+
+        let entries = match self.namespaces.entry(namespace.to_string()) {
+            Entry::Occupied(entries) => entries.into_mut(),
+            Entry::Vacant(entries) => {
+                self.live_size += (HEADER_SIZE + namespace.len()) as u64;
+                entries.insert(BTreeMap::new())
+            }
+        };
+
+        let entry_size = |key: &[u8], value: &[u8]| {
+            (HEADER_SIZE + namespace.len() + key.len() + value.len()) as u64
+        };
+
+        let old = match record.op {
+            OP_SET => entries.insert(record.key.to_vec(), record.value.to_vec()),
+            OP_DELETE => entries.remove(record.key),
+            _ => None,
+        };
+
+        if let Some(old) = old {
+            self.live_size -= entry_size(record.key, &old);
+        }
+
+        if record.op == OP_SET {
+            self.live_size += entry_size(record.key, record.value);
+        }
+    }
+
+    /// Queues a rewrite of the log with only live entries once it's grown
+    /// large and mostly stale.
+    fn maybe_compact(&mut self) -> Option<Flush> {
+        let log = self.log.as_ref()?;
+
+        if self.log_size < COMPACT_MIN_SIZE || self.log_size < self.live_size * 2 {
+            return None;
+        }
+
+        let mut data = Vec::new();
+        for (namespace, entries) in self.namespaces.iter() {
+            let namespace = namespace.as_bytes();
+            let create = Record {
+                op: OP_CREATE,
+                namespace,
+                key: &[],
+                value: &[],
+            };
+
+            create.encode(&mut data);
+
+            for (key, value) in entries.iter() {
+                let set = Record {
+                    op: OP_SET,
+                    namespace,
+                    key,
+                    value,
+                };
+
+                set.encode(&mut data);
+            }
+        }
+
+        self.log_size = data.len() as u64;
+        Some(log.queue(LogOp::Rewrite(data)))
+    }
+}
+
+/// Waits for a change to a [Store] to be written to its log.
+pub struct Flush(Option<oneshot::Receiver<std::io::Result<()>>>);
+
+impl Flush {
+    /// A flush of a change that doesn't need to be written.
+    fn done() -> Self {
+        Self(None)
+    }
+
+    /// Waits for the change and every change before it to be written.
+    pub async fn wait(self) -> std::io::Result<()> {
+        match self.0 {
+            Some(rx) => rx.await.unwrap_or_else(|_| Err(writer_exited())),
+            None => Ok(()),
+        }
+    }
+
+    /// Like [Self::wait], but blocks the current thread.
+    pub fn blocking_wait(self) -> std::io::Result<()> {
+        match self.0 {
+            Some(rx) => rx.blocking_recv().unwrap_or_else(|_| Err(writer_exited())),
+            None => Ok(()),
+        }
+    }
+}
+
+fn writer_exited() -> std::io::Error {
+    std::io::Error::new(ErrorKind::BrokenPipe, "key-value log writer exited")
+}
+
+/// A single change to the store.
+struct Record<'a> {
+    op: u8,
+    namespace: &'a [u8],
+    key: &'a [u8],
+    value: &'a [u8],
+}
+
+/// The result of decoding a [Record].
+enum Decoded<'a> {
+    /// A whole record and its length.
+    Record(Record<'a>, usize),
+
+    /// There's no more data.
+    End,
+
+    /// The data ends partway through a record.
+    Torn,
+
+    /// The data isn't a valid record.
+    Invalid,
+}
+
+impl<'a> Record<'a> {
+    /// Decodes a record from the start of `data`.
+    fn decode(data: &'a [u8]) -> Decoded<'a> {
+        let Some(&op) = data.first() else {
+            return Decoded::End;
+        };
+
+        if op != OP_SET && op != OP_DELETE && op != OP_CREATE {
+            return Decoded::Invalid;
+        }
+
+        let Some(header) = data.get(..HEADER_SIZE) else {
+            return Decoded::Torn;
+        };
+
+        let len = |at: usize| u32::from_le_bytes(header[at..at + 4].try_into().unwrap()) as usize;
+        let ns_end = HEADER_SIZE + len(1);
+        let key_end = ns_end + len(5);
+        let value_end = key_end + len(9);
+
+        if value_end > data.len() {
+            return Decoded::Torn;
+        }
+
+        let record = Self {
+            op,
+            namespace: &data[HEADER_SIZE..ns_end],
+            key: &data[ns_end..key_end],
+            value: &data[key_end..value_end],
+        };
+
+        if std::str::from_utf8(record.namespace).is_err() {
+            return Decoded::Invalid;
+        }
+
+        Decoded::Record(record, value_end)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        out.push(self.op);
+
+        for field in [self.namespace, self.key, self.value] {
+            out.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        }
+
+        for field in [self.namespace, self.key, self.value] {
+            out.extend_from_slice(field);
+        }
+    }
+}
+
+/// A queued change to a [Log].
+enum LogOp {
+    /// Appends encoded records.
+    Append(Vec<u8>),
+
+    /// Replaces the whole log with encoded records.
+    Rewrite(Vec<u8>),
+}
+
+/// A handle to the thread writing a [Store]'s log. The thread exits once
+/// the handle is dropped and every queued change is written.
+struct LogWriter {
+    ops: mpsc::Sender<(LogOp, oneshot::Sender<std::io::Result<()>>)>,
+}
+
+impl LogWriter {
+    fn spawn(mut log: Log) -> std::io::Result<Self> {
+        let (ops, ops_rx) = mpsc::channel::<(LogOp, oneshot::Sender<_>)>();
+
+        std::thread::Builder::new()
+            .name("kv-log".to_string())
+            .spawn(move || {
+                for (op, done) in ops_rx {
+                    let result = match op {
+                        LogOp::Append(data) => log.append(&data),
+                        LogOp::Rewrite(data) => log.rewrite(&data),
+                    };
+
+                    if let Err(err) = result.as_ref() {
+                        warn!("Failed to write key-value log {:?}: {:?}", log.path, err);
+                    }
+
+                    let _ = done.send(result);
+                }
+            })?;
+
+        Ok(Self { ops })
+    }
+
+    fn queue(&self, op: LogOp) -> Flush {
+        let (tx, rx) = oneshot::channel();
+        let _ = self.ops.send((op, tx));
+        Flush(Some(rx))
+    }
+}
+
+/// The append-only file backing a [Store].
+struct Log {
+    path: PathBuf,
+    file: File,
+}
+
+impl Log {
+    fn append(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(data)?;
+        self.file.flush()
+    }
+
+    /// Atomically replaces the log with the given records.
+    fn rewrite(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut tmp_path = self.path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+        tmp.write_all(data)?;
+        tmp.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("hearth-kv-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("store.log")
+    }
+
+    #[test]
+    fn persists_across_reopen() {
+        let path = temp_path("reopen");
+
+        let mut store = Store::open(path.clone()).unwrap();
+        store.set("ns", b"kept", b"1").blocking_wait().unwrap();
+        store.set("ns", b"changed", b"old").blocking_wait().unwrap();
+        store.set("ns", b"changed", b"new").blocking_wait().unwrap();
+        store
+            .set("ns", b"deleted", b"gone")
+            .blocking_wait()
+            .unwrap();
+        store.delete("ns", b"deleted").blocking_wait().unwrap();
+        drop(store);
+
+        let store = Store::open(path.clone()).unwrap();
+        assert_eq!(store.get("ns", b"kept"), Some(&b"1"[..]));
+        assert_eq!(store.get("ns", b"changed"), Some(&b"new"[..]));
+        assert_eq!(store.get("ns", b"deleted"), None);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn discards_torn_record() {
+        let path = temp_path("torn");
+
+        let mut store = Store::open(path.clone()).unwrap();
+        store.set("ns", b"whole", b"value").blocking_wait().unwrap();
+        drop(store);
+
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[OP_SET, 2, 0]).unwrap();
+        drop(file);
+
+        let mut store = Store::open(path.clone()).unwrap();
+        assert_eq!(store.get("ns", b"whole"), Some(&b"value"[..]));
+
+        // new records must not be appended after the garbage
+        store.set("ns", b"after", b"crash").blocking_wait().unwrap();
+        drop(store);
+        let store = Store::open(path.clone()).unwrap();
+        assert_eq!(store.get("ns", b"after"), Some(&b"crash"[..]));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn refuses_corrupt_log() {
+        let path = temp_path("corrupt");
+
+        let mut store = Store::open(path.clone()).unwrap();
+        store.set("ns", b"first", b"1").blocking_wait().unwrap();
+        store.set("ns", b"second", b"2").blocking_wait().unwrap();
+        drop(store);
+
+        // corrupt the opcode of the first record
+        let mut data = std::fs::read(&path).unwrap();
+        data[0] = 0xff;
+        std::fs::write(&path, &data).unwrap();
+
+        let err = Store::open(path.clone()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn empty_namespaces_persist() {
+        let path = temp_path("namespaces");
+
+        let mut store = Store::open(path.clone()).unwrap();
+        store.create_namespace("ns").blocking_wait().unwrap();
+        assert!(store.has_namespace("ns"));
+        assert!(!store.has_namespace("other"));
+        drop(store);
+
+        let store = Store::open(path.clone()).unwrap();
+        assert!(store.has_namespace("ns"));
+        assert!(store.list("ns", b"").is_empty());
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn list_by_prefix() {
+        let mut store = Store::in_memory();
+        for key in ["a", "ab", "abc", "b", "aa"] {
+            store
+                .set("ns", key.as_bytes(), b"")
+                .blocking_wait()
+                .unwrap();
+        }
+
+        store.set("other", b"ab", b"").blocking_wait().unwrap();
+
+        assert_eq!(store.list("ns", b"ab"), [b"ab".to_vec(), b"abc".to_vec()]);
+        assert_eq!(store.list("ns", b"").len(), 5);
+        assert!(store.list("missing", b"").is_empty());
+    }
+
+    #[test]
+    fn compacts_stale_log() {
+        let path = temp_path("compact");
+        let value = vec![0u8; 64 * 1024];
+
+        let mut store = Store::open(path.clone()).unwrap();
+        for _ in 0..64 {
+            store.set("ns", b"key", &value).blocking_wait().unwrap();
+        }
+
+        let size = std::fs::metadata(&path).unwrap().len();
+        assert!(size < COMPACT_MIN_SIZE, "log wasn't compacted: {}", size);
+        drop(store);
+
+        let store = Store::open(path.clone()).unwrap();
+        assert_eq!(store.get("ns", b"key"), Some(value.as_slice()));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
@@ -16,20 +16,49 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use chacha20::cipher::Unsigned;
 use opaque_ke::errors::*;
 use opaque_ke::*;
 use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 /// The 64-byte key generated by the authentication step.
 pub type SessionKey = [u8; 64];
 
+/// The result of a successful login.
+#[derive(Clone, Debug)]
+pub struct Authenticated {
+    /// The name of the user that logged in.
+    pub user: String,
+
+    /// The session key shared between the client and the server.
+    pub session_key: SessionKey,
+}
+
+/// The serialized form of a [ServerAuthenticator]'s user table.
+///
+/// All fields are base64-encoded OPAQUE server data, so this never contains
+/// plaintext passwords. However, the setup is a server secret and must be
+/// kept private.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UserFile {
+    /// The server's OPAQUE setup.
+    pub setup: String,
+
+    /// A map of user names to their OPAQUE password registration.
+    pub users: HashMap<String, String>,
+}
+
 #[derive(Debug)]
 pub enum AuthenticationError {
     IoError(std::io::Error),
     ProtocolError(ProtocolError),
     InternalError(InternalError),
+    InvalidUserFile(String),
 }
 
 impl From<std::io::Error> for AuthenticationError {
@@ -63,32 +92,92 @@ pub struct ServerListener {}
 
 pub struct ServerAuthenticator {
     setup: ServerSetup<CS>,
-    registration: ServerRegistration<CS>,
+    users: HashMap<String, ServerRegistration<CS>>,
+}
+
+impl Default for ServerAuthenticator {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ServerAuthenticator {
+    /// Creates an authenticator with a fresh setup and no users.
+    pub fn new() -> Self {
+        Self {
+            setup: ServerSetup::new(&mut OsRng),
+            users: HashMap::new(),
+        }
+    }
+
+    /// Creates an authenticator with a single anonymous user (an empty user
+    /// name) with the given password.
     pub fn from_password(pw: &[u8]) -> Result<Self, AuthenticationError> {
+        let mut auth = Self::new();
+        auth.add_user("", pw)?;
+        Ok(auth)
+    }
+
+    /// Loads an authenticator from a user file.
+    pub fn from_user_file(file: &UserFile) -> Result<Self, AuthenticationError> {
+        let decode = |name: &str, data: &str| {
+            BASE64
+                .decode(data)
+                .map_err(|err| AuthenticationError::InvalidUserFile(format!("{}: {}", name, err)))
+        };
+
+        let setup = ServerSetup::deserialize(&decode("setup", &file.setup)?)?;
+        let mut users = HashMap::with_capacity(file.users.len());
+        for (name, registration) in file.users.iter() {
+            let registration = ServerRegistration::deserialize(&decode(name, registration)?)?;
+            users.insert(name.to_owned(), registration);
+        }
+
+        Ok(Self { setup, users })
+    }
+
+    /// Serializes this authenticator's user table to a user file.
+    pub fn to_user_file(&self) -> UserFile {
+        UserFile {
+            setup: BASE64.encode(self.setup.serialize()),
+            users: self
+                .users
+                .iter()
+                .map(|(name, registration)| {
+                    (name.to_owned(), BASE64.encode(registration.serialize()))
+                })
+                .collect(),
+        }
+    }
+
+    /// Adds a user or replaces an existing user's password.
+    pub fn add_user(&mut self, user: &str, pw: &[u8]) -> Result<(), AuthenticationError> {
         let mut rng = OsRng;
         let client_start = ClientRegistration::start(&mut rng, pw)?;
-        let setup = ServerSetup::new(&mut rng);
-        let cred_id = b"";
-        let server_start = ServerRegistration::start(&setup, client_start.message, cred_id)?;
+        let cred_id = user.as_bytes();
+        let server_start = ServerRegistration::start(&self.setup, client_start.message, cred_id)?;
         let client_finish =
             client_start
                 .state
                 .finish(&mut rng, pw, server_start.message, Default::default())?;
         let registration = ServerRegistration::finish(client_finish.message);
-
-        Ok(Self {
-            setup,
-            registration,
-        })
+        self.users.insert(user.to_string(), registration);
+        Ok(())
     }
 
+    /// Performs the server side of a login.
+    ///
+    /// Logins to unknown users proceed with a fake registration so that to
+    /// the client, they are indistinguishable from a wrong password.
     pub async fn login<T: AsyncRead + AsyncWrite + Unpin>(
         &self,
         client: &mut T,
-    ) -> Result<SessionKey, AuthenticationError> {
+    ) -> Result<Authenticated, AuthenticationError> {
+        let user_len = client.read_u8().await?;
+        let mut user = vec![0u8; user_len as usize];
+        client.read_exact(&mut user).await?;
+        let user = String::from_utf8_lossy(&user).to_string();
+
         let request_len = CredentialRequestLen::<CS>::to_usize();
         let mut request_msg = vec![0u8; request_len];
         client.read_exact(&mut request_msg).await?;
@@ -98,9 +187,9 @@ impl ServerAuthenticator {
         let login_start = ServerLogin::start(
             &mut rng,
             &self.setup,
-            Some(self.registration.clone()),
+            self.users.get(&user).cloned(),
             request,
-            b"",
+            user.as_bytes(),
             Default::default(),
         )?;
 
@@ -113,14 +202,30 @@ impl ServerAuthenticator {
         client.read_exact(&mut finalize_msg).await?;
         let finalize = CredentialFinalization::<CS>::deserialize(&finalize_msg)?;
         let finish = login_start.state.finish(finalize)?;
-        Ok(finish.session_key.into())
+
+        Ok(Authenticated {
+            user,
+            session_key: finish.session_key.into(),
+        })
     }
 }
 
+/// Performs the client side of a login as the given user.
+///
+/// User names longer than 255 bytes are rejected.
 pub async fn login<T: AsyncRead + AsyncWrite + Unpin>(
     server: &mut T,
+    user: &str,
     pw: &[u8],
 ) -> Result<SessionKey, AuthenticationError> {
+    let user_len: u8 = user.len().try_into().map_err(|_| {
+        let kind = std::io::ErrorKind::InvalidInput;
+        std::io::Error::new(kind, "user name is too long")
+    })?;
+
+    server.write_u8(user_len).await?;
+    server.write_all(user.as_bytes()).await?;
+
     let mut rng = OsRng;
     let start = ClientLogin::<CS>::start(&mut rng, pw)?;
     let start_msg = start.message.serialize();
@@ -154,9 +259,9 @@ mod tests {
         let auth = ServerAuthenticator::from_password(password).unwrap();
         let (mut client, mut server) = tokio::io::duplex(128);
         let server_join = tokio::spawn(async move { auth.login(&mut client).await });
-        let client_result = login(&mut server, password).await;
+        let client_result = login(&mut server, "", password).await;
         let server_result = server_join.await.unwrap();
-        let server_key = server_result.unwrap();
+        let server_key = server_result.unwrap().session_key;
         let client_key = client_result.unwrap();
         assert_eq!(server_key, client_key);
     }
@@ -168,10 +273,48 @@ mod tests {
         let auth = ServerAuthenticator::from_password(password).unwrap();
         let (mut client, mut server) = tokio::io::duplex(128);
         tokio::spawn(async move { auth.login(&mut client).await });
-        let client_result = login(&mut server, wrong_password).await;
+        let client_result = login(&mut server, "", wrong_password).await;
+        match client_result {
+            Err(AuthenticationError::ProtocolError(ProtocolError::InvalidLoginError)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn authenticate_named_user() {
+        let password = b"deadbeef";
+        let mut auth = ServerAuthenticator::new();
+        auth.add_user("alice", password).unwrap();
+        let (mut client, mut server) = tokio::io::duplex(128);
+        let server_join = tokio::spawn(async move { auth.login(&mut client).await });
+        let client_key = login(&mut server, "alice", password).await.unwrap();
+        let server_result = server_join.await.unwrap().unwrap();
+        assert_eq!(server_result.user, "alice");
+        assert_eq!(server_result.session_key, client_key);
+    }
+
+    #[tokio::test]
+    async fn authenticate_unknown_user() {
+        let password = b"deadbeef";
+        let mut auth = ServerAuthenticator::new();
+        auth.add_user("alice", password).unwrap();
+        let (mut client, mut server) = tokio::io::duplex(128);
+        tokio::spawn(async move { auth.login(&mut client).await });
+        let client_result = login(&mut server, "bob", password).await;
         match client_result {
             Err(AuthenticationError::ProtocolError(ProtocolError::InvalidLoginError)) => {}
             result => panic!("Unexpected result: {:?}", result),
         }
     }
+
+    #[tokio::test]
+    async fn user_file_round_trip() {
+        let password = b"deadbeef";
+        let mut auth = ServerAuthenticator::new();
+        auth.add_user("alice", password).unwrap();
+        let auth = ServerAuthenticator::from_user_file(&auth.to_user_file()).unwrap();
+        let (mut client, mut server) = tokio::io::duplex(128);
+        tokio::spawn(async move { auth.login(&mut client).await });
+        login(&mut server, "alice", password).await.unwrap();
+    }
 }
@@ -16,9 +16,431 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
+use std::{
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
 use flume::{unbounded, Receiver, Sender};
-use hearth_schema::protocol::CapOperation;
-use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use hearth_schema::network::{CapTraffic, ConnectionTraffic};
+use hearth_schema::protocol::{CapOperation, LocalCapOperation, RemoteCapOperation};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::oneshot,
+    time::{interval, timeout, Instant, MissedTickBehavior},
+};
+
+/// Configuration for network connections.
+///
+/// Loaded from the `network` table of the config file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// The number of seconds between keepalive pings sent to the other end.
+    pub keepalive_interval: f64,
+
+    /// The number of seconds without receiving anything from the other end
+    /// after which the connection is considered dead. Must be longer than
+    /// `keepalive_interval`, or healthy connections would time out between
+    /// pings.
+    pub keepalive_timeout: f64,
+
+    /// The maximum size in bytes of a single received message, both as sent
+    /// and after decompression. Connections that send larger messages are
+    /// closed. Must be between 1 and [MAX_MESSAGE_SIZE_LIMIT].
+    pub max_message_size: u32,
+
+    /// The maximum number of bytes received per second. Unlimited if unset.
+    /// Must be positive if set.
+    pub max_bytes_per_second: Option<f64>,
+
+    /// The maximum number of operations received per second. Unlimited if
+    /// unset. Must be positive if set.
+    pub max_ops_per_second: Option<f64>,
+
+    /// Messages at least this many bytes long are compressed with zstd if the
+    /// other end supports it. Compression is disabled if unset.
+    pub compression_threshold: Option<usize>,
+
+    /// The zstd compression level.
+    pub compression_level: i32,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            keepalive_interval: 5.0,
+            keepalive_timeout: 30.0,
+            max_message_size: 16 * 1024 * 1024,
+            max_bytes_per_second: None,
+            max_ops_per_second: None,
+            compression_threshold: Some(16 * 1024),
+            compression_level: 3,
+        }
+    }
+}
+
+impl ConnectionConfig {
+    /// Checks that every value can be used by a connection.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        positive("keepalive_interval", self.keepalive_interval)?;
+        positive("keepalive_timeout", self.keepalive_timeout)?;
+
+        if self.keepalive_timeout <= self.keepalive_interval {
+            return Err(ConfigError {
+                field: "keepalive_timeout",
+                value: self.keepalive_timeout,
+                expected: "longer than keepalive_interval",
+            });
+        }
+
+        if !(1..=MAX_MESSAGE_SIZE_LIMIT).contains(&self.max_message_size) {
+            return Err(ConfigError {
+                field: "max_message_size",
+                value: self.max_message_size as f64,
+                expected: "between 1 and 1073741824",
+            });
+        }
+
+        if let Some(rate) = self.max_bytes_per_second {
+            positive("max_bytes_per_second", rate)?;
+        }
+
+        if let Some(rate) = self.max_ops_per_second {
+            positive("max_ops_per_second", rate)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks that a config value is positive and finite.
+fn positive(field: &'static str, value: f64) -> Result<(), ConfigError> {
+    match value.is_finite() && value > 0.0 {
+        true => Ok(()),
+        false => Err(ConfigError {
+            field,
+            value,
+            expected: "positive and finite",
+        }),
+    }
+}
+
+/// A [ConnectionConfig] value that's out of range.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigError {
+    /// The name of the invalid field.
+    pub field: &'static str,
+
+    /// The invalid value.
+    pub value: f64,
+
+    /// A description of the values that are allowed.
+    pub expected: &'static str,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(
+            fmt,
+            "network config `{}` must be {}, not {}",
+            self.field, self.expected, self.value
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The largest allowed [ConnectionConfig::max_message_size], in bytes.
+///
+/// Received messages are buffered whole, so this keeps a misconfigured
+/// limit from letting one peer exhaust the host's memory.
+pub const MAX_MESSAGE_SIZE_LIMIT: u32 = 1 << 30;
+
+/// Feature flag sent at the start of a connection to signal zstd support.
+const FEATURE_ZSTD: u8 = 1 << 0;
+
+/// Frame tag for uncompressed messages.
+const TAG_RAW: u8 = 0;
+
+/// Frame tag for zstd-compressed messages.
+const TAG_ZSTD: u8 = 1;
+
+/// The number of capability IDs that per-capability traffic is counted for.
+///
+/// Capability IDs are table indices, so they're small and densely packed.
+/// Traffic for larger IDs is only counted in the connection's totals.
+const TRACKED_CAPS: usize = 256;
+
+/// The number of capabilities reported in each direction of a snapshot.
+const BUSIEST_CAPS: usize = 5;
+
+/// Counters for one direction of a connection's traffic.
+#[derive(Debug)]
+struct DirectionStats {
+    ops: AtomicU64,
+    bytes: AtomicU64,
+    cap_ops: [AtomicU64; TRACKED_CAPS],
+    cap_bytes: [AtomicU64; TRACKED_CAPS],
+}
+
+impl Default for DirectionStats {
+    fn default() -> Self {
+        Self {
+            ops: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            cap_ops: std::array::from_fn(|_| AtomicU64::new(0)),
+            cap_bytes: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl DirectionStats {
+    /// Counts a frame. `op` is `None` for keepalive pings.
+    fn record(&self, op: Option<&CapOperation>, frame_len: usize) {
+        let bytes = frame_len as u64 + 4;
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+
+        let Some(op) = op else {
+            return;
+        };
+
+        self.ops.fetch_add(1, Ordering::Relaxed);
+
+        let id = op_cap(op) as usize;
+        if id < TRACKED_CAPS {
+            self.cap_ops[id].fetch_add(1, Ordering::Relaxed);
+            self.cap_bytes[id].fetch_add(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Lists the capabilities with the most operations, busiest first.
+    fn busiest(&self) -> Vec<CapTraffic> {
+        let mut caps: Vec<_> = (0..TRACKED_CAPS)
+            .map(|id| CapTraffic {
+                id: id as u32,
+                ops: self.cap_ops[id].load(Ordering::Relaxed),
+                bytes: self.cap_bytes[id].load(Ordering::Relaxed),
+            })
+            .filter(|cap| cap.ops > 0)
+            .collect();
+
+        caps.sort_by(|a, b| b.ops.cmp(&a.ops).then(a.id.cmp(&b.id)));
+        caps.truncate(BUSIEST_CAPS);
+        caps
+    }
+}
+
+/// Gets the ID of the capability that an operation refers to.
+fn op_cap(op: &CapOperation) -> u32 {
+    match op {
+        CapOperation::Local(op) => match op {
+            LocalCapOperation::DeclareCap { id, .. }
+            | LocalCapOperation::RevokeCap { id, .. }
+            | LocalCapOperation::SetRootCap { id } => *id,
+        },
+        CapOperation::Remote(op) => match op {
+            RemoteCapOperation::AcknowledgeRevocation { id }
+            | RemoteCapOperation::FreeCap { id }
+            | RemoteCapOperation::Send { id, .. }
+            | RemoteCapOperation::Kill { id }
+            | RemoteCapOperation::Shutdown { id, .. } => *id,
+        },
+    }
+}
+
+/// Counters for the traffic over a connection.
+///
+/// Every counter is a relaxed atomic updated by the connection's tasks, so
+/// they're always on and may be read at any time.
+#[derive(Debug)]
+pub struct ConnectionStats {
+    /// The total size of all sent messages before compression.
+    pub uncompressed_bytes: AtomicU64,
+
+    /// The total size of all sent messages after compression.
+    pub compressed_bytes: AtomicU64,
+
+    /// When the connection was opened.
+    opened: Instant,
+
+    /// Milliseconds after opening that an operation was last sent or
+    /// received, plus one, or zero if none have been.
+    last_activity: AtomicU64,
+
+    incoming: DirectionStats,
+    outgoing: DirectionStats,
+
+    /// The depths of the operation queues, sampled by the reader and writer
+    /// tasks on every frame.
+    ///
+    /// These are plain counters instead of handles to the queues so that
+    /// the stats don't keep the queues open after either end is dropped.
+    incoming_queue: AtomicU64,
+    outgoing_queue: AtomicU64,
+}
+
+impl ConnectionStats {
+    fn new() -> Self {
+        Self {
+            uncompressed_bytes: AtomicU64::new(0),
+            compressed_bytes: AtomicU64::new(0),
+            opened: Instant::now(),
+            last_activity: AtomicU64::new(0),
+            incoming: DirectionStats::default(),
+            outgoing: DirectionStats::default(),
+            incoming_queue: AtomicU64::new(0),
+            outgoing_queue: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, uncompressed: usize, compressed: usize) {
+        let uncompressed = uncompressed as u64;
+        let compressed = compressed as u64;
+        self.uncompressed_bytes
+            .fetch_add(uncompressed, Ordering::Relaxed);
+        self.compressed_bytes
+            .fetch_add(compressed, Ordering::Relaxed);
+    }
+
+    fn record_in(&self, op: Option<&CapOperation>, frame_len: usize) {
+        self.incoming.record(op, frame_len);
+        self.touch(op);
+    }
+
+    fn record_out(&self, op: Option<&CapOperation>, frame_len: usize) {
+        self.outgoing.record(op, frame_len);
+        self.touch(op);
+    }
+
+    fn touch(&self, op: Option<&CapOperation>) {
+        if op.is_some() {
+            let elapsed = self.opened.elapsed().as_millis() as u64;
+            self.last_activity.store(elapsed + 1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes a snapshot of these counters.
+    pub fn snapshot(&self) -> ConnectionTraffic {
+        let age_ms = self.opened.elapsed().as_millis() as u64;
+        let idle_ms = match self.last_activity.load(Ordering::Relaxed) {
+            0 => None,
+            last => Some(age_ms.saturating_sub(last - 1)),
+        };
+
+        ConnectionTraffic {
+            age_ms,
+            idle_ms,
+            ops_in: self.incoming.ops.load(Ordering::Relaxed),
+            ops_out: self.outgoing.ops.load(Ordering::Relaxed),
+            bytes_in: self.incoming.bytes.load(Ordering::Relaxed),
+            bytes_out: self.outgoing.bytes.load(Ordering::Relaxed),
+            queue_in: self.incoming_queue.load(Ordering::Relaxed),
+            queue_out: self.outgoing_queue.load(Ordering::Relaxed),
+            busiest_in: self.incoming.busiest(),
+            busiest_out: self.outgoing.busiest(),
+        }
+    }
+}
+
+/// Encodes an operation into a tagged frame body, compressing it if it's
+/// large enough and compression actually shrinks it.
+fn encode_frame(
+    op: &CapOperation,
+    compression: Option<(usize, i32)>,
+    stats: &ConnectionStats,
+) -> Vec<u8> {
+    let payload = bincode::serialize(op).unwrap();
+
+    if let Some((threshold, level)) = compression {
+        if payload.len() >= threshold {
+            match zstd::bulk::compress(&payload, level) {
+                Ok(compressed) if compressed.len() < payload.len() => {
+                    stats.record(payload.len(), compressed.len());
+                    let mut frame = Vec::with_capacity(compressed.len() + 1);
+                    frame.push(TAG_ZSTD);
+                    frame.extend_from_slice(&compressed);
+                    return frame;
+                }
+                Ok(_) => {} // negative gain; send uncompressed
+                Err(err) => tracing::warn!("failed to compress message: {:?}", err),
+            }
+        }
+    }
+
+    stats.record(payload.len(), payload.len());
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.push(TAG_RAW);
+    frame.extend_from_slice(&payload);
+    frame
+}
+
+/// Decodes a tagged frame body into an operation.
+///
+/// Decompressed messages are limited to `max_size` bytes.
+fn decode_frame(frame: &[u8], max_size: u32) -> Result<CapOperation, String> {
+    let (tag, body) = frame.split_first().ok_or("empty frame")?;
+
+    match *tag {
+        TAG_RAW => bincode::deserialize(body).map_err(|err| err.to_string()),
+        TAG_ZSTD => {
+            use std::io::Read;
+
+            // read one byte past the limit to detect oversized messages
+            // without allocating the whole limit up front
+            let mut payload = Vec::new();
+            zstd::stream::read::Decoder::new(body)
+                .map_err(|err| format!("decompression failed: {}", err))?
+                .take(max_size as u64 + 1)
+                .read_to_end(&mut payload)
+                .map_err(|err| format!("decompression failed: {}", err))?;
+
+            if payload.len() > max_size as usize {
+                return Err(format!("decompressed message exceeds {} bytes", max_size));
+            }
+
+            bincode::deserialize(&payload).map_err(|err| err.to_string())
+        }
+        other => Err(format!("unknown frame tag {}", other)),
+    }
+}
+
+/// A token bucket rate limiter with a burst capacity of one second.
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            tokens: rate,
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes tokens from the bucket, waiting until the bucket is no longer in
+    /// debt.
+    async fn take(&mut self, num: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.last = now;
+        self.tokens -= num;
+
+        if self.tokens < 0.0 {
+            let wait = Duration::from_secs_f64(-self.tokens / self.rate);
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
 
 pub struct Connection {
     /// An outgoing channel for capability operations.
@@ -26,43 +448,535 @@ pub struct Connection {
 
     /// A channel for incoming capability operations.
     pub op_rx: Receiver<CapOperation>,
+
+    /// Resolves when the transport has been closed or has failed.
+    pub closed: oneshot::Receiver<()>,
+
+    /// Counters for this connection's traffic in both directions.
+    pub stats: Arc<ConnectionStats>,
 }
 
 impl Connection {
-    /// Creates a connection for the given transport.
+    /// Creates a connection for the given transport with the default config.
     pub fn new(
+        rx: impl AsyncRead + Unpin + Send + 'static,
+        tx: impl AsyncWrite + Unpin + Send + 'static,
+    ) -> Self {
+        Self::start(rx, tx, ConnectionConfig::default())
+    }
+
+    /// Creates a connection for the given transport.
+    ///
+    /// Both ends of the connection begin by sending a byte of feature flags.
+    /// Messages are only compressed if the other end supports it.
+    ///
+    /// Both ends of the connection periodically send keepalive pings, which
+    /// are empty frames. If nothing is received for longer than the keepalive
+    /// timeout, the connection is closed.
+    ///
+    /// Fails if the config doesn't [validate](ConnectionConfig::validate).
+    pub fn with_config(
+        rx: impl AsyncRead + Unpin + Send + 'static,
+        tx: impl AsyncWrite + Unpin + Send + 'static,
+        config: ConnectionConfig,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        Ok(Self::start(rx, tx, config))
+    }
+
+    /// Starts a connection with an already validated config.
+    fn start(
         mut rx: impl AsyncRead + Unpin + Send + 'static,
         mut tx: impl AsyncWrite + Unpin + Send + 'static,
+        config: ConnectionConfig,
     ) -> Self {
         let (outgoing_tx, outgoing_rx) = unbounded();
         let (incoming_tx, incoming_rx) = unbounded();
+        let (closed_tx, closed) = oneshot::channel();
+        let keepalive_interval = Duration::from_secs_f64(config.keepalive_interval);
+        let keepalive_timeout = Duration::from_secs_f64(config.keepalive_timeout);
+        let max_message_size = config.max_message_size;
+        let mut byte_limit = config.max_bytes_per_second.map(TokenBucket::new);
+        let mut op_limit = config.max_ops_per_second.map(TokenBucket::new);
+        let compression = config
+            .compression_threshold
+            .map(|threshold| (threshold, config.compression_level));
+        let stats = Arc::new(ConnectionStats::new());
+        let (peer_features_tx, peer_features_rx) = oneshot::channel::<u8>();
+
+        let writer_stats = stats.clone();
+        let writer = tokio::spawn(async move {
+            let features = if compression.is_some() {
+                FEATURE_ZSTD
+            } else {
+                0
+            };
+
+            if let Err(err) = tx.write_u8(features).await {
+                tracing::debug!("connection write error: {:?}", err);
+                return;
+            }
+
+            // message-based transports only send what's been flushed
+            if let Err(err) = tx.flush().await {
+                tracing::debug!("connection write error: {:?}", err);
+                return;
+            }
+
+            let Ok(peer_features) = peer_features_rx.await else {
+                return;
+            };
+
+            let compression = compression.filter(|_| peer_features & FEATURE_ZSTD != 0);
+
+            let mut pings = interval(keepalive_interval);
+            pings.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                let op = tokio::select! {
+                    op = outgoing_rx.recv_async() => match op {
+                        Ok(op) => Some(op),
+                        Err(_) => break,
+                    },
+                    _ = pings.tick() => None,
+                };
+
+                let queued = outgoing_rx.len() as u64;
+                writer_stats.outgoing_queue.store(queued, Ordering::Relaxed);
+
+                let payload = match op.as_ref() {
+                    Some(op) => encode_frame(op, compression, &writer_stats),
+                    None => Vec::new(),
+                };
+
+                writer_stats.record_out(op.as_ref(), payload.len());
 
-        tokio::spawn(async move {
-            while let Ok(op) = outgoing_rx.recv_async().await {
-                let payload = bincode::serialize(&op).unwrap();
                 let len = payload.len() as u32;
-                tx.write_u32_le(len).await.unwrap();
-                tx.write_all(&payload).await.unwrap();
+                if let Err(err) = tx.write_u32_le(len).await {
+                    tracing::debug!("connection write error: {:?}", err);
+                    break;
+                }
+
+                if let Err(err) = tx.write_all(&payload).await {
+                    tracing::debug!("connection write error: {:?}", err);
+                    break;
+                }
+
+                if let Err(err) = tx.flush().await {
+                    tracing::debug!("connection write error: {:?}", err);
+                    break;
+                }
             }
         });
 
-        #[allow(clippy::read_zero_byte_vec)]
+        let reader_stats = stats.clone();
         tokio::spawn(async move {
+            match timeout(keepalive_timeout, rx.read_u8()).await {
+                Ok(Ok(features)) => {
+                    let _ = peer_features_tx.send(features);
+                }
+                Ok(Err(err)) => {
+                    tracing::debug!("connection read error: {:?}", err);
+                    writer.abort();
+                    let _ = closed_tx.send(());
+                    return;
+                }
+                Err(_) => {
+                    tracing::warn!("connection timed out during handshake");
+                    writer.abort();
+                    let _ = closed_tx.send(());
+                    return;
+                }
+            }
+
             let mut buf = Vec::new();
             loop {
-                let len = rx.read_u32_le().await.unwrap();
-                buf.resize(len as usize, 0);
-                rx.read_exact(&mut buf).await.unwrap();
-                let op = bincode::deserialize(&buf).unwrap();
+                let len = match timeout(keepalive_timeout, rx.read_u32_le()).await {
+                    Ok(Ok(len)) => len,
+                    Ok(Err(err)) => {
+                        tracing::debug!("connection read error: {:?}", err);
+                        break;
+                    }
+                    Err(_) => {
+                        tracing::warn!("connection timed out after {:?}", keepalive_timeout);
+                        break;
+                    }
+                };
+
+                // check limits before reading and decrypting the message body
+                if len > max_message_size {
+                    tracing::error!(
+                        "connection protocol error: message of {} bytes exceeds limit of {}",
+                        len,
+                        max_message_size
+                    );
+                    break;
+                }
+
+                if let Some(limit) = byte_limit.as_mut() {
+                    limit.take(len as f64 + 4.0).await;
+                }
+
+                if len > 0 {
+                    if let Some(limit) = op_limit.as_mut() {
+                        limit.take(1.0).await;
+                    }
+                }
+
+                // grow the buffer as the body arrives instead of trusting the
+                // length up front, so a bare header can't force an allocation
+                buf.clear();
+                let body = (&mut rx).take(len as u64).read_to_end(&mut buf);
+                match timeout(keepalive_timeout, body).await {
+                    Ok(Ok(read)) if read == len as usize => {}
+                    Ok(Ok(_)) => {
+                        tracing::debug!("connection closed in the middle of a message");
+                        break;
+                    }
+                    Ok(Err(err)) => {
+                        tracing::debug!("connection read error: {:?}", err);
+                        break;
+                    }
+                    Err(_) => {
+                        tracing::warn!("connection timed out after {:?}", keepalive_timeout);
+                        break;
+                    }
+                }
+
+                let queued = incoming_tx.len() as u64;
+                reader_stats.incoming_queue.store(queued, Ordering::Relaxed);
+
+                // empty frames are keepalive pings
+                if buf.is_empty() {
+                    reader_stats.record_in(None, 0);
+                    continue;
+                }
+
+                let op = match decode_frame(&buf, max_message_size) {
+                    Ok(op) => op,
+                    Err(err) => {
+                        tracing::error!("connection protocol error: {:?}", err);
+                        break;
+                    }
+                };
+
+                reader_stats.record_in(Some(&op), buf.len());
+
                 if incoming_tx.send(op).is_err() {
                     break;
                 }
             }
+
+            writer.abort();
+            let _ = closed_tx.send(()); // ignore if nobody is listening
         });
 
         Self {
             op_tx: outgoing_tx,
             op_rx: incoming_rx,
+            closed,
+            stats,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_schema::protocol::RemoteCapOperation;
+    use tokio::sync::oneshot::error::TryRecvError;
+
+    const CONFIG: ConnectionConfig = ConnectionConfig {
+        keepalive_interval: 1.0,
+        keepalive_timeout: 3.0,
+        max_message_size: 1024 * 1024,
+        max_bytes_per_second: None,
+        max_ops_per_second: None,
+        compression_threshold: Some(64),
+        compression_level: 3,
+    };
+
+    #[test]
+    fn invalid_keepalive_is_rejected() {
+        assert_eq!(CONFIG.validate(), Ok(()));
+
+        for value in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let config = ConnectionConfig {
+                keepalive_interval: value,
+                ..CONFIG
+            };
+
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.field, "keepalive_interval");
+
+            let config = ConnectionConfig {
+                keepalive_timeout: value,
+                ..CONFIG
+            };
+
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.field, "keepalive_timeout");
+        }
+    }
+
+    #[test]
+    fn timeout_must_outlast_interval() {
+        for timeout in [0.5, 1.0] {
+            let config = ConnectionConfig {
+                keepalive_timeout: timeout,
+                ..CONFIG
+            };
+
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.field, "keepalive_timeout");
+        }
+    }
+
+    #[test]
+    fn invalid_rates_are_rejected() {
+        for value in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let config = ConnectionConfig {
+                max_bytes_per_second: Some(value),
+                ..CONFIG
+            };
+
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.field, "max_bytes_per_second");
+
+            let config = ConnectionConfig {
+                max_ops_per_second: Some(value),
+                ..CONFIG
+            };
+
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.field, "max_ops_per_second");
+        }
+    }
+
+    #[test]
+    fn unbounded_message_size_is_rejected() {
+        for value in [0, MAX_MESSAGE_SIZE_LIMIT + 1, u32::MAX] {
+            let config = ConnectionConfig {
+                max_message_size: value,
+                ..CONFIG
+            };
+
+            let err = config.validate().unwrap_err();
+            assert_eq!(err.field, "max_message_size");
+        }
+    }
+
+    #[test]
+    fn decompression_is_limited() {
+        let payload = bincode::serialize(&test_op()).unwrap();
+        let mut frame = vec![TAG_ZSTD];
+        frame.extend(zstd::bulk::compress(&payload, 3).unwrap());
+
+        let max_size = payload.len() as u32;
+        assert_eq!(decode_frame(&frame, max_size).unwrap(), test_op());
+        assert!(decode_frame(&frame, max_size - 1).is_err());
+    }
+
+    /// Sends the feature handshake and a single raw framed op.
+    async fn send_raw(remote: &mut tokio::io::DuplexStream, op: &CapOperation) {
+        let payload = bincode::serialize(op).unwrap();
+        let len = payload.len() as u32 + 1;
+        remote.write_u8(0).await.unwrap();
+        remote.write_u32_le(len).await.unwrap();
+        remote.write_u8(TAG_RAW).await.unwrap();
+        remote.write_all(&payload).await.unwrap();
+    }
+
+    /// Returns the size of a raw frame containing the given op.
+    fn raw_frame_len(op: &CapOperation) -> u32 {
+        bincode::serialized_size(op).unwrap() as u32 + 1
+    }
+
+    /// Sends an op between two connected connections and returns the
+    /// sender's stats.
+    async fn round_trip(op: CapOperation) -> Arc<ConnectionStats> {
+        let (local, remote) = tokio::io::duplex(1024);
+        let (local_rx, local_tx) = tokio::io::split(local);
+        let (remote_rx, remote_tx) = tokio::io::split(remote);
+        let local = Connection::with_config(local_rx, local_tx, CONFIG).unwrap();
+        let remote = Connection::with_config(remote_rx, remote_tx, CONFIG).unwrap();
+        local.op_tx.send(op.clone()).unwrap();
+        assert_eq!(remote.op_rx.recv_async().await.unwrap(), op);
+        local.stats
+    }
+
+    #[tokio::test]
+    async fn compressible_round_trip() {
+        let stats = round_trip(CapOperation::Remote(RemoteCapOperation::Send {
+            id: 0,
+            data: vec![0xaa; 64 * 1024],
+            caps: vec![],
+        }))
+        .await;
+
+        let uncompressed = stats.uncompressed_bytes.load(Ordering::Relaxed);
+        let compressed = stats.compressed_bytes.load(Ordering::Relaxed);
+        assert!(compressed < uncompressed);
+    }
+
+    #[tokio::test]
+    async fn incompressible_round_trip() {
+        use rand::RngCore;
+
+        let mut data = vec![0u8; 64 * 1024];
+        rand::thread_rng().fill_bytes(&mut data);
+
+        let stats = round_trip(CapOperation::Remote(RemoteCapOperation::Send {
+            id: 0,
+            data,
+            caps: vec![],
+        }))
+        .await;
+
+        let uncompressed = stats.uncompressed_bytes.load(Ordering::Relaxed);
+        let compressed = stats.compressed_bytes.load(Ordering::Relaxed);
+        assert_eq!(compressed, uncompressed);
+    }
+
+    #[tokio::test]
+    async fn loopback_counters() {
+        let (local, remote) = tokio::io::duplex(1024);
+        let (local_rx, local_tx) = tokio::io::split(local);
+        let (remote_rx, remote_tx) = tokio::io::split(remote);
+        let local = Connection::with_config(local_rx, local_tx, CONFIG).unwrap();
+        let remote = Connection::with_config(remote_rx, remote_tx, CONFIG).unwrap();
+
+        let before = local.stats.snapshot();
+        assert_eq!(before.ops_out, 0);
+        assert_eq!(before.idle_ms, None);
+
+        let op = |id| {
+            CapOperation::Remote(RemoteCapOperation::Send {
+                id,
+                data: vec![0xaa; 16],
+                caps: vec![],
+            })
+        };
+
+        for id in [3, 3, 7] {
+            local.op_tx.send(op(id)).unwrap();
+            assert_eq!(remote.op_rx.recv_async().await.unwrap(), op(id));
+        }
+
+        remote.op_tx.send(op(1)).unwrap();
+        assert_eq!(local.op_rx.recv_async().await.unwrap(), op(1));
+
+        let sent = local.stats.snapshot();
+        assert_eq!(sent.ops_out, 3);
+        assert_eq!(sent.ops_in, 1);
+        assert!(sent.bytes_out > 3 * 16);
+        assert!(sent.bytes_in > 16);
+        assert_eq!(sent.queue_in, 0);
+        assert!(sent.idle_ms.is_some());
+        let busiest: Vec<_> = sent
+            .busiest_out
+            .iter()
+            .map(|cap| (cap.id, cap.ops))
+            .collect();
+        assert_eq!(busiest, [(3, 2), (7, 1)]);
+
+        let received = remote.stats.snapshot();
+        assert_eq!(received.ops_in, 3);
+        assert_eq!(received.ops_out, 1);
+        assert_eq!(received.busiest_in, sent.busiest_out);
+        assert_eq!(received.bytes_in, sent.bytes_out);
+    }
+
+    fn test_op() -> CapOperation {
+        CapOperation::Remote(RemoteCapOperation::Send {
+            id: 0,
+            data: vec![0xaa; 256],
+            caps: vec![],
+        })
+    }
+
+    #[tokio::test]
+    async fn message_under_limit() {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let (rx, tx) = tokio::io::split(local);
+        let op = test_op();
+        let len = raw_frame_len(&op);
+
+        let config = ConnectionConfig {
+            max_message_size: len,
+            ..CONFIG
+        };
+
+        let conn = Connection::with_config(rx, tx, config).unwrap();
+        send_raw(&mut remote, &op).await;
+        assert_eq!(conn.op_rx.recv_async().await.unwrap(), op);
+    }
+
+    #[tokio::test]
+    async fn message_over_limit() {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let (rx, tx) = tokio::io::split(local);
+        let op = test_op();
+        let len = raw_frame_len(&op);
+
+        let config = ConnectionConfig {
+            max_message_size: len - 1,
+            ..CONFIG
+        };
+
+        let conn = Connection::with_config(rx, tx, config).unwrap();
+        send_raw(&mut remote, &op).await;
+        conn.closed.await.unwrap();
+        assert!(conn.op_rx.recv_async().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn dropped_receiver_closes_connection() {
+        let (local, remote) = tokio::io::duplex(1024);
+        let (local_rx, local_tx) = tokio::io::split(local);
+        let (remote_rx, remote_tx) = tokio::io::split(remote);
+        let local = Connection::with_config(local_rx, local_tx, CONFIG).unwrap();
+        let remote = Connection::with_config(remote_rx, remote_tx, CONFIG).unwrap();
+
+        let Connection { op_rx, closed, .. } = remote;
+        drop(op_rx);
+
+        // the next received op has nowhere to go
+        local.op_tx.send(test_op()).unwrap();
+        closed.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn oversized_header_closes_connection() {
+        let (local, mut remote) = tokio::io::duplex(1024);
+        let (rx, tx) = tokio::io::split(local);
+        let conn = Connection::with_config(rx, tx, CONFIG).unwrap();
+        remote.write_u8(0).await.unwrap();
+        remote.write_u32_le(u32::MAX).await.unwrap();
+        conn.closed.await.unwrap();
+        assert!(conn.op_rx.recv_async().await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_peer_times_out() {
+        let (local, _remote) = tokio::io::duplex(1024);
+        let (rx, tx) = tokio::io::split(local);
+        let start = tokio::time::Instant::now();
+        let conn = Connection::with_config(rx, tx, CONFIG).unwrap();
+        conn.closed.await.unwrap();
+        assert!(start.elapsed() >= Duration::from_secs(3));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keepalive_prevents_timeout() {
+        let (local, remote) = tokio::io::duplex(1024);
+        let (local_rx, local_tx) = tokio::io::split(local);
+        let (remote_rx, remote_tx) = tokio::io::split(remote);
+        let mut local = Connection::with_config(local_rx, local_tx, CONFIG).unwrap();
+        let mut remote = Connection::with_config(remote_rx, remote_tx, CONFIG).unwrap();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(local.closed.try_recv(), Err(TryRecvError::Empty));
+        assert_eq!(remote.closed.try_recv(), Err(TryRecvError::Empty));
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! LAN discovery of servers using mDNS.
+//!
+//! Servers advertise a [SERVICE_TYPE] service whose TXT record describes the
+//! server. Clients browse for the service for a while and collect every
+//! server that answers.
+
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::Serialize;
+
+use crate::PROTOCOL_VERSION;
+
+/// The mDNS service type that servers are advertised under.
+pub const SERVICE_TYPE: &str = "_hearth._tcp.local.";
+
+/// The TXT record key for the server's protocol version.
+const VERSION_KEY: &str = "version";
+
+/// The TXT record key for whether the server requires a password.
+const PASSWORD_KEY: &str = "password";
+
+/// Converts an mDNS error into an IO error.
+fn to_io_error(err: mdns_sd::Error) -> Error {
+    Error::new(ErrorKind::Other, err)
+}
+
+/// A server found on the local network.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DiscoveredServer {
+    /// The server's advertised name.
+    pub name: String,
+
+    /// The addresses the server can be reached at.
+    pub addrs: Vec<SocketAddr>,
+
+    /// The server's protocol version, if it advertised a valid one.
+    pub protocol_version: Option<u32>,
+
+    /// Whether the server requires a password to log in.
+    pub password_required: bool,
+}
+
+impl DiscoveredServer {
+    /// Returns true if this server speaks the same protocol as us.
+    pub fn is_compatible(&self) -> bool {
+        self.protocol_version == Some(PROTOCOL_VERSION)
+    }
+}
+
+/// Builds the TXT record properties that describe a server.
+fn to_properties(password_required: bool) -> HashMap<String, String> {
+    HashMap::from([
+        (VERSION_KEY.to_string(), PROTOCOL_VERSION.to_string()),
+        (PASSWORD_KEY.to_string(), password_required.to_string()),
+    ])
+}
+
+/// Parses a server's description from its TXT record properties. Missing
+/// or invalid properties are treated as unknown.
+fn from_properties(
+    name: String,
+    addrs: Vec<SocketAddr>,
+    get: impl Fn(&str) -> Option<String>,
+) -> DiscoveredServer {
+    DiscoveredServer {
+        name,
+        addrs,
+        protocol_version: get(VERSION_KEY).and_then(|version| version.parse().ok()),
+        // assume a password is required unless told otherwise
+        password_required: get(PASSWORD_KEY).as_deref() != Some("false"),
+    }
+}
+
+/// Removes the service type from a full mDNS instance name.
+fn instance_name(fullname: &str) -> &str {
+    fullname
+        .strip_suffix(SERVICE_TYPE)
+        .map(|name| name.trim_end_matches('.'))
+        .unwrap_or(fullname)
+}
+
+/// Turns a server name into a valid mDNS host name label.
+fn host_label(name: &str) -> String {
+    let label: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+
+    let label = label.trim_matches('-');
+    if label.is_empty() {
+        "hearth".to_string()
+    } else {
+        label.to_ascii_lowercase()
+    }
+}
+
+/// An active advertisement of a server on the local network. The server
+/// stops being advertised when this is dropped.
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+impl Advertisement {
+    /// Starts advertising a server on all local interfaces.
+    pub fn new(name: &str, port: u16, password_required: bool) -> std::io::Result<Self> {
+        let daemon = ServiceDaemon::new().map_err(to_io_error)?;
+        let host = format!("{}.local.", host_label(name));
+        let properties = to_properties(password_required);
+        let info = ServiceInfo::new(SERVICE_TYPE, name, &host, "", port, properties)
+            .map_err(to_io_error)?
+            .enable_addr_auto();
+
+        let fullname = info.get_fullname().to_string();
+        daemon.register(info).map_err(to_io_error)?;
+        Ok(Self { daemon, fullname })
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Browses the local network for servers for the given duration.
+///
+/// This blocks for the whole duration, so call it from a blocking context.
+/// Servers are returned in the order they were found.
+pub fn browse(duration: Duration) -> std::io::Result<Vec<DiscoveredServer>> {
+    let daemon = ServiceDaemon::new().map_err(to_io_error)?;
+    let events = daemon.browse(SERVICE_TYPE).map_err(to_io_error)?;
+    let deadline = Instant::now() + duration;
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+
+        let Ok(event) = events.recv_timeout(remaining) else {
+            break;
+        };
+
+        let ServiceEvent::ServiceResolved(info) = event else {
+            continue;
+        };
+
+        let port = info.get_port();
+        let mut addrs: Vec<SocketAddr> = info
+            .get_addresses()
+            .iter()
+            .map(|ip| SocketAddr::new(IpAddr::from(*ip), port))
+            .collect();
+        addrs.sort();
+
+        let name = instance_name(info.get_fullname()).to_string();
+        let get = |key: &str| info.get_property_val_str(key).map(str::to_string);
+        let server = from_properties(name, addrs, get);
+
+        // servers are resolved again when their records change
+        match servers.iter_mut().find(|found| found.name == server.name) {
+            Some(found) => *found = server,
+            None => servers.push(server),
+        }
+    }
+
+    let _ = daemon.stop_browse(SERVICE_TYPE);
+    let _ = daemon.shutdown();
+    Ok(servers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(properties: &HashMap<String, String>) -> DiscoveredServer {
+        from_properties("test".to_string(), vec![], |key| {
+            properties.get(key).cloned()
+        })
+    }
+
+    #[test]
+    fn properties_round_trip() {
+        let open = parse(&to_properties(false));
+        assert!(open.is_compatible());
+        assert!(!open.password_required);
+
+        let locked = parse(&to_properties(true));
+        assert!(locked.is_compatible());
+        assert!(locked.password_required);
+    }
+
+    #[test]
+    fn missing_properties() {
+        let server = parse(&HashMap::new());
+        assert_eq!(server.protocol_version, None);
+        assert!(!server.is_compatible());
+        assert!(server.password_required);
+    }
+
+    #[test]
+    fn names() {
+        let fullname = format!("My Server.{}", SERVICE_TYPE);
+        assert_eq!(instance_name(&fullname), "My Server");
+        assert_eq!(host_label("My Server!"), "my-server");
+        assert_eq!(host_label("???"), "hearth");
+    }
+}
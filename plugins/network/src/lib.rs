@@ -19,6 +19,16 @@
 pub mod auth;
 pub mod connection;
 pub mod encryption;
+pub mod stats;
+pub mod tls;
+pub mod websocket;
+
+#[cfg(feature = "mdns")]
+pub mod discovery;
+
+/// The version of the network protocol, advertised to clients before they
+/// connect. Bumped whenever old clients can't talk to new servers.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[cfg(test)]
 mod tests {
@@ -39,7 +49,7 @@ mod tests {
         let (mut client, mut server) = tokio::io::duplex(128);
 
         tokio::spawn(async move {
-            let session_key = authenticator.login(&mut client).await.unwrap();
+            let session_key = authenticator.login(&mut client).await.unwrap().session_key;
             let client_key = Key::from_client_session(&session_key);
             let server_key = Key::from_server_session(&session_key);
             let (rx, tx) = tokio::io::split(client);
@@ -54,7 +64,7 @@ mod tests {
             encryptor.flush().await.unwrap();
         });
 
-        let session_key = auth::login(&mut server, PASSWORD).await.unwrap();
+        let session_key = auth::login(&mut server, "", PASSWORD).await.unwrap();
         let client_key = Key::from_client_session(&session_key);
         let server_key = Key::from_server_session(&session_key);
         let (rx, tx) = tokio::io::split(server);
@@ -0,0 +1,94 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Reporting connection traffic counters to processes.
+
+use std::sync::{Arc, Weak};
+
+use hearth_runtime::{
+    async_trait, cargo_process_metadata,
+    process::ProcessMetadata,
+    utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner},
+};
+use hearth_schema::network::{NetworkStatsRequest, PeerTraffic, NETWORK_STATS_SERVICE};
+use parking_lot::Mutex;
+
+use crate::connection::ConnectionStats;
+
+/// A shared list of the connections whose counters are reported.
+///
+/// Connections are only weakly referenced, so they're forgotten once closed.
+#[derive(Clone, Debug, Default)]
+pub struct TrackedConnections {
+    inner: Arc<Mutex<Vec<(String, Weak<ConnectionStats>)>>>,
+}
+
+impl TrackedConnections {
+    /// Starts reporting a connection's counters under a peer's name.
+    pub fn track(&self, peer: String, stats: &Arc<ConnectionStats>) {
+        let mut inner = self.inner.lock();
+        inner.retain(|(_, stats)| stats.strong_count() > 0);
+        inner.push((peer, Arc::downgrade(stats)));
+    }
+
+    /// Takes a snapshot of every open connection's counters.
+    pub fn snapshot(&self) -> Vec<PeerTraffic> {
+        let mut inner = self.inner.lock();
+        inner.retain(|(_, stats)| stats.strong_count() > 0);
+        inner
+            .iter()
+            .filter_map(|(peer, stats)| {
+                Some(PeerTraffic {
+                    peer: peer.clone(),
+                    traffic: stats.upgrade()?.snapshot(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// A service that reports the traffic counters of a host's connections.
+pub struct NetworkStatsService {
+    pub connections: TrackedConnections,
+}
+
+#[async_trait]
+impl RequestResponseProcess for NetworkStatsService {
+    type Request = NetworkStatsRequest;
+    type Response = Vec<PeerTraffic>;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, Self::Request>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let NetworkStatsRequest::List = request.data;
+        self.connections.snapshot().into()
+    }
+}
+
+impl ServiceRunner for NetworkStatsService {
+    const NAME: &'static str = NETWORK_STATS_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Reports traffic counters for network connections. Accepts NetworkStatsRequest.".into(),
+        );
+        meta
+    }
+}
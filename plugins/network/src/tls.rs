@@ -0,0 +1,304 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fs::File,
+    io::{BufReader, Error, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::{
+    rustls::{
+        Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerConfig,
+        ServerName,
+    },
+    TlsAcceptor, TlsConnector,
+};
+
+/// The transport mode byte sent by the server at the start of a connection.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransportMode {
+    /// The connection continues unencrypted until password authentication.
+    Plain = 0,
+
+    /// The connection is upgraded to TLS before password authentication.
+    Tls = 1,
+}
+
+impl TryFrom<u8> for TransportMode {
+    type Error = Error;
+
+    fn try_from(byte: u8) -> Result<Self, Error> {
+        match byte {
+            0 => Ok(TransportMode::Plain),
+            1 => Ok(TransportMode::Tls),
+            other => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "unknown transport mode {}; is the server up to date?",
+                    other
+                ),
+            )),
+        }
+    }
+}
+
+/// A bidirectional byte stream that a connection can be run over.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+/// Server-side TLS configuration, loaded from the `tls` table of the config
+/// file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ServerTlsConfig {
+    /// The path to the PEM-encoded certificate chain.
+    pub cert: PathBuf,
+
+    /// The path to the PEM-encoded PKCS#8 private key.
+    pub key: PathBuf,
+}
+
+/// The server side of TLS transport security.
+pub struct ServerTls {
+    acceptor: TlsAcceptor,
+}
+
+impl ServerTls {
+    /// Loads a server's certificate chain and private key from PEM files.
+    pub fn from_config(config: &ServerTlsConfig) -> std::io::Result<Self> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(&config.cert)?))?;
+        let mut keys =
+            rustls_pemfile::pkcs8_private_keys(&mut BufReader::new(File::open(&config.key)?))?;
+
+        if keys.is_empty() {
+            let msg = format!("no PKCS#8 private key found in {:?}", config.key);
+            return Err(Error::new(ErrorKind::InvalidData, msg));
+        }
+
+        Self::from_der(certs, keys.remove(0))
+    }
+
+    /// Creates server TLS from a DER-encoded certificate chain and private key.
+    pub fn from_der(certs: Vec<Vec<u8>>, key: Vec<u8>) -> std::io::Result<Self> {
+        let certs = certs.into_iter().map(Certificate).collect();
+
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(certs, PrivateKey(key))
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+
+        Ok(Self {
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+        })
+    }
+
+    /// Performs the server side of a TLS handshake on a stream.
+    pub(crate) async fn wrap<S: Transport + 'static>(
+        &self,
+        stream: S,
+    ) -> std::io::Result<Box<dyn Transport>> {
+        Ok(Box::new(self.acceptor.accept(stream).await?))
+    }
+}
+
+/// The client side of TLS transport security.
+pub struct ClientTls {
+    connector: TlsConnector,
+}
+
+impl ClientTls {
+    /// Creates client TLS that verifies servers using the well-known web
+    /// root certificates.
+    pub fn with_web_roots() -> Self {
+        let mut roots = RootCertStore::empty();
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject,
+                ta.spki,
+                ta.name_constraints,
+            )
+        }));
+
+        Self::with_roots(roots)
+    }
+
+    /// Creates client TLS that only trusts the certificates in the given PEM
+    /// file. Used to pin self-signed server certificates.
+    pub fn with_pinned_file(path: &Path) -> std::io::Result<Self> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(path)?))?;
+        Self::with_pinned_der(certs)
+    }
+
+    /// Creates client TLS that only trusts the given DER-encoded certificates.
+    pub fn with_pinned_der(certs: Vec<Vec<u8>>) -> std::io::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for cert in certs {
+            roots
+                .add(&Certificate(cert))
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        }
+
+        Ok(Self::with_roots(roots))
+    }
+
+    fn with_roots(roots: RootCertStore) -> Self {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Self {
+            connector: TlsConnector::from(Arc::new(config)),
+        }
+    }
+
+    /// Performs the client side of a TLS handshake on a stream, verifying
+    /// the server's certificate against the given name.
+    pub(crate) async fn wrap<S: Transport + 'static>(
+        &self,
+        stream: S,
+        server_name: &str,
+    ) -> std::io::Result<Box<dyn Transport>> {
+        let name = ServerName::try_from(server_name)
+            .map_err(|err| Error::new(ErrorKind::InvalidInput, err))?;
+        Ok(Box::new(self.connector.connect(name, stream).await?))
+    }
+}
+
+/// Performs the server side of transport negotiation.
+///
+/// Sends the client the transport mode, then upgrades to TLS if configured.
+pub async fn accept<S: Transport + 'static>(
+    mut stream: S,
+    tls: Option<&ServerTls>,
+) -> std::io::Result<Box<dyn Transport>> {
+    let Some(tls) = tls else {
+        stream.write_u8(TransportMode::Plain as u8).await?;
+        stream.flush().await?;
+        return Ok(Box::new(stream));
+    };
+
+    stream.write_u8(TransportMode::Tls as u8).await?;
+    stream.flush().await?;
+    tls.wrap(stream).await
+}
+
+/// Performs the client side of transport negotiation.
+///
+/// Fails if the server's transport mode doesn't match the client's: either
+/// the server requires TLS and the client has none configured, or the client
+/// requires TLS and the server doesn't offer it.
+pub async fn connect<S: Transport + 'static>(
+    mut stream: S,
+    server_name: &str,
+    tls: Option<&ClientTls>,
+) -> std::io::Result<Box<dyn Transport>> {
+    let mode = TransportMode::try_from(stream.read_u8().await?)?;
+
+    match (mode, tls) {
+        (TransportMode::Plain, None) => Ok(Box::new(stream)),
+        (TransportMode::Tls, Some(tls)) => tls.wrap(stream, server_name).await,
+        (TransportMode::Tls, None) => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "server requires TLS",
+        )),
+        (TransportMode::Plain, Some(_)) => Err(Error::new(
+            ErrorKind::PermissionDenied,
+            "client requires TLS but the server does not offer it",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::auth::{login, ServerAuthenticator};
+
+    const PASSWORD: &[u8] = b"deadbeef";
+
+    fn generate_cert() -> (Vec<u8>, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let der = cert.serialize_der().unwrap();
+        let key = cert.serialize_private_key_der();
+        (der, key)
+    }
+
+    /// Runs a loopback server with the given TLS config and returns the
+    /// result of a client connection attempt.
+    async fn loopback(
+        server_tls: Option<ServerTls>,
+        client_tls: Option<ClientTls>,
+    ) -> std::io::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let authenticator = ServerAuthenticator::from_password(PASSWORD).unwrap();
+
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let Ok(mut transport) = accept(socket, server_tls.as_ref()).await else {
+                return;
+            };
+
+            let _ = authenticator.login(&mut transport).await;
+        });
+
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let mut transport = connect(socket, "localhost", client_tls.as_ref()).await?;
+        login(&mut transport, "", PASSWORD).await.unwrap();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn plain() {
+        loopback(None, None).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn accepted_cert() {
+        let (cert, key) = generate_cert();
+        let server = ServerTls::from_der(vec![cert.clone()], key).unwrap();
+        let client = ClientTls::with_pinned_der(vec![cert]).unwrap();
+        loopback(Some(server), Some(client)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn rejected_cert() {
+        let (cert, key) = generate_cert();
+        let (other_cert, _) = generate_cert();
+        let server = ServerTls::from_der(vec![cert], key).unwrap();
+        let client = ClientTls::with_pinned_der(vec![other_cert]).unwrap();
+        assert!(loopback(Some(server), Some(client)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn server_requires_tls() {
+        let (cert, key) = generate_cert();
+        let server = ServerTls::from_der(vec![cert], key).unwrap();
+        let err = loopback(Some(server), None).await.unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+}
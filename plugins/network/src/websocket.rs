@@ -0,0 +1,290 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! WebSocket transport, so that peers that can't open raw TCP sockets (like
+//! web browsers) can connect.
+//!
+//! The WebSocket connection carries the same byte stream as a TCP connection
+//! would, starting from transport negotiation, split into binary messages.
+//! Secure WebSockets (`wss://`) are encrypted by TLS underneath the WebSocket
+//! layer, so the transport mode negotiated inside of it is always plain.
+
+use std::{
+    io::{Error, ErrorKind},
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use futures_util::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{
+    tungstenite::{Error as WsError, Message},
+    WebSocketStream,
+};
+
+use crate::tls::{ClientTls, ServerTls, Transport};
+
+/// Converts a WebSocket error into an IO error.
+fn to_io_error(err: WsError) -> Error {
+    match err {
+        WsError::Io(err) => err,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => ErrorKind::BrokenPipe.into(),
+        other => Error::new(ErrorKind::Other, other),
+    }
+}
+
+/// A byte stream carried over binary WebSocket messages.
+///
+/// Writes are buffered until the transport is flushed, at which point the
+/// buffered bytes are sent as a single message. Writes never return
+/// [Poll::Pending], so writers layered on top that can't resume partial
+/// writes (like [crate::encryption::AsyncEncryptor]) stay consistent;
+/// back pressure is applied when flushing instead.
+pub struct WsTransport<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    write_buf: Vec<u8>,
+}
+
+impl<S> WsTransport<S> {
+    /// Wraps an established WebSocket stream.
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WsTransport<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.read_pos >= self.read_buf.len() {
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    self.read_buf = data;
+                    self.read_pos = 0;
+                }
+                Some(Ok(Message::Text(_))) => {
+                    let msg = "text WebSocket messages are not supported";
+                    return Poll::Ready(Err(Error::new(ErrorKind::InvalidData, msg)));
+                }
+                // pings are answered by the WebSocket stream itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Err(err)) => return Poll::Ready(Err(to_io_error(err))),
+            }
+        }
+
+        let this = &mut *self;
+        let available = &this.read_buf[this.read_pos..];
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        this.read_pos += len;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WsTransport<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.write_buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if !self.write_buf.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(to_io_error)?;
+            let data = std::mem::take(&mut self.write_buf);
+            Pin::new(&mut self.inner)
+                .start_send(Message::Binary(data))
+                .map_err(to_io_error)?;
+        }
+
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(to_io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(to_io_error)
+    }
+}
+
+/// Accepts a WebSocket connection on a stream, performing a TLS handshake
+/// first if configured.
+pub async fn accept<S: Transport + 'static>(
+    stream: S,
+    tls: Option<&ServerTls>,
+) -> std::io::Result<WsTransport<Box<dyn Transport>>> {
+    let stream: Box<dyn Transport> = match tls {
+        Some(tls) => tls.wrap(stream).await?,
+        None => Box::new(stream),
+    };
+
+    let ws = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(to_io_error)?;
+
+    Ok(WsTransport::new(ws))
+}
+
+/// Opens a WebSocket connection to the given URL on a connected stream.
+///
+/// `wss://` URLs require client TLS, which is used to verify the server's
+/// certificate against `server_name`.
+pub async fn connect<S: Transport + 'static>(
+    stream: S,
+    url: &str,
+    server_name: &str,
+    tls: Option<&ClientTls>,
+) -> std::io::Result<WsTransport<Box<dyn Transport>>> {
+    let stream: Box<dyn Transport> = match (url.starts_with("wss://"), tls) {
+        (true, Some(tls)) => tls.wrap(stream, server_name).await?,
+        (true, None) => {
+            let msg = "secure WebSocket URL given without client TLS";
+            return Err(Error::new(ErrorKind::InvalidInput, msg));
+        }
+        (false, _) => Box::new(stream),
+    };
+
+    let (ws, _response) = tokio_tungstenite::client_async(url, stream)
+        .await
+        .map_err(to_io_error)?;
+
+    Ok(WsTransport::new(ws))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_schema::{
+        protocol::{CapOperation, LocalCapOperation},
+        Permissions,
+    };
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::auth::{login, ServerAuthenticator, SessionKey};
+    use crate::connection::Connection;
+    use crate::encryption::{AsyncDecryptor, AsyncEncryptor, Key};
+    use crate::tls;
+
+    const PASSWORD: &[u8] = b"deadbeef";
+
+    /// Starts a connection on an authenticated transport and gives the
+    /// other end a root cap.
+    fn begin(transport: Box<dyn Transport>, session_key: &SessionKey, client: bool) -> Connection {
+        let client_key = Key::from_client_session(session_key);
+        let server_key = Key::from_server_session(session_key);
+        let (rx_key, tx_key) = if client {
+            (server_key, client_key)
+        } else {
+            (client_key, server_key)
+        };
+
+        let (rx, tx) = tokio::io::split(transport);
+        let rx = AsyncDecryptor::new(&rx_key, rx);
+        let tx = AsyncEncryptor::new(&tx_key, tx);
+        let conn = Connection::new(rx, tx);
+
+        let id = if client { 1 } else { 2 };
+        let perms = Permissions::SEND;
+        let ops = [
+            LocalCapOperation::DeclareCap { id, perms },
+            LocalCapOperation::SetRootCap { id },
+        ];
+
+        for op in ops {
+            conn.op_tx.send(CapOperation::Local(op)).unwrap();
+        }
+
+        conn
+    }
+
+    /// Asserts that the next two ops declare and set the given root cap.
+    async fn expect_root_cap(conn: &Connection, id: u32) {
+        let declared = conn.op_rx.recv_async().await.unwrap();
+        let expected = LocalCapOperation::DeclareCap {
+            id,
+            perms: Permissions::SEND,
+        };
+        assert_eq!(declared, CapOperation::Local(expected));
+
+        let set = conn.op_rx.recv_async().await.unwrap();
+        let expected = LocalCapOperation::SetRootCap { id };
+        assert_eq!(set, CapOperation::Local(expected));
+    }
+
+    #[tokio::test]
+    async fn loopback_root_cap_exchange() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let authenticator = ServerAuthenticator::from_password(PASSWORD).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let ws = accept(socket, None).await.unwrap();
+            let mut transport = tls::accept(ws, None).await.unwrap();
+            let session_key = authenticator
+                .login(&mut transport)
+                .await
+                .unwrap()
+                .session_key;
+
+            let conn = begin(transport, &session_key, false);
+            expect_root_cap(&conn, 1).await;
+            conn
+        });
+
+        let url = format!("ws://{}", addr);
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let ws = connect(socket, &url, "localhost", None).await.unwrap();
+        let mut transport = tls::connect(ws, "localhost", None).await.unwrap();
+        let session_key = login(&mut transport, "", PASSWORD).await.unwrap();
+
+        let conn = begin(transport, &session_key, true);
+        expect_root_cap(&conn, 2).await;
+        let _server_conn = server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn secure_url_requires_tls() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let url = format!("wss://{}", addr);
+        let err = connect(socket, &url, "localhost", None)
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+    }
+}
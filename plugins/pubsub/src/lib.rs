@@ -0,0 +1,542 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use hearth_runtime::{
+    async_trait, cargo_process_metadata,
+    flue::{
+        CapabilityHandle, CapabilityRef, OwnedCapability, OwnedTableSignal, Permissions, Table,
+    },
+    hearth_schema::pubsub::*,
+    process::{Process, ProcessMetadata},
+    runtime::{Plugin, Runtime, RuntimeBuilder},
+    tokio::{self, sync::Notify},
+    tracing::{debug, warn, Instrument},
+    utils::{
+        ProcessRunner, RequestInfo, RequestResponseProcess, ResponseInfo, RunnerContext,
+        ServiceRunner,
+    },
+};
+
+/// A plugin that provides runtime-wide publish-subscribe topics to guests.
+///
+/// Adds the [PubSubFactory] service.
+pub struct PubSubPlugin {
+    /// The maximum number of undelivered messages per subscriber.
+    pub queue_len: usize,
+}
+
+impl Default for PubSubPlugin {
+    fn default() -> Self {
+        Self { queue_len: 256 }
+    }
+}
+
+impl Plugin for PubSubPlugin {
+    fn build(&mut self, builder: &mut RuntimeBuilder) {
+        builder.add_plugin(PubSubFactory {
+            queue_len: self.queue_len,
+        });
+    }
+}
+
+/// Creates new [Topic] processes on request.
+pub struct PubSubFactory {
+    queue_len: usize,
+}
+
+#[async_trait]
+impl RequestResponseProcess for PubSubFactory {
+    type Request = PubSubRequest;
+    type Response = PubSubResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, Self::Request>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match request.data {
+            PubSubRequest::CreateTopic => {}
+        }
+
+        let Some(owner) = request.cap_args.first() else {
+            return PubSubError::MissingOwner.into();
+        };
+
+        if !owner.get_permissions().contains(Permissions::MONITOR) {
+            return PubSubError::OwnerNotMonitorable.into();
+        }
+
+        let mut meta = cargo_process_metadata!();
+        meta.name = Some("Topic".to_string());
+
+        let topic = Topic {
+            owner: owner.to_owned(),
+            queue_len: self.queue_len,
+            subscribers: HashMap::new(),
+        };
+
+        let child = request.spawn(meta, topic);
+
+        ResponseInfo {
+            data: Ok(()),
+            caps: vec![child],
+        }
+    }
+}
+
+impl ServiceRunner for PubSubFactory {
+    const NAME: &'static str = PUBSUB_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        cargo_process_metadata!()
+    }
+}
+
+/// A single pub-sub topic.
+///
+/// Each subscriber is delivered to by its own task so that a slow subscriber
+/// can't hold up publishers or the other subscribers.
+pub struct Topic {
+    owner: OwnedCapability,
+    queue_len: usize,
+    subscribers: HashMap<CapabilityHandle, Arc<SubscriberQueue>>,
+}
+
+#[async_trait]
+impl ProcessRunner for Topic {
+    async fn run(mut self, label: String, runtime: Arc<Runtime>, ctx: &Process) {
+        let table = ctx.borrow_table();
+        let owner = table.import_owned(self.owner.clone()).unwrap();
+        let owner = table.wrap_handle(owner).unwrap();
+
+        if let Err(err) = owner.monitor(ctx.borrow_parent()) {
+            warn!("{:?} failed to monitor owner: {:?}", label, err);
+            return;
+        }
+
+        let owner_key = key_of(&owner);
+        drop(owner);
+
+        loop {
+            use OwnedTableSignal::*;
+            match ctx.borrow_parent().recv_owned().await {
+                Some(Message { data, caps }) => {
+                    let request: TopicRequest = match serde_json::from_slice(&data) {
+                        Ok(request) => request,
+                        Err(err) => {
+                            debug!("{:?} failed to parse request: {:?}", label, err);
+                            continue;
+                        }
+                    };
+
+                    self.on_request(table, &runtime, ctx, request, &caps);
+                }
+                Some(Down { handle }) => {
+                    let key = key_of(&handle);
+
+                    if key == owner_key {
+                        table.dec_ref(key).unwrap();
+                        break;
+                    }
+
+                    self.remove(table, key);
+                    table.dec_ref(key).unwrap();
+                }
+                None => break,
+            }
+        }
+
+        table.dec_ref(owner_key).unwrap();
+
+        for (key, queue) in self.subscribers.drain() {
+            queue.close();
+            table.dec_ref(key).unwrap();
+        }
+    }
+}
+
+impl Topic {
+    fn on_request(
+        &mut self,
+        table: &Table,
+        runtime: &Arc<Runtime>,
+        ctx: &Process,
+        request: TopicRequest,
+        caps: &[CapabilityRef],
+    ) {
+        let sub = caps.first();
+
+        match request {
+            TopicRequest::Publish { payload } => {
+                // forget subscribers that could no longer be delivered to
+                let failed: Vec<_> = self
+                    .subscribers
+                    .iter()
+                    .filter(|(_, queue)| queue.is_closed())
+                    .map(|(key, _)| *key)
+                    .collect();
+
+                for key in failed {
+                    self.remove(table, key);
+                }
+
+                let payload: Arc<[u8]> = payload.into();
+                for queue in self.subscribers.values() {
+                    queue.push(payload.clone());
+                }
+            }
+            TopicRequest::Subscribe => {
+                let Some(sub) = sub else {
+                    debug!("Subscribe message is missing capability");
+                    return;
+                };
+
+                self.subscribe(table, runtime, ctx, sub);
+            }
+            TopicRequest::Unsubscribe => {
+                let Some(sub) = sub else {
+                    debug!("Unsubscribe message is missing capability");
+                    return;
+                };
+
+                let key = key_of(sub);
+                self.remove(table, key);
+                table.dec_ref(key).unwrap();
+            }
+        }
+    }
+
+    fn subscribe(
+        &mut self,
+        table: &Table,
+        runtime: &Arc<Runtime>,
+        ctx: &Process,
+        sub: &CapabilityRef,
+    ) {
+        if !sub.get_permissions().contains(Permissions::SEND) {
+            debug!("Topic subscriber doesn't permit send");
+            return;
+        }
+
+        let key = key_of(sub);
+        if self.subscribers.contains_key(&key) {
+            // already subscribed; release the duplicate key
+            table.dec_ref(key).unwrap();
+            return;
+        }
+
+        if sub.get_permissions().contains(Permissions::MONITOR) {
+            sub.monitor(ctx.borrow_parent()).unwrap();
+        }
+
+        let queue = Arc::new(SubscriberQueue::new(self.queue_len));
+        self.subscribers.insert(key, queue.clone());
+
+        let sub = sub.demote(Permissions::SEND).unwrap().to_owned();
+        let post = runtime.post.clone();
+
+        tokio::spawn(
+            async move {
+                let table = Table::new(post);
+                let sub = table.import_owned(sub).unwrap();
+                let sub = table.wrap_handle(sub).unwrap();
+
+                while let Some(payload) = queue.pop().await {
+                    if let Err(err) = sub.send(&payload, &[]).await {
+                        debug!("Topic delivery error: {:?}", err);
+                        queue.close();
+                        break;
+                    }
+                }
+
+                let dropped = queue.dropped();
+                if dropped > 0 {
+                    debug!("Topic subscriber missed {} messages", dropped);
+                }
+            }
+            .instrument(ctx.span()),
+        );
+    }
+
+    /// Removes a subscriber by its key, if it's subscribed.
+    fn remove(&mut self, table: &Table, key: CapabilityHandle) {
+        if let Some(queue) = self.subscribers.remove(&key) {
+            queue.close();
+            table.dec_ref(key).unwrap();
+        }
+    }
+}
+
+/// Gets a permissionless handle identifying a capability's route.
+///
+/// The returned handle holds a reference that must be manually freed.
+fn key_of(cap: &CapabilityRef) -> CapabilityHandle {
+    cap.demote(Permissions::empty()).unwrap().into_handle()
+}
+
+/// A bounded queue of undelivered messages to a single subscriber.
+///
+/// When the queue is full, the oldest message is dropped to make room.
+pub struct SubscriberQueue {
+    capacity: usize,
+    state: Mutex<QueueState>,
+    notify: Notify,
+}
+
+#[derive(Default)]
+struct QueueState {
+    messages: VecDeque<Arc<[u8]>>,
+    dropped: usize,
+    closed: bool,
+}
+
+impl SubscriberQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            state: Default::default(),
+            notify: Notify::new(),
+        }
+    }
+
+    /// Enqueues a message, dropping the oldest one if the queue is full.
+    pub fn push(&self, message: Arc<[u8]>) {
+        let mut state = self.state.lock().unwrap();
+
+        if state.closed {
+            return;
+        }
+
+        if state.messages.len() == self.capacity {
+            state.messages.pop_front();
+            state.dropped += 1;
+        }
+
+        state.messages.push_back(message);
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next message. Returns `None` once the queue is closed.
+    pub async fn pop(&self) -> Option<Arc<[u8]>> {
+        loop {
+            {
+                let mut state = self.state.lock().unwrap();
+
+                if state.closed {
+                    return None;
+                }
+
+                if let Some(message) = state.messages.pop_front() {
+                    return Some(message);
+                }
+            }
+
+            self.notify.notified().await;
+        }
+    }
+
+    /// Closes the queue, discarding any undelivered messages.
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.messages.clear();
+        drop(state);
+        self.notify.notify_one();
+    }
+
+    /// Returns true if the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Returns how many messages have been dropped because the queue was full.
+    pub fn dropped(&self) -> usize {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_runtime::testing::{TestRuntime, TestRuntimeBuilder};
+    use serde_json::json;
+
+    fn create_topic<'a>(runtime: &'a TestRuntime, owner: &CapabilityRef) -> CapabilityRef<'a> {
+        let factory = runtime.get_service(PUBSUB_SERVICE).unwrap();
+        let (result, mut caps) =
+            runtime.request::<_, PubSubResponse>(&factory, &PubSubRequest::CreateTopic, &[owner]);
+
+        assert_eq!(result, Ok(()));
+        caps.remove(0)
+    }
+
+    fn publish(runtime: &TestRuntime, topic: &CapabilityRef, value: serde_json::Value) {
+        let payload = serde_json::to_vec(&value).unwrap();
+        runtime.send(topic, &TopicRequest::Publish { payload }, &[]);
+    }
+
+    #[test]
+    fn queue_drops_oldest() {
+        let queue = SubscriberQueue::new(2);
+        for message in [b"a", b"b", b"c"] {
+            queue.push(Arc::from(&message[..]));
+        }
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(rt.block_on(queue.pop()).as_deref(), Some(&b"b"[..]));
+        assert_eq!(rt.block_on(queue.pop()).as_deref(), Some(&b"c"[..]));
+        assert_eq!(queue.dropped(), 1);
+
+        queue.close();
+        assert!(queue.is_closed());
+        assert_eq!(rt.block_on(queue.pop()), None);
+    }
+
+    #[test]
+    fn missing_owner_is_refused() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(PubSubPlugin::default());
+        let runtime = builder.build();
+
+        let factory = runtime.get_service(PUBSUB_SERVICE).unwrap();
+        let (result, _) =
+            runtime.request::<_, PubSubResponse>(&factory, &PubSubRequest::CreateTopic, &[]);
+        assert_eq!(result, Err(PubSubError::MissingOwner));
+    }
+
+    #[test]
+    fn subscribers_receive_in_order() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(PubSubPlugin::default());
+        let runtime = builder.build();
+
+        let owner = runtime.mailbox();
+        let owner_cap = owner.capability(Permissions::MONITOR);
+        let topic = create_topic(&runtime, &owner_cap);
+
+        let first = runtime.mailbox();
+        let second = runtime.mailbox();
+        for sub in [&first, &second] {
+            let cap = sub.capability(Permissions::SEND | Permissions::MONITOR);
+            runtime.send(&topic, &TopicRequest::Subscribe, &[&cap]);
+        }
+
+        for index in 0..4 {
+            publish(&runtime, &topic, json!(index));
+        }
+
+        for sub in [&first, &second] {
+            for index in 0..4 {
+                let (value, _) = sub.recv_json::<serde_json::Value>();
+                assert_eq!(value, json!(index));
+            }
+        }
+    }
+
+    #[test]
+    fn unsubscribe_stops_delivery() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(PubSubPlugin::default());
+        let runtime = builder.build();
+
+        let owner = runtime.mailbox();
+        let owner_cap = owner.capability(Permissions::MONITOR);
+        let topic = create_topic(&runtime, &owner_cap);
+
+        let leaving = runtime.mailbox();
+        let leaving_cap = leaving.capability(Permissions::SEND);
+        let staying = runtime.mailbox();
+        let staying_cap = staying.capability(Permissions::SEND);
+        runtime.send(&topic, &TopicRequest::Subscribe, &[&leaving_cap]);
+        runtime.send(&topic, &TopicRequest::Subscribe, &[&staying_cap]);
+        runtime.send(&topic, &TopicRequest::Unsubscribe, &[&leaving_cap]);
+
+        publish(&runtime, &topic, json!("hello"));
+        assert_eq!(staying.recv_json::<serde_json::Value>().0, json!("hello"));
+
+        // a message published while unsubscribed must never arrive
+        runtime.send(&topic, &TopicRequest::Subscribe, &[&leaving_cap]);
+        publish(&runtime, &topic, json!("again"));
+        assert_eq!(leaving.recv_json::<serde_json::Value>().0, json!("again"));
+    }
+
+    /// Subscribes the topic in its first initial capability to its parent
+    /// mailbox, tells its second that it's ready, then forwards it the first
+    /// published message.
+    const SUBSCRIBER_GUEST: &str = r#"
+        (module
+            (import "hearth::mailbox" "make_capability"
+                (func $make_capability (param i32 i32) (result i32)))
+            (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+            (import "hearth::mailbox" "get_message_data_len"
+                (func $data_len (param i32) (result i32)))
+            (import "hearth::mailbox" "get_message_data"
+                (func $get_message_data (param i32 i32)))
+            (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 16) "\"Subscribe\"")
+            (data (i32.const 32) "ready")
+            (func (export "run")
+                (local $signal i32)
+                (i32.store (i32.const 0) (call $make_capability (i32.const 0) (i32.const 1)))
+                (call $send (i32.const 0) (i32.const 16) (i32.const 11) (i32.const 0) (i32.const 1))
+                (call $send (i32.const 1) (i32.const 32) (i32.const 5) (i32.const 0) (i32.const 0))
+                (local.set $signal (call $recv (i32.const 0)))
+                (call $get_message_data (local.get $signal) (i32.const 64))
+                (call $send (i32.const 1)
+                    (i32.const 64) (call $data_len (local.get $signal))
+                    (i32.const 0) (i32.const 0))))
+    "#;
+
+    /// Publishes "hi" to the topic in its first initial capability.
+    const PUBLISHER_GUEST: &str = r#"
+        (module
+            (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{\"Publish\":{\"payload\":[104,105]}}")
+            (func (export "run")
+                (call $send (i32.const 0) (i32.const 0) (i32.const 33) (i32.const 0) (i32.const 0))))
+    "#;
+
+    #[test]
+    fn guests_share_topic() {
+        let config = toml::from_str("wasm.allow = [\"*\"]").unwrap();
+        let mut builder = TestRuntimeBuilder::with_config(config);
+        builder.add_plugin(PubSubPlugin::default());
+        builder.add_plugin(hearth_wasm::WasmPlugin::default());
+        let runtime = builder.build();
+
+        let owner = runtime.mailbox();
+        let owner_cap = owner.capability(Permissions::MONITOR);
+        let topic = create_topic(&runtime, &owner_cap);
+
+        let report = runtime.mailbox();
+        let report_cap = report.capability(Permissions::SEND);
+        let _subscriber = runtime.spawn_wasm_with(SUBSCRIBER_GUEST, &[&topic, &report_cap]);
+        assert_eq!(report.recv().0, b"ready");
+
+        let _publisher = runtime.spawn_wasm_with(PUBLISHER_GUEST, &[&topic]);
+        assert_eq!(report.recv().0, b"hi");
+    }
+}
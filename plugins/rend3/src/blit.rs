@@ -0,0 +1,234 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! A simple routine for copying one render target to another of a different
+//! size.
+
+use rend3::graph::{RenderGraph, RenderPassTarget, RenderPassTargets, RenderTargetHandle};
+use rend3::types::{SampleCount, TextureHandle};
+use wgpu::*;
+
+/// Stretches a single-sampled render target onto another with linear
+/// filtering.
+pub struct BlitRoutine {
+    shader: ShaderModule,
+    bgl: BindGroupLayout,
+    layout: PipelineLayout,
+    pipeline: RenderPipeline,
+    sampler: Sampler,
+    format: TextureFormat,
+}
+
+impl BlitRoutine {
+    /// Creates a blit routine targeting the given format and sample count.
+    pub fn new(device: &Device, format: TextureFormat, sample_count: SampleCount) -> Self {
+        let shader = device.create_shader_module(&include_wgsl!("blit.wgsl"));
+
+        let bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("blit bind group layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+
+        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("blit pipeline layout"),
+            bind_group_layouts: &[&bgl],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = Self::create_pipeline(device, &shader, &layout, format, sample_count);
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("blit sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            shader,
+            bgl,
+            layout,
+            pipeline,
+            sampler,
+            format,
+        }
+    }
+
+    /// Rebuilds the pipeline to target a new sample count.
+    pub fn set_sample_count(&mut self, device: &Device, sample_count: SampleCount) {
+        self.pipeline = Self::create_pipeline(
+            device,
+            &self.shader,
+            &self.layout,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Adds a node blitting `src` onto `dst` to a render graph.
+    ///
+    /// If `dst` is multisampled, `resolve` is the single-sampled target to
+    /// resolve it to.
+    pub fn add_to_graph<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        src: RenderTargetHandle,
+        dst: RenderTargetHandle,
+        resolve: Option<RenderTargetHandle>,
+    ) {
+        let mut builder = graph.add_node("blit");
+        let src_handle = builder.add_render_target_input(src);
+        let dst_handle = builder.add_render_target_output(dst);
+        let resolve_handle = resolve.map(|resolve| builder.add_render_target_output(resolve));
+
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: dst_handle,
+                clear: Color::BLACK,
+                resolve: resolve_handle,
+            }],
+            depth_stencil: None,
+        });
+
+        let this = builder.passthrough_ref(self);
+
+        builder.build(
+            move |pt, renderer, encoder_or_pass, temps, _ready, graph_data| {
+                let this = pt.get(this);
+                let rpass = encoder_or_pass.get_rpass(rpass_handle);
+                let src = graph_data.get_render_target(src_handle);
+                let bind_group = temps.add(this.create_bind_group(&renderer.device, src));
+                rpass.set_pipeline(&this.pipeline);
+                rpass.set_bind_group(0, bind_group, &[]);
+                rpass.draw(0..3, 0..1);
+            },
+        );
+    }
+
+    /// Adds a node blitting `src` onto a rend3 2D texture to a render graph.
+    ///
+    /// rend3 creates its textures as render attachments so that it can
+    /// generate their mipmaps, which lets this draw straight into them. The
+    /// texture must have a single mip level.
+    pub fn add_to_texture<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        src: RenderTargetHandle,
+        dst: TextureHandle,
+    ) {
+        let mut builder = graph.add_node("blit to texture");
+        let src_handle = builder.add_render_target_input(src);
+        builder.add_external_output();
+        let this = builder.passthrough_ref(self);
+
+        builder.build(
+            move |pt, renderer, encoder_or_pass, _temps, _ready, graph_data| {
+                let this = pt.get(this);
+                let encoder = encoder_or_pass.get_encoder();
+                let src = graph_data.get_render_target(src_handle);
+                let dst = graph_data.d2_texture_manager.get_view(dst.get_raw());
+                let bind_group = this.create_bind_group(&renderer.device, src);
+
+                let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: Some("blit to texture"),
+                    color_attachments: &[RenderPassColorAttachment {
+                        view: dst,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Clear(Color::TRANSPARENT),
+                            store: true,
+                        },
+                    }],
+                    depth_stencil_attachment: None,
+                });
+
+                rpass.set_pipeline(&this.pipeline);
+                rpass.set_bind_group(0, &bind_group, &[]);
+                rpass.draw(0..3, 0..1);
+            },
+        );
+    }
+
+    fn create_bind_group(&self, device: &Device, src: &TextureView) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("blit bind group"),
+            layout: &self.bgl,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(src),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        })
+    }
+
+    fn create_pipeline(
+        device: &Device,
+        shader: &ShaderModule,
+        layout: &PipelineLayout,
+        format: TextureFormat,
+        sample_count: SampleCount,
+    ) -> RenderPipeline {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("blit pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: sample_count as u32,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        })
+    }
+}
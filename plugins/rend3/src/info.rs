@@ -0,0 +1,270 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Guest-facing information about the renderer's GPU and settings.
+
+use hearth_runtime::flue::Table;
+use hearth_runtime::hearth_schema::renderer::*;
+use hearth_runtime::process::ProcessMetadata;
+use hearth_runtime::tracing::debug;
+use hearth_runtime::utils::{RequestInfo, RequestResponseProcess, ResponseInfo, ServiceRunner};
+use hearth_runtime::{async_trait, cargo_process_metadata};
+use rend3::types::SampleCount;
+use rend3::InstanceAdapterDevice;
+use tokio::sync::watch;
+use wgpu::{Backend, DeviceType, Features, TextureFormat};
+
+/// Describes a renderer's adapter, device, and current settings.
+pub fn renderer_info(
+    iad: &InstanceAdapterDevice,
+    surface_format: TextureFormat,
+    sample_count: SampleCount,
+    resolution_scale: f32,
+) -> RendererInfo {
+    let adapter = iad.adapter.get_info();
+    let limits = iad.device.limits();
+    let features = iad.device.features();
+
+    RendererInfo {
+        adapter: AdapterInfo {
+            name: adapter.name,
+            vendor: adapter.vendor.try_into().unwrap_or(0),
+            device: adapter.device.try_into().unwrap_or(0),
+            device_type: adapter_type(adapter.device_type),
+            backend: graphics_backend(adapter.backend),
+        },
+        limits: RendererLimits {
+            max_texture_dimension_2d: limits.max_texture_dimension_2d,
+            max_texture_dimension_3d: limits.max_texture_dimension_3d,
+            max_texture_array_layers: limits.max_texture_array_layers,
+            max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size,
+            max_uniform_buffer_binding_size: limits.max_uniform_buffer_binding_size,
+        },
+        features: RendererFeatures {
+            timestamp_query: features.contains(Features::TIMESTAMP_QUERY),
+            texture_compression_bc: features.contains(Features::TEXTURE_COMPRESSION_BC),
+            texture_compression_etc2: features.contains(Features::TEXTURE_COMPRESSION_ETC2),
+            texture_compression_astc: features.contains(Features::TEXTURE_COMPRESSION_ASTC_LDR),
+            polygon_mode_line: features.contains(Features::POLYGON_MODE_LINE),
+        },
+        surface_format: format!("{:?}", surface_format),
+        sample_count: sample_count as u32,
+        resolution_scale,
+    }
+}
+
+/// Converts a wgpu device type to its guest-facing [AdapterType].
+fn adapter_type(device_type: DeviceType) -> AdapterType {
+    match device_type {
+        DeviceType::IntegratedGpu => AdapterType::Integrated,
+        DeviceType::DiscreteGpu => AdapterType::Discrete,
+        DeviceType::VirtualGpu => AdapterType::Virtual,
+        DeviceType::Cpu => AdapterType::Cpu,
+        DeviceType::Other => AdapterType::Other,
+    }
+}
+
+/// Converts a wgpu backend to its guest-facing [GraphicsBackend].
+fn graphics_backend(backend: Backend) -> GraphicsBackend {
+    match backend {
+        Backend::Vulkan => GraphicsBackend::Vulkan,
+        Backend::Metal => GraphicsBackend::Metal,
+        Backend::Dx12 => GraphicsBackend::Dx12,
+        Backend::Dx11 => GraphicsBackend::Dx11,
+        Backend::Gl => GraphicsBackend::Gl,
+        Backend::BrowserWebGpu => GraphicsBackend::WebGpu,
+        Backend::Empty => GraphicsBackend::Other,
+    }
+}
+
+/// Provides information about the renderer to guests. Accepts
+/// [RendererInfoRequest].
+pub struct RendererInfoService {
+    info: watch::Receiver<RendererInfo>,
+}
+
+impl RendererInfoService {
+    /// Creates a service that follows a renderer's info.
+    pub fn new(info: watch::Receiver<RendererInfo>) -> Self {
+        Self { info }
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for RendererInfoService {
+    type Request = RendererInfoRequest;
+    type Response = RendererInfoResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, RendererInfoRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match request.data {
+            RendererInfoRequest::GetInfo => {
+                let info = self.info.borrow().clone();
+                Ok(RendererInfoSuccess::Info(info)).into()
+            }
+            RendererInfoRequest::Subscribe => {
+                let Some(subscriber) = request.cap_args.first() else {
+                    return RendererInfoError::MissingCapability.into();
+                };
+
+                let subscriber = subscriber.to_owned();
+                let post = request.runtime.post.clone();
+                let mut info = self.info.clone();
+
+                tokio::spawn(async move {
+                    let table = Table::new(post);
+                    let subscriber = table.import_owned(subscriber).unwrap();
+                    let subscriber = table.wrap_handle(subscriber).unwrap();
+
+                    loop {
+                        let data = serde_json::to_vec(&*info.borrow_and_update()).unwrap();
+                        if let Err(err) = subscriber.send(&data, &[]).await {
+                            debug!("Renderer info subscriber error: {:?}", err);
+                            break;
+                        }
+
+                        // the renderer is gone
+                        if info.changed().await.is_err() {
+                            break;
+                        }
+                    }
+                });
+
+                Ok(RendererInfoSuccess::Subscribed).into()
+            }
+        }
+    }
+}
+
+impl ServiceRunner for RendererInfoService {
+    const NAME: &'static str = RENDERER_INFO_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Provides the renderer's GPU, limits, and settings. Accepts RendererInfoRequest."
+                .to_string(),
+        );
+
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_runtime::flue::Permissions;
+    use hearth_runtime::testing::{TestRuntime, TestRuntimeBuilder};
+
+    fn test_info(sample_count: u32) -> RendererInfo {
+        RendererInfo {
+            adapter: AdapterInfo {
+                name: "Test Adapter".to_string(),
+                vendor: 0x10de,
+                device: 0x2204,
+                device_type: AdapterType::Discrete,
+                backend: GraphicsBackend::Vulkan,
+            },
+            limits: RendererLimits {
+                max_texture_dimension_2d: 8192,
+                max_texture_dimension_3d: 2048,
+                max_texture_array_layers: 256,
+                max_storage_buffer_binding_size: 128 << 20,
+                max_uniform_buffer_binding_size: 64 << 10,
+            },
+            features: RendererFeatures::default(),
+            surface_format: "Bgra8UnormSrgb".to_string(),
+            sample_count,
+            resolution_scale: 1.0,
+        }
+    }
+
+    fn test_runtime(info: watch::Receiver<RendererInfo>) -> TestRuntime {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(RendererInfoService::new(info));
+        builder.build()
+    }
+
+    #[test]
+    fn wgpu_types_convert() {
+        assert_eq!(
+            adapter_type(DeviceType::IntegratedGpu),
+            AdapterType::Integrated
+        );
+        assert_eq!(adapter_type(DeviceType::Cpu), AdapterType::Cpu);
+        assert_eq!(
+            graphics_backend(Backend::BrowserWebGpu),
+            GraphicsBackend::WebGpu
+        );
+        assert_eq!(graphics_backend(Backend::Empty), GraphicsBackend::Other);
+    }
+
+    #[test]
+    fn get_info_returns_current() {
+        let (tx, rx) = watch::channel(test_info(1));
+        let runtime = test_runtime(rx);
+        let service = runtime.get_service(RENDERER_INFO_SERVICE).unwrap();
+
+        tx.send_replace(test_info(4));
+
+        let request = RendererInfoRequest::GetInfo;
+        let (response, _) = runtime.request::<_, RendererInfoResponse>(&service, &request, &[]);
+        assert!(matches!(response, Ok(RendererInfoSuccess::Info(info)) if info == test_info(4)));
+    }
+
+    #[test]
+    fn subscribe_requires_capability() {
+        let (_tx, rx) = watch::channel(test_info(1));
+        let runtime = test_runtime(rx);
+        let service = runtime.get_service(RENDERER_INFO_SERVICE).unwrap();
+
+        let request = RendererInfoRequest::Subscribe;
+        let (response, _) = runtime.request::<_, RendererInfoResponse>(&service, &request, &[]);
+        assert!(matches!(
+            response,
+            Err(RendererInfoError::MissingCapability)
+        ));
+    }
+
+    #[test]
+    fn subscribers_follow_changes() {
+        let (tx, rx) = watch::channel(test_info(1));
+        let runtime = test_runtime(rx);
+        let service = runtime.get_service(RENDERER_INFO_SERVICE).unwrap();
+
+        let subscriber = runtime.mailbox();
+        let subscriber_cap = subscriber.capability(Permissions::SEND);
+        let request = RendererInfoRequest::Subscribe;
+        let (response, _) =
+            runtime.request::<_, RendererInfoResponse>(&service, &request, &[&subscriber_cap]);
+        assert!(matches!(response, Ok(RendererInfoSuccess::Subscribed)));
+
+        let (info, _) = subscriber.recv_json::<RendererInfo>();
+        assert_eq!(info, test_info(1));
+
+        tx.send_replace(test_info(4));
+        let (info, _) = subscriber.recv_json::<RendererInfo>();
+        assert_eq!(info.sample_count, 4);
+
+        runtime.settle();
+        assert!(subscriber.try_recv().is_none());
+    }
+}
@@ -16,27 +16,52 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
+use std::num::NonZeroU32;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use glam::{UVec2, Vec4};
 use hearth_runtime::runtime::{Plugin, RuntimeBuilder};
+use hearth_runtime::tracing::{error, warn};
 use rend3::graph::{ReadyData, RenderGraph};
-use rend3::types::{Camera, SampleCount, TextureHandle};
+use rend3::types::{Camera, CameraProjection, SampleCount, TextureHandle};
 use rend3::util::output::OutputFrame;
-use rend3::{InstanceAdapterDevice, Renderer};
+use rend3::{InstanceAdapterDevice, Renderer, RendererInitializationError, RendererMode};
 use rend3_routine::base::{BaseRenderGraph, BaseRenderGraphIntermediateState};
 use rend3_routine::pbr::PbrRoutine;
 use rend3_routine::skybox::SkyboxRoutine;
 use rend3_routine::tonemapping::TonemappingRoutine;
-use tokio::sync::{mpsc, oneshot};
-use wgpu::TextureFormat;
+
+use blit::BlitRoutine;
+use hearth_runtime::hearth_schema::renderer::{FrameTimings, RendererInfo};
+use info::RendererInfoService;
+use targets::{FrameTargets, SharedTarget, SharedTargetRoutine, SharedTargets};
+use timing::{millis, timestamp, GpuProfiler, RenderStats};
+use tokio::sync::{mpsc, oneshot, watch};
+use wgpu::{
+    BufferDescriptor, BufferUsages, CommandEncoderDescriptor, Extent3d, ImageCopyBuffer,
+    ImageDataLayout, Limits, Maintain, MapMode, Texture, TextureDescriptor, TextureDimension,
+    TextureFormat, TextureUsages, TextureView, TextureViewDescriptor,
+};
+use wgpu_core::device::DeviceError;
 
 pub use rend3;
 pub use rend3_routine;
 pub use wgpu;
 
+pub mod blit;
+pub mod info;
+pub mod targets;
+pub mod timing;
 pub mod utils;
 
+/// The format of the HDR color target that the base render graph renders
+/// the scene into before tonemapping.
+///
+/// [Routines][Routine] draw into this target, so their pipelines must use it.
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
 /// The info about a frame passed to [Routine::draw].
 pub struct RoutineInfo<'a, 'graph> {
     pub state: &'a BaseRenderGraphIntermediateState,
@@ -44,10 +69,35 @@ pub struct RoutineInfo<'a, 'graph> {
     pub resolution: UVec2,
     pub ready_data: &'a ReadyData,
     pub graph: &'a mut RenderGraph<'graph>,
+
+    /// The render graph targets of every [SharedTarget] this frame.
+    pub targets: &'a FrameTargets,
 }
 
 pub trait Routine: Send + Sync + 'static {
     fn build_node(&mut self) -> Box<dyn Node<'_> + '_>;
+
+    /// Called when the renderer's sample count changes, before the next
+    /// frame is drawn. Routines should rebuild their pipelines to match.
+    fn set_sample_count(&mut self, _sample_count: SampleCount) {}
+
+    /// Whether this routine always draws at the output's native resolution,
+    /// even when the scene is rendered at a scaled resolution.
+    ///
+    /// When the resolution is scaled, native-resolution routines draw into
+    /// a separate target with its own depth buffer, so they are not occluded
+    /// by the scene.
+    fn native_resolution(&self) -> bool {
+        false
+    }
+
+    /// Called after the GPU device was lost and the renderer was recreated
+    /// on a new one, before the next frame is drawn.
+    ///
+    /// Everything that the routine created on the old device is invalid, so
+    /// routines should recreate their GPU resources on the new renderer's
+    /// device and re-upload their contents from CPU-side copies.
+    fn on_device_reset(&mut self, _renderer: &Arc<Renderer>) {}
 }
 
 pub trait Node<'a> {
@@ -65,8 +115,101 @@ pub struct FrameRequest {
     /// The camera to use for this frame.
     pub camera: Camera,
 
-    /// This oneshot message is sent when the frame is done rendering.
-    pub on_complete: oneshot::Sender<()>,
+    /// This oneshot message is sent when the frame is done rendering, or
+    /// when it's dropped in favor of a newer frame.
+    pub on_complete: oneshot::Sender<FrameOutcome>,
+}
+
+/// What happened to a [FrameRequest].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameOutcome {
+    /// The frame was rendered.
+    Rendered,
+
+    /// The frame was skipped because a newer request for the same output
+    /// was queued before the renderer got to it.
+    Dropped,
+
+    /// The frame was skipped because the GPU device was lost. By the time
+    /// this is sent, the renderer has been recreated on a new device, so the
+    /// output has to be recreated from [Rend3Plugin::subscribe_device]
+    /// before requesting another frame.
+    DeviceReset,
+}
+
+/// The maximum number of [FrameRequests][FrameRequest] that can be queued.
+/// Once the queue is full, producers wait for the renderer to catch up.
+pub const FRAME_QUEUE_DEPTH: usize = 4;
+
+/// The pixel format of a [Capture].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaptureFormat {
+    /// Four bytes per pixel: red, green, blue, and alpha.
+    #[default]
+    Rgba8,
+
+    /// Three bytes per pixel: red, green, and blue.
+    Rgb8,
+}
+
+impl CaptureFormat {
+    /// The number of bytes each pixel takes in this format.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            CaptureFormat::Rgba8 => 4,
+            CaptureFormat::Rgb8 => 3,
+        }
+    }
+}
+
+/// A request to the renderer to capture a frame into CPU-readable memory.
+///
+/// Captures are rendered offscreen using the camera of the last
+/// [FrameRequest], so they don't disturb the interactive frames.
+pub struct CaptureRequest {
+    /// The dimensions of the capture. If `None`, uses the resolution of the
+    /// last drawn frame.
+    pub resolution: Option<UVec2>,
+
+    /// The pixel format to return the capture in.
+    pub format: CaptureFormat,
+
+    /// This oneshot message is sent with the captured pixels. It is dropped
+    /// if the capture fails.
+    pub reply: oneshot::Sender<Capture>,
+}
+
+/// The pixels of a captured frame.
+pub struct Capture {
+    /// The dimensions of the capture.
+    pub resolution: UVec2,
+
+    /// The pixel format of `data`.
+    pub format: CaptureFormat,
+
+    /// Tightly-packed rows of pixels, from top to bottom.
+    pub data: Vec<u8>,
+}
+
+/// An offscreen texture that frames are drawn to in headless mode.
+struct HeadlessTarget {
+    // kept alive for the view
+    _texture: Texture,
+    view: Arc<TextureView>,
+    resolution: UVec2,
+}
+
+impl HeadlessTarget {
+    fn new(device: &wgpu::Device, resolution: UVec2, format: TextureFormat) -> Self {
+        let texture = create_target(device, "headless target", resolution, format);
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        Self {
+            _texture: texture,
+            view: Arc::new(view),
+            resolution,
+        }
+    }
 }
 
 /// An update to the global rend3 state.
@@ -76,12 +219,59 @@ pub enum Rend3Command {
 
     /// Updates the ambient lighting.
     SetAmbient(Vec4),
+
+    /// Updates the multisampling sample count.
+    SetSampleCount(SampleCount),
+
+    /// Updates the scale of the scene's render resolution relative to the
+    /// output resolution. Clamped to [MIN_RESOLUTION_SCALE] and
+    /// [MAX_RESOLUTION_SCALE].
+    SetResolutionScale(f32),
+
+    /// Updates the frame time budget. Frames that take longer log a warning
+    /// with their timings. `None` disables the warning.
+    SetFrameBudget(Option<Duration>),
+
+    /// Recreates the device and renderer as if the device had been lost.
+    ResetDevice,
+}
+
+/// The device and renderer that a [Rend3Plugin] currently draws with.
+///
+/// These are replaced when the plugin recovers from a lost device.
+#[derive(Clone)]
+pub struct RenderDevice {
+    pub iad: InstanceAdapterDevice,
+    pub renderer: Arc<Renderer>,
 }
 
+/// The minimum resolution scale.
+pub const MIN_RESOLUTION_SCALE: f32 = 0.25;
+
+/// The maximum resolution scale.
+pub const MAX_RESOLUTION_SCALE: f32 = 2.0;
+
+/// How long to wait after the first failed attempt to recreate a lost
+/// device. The wait doubles after every further failure.
+pub const DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The longest wait between attempts to recreate a lost device.
+pub const MAX_DEVICE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How many times to try recreating a lost device before giving up.
+pub const MAX_DEVICE_RETRIES: u32 = 8;
+
 /// A rend3 Hearth plugin for adding 3D rendering to a Hearth runtime.
 ///
 /// This plugin can be acquired by other plugins during runtime building to add
 /// more nodes to the render graph.
+///
+/// If the GPU device is lost, such as when the driver resets, the plugin
+/// recreates its device and renderer and calls
+/// [Routine::on_device_reset] on every routine. Plugins that create
+/// resources on [Self::renderer] directly have to follow
+/// [Self::subscribe_device] and recreate them on the new renderer. Rendering
+/// stops if the device can't be recreated after [MAX_DEVICE_RETRIES] tries.
 pub struct Rend3Plugin {
     pub iad: InstanceAdapterDevice,
     pub surface_format: TextureFormat,
@@ -91,20 +281,69 @@ pub struct Rend3Plugin {
     pub tonemapping_routine: TonemappingRoutine,
     pub skybox_routine: SkyboxRoutine,
     pub ambient: Vec4,
-    pub frame_request_tx: mpsc::UnboundedSender<FrameRequest>,
+    pub sample_count: SampleCount,
+    pub resolution_scale: f32,
+    pub stats: Arc<RenderStats>,
+    pub frame_request_tx: mpsc::Sender<FrameRequest>,
+    pub capture_request_tx: mpsc::UnboundedSender<CaptureRequest>,
     pub command_tx: mpsc::UnboundedSender<Rend3Command>,
+    pub targets: SharedTargets,
+    info_tx: watch::Sender<RendererInfo>,
+    device_tx: watch::Sender<RenderDevice>,
+    device_lost: Arc<AtomicBool>,
+    reset_frames: Vec<oneshot::Sender<FrameOutcome>>,
     new_skybox: Option<TextureHandle>,
-    frame_request_rx: mpsc::UnboundedReceiver<FrameRequest>,
+    last_frame: Option<(UVec2, Camera)>,
+    headless: Option<HeadlessTarget>,
+    frame_request_rx: mpsc::Receiver<FrameRequest>,
+    capture_request_rx: mpsc::UnboundedReceiver<CaptureRequest>,
     command_rx: mpsc::UnboundedReceiver<Rend3Command>,
+    blit_routine: BlitRoutine,
+    profiler: Option<GpuProfiler>,
+    target_routine: SharedTargetRoutine,
     routines: Vec<Box<dyn Routine>>,
 }
 
 impl Plugin for Rend3Plugin {
-    fn finalize(mut self, _builder: &mut RuntimeBuilder) {
+    fn finalize(mut self, builder: &mut RuntimeBuilder) {
+        builder.add_plugin(RendererInfoService::new(self.subscribe_info()));
+
         tokio::spawn(async move {
-            while let Some(frame) = self.frame_request_rx.recv().await {
-                self.flush_commands();
-                self.draw(frame);
+            loop {
+                // prefer frames so that queued captures never delay the
+                // interactive frame queue by more than one capture
+                tokio::select! {
+                    biased;
+                    Some(frame) = self.frame_request_rx.recv() => {
+                        let (frames, dropped) = coalesce_frames(
+                            frame,
+                            &mut self.frame_request_rx,
+                            |a, b| same_output(&a.output_frame, &b.output_frame),
+                        );
+
+                        self.stats.record_dropped(dropped.len() as u64);
+                        for frame in dropped {
+                            let _ = frame.on_complete.send(FrameOutcome::Dropped);
+                        }
+
+                        for frame in frames {
+                            self.flush_commands();
+                            self.draw(frame);
+                        }
+                    }
+                    Some(capture) = self.capture_request_rx.recv() => {
+                        self.flush_commands();
+                        self.capture(capture);
+                    }
+                    else => break,
+                }
+
+                if self.is_device_lost() {
+                    if let Err(err) = self.recover().await {
+                        error!("Giving up on rendering: {}", err);
+                        break;
+                    }
+                }
             }
         });
     }
@@ -114,8 +353,72 @@ impl Rend3Plugin {
     /// Creates a new rend3 plugin from an existing [InstanceAdapterDevice] and
     /// the target window's texture format.
     pub fn new(iad: InstanceAdapterDevice, surface_format: TextureFormat) -> Self {
+        Self::try_new(iad, surface_format).unwrap()
+    }
+
+    /// Creates a new rend3 plugin that renders to an offscreen texture
+    /// instead of a window.
+    ///
+    /// If no hardware adapter is available, this falls back to any adapter
+    /// that supports rend3's CPU-powered mode, such as a software renderer.
+    /// Fails if neither is available.
+    pub async fn new_headless(
+        resolution: UVec2,
+        format: TextureFormat,
+    ) -> Result<Self, RendererInitializationError> {
+        let iad = match create_iad(None).await {
+            Ok(iad) => iad,
+            Err(err) => {
+                warn!("No GPU adapter available ({}); trying a fallback", err);
+                create_iad(Some(RendererMode::CPUPowered)).await?
+            }
+        };
+
+        let mut plugin = Self::try_new(iad, format)?;
+        plugin.headless = Some(HeadlessTarget::new(&plugin.iad.device, resolution, format));
+
+        // captures need a camera before any frame has been drawn
+        let camera = Camera {
+            projection: CameraProjection::Perspective {
+                vfov: 60.0,
+                near: 0.1,
+            },
+            view: glam::Mat4::IDENTITY,
+        };
+
+        plugin.last_frame = Some((resolution, camera));
+
+        Ok(plugin)
+    }
+
+    /// Creates a [FrameRequest] drawing to this plugin's offscreen target,
+    /// along with the receiver for its completion.
+    ///
+    /// Returns `None` if this plugin was not created with
+    /// [Rend3Plugin::new_headless].
+    pub fn headless_frame_request(
+        &self,
+        camera: Camera,
+    ) -> Option<(FrameRequest, oneshot::Receiver<FrameOutcome>)> {
+        let target = self.headless.as_ref()?;
+        let (on_complete, on_complete_rx) = oneshot::channel();
+
+        let request = FrameRequest {
+            output_frame: OutputFrame::View(target.view.clone()),
+            resolution: target.resolution,
+            camera,
+            on_complete,
+        };
+
+        Some((request, on_complete_rx))
+    }
+
+    fn try_new(
+        iad: InstanceAdapterDevice,
+        surface_format: TextureFormat,
+    ) -> Result<Self, RendererInitializationError> {
         let handedness = rend3::types::Handedness::Right;
-        let renderer = Renderer::new(iad.to_owned(), handedness, None).unwrap();
+        let renderer = Renderer::new(iad.to_owned(), handedness, None)?;
         let base_render_graph = BaseRenderGraph::new(&renderer);
         let mut data_core = renderer.data_core.lock();
         let interfaces = &base_render_graph.interfaces;
@@ -124,10 +427,31 @@ impl Rend3Plugin {
         let skybox_routine = SkyboxRoutine::new(&renderer, interfaces);
         drop(data_core);
 
-        let (frame_request_tx, frame_request_rx) = mpsc::unbounded_channel();
+        let blit_routine = BlitRoutine::new(&iad.device, HDR_FORMAT, SampleCount::One);
+
+        let profiler = GpuProfiler::new(&iad.device, &iad.queue);
+        if profiler.is_none() {
+            warn!("GPU does not support timestamp queries; GPU frame timings are disabled");
+        }
+
+        let (frame_request_tx, frame_request_rx) = mpsc::channel(FRAME_QUEUE_DEPTH);
+        let (capture_request_tx, capture_request_rx) = mpsc::unbounded_channel();
         let (command_tx, command_rx) = mpsc::unbounded_channel();
+        let targets = SharedTargets::new(renderer.clone());
+        let target_routine = SharedTargetRoutine::new(targets.clone());
 
-        Self {
+        let info = info::renderer_info(&iad, surface_format, SampleCount::One, 1.0);
+        let (info_tx, _) = watch::channel(info);
+
+        let device = RenderDevice {
+            iad: iad.clone(),
+            renderer: renderer.clone(),
+        };
+
+        let (device_tx, _) = watch::channel(device);
+        let device_lost = watch_device_loss(&iad.device);
+
+        Ok(Self {
             iad,
             surface_format,
             renderer,
@@ -137,12 +461,52 @@ impl Rend3Plugin {
             skybox_routine,
             frame_request_tx,
             frame_request_rx,
+            capture_request_tx,
+            capture_request_rx,
             command_tx,
             command_rx,
+            targets,
+            info_tx,
+            device_tx,
+            device_lost,
+            reset_frames: Vec::new(),
             new_skybox: None,
+            last_frame: None,
+            headless: None,
             ambient: Vec4::ZERO,
+            sample_count: SampleCount::One,
+            resolution_scale: 1.0,
+            stats: Default::default(),
+            blit_routine,
+            profiler,
+            target_routine,
             routines: Vec::new(),
-        }
+        })
+    }
+
+    /// Gets the renderer's current [RendererInfo].
+    pub fn info(&self) -> RendererInfo {
+        self.info_tx.borrow().clone()
+    }
+
+    /// Returns a receiver that follows the renderer's [RendererInfo] as its
+    /// settings change.
+    pub fn subscribe_info(&self) -> watch::Receiver<RendererInfo> {
+        self.info_tx.subscribe()
+    }
+
+    /// Returns a receiver that follows the device and renderer that this
+    /// plugin draws with.
+    ///
+    /// Windows use this to recreate their surfaces after the device is
+    /// lost.
+    pub fn subscribe_device(&self) -> watch::Receiver<RenderDevice> {
+        self.device_tx.subscribe()
+    }
+
+    /// Tests if the GPU device has been lost since it was created.
+    pub fn is_device_lost(&self) -> bool {
+        self.device_lost.load(Ordering::Acquire)
     }
 
     /// Adds a new [Routine] to this plugin.
@@ -150,6 +514,14 @@ impl Rend3Plugin {
         self.routines.push(Box::new(routine));
     }
 
+    /// Creates an offscreen render target that [Routines][Routine] can draw
+    /// into and materials can sample.
+    ///
+    /// See [SharedTargets::create] for details.
+    pub fn create_target(&self, label: &str, size: UVec2, format: TextureFormat) -> SharedTarget {
+        self.targets.create(label, size, format)
+    }
+
     /// Flushes and applies all [Rend3Command] messages.
     pub fn flush_commands(&mut self) {
         while let Ok(command) = self.command_rx.try_recv() {
@@ -161,33 +533,287 @@ impl Rend3Plugin {
                 SetAmbient(ambient) => {
                     self.ambient = ambient;
                 }
+                SetSampleCount(sample_count) if sample_count != self.sample_count => {
+                    self.sample_count = sample_count;
+                    let device = &self.iad.device;
+                    self.blit_routine.set_sample_count(device, sample_count);
+
+                    for routine in self.routines.iter_mut() {
+                        routine.set_sample_count(sample_count);
+                    }
+
+                    self.info_tx
+                        .send_modify(|info| info.sample_count = sample_count as u32);
+                }
+                SetSampleCount(_) => {}
+                SetResolutionScale(scale) if scale.is_finite() => {
+                    let scale = scale.clamp(MIN_RESOLUTION_SCALE, MAX_RESOLUTION_SCALE);
+                    if scale != self.resolution_scale {
+                        self.resolution_scale = scale;
+                        self.info_tx
+                            .send_modify(|info| info.resolution_scale = scale);
+                    }
+                }
+                SetResolutionScale(_) => {}
+                SetFrameBudget(budget) => {
+                    self.stats.set_budget(budget);
+                }
+                ResetDevice => {
+                    warn!("Resetting the GPU device by request");
+                    self.device_lost.store(true, Ordering::Release);
+                }
             }
         }
     }
 
     /// Draws a frame in response to a [FrameRequest].
+    ///
+    /// If the device is lost, the frame isn't drawn and its completion is
+    /// held until [Self::recover] has recreated the renderer.
     pub fn draw(&mut self, request: FrameRequest) {
+        self.last_frame = Some((request.resolution, request.camera));
+
+        if !self.is_device_lost() {
+            self.render(request.output_frame, request.resolution, request.camera);
+        }
+
+        // the loss may also have been detected while rendering
+        if self.is_device_lost() {
+            self.reset_frames.push(request.on_complete);
+        } else {
+            let _ = request.on_complete.send(FrameOutcome::Rendered); // ignore hangup
+        }
+    }
+
+    /// Recreates the device and renderer after the device has been lost.
+    ///
+    /// Failed attempts are retried with exponential backoff, starting at
+    /// [DEVICE_RETRY_INTERVAL], until [MAX_DEVICE_RETRIES] have failed.
+    ///
+    /// Frames that were skipped because of the loss complete with
+    /// [FrameOutcome::DeviceReset] once the renderer is ready again.
+    pub async fn recover(&mut self) -> Result<(), RendererInitializationError> {
+        error!("GPU device lost; recreating the renderer");
+
+        let mut delay = DEVICE_RETRY_INTERVAL;
+        let mut attempt = 1;
+
+        while let Err(err) = self.reset_device().await {
+            if attempt >= MAX_DEVICE_RETRIES {
+                return Err(err);
+            }
+
+            error!(
+                "Failed to recreate the renderer (attempt {} of {}): {}",
+                attempt, MAX_DEVICE_RETRIES, err
+            );
+
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_DEVICE_RETRY_INTERVAL);
+            attempt += 1;
+        }
+
+        for on_complete in self.reset_frames.drain(..) {
+            let _ = on_complete.send(FrameOutcome::DeviceReset);
+        }
+
+        Ok(())
+    }
+
+    /// Replaces the device, the renderer, and every GPU resource owned by
+    /// this plugin, then notifies routines with [Routine::on_device_reset].
+    ///
+    /// The skybox is cleared, since its texture was created on the old
+    /// renderer.
+    pub async fn reset_device(&mut self) -> Result<(), RendererInitializationError> {
+        let iad = create_iad(Some(self.iad.mode)).await?;
+        let fresh = Self::try_new(iad, self.surface_format)?;
+        let device = &fresh.iad.device;
+
+        if let Some(headless) = self.headless.as_mut() {
+            *headless = HeadlessTarget::new(device, headless.resolution, self.surface_format);
+        }
+
+        self.blit_routine = BlitRoutine::new(device, HDR_FORMAT, self.sample_count);
+        self.iad = fresh.iad;
+        self.renderer = fresh.renderer;
+        self.base_render_graph = fresh.base_render_graph;
+        self.pbr_routine = fresh.pbr_routine;
+        self.tonemapping_routine = fresh.tonemapping_routine;
+        self.skybox_routine = fresh.skybox_routine;
+        self.profiler = fresh.profiler;
+        self.device_lost = fresh.device_lost;
+        self.new_skybox = None;
+
+        self.targets.reset(self.renderer.clone());
+        self.target_routine.reset();
+
+        for routine in self.routines.iter_mut() {
+            routine.on_device_reset(&self.renderer);
+        }
+
+        let info = info::renderer_info(
+            &self.iad,
+            self.surface_format,
+            self.sample_count,
+            self.resolution_scale,
+        );
+
+        self.info_tx.send_replace(info);
+        self.device_tx.send_replace(RenderDevice {
+            iad: self.iad.clone(),
+            renderer: self.renderer.clone(),
+        });
+
+        Ok(())
+    }
+
+    /// Renders a frame offscreen in response to a [CaptureRequest].
+    ///
+    /// The pixels are read back asynchronously so that the render loop
+    /// doesn't wait on the copy.
+    pub fn capture(&mut self, request: CaptureRequest) {
+        if self.is_device_lost() {
+            warn!("cannot capture while the GPU device is lost");
+            return;
+        }
+
+        let Some((last_resolution, camera)) = self.last_frame else {
+            warn!("cannot capture before the first frame is drawn");
+            return;
+        };
+
+        let resolution = request.resolution.unwrap_or(last_resolution);
+        if resolution.x == 0 || resolution.y == 0 {
+            warn!("cannot capture an empty frame");
+            return;
+        }
+
+        let limits = self.iad.device.limits();
+        if !fits_texture(resolution, &limits) {
+            warn!(
+                "cannot capture {}x{} frame: the maximum texture dimension is {}",
+                resolution.x, resolution.y, limits.max_texture_dimension_2d
+            );
+            return;
+        }
+
+        let bgra = match self.surface_format {
+            TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb => false,
+            TextureFormat::Bgra8Unorm | TextureFormat::Bgra8UnormSrgb => true,
+            other => {
+                warn!("cannot capture frames of format {:?}", other);
+                return;
+            }
+        };
+
+        let device = self.iad.device.clone();
+        let size = Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            depth_or_array_layers: 1,
+        };
+
+        let texture = create_target(&device, "capture texture", resolution, self.surface_format);
+
+        let view = texture.create_view(&TextureViewDescriptor::default());
+        self.render(OutputFrame::View(Arc::new(view)), resolution, camera);
+
+        // buffer copies require each row to be aligned
+        let padded_row = padded_row_size(resolution.x);
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("capture readback buffer"),
+            size: padded_row as u64 * resolution.y as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("capture readback"),
+        });
+
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row as u32),
+                    rows_per_image: None,
+                },
+            },
+            size,
+        );
+
+        self.iad.queue.submit(Some(encoder.finish()));
+
+        tokio::spawn(async move {
+            let slice = buffer.slice(..);
+            let mapped = slice.map_async(MapMode::Read);
+
+            let poll_device = device.clone();
+            let _ = tokio::task::spawn_blocking(move || poll_device.poll(Maintain::Wait)).await;
+
+            if let Err(err) = mapped.await {
+                warn!("failed to map capture buffer: {:?}", err);
+                return;
+            }
+
+            let data = unpad_pixels(
+                &slice.get_mapped_range(),
+                resolution,
+                padded_row,
+                bgra,
+                request.format,
+            );
+
+            let _ = request.reply.send(Capture {
+                resolution,
+                format: request.format,
+                data,
+            });
+        });
+    }
+
+    /// Renders the scene into an output frame.
+    fn render(&mut self, output_frame: OutputFrame, resolution: UVec2, camera: Camera) {
+        let frame_start = Instant::now();
+
+        // deliver the GPU timings of previously finished frames
+        self.iad.device.poll(Maintain::Poll);
+
+        // new target textures have to exist before rend3 is readied
+        self.target_routine.prepare(&self.iad.device);
+
         let (cmd_bufs, ready) = self.renderer.ready();
+        let ready_time = frame_start.elapsed();
+        let build_start = Instant::now();
 
         if let Some(skybox) = self.new_skybox.take() {
             self.skybox_routine.set_background_texture(Some(skybox));
             self.skybox_routine.ready(&self.renderer);
         }
 
-        let aspect = request.resolution.as_vec2();
+        let aspect = resolution.as_vec2();
         let aspect = aspect.x / aspect.y;
         self.renderer.set_aspect_ratio(aspect);
-        self.renderer.set_camera_data(request.camera);
+        self.renderer.set_camera_data(camera);
 
         let nodes: Vec<_> = self
             .routines
             .iter_mut()
-            .map(|routine| routine.build_node())
+            .map(|routine| (routine.native_resolution(), routine.build_node()))
             .collect();
 
+        let scaled = scale_resolution(resolution, self.resolution_scale);
+        let upscale = scaled != resolution;
+
+        let profiler = self.profiler.as_ref();
+        let readback = profiler.map(|profiler| profiler.create_readback(&self.iad.device));
+
         let mut graph_data = RenderGraph::new();
         let graph = &mut graph_data;
-        let samples = SampleCount::One;
+        let samples = self.sample_count;
         let base = &self.base_render_graph;
         let ambient = self.ambient;
         let pbr = &self.pbr_routine;
@@ -198,10 +824,10 @@ impl Rend3Plugin {
         //
         // we need to override this function so that we can hook into the
         // graph's state in our custom nodes
-        let state =
-            BaseRenderGraphIntermediateState::new(graph, &ready, request.resolution, samples);
+        let state = BaseRenderGraphIntermediateState::new(graph, &ready, scaled, samples);
 
         // Preparing and uploading data
+        timestamp(profiler, graph, 0);
         state.pre_skinning(graph);
         state.pbr_pre_culling(graph);
         state.create_frame_uniforms(graph, base, ambient);
@@ -214,7 +840,9 @@ impl Rend3Plugin {
         state.pbr_culling(graph, base, pbr);
 
         // Depth-only rendering
+        timestamp(profiler, graph, 1);
         state.pbr_shadow_rendering(graph, pbr);
+        timestamp(profiler, graph, 2);
         state.pbr_prepass_rendering(graph, pbr, samples);
 
         // Skybox
@@ -223,24 +851,471 @@ impl Rend3Plugin {
         // Forward rendering
         state.pbr_forward_rendering(graph, pbr, samples);
 
-        // Make the reference to the surface
-        let surface = graph.add_surface_texture();
-        state.tonemapping(graph, &self.tonemapping_routine, surface);
-
+        // Custom routines, drawn into the multisampled HDR target
+        timestamp(profiler, graph, 3);
+        let targets = self.target_routine.add_targets(graph);
         let mut info = RoutineInfo {
             state: &state,
-            sample_count: SampleCount::One,
-            resolution: request.resolution,
+            sample_count: samples,
+            resolution: scaled,
             ready_data: &ready,
             graph,
+            targets: &targets,
         };
 
-        for node in nodes.iter() {
-            node.draw(&mut info);
+        for (native, node) in nodes.iter() {
+            if !(upscale && *native) {
+                node.draw(&mut info);
+            }
+        }
+
+        // Make the reference to the surface
+        let graph = &mut graph_data;
+        let surface = graph.add_surface_texture();
+
+        let native_state = upscale.then(|| {
+            // stretch the scaled scene onto native-resolution targets
+            let native_state =
+                BaseRenderGraphIntermediateState::new(graph, &ready, resolution, samples);
+            native_state.create_frame_uniforms(graph, base, ambient);
+
+            let src = state.resolve.unwrap_or(state.color);
+            let blit = &self.blit_routine;
+            blit.add_to_graph(graph, src, native_state.color, native_state.resolve);
+
+            let mut info = RoutineInfo {
+                state: &native_state,
+                sample_count: samples,
+                resolution,
+                ready_data: &ready,
+                graph,
+                targets: &targets,
+            };
+
+            for (native, node) in nodes.iter() {
+                if *native {
+                    node.draw(&mut info);
+                }
+            }
+
+            native_state
+        });
+
+        let graph = &mut graph_data;
+        self.target_routine.add_blits(graph, &targets);
+        let final_state = native_state.as_ref().unwrap_or(&state);
+        timestamp(profiler, graph, 4);
+        final_state.tonemapping(graph, &self.tonemapping_routine, surface);
+        timestamp(profiler, graph, 5);
+
+        if let (Some(profiler), Some(readback)) = (profiler, readback.as_ref()) {
+            profiler.add_resolve(graph, readback);
+        }
+
+        let build_time = build_start.elapsed();
+        let execute_start = Instant::now();
+        graph_data.execute(&self.renderer, output_frame, cmd_bufs, &ready);
+        let execute_time = execute_start.elapsed();
+
+        let timings = FrameTimings {
+            ready: millis(ready_time),
+            build: millis(build_time),
+            execute: millis(execute_time),
+            cpu_total: millis(frame_start.elapsed()),
+            gpu_passes: None,
+            dropped_frames: self.stats.dropped_frames(),
+        };
+
+        let (Some(profiler), Some(readback)) = (self.profiler.as_ref(), readback) else {
+            self.stats.push(timings);
+            return;
+        };
+
+        let period = profiler.period();
+        let stats = self.stats.clone();
+        tokio::spawn(async move {
+            let gpu_passes = GpuProfiler::read(readback, period).await;
+            stats.push(FrameTimings {
+                gpu_passes,
+                ..timings
+            });
+        });
+    }
+}
+
+/// Creates an [InstanceAdapterDevice], enabling timestamp queries if the
+/// adapter supports them.
+pub async fn create_iad(
+    mode: Option<RendererMode>,
+) -> Result<InstanceAdapterDevice, RendererInitializationError> {
+    let features = Some(wgpu::Features::TIMESTAMP_QUERY);
+    match rend3::create_iad(None, None, mode, features).await {
+        Ok(iad) => Ok(iad),
+        Err(_) => rend3::create_iad(None, None, mode, None).await,
+    }
+}
+
+/// Installs an error handler on a device that flags the device as lost
+/// instead of panicking.
+///
+/// wgpu 0.12 has no device loss callback, so a loss is detected from the
+/// errors that every operation on a lost device reports.
+fn watch_device_loss(device: &wgpu::Device) -> Arc<AtomicBool> {
+    let lost = Arc::new(AtomicBool::new(false));
+    let flag = lost.clone();
+
+    device.on_uncaptured_error(move |err| {
+        if is_device_loss(&err) {
+            // only log the first of many errors
+            if !flag.swap(true, Ordering::AcqRel) {
+                error!("GPU device lost: {}", err);
+            }
+        } else {
+            error!("Uncaptured wgpu error: {}", err);
+        }
+    });
+
+    lost
+}
+
+/// Tests if an error was caused by a lost device.
+///
+/// Like wgpu's own out-of-memory detection, this looks for wgpu-core's
+/// device error anywhere in the error's chain of sources.
+fn is_device_loss(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+
+    while let Some(err) = source {
+        if let Some(DeviceError::Lost) = err.downcast_ref::<DeviceError>() {
+            return true;
+        }
+
+        source = err.source();
+    }
+
+    false
+}
+
+/// Takes every frame request that's already queued behind `first` and
+/// keeps only the newest request for each output.
+///
+/// Returns the requests to draw, in the order they were queued, and the
+/// stale requests that they replace.
+fn coalesce_frames<T>(
+    first: T,
+    rx: &mut mpsc::Receiver<T>,
+    same_output: impl Fn(&T, &T) -> bool,
+) -> (Vec<T>, Vec<T>) {
+    let mut frames = vec![first];
+    let mut dropped = Vec::new();
+
+    while let Ok(frame) = rx.try_recv() {
+        if let Some(index) = frames.iter().position(|old| same_output(old, &frame)) {
+            dropped.push(frames.remove(index));
+        }
+
+        frames.push(frame);
+    }
+
+    (frames, dropped)
+}
+
+/// Whether two output frames draw to the same surface or texture.
+fn same_output(a: &OutputFrame, b: &OutputFrame) -> bool {
+    match (a, b) {
+        (OutputFrame::Surface { surface: a }, OutputFrame::Surface { surface: b }) => {
+            Arc::ptr_eq(a, b)
+        }
+        (OutputFrame::View(a), OutputFrame::View(b)) => Arc::ptr_eq(a, b),
+        _ => false,
+    }
+}
+
+/// Scales a resolution, keeping it at least one pixel in each dimension.
+fn scale_resolution(resolution: UVec2, scale: f32) -> UVec2 {
+    if scale == 1.0 {
+        return resolution;
+    }
+
+    (resolution.as_vec2() * scale)
+        .round()
+        .as_uvec2()
+        .max(UVec2::ONE)
+}
+
+/// Creates a texture that frames can be rendered to and copied out of.
+fn create_target(
+    device: &wgpu::Device,
+    label: &str,
+    resolution: UVec2,
+    format: TextureFormat,
+) -> Texture {
+    device.create_texture(&TextureDescriptor {
+        label: Some(label),
+        size: Extent3d {
+            width: resolution.x,
+            height: resolution.y,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format,
+        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::COPY_SRC,
+    })
+}
+
+/// Tests if a 2D texture of the given resolution is within a device's limits.
+fn fits_texture(resolution: UVec2, limits: &Limits) -> bool {
+    resolution.max_element() <= limits.max_texture_dimension_2d
+}
+
+/// Returns the size in bytes of a row of RGBA8 pixels padded to wgpu's
+/// buffer copy alignment.
+fn padded_row_size(width: u32) -> usize {
+    let row = width as usize * 4;
+    let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize;
+    (row + align - 1) / align * align
+}
+
+/// Converts padded rows of 8-bit RGBA or BGRA pixels to tightly-packed rows
+/// of the given format.
+fn unpad_pixels(
+    data: &[u8],
+    resolution: UVec2,
+    padded_row: usize,
+    bgra: bool,
+    format: CaptureFormat,
+) -> Vec<u8> {
+    let width = resolution.x as usize;
+    let height = resolution.y as usize;
+    let mut out = Vec::with_capacity(width * height * format.bytes_per_pixel());
+
+    for row in data.chunks(padded_row).take(height) {
+        for pixel in row[..width * 4].chunks_exact(4) {
+            let (r, b) = if bgra {
+                (pixel[2], pixel[0])
+            } else {
+                (pixel[0], pixel[2])
+            };
+
+            out.extend_from_slice(&[r, pixel[1], b]);
+
+            if format == CaptureFormat::Rgba8 {
+                out.push(pixel[3]);
+            }
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_resolutions() {
+        let resolution = UVec2::new(3840, 2160);
+        assert_eq!(scale_resolution(resolution, 1.0), resolution);
+        assert_eq!(scale_resolution(resolution, 0.5), UVec2::new(1920, 1080));
+        assert_eq!(scale_resolution(UVec2::new(3, 1), 0.25), UVec2::new(1, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn flooded_frames_have_bounded_latency() {
+        use tokio::time::{sleep, Instant};
+
+        const FRAMES: usize = 200;
+        const FRAME_TIME: Duration = Duration::from_millis(5);
+
+        // each request is tagged with its output and when it was sent
+        let (tx, mut rx) = mpsc::channel::<(u32, usize, Instant)>(FRAME_QUEUE_DEPTH);
+
+        let producer = tokio::spawn(async move {
+            for index in 0..FRAMES {
+                for output in 0..2 {
+                    tx.send((output, index, Instant::now())).await.unwrap();
+                }
+            }
+        });
+
+        let mut drawn = 0;
+        let mut dropped = 0;
+        let mut last_latency = [None; 2];
+
+        while let Some(first) = rx.recv().await {
+            let (frames, stale) = coalesce_frames(first, &mut rx, |a, b| a.0 == b.0);
+            dropped += stale.len();
+
+            for (output, index, sent) in frames {
+                // simulate a renderer that's much slower than the producer
+                sleep(FRAME_TIME).await;
+                drawn += 1;
+
+                if index == FRAMES - 1 {
+                    last_latency[output as usize] = Some(sent.elapsed());
+                }
+            }
+        }
+
+        producer.await.unwrap();
+
+        assert_eq!(drawn + dropped, FRAMES * 2);
+        assert!(dropped > 0);
+
+        // the final frames waited on at most a full queue, not on every
+        // stale frame before them
+        let bound = FRAME_TIME * (FRAME_QUEUE_DEPTH as u32 + 2) * 2;
+        for latency in last_latency {
+            let latency = latency.expect("final frame was dropped");
+            assert!(latency < bound, "final frame took {:?}", latency);
+        }
+    }
+
+    #[test]
+    fn coalesce_keeps_newest_per_output() {
+        let (tx, mut rx) = mpsc::channel(8);
+        for frame in [(1, 'b'), (0, 'c'), (1, 'd'), (2, 'e')] {
+            tx.try_send(frame).unwrap();
+        }
+
+        let (frames, dropped) = coalesce_frames((0, 'a'), &mut rx, |a, b| a.0 == b.0);
+        assert_eq!(frames, vec![(0, 'c'), (1, 'd'), (2, 'e')]);
+        assert_eq!(dropped, vec![(0, 'a'), (1, 'b')]);
+    }
+
+    /// An error caused by another error, like wgpu-core's context errors.
+    #[derive(Debug)]
+    struct Caused(DeviceError);
+
+    impl std::fmt::Display for Caused {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "failed to create buffer")
+        }
+    }
+
+    impl std::error::Error for Caused {
+        fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[test]
+    fn device_loss_errors() {
+        let lost = wgpu::Error::Validation {
+            source: Box::new(Caused(DeviceError::Lost)),
+            description: "failed to create buffer".to_string(),
+        };
+
+        assert!(is_device_loss(&lost));
+
+        let oom = wgpu::Error::OutOfMemory {
+            source: Box::new(Caused(DeviceError::OutOfMemory)),
+        };
+
+        assert!(!is_device_loss(&oom));
+        assert!(!is_device_loss(&Caused(DeviceError::Invalid)));
+    }
+
+    /// Counts how many times its device has been reset.
+    struct ResetCounter(Arc<std::sync::atomic::AtomicUsize>);
+
+    impl Routine for ResetCounter {
+        fn build_node(&mut self) -> Box<dyn Node<'_> + '_> {
+            Box::new(NoopNode)
+        }
+
+        fn on_device_reset(&mut self, _renderer: &Arc<Renderer>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    struct NoopNode;
+
+    impl<'a> Node<'a> for NoopNode {
+        fn draw<'graph>(&'graph self, _info: &mut RoutineInfo<'_, 'graph>) {}
+    }
+
+    #[tokio::test]
+    async fn frames_render_after_device_reset() {
+        let size = UVec2::new(64, 64);
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let mut plugin = match Rend3Plugin::new_headless(size, format).await {
+            Ok(plugin) => plugin,
+            Err(err) => {
+                eprintln!("skipping device reset test without an adapter: {}", err);
+                return;
+            }
+        };
+
+        let resets = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        plugin.add_routine(ResetCounter(resets.clone()));
+        let mut devices = plugin.subscribe_device();
+        let old = plugin.renderer.clone();
+
+        let camera = plugin.last_frame.unwrap().1;
+        let (request, rendered) = plugin.headless_frame_request(camera).unwrap();
+        plugin.draw(request);
+        assert_eq!(rendered.await, Ok(FrameOutcome::Rendered));
+
+        // a lost device skips the frame until the renderer is recreated
+        plugin.command_tx.send(Rend3Command::ResetDevice).unwrap();
+        plugin.flush_commands();
+        let (request, skipped) = plugin.headless_frame_request(camera).unwrap();
+        plugin.draw(request);
+        plugin.recover().await.unwrap();
+        assert_eq!(skipped.await, Ok(FrameOutcome::DeviceReset));
+
+        assert!(!plugin.is_device_lost());
+        assert_eq!(resets.load(Ordering::Relaxed), 1);
+        assert!(!Arc::ptr_eq(&old, &plugin.renderer));
+        assert!(devices.has_changed().unwrap());
+        assert!(Arc::ptr_eq(
+            &devices.borrow_and_update().renderer,
+            &plugin.renderer
+        ));
+
+        let (request, rendered) = plugin.headless_frame_request(camera).unwrap();
+        plugin.draw(request);
+        assert_eq!(rendered.await, Ok(FrameOutcome::Rendered));
+    }
+
+    #[test]
+    fn oversized_textures_do_not_fit() {
+        let limits = Limits::downlevel_defaults();
+        let max = limits.max_texture_dimension_2d;
+        assert!(fits_texture(UVec2::new(max, max), &limits));
+        assert!(!fits_texture(UVec2::new(max + 1, 1), &limits));
+        assert!(!fits_texture(UVec2::new(1, max + 1), &limits));
+    }
+
+    #[test]
+    fn padded_rows_are_aligned() {
+        assert_eq!(padded_row_size(64), 256);
+        assert_eq!(padded_row_size(65), 512);
+        assert_eq!(padded_row_size(1), 256);
+    }
+
+    #[test]
+    fn unpad_odd_width() {
+        let resolution = UVec2::new(3, 2);
+        let padded_row = padded_row_size(resolution.x);
+        let mut data = vec![0xff; padded_row * 2];
+        for y in 0..2 {
+            for x in 0..3 {
+                let offset = y * padded_row + x * 4;
+                data[offset..(offset + 4)].copy_from_slice(&[1, 2, 3, (y * 3 + x) as u8]);
+            }
         }
 
-        graph_data.execute(&self.renderer, request.output_frame, cmd_bufs, &ready);
+        let rgba = unpad_pixels(&data, resolution, padded_row, false, CaptureFormat::Rgba8);
+        assert_eq!(rgba.len(), 3 * 2 * 4);
+        assert_eq!(&rgba[..8], &[1, 2, 3, 0, 1, 2, 3, 1]);
+        assert_eq!(&rgba[20..], &[1, 2, 3, 5]);
 
-        let _ = request.on_complete.send(()); // ignore hangup
+        let rgb = unpad_pixels(&data, resolution, padded_row, true, CaptureFormat::Rgb8);
+        assert_eq!(rgb.len(), 3 * 2 * 3);
+        assert_eq!(&rgb[..3], &[3, 2, 1]);
     }
 }
@@ -0,0 +1,328 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Offscreen render targets that routines draw into and materials sample.
+//!
+//! Each [SharedTarget] is backed by a rend3 2D texture, so it can be
+//! assigned to materials like any other texture. Every frame, the target is
+//! added to the render graph as a single-sampled render target that routines
+//! draw into, which is then blitted into the texture once all routines have
+//! drawn.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+
+use glam::UVec2;
+use parking_lot::Mutex;
+use rend3::graph::{RenderGraph, RenderTargetDescriptor, RenderTargetHandle};
+use rend3::types::{MipmapCount, MipmapSource, SampleCount, Texture, TextureHandle};
+use rend3::Renderer;
+use tokio::sync::watch;
+use wgpu::{Device, TextureFormat, TextureSampleType, TextureUsages};
+
+use crate::blit::BlitRoutine;
+
+/// The texture currently backing a [SharedTarget].
+#[derive(Clone, Debug)]
+pub struct TargetTexture {
+    /// The dimensions of the texture.
+    pub size: UVec2,
+
+    /// The rend3 handle to the texture.
+    pub handle: TextureHandle,
+}
+
+struct TargetInner {
+    label: String,
+    format: TextureFormat,
+    pending_size: Mutex<Option<UVec2>>,
+    texture: watch::Sender<TargetTexture>,
+}
+
+/// A handle to an offscreen render target.
+///
+/// Dropping every clone of a target removes it from the render graph.
+/// Materials that still use its texture keep the last frame drawn to it.
+#[derive(Clone)]
+pub struct SharedTarget {
+    inner: Arc<TargetInner>,
+}
+
+impl SharedTarget {
+    /// Gets this target's label.
+    pub fn label(&self) -> &str {
+        &self.inner.label
+    }
+
+    /// Gets this target's texture format.
+    pub fn format(&self) -> TextureFormat {
+        self.inner.format
+    }
+
+    /// Gets the texture currently backing this target.
+    pub fn texture(&self) -> TargetTexture {
+        self.inner.texture.borrow().clone()
+    }
+
+    /// Subscribes to this target's texture.
+    ///
+    /// Resizing a target replaces its texture, so materials using it need
+    /// to be updated with the new handle when this receiver changes.
+    pub fn subscribe(&self) -> watch::Receiver<TargetTexture> {
+        self.inner.texture.subscribe()
+    }
+
+    /// Resizes this target.
+    ///
+    /// The new texture is created at the start of the next frame, at which
+    /// point subscribers are notified.
+    pub fn resize(&self, size: UVec2) {
+        *self.inner.pending_size.lock() = Some(size);
+    }
+}
+
+/// A registry of every [SharedTarget] that is drawn to each frame.
+#[derive(Clone)]
+pub struct SharedTargets {
+    renderer: Arc<Mutex<Arc<Renderer>>>,
+    targets: Arc<Mutex<Vec<Weak<TargetInner>>>>,
+}
+
+impl SharedTargets {
+    /// Creates an empty registry.
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        Self {
+            renderer: Arc::new(Mutex::new(renderer)),
+            targets: Default::default(),
+        }
+    }
+
+    /// Moves every live target to a new renderer after a device reset.
+    ///
+    /// Each target gets a blank texture of the same size, and its
+    /// subscribers are notified of the new handle.
+    pub(crate) fn reset(&self, renderer: Arc<Renderer>) {
+        let targets = self.targets.lock();
+        *self.renderer.lock() = renderer.clone();
+
+        for target in targets.iter().filter_map(Weak::upgrade) {
+            let size = target.texture.borrow().size;
+            let texture = create_texture(&renderer, &target.label, size, target.format);
+            target.texture.send_replace(texture);
+        }
+    }
+
+    /// Creates a new target.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `format` is not a filterable color format that can be
+    /// rendered to. See [is_target_format].
+    pub fn create(&self, label: &str, size: UVec2, format: TextureFormat) -> SharedTarget {
+        assert!(
+            is_target_format(format),
+            "{:?} can't be used as a shared target format",
+            format
+        );
+
+        // hold the lock while the texture is created so that the target is
+        // never added to a frame before rend3 has its texture ready
+        let mut targets = self.targets.lock();
+        let renderer = self.renderer.lock().clone();
+        let texture = create_texture(&renderer, label, size, format);
+
+        let inner = Arc::new(TargetInner {
+            label: label.to_string(),
+            format,
+            pending_size: Mutex::new(None),
+            texture: watch::channel(texture).0,
+        });
+
+        targets.push(Arc::downgrade(&inner));
+        SharedTarget { inner }
+    }
+}
+
+/// Whether a texture format can be used for a [SharedTarget].
+pub fn is_target_format(format: TextureFormat) -> bool {
+    let info = format.describe();
+    let filterable = info.sample_type == TextureSampleType::Float { filterable: true };
+    let usages = info.guaranteed_format_features.allowed_usages;
+    filterable && usages.contains(TextureUsages::RENDER_ATTACHMENT)
+}
+
+/// Creates a blank rend3 texture for a target, clamping its size to what
+/// the device supports.
+fn create_texture(
+    renderer: &Renderer,
+    label: &str,
+    size: UVec2,
+    format: TextureFormat,
+) -> TargetTexture {
+    let max = renderer.device.limits().max_texture_dimension_2d;
+    let size = size.clamp(UVec2::ONE, UVec2::splat(max));
+    let texel_size = format.describe().block_size as usize;
+    let data = vec![0; size.x as usize * size.y as usize * texel_size];
+
+    let handle = renderer.add_texture_2d(Texture {
+        label: Some(label.to_string()),
+        data,
+        format,
+        size,
+        mip_count: MipmapCount::ONE,
+        mip_source: MipmapSource::Uploaded,
+    });
+
+    TargetTexture { size, handle }
+}
+
+/// A target added to a frame's render graph.
+struct FrameTarget {
+    inner: Arc<TargetInner>,
+    texture: TargetTexture,
+    handle: RenderTargetHandle,
+}
+
+/// The render graph targets of every [SharedTarget] in a frame.
+#[derive(Default)]
+pub struct FrameTargets {
+    targets: Vec<FrameTarget>,
+}
+
+impl FrameTargets {
+    /// Gets the render graph target for a [SharedTarget] this frame.
+    ///
+    /// Returns `None` if the target was created after the frame started.
+    pub fn get(&self, target: &SharedTarget) -> Option<RenderTargetHandle> {
+        self.targets
+            .iter()
+            .find(|frame| Arc::ptr_eq(&frame.inner, &target.inner))
+            .map(|frame| frame.handle)
+    }
+
+    /// Gets the size of a [SharedTarget] this frame.
+    pub fn size(&self, target: &SharedTarget) -> Option<UVec2> {
+        self.targets
+            .iter()
+            .find(|frame| Arc::ptr_eq(&frame.inner, &target.inner))
+            .map(|frame| frame.texture.size)
+    }
+}
+
+/// Adds the targets in a [SharedTargets] registry to each frame's render
+/// graph and copies what was drawn to them into their textures.
+pub struct SharedTargetRoutine {
+    registry: SharedTargets,
+    blits: HashMap<TextureFormat, BlitRoutine>,
+    frame: Vec<(Arc<TargetInner>, TargetTexture)>,
+}
+
+impl SharedTargetRoutine {
+    /// Creates a routine for all of the targets in a registry.
+    pub fn new(registry: SharedTargets) -> Self {
+        Self {
+            registry,
+            blits: HashMap::new(),
+            frame: Vec::new(),
+        }
+    }
+
+    /// Collects the live targets for the next frame and applies pending
+    /// resizes.
+    ///
+    /// This creates rend3 textures, so it must be called before the
+    /// renderer is readied for the frame.
+    pub fn prepare(&mut self, device: &Device) {
+        let renderer = self.registry.renderer.lock().clone();
+        let mut targets = self.registry.targets.lock();
+        targets.retain(|target| target.strong_count() > 0);
+
+        self.frame.clear();
+        for target in targets.iter().filter_map(Weak::upgrade) {
+            let pending = target.pending_size.lock().take();
+            let current = target.texture.borrow().size;
+            if let Some(size) = pending.filter(|size| *size != current) {
+                let texture = create_texture(&renderer, &target.label, size, target.format);
+                target.texture.send_replace(texture);
+            }
+
+            self.blits
+                .entry(target.format)
+                .or_insert_with(|| BlitRoutine::new(device, target.format, SampleCount::One));
+
+            let texture = target.texture.borrow().clone();
+            self.frame.push((target, texture));
+        }
+    }
+
+    /// Drops the blit pipelines created on a lost device. They're recreated
+    /// on the new device by the next [Self::prepare].
+    pub fn reset(&mut self) {
+        self.blits.clear();
+        self.frame.clear();
+    }
+
+    /// Adds a render target for each target prepared for this frame.
+    pub fn add_targets(&self, graph: &mut RenderGraph) -> FrameTargets {
+        let targets = self
+            .frame
+            .iter()
+            .map(|(inner, texture)| {
+                let handle = graph.add_render_target(RenderTargetDescriptor {
+                    label: Some(inner.label.clone()),
+                    resolution: texture.size,
+                    samples: SampleCount::One,
+                    format: inner.format,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                });
+
+                FrameTarget {
+                    inner: inner.clone(),
+                    texture: texture.clone(),
+                    handle,
+                }
+            })
+            .collect();
+
+        FrameTargets { targets }
+    }
+
+    /// Adds nodes copying every frame target into its texture.
+    ///
+    /// Must be added after every node that draws to the targets.
+    pub fn add_blits<'a>(&'a self, graph: &mut RenderGraph<'a>, targets: &FrameTargets) {
+        for target in targets.targets.iter() {
+            let blit = &self.blits[&target.inner.format];
+            blit.add_to_texture(graph, target.handle, target.texture.handle.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_formats() {
+        assert!(is_target_format(TextureFormat::Rgba8UnormSrgb));
+        assert!(is_target_format(TextureFormat::Rgba16Float));
+        assert!(!is_target_format(TextureFormat::Depth32Float));
+        assert!(!is_target_format(TextureFormat::R32Uint));
+        assert!(!is_target_format(TextureFormat::Bc1RgbaUnorm));
+    }
+}
@@ -0,0 +1,265 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Frame timing instrumentation.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use hearth_runtime::hearth_schema::renderer::{FrameTimings, PassTiming};
+use hearth_runtime::tracing::warn;
+use parking_lot::Mutex;
+use rend3::graph::RenderGraph;
+use wgpu::*;
+
+/// The number of frames of timings kept in [RenderStats].
+pub const HISTORY_LEN: usize = 120;
+
+/// The names of the major passes timed on the GPU, in order.
+pub const GPU_PASSES: &[&str] = &["culling", "shadows", "forward", "routines", "tonemapping"];
+
+/// The number of timestamps written per frame: one before each pass and one
+/// after the last.
+const TIMESTAMP_COUNT: u32 = GPU_PASSES.len() as u32 + 1;
+
+/// The size in bytes of a frame's resolved timestamps.
+const TIMESTAMPS_SIZE: BufferAddress = TIMESTAMP_COUNT as BufferAddress * 8;
+
+/// Converts a duration to milliseconds.
+pub(crate) fn millis(duration: Duration) -> f32 {
+    duration.as_secs_f32() * 1000.0
+}
+
+/// A shared history of the timings of recent frames.
+#[derive(Default)]
+pub struct RenderStats {
+    history: Mutex<VecDeque<FrameTimings>>,
+    budget: Mutex<Option<f32>>,
+    dropped: AtomicU64,
+}
+
+impl RenderStats {
+    /// Returns the timings of the most recent frames, oldest first.
+    pub fn history(&self) -> Vec<FrameTimings> {
+        self.history.lock().iter().cloned().collect()
+    }
+
+    /// Sets the frame time budget. Frames that take longer than this on
+    /// either the CPU or the GPU log a warning.
+    pub fn set_budget(&self, budget: Option<Duration>) {
+        *self.budget.lock() = budget.map(millis);
+    }
+
+    /// Returns the total number of frame requests that were dropped because
+    /// a newer request for the same output replaced them.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Records frame requests that were dropped without being drawn.
+    pub fn record_dropped(&self, count: u64) {
+        self.dropped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Records the timings of a finished frame.
+    pub fn push(&self, timings: FrameTimings) {
+        if let Some(budget) = *self.budget.lock() {
+            let gpu_total = timings.gpu_total().unwrap_or(0.0);
+            if timings.cpu_total > budget || gpu_total > budget {
+                warn!("Frame exceeded {:.2}ms budget: {:?}", budget, timings);
+            }
+        }
+
+        let mut history = self.history.lock();
+        if history.len() >= HISTORY_LEN {
+            history.pop_front();
+        }
+
+        history.push_back(timings);
+    }
+}
+
+/// Measures GPU time per pass using timestamp queries.
+pub(crate) struct GpuProfiler {
+    query_set: QuerySet,
+    resolve_buffer: Buffer,
+
+    /// The number of nanoseconds per timestamp tick.
+    period: f32,
+}
+
+impl GpuProfiler {
+    /// Creates a profiler, or returns `None` if the device doesn't support
+    /// timestamp queries.
+    pub fn new(device: &Device, queue: &Queue) -> Option<Self> {
+        if !device.features().contains(Features::TIMESTAMP_QUERY) {
+            return None;
+        }
+
+        let query_set = device.create_query_set(&QuerySetDescriptor {
+            label: Some("frame timestamps"),
+            ty: QueryType::Timestamp,
+            count: TIMESTAMP_COUNT,
+        });
+
+        let resolve_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("frame timestamp resolve buffer"),
+            size: TIMESTAMPS_SIZE,
+            usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        Some(Self {
+            query_set,
+            resolve_buffer,
+            period: queue.get_timestamp_period(),
+        })
+    }
+
+    /// Creates a buffer to read a frame's timestamps back into.
+    pub fn create_readback(&self, device: &Device) -> Buffer {
+        device.create_buffer(&BufferDescriptor {
+            label: Some("frame timestamp readback buffer"),
+            size: TIMESTAMPS_SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Adds a node to a render graph that writes the timestamp at `index`.
+    pub fn add_timestamp<'a>(&'a self, graph: &mut RenderGraph<'a>, index: u32) {
+        let mut builder = graph.add_node("timestamp");
+        builder.add_external_output();
+        let this = builder.passthrough_ref(self);
+
+        builder.build(
+            move |pt, _renderer, encoder_or_pass, _temps, _ready, _graph_data| {
+                let this = pt.get(this);
+                let encoder = encoder_or_pass.get_encoder();
+                encoder.write_timestamp(&this.query_set, index);
+            },
+        );
+    }
+
+    /// Adds a node to a render graph that copies all of the frame's
+    /// timestamps into a readback buffer.
+    pub fn add_resolve<'a>(&'a self, graph: &mut RenderGraph<'a>, readback: &'a Buffer) {
+        let mut builder = graph.add_node("resolve timestamps");
+        builder.add_external_output();
+        let this = builder.passthrough_ref(self);
+        let readback = builder.passthrough_ref(readback);
+
+        builder.build(
+            move |pt, _renderer, encoder_or_pass, _temps, _ready, _graph_data| {
+                let this = pt.get(this);
+                let readback = pt.get(readback);
+                let encoder = encoder_or_pass.get_encoder();
+                let queries = 0..TIMESTAMP_COUNT;
+                encoder.resolve_query_set(&this.query_set, queries, &this.resolve_buffer, 0);
+                encoder.copy_buffer_to_buffer(
+                    &this.resolve_buffer,
+                    0,
+                    readback,
+                    0,
+                    TIMESTAMPS_SIZE,
+                );
+            },
+        );
+    }
+
+    /// Reads the per-pass timings out of a readback buffer once the GPU has
+    /// finished the frame.
+    ///
+    /// The mapping only completes once the device is polled.
+    pub async fn read(readback: Buffer, period: f32) -> Option<Vec<PassTiming>> {
+        let slice = readback.slice(..);
+        slice.map_async(MapMode::Read).await.ok()?;
+
+        let stamps: Vec<u64> = slice
+            .get_mapped_range()
+            .chunks_exact(8)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        Some(pass_timings(&stamps, period))
+    }
+
+    /// The number of nanoseconds per timestamp tick.
+    pub fn period(&self) -> f32 {
+        self.period
+    }
+}
+
+/// Writes a timestamp to a render graph if GPU profiling is enabled.
+pub(crate) fn timestamp<'a>(
+    profiler: Option<&'a GpuProfiler>,
+    graph: &mut RenderGraph<'a>,
+    index: u32,
+) {
+    if let Some(profiler) = profiler {
+        profiler.add_timestamp(graph, index);
+    }
+}
+
+/// Converts consecutive pass timestamps into named pass timings.
+fn pass_timings(stamps: &[u64], period: f32) -> Vec<PassTiming> {
+    GPU_PASSES
+        .iter()
+        .zip(stamps.windows(2))
+        .map(|(name, window)| PassTiming {
+            name: name.to_string(),
+            time: window[1].saturating_sub(window[0]) as f32 * period / 1_000_000.0,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pass_timings_from_stamps() {
+        let stamps = [100, 1_000_100, 1_500_100, 1_500_100, 3_500_100, 3_600_100];
+        let timings = pass_timings(&stamps, 1.0);
+        let times: Vec<f32> = timings.iter().map(|pass| pass.time).collect();
+        assert_eq!(times, vec![1.0, 0.5, 0.0, 2.0, 0.1]);
+        assert_eq!(timings[0].name, "culling");
+    }
+
+    #[test]
+    fn pass_timings_ignore_reversed_stamps() {
+        let timings = pass_timings(&[10, 5, 5, 5, 5, 5], 1.0);
+        assert_eq!(timings[0].time, 0.0);
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let stats = RenderStats::default();
+        for i in 0..(HISTORY_LEN + 10) {
+            stats.push(FrameTimings {
+                cpu_total: i as f32,
+                ..Default::default()
+            });
+        }
+
+        let history = stats.history();
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history[0].cpu_total, 10.0);
+    }
+}
@@ -109,6 +109,11 @@ impl<T: Pod> DynamicMesh<T> {
         self.indices.update(device, queue, indices);
     }
 
+    /// Tests if this mesh has no indices to draw.
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
     /// Bind this mesh to the given render pass and perform a draw operation.
     pub fn draw<'a>(&'a self, rpass: &mut RenderPass<'a>) {
         let vs = self.vertices.get_buffer();
@@ -16,29 +16,40 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::io::Cursor;
+use std::sync::{Arc, Weak};
 
+use glam::Mat4;
 use hearth_rend3::{
     rend3::{types::*, *},
     rend3_routine::pbr::{AlbedoComponent, PbrMaterial},
-    Rend3Command, Rend3Plugin,
+    timing::RenderStats,
+    CaptureFormat, CaptureRequest, Rend3Command, Rend3Plugin,
 };
 use hearth_runtime::{
     anyhow::{self, bail},
     asset::{AssetLoader, AssetStore, JsonAssetLoader},
     async_trait, cargo_process_metadata,
-    hearth_schema::{renderer::*, LumpId},
+    hearth_schema::{
+        lump::{LumpMetadata, LumpOrigin},
+        renderer::*,
+        LumpId,
+    },
     process::ProcessMetadata,
     runtime::{Plugin, RuntimeBuilder},
-    tokio::sync::mpsc::UnboundedSender,
-    tracing::{error, warn},
+    tokio::{
+        self,
+        sync::{broadcast::error::RecvError, mpsc::UnboundedSender, oneshot, RwLock},
+    },
+    tracing::{error, info, warn},
     utils::{
         MessageInfo, RequestInfo, RequestResponseProcess, ResponseInfo, RunnerContext,
         ServiceRunner, SinkProcess,
     },
 };
+use parking_lot::Mutex;
 
-pub struct MeshLoader(Arc<Renderer>);
+pub struct MeshLoader(GuestScene);
 
 #[async_trait]
 impl JsonAssetLoader for MeshLoader {
@@ -64,13 +75,13 @@ impl JsonAssetLoader for MeshLoader {
 
         let _ = mesh.validate()?;
 
-        let handle = self.0.add_mesh(mesh);
+        let handle = self.0.renderer().add_mesh(mesh);
 
         Ok(handle)
     }
 }
 
-pub struct MaterialLoader(Arc<Renderer>);
+pub struct MaterialLoader(GuestScene);
 
 #[async_trait]
 impl JsonAssetLoader for MaterialLoader {
@@ -89,12 +100,12 @@ impl JsonAssetLoader for MaterialLoader {
             ..Default::default()
         };
 
-        let handle = self.0.add_material(material);
+        let handle = self.0.renderer().add_material(material);
         Ok(handle)
     }
 }
 
-pub struct TextureLoader(Arc<Renderer>);
+pub struct TextureLoader(GuestScene);
 
 #[async_trait]
 impl JsonAssetLoader for TextureLoader {
@@ -121,12 +132,12 @@ impl JsonAssetLoader for TextureLoader {
             mip_source: MipmapSource::Uploaded,
         };
 
-        let handle = self.0.add_texture_2d(texture);
+        let handle = self.0.renderer().add_texture_2d(texture);
         Ok(handle)
     }
 }
 
-pub struct CubeTextureLoader(Arc<Renderer>);
+pub struct CubeTextureLoader(GuestScene);
 
 #[async_trait]
 impl JsonAssetLoader for CubeTextureLoader {
@@ -153,71 +164,422 @@ impl JsonAssetLoader for CubeTextureLoader {
             mip_source: MipmapSource::Generated,
         };
 
-        let handle = self.0.add_texture_cube(texture);
+        let handle = self.0.renderer().add_texture_cube(texture);
 
         Ok(handle)
     }
 }
 
-pub struct DirectionalLightInstance {
+/// Helper function to attempt to load an asset but log a warning and return
+/// a `RendererError::LumpError` if unsuccessful.
+async fn try_load_asset<T: AssetLoader>(
+    store: &AssetStore,
+    lump: &LumpId,
+) -> Result<Arc<T::Asset>, RendererError> {
+    store.load_asset::<T>(lump).await.map_err(|err| {
+        error!(
+            "failed to load {}: {err:?}",
+            std::any::type_name::<T::Asset>(),
+        );
+
+        RendererError::LumpError
+    })
+}
+
+/// The renderer that guest resources are created on, and every guest
+/// resource that's still alive.
+///
+/// When the GPU device is reset, [Self::restore] moves the scene to the new
+/// renderer by recreating every resource from what it was created with.
+#[derive(Clone)]
+pub struct GuestScene {
+    inner: Arc<SceneInner>,
+}
+
+struct SceneInner {
+    /// Held for reading while guest resources are created and for writing
+    /// while they're restored, so that the renderer can't change halfway
+    /// through creating a resource.
+    gate: RwLock<()>,
+    renderer: Mutex<Arc<Renderer>>,
+    objects: Mutex<Vec<Weak<Mutex<ObjectState>>>>,
+    lights: Mutex<Vec<Weak<Mutex<LightState>>>>,
+    skybox: Mutex<Option<LumpId>>,
+}
+
+impl GuestScene {
+    /// Creates an empty scene on a renderer.
+    pub fn new(renderer: Arc<Renderer>) -> Self {
+        Self {
+            inner: Arc::new(SceneInner {
+                gate: RwLock::new(()),
+                renderer: Mutex::new(renderer),
+                objects: Default::default(),
+                lights: Default::default(),
+                skybox: Default::default(),
+            }),
+        }
+    }
+
+    /// Gets the renderer that new guest resources are created on.
+    pub fn renderer(&self) -> Arc<Renderer> {
+        self.inner.renderer.lock().clone()
+    }
+
+    /// Adds a directional light to the scene.
+    async fn add_light(&self, state: DirectionalLightState) -> Arc<Mutex<LightState>> {
+        let _gate = self.inner.gate.read().await;
+        let light = LightState::new(self.renderer(), state);
+        let light = Arc::new(Mutex::new(light));
+        self.inner.lights.lock().push(Arc::downgrade(&light));
+        light
+    }
+
+    /// Loads an object's assets and adds it to the scene.
+    async fn add_object(
+        &self,
+        store: &AssetStore,
+        desc: ObjectDesc,
+    ) -> Result<Arc<Mutex<ObjectState>>, RendererError> {
+        let _gate = self.inner.gate.read().await;
+        let renderer = self.renderer();
+        let handles = ObjectHandles::new(store, &renderer, &desc).await?;
+
+        let object = Arc::new(Mutex::new(ObjectState {
+            renderer,
+            handles,
+            desc,
+        }));
+
+        self.inner.objects.lock().push(Arc::downgrade(&object));
+        Ok(object)
+    }
+
+    /// Loads a cube texture and makes it the skybox.
+    async fn set_skybox(
+        &self,
+        store: &AssetStore,
+        command_tx: &UnboundedSender<Rend3Command>,
+        lump: LumpId,
+    ) -> Result<(), RendererError> {
+        let _gate = self.inner.gate.read().await;
+        let texture = try_load_asset::<CubeTextureLoader>(store, &lump).await?;
+        *self.inner.skybox.lock() = Some(lump);
+        let _ = command_tx.send(Rend3Command::SetSkybox(texture.as_ref().clone()));
+        Ok(())
+    }
+
+    /// Recreates every live guest resource that uses an asset loaded from
+    /// `old` with the asset reloaded from `new`.
+    async fn reload(
+        &self,
+        store: &AssetStore,
+        command_tx: &UnboundedSender<Rend3Command>,
+        old: LumpId,
+        new: LumpId,
+    ) {
+        let _gate = self.inner.gate.read().await;
+        let renderer = self.renderer();
+
+        for object in live(&self.inner.objects) {
+            let mut desc = object.lock().desc.clone();
+            if desc.mesh != old && desc.material != old {
+                continue;
+            }
+
+            if desc.mesh == old {
+                desc.mesh = new;
+            }
+
+            if desc.material == old {
+                desc.material = new;
+            }
+
+            match ObjectHandles::new(store, &renderer, &desc).await {
+                Ok(handles) => {
+                    // the transform may have changed while the assets loaded
+                    let mut object = object.lock();
+                    object.desc.mesh = desc.mesh;
+                    object.desc.material = desc.material;
+                    object.replace(&renderer, handles);
+                }
+                Err(err) => error!("failed to reload object: {:?}", err),
+            }
+        }
+
+        let skybox = *self.inner.skybox.lock();
+        if skybox == Some(old) {
+            match try_load_asset::<CubeTextureLoader>(store, &new).await {
+                Ok(texture) => {
+                    *self.inner.skybox.lock() = Some(new);
+                    let _ = command_tx.send(Rend3Command::SetSkybox(texture.as_ref().clone()));
+                }
+                Err(err) => error!("failed to reload skybox: {:?}", err),
+            }
+        }
+    }
+
+    /// Recreates every live guest resource on a new renderer.
+    ///
+    /// Every asset that was loaded on the old renderer is forgotten, so that
+    /// it's uploaded again when it's next used.
+    pub async fn restore(
+        &self,
+        store: &AssetStore,
+        renderer: Arc<Renderer>,
+        command_tx: &UnboundedSender<Rend3Command>,
+    ) {
+        let _gate = self.inner.gate.write().await;
+        *self.inner.renderer.lock() = renderer.clone();
+
+        // materials hold textures, so forget textures first
+        let cleared = [
+            store.clear::<TextureLoader>().await,
+            store.clear::<CubeTextureLoader>().await,
+            store.clear::<MeshLoader>().await,
+            store.clear::<MaterialLoader>().await,
+        ];
+
+        for err in cleared.into_iter().filter_map(Result::err) {
+            error!("failed to forget guest assets: {:?}", err);
+        }
+
+        let lights = live(&self.inner.lights);
+        for light in lights.iter() {
+            light.lock().recreate(&renderer);
+        }
+
+        let objects = live(&self.inner.objects);
+        for object in objects.iter() {
+            let desc = object.lock().desc.clone();
+
+            match ObjectHandles::new(store, &renderer, &desc).await {
+                Ok(handles) => object.lock().replace(&renderer, handles),
+                Err(err) => error!("failed to restore object: {:?}", err),
+            }
+        }
+
+        let skybox = *self.inner.skybox.lock();
+        if let Some(lump) = skybox {
+            match try_load_asset::<CubeTextureLoader>(store, &lump).await {
+                Ok(texture) => {
+                    let _ = command_tx.send(Rend3Command::SetSkybox(texture.as_ref().clone()));
+                }
+                Err(err) => error!("failed to restore skybox: {:?}", err),
+            }
+        }
+
+        info!(
+            "Restored {} objects and {} lights after a GPU device reset",
+            objects.len(),
+            lights.len()
+        );
+    }
+}
+
+/// Upgrades every live resource in a list, forgetting dropped ones.
+fn live<T>(list: &Mutex<Vec<Weak<Mutex<T>>>>) -> Vec<Arc<Mutex<T>>> {
+    let mut list = list.lock();
+    list.retain(|item| item.strong_count() > 0);
+    list.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// A directional light and the state it's recreated from.
+struct LightState {
     renderer: Arc<Renderer>,
     handle: ResourceHandle<DirectionalLight>,
+    state: DirectionalLightState,
 }
 
-#[async_trait]
-impl SinkProcess for DirectionalLightInstance {
-    type Message = DirectionalLightUpdate;
+impl LightState {
+    fn new(renderer: Arc<Renderer>, state: DirectionalLightState) -> Self {
+        let handle = renderer.add_directional_light(Self::light(&state));
 
-    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>) {
+        Self {
+            renderer,
+            handle,
+            state,
+        }
+    }
+
+    fn light(state: &DirectionalLightState) -> DirectionalLight {
+        DirectionalLight {
+            color: state.color,
+            intensity: state.intensity,
+            direction: state.direction,
+            distance: state.distance,
+        }
+    }
+
+    fn recreate(&mut self, renderer: &Arc<Renderer>) {
+        self.handle = renderer.add_directional_light(Self::light(&self.state));
+        self.renderer = renderer.clone();
+    }
+
+    fn update(&mut self, update: DirectionalLightUpdate) {
         let mut change = DirectionalLightChange::default();
 
         use DirectionalLightUpdate::*;
-        match message.data {
-            Color(color) => change.color = Some(color),
-            Intensity(intensity) => change.intensity = Some(intensity),
-            Direction(direction) => change.direction = Some(direction),
-            Distance(distance) => change.distance = Some(distance),
+        match update {
+            Color(color) => {
+                self.state.color = color;
+                change.color = Some(color);
+            }
+            Intensity(intensity) => {
+                self.state.intensity = intensity;
+                change.intensity = Some(intensity);
+            }
+            Direction(direction) => {
+                self.state.direction = direction;
+                change.direction = Some(direction);
+            }
+            Distance(distance) => {
+                self.state.distance = distance;
+                change.distance = Some(distance);
+            }
         }
 
         self.renderer.update_directional_light(&self.handle, change);
     }
 }
 
-pub struct ObjectInstance {
-    renderer: Arc<Renderer>,
-    handle: ObjectHandle,
+/// What an object is created from.
+#[derive(Clone)]
+struct ObjectDesc {
+    mesh: LumpId,
+    material: LumpId,
+    transform: Mat4,
+
+    /// The object's current joint matrices, if it's animated.
+    joint_matrices: Option<Vec<Mat4>>,
+}
+
+/// The renderer handles of an object.
+struct ObjectHandles {
+    object: ObjectHandle,
     skeleton: Option<SkeletonHandle>,
 }
 
+impl ObjectHandles {
+    /// Loads an object's assets and adds it to a renderer.
+    async fn new(
+        store: &AssetStore,
+        renderer: &Arc<Renderer>,
+        desc: &ObjectDesc,
+    ) -> Result<Self, RendererError> {
+        let mesh = try_load_asset::<MeshLoader>(store, &desc.mesh).await?;
+        let material = try_load_asset::<MaterialLoader>(store, &desc.material).await?;
+
+        let (mesh_kind, skeleton) = if let Some(joint_matrices) = desc.joint_matrices.as_ref() {
+            let skeleton = renderer.add_skeleton(Skeleton {
+                joint_matrices: joint_matrices.to_owned(),
+                mesh: mesh.as_ref().to_owned(),
+            });
+
+            (ObjectMeshKind::Animated(skeleton.clone()), Some(skeleton))
+        } else {
+            (ObjectMeshKind::Static(mesh.as_ref().to_owned()), None)
+        };
+
+        let object = renderer.add_object(Object {
+            mesh_kind,
+            material: material.as_ref().to_owned(),
+            transform: desc.transform,
+        });
+
+        Ok(Self { object, skeleton })
+    }
+}
+
+/// An object and what it's recreated from.
+struct ObjectState {
+    renderer: Arc<Renderer>,
+    handles: ObjectHandles,
+    desc: ObjectDesc,
+}
+
+impl ObjectState {
+    /// Replaces this object with one recreated on a new renderer, catching
+    /// it up on updates made while it was being recreated.
+    fn replace(&mut self, renderer: &Arc<Renderer>, handles: ObjectHandles) {
+        self.renderer = renderer.clone();
+        self.handles = handles;
+        self.renderer
+            .set_object_transform(&self.handles.object, self.desc.transform);
+
+        if let (Some(skeleton), Some(matrices)) = (
+            self.handles.skeleton.as_ref(),
+            self.desc.joint_matrices.as_ref(),
+        ) {
+            self.renderer
+                .set_skeleton_joint_matrices(skeleton, matrices.to_owned());
+        }
+    }
+}
+
+pub struct DirectionalLightInstance {
+    light: Arc<Mutex<LightState>>,
+}
+
+#[async_trait]
+impl SinkProcess for DirectionalLightInstance {
+    type Message = DirectionalLightUpdate;
+
+    async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>) {
+        self.light.lock().update(message.data);
+    }
+}
+
+pub struct ObjectInstance {
+    object: Arc<Mutex<ObjectState>>,
+}
+
 #[async_trait]
 impl SinkProcess for ObjectInstance {
     type Message = ObjectUpdate;
 
     async fn on_message<'a>(&'a mut self, message: MessageInfo<'a, Self::Message>) {
+        let mut object = self.object.lock();
+        let object = &mut *object;
+
         use ObjectUpdate::*;
         match &message.data {
             Transform(transform) => {
-                self.renderer.set_object_transform(&self.handle, *transform);
+                object.desc.transform = *transform;
+                object
+                    .renderer
+                    .set_object_transform(&object.handles.object, *transform);
             }
             JointMatrices(matrices) => {
-                let Some(skeleton) = self.skeleton.as_ref() else {
+                let Some(skeleton) = object.handles.skeleton.as_ref() else {
                     warn!("tried to update joint matrices on static object");
                     return;
                 };
 
-                self.renderer
+                object.desc.joint_matrices = Some(matrices.to_owned());
+                object
+                    .renderer
                     .set_skeleton_joint_matrices(skeleton, matrices.to_owned());
             }
             JointTransforms {
                 joint_global,
                 inverse_bind,
             } => {
-                let Some(skeleton) = self.skeleton.as_ref() else {
+                let Some(skeleton) = object.handles.skeleton.as_ref() else {
                     warn!("tried to update joint transforms on static object");
                     return;
                 };
 
-                self.renderer
+                // the same matrices that rend3 computes from these
+                let matrices = joint_global
+                    .iter()
+                    .zip(inverse_bind.iter())
+                    .map(|(global, inverse)| *global * *inverse)
+                    .collect();
+
+                object.desc.joint_matrices = Some(matrices);
+                object
+                    .renderer
                     .set_skeleton_joint_transforms(skeleton, joint_global, inverse_bind);
             }
         }
@@ -226,7 +588,7 @@ impl SinkProcess for ObjectInstance {
 
 /// Implements the renderer message protocol.
 pub struct RendererService {
-    renderer: Arc<Renderer>,
+    scene: GuestScene,
     command_tx: UnboundedSender<Rend3Command>,
 }
 
@@ -239,22 +601,13 @@ impl RequestResponseProcess for RendererService {
         &'a mut self,
         request: &mut RequestInfo<'a, Self::Request>,
     ) -> ResponseInfo<'a, Self::Response> {
+        let store = &request.runtime.asset_store;
+
         use RendererRequest::*;
         match &request.data {
             AddDirectionalLight { initial_state } => {
-                let light = DirectionalLight {
-                    color: initial_state.color,
-                    intensity: initial_state.intensity,
-                    direction: initial_state.direction,
-                    distance: initial_state.distance,
-                };
-
-                let handle = self.renderer.add_directional_light(light);
-
-                let instance = DirectionalLightInstance {
-                    renderer: self.renderer.clone(),
-                    handle,
-                };
+                let light = self.scene.add_light(initial_state.to_owned()).await;
+                let instance = DirectionalLightInstance { light };
 
                 let mut meta = cargo_process_metadata!();
                 meta.name = Some("DirectionalLight".to_string());
@@ -276,42 +629,20 @@ impl RequestResponseProcess for RendererService {
                 material,
                 transform,
             } => {
-                let mesh = match Self::try_load_asset::<MeshLoader>(&request, mesh).await {
-                    Ok(mesh) => mesh,
-                    Err(err) => return err.into(),
-                };
-
-                let material =
-                    match Self::try_load_asset::<MaterialLoader>(&request, material).await {
-                        Ok(material) => material,
-                        Err(err) => return err.into(),
-                    };
-
-                let (mesh_kind, skeleton) = if let Some(skeleton) = skeleton.as_ref() {
-                    let skeleton = self.renderer.add_skeleton(Skeleton {
-                        joint_matrices: skeleton.to_owned(),
-                        mesh: mesh.as_ref().to_owned(),
-                    });
-
-                    (ObjectMeshKind::Animated(skeleton.clone()), Some(skeleton))
-                } else {
-                    (ObjectMeshKind::Static(mesh.as_ref().to_owned()), None)
-                };
-
-                let object = Object {
-                    mesh_kind,
-                    material: material.as_ref().to_owned(),
+                let desc = ObjectDesc {
+                    mesh: *mesh,
+                    material: *material,
                     transform: *transform,
+                    joint_matrices: skeleton.to_owned(),
                 };
 
-                let handle = self.renderer.add_object(object);
-
-                let instance = ObjectInstance {
-                    renderer: self.renderer.clone(),
-                    handle,
-                    skeleton,
+                let object = match self.scene.add_object(store, desc).await {
+                    Ok(object) => object,
+                    Err(err) => return err.into(),
                 };
 
+                let instance = ObjectInstance { object };
+
                 let mut meta = cargo_process_metadata!();
                 meta.name = Some("ObjectInstance".to_string());
                 meta.description =
@@ -325,19 +656,30 @@ impl RequestResponseProcess for RendererService {
                 };
             }
             SetSkybox { texture } => {
-                let texture =
-                    match Self::try_load_asset::<CubeTextureLoader>(&request, texture).await {
-                        Ok(texture) => texture,
-                        Err(err) => return err.into(),
-                    };
-
-                let _ = self
-                    .command_tx
-                    .send(Rend3Command::SetSkybox(texture.as_ref().clone()));
+                let result = self
+                    .scene
+                    .set_skybox(store, &self.command_tx, *texture)
+                    .await;
+
+                if let Err(err) = result {
+                    return err.into();
+                }
             }
             SetAmbientLighting { ambient } => {
                 let _ = self.command_tx.send(Rend3Command::SetAmbient(*ambient));
             }
+            SetSampleCount { samples } => {
+                let Ok(samples) = SampleCount::try_from(*samples) else {
+                    return RendererError::UnsupportedSampleCount.into();
+                };
+
+                let _ = self.command_tx.send(Rend3Command::SetSampleCount(samples));
+            }
+            SetResolutionScale { scale } => {
+                let _ = self
+                    .command_tx
+                    .send(Rend3Command::SetResolutionScale(*scale));
+            }
         }
 
         ResponseInfo {
@@ -360,32 +702,113 @@ impl ServiceRunner for RendererService {
 }
 
 impl RendererService {
-    pub fn new(renderer: Arc<Renderer>, command_tx: UnboundedSender<Rend3Command>) -> Self {
-        Self {
-            renderer,
-            command_tx,
+    pub fn new(scene: GuestScene, command_tx: UnboundedSender<Rend3Command>) -> Self {
+        Self { scene, command_tx }
+    }
+}
+
+/// Captures screenshots of the renderer. Accepts [ScreenshotRequest].
+pub struct ScreenshotService {
+    capture_tx: UnboundedSender<CaptureRequest>,
+}
+
+#[async_trait]
+impl RequestResponseProcess for ScreenshotService {
+    type Request = ScreenshotRequest;
+    type Response = ScreenshotResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, ScreenshotRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let (reply, capture) = oneshot::channel();
+
+        let _ = self.capture_tx.send(CaptureRequest {
+            resolution: request.data.resolution,
+            format: CaptureFormat::Rgba8,
+            reply,
+        });
+
+        let Ok(capture) = capture.await else {
+            let response: ScreenshotResponse = Err(ScreenshotError::CaptureFailed);
+            return response.into();
+        };
+
+        // PNG encoding is CPU-heavy, so keep it off of the async workers
+        let encoded = hearth_runtime::tokio::task::spawn_blocking(move || {
+            let size = capture.resolution;
+            let image = image::RgbaImage::from_raw(size.x, size.y, capture.data)?;
+            let mut png = Cursor::new(Vec::new());
+            image
+                .write_to(&mut png, image::ImageOutputFormat::Png)
+                .ok()?;
+            Some(png.into_inner())
+        })
+        .await;
+
+        let response: ScreenshotResponse = match encoded {
+            Ok(Some(png)) => {
+                let metadata = LumpMetadata {
+                    content_type: Some("image/png".to_string()),
+                    origin: Some(LumpOrigin::Host),
+                };
+
+                Ok(request
+                    .runtime
+                    .lump_store
+                    .add_lump_with(png.into(), metadata)
+                    .await)
+            }
+            _ => Err(ScreenshotError::EncodingFailed),
+        };
+
+        response.into()
+    }
+}
+
+impl ServiceRunner for ScreenshotService {
+    const NAME: &'static str = SCREENSHOT_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Captures screenshots of the renderer as PNG lumps. Accepts ScreenshotRequest."
+                .to_string(),
+        );
+
+        meta
+    }
+}
+
+/// Provides renderer statistics to guests. Accepts [RenderStatsRequest].
+pub struct RenderStatsService {
+    stats: Arc<RenderStats>,
+}
+
+#[async_trait]
+impl RequestResponseProcess for RenderStatsService {
+    type Request = RenderStatsRequest;
+    type Response = RenderStatsResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, RenderStatsRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match request.data {
+            RenderStatsRequest::GetFrameTimings => self.stats.history().into(),
         }
     }
+}
 
-    /// Helper function to attempt to load an asset but log a warning and return
-    /// a `RendererError::LumpError` if unsuccessful.
-    async fn try_load_asset<T: AssetLoader>(
-        request: &RequestInfo<'_, RendererRequest>,
-        lump: &LumpId,
-    ) -> Result<Arc<T::Asset>, RendererError> {
-        request
-            .runtime
-            .asset_store
-            .load_asset::<T>(lump)
-            .await
-            .map_err(|err| {
-                error!(
-                    "failed to load {}: {err:?}",
-                    std::any::type_name::<T::Asset>(),
-                );
+impl ServiceRunner for RenderStatsService {
+    const NAME: &'static str = RENDER_STATS_SERVICE;
 
-                RendererError::LumpError
-            })
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Provides renderer statistics. Accepts RenderStatsRequest.".to_string());
+
+        meta
     }
 }
 
@@ -399,14 +822,225 @@ impl Plugin for RendererPlugin {
             .get_plugin::<Rend3Plugin>()
             .expect("rend3 plugin was not found");
 
-        let renderer = rend3.renderer.clone();
+        let scene = GuestScene::new(rend3.renderer.clone());
+        let mut devices = rend3.subscribe_device();
         let command_tx = rend3.command_tx.clone();
+        let capture_tx = rend3.capture_request_tx.clone();
+        let stats = rend3.stats.clone();
 
         builder
-            .add_asset_loader(MeshLoader(renderer.clone()))
-            .add_asset_loader(MaterialLoader(renderer.clone()))
-            .add_asset_loader(TextureLoader(renderer.clone()))
-            .add_asset_loader(CubeTextureLoader(renderer.clone()))
-            .add_plugin(RendererService::new(renderer, command_tx));
+            .add_asset_loader(MeshLoader(scene.clone()))
+            .add_asset_loader(MaterialLoader(scene.clone()))
+            .add_asset_loader(TextureLoader(scene.clone()))
+            .add_asset_loader(CubeTextureLoader(scene.clone()))
+            .add_plugin(RendererService::new(scene.clone(), command_tx.clone()))
+            .add_plugin(ScreenshotService { capture_tx })
+            .add_plugin(RenderStatsService { stats });
+
+        // swap reloaded assets into the guest resources that use them
+        let reload_scene = scene.clone();
+        let reload_tx = command_tx.clone();
+        builder.add_runner(move |runtime| {
+            let store = &runtime.asset_store;
+            let mut meshes = store.subscribe::<MeshLoader>().unwrap();
+            let mut materials = store.subscribe::<MaterialLoader>().unwrap();
+            let mut skyboxes = store.subscribe::<CubeTextureLoader>().unwrap();
+
+            tokio::spawn(async move {
+                loop {
+                    let reload = tokio::select! {
+                        reload = meshes.recv() => reload.map(|reload| (reload.old, reload.new)),
+                        reload = materials.recv() => reload.map(|reload| (reload.old, reload.new)),
+                        reload = skyboxes.recv() => reload.map(|reload| (reload.old, reload.new)),
+                    };
+
+                    match reload {
+                        Ok((old, new)) => {
+                            reload_scene
+                                .reload(&runtime.asset_store, &reload_tx, old, new)
+                                .await
+                        }
+                        Err(RecvError::Lagged(missed)) => {
+                            warn!("missed {} asset reloads", missed)
+                        }
+                        Err(RecvError::Closed) => break,
+                    }
+                }
+            });
+        });
+
+        // move guest resources to each new renderer after a device reset
+        builder.add_runner(move |runtime| {
+            tokio::spawn(async move {
+                while devices.changed().await.is_ok() {
+                    let renderer = devices.borrow_and_update().renderer.clone();
+                    scene
+                        .restore(&runtime.asset_store, renderer, &command_tx)
+                        .await;
+                }
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::{UVec2, Vec2, Vec3, Vec4};
+    use hearth_rend3::{wgpu::TextureFormat, FrameOutcome};
+    use hearth_runtime::{hearth_schema::ByteVec, lump::LumpStoreImpl};
+
+    /// A single triangle.
+    fn triangle() -> MeshData {
+        MeshData {
+            positions: ByteVec(vec![Vec3::ZERO, Vec3::X, Vec3::Y]),
+            normals: ByteVec(vec![Vec3::Z; 3]),
+            tangents: ByteVec(vec![Vec3::X; 3]),
+            uv0: ByteVec(vec![Vec2::ZERO; 3]),
+            uv1: ByteVec(vec![Vec2::ZERO; 3]),
+            colors: ByteVec(vec![[0xff; 4]; 3]),
+            joint_indices: ByteVec(vec![[0; 4]; 3]),
+            joint_weights: ByteVec(vec![Vec4::ZERO; 3]),
+            indices: ByteVec(vec![0, 1, 2]),
+        }
+    }
+
+    #[tokio::test]
+    async fn guest_scene_renders_after_device_reset() {
+        let size = UVec2::new(64, 64);
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let mut rend3 = match Rend3Plugin::new_headless(size, format).await {
+            Ok(plugin) => plugin,
+            Err(err) => {
+                eprintln!("skipping device reset test without an adapter: {}", err);
+                return;
+            }
+        };
+
+        let scene = GuestScene::new(rend3.renderer.clone());
+        let lumps = Arc::new(LumpStoreImpl::new());
+        let mut store = AssetStore::new(lumps.clone());
+        store.add_loader(MeshLoader(scene.clone()));
+        store.add_loader(MaterialLoader(scene.clone()));
+        store.add_loader(TextureLoader(scene.clone()));
+        store.add_loader(CubeTextureLoader(scene.clone()));
+
+        let texture = TextureData {
+            label: None,
+            size: UVec2::ONE,
+            data: vec![0xff; 4],
+        };
+
+        let texture = serde_json::to_vec(&texture).unwrap();
+        let albedo = lumps.add_lump(texture.into()).await;
+        let material = serde_json::to_vec(&MaterialData { albedo }).unwrap();
+        let material = lumps.add_lump(material.into()).await;
+        let mesh = serde_json::to_vec(&triangle()).unwrap();
+        let mesh = lumps.add_lump(mesh.into()).await;
+
+        let desc = ObjectDesc {
+            mesh,
+            material,
+            transform: Mat4::IDENTITY,
+            joint_matrices: None,
+        };
+
+        let object = scene.add_object(&store, desc).await.unwrap();
+
+        let light = scene
+            .add_light(DirectionalLightState {
+                color: Vec3::ONE,
+                intensity: 1.0,
+                direction: -Vec3::Y,
+                distance: 10.0,
+            })
+            .await;
+
+        let old_mesh = store.load_asset::<MeshLoader>(&mesh).await.unwrap();
+
+        rend3.reset_device().await.unwrap();
+        let renderer = rend3.renderer.clone();
+        scene.restore(&store, renderer, &rend3.command_tx).await;
+
+        assert!(Arc::ptr_eq(&scene.renderer(), &rend3.renderer));
+        assert!(Arc::ptr_eq(&object.lock().renderer, &rend3.renderer));
+        assert!(Arc::ptr_eq(&light.lock().renderer, &rend3.renderer));
+
+        // assets are uploaded again instead of reusing dead handles
+        let new_mesh = store.load_asset::<MeshLoader>(&mesh).await.unwrap();
+        assert!(!Arc::ptr_eq(&old_mesh, &new_mesh));
+
+        let camera = Camera {
+            projection: CameraProjection::Perspective {
+                vfov: 60.0,
+                near: 0.1,
+            },
+            view: Mat4::IDENTITY,
+        };
+
+        let (request, rendered) = rend3.headless_frame_request(camera).unwrap();
+        rend3.draw(request);
+        assert_eq!(rendered.await, Ok(FrameOutcome::Rendered));
+    }
+
+    #[tokio::test]
+    async fn guest_objects_follow_reloaded_assets() {
+        let size = UVec2::new(64, 64);
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let rend3 = match Rend3Plugin::new_headless(size, format).await {
+            Ok(plugin) => plugin,
+            Err(err) => {
+                eprintln!("skipping asset reload test without an adapter: {}", err);
+                return;
+            }
+        };
+
+        let scene = GuestScene::new(rend3.renderer.clone());
+        let lumps = Arc::new(LumpStoreImpl::new());
+        let mut store = AssetStore::new(lumps.clone());
+        store.add_loader(MeshLoader(scene.clone()));
+        store.add_loader(MaterialLoader(scene.clone()));
+        store.add_loader(TextureLoader(scene.clone()));
+
+        let texture = TextureData {
+            label: None,
+            size: UVec2::ONE,
+            data: vec![0xff; 4],
+        };
+
+        let texture = serde_json::to_vec(&texture).unwrap();
+        let albedo = lumps.add_lump(texture.into()).await;
+        let material = serde_json::to_vec(&MaterialData { albedo }).unwrap();
+        let material = lumps.add_lump(material.into()).await;
+        let old_mesh = serde_json::to_vec(&triangle()).unwrap();
+        let old_mesh = lumps.add_lump(old_mesh.into()).await;
+
+        let mut flipped = triangle();
+        flipped.indices = ByteVec(vec![0, 2, 1]);
+        let new_mesh = serde_json::to_vec(&flipped).unwrap();
+        let new_mesh = lumps.add_lump(new_mesh.into()).await;
+
+        let desc = ObjectDesc {
+            mesh: old_mesh,
+            material,
+            transform: Mat4::IDENTITY,
+            joint_matrices: None,
+        };
+
+        let object = scene.add_object(&store, desc).await.unwrap();
+
+        store
+            .reload::<MeshLoader>(&old_mesh, &new_mesh)
+            .await
+            .unwrap();
+
+        scene
+            .reload(&store, &rend3.command_tx, old_mesh, new_mesh)
+            .await;
+
+        let object = object.lock();
+        assert_eq!(object.desc.mesh, new_mesh);
+        assert_eq!(object.desc.material, material);
     }
 }
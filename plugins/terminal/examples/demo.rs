@@ -18,6 +18,7 @@
 
 use std::f32::consts::FRAC_PI_2;
 use std::sync::Arc;
+use std::time::Duration;
 
 use glam::Vec2;
 use hearth_rend3::rend3::graph::RenderGraph;
@@ -27,9 +28,9 @@ use hearth_rend3::rend3_routine::base::{BaseRenderGraph, BaseRenderGraphIntermed
 use hearth_rend3::wgpu::{self, TextureFormat};
 use hearth_schema::terminal::TerminalState;
 use hearth_schema::Color;
-use hearth_terminal::draw::{TerminalDrawState, TerminalPipelines};
+use hearth_terminal::draw::{DrawStats, TerminalBatch, TerminalDrawState, TerminalPipelines};
 use hearth_terminal::terminal::{Terminal, TerminalConfig};
-use hearth_terminal::text::{FaceAtlas, FontSet};
+use hearth_terminal::text::{AtlasDevice, FaceAtlas, FallbackFace, FontSet};
 use winit::dpi::PhysicalPosition;
 use winit::event::{ElementState, Event, MouseButton, MouseScrollDelta, WindowEvent};
 use winit::event_loop::ControlFlow;
@@ -43,6 +44,7 @@ fn load_skybox_image(data: &mut Vec<u8>, image: &[u8]) {
 
 pub struct DemoInner {
     pipelines: TerminalPipelines,
+    batch: TerminalBatch,
     draw_state: TerminalDrawState,
     terminal: Arc<Terminal>,
     skybox: TextureHandle,
@@ -66,8 +68,8 @@ impl DemoInner {
         };
 
         let fonts = ttf_srcs.map(|src| {
-            let face = owned_ttf_parser::OwnedFace::from_vec(src, 0).unwrap();
-            let face_atlas = FaceAtlas::new(face, &renderer.device, renderer.queue.to_owned());
+            let queue = renderer.queue.to_owned();
+            let face_atlas = FaceAtlas::from_data(src, renderer.device.clone(), queue).unwrap();
             Arc::new(face_atlas)
         });
 
@@ -108,12 +110,38 @@ impl DemoInner {
             renderer.device.clone(),
             renderer.queue.clone(),
             surface_format,
+            SAMPLE_COUNT,
         );
 
         let command = None; // autoselect shell
-        let config = TerminalConfig { fonts, command };
-        let terminal = Terminal::new(config.clone(), state.clone());
-        let draw_state = TerminalDrawState::new(&pipelines, terminal.get_fonts());
+                            // load fallback fonts from the command line
+        let atlas_device = AtlasDevice::new(renderer.device.clone(), renderer.queue.clone());
+        let fallbacks = std::env::args()
+            .skip(1)
+            .filter_map(|path| {
+                let src = std::fs::read(&path).unwrap();
+                FallbackFace::new(path, src, atlas_device.clone())
+            })
+            .collect();
+
+        let palette = hearth_terminal::palette::default_palette();
+        let config = TerminalConfig {
+            fonts,
+            fallbacks,
+            command,
+            palette,
+            grid_size: None,
+            hold: false,
+            resize_delay: Duration::from_millis(50),
+        };
+        let terminal = Terminal::new(config.clone(), state.clone()).unwrap();
+        let batch = TerminalBatch::new(&pipelines, Arc::new(DrawStats::default()));
+
+        // print some box drawing, emoji, and CJK to exercise fallback fonts
+        terminal.send_input("echo '┌─┬─┐ ╭──╮ ▒▓█ 😀 🦀 漢字かな'\n");
+
+        // color emoji are drawn from a separate atlas alongside plain text
+        terminal.send_input("echo 'emoji: 🎉 ok 🔥 done 👍'\n");
 
         // load skybox
         let mut data = Vec::new();
@@ -135,7 +163,8 @@ impl DemoInner {
 
         Self {
             pipelines,
-            draw_state,
+            batch,
+            draw_state: TerminalDrawState::default(),
             terminal,
             skybox,
             orbit_pitch: 0.0,
@@ -331,7 +360,12 @@ impl rend3_framework::App for Demo {
                     surface: Arc::clone(surface.unwrap()),
                 };
 
-                inner.terminal.update_draw_state(&mut inner.draw_state);
+                inner.terminal.request_draw_state(true);
+                if let Some(draw_state) = inner.terminal.take_draw_state() {
+                    inner.draw_state = draw_state;
+                }
+
+                inner.batch.update(&inner.pipelines, &[&inner.draw_state]);
 
                 let pbr_routine = rend3_framework::lock(&routines.pbr);
                 let mut skybox_routine = rend3_framework::lock(&routines.skybox);
@@ -360,11 +394,17 @@ impl rend3_framework::App for Demo {
                     SAMPLE_COUNT,
                 );
 
-                let draws = &[&inner.draw_state];
                 let output = graph.add_surface_texture();
-                inner
-                    .pipelines
-                    .add_to_graph(draws, &mut graph, output, state.depth);
+                inner.pipelines.add_to_graph(
+                    &inner.batch,
+                    &mut graph,
+                    output,
+                    None,
+                    state.depth,
+                    resolution,
+                    None,
+                    None,
+                );
 
                 graph.execute(renderer, frame, cmd_bufs, &ready);
             }
@@ -0,0 +1,238 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Renders a terminal into a shared render target and maps it onto a quad
+//! in the PBR scene. Press F1 to toggle the target's resolution.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use glam::{Mat4, Quat, UVec2, Vec2, Vec3};
+use hearth_rend3::rend3::types::*;
+use hearth_rend3::rend3::util::output::OutputFrame;
+use hearth_rend3::rend3_routine::pbr::{AlbedoComponent, PbrMaterial};
+use hearth_rend3::targets::TargetTexture;
+use hearth_rend3::wgpu::{self, TextureFormat};
+use hearth_rend3::{FrameRequest, Rend3Plugin};
+use hearth_schema::terminal::TerminalState;
+use hearth_terminal::draw::{target_view_proj, DrawStats};
+use hearth_terminal::terminal::{Terminal, TerminalConfig};
+use hearth_terminal::text::{FaceAtlas, FontSet};
+use hearth_terminal::{BlinkPhase, TerminalRoutine, TerminalTarget};
+use tokio::sync::{mpsc, oneshot};
+use winit::event::{ElementState, Event, VirtualKeyCode, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::WindowBuilder;
+
+const SMALL_TARGET: UVec2 = UVec2::new(512, 384);
+const LARGE_TARGET: UVec2 = UVec2::new(1536, 1152);
+
+fn quad_material(texture: &TargetTexture) -> PbrMaterial {
+    PbrMaterial {
+        albedo: AlbedoComponent::Texture(texture.handle.clone()),
+        unlit: true,
+        ..Default::default()
+    }
+}
+
+fn quad_mesh(half_size: Vec2) -> Mesh {
+    let (x, y) = (half_size.x, half_size.y);
+
+    let vertices = vec![
+        Vec3::new(-x, y, 0.0),
+        Vec3::new(-x, -y, 0.0),
+        Vec3::new(x, -y, 0.0),
+        Vec3::new(x, y, 0.0),
+    ];
+
+    // the top of the target is the first row of the texture
+    let uvs = vec![
+        Vec2::new(0.0, 0.0),
+        Vec2::new(0.0, 1.0),
+        Vec2::new(1.0, 1.0),
+        Vec2::new(1.0, 0.0),
+    ];
+
+    MeshBuilder::new(vertices, Handedness::Right)
+        .with_vertex_normals(vec![Vec3::Z; 4])
+        .with_vertex_uv0(uvs)
+        .with_indices(vec![0, 1, 2, 0, 2, 3])
+        .build()
+        .unwrap()
+}
+
+#[tokio::main]
+async fn main() {
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("Hearth terminal texture")
+        .build(&event_loop)
+        .unwrap();
+
+    let size = window.inner_size();
+    let format = TextureFormat::Bgra8UnormSrgb;
+    let iad = hearth_rend3::create_iad(None).await.unwrap();
+    let surface = Arc::new(unsafe { iad.instance.create_surface(&window) });
+
+    let mut config = wgpu::SurfaceConfiguration {
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        format,
+        width: size.width,
+        height: size.height,
+        present_mode: wgpu::PresentMode::Fifo,
+    };
+
+    surface.configure(&iad.device, &config);
+
+    let mut rend3 = Rend3Plugin::new(iad.to_owned(), format);
+    let renderer = rend3.renderer.clone();
+
+    let ttf_srcs = FontSet {
+        regular: include_bytes!("../../../resources/mononoki/mononoki-Regular.ttf").to_vec(),
+        italic: include_bytes!("../../../resources/mononoki/mononoki-Italic.ttf").to_vec(),
+        bold: include_bytes!("../../../resources/mononoki/mononoki-Bold.ttf").to_vec(),
+        bold_italic: include_bytes!("../../../resources/mononoki/mononoki-BoldItalic.ttf").to_vec(),
+    };
+
+    let fonts = ttf_srcs.map(|src| {
+        let queue = renderer.queue.to_owned();
+        let face_atlas = FaceAtlas::from_data(src, renderer.device.clone(), queue).unwrap();
+        Arc::new(face_atlas)
+    });
+
+    let state = TerminalState {
+        position: Vec3::ZERO,
+        orientation: Quat::IDENTITY,
+        half_size: Vec2::new(1.2, 0.9),
+        padding: Vec2::splat(0.1),
+        opacity: 1.0,
+        units_per_em: 0.04,
+        colors: Default::default(),
+    };
+
+    let terminal = Terminal::new(
+        TerminalConfig {
+            fonts,
+            fallbacks: vec![],
+            command: None,
+            palette: hearth_terminal::palette::default_palette(),
+            grid_size: None,
+            hold: false,
+            resize_delay: Duration::from_millis(50),
+        },
+        state.clone(),
+    )
+    .unwrap();
+
+    // draw the terminal into a target instead of the scene
+    let target = rend3.create_target(
+        "terminal texture",
+        SMALL_TARGET,
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let mut texture_rx = target.subscribe();
+
+    let (new_terminals_tx, new_terminals) = mpsc::unbounded_channel();
+    let (_new_fonts_tx, new_fonts) = mpsc::unbounded_channel();
+    let blink = BlinkPhase::new(Some(Duration::from_millis(500)));
+    let stats = Arc::new(DrawStats::default());
+    let mut routine = TerminalRoutine::new(&rend3, new_terminals, new_fonts, blink, stats);
+
+    routine.set_target(Some(TerminalTarget {
+        target: target.clone(),
+        view_proj: target_view_proj(Mat4::IDENTITY, state.half_size),
+    }));
+
+    rend3.add_routine(routine);
+    let _ = new_terminals_tx.send(terminal.clone());
+
+    // map the target's texture onto a quad the size of the terminal
+    let mesh = renderer.add_mesh(quad_mesh(state.half_size));
+    let material = renderer.add_material(quad_material(&target.texture()));
+    let object = renderer.add_object(Object {
+        mesh_kind: ObjectMeshKind::Static(mesh),
+        material: material.clone(),
+        transform: Mat4::IDENTITY,
+    });
+
+    let camera = Camera {
+        projection: CameraProjection::Perspective {
+            vfov: 60.0,
+            near: 0.1,
+        },
+        view: Mat4::look_at_rh(Vec3::new(0.0, 0.5, 3.0), Vec3::ZERO, Vec3::Y),
+    };
+
+    let start = Instant::now();
+
+    event_loop.run(move |event, _, control_flow| match event {
+        Event::WindowEvent { event, .. } => match event {
+            WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit,
+            WindowEvent::Resized(size) => {
+                config.width = size.width.max(1);
+                config.height = size.height.max(1);
+                surface.configure(&iad.device, &config);
+            }
+            WindowEvent::ReceivedCharacter(c) => {
+                terminal.send_input(c.to_string().as_str());
+            }
+            WindowEvent::KeyboardInput { input, .. } => {
+                let pressed = input.state == ElementState::Pressed;
+                if pressed && input.virtual_keycode == Some(VirtualKeyCode::F1) {
+                    let size = if target.texture().size == SMALL_TARGET {
+                        LARGE_TARGET
+                    } else {
+                        SMALL_TARGET
+                    };
+
+                    target.resize(size);
+                }
+            }
+            _ => {}
+        },
+        Event::MainEventsCleared => {
+            if terminal.should_quit() {
+                *control_flow = ControlFlow::Exit;
+            } else {
+                window.request_redraw();
+            }
+        }
+        Event::RedrawRequested(_) => {
+            // resizing replaces the texture, so point the material at it
+            if texture_rx.has_changed().unwrap_or(false) {
+                let texture = texture_rx.borrow_and_update().clone();
+                renderer.update_material(&material, quad_material(&texture));
+            }
+
+            let angle = (start.elapsed().as_secs_f32() * 0.5).sin() * 0.6;
+            renderer.set_object_transform(&object, Mat4::from_rotation_y(angle));
+
+            let (on_complete, _on_complete_rx) = oneshot::channel();
+            rend3.flush_commands();
+            rend3.draw(FrameRequest {
+                output_frame: OutputFrame::Surface {
+                    surface: surface.clone(),
+                },
+                resolution: UVec2::new(config.width, config.height),
+                camera,
+                on_complete,
+            });
+        }
+        _ => {}
+    });
+}
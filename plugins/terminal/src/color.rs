@@ -0,0 +1,550 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use glam::{vec2, UVec2, Vec2};
+use hearth_rend3::wgpu::{util::DeviceExt, *};
+use hearth_runtime::tracing::{debug, warn};
+use image::{imageops::FilterType, RgbaImage};
+use owned_ttf_parser::{
+    colr::Painter, Face, GlyphId, OutlineBuilder, RasterImageFormat, RgbaColor,
+};
+
+/// The height of every glyph in a [ColorAtlas], in texels.
+///
+/// Color glyphs are bitmaps, so unlike MSDF glyphs they're rasterized once
+/// at a fixed resolution that's about the height of a cell up close.
+pub const COLOR_GLYPH_SIZE: u32 = 64;
+
+/// The width and height of a [ColorAtlas]'s texture, in texels.
+pub const COLOR_ATLAS_SIZE: u32 = 1024;
+
+/// The empty space between glyphs in a [ColorAtlas], in texels, so that
+/// filtering doesn't bleed neighboring glyphs into each other.
+const COLOR_GLYPH_PADDING: u32 = 1;
+
+/// The color that COLR layers painted in the foreground color are drawn
+/// with, since color glyphs ignore the cell's foreground color.
+const COLR_FOREGROUND: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// Tests if a glyph is drawn in color, either from a bitmap (CBDT or sbix)
+/// or from colored layers (COLR).
+///
+/// MSDF atlases only hold outlines, so color glyphs need to be drawn from a
+/// [ColorAtlas] instead.
+pub fn is_color_glyph(face: &Face, glyph: u16) -> bool {
+    let id = GlyphId(glyph);
+
+    face.is_color_glyph(id)
+        || face
+            .glyph_raster_image(id, u16::MAX)
+            .map_or(false, |image| is_color_format(image.format))
+}
+
+fn is_color_format(format: RasterImageFormat) -> bool {
+    matches!(
+        format,
+        RasterImageFormat::PNG | RasterImageFormat::BitmapPremulBgra32
+    )
+}
+
+/// Rasterizes a color glyph into an image [COLOR_GLYPH_SIZE] texels tall.
+///
+/// Bitmap glyphs are scaled from their largest strike. COLR glyphs are
+/// rasterized over the face's ascender-to-descender height and the glyph's
+/// advance. Returns `None` if the glyph isn't a color glyph or its bitmap
+/// can't be decoded.
+pub fn rasterize_color_glyph(face: &Face, glyph: u16) -> Option<RgbaImage> {
+    let id = GlyphId(glyph);
+    let size = COLOR_GLYPH_SIZE;
+
+    if face.is_color_glyph(id) {
+        let height = face.ascender() as f32 - face.descender() as f32;
+        let advance = face.glyph_hor_advance(id)? as f32;
+        let scale = size as f32 / height;
+        let width = ((advance * scale).ceil() as u32).clamp(1, COLOR_ATLAS_SIZE);
+
+        let mut painter = LayerPainter {
+            face,
+            scale,
+            ascender: face.ascender() as f32,
+            size: UVec2::new(width, size),
+            edges: Vec::new(),
+            canvas: vec![[0.0; 4]; (width * size) as usize],
+        };
+
+        face.paint_color_glyph(id, 0, &mut painter)?;
+        return Some(painter.into_image());
+    }
+
+    let raster = face.glyph_raster_image(id, u16::MAX)?;
+    let image = match raster.format {
+        RasterImageFormat::PNG => {
+            image::load_from_memory_with_format(raster.data, image::ImageFormat::Png)
+                .map_err(|err| debug!("Failed to decode color glyph {}: {}", glyph, err))
+                .ok()?
+                .into_rgba8()
+        }
+        RasterImageFormat::BitmapPremulBgra32 => {
+            let (width, height) = (raster.width as u32, raster.height as u32);
+            let pixels = raster.data.chunks_exact(4).flat_map(|bgra| {
+                let [b, g, r, a] = [bgra[0], bgra[1], bgra[2], bgra[3]];
+                let unpremultiply = |c: u8| match a {
+                    0 => 0,
+                    a => (c as u32 * 255 / a as u32).min(255) as u8,
+                };
+
+                [unpremultiply(r), unpremultiply(g), unpremultiply(b), a]
+            });
+
+            RgbaImage::from_vec(width, height, pixels.collect())?
+        }
+        _ => return None,
+    };
+
+    if image.height() == 0 {
+        return None;
+    }
+
+    let aspect = image.width() as f32 / image.height() as f32;
+    let width = ((size as f32 * aspect).round() as u32).clamp(1, COLOR_ATLAS_SIZE);
+    Some(image::imageops::resize(
+        &image,
+        width,
+        size,
+        FilterType::Triangle,
+    ))
+}
+
+/// Paints the layers of a COLR glyph onto a premultiplied canvas.
+struct LayerPainter<'a, 'b> {
+    face: &'a Face<'b>,
+    scale: f32,
+    ascender: f32,
+    size: UVec2,
+
+    /// The flattened outline of the current layer, in texels.
+    edges: Vec<(Vec2, Vec2)>,
+
+    canvas: Vec<[f32; 4]>,
+}
+
+impl LayerPainter<'_, '_> {
+    /// Composites the current layer over the canvas in a straight sRGB color.
+    fn fill(&mut self, color: [f32; 4]) {
+        let coverage = coverage(&self.edges, self.size);
+
+        for (dst, coverage) in self.canvas.iter_mut().zip(coverage) {
+            let alpha = color[3] * coverage;
+            for (channel, src) in dst[..3].iter_mut().zip(color) {
+                *channel = src * alpha + *channel * (1.0 - alpha);
+            }
+
+            dst[3] = alpha + dst[3] * (1.0 - alpha);
+        }
+    }
+
+    fn into_image(self) -> RgbaImage {
+        let pixels = self.canvas.iter().flat_map(|[r, g, b, a]| {
+            let unpremultiply = |c: f32| match *a {
+                a if a > 0.0 => (c / a * 255.0).round().clamp(0.0, 255.0) as u8,
+                _ => 0,
+            };
+
+            let alpha = (a * 255.0).round().clamp(0.0, 255.0) as u8;
+            [
+                unpremultiply(*r),
+                unpremultiply(*g),
+                unpremultiply(*b),
+                alpha,
+            ]
+        });
+
+        RgbaImage::from_vec(self.size.x, self.size.y, pixels.collect()).unwrap()
+    }
+}
+
+impl Painter for LayerPainter<'_, '_> {
+    fn outline(&mut self, glyph_id: GlyphId) {
+        let mut flattener = Flattener {
+            scale: self.scale,
+            ascender: self.ascender,
+            start: Vec2::ZERO,
+            pen: Vec2::ZERO,
+            edges: std::mem::take(&mut self.edges),
+        };
+
+        flattener.edges.clear();
+        self.face.outline_glyph(glyph_id, &mut flattener);
+        flattener.close();
+        self.edges = flattener.edges;
+    }
+
+    fn paint_foreground(&mut self) {
+        self.fill(COLR_FOREGROUND);
+    }
+
+    fn paint_color(&mut self, color: RgbaColor) {
+        let channel = |c: u8| c as f32 / 255.0;
+        self.fill([
+            channel(color.red),
+            channel(color.green),
+            channel(color.blue),
+            channel(color.alpha),
+        ]);
+    }
+}
+
+/// Flattens a glyph outline in font units into line segments in texels,
+/// with Y pointing down from the ascender.
+struct Flattener {
+    scale: f32,
+    ascender: f32,
+    start: Vec2,
+    pen: Vec2,
+    edges: Vec<(Vec2, Vec2)>,
+}
+
+impl Flattener {
+    /// How many line segments each curve is split into.
+    const CURVE_SEGMENTS: usize = 8;
+
+    fn point(&self, x: f32, y: f32) -> Vec2 {
+        vec2(x, self.ascender - y) * self.scale
+    }
+
+    fn segment_to(&mut self, to: Vec2) {
+        if to != self.pen {
+            self.edges.push((self.pen, to));
+        }
+
+        self.pen = to;
+    }
+
+    fn flatten(&mut self, eval: impl Fn(f32) -> Vec2) {
+        for step in 1..=Self::CURVE_SEGMENTS {
+            let t = step as f32 / Self::CURVE_SEGMENTS as f32;
+            self.segment_to(eval(t));
+        }
+    }
+}
+
+impl OutlineBuilder for Flattener {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.close();
+        self.start = self.point(x, y);
+        self.pen = self.start;
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        let to = self.point(x, y);
+        self.segment_to(to);
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        let (p0, p1, p2) = (self.pen, self.point(x1, y1), self.point(x, y));
+        self.flatten(|t| p0.lerp(p1, t).lerp(p1.lerp(p2, t), t));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        let (p0, p1) = (self.pen, self.point(x1, y1));
+        let (p2, p3) = (self.point(x2, y2), self.point(x, y));
+        self.flatten(|t| {
+            let a = p0.lerp(p1, t).lerp(p1.lerp(p2, t), t);
+            let b = p1.lerp(p2, t).lerp(p2.lerp(p3, t), t);
+            a.lerp(b, t)
+        });
+    }
+
+    fn close(&mut self) {
+        let start = self.start;
+        self.segment_to(start);
+    }
+}
+
+/// Computes the coverage of each texel by a closed path of edges using the
+/// non-zero winding rule, supersampled on a 4x4 grid.
+fn coverage(edges: &[(Vec2, Vec2)], size: UVec2) -> Vec<f32> {
+    const SAMPLES: u32 = 4;
+    let weight = 1.0 / (SAMPLES * SAMPLES) as f32;
+    let row_samples = size.x * SAMPLES;
+
+    let mut coverage = vec![0.0; (size.x * size.y) as usize];
+    let mut crossings = Vec::new();
+
+    for sample_y in 0..size.y * SAMPLES {
+        let y = (sample_y as f32 + 0.5) / SAMPLES as f32;
+
+        crossings.clear();
+        for (a, b) in edges.iter() {
+            if (a.y <= y) != (b.y <= y) {
+                let x = a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x);
+                let direction = if b.y > a.y { 1 } else { -1 };
+                crossings.push((x, direction));
+            }
+        }
+
+        crossings.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let row = (sample_y / SAMPLES * size.x) as usize;
+        let mut winding = 0;
+        for pair in crossings.windows(2) {
+            winding += pair[0].1;
+            if winding == 0 {
+                continue;
+            }
+
+            // the first and last sample columns whose centers are in the span
+            let first = (pair[0].0 * SAMPLES as f32 - 0.5).ceil().max(0.0) as u32;
+            let last = ((pair[1].0 * SAMPLES as f32 - 0.5).ceil().max(0.0) as u32).min(row_samples);
+
+            for sample_x in first..last {
+                coverage[row + (sample_x / SAMPLES) as usize] += weight;
+            }
+        }
+    }
+
+    coverage
+}
+
+/// Finds room for a glyph of a given width on the shelves of a
+/// [ColorAtlas], advancing the packing cursor past it.
+///
+/// Glyphs are packed left to right into rows [COLOR_GLYPH_SIZE] texels tall.
+/// Returns `None` once the atlas is full.
+fn allocate(cursor: &mut UVec2, width: u32) -> Option<UVec2> {
+    if cursor.x + width > COLOR_ATLAS_SIZE {
+        *cursor = UVec2::new(0, cursor.y + COLOR_GLYPH_SIZE + COLOR_GLYPH_PADDING);
+    }
+
+    if width > COLOR_ATLAS_SIZE || cursor.y + COLOR_GLYPH_SIZE > COLOR_ATLAS_SIZE {
+        return None;
+    }
+
+    let position = *cursor;
+    cursor.x += width + COLOR_GLYPH_PADDING;
+    Some(position)
+}
+
+/// The location of a glyph in a [ColorAtlas].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ColorGlyph {
+    /// The texture coordinates of the glyph's top-left corner.
+    pub tex_min: Vec2,
+
+    /// The texture coordinates of the glyph's bottom-right corner.
+    pub tex_max: Vec2,
+
+    /// The glyph's width divided by its height.
+    pub aspect: f32,
+}
+
+struct ColorAtlasInner {
+    /// Every glyph that's been requested, or `None` if it couldn't be added.
+    glyphs: HashMap<u16, Option<ColorGlyph>>,
+
+    /// Where the next glyph is packed.
+    cursor: UVec2,
+
+    /// Set once a glyph didn't fit, so that's only logged once.
+    full: bool,
+}
+
+/// An RGBA atlas of color glyphs from a single face.
+///
+/// Glyphs are rasterized and uploaded the first time that they're
+/// requested.
+pub struct ColorAtlas {
+    pub texture: Texture,
+    queue: Arc<Queue>,
+    inner: Mutex<ColorAtlasInner>,
+}
+
+impl ColorAtlas {
+    /// Creates an empty atlas.
+    pub fn new(device: &Device, queue: Arc<Queue>) -> Self {
+        let size = Extent3d {
+            width: COLOR_ATLAS_SIZE,
+            height: COLOR_ATLAS_SIZE,
+            depth_or_array_layers: 1,
+        };
+
+        // color glyphs are sampled as they are, so they're stored in sRGB to
+        // be filtered and blended in linear space
+        let texture = device.create_texture_with_data(
+            &queue,
+            &TextureDescriptor {
+                label: Some("AlacrittyRoutine::color_glyph_texture"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8UnormSrgb,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            },
+            &vec![0u8; (COLOR_ATLAS_SIZE * COLOR_ATLAS_SIZE * 4) as usize],
+        );
+
+        Self {
+            texture,
+            queue,
+            inner: Mutex::new(ColorAtlasInner {
+                glyphs: HashMap::new(),
+                cursor: UVec2::ZERO,
+                full: false,
+            }),
+        }
+    }
+
+    /// Looks up a glyph from `face` in this atlas, rasterizing and uploading
+    /// it the first time.
+    ///
+    /// Returns `None` if the glyph can't be rasterized or doesn't fit.
+    pub fn get(&self, face: &Face, glyph: u16) -> Option<ColorGlyph> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(cached) = inner.glyphs.get(&glyph) {
+            return *cached;
+        }
+
+        let added = rasterize_color_glyph(face, glyph).and_then(|image| {
+            let Some(position) = allocate(&mut inner.cursor, image.width()) else {
+                if !inner.full {
+                    warn!("Color glyph atlas is full");
+                    inner.full = true;
+                }
+
+                return None;
+            };
+
+            self.upload(position, &image);
+
+            let atlas_size = Vec2::splat(COLOR_ATLAS_SIZE as f32);
+            let extent = UVec2::new(image.width(), image.height());
+
+            Some(ColorGlyph {
+                tex_min: position.as_vec2() / atlas_size,
+                tex_max: (position + extent).as_vec2() / atlas_size,
+                aspect: image.width() as f32 / image.height() as f32,
+            })
+        });
+
+        inner.glyphs.insert(glyph, added);
+        added
+    }
+
+    /// Tests if this atlas's texture is on the given queue's device.
+    pub fn is_on(&self, queue: &Arc<Queue>) -> bool {
+        Arc::ptr_eq(&self.queue, queue)
+    }
+
+    fn upload(&self, position: UVec2, image: &RgbaImage) {
+        self.queue.write_texture(
+            ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: position.x,
+                    y: position.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            image.as_raw(),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(image.width() * 4),
+                rows_per_image: std::num::NonZeroU32::new(image.height()),
+            },
+            Extent3d {
+                width: image.width(),
+                height: image.height(),
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONONOKI: &[u8] = include_bytes!("../../../resources/mononoki/mononoki-Regular.ttf");
+
+    fn square(min: Vec2, max: Vec2) -> Vec<(Vec2, Vec2)> {
+        let (tr, bl) = (vec2(max.x, min.y), vec2(min.x, max.y));
+        vec![(min, tr), (tr, max), (max, bl), (bl, min)]
+    }
+
+    #[test]
+    fn outline_glyphs_are_not_color() {
+        let face = Face::parse(MONONOKI, 0).unwrap();
+        let glyph = face.glyph_index('A').unwrap().0;
+        assert!(!is_color_glyph(&face, glyph));
+        assert_eq!(rasterize_color_glyph(&face, glyph), None);
+    }
+
+    #[test]
+    fn coverage_fills_paths() {
+        let edges = square(vec2(1.0, 1.0), vec2(3.0, 2.5));
+        let coverage = coverage(&edges, UVec2::new(4, 4));
+
+        assert_eq!(coverage[0], 0.0);
+        assert_eq!(coverage[4 + 1], 1.0);
+        assert_eq!(coverage[4 + 2], 1.0);
+        assert_eq!(coverage[4 + 3], 0.0);
+        assert_eq!(coverage[2 * 4 + 1], 0.5);
+        assert_eq!(coverage[3 * 4 + 1], 0.0);
+    }
+
+    #[test]
+    fn coverage_uses_nonzero_winding() {
+        // a square inside another square with the same winding stays filled
+        let mut edges = square(Vec2::ZERO, Vec2::splat(4.0));
+        edges.extend(square(Vec2::ONE, Vec2::splat(3.0)));
+        let coverage = coverage(&edges, UVec2::new(4, 4));
+        assert_eq!(coverage[4 + 1], 1.0);
+    }
+
+    #[test]
+    fn allocation_wraps_shelves() {
+        let mut cursor = UVec2::ZERO;
+        let width = COLOR_ATLAS_SIZE / 2;
+
+        assert_eq!(allocate(&mut cursor, width), Some(UVec2::ZERO));
+
+        let row = COLOR_GLYPH_SIZE + COLOR_GLYPH_PADDING;
+        assert_eq!(allocate(&mut cursor, width), Some(UVec2::new(0, row)));
+        assert_eq!(allocate(&mut cursor, COLOR_ATLAS_SIZE + 1), None);
+    }
+
+    #[test]
+    fn allocation_fails_when_full() {
+        let mut cursor = UVec2::ZERO;
+        let rows = COLOR_ATLAS_SIZE / (COLOR_GLYPH_SIZE + COLOR_GLYPH_PADDING);
+
+        for _ in 0..rows {
+            assert!(allocate(&mut cursor, COLOR_ATLAS_SIZE).is_some());
+        }
+
+        assert_eq!(allocate(&mut cursor, COLOR_ATLAS_SIZE), None);
+    }
+}
@@ -16,25 +16,194 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use bytemuck::{Pod, Zeroable};
-use glam::Mat4;
+use glam::{IVec2, Mat4, UVec2, Vec2, Vec3, Vec4};
 use hearth_rend3::{
-    rend3::graph::{
-        DepthHandle, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
-        RenderTargetHandle,
+    rend3::{
+        graph::{
+            DepthHandle, RenderGraph, RenderPassDepthTarget, RenderPassTarget, RenderPassTargets,
+            RenderTargetDescriptor, RenderTargetHandle,
+        },
+        types::SampleCount,
     },
-    utils::DynamicMesh,
+    utils::GpuVector,
     wgpu::*,
 };
+use hearth_runtime::tokio::sync::mpsc::UnboundedSender;
+use hearth_schema::terminal::{TerminalDepthMode, TerminalOutline, TerminalRenderStats};
 
-use crate::text::{FaceAtlas, FontSet};
+use crate::{color::ColorAtlas, text::FaceAtlas};
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, Pod, Zeroable)]
 pub struct CameraUniform {
     pub mvp: glam::Mat4,
+
+    /// The sRGB color of the glyph outline.
+    pub outline_color: Vec4,
+
+    /// The on-screen size of the glyph atlas's distance range, in pixels.
+    pub px_range: f32,
+
+    /// The thickness of the glyph outline in normalized distance units, or
+    /// zero for no outline.
+    pub outline_width: f32,
+
+    pub _padding: [f32; 2],
+}
+
+impl CameraUniform {
+    /// Creates the uniform for drawing a terminal with the given
+    /// model-view-projection matrix at an output resolution.
+    ///
+    /// `em_range` is the distance range of the terminal's glyph atlases in
+    /// ems. The range is measured at the terminal's origin, so it's shared by
+    /// every glyph in the draw.
+    pub fn new(
+        mvp: Mat4,
+        units_per_em: f32,
+        em_range: f32,
+        resolution: UVec2,
+        outline: Option<TerminalOutline>,
+    ) -> Self {
+        let px_per_em = screen_px_per_em(mvp, units_per_em, resolution.as_vec2()).unwrap_or(0.0);
+        let px_range = (px_per_em * em_range).max(1.0);
+
+        let (outline_color, outline_width) = match outline {
+            Some(outline) => {
+                let (a, r, g, b) = outline.color.to_argb();
+                let color = Vec4::new(r as f32, g as f32, b as f32, a as f32) / 255.0;
+                let width = (outline.width / em_range).clamp(0.0, 0.5);
+                (color, width)
+            }
+            None => (Vec4::ZERO, 0.0),
+        };
+
+        Self {
+            mvp,
+            outline_color,
+            px_range,
+            outline_width,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// Chooses the clear values of the terminal pass's color and depth targets
+/// for [TerminalPipelines::add_to_graph].
+///
+/// rend3 only clears a color target with its pass's value if the pass is the
+/// first to use it in the frame, and loads it otherwise. When compositing,
+/// depth is always loaded and the color falls back to transparent. When
+/// clearing, the pass's values match the requested clear, so it applies
+/// whether rend3 clears the targets or [TerminalPipelines::draw_clear] does.
+fn pass_clears(clear: Option<Color>) -> (Color, Option<f32>) {
+    match clear {
+        // reversed depth puts the far plane at zero
+        Some(color) => (color, Some(0.0)),
+        None => (Color::TRANSPARENT, None),
+    }
+}
+
+/// Converts an sRGB color to the linear color used by [TerminalPipelines::draw_clear].
+pub fn linear_clear_color(color: hearth_schema::Color) -> Color {
+    let (a, r, g, b) = color.to_argb();
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c > 0.04045 {
+            ((c + 0.055) / 1.055).powf(2.4)
+        } else {
+            c / 12.92
+        }
+    };
+
+    Color {
+        r: linear(r),
+        g: linear(g),
+        b: linear(b),
+        a: a as f64 / 255.0,
+    }
+}
+
+/// Computes the on-screen size of one em, in pixels, for text at the origin
+/// of a model-view-projection matrix's model space.
+///
+/// The horizontal and vertical sizes are averaged, so text viewed at an
+/// angle is treated as smaller. Returns `None` if the origin is behind the
+/// camera.
+pub fn screen_px_per_em(mvp: Mat4, units_per_em: f32, resolution: Vec2) -> Option<f32> {
+    let project = |pos: Vec3| {
+        let clip = mvp * pos.extend(1.0);
+        (clip.w > 0.0).then(|| Vec2::new(clip.x, clip.y) / clip.w * resolution * 0.5)
+    };
+
+    let origin = project(Vec3::ZERO)?;
+    let x = project(Vec3::X * units_per_em).map_or(0.0, |x| x.distance(origin));
+    let y = project(Vec3::Y * units_per_em).map_or(0.0, |y| y.distance(origin));
+    Some((x + y) / 2.0)
+}
+
+/// Projects a world-space position to a position on screen, in pixels from
+/// the top-left corner of the output. Returns `None` if the position is
+/// behind the camera.
+pub fn screen_position(vp: Mat4, position: Vec3, resolution: UVec2) -> Option<Vec2> {
+    let clip = vp * position.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+
+    let ndc = Vec2::new(clip.x, clip.y) / clip.w;
+    let uv = Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) * 0.5;
+    Some(uv * resolution.as_vec2())
+}
+
+/// Sends the on-screen position of the focused terminal's cursor whenever
+/// it moves, so that IME candidate windows can follow it.
+pub struct ImeReporter {
+    tx: UnboundedSender<Vec2>,
+    last: Mutex<Option<IVec2>>,
+}
+
+impl ImeReporter {
+    pub fn new(tx: UnboundedSender<Vec2>) -> Self {
+        Self {
+            tx,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Reports a position if it has moved by at least a pixel.
+    pub fn report(&self, position: Vec2) {
+        let rounded = position.round().as_ivec2();
+        let mut last = self.last.lock().unwrap();
+        if *last != Some(rounded) {
+            *last = Some(rounded);
+            let _ = self.tx.send(position);
+        }
+    }
+}
+
+/// Computes the view-space depth of a model's origin, which increases with
+/// distance in front of the camera.
+pub fn view_depth(vp: Mat4, model: Mat4) -> f32 {
+    // the clip-space w of the origin is its distance along the view axis
+    (vp * model).w_axis.w
+}
+
+/// Computes a view-projection matrix that fits a terminal, given by its
+/// model matrix and half-size, to the whole of an offscreen target.
+pub fn target_view_proj(model: Mat4, half_size: Vec2) -> Mat4 {
+    // near and far are swapped for rend3's reversed depth
+    let (x, y) = (half_size.x, half_size.y);
+    Mat4::orthographic_rh(-x, x, -y, y, 1.0, -1.0) * model.inverse()
 }
 
 #[repr(C)]
@@ -99,26 +268,39 @@ impl GlyphVertex {
 pub struct TerminalPipelines {
     device: Arc<Device>,
     queue: Arc<Queue>,
+    shader: ShaderModule,
+    solid_layout: PipelineLayout,
+    glyph_layout: PipelineLayout,
+    format: TextureFormat,
     camera_bgl: BindGroupLayout,
     glyph_bgl: BindGroupLayout,
     solid_pipeline: RenderPipeline,
+    opaque_bg_pipeline: RenderPipeline,
     glyph_pipeline: RenderPipeline,
+    color_glyph_pipeline: RenderPipeline,
+    clear_layout: PipelineLayout,
+    clear_pipeline: RenderPipeline,
     atlas_sampler: Sampler,
 }
 
 impl TerminalPipelines {
     /// Initialize a device and queue's GPU state targeting the given output
-    /// surface format.
-    pub fn new(device: Arc<Device>, queue: Arc<Queue>, format: TextureFormat) -> Self {
+    /// surface format and sample count.
+    pub fn new(
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        format: TextureFormat,
+        sample_count: SampleCount,
+    ) -> Self {
         let shader = device.create_shader_module(&include_wgsl!("shaders.wgsl"));
 
         let camera_bgl = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Alacritty camera bind group layout"),
             entries: &[BindGroupLayoutEntry {
                 binding: 0,
-                visibility: ShaderStages::VERTEX,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
                 ty: BindingType::Buffer {
-                    ty: BufferBindingType::Uniform,
+                    ty: BufferBindingType::Storage { read_only: true },
                     has_dynamic_offset: false,
                     min_binding_size: None,
                 },
@@ -148,27 +330,199 @@ impl TerminalPipelines {
             ],
         });
 
-        let layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("AlacrittyRoutine pipeline layout"),
+        // solid geometry doesn't sample an atlas, so it doesn't need a glyph
+        // bind group to be bound
+        let solid_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("AlacrittyRoutine solid pipeline layout"),
+            bind_group_layouts: &[&camera_bgl],
+            push_constant_ranges: &[],
+        });
+
+        let glyph_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("AlacrittyRoutine glyph pipeline layout"),
             bind_group_layouts: &[&camera_bgl, &glyph_bgl],
             push_constant_ranges: &[],
         });
 
-        let make_pipeline = |label, vs, fs, vert_layout| {
+        let [solid_pipeline, opaque_bg_pipeline, glyph_pipeline, color_glyph_pipeline] =
+            Self::make_pipelines(
+                &device,
+                &shader,
+                &solid_layout,
+                &glyph_layout,
+                format,
+                sample_count,
+            );
+
+        let clear_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("AlacrittyRoutine clear pipeline layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+
+        let clear_pipeline =
+            Self::make_clear_pipeline(&device, &shader, &clear_layout, format, sample_count);
+
+        let atlas_sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        Self {
+            device,
+            queue,
+            shader,
+            solid_layout,
+            glyph_layout,
+            format,
+            camera_bgl,
+            glyph_bgl,
+            solid_pipeline,
+            opaque_bg_pipeline,
+            glyph_pipeline,
+            color_glyph_pipeline,
+            clear_layout,
+            clear_pipeline,
+            atlas_sampler,
+        }
+    }
+
+    /// Creates a bind group for sampling a view of a glyph atlas's texture.
+    pub fn create_glyph_bind_group(&self, atlas_view: &TextureView) -> BindGroup {
+        self.device.create_bind_group(&BindGroupDescriptor {
+            label: None,
+            layout: &self.glyph_bgl,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(atlas_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(&self.atlas_sampler),
+                },
+            ],
+        })
+    }
+
+    /// Rebuilds the pipelines to target a new sample count.
+    pub fn set_sample_count(&mut self, sample_count: SampleCount) {
+        let [solid_pipeline, opaque_bg_pipeline, glyph_pipeline, color_glyph_pipeline] =
+            Self::make_pipelines(
+                &self.device,
+                &self.shader,
+                &self.solid_layout,
+                &self.glyph_layout,
+                self.format,
+                sample_count,
+            );
+
+        self.solid_pipeline = solid_pipeline;
+        self.opaque_bg_pipeline = opaque_bg_pipeline;
+        self.glyph_pipeline = glyph_pipeline;
+        self.color_glyph_pipeline = color_glyph_pipeline;
+
+        self.clear_pipeline = Self::make_clear_pipeline(
+            &self.device,
+            &self.shader,
+            &self.clear_layout,
+            self.format,
+            sample_count,
+        );
+    }
+
+    /// Creates the pipeline used by [Self::draw_clear].
+    ///
+    /// The clear color is given by the blend constant, so the pipeline
+    /// doesn't need any bindings.
+    fn make_clear_pipeline(
+        device: &Device,
+        shader: &ShaderModule,
+        layout: &PipelineLayout,
+        format: TextureFormat,
+        sample_count: SampleCount,
+    ) -> RenderPipeline {
+        let constant = BlendComponent {
+            src_factor: BlendFactor::Constant,
+            dst_factor: BlendFactor::Zero,
+            operation: BlendOperation::Add,
+        };
+
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("AlacrittyRoutine clear pipeline"),
+            layout: Some(layout),
+            vertex: VertexState {
+                module: shader,
+                entry_point: "clear_vs",
+                buffers: &[],
+            },
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            primitive: PrimitiveState::default(),
+            multisample: MultisampleState {
+                count: sample_count as u32,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: shader,
+                entry_point: "clear_fs",
+                targets: &[ColorTargetState {
+                    format,
+                    blend: Some(BlendState {
+                        color: constant,
+                        alpha: constant,
+                    }),
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+            multiview: None,
+        })
+    }
+
+    /// Creates the solid, opaque background, glyph, and color glyph
+    /// pipelines.
+    fn make_pipelines(
+        device: &Device,
+        shader: &ShaderModule,
+        solid_layout: &PipelineLayout,
+        glyph_layout: &PipelineLayout,
+        format: TextureFormat,
+        sample_count: SampleCount,
+    ) -> [RenderPipeline; 4] {
+        // glyphs and overlays are coplanar with the background that they're
+        // drawn on, so they're nudged towards the camera to always pass the
+        // depth test against an opaque background
+        let coplanar_bias = DepthBiasState {
+            constant: 2,
+            slope_scale: 1.0,
+            clamp: 0.0,
+        };
+
+        let make_pipeline = |label, layout, vs, fs, vert_layout, depth_write, bias| {
             device.create_render_pipeline(&RenderPipelineDescriptor {
                 label: Some(label),
-                layout: Some(&layout),
+                layout: Some(layout),
                 vertex: VertexState {
-                    module: &shader,
+                    module: shader,
                     entry_point: vs,
                     buffers: &[vert_layout],
                 },
                 depth_stencil: Some(DepthStencilState {
                     format: TextureFormat::Depth32Float,
-                    depth_write_enabled: false,
+                    depth_write_enabled: depth_write,
                     depth_compare: CompareFunction::GreaterEqual,
                     stencil: StencilState::default(),
-                    bias: DepthBiasState::default(),
+                    bias,
                 }),
                 primitive: PrimitiveState {
                     topology: PrimitiveTopology::TriangleList,
@@ -179,9 +533,12 @@ impl TerminalPipelines {
                     polygon_mode: PolygonMode::Fill,
                     conservative: false,
                 },
-                multisample: MultisampleState::default(),
+                multisample: MultisampleState {
+                    count: sample_count as u32,
+                    ..Default::default()
+                },
                 fragment: Some(FragmentState {
-                    module: &shader,
+                    module: shader,
                     entry_point: fs,
                     targets: &[ColorTargetState {
                         format,
@@ -195,206 +552,909 @@ impl TerminalPipelines {
 
         let solid_pipeline = make_pipeline(
             "AlacrittyRoutine solid pipeline",
+            solid_layout,
+            "solid_vs",
+            "solid_fs",
+            SolidVertex::LAYOUT,
+            false,
+            coplanar_bias,
+        );
+
+        let opaque_bg_pipeline = make_pipeline(
+            "AlacrittyRoutine opaque background pipeline",
+            solid_layout,
             "solid_vs",
             "solid_fs",
             SolidVertex::LAYOUT,
+            true,
+            DepthBiasState::default(),
         );
 
         let glyph_pipeline = make_pipeline(
             "AlacrittyRoutine glyph pipeline",
+            glyph_layout,
             "glyph_vs",
             "glyph_fs",
             GlyphVertex::LAYOUT,
+            false,
+            coplanar_bias,
         );
 
-        let atlas_sampler = device.create_sampler(&SamplerDescriptor {
-            address_mode_u: AddressMode::ClampToEdge,
-            address_mode_v: AddressMode::ClampToEdge,
-            address_mode_w: AddressMode::ClampToEdge,
-            mag_filter: FilterMode::Linear,
-            min_filter: FilterMode::Linear,
-            mipmap_filter: FilterMode::Nearest,
-            ..Default::default()
-        });
+        // color glyphs sample their colors from the atlas instead of
+        // testing a distance field, so they don't support outlines
+        let color_glyph_pipeline = make_pipeline(
+            "AlacrittyRoutine color glyph pipeline",
+            glyph_layout,
+            "glyph_vs",
+            "color_glyph_fs",
+            GlyphVertex::LAYOUT,
+            false,
+            coplanar_bias,
+        );
 
-        Self {
-            device,
-            queue,
-            camera_bgl,
-            glyph_bgl,
+        [
             solid_pipeline,
+            opaque_bg_pipeline,
             glyph_pipeline,
-            atlas_sampler,
-        }
+            color_glyph_pipeline,
+        ]
     }
 
-    /// Adds a set of pipelines and associated set of [TerminalDrawState] to a
-    /// rend3 render graph.
+    /// Adds a set of pipelines and a [TerminalBatch] to a rend3 render graph.
+    ///
+    /// If `output` is multisampled, `resolve` is the single-sampled target to
+    /// resolve it to.
+    ///
+    /// Terminals are composited over the existing contents of `output` and
+    /// `depth`. If `clear` is given, both are cleared first, the color to
+    /// `clear` and the depth to the far plane, so that the terminals are
+    /// drawn in isolation.
+    #[allow(clippy::too_many_arguments)]
     pub fn add_to_graph<'a>(
         &'a self,
-        draws: &'a [&'a TerminalDrawState],
+        batch: &'a TerminalBatch,
         graph: &mut RenderGraph<'a>,
         output: RenderTargetHandle,
+        resolve: Option<RenderTargetHandle>,
         depth: RenderTargetHandle,
+        resolution: UVec2,
+        clear: Option<Color>,
+        ime: Option<&'a ImeReporter>,
     ) {
         let mut builder = graph.add_node("terminal");
         let output_handle = builder.add_render_target_output(output);
+        let resolve_handle = resolve.map(|resolve| builder.add_render_target_output(resolve));
         let depth_handle = builder.add_render_target_output(depth);
+
+        let (color_clear, depth_clear) = pass_clears(clear);
         let rpass_handle = builder.add_renderpass(RenderPassTargets {
             targets: vec![RenderPassTarget {
                 color: output_handle,
-                clear: Color::BLACK,
-                resolve: None,
+                clear: color_clear,
+                resolve: resolve_handle,
             }],
             depth_stencil: Some(RenderPassDepthTarget {
                 target: DepthHandle::RenderTarget(depth_handle),
-                depth_clear: Some(0.0),
+                depth_clear,
                 stencil_clear: None,
             }),
         });
 
         let pipelines = builder.passthrough_ref(self);
-
-        let draws: Vec<_> = draws
-            .iter()
-            .map(|draw| builder.passthrough_ref(*draw))
-            .collect();
+        let batch = builder.passthrough_ref(batch);
 
         builder.build(
             move |pt, _renderer, encoder_or_pass, _temps, _ready, graph_data| {
                 let pipelines = pt.get(pipelines);
+                let batch = pt.get(batch);
                 let rpass = encoder_or_pass.get_rpass(rpass_handle);
                 let vp = graph_data.camera_manager.view_proj();
 
-                for draw in draws {
-                    pipelines.draw_terminal(pt.get(draw), rpass, vp);
+                if let Some(clear) = clear {
+                    pipelines.draw_clear(rpass, clear);
                 }
+
+                pipelines.draw_batch(batch, rpass, vp, resolution);
+
+                if let (Some(ime), Some(position)) = (ime, batch.ime_position(vp, resolution)) {
+                    ime.report(position);
+                }
+            },
+        );
+    }
+
+    /// Adds a set of pipelines and a [TerminalBatch] to a rend3 render graph,
+    /// drawing into a single-sampled offscreen target, such as a shared
+    /// render target, with a fixed view-projection matrix.
+    ///
+    /// The target is cleared to transparent black and given its own depth
+    /// buffer, so the pipelines must have been created with the target's
+    /// format and a sample count of one.
+    pub fn add_to_target<'a>(
+        &'a self,
+        batch: &'a TerminalBatch,
+        graph: &mut RenderGraph<'a>,
+        output: RenderTargetHandle,
+        resolution: UVec2,
+        vp: Mat4,
+    ) {
+        let depth = graph.add_render_target(RenderTargetDescriptor {
+            label: Some("terminal target depth".into()),
+            resolution,
+            samples: SampleCount::One,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        });
+
+        let mut builder = graph.add_node("terminal target");
+        let output_handle = builder.add_render_target_output(output);
+        let depth_handle = builder.add_render_target_output(depth);
+
+        // both targets are new this frame, so these clears always apply
+        let rpass_handle = builder.add_renderpass(RenderPassTargets {
+            targets: vec![RenderPassTarget {
+                color: output_handle,
+                clear: Color::TRANSPARENT,
+                resolve: None,
+            }],
+            depth_stencil: Some(RenderPassDepthTarget {
+                target: DepthHandle::RenderTarget(depth_handle),
+                depth_clear: Some(0.0),
+                stencil_clear: None,
+            }),
+        });
+
+        let pipelines = builder.passthrough_ref(self);
+        let batch = builder.passthrough_ref(batch);
+
+        builder.build(
+            move |pt, _renderer, encoder_or_pass, _temps, _ready, _graph_data| {
+                let pipelines = pt.get(pipelines);
+                let batch = pt.get(batch);
+                let rpass = encoder_or_pass.get_rpass(rpass_handle);
+                pipelines.draw_batch(batch, rpass, vp, resolution);
             },
         );
     }
 
-    /// Renders a single [TerminalDrawState].
-    pub fn draw_terminal<'a>(
+    /// Overwrites the whole color target with a color and resets its depth
+    /// to the far plane.
+    ///
+    /// Render pass clears can't be forced on targets that earlier passes
+    /// have drawn to, so this draws a fullscreen triangle instead.
+    pub fn draw_clear<'a>(&'a self, rpass: &mut RenderPass<'a>, color: Color) {
+        rpass.set_pipeline(&self.clear_pipeline);
+        rpass.set_blend_constant(color);
+        rpass.draw(0..3, 0..1);
+    }
+
+    /// Renders every terminal in a [TerminalBatch].
+    ///
+    /// Opaque terminals are drawn first. Their backgrounds write depth, so
+    /// each layer of geometry is drawn for all of them at once without
+    /// regard to order. Transparent terminals are then drawn one at a time
+    /// from back to front so that each blends over the ones behind it.
+    pub fn draw_batch<'a>(
         &'a self,
-        terminal: &'a TerminalDrawState,
+        batch: &'a TerminalBatch,
         rpass: &mut RenderPass<'a>,
         vp: Mat4,
+        resolution: UVec2,
     ) {
-        let model = terminal.model;
+        let Some(camera_bind_group) = batch.camera_bind_group.as_ref() else {
+            batch.stats.record(0, 0);
+            return;
+        };
+
+        let cameras: Vec<_> = batch
+            .terminals
+            .iter()
+            .map(|terminal| {
+                CameraUniform::new(
+                    vp * terminal.model,
+                    terminal.units_per_em,
+                    terminal.em_range,
+                    resolution,
+                    terminal.outline,
+                )
+            })
+            .collect();
 
         self.queue.write_buffer(
-            &terminal.camera_buffer,
+            batch.cameras.get_buffer(),
             0,
-            bytemuck::cast_slice(&[CameraUniform { mvp: vp * model }]),
+            bytemuck::cast_slice(&cameras),
         );
 
-        // set the camera bind group for all draw calls
-        rpass.set_bind_group(0, &terminal.camera_bind_group, &[]);
+        let terminals = batch
+            .terminals
+            .iter()
+            .map(|terminal| (terminal.model, terminal.depth_mode));
+        let (opaque, transparent) = draw_order(vp, terminals);
 
-        // set the regular glyph bind group for solid geo drawing
-        rpass.set_bind_group(1, &terminal.glyph_bind_groups.regular, &[]);
+        // every terminal's camera is looked up by instance index
+        rpass.set_bind_group(0, camera_bind_group, &[]);
 
-        // draw solid geo
-        rpass.set_pipeline(&self.solid_pipeline);
-        terminal.bg_mesh.draw(rpass);
+        let mut draw_calls = self.draw_layers(batch, rpass, &opaque, &self.opaque_bg_pipeline);
 
-        // set the glyph pipeline for all glyph draws
-        rpass.set_pipeline(&self.glyph_pipeline);
+        for terminal in transparent {
+            draw_calls += self.draw_layers(batch, rpass, &[terminal], &self.solid_pipeline);
+        }
+
+        batch.stats.record(batch.terminals.len() as u32, draw_calls);
+    }
+
+    /// Draws every layer of a set of terminals in a batch, in the given
+    /// order. Backgrounds are drawn with `bg_pipeline`. Returns the number of
+    /// draw calls issued.
+    fn draw_layers<'a>(
+        &'a self,
+        batch: &'a TerminalBatch,
+        rpass: &mut RenderPass<'a>,
+        order: &[u32],
+        bg_pipeline: &'a RenderPipeline,
+    ) -> u32 {
+        if order.is_empty() {
+            return 0;
+        }
 
-        // draw regular glyphs
-        // regular glyph bind group is already bound
-        terminal.glyph_meshes.regular.draw(rpass);
+        let mut draw_calls = 0;
 
-        // draw italic glyphs
-        rpass.set_bind_group(1, &terminal.glyph_bind_groups.italic, &[]);
-        terminal.glyph_meshes.italic.draw(rpass);
+        // chrome shares the background's pipeline and goes first so that
+        // shadows stay behind the terminals that cast them
+        rpass.set_pipeline(bg_pipeline);
+        draw_calls += batch.chrome.draw(rpass, order);
+        draw_calls += batch.bg.draw(rpass, order);
 
-        // draw bold glyphs
-        rpass.set_bind_group(1, &terminal.glyph_bind_groups.bold, &[]);
-        terminal.glyph_meshes.bold.draw(rpass);
+        rpass.set_pipeline(&self.glyph_pipeline);
+        for glyphs in batch.glyphs.iter() {
+            draw_calls += glyphs.draw(rpass, order);
+        }
 
-        // draw bold italic glyphs
-        rpass.set_bind_group(1, &terminal.glyph_bind_groups.bold_italic, &[]);
-        terminal.glyph_meshes.bold_italic.draw(rpass);
+        rpass.set_pipeline(&self.color_glyph_pipeline);
+        for glyphs in batch.color_glyphs.iter() {
+            draw_calls += glyphs.draw(rpass, order);
+        }
 
-        // draw overlay geo
         rpass.set_pipeline(&self.solid_pipeline);
-        terminal.overlay_mesh.draw(rpass);
+        draw_calls += batch.overlay.draw(rpass, order);
+
+        draw_calls
     }
 }
 
-/// A ready-to-render terminal state.
+/// Sorts terminals, given by their model matrices and depth modes, into the
+/// order that they're drawn in.
+///
+/// Returns the indices of the opaque terminals in their original order,
+/// followed by the indices of the transparent terminals from back to front.
+pub fn draw_order(
+    vp: Mat4,
+    terminals: impl Iterator<Item = (Mat4, TerminalDepthMode)>,
+) -> (Vec<u32>, Vec<u32>) {
+    let mut opaque = Vec::new();
+    let mut transparent = Vec::new();
+
+    for (index, (model, depth_mode)) in terminals.enumerate() {
+        match depth_mode {
+            TerminalDepthMode::Opaque => opaque.push(index as u32),
+            TerminalDepthMode::Transparent => {
+                transparent.push((index as u32, view_depth(vp, model)))
+            }
+        }
+    }
+
+    // transparent terminals don't write depth, so they only blend correctly
+    // if the ones behind them are drawn first
+    transparent.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    let transparent = transparent.into_iter().map(|(index, _)| index).collect();
+
+    (opaque, transparent)
+}
+
+/// CPU-side geometry for one layer of a terminal.
+#[derive(Clone, Debug)]
+pub struct MeshData<T> {
+    pub vertices: Vec<T>,
+    pub indices: Vec<u32>,
+}
+
+impl<T> Default for MeshData<T> {
+    fn default() -> Self {
+        Self {
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+}
+
+impl<T: Copy> MeshData<T> {
+    /// Tests if this mesh has no indices to draw.
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// Appends another mesh's geometry to this one.
+    pub fn append(&mut self, other: &MeshData<T>) {
+        let base = self.vertices.len() as u32;
+        self.vertices.extend_from_slice(&other.vertices);
+        self.indices
+            .extend(other.indices.iter().map(|index| index + base));
+    }
+}
+
+/// A terminal's ready-to-render geometry and transform.
+///
+/// Terminals only build this on the CPU. Every frame, [TerminalBatch]
+/// uploads all terminals' draw states into shared GPU buffers.
+#[derive(Default)]
 pub struct TerminalDrawState {
-    pub device: Arc<Device>,
-    pub queue: Arc<Queue>,
     pub model: Mat4,
-    pub camera_buffer: Buffer,
-    pub camera_bind_group: BindGroup,
-    pub glyph_bind_groups: FontSet<BindGroup>,
-    pub bg_mesh: DynamicMesh<SolidVertex>,
-    pub glyph_meshes: FontSet<DynamicMesh<GlyphVertex>>,
-    pub overlay_mesh: DynamicMesh<SolidVertex>,
+    pub units_per_em: f32,
+    pub em_range: f32,
+    pub outline: Option<TerminalOutline>,
+    pub depth_mode: TerminalDepthMode,
+    pub bg: MeshData<SolidVertex>,
+
+    /// Glyph geometry, grouped by the atlas that it samples, along with the
+    /// [FaceAtlas::generation] that its texture coordinates were placed in.
+    pub glyphs: Vec<(Arc<FaceAtlas>, u64, MeshData<GlyphVertex>)>,
+
+    /// Color glyph geometry, grouped by the atlas that it samples. Only the
+    /// alpha of these glyphs' vertex colors is used.
+    ///
+    /// Color atlases never move their glyphs, so their generation is always
+    /// zero.
+    pub color_glyphs: Vec<(Arc<ColorAtlas>, u64, MeshData<GlyphVertex>)>,
+
+    pub overlay: MeshData<SolidVertex>,
+
+    /// Title bar, border, and shadow geometry drawn around the terminal.
+    pub chrome: MeshData<SolidVertex>,
+
+    /// The position of the bottom-left corner of the cursor in model space,
+    /// if this terminal has focus. IME candidate windows are placed here.
+    pub ime_anchor: Option<Vec2>,
 }
 
 impl TerminalDrawState {
-    pub fn new(pipelines: &TerminalPipelines, fonts: FontSet<Arc<FaceAtlas>>) -> Self {
+    /// Adds glyphs drawn from an atlas, merging them with any glyphs from
+    /// the same atlas.
+    ///
+    /// Font styles without their own face share the regular face's atlas,
+    /// so this keeps them in the same draw.
+    ///
+    /// `generation` is the [FaceAtlas::generation] returned when the glyphs
+    /// were placed.
+    pub fn add_glyphs(
+        &mut self,
+        atlas: &Arc<FaceAtlas>,
+        generation: u64,
+        mesh: &MeshData<GlyphVertex>,
+    ) {
+        add_to_atlas_group(&mut self.glyphs, atlas, generation, mesh);
+    }
+
+    /// Adds color glyphs drawn from an atlas, merging them with any color
+    /// glyphs from the same atlas.
+    pub fn add_color_glyphs(&mut self, atlas: &Arc<ColorAtlas>, mesh: &MeshData<GlyphVertex>) {
+        add_to_atlas_group(&mut self.color_glyphs, atlas, 0, mesh);
+    }
+}
+
+/// Appends a mesh to the group of meshes sampling the same atlas.
+///
+/// A group is as old as its oldest mesh, so that a mesh placed before the
+/// atlas changed is never drawn with the changed atlas.
+fn add_to_atlas_group<A>(
+    groups: &mut Vec<(Arc<A>, u64, MeshData<GlyphVertex>)>,
+    atlas: &Arc<A>,
+    generation: u64,
+    mesh: &MeshData<GlyphVertex>,
+) {
+    if mesh.is_empty() {
+        return;
+    }
+
+    match groups.iter_mut().find(|(a, _, _)| Arc::ptr_eq(a, atlas)) {
+        Some((_, old, glyphs)) => {
+            *old = (*old).min(generation);
+            glyphs.append(mesh);
+        }
+        None => groups.push((atlas.to_owned(), generation, mesh.to_owned())),
+    }
+}
+
+/// Counters describing the most recently rendered batch of terminals.
+#[derive(Debug, Default)]
+pub struct DrawStats {
+    terminals: AtomicU32,
+    draw_calls: AtomicU32,
+}
+
+impl DrawStats {
+    /// Records the results of drawing a batch.
+    pub fn record(&self, terminals: u32, draw_calls: u32) {
+        self.terminals.store(terminals, Ordering::Relaxed);
+        self.draw_calls.store(draw_calls, Ordering::Relaxed);
+    }
+
+    /// Gets the most recently recorded counters.
+    pub fn get(&self) -> TerminalRenderStats {
+        TerminalRenderStats {
+            terminals: self.terminals.load(Ordering::Relaxed),
+            draw_calls: self.draw_calls.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The transform and glyph parameters of one terminal in a batch.
+struct BatchedTerminal {
+    model: Mat4,
+    units_per_em: f32,
+    em_range: f32,
+    outline: Option<TerminalOutline>,
+    depth_mode: TerminalDepthMode,
+}
+
+/// The range of one terminal's geometry within a [BatchLayer].
+#[derive(Clone, Debug, Default)]
+struct DrawRange {
+    indices: Range<u32>,
+    base_vertex: i32,
+}
+
+/// Shared GPU buffers holding one layer of geometry from every terminal.
+struct BatchLayer<T> {
+    vertices: GpuVector<T>,
+    indices: GpuVector<u32>,
+
+    /// The range of each terminal's geometry, indexed by terminal.
+    ranges: Vec<DrawRange>,
+}
+
+impl<T: Pod> BatchLayer<T> {
+    fn new(device: &Device, label: &str) -> Self {
+        Self {
+            vertices: GpuVector::new(
+                device,
+                Some(format!("{} vertices", label)),
+                BufferUsages::VERTEX,
+            ),
+            indices: GpuVector::new(
+                device,
+                Some(format!("{} indices", label)),
+                BufferUsages::INDEX,
+            ),
+            ranges: Vec::new(),
+        }
+    }
+
+    /// Concatenates each terminal's mesh, in terminal order, and uploads the
+    /// result. Terminals without a mesh in this layer are given an empty range.
+    fn update<'a>(
+        &mut self,
+        device: &Device,
+        queue: &Queue,
+        meshes: impl Iterator<Item = Option<&'a MeshData<T>>>,
+    ) where
+        T: 'a,
+    {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        self.ranges.clear();
+
+        for mesh in meshes {
+            let start = indices.len() as u32;
+            let base_vertex = vertices.len() as i32;
+
+            if let Some(mesh) = mesh {
+                vertices.extend_from_slice(&mesh.vertices);
+                indices.extend_from_slice(&mesh.indices);
+            }
+
+            self.ranges.push(DrawRange {
+                indices: start..indices.len() as u32,
+                base_vertex,
+            });
+        }
+
+        self.vertices.update(device, queue, &vertices);
+        self.indices.update(device, queue, &indices);
+    }
+
+    /// Tests if any of the given terminals have geometry in this layer.
+    fn has_draws(&self, order: &[u32]) -> bool {
+        order
+            .iter()
+            .any(|terminal| !self.ranges[*terminal as usize].indices.is_empty())
+    }
+
+    /// Binds this layer's buffers and draws each terminal's range in the
+    /// given order. Returns the number of draw calls issued.
+    fn draw<'a>(&'a self, rpass: &mut RenderPass<'a>, order: &[u32]) -> u32 {
+        if !self.has_draws(order) {
+            return 0;
+        }
+
+        rpass.set_vertex_buffer(0, self.vertices.get_buffer().slice(..));
+        rpass.set_index_buffer(self.indices.get_buffer().slice(..), IndexFormat::Uint32);
+
+        let mut draw_calls = 0;
+        for terminal in order.iter().copied() {
+            let range = &self.ranges[terminal as usize];
+            if !range.indices.is_empty() {
+                let instances = terminal..(terminal + 1);
+                rpass.draw_indexed(range.indices.clone(), range.base_vertex, instances);
+                draw_calls += 1;
+            }
+        }
+
+        draw_calls
+    }
+}
+
+/// The glyphs of every terminal that sample the same atlas.
+struct GlyphLayer<A> {
+    atlas: Arc<A>,
+
+    /// The generation of the atlas when [Self::bind_group] was created.
+    generation: u64,
+    bind_group: BindGroup,
+    layer: BatchLayer<GlyphVertex>,
+}
+
+impl<A> GlyphLayer<A> {
+    /// Rebuilds a set of glyph layers from each draw state's glyphs, keeping
+    /// the buffers and bind groups of atlases that are still in use.
+    ///
+    /// `groups` selects which of a draw state's glyph groups to use,
+    /// `view` creates a view of an atlas's texture to bind along with the
+    /// atlas's generation, and `generation` looks up an atlas's generation.
+    ///
+    /// Bind groups are recreated when their atlas's generation changes, and
+    /// meshes placed in a different generation than the bound one are
+    /// skipped until they're placed again.
+    fn update_all(
+        layers: &mut Vec<Self>,
+        pipelines: &TerminalPipelines,
+        draws: &[&TerminalDrawState],
+        groups: impl Fn(&TerminalDrawState) -> &[(Arc<A>, u64, MeshData<GlyphVertex>)],
+        view: impl Fn(&A) -> (TextureView, u64),
+        generation: impl Fn(&A) -> u64,
+    ) {
         let device = pipelines.device.as_ref();
+        let queue = pipelines.queue.as_ref();
 
-        let camera_buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("Alacritty terminal camera buffer"),
-            size: std::mem::size_of::<CameraUniform>() as BufferAddress,
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
+        let mut atlases: Vec<&Arc<A>> = Vec::new();
+        for (atlas, _, _) in draws.iter().flat_map(|draw| groups(draw).iter()) {
+            if !atlases.iter().any(|other| Arc::ptr_eq(other, atlas)) {
+                atlases.push(atlas);
+            }
+        }
 
-        let camera_bind_group = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Alacritty terminal camera bind group"),
-            layout: &pipelines.camera_bgl,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
+        let mut old_layers = std::mem::take(layers);
+        for atlas in atlases {
+            let old = old_layers
+                .iter()
+                .position(|glyphs| Arc::ptr_eq(&glyphs.atlas, atlas));
 
-        let glyph_bind_groups = fonts.map(|font| {
-            let atlas_view = font.texture.create_view(&Default::default());
+            let mut glyphs = match old {
+                Some(index) => old_layers.swap_remove(index),
+                None => {
+                    let (view, generation) = view(atlas);
+                    GlyphLayer {
+                        atlas: atlas.to_owned(),
+                        generation,
+                        bind_group: pipelines.create_glyph_bind_group(&view),
+                        layer: BatchLayer::new(device, "Alacritty glyph batch"),
+                    }
+                }
+            };
 
-            let glyph_bind_group = pipelines.device.create_bind_group(&BindGroupDescriptor {
-                label: None,
-                layout: &pipelines.glyph_bgl,
-                entries: &[
-                    BindGroupEntry {
-                        binding: 0,
-                        resource: BindingResource::TextureView(&atlas_view),
-                    },
-                    BindGroupEntry {
-                        binding: 1,
-                        resource: BindingResource::Sampler(&pipelines.atlas_sampler),
-                    },
-                ],
+            if glyphs.generation != generation(atlas) {
+                let (view, generation) = view(atlas);
+                glyphs.generation = generation;
+                glyphs.bind_group = pipelines.create_glyph_bind_group(&view);
+            }
+
+            let bound = glyphs.generation;
+            let meshes = draws.iter().map(|draw| {
+                groups(draw)
+                    .iter()
+                    .find(|(other, _, _)| Arc::ptr_eq(other, atlas))
+                    .filter(|(_, generation, _)| *generation == bound)
+                    .map(|(_, _, mesh)| mesh)
             });
 
-            glyph_bind_group
-        });
+            glyphs.layer.update(device, queue, meshes);
+            layers.push(glyphs);
+        }
+    }
 
-        let glyph_meshes = FontSet {
-            regular: "Alacritty regular glyph mesh",
-            italic: "Alacritty italic glyph mesh",
-            bold: "Alacritty bold glyph mesh",
-            bold_italic: "Alacritty bold italic glyph mesh",
+    /// Binds this layer's atlas and draws the given terminals' glyphs.
+    /// Returns the number of draw calls issued.
+    fn draw<'a>(&'a self, rpass: &mut RenderPass<'a>, order: &[u32]) -> u32 {
+        if !self.layer.has_draws(order) {
+            return 0;
         }
-        .map(|name| DynamicMesh::new(device, Some(name.to_string())));
+
+        rpass.set_bind_group(1, &self.bind_group, &[]);
+        self.layer.draw(rpass, order)
+    }
+}
+
+/// The geometry of every terminal, uploaded into shared buffers.
+pub struct TerminalBatch {
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    terminals: Vec<BatchedTerminal>,
+    cameras: GpuVector<CameraUniform>,
+    camera_bind_group: Option<BindGroup>,
+    bg: BatchLayer<SolidVertex>,
+    glyphs: Vec<GlyphLayer<FaceAtlas>>,
+    color_glyphs: Vec<GlyphLayer<ColorAtlas>>,
+    overlay: BatchLayer<SolidVertex>,
+    chrome: BatchLayer<SolidVertex>,
+    stats: Arc<DrawStats>,
+
+    /// The world-space position of the focused terminal's IME anchor.
+    ime_anchor: Option<Vec3>,
+}
+
+impl TerminalBatch {
+    pub fn new(pipelines: &TerminalPipelines, stats: Arc<DrawStats>) -> Self {
+        let device = pipelines.device.as_ref();
 
         Self {
-            model: Mat4::IDENTITY,
-            camera_buffer,
-            camera_bind_group,
-            bg_mesh: DynamicMesh::new(device, Some("Alacritty background mesh".into())),
-            glyph_meshes,
-            overlay_mesh: DynamicMesh::new(device, Some("Alacritty overlay mesh".into())),
-            glyph_bind_groups,
             device: pipelines.device.to_owned(),
             queue: pipelines.queue.to_owned(),
+            terminals: Vec::new(),
+            cameras: GpuVector::new(
+                device,
+                Some("Alacritty terminal camera buffer".into()),
+                BufferUsages::STORAGE,
+            ),
+            camera_bind_group: None,
+            bg: BatchLayer::new(device, "Alacritty background batch"),
+            glyphs: Vec::new(),
+            color_glyphs: Vec::new(),
+            overlay: BatchLayer::new(device, "Alacritty overlay batch"),
+            chrome: BatchLayer::new(device, "Alacritty chrome batch"),
+            stats,
+            ime_anchor: None,
         }
     }
+
+    /// Projects the focused terminal's cursor to a position on screen. See
+    /// [screen_position].
+    pub fn ime_position(&self, vp: Mat4, resolution: UVec2) -> Option<Vec2> {
+        screen_position(vp, self.ime_anchor?, resolution)
+    }
+
+    /// Replaces the contents of this batch with the given draw states.
+    pub fn update(&mut self, pipelines: &TerminalPipelines, draws: &[&TerminalDrawState]) {
+        let device = self.device.as_ref();
+        let queue = self.queue.as_ref();
+
+        self.terminals = draws
+            .iter()
+            .map(|draw| BatchedTerminal {
+                model: draw.model,
+                units_per_em: draw.units_per_em,
+                em_range: draw.em_range,
+                outline: draw.outline,
+                depth_mode: draw.depth_mode,
+            })
+            .collect();
+
+        self.ime_anchor = draws.iter().find_map(|draw| {
+            let anchor = draw.ime_anchor?;
+            Some(draw.model.transform_point3(anchor.extend(0.0)))
+        });
+
+        // the cameras depend on the view, so they're only written once the
+        // batch is drawn. this just makes room for them.
+        let cameras = vec![CameraUniform::zeroed(); draws.len()];
+        self.cameras.update(device, queue, &cameras);
+
+        // the camera buffer may have been reallocated, so always rebind it
+        self.camera_bind_group = (!draws.is_empty()).then(|| {
+            device.create_bind_group(&BindGroupDescriptor {
+                label: Some("Alacritty terminal camera bind group"),
+                layout: &pipelines.camera_bgl,
+                entries: &[BindGroupEntry {
+                    binding: 0,
+                    resource: self.cameras.get_buffer().as_entire_binding(),
+                }],
+            })
+        });
+
+        self.bg
+            .update(device, queue, draws.iter().map(|draw| Some(&draw.bg)));
+
+        self.overlay
+            .update(device, queue, draws.iter().map(|draw| Some(&draw.overlay)));
+
+        self.chrome
+            .update(device, queue, draws.iter().map(|draw| Some(&draw.chrome)));
+
+        GlyphLayer::update_all(
+            &mut self.glyphs,
+            pipelines,
+            draws,
+            |draw| &draw.glyphs,
+            |atlas| atlas.view(),
+            |atlas| atlas.generation(),
+        );
+
+        GlyphLayer::update_all(
+            &mut self.color_glyphs,
+            pipelines,
+            draws,
+            |draw| &draw.color_glyphs,
+            |atlas| (atlas.texture.create_view(&Default::default()), 0),
+            |_| 0,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compositing_loads_targets() {
+        // depth from the scene must be kept for terminals to be occluded
+        assert_eq!(pass_clears(None), (Color::TRANSPARENT, None));
+
+        let clear = Color {
+            r: 0.1,
+            g: 0.2,
+            b: 0.3,
+            a: 1.0,
+        };
+
+        assert_eq!(pass_clears(Some(clear)), (clear, Some(0.0)));
+    }
+
+    #[test]
+    fn px_per_em_scales_with_distance() {
+        let resolution = Vec2::new(800.0, 600.0);
+        let proj = Mat4::perspective_infinite_reverse_rh(1.0, 800.0 / 600.0, 0.1);
+
+        let at = |distance: f32| {
+            let mvp = proj * Mat4::from_translation(Vec3::new(0.0, 0.0, -distance));
+            screen_px_per_em(mvp, 0.1, resolution).unwrap()
+        };
+
+        let near = at(1.0);
+        let far = at(4.0);
+        assert!(near > far);
+        assert!((near / far - 4.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn px_per_em_behind_camera() {
+        let proj = Mat4::perspective_infinite_reverse_rh(1.0, 1.0, 0.1);
+        let mvp = proj * Mat4::from_translation(Vec3::new(0.0, 0.0, 1.0));
+        assert_eq!(screen_px_per_em(mvp, 0.1, Vec2::splat(512.0)), None);
+    }
+
+    #[test]
+    fn screen_position_from_top_left() {
+        let resolution = UVec2::new(800, 600);
+        let center = screen_position(Mat4::IDENTITY, Vec3::ZERO, resolution).unwrap();
+        assert_eq!(center, Vec2::new(400.0, 300.0));
+
+        let top_left = Vec3::new(-1.0, 1.0, 0.0);
+        let corner = screen_position(Mat4::IDENTITY, top_left, resolution).unwrap();
+        assert_eq!(corner, Vec2::ZERO);
+
+        let proj = Mat4::perspective_infinite_reverse_rh(1.0, 1.0, 0.1);
+        let behind = screen_position(proj, Vec3::Z, resolution);
+        assert_eq!(behind, None);
+    }
+
+    #[test]
+    fn view_depth_orders_by_distance() {
+        let proj = Mat4::perspective_infinite_reverse_rh(1.0, 1.0, 0.1);
+        let view = Mat4::from_rotation_y(0.5);
+        let vp = proj * view;
+
+        let at = |distance: f32| {
+            let pos = view
+                .inverse()
+                .transform_vector3(Vec3::new(0.2, 0.0, -distance));
+            view_depth(vp, Mat4::from_translation(pos))
+        };
+
+        assert!(at(2.0) > at(1.0));
+        assert!((at(3.0) - 3.0).abs() < 0.001);
+    }
+
+    #[test]
+    fn target_view_proj_fits_terminal() {
+        let model = Mat4::from_rotation_translation(
+            glam::Quat::from_rotation_y(1.0),
+            Vec3::new(2.0, 1.0, -3.0),
+        );
+
+        let half_size = Vec2::new(1.2, 0.9);
+        let mvp = target_view_proj(model, half_size) * model;
+        let project = |pos: Vec3| mvp.project_point3(pos);
+
+        let corner = project(half_size.extend(0.0));
+        assert!(corner.truncate().abs_diff_eq(Vec2::ONE, 0.001));
+        let corner = project(-half_size.extend(0.0));
+        assert!(corner.truncate().abs_diff_eq(-Vec2::ONE, 0.001));
+
+        // geometry in front of the terminal is nearer in reversed depth
+        assert!(project(Vec3::Z * 0.01).z > project(Vec3::ZERO).z);
+    }
+
+    #[test]
+    fn opaque_terminals_are_drawn_first() {
+        let proj = Mat4::perspective_infinite_reverse_rh(1.0, 1.0, 0.1);
+        let at = |distance: f32, depth_mode| {
+            let model = Mat4::from_translation(Vec3::new(0.0, 0.0, -distance));
+            (model, depth_mode)
+        };
+
+        let terminals = [
+            at(1.0, TerminalDepthMode::Transparent),
+            at(2.0, TerminalDepthMode::Opaque),
+            at(3.0, TerminalDepthMode::Transparent),
+            at(0.5, TerminalDepthMode::Opaque),
+            at(2.0, TerminalDepthMode::Transparent),
+        ];
+
+        let (opaque, transparent) = draw_order(proj, terminals.into_iter());
+        assert_eq!(opaque, [1, 3]);
+        assert_eq!(transparent, [2, 4, 0]);
+    }
+
+    #[test]
+    fn clear_color_is_linear() {
+        let white = linear_clear_color(hearth_schema::Color(0xffffffff));
+        assert_eq!((white.r, white.g, white.b, white.a), (1.0, 1.0, 1.0, 1.0));
+
+        let gray = linear_clear_color(hearth_schema::Color(0x80808080));
+        assert!((gray.r - 0.2159).abs() < 0.001);
+        assert!((gray.a - 0.502).abs() < 0.001);
+    }
+
+    #[test]
+    fn appended_indices_are_offset() {
+        let vertex = |x: f32| SolidVertex {
+            position: Vec2::new(x, 0.0),
+            color: 0,
+        };
+
+        let mut mesh = MeshData {
+            vertices: vec![vertex(0.0), vertex(1.0), vertex(2.0)],
+            indices: vec![0, 1, 2],
+        };
+
+        let other = MeshData {
+            vertices: vec![vertex(3.0), vertex(4.0), vertex(5.0)],
+            indices: vec![2, 1, 0],
+        };
+
+        mesh.append(&other);
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(mesh.indices, [0, 1, 2, 5, 4, 3]);
+    }
+
+    #[test]
+    fn outline_width_is_clamped() {
+        let outline = TerminalOutline {
+            color: hearth_schema::Color(0xff000000),
+            width: 10.0,
+        };
+
+        let uniform = CameraUniform::new(Mat4::IDENTITY, 1.0, 0.1, UVec2::ONE, Some(outline));
+        assert_eq!(uniform.outline_width, 0.5);
+        assert_eq!(uniform.outline_color, Vec4::new(0.0, 0.0, 0.0, 1.0));
+    }
 }
@@ -0,0 +1,247 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Arc;
+
+use hearth_runtime::anyhow::{Context, Result};
+use hearth_runtime::asset::{AssetLoader, AssetStore};
+use hearth_runtime::{async_trait, tokio};
+use hearth_schema::{terminal::TerminalFonts, LumpId};
+use owned_ttf_parser::{AsFaceRef, OwnedFace};
+
+use crate::text::{AtlasDevice, FaceAtlas, FontError, FontSet};
+
+/// Loads [FaceAtlas] assets from lumps containing TrueType or OpenType font
+/// files.
+///
+/// Atlases are cached by the asset store, so every load of the same lump
+/// shares one atlas.
+pub struct FontLoader {
+    device: AtlasDevice,
+}
+
+impl FontLoader {
+    /// Creates a loader that uploads atlases to the given device's current
+    /// device.
+    pub fn new(device: AtlasDevice) -> Self {
+        Self { device }
+    }
+}
+
+#[async_trait]
+impl AssetLoader for FontLoader {
+    type Asset = FaceAtlas;
+
+    async fn load_asset(&self, _store: &AssetStore, data: &[u8]) -> Result<FaceAtlas> {
+        let face = parse_face(data.to_vec())?;
+
+        // generating MSDF atlases takes a while, so keep it off of the executor
+        let (device, queue) = self.device.get();
+        let atlas = tokio::task::spawn_blocking(move || FaceAtlas::new(face, device, queue))
+            .await
+            .context("font loading task failed")??;
+
+        Ok(atlas)
+    }
+}
+
+/// Parses a face from font data, rejecting faces that atlases can't be
+/// generated from.
+pub fn parse_face(src: Vec<u8>) -> Result<OwnedFace, FontError> {
+    let face = OwnedFace::from_vec(src, 0)?;
+
+    // atlases only cover a face's default outlines, so variations would be
+    // silently ignored
+    if face.as_face_ref().is_variable() {
+        return Err(FontError::Variable);
+    }
+
+    Ok(face)
+}
+
+/// Loads a set of fonts through the asset store.
+///
+/// Missing styles share the regular atlas, which also tells the renderer
+/// that there's no real bold face.
+pub async fn load_fonts(
+    store: &AssetStore,
+    lumps: &TerminalFonts,
+) -> Result<FontSet<Arc<FaceAtlas>>> {
+    let regular = load_font(store, lumps.regular).await?;
+
+    Ok(FontSet {
+        italic: load_style(store, lumps.italic, &regular).await?,
+        bold: load_style(store, lumps.bold, &regular).await?,
+        bold_italic: load_style(store, lumps.bold_italic, &regular).await?,
+        regular,
+    })
+}
+
+/// Loads an optional font style, falling back to the regular font.
+async fn load_style(
+    store: &AssetStore,
+    lump: Option<LumpId>,
+    regular: &Arc<FaceAtlas>,
+) -> Result<Arc<FaceAtlas>> {
+    match lump {
+        Some(lump) => load_font(store, lump).await,
+        None => Ok(regular.clone()),
+    }
+}
+
+/// Loads a single font through the asset store.
+async fn load_font(store: &AssetStore, lump: LumpId) -> Result<Arc<FaceAtlas>> {
+    store
+        .load_asset::<FontLoader>(&lump)
+        .await
+        .with_context(|| format!("loading font {}", lump))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use glam::{uvec2, UVec2};
+    use hearth_rend3::{wgpu::TextureFormat, Rend3Plugin};
+    use hearth_runtime::lump::LumpStoreImpl;
+
+    const MONONOKI: &[u8] = include_bytes!("../../../resources/mononoki/mononoki-Regular.ttf");
+
+    /// Creates an asset store with a [FontLoader], or `None` if this machine
+    /// has no usable graphics adapter.
+    async fn setup() -> Option<(AssetStore, Arc<LumpStoreImpl>)> {
+        let size: UVec2 = uvec2(64, 64);
+        let rend3 = match Rend3Plugin::new_headless(size, TextureFormat::Rgba8UnormSrgb).await {
+            Ok(rend3) => rend3,
+            Err(err) => {
+                eprintln!("skipping font loader test without an adapter: {}", err);
+                return None;
+            }
+        };
+
+        let renderer = &rend3.renderer;
+        let device = AtlasDevice::new(renderer.device.clone(), renderer.queue.clone());
+        let loader = FontLoader::new(device);
+        let lumps = Arc::new(LumpStoreImpl::new());
+        let mut store = AssetStore::new(lumps.clone());
+        store.add_loader(loader);
+        Some((store, lumps))
+    }
+
+    #[test]
+    fn rejects_invalid_fonts() {
+        let result = parse_face(b"not a font".to_vec());
+        assert!(matches!(result, Err(FontError::Parse(_))));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn loads_fonts_from_lumps() {
+        let Some((store, lumps)) = setup().await else {
+            return;
+        };
+
+        let lump = lumps.add_lump(MONONOKI.to_vec().into()).await;
+        let atlas = store.load_asset::<FontLoader>(&lump).await.unwrap();
+
+        let face = atlas.face.as_face_ref();
+        let glyph = face.glyph_index('A').unwrap().0;
+        assert!(atlas.glyph_metrics(glyph).is_some());
+        assert!(matches!(
+            atlas.atlas.glyphs.get(glyph as usize),
+            Some(Some(_))
+        ));
+
+        // duplicate loads share one atlas
+        let fonts = TerminalFonts {
+            regular: lump,
+            italic: None,
+            bold: Some(lump),
+            bold_italic: None,
+        };
+
+        let fonts = load_fonts(&store, &fonts).await.unwrap();
+        assert!(Arc::ptr_eq(&fonts.regular, &atlas));
+        assert!(Arc::ptr_eq(&fonts.bold, &atlas));
+        assert!(Arc::ptr_eq(&fonts.italic, &atlas));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn atlases_migrate_to_reset_devices() {
+        let size: UVec2 = uvec2(64, 64);
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let old = Rend3Plugin::new_headless(size, format).await;
+        let new = Rend3Plugin::new_headless(size, format).await;
+        let (Ok(old), Ok(new)) = (old, new) else {
+            eprintln!("skipping atlas migration test without an adapter");
+            return;
+        };
+
+        let old = &old.renderer;
+        let device = AtlasDevice::new(old.device.clone(), old.queue.clone());
+        let atlas =
+            FaceAtlas::from_data(MONONOKI.to_vec(), old.device.clone(), old.queue.clone()).unwrap();
+
+        let glyph = atlas.face.as_face_ref().glyph_index('A').unwrap().0;
+        atlas.touch(&[glyph]);
+
+        // atlases stay put until the device is actually replaced
+        atlas.migrate(&device);
+        assert!(atlas.is_on(&old.queue));
+        assert!(atlas.is_resident(glyph));
+
+        let new = &new.renderer;
+        device.replace(new.device.clone(), new.queue.clone());
+        let generation = atlas.generation();
+        atlas.migrate(&device);
+        assert!(atlas.is_on(&new.queue));
+        assert!(!atlas.is_resident(glyph));
+        assert!(atlas.generation() > generation);
+
+        // glyphs are uploaded to the new texture when they're next touched
+        atlas.touch(&[glyph]);
+        assert!(atlas.is_resident(glyph));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn atlases_grow_to_fit_glyphs() {
+        let size: UVec2 = uvec2(64, 64);
+        let format = TextureFormat::Rgba8UnormSrgb;
+        let Ok(rend3) = Rend3Plugin::new_headless(size, format).await else {
+            eprintln!("skipping atlas growth test without an adapter");
+            return;
+        };
+
+        let renderer = &rend3.renderer;
+        let atlas = FaceAtlas::from_data(
+            MONONOKI.to_vec(),
+            renderer.device.clone(),
+            renderer.queue.clone(),
+        )
+        .unwrap();
+
+        // more glyphs than fit in the initial texture
+        let glyphs: Vec<u16> = (0..atlas.atlas.glyphs.len() as u16)
+            .filter(|glyph| atlas.atlas.glyphs[*glyph as usize].is_some())
+            .collect();
+
+        let generation = atlas.touch(&glyphs);
+        assert!(generation > 0);
+        assert!(glyphs.iter().all(|glyph| atlas.is_resident(*glyph)));
+        assert_eq!(atlas.view().1, generation);
+    }
+}
@@ -0,0 +1,278 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{ops::Range, sync::OnceLock};
+
+use alacritty_terminal::{
+    index::{Column, Line, Point},
+    term::cell::{Cell, Flags},
+};
+use hearth_schema::terminal::HotspotKind;
+use regex::Regex;
+
+/// A URL or path found in a terminal's grid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hotspot {
+    /// The first cell of the match.
+    pub start: Point,
+
+    /// The last cell of the match, inclusive.
+    pub end: Point,
+
+    /// The matched text.
+    pub text: String,
+
+    /// What kind of text was matched.
+    pub kind: HotspotKind,
+}
+
+impl Hotspot {
+    /// Tests if a cell is part of this hotspot.
+    pub fn contains(&self, point: Point) -> bool {
+        self.start <= point && point <= self.end
+    }
+}
+
+fn url_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX
+        .get_or_init(|| Regex::new(r#"\b(?:https?|ftp|file|ssh|git)://[^\s<>"'`{}|\\^]+"#).unwrap())
+}
+
+fn path_regex() -> &'static Regex {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    REGEX.get_or_init(|| {
+        Regex::new(r#"(?:^|[\s'"(\[=:])((?:~|\.{1,2})?/[^\s'"()<>\[\]/][^\s'"()<>\[\]]*)"#).unwrap()
+    })
+}
+
+/// Trims punctuation that's more likely to end a sentence than a match, as
+/// well as closing brackets with no opening bracket in the match.
+fn trim_match(text: &str) -> &str {
+    let mut text = text;
+    loop {
+        let Some(last) = text.chars().last() else {
+            return text;
+        };
+
+        let unbalanced =
+            |open, close| last == close && text.matches(open).count() < text.matches(close).count();
+
+        let trim = matches!(last, '.' | ',' | ';' | ':' | '!' | '?')
+            || unbalanced('(', ')')
+            || unbalanced('[', ']');
+
+        if !trim {
+            return text;
+        }
+
+        text = &text[..text.len() - last.len_utf8()];
+    }
+}
+
+/// Finds hotspots in the text of a logical line. `points` maps the byte
+/// offset of each character in `text` to its cell.
+fn scan_line(text: &str, points: &[(usize, Point)], hotspots: &mut Vec<Hotspot>) {
+    let point_at = |offset: usize| {
+        let index = points.partition_point(|(start, _)| *start < offset);
+        points[index].1
+    };
+
+    let mut push = |range: Range<usize>, kind| {
+        let matched = trim_match(&text[range.clone()]);
+        if matched.is_empty() {
+            return;
+        }
+
+        let last_char = matched.char_indices().last().unwrap().0;
+        hotspots.push(Hotspot {
+            start: point_at(range.start),
+            end: point_at(range.start + last_char),
+            text: matched.to_string(),
+            kind,
+        });
+    };
+
+    let urls: Vec<_> = url_regex().find_iter(text).map(|m| m.range()).collect();
+    for url in urls.iter() {
+        push(url.clone(), HotspotKind::Url);
+    }
+
+    for captures in path_regex().captures_iter(text) {
+        let path = captures.get(1).unwrap().range();
+        let in_url = urls
+            .iter()
+            .any(|url| path.start < url.end && url.start < path.end);
+
+        if !in_url {
+            push(path, HotspotKind::Path);
+        }
+    }
+}
+
+/// Finds URLs and paths in lines of cells, in order.
+///
+/// Soft-wrapped lines are joined, so matches may span lines. The lines
+/// must be contiguous.
+pub fn find_hotspots<'a, L, C>(lines: L) -> Vec<Hotspot>
+where
+    L: IntoIterator<Item = (Line, C)>,
+    C: IntoIterator<Item = &'a Cell>,
+{
+    let mut hotspots = Vec::new();
+    let mut text = String::new();
+    let mut points = Vec::new();
+
+    for (line, cells) in lines {
+        let mut wrapped = false;
+
+        for (column, cell) in cells.into_iter().enumerate() {
+            wrapped = cell.flags.contains(Flags::WRAPLINE);
+
+            // wide characters are mapped to their first cell
+            let spacer = Flags::WIDE_CHAR_SPACER | Flags::LEADING_WIDE_CHAR_SPACER;
+            if cell.flags.intersects(spacer) {
+                continue;
+            }
+
+            points.push((text.len(), Point::new(line, Column(column))));
+            text.push(cell.c);
+        }
+
+        if !wrapped {
+            scan_line(&text, &points, &mut hotspots);
+            text.clear();
+            points.clear();
+        }
+    }
+
+    scan_line(&text, &points, &mut hotspots);
+    hotspots.sort_by_key(|hotspot| hotspot.start);
+    hotspots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lays out text in a grid of the given width, soft-wrapping long lines.
+    fn grid(text: &str, columns: usize) -> Vec<Vec<Cell>> {
+        let mut rows = Vec::new();
+        for line in text.lines() {
+            let chars: Vec<char> = line.chars().collect();
+            let chunks: Vec<&[char]> = chars.chunks(columns).collect();
+            let chunks = if chunks.is_empty() {
+                vec![&[][..]]
+            } else {
+                chunks
+            };
+            let last = chunks.len() - 1;
+
+            for (index, chunk) in chunks.into_iter().enumerate() {
+                let mut row = vec![Cell::default(); columns];
+                for (cell, c) in row.iter_mut().zip(chunk) {
+                    cell.c = *c;
+                }
+
+                if index < last {
+                    row[columns - 1].flags.insert(Flags::WRAPLINE);
+                }
+
+                rows.push(row);
+            }
+        }
+
+        rows
+    }
+
+    fn find(rows: &[Vec<Cell>]) -> Vec<Hotspot> {
+        find_hotspots(
+            rows.iter()
+                .enumerate()
+                .map(|(line, row)| (Line(line as i32), row.iter())),
+        )
+    }
+
+    fn point(line: i32, column: usize) -> Point {
+        Point::new(Line(line), Column(column))
+    }
+
+    #[test]
+    fn finds_url() {
+        let hotspots = find(&grid("see https://example.com/docs.", 40));
+        assert_eq!(
+            hotspots,
+            vec![Hotspot {
+                start: point(0, 4),
+                end: point(0, 27),
+                text: "https://example.com/docs".to_string(),
+                kind: HotspotKind::Url,
+            }]
+        );
+    }
+
+    #[test]
+    fn url_wraps_across_lines() {
+        let hotspots = find(&grid("go to https://example.com/a/long/path now", 16));
+        assert_eq!(hotspots.len(), 1);
+        assert_eq!(hotspots[0].text, "https://example.com/a/long/path");
+        assert_eq!(hotspots[0].start, point(0, 6));
+        assert_eq!(hotspots[0].end, point(2, 4));
+        assert!(hotspots[0].contains(point(1, 0)));
+        assert!(!hotspots[0].contains(point(2, 5)));
+    }
+
+    #[test]
+    fn hard_line_breaks_end_urls() {
+        let hotspots = find(&grid("https://example.com/a\nb", 40));
+        assert_eq!(hotspots.len(), 1);
+        assert_eq!(hotspots[0].text, "https://example.com/a");
+    }
+
+    #[test]
+    fn finds_paths() {
+        let hotspots = find(&grid("edit ~/notes.txt or ./src/main.rs", 80));
+        let texts: Vec<_> = hotspots.iter().map(|h| (h.text.as_str(), h.kind)).collect();
+        assert_eq!(
+            texts,
+            vec![
+                ("~/notes.txt", HotspotKind::Path),
+                ("./src/main.rs", HotspotKind::Path)
+            ]
+        );
+    }
+
+    #[test]
+    fn paths_inside_urls_are_ignored() {
+        let hotspots = find(&grid("file:///etc/hosts", 80));
+        assert_eq!(hotspots.len(), 1);
+        assert_eq!(hotspots[0].kind, HotspotKind::Url);
+    }
+
+    #[test]
+    fn unbalanced_brackets_are_trimmed() {
+        let hotspots = find(&grid(
+            "(see https://en.wikipedia.org/wiki/Rust_(language))",
+            80,
+        ));
+        assert_eq!(
+            hotspots[0].text,
+            "https://en.wikipedia.org/wiki/Rust_(language)"
+        );
+    }
+}
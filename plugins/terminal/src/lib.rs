@@ -16,30 +16,61 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use draw::{TerminalDrawState, TerminalPipelines};
-use hearth_rend3::*;
+use alacritty_terminal::grid::Scroll;
+use draw::{DrawStats, ImeReporter, TerminalBatch, TerminalDrawState, TerminalPipelines};
+use font::FontLoader;
+use glam::{Mat4, UVec2, Vec2};
+use hearth_rend3::{rend3::types::SampleCount, targets::SharedTarget, *};
 use hearth_runtime::{
     async_trait, cargo_process_metadata,
+    flue::{OwnedCapability, Table},
     process::ProcessMetadata,
-    runtime::{Plugin, RuntimeBuilder},
+    runtime::{Plugin, Runtime, RuntimeBuilder},
+    tokio,
     tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    tracing::{debug, error, warn},
     utils::*,
 };
 use hearth_schema::terminal::*;
+use pty::ShellCommand;
+use serde::Deserialize;
 use terminal::{Terminal, TerminalConfig};
-use text::{FaceAtlas, FontSet};
+use text::{AtlasDevice, FaceAtlas, FallbackFace, FontSet};
+
+/// Color glyph rasterization and atlases.
+pub mod color;
 
 /// Terminal rendering code.
 pub mod draw;
 
+/// Loading fonts from lumps as assets.
+pub mod font;
+
+/// URL and path detection in terminal contents.
+pub mod hotspot;
+
+/// Color palettes and built-in themes.
+pub mod palette;
+
+/// Child processes attached to pseudoterminals.
+pub mod pty;
+
 /// Integration with `alacritty_terminal`.
 pub mod terminal;
 
 /// Low-level text and font helpers.
 pub mod text;
 
+/// Background workers.
+pub mod worker;
+
 /// Contains a terminal and its cached draw state.
 pub struct TerminalWrapper {
     terminal: Arc<Terminal>,
@@ -48,73 +79,280 @@ pub struct TerminalWrapper {
 
 impl TerminalWrapper {
     /// Updates this terminal's draw state. Returns true if this terminal has not quit.
-    pub fn update(&mut self) -> bool {
+    ///
+    /// Atlases that are still on a lost device are moved to `device` first.
+    pub fn update(&mut self, blink_on: bool, device: &AtlasDevice) -> bool {
         let quit = self.terminal.should_quit();
 
         if !quit {
-            self.terminal.update_draw_state(&mut self.draw_state);
+            let fonts = self.terminal.get_fonts();
+            fonts.for_each(|atlas| atlas.migrate(device));
+
+            // meshes are built in the background, so this draws the newest
+            // finished state while the next one is being built
+            self.terminal.request_draw_state(blink_on);
+            if let Some(draw_state) = self.terminal.take_draw_state() {
+                self.draw_state = draw_state;
+            }
+
+            // the draw state may have been built before a device reset
+            for (atlas, _, _) in self.draw_state.glyphs.iter() {
+                atlas.migrate(device);
+            }
+
+            // color atlases are recreated by the next draw state instead
+            let (_, queue) = device.get();
+            let color_glyphs = &mut self.draw_state.color_glyphs;
+            color_glyphs.retain(|(atlas, _, _)| atlas.is_on(&queue));
         }
 
         !quit
     }
 }
 
+/// Tracks the on/off phase shared by everything that blinks.
+pub struct BlinkPhase {
+    start: Instant,
+    interval: Option<Duration>,
+}
+
+impl BlinkPhase {
+    /// Creates a blink phase that toggles every `interval`, or never blinks
+    /// if `interval` is `None`.
+    pub fn new(interval: Option<Duration>) -> Self {
+        Self {
+            start: Instant::now(),
+            interval: interval.filter(|interval| !interval.is_zero()),
+        }
+    }
+
+    /// Returns whether blinking things are currently shown.
+    pub fn is_on(&self) -> bool {
+        match self.interval {
+            Some(interval) => (self.start.elapsed().as_nanos() / interval.as_nanos()) % 2 == 0,
+            None => true,
+        }
+    }
+}
+
+/// An offscreen target that a [TerminalRoutine] draws its terminals into
+/// instead of the scene.
+pub struct TerminalTarget {
+    /// The target to draw into.
+    pub target: SharedTarget,
+
+    /// The view-projection matrix to draw the terminals with. See
+    /// [draw::target_view_proj] to fit a single terminal to the target.
+    pub view_proj: Mat4,
+}
+
 pub struct TerminalRoutine {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    atlas_device: AtlasDevice,
+    sample_count: SampleCount,
+    stats: Arc<DrawStats>,
     pipelines: TerminalPipelines,
+    batch: TerminalBatch,
     terminals: Vec<TerminalWrapper>,
     new_terminals: UnboundedReceiver<Arc<Terminal>>,
+    new_fonts: UnboundedReceiver<FontSet<Arc<FaceAtlas>>>,
+    blink: BlinkPhase,
+    clear: Option<wgpu::Color>,
+    target: Option<TerminalTarget>,
+    ime: Option<ImeReporter>,
 }
 
 impl TerminalRoutine {
-    pub fn new(rend3: &Rend3Plugin, new_terminals: UnboundedReceiver<Arc<Terminal>>) -> Self {
+    pub fn new(
+        rend3: &Rend3Plugin,
+        new_terminals: UnboundedReceiver<Arc<Terminal>>,
+        new_fonts: UnboundedReceiver<FontSet<Arc<FaceAtlas>>>,
+        blink: BlinkPhase,
+        stats: Arc<DrawStats>,
+    ) -> Self {
+        let pipelines = TerminalPipelines::new(
+            rend3.renderer.device.to_owned(),
+            rend3.renderer.queue.to_owned(),
+            HDR_FORMAT,
+            rend3.sample_count,
+        );
+
+        let device = rend3.renderer.device.to_owned();
+        let queue = rend3.renderer.queue.to_owned();
+
         Self {
-            pipelines: TerminalPipelines::new(
-                rend3.renderer.device.to_owned(),
-                rend3.renderer.queue.to_owned(),
-                rend3.surface_format,
-            ),
+            atlas_device: AtlasDevice::new(device.clone(), queue.clone()),
+            device,
+            queue,
+            sample_count: rend3.sample_count,
+            batch: TerminalBatch::new(&pipelines, stats.clone()),
+            stats,
+            pipelines,
             terminals: vec![],
             new_terminals,
+            new_fonts,
+            blink,
+            clear: None,
+            target: None,
+            ime: None,
         }
     }
+
+    /// Sets an offscreen target to draw terminals into instead of the
+    /// scene, or `None` to draw them into the scene again.
+    ///
+    /// Targets are single-sampled and may have any format, so this rebuilds
+    /// the routine's pipelines to match.
+    pub fn set_target(&mut self, target: Option<TerminalTarget>) {
+        self.target = target;
+        self.rebuild_pipelines();
+    }
+
+    /// The device that font atlases drawn by this routine should be created
+    /// on. It's replaced when the routine's device is reset.
+    pub fn atlas_device(&self) -> &AtlasDevice {
+        &self.atlas_device
+    }
+
+    /// Rebuilds the pipelines and batch for the current device and target.
+    fn rebuild_pipelines(&mut self) {
+        let (format, sample_count) = match self.target.as_ref() {
+            Some(target) => (target.target.format(), SampleCount::One),
+            None => (HDR_FORMAT, self.sample_count),
+        };
+
+        self.pipelines = TerminalPipelines::new(
+            self.device.clone(),
+            self.queue.clone(),
+            format,
+            sample_count,
+        );
+
+        self.batch = TerminalBatch::new(&self.pipelines, self.stats.clone());
+    }
+
+    /// Sets the color that the terminal pass clears its target to before
+    /// drawing, or `None` to draw over the rest of the scene.
+    ///
+    /// Clearing also resets depth, so terminals drawn in an isolated pass
+    /// aren't occluded by the scene.
+    pub fn set_clear(&mut self, clear: Option<wgpu::Color>) {
+        self.clear = clear;
+    }
+
+    /// Sets a sender of the focused terminal's on-screen cursor position,
+    /// in pixels from the top-left corner of the output, for placing IME
+    /// candidate windows. Positions are only sent when the cursor moves.
+    pub fn set_ime_positions(&mut self, tx: UnboundedSender<Vec2>) {
+        self.ime = Some(ImeReporter::new(tx));
+    }
 }
 
 impl Routine for TerminalRoutine {
     fn build_node(&mut self) -> Box<dyn Node + '_> {
         while let Ok(terminal) = self.new_terminals.try_recv() {
             self.terminals.push(TerminalWrapper {
-                draw_state: TerminalDrawState::new(&self.pipelines, terminal.get_fonts()),
+                draw_state: TerminalDrawState::default(),
                 terminal,
             });
         }
 
+        // apply the latest font change, after adding new terminals so that
+        // none of them miss it
+        let mut fonts = None;
+        while let Ok(new_fonts) = self.new_fonts.try_recv() {
+            fonts = Some(new_fonts);
+        }
+
+        if let Some(fonts) = fonts {
+            for term in self.terminals.iter() {
+                term.terminal.set_fonts(fonts.clone());
+            }
+        }
+
         // update draw states and remove terminals that have quit
-        self.terminals.retain_mut(TerminalWrapper::update);
+        let blink_on = self.blink.is_on();
+        let device = &self.atlas_device;
+        self.terminals
+            .retain_mut(|term| term.update(blink_on, device));
+
+        let draws: Vec<_> = self.terminals.iter().map(|term| &term.draw_state).collect();
+        self.batch.update(&self.pipelines, &draws);
 
         Box::new(TerminalNode {
             pipelines: &self.pipelines,
-            draws: self.terminals.iter().map(|term| &term.draw_state).collect(),
+            batch: &self.batch,
+            clear: self.clear,
+            target: self.target.as_ref(),
+            ime: self.ime.as_ref(),
         })
     }
+
+    fn set_sample_count(&mut self, sample_count: SampleCount) {
+        self.sample_count = sample_count;
+
+        // offscreen targets are always single-sampled
+        if self.target.is_none() {
+            self.pipelines.set_sample_count(sample_count);
+        }
+    }
+
+    // keep text sharp when the scene's resolution is scaled
+    fn native_resolution(&self) -> bool {
+        true
+    }
+
+    fn on_device_reset(&mut self, renderer: &Arc<rend3::Renderer>) {
+        self.device = renderer.device.to_owned();
+        self.queue = renderer.queue.to_owned();
+
+        // atlases move themselves over as they're next drawn
+        let device = self.device.clone();
+        self.atlas_device.replace(device, self.queue.clone());
+
+        self.rebuild_pipelines();
+    }
 }
 
 pub struct TerminalNode<'a> {
     pipelines: &'a TerminalPipelines,
-    draws: Vec<&'a TerminalDrawState>,
+    batch: &'a TerminalBatch,
+    clear: Option<wgpu::Color>,
+    target: Option<&'a TerminalTarget>,
+    ime: Option<&'a ImeReporter>,
 }
 
 impl<'a> Node<'a> for TerminalNode<'a> {
     fn draw<'graph>(&'graph self, info: &mut RoutineInfo<'_, 'graph>) {
-        let output = info.graph.add_surface_texture();
+        if let Some(target) = self.target {
+            let output = info.targets.get(&target.target);
+            let resolution = info.targets.size(&target.target);
+            if let (Some(output), Some(resolution)) = (output, resolution) {
+                let vp = target.view_proj;
+                self.pipelines
+                    .add_to_target(self.batch, info.graph, output, resolution, vp);
+            }
+
+            return;
+        }
+
+        let output = info.state.color;
+        let resolve = info.state.resolve;
         let depth = info.state.depth;
-        self.pipelines
-            .add_to_graph(self.draws.as_slice(), info.graph, output, depth);
+        let resolution = info.resolution;
+        self.pipelines.add_to_graph(
+            self.batch, info.graph, output, resolve, depth, resolution, self.clear, self.ime,
+        );
     }
 }
 
 /// Guest-exposed terminal process.
 pub struct TerminalSink {
     inner: Arc<Terminal>,
+
+    /// The maximum size of extracted text replies, in bytes.
+    max_text_bytes: usize,
 }
 
 impl Drop for TerminalSink {
@@ -138,6 +376,93 @@ impl SinkProcess for TerminalSink {
             TerminalUpdate::State(state) => {
                 self.inner.update(state);
             }
+            TerminalUpdate::SetPalette(palette) => {
+                self.inner.set_palette(palette);
+            }
+            TerminalUpdate::SetTheme(name) => {
+                if !self.inner.set_theme(&name) {
+                    warn!("Unknown terminal theme {:?}", name);
+                }
+            }
+            TerminalUpdate::SetOutline(outline) => {
+                self.inner.set_outline(outline);
+            }
+            TerminalUpdate::SetOpacity { background, glyphs } => {
+                self.inner.set_opacity(background, glyphs);
+            }
+            TerminalUpdate::SetDepthMode(depth_mode) => {
+                self.inner.set_depth_mode(depth_mode);
+            }
+            TerminalUpdate::Ime(ime) => {
+                self.inner.on_ime(ime);
+            }
+            TerminalUpdate::SetFocused(focused) => {
+                self.inner.set_focused(focused);
+            }
+            TerminalUpdate::InputBytes(bytes) => {
+                self.inner.send_input_bytes(bytes);
+            }
+            TerminalUpdate::SetChrome(chrome) => {
+                self.inner.set_chrome(chrome);
+            }
+            TerminalUpdate::SetTitle(title) => {
+                self.inner.set_title(title);
+            }
+            TerminalUpdate::Resize { cols, rows, mode } => {
+                let size = UVec2::new(cols as u32, rows as u32);
+                self.inner.set_grid_size(Some(size), mode);
+            }
+            TerminalUpdate::ScrollLines(lines) => {
+                self.inner.scroll(Scroll::Delta(lines));
+            }
+            TerminalUpdate::ScrollToTop => {
+                self.inner.scroll(Scroll::Top);
+            }
+            TerminalUpdate::ScrollToBottom => {
+                self.inner.scroll(Scroll::Bottom);
+            }
+            TerminalUpdate::SetPointer(pointer) => {
+                self.inner.set_pointer(pointer);
+            }
+            TerminalUpdate::GetCellAt { local_point } => {
+                let Some(reply) = request.caps.first() else {
+                    debug!(
+                        "GetCellAt request to {:?} has no reply address",
+                        request.label
+                    );
+                    return;
+                };
+
+                let cell = self.inner.cell_at(local_point);
+                let data = serde_json::to_vec(&cell).unwrap();
+                let _ = reply.send(&data, &[]).await;
+            }
+            TerminalUpdate::GetHotspots => {
+                let Some(reply) = request.caps.first() else {
+                    debug!(
+                        "GetHotspots request to {:?} has no reply address",
+                        request.label
+                    );
+                    return;
+                };
+
+                let hotspots = self.inner.hotspots();
+                let data = serde_json::to_vec(&hotspots).unwrap();
+                let _ = reply.send(&data, &[]).await;
+            }
+            TerminalUpdate::GetText { range } => {
+                let Some(reply) = request.caps.first() else {
+                    debug!(
+                        "GetText request to {:?} has no reply address",
+                        request.label
+                    );
+                    return;
+                };
+
+                let text = self.inner.get_text(range, self.max_text_bytes);
+                let data = serde_json::to_vec(&text).unwrap();
+                let _ = reply.send(&data, &[]).await;
+            }
         }
     }
 }
@@ -145,7 +470,14 @@ impl SinkProcess for TerminalSink {
 /// Guest-exposed service plugin.
 pub struct TerminalFactory {
     fonts: FontSet<Arc<FaceAtlas>>,
+    fallbacks: Vec<Arc<FallbackFace>>,
+    palette: TerminalPalette,
+    max_text_bytes: usize,
+    resize_delay: Duration,
+    allowed_commands: Option<Vec<AllowedCommand>>,
     new_terminals_tx: UnboundedSender<Arc<Terminal>>,
+    new_fonts_tx: UnboundedSender<FontSet<Arc<FaceAtlas>>>,
+    stats: Arc<DrawStats>,
 }
 
 #[async_trait]
@@ -157,23 +489,92 @@ impl RequestResponseProcess for TerminalFactory {
         &'a mut self,
         request: &mut RequestInfo<'a, Self::Request>,
     ) -> ResponseInfo<'a, Self::Response> {
-        let FactoryRequest::CreateTerminal(state) = &request.data;
-
         let config = TerminalConfig {
             fonts: self.fonts.to_owned(),
+            fallbacks: self.fallbacks.to_owned(),
             command: None,
+            palette: self.palette.clone(),
+            grid_size: None,
+            hold: false,
+            resize_delay: self.resize_delay,
+        };
+
+        let (config, state, owner) = match &request.data {
+            FactoryRequest::CreateTerminal(state) => {
+                let allowed = self.allowed_commands.as_deref();
+                let command = match default_command(allowed, ShellCommand::default_shell) {
+                    Ok(command) => command,
+                    Err(err) => return err.into(),
+                };
+
+                (TerminalConfig { command, ..config }, state, None)
+            }
+            FactoryRequest::SpawnShell(spawn) => {
+                if let Err(err) = check_spawn(self.allowed_commands.as_deref(), spawn) {
+                    return err.into();
+                }
+
+                let grid_size = UVec2::new(spawn.cols as u32, spawn.rows as u32);
+
+                let config = TerminalConfig {
+                    command: Some(ShellCommand {
+                        program: spawn.command.clone(),
+                        args: spawn.args.clone(),
+                        env: spawn.env.clone(),
+                    }),
+                    grid_size: (grid_size.min_element() > 0).then_some(grid_size),
+                    hold: true,
+                    ..config
+                };
+
+                let config = match spawn.fonts.as_ref() {
+                    None => config,
+                    Some(fonts) => {
+                        match font::load_fonts(&request.runtime.asset_store, fonts).await {
+                            Ok(fonts) => TerminalConfig { fonts, ..config },
+                            Err(err) => {
+                                return FactoryError::FontError(format!("{:#}", err)).into()
+                            }
+                        }
+                    }
+                };
+
+                let owner = request.cap_args.first().map(|cap| cap.to_owned());
+                (config, &spawn.state, owner)
+            }
+            FactoryRequest::SetFont(fonts) => {
+                let fonts = fonts.clone();
+                return match self.set_font(request.runtime, fonts).await {
+                    Ok(()) => Ok(FactorySuccess::FontReplaced).into(),
+                    Err(err) => FactoryError::FontError(err).into(),
+                };
+            }
+            FactoryRequest::GetStats => {
+                return Ok(FactorySuccess::Stats(self.stats.get())).into();
+            }
+        };
+
+        let terminal = match Terminal::new(config, state.clone()) {
+            Ok(terminal) => terminal,
+            Err(err) => return FactoryError::SpawnFailed(err.to_string()).into(),
         };
 
-        let terminal = Terminal::new(config, state.clone());
         let _ = self.new_terminals_tx.send(terminal.clone());
 
+        if let Some(owner) = owner {
+            report_exit(&terminal, request.runtime, owner);
+        }
+
         // create metadata for the child TerminalSink since it's a sink, not a
         // service, and it doesn't have get_process_metadata()
         let mut meta = cargo_process_metadata!();
         meta.name = Some("TerminalSink".to_string());
         meta.description = Some("An instance of a terminal. Accepts TerminalUpdate.".to_string());
 
-        let sink = TerminalSink { inner: terminal };
+        let sink = TerminalSink {
+            inner: terminal,
+            max_text_bytes: self.max_text_bytes,
+        };
         let child = request.spawn(meta, sink);
 
         ResponseInfo {
@@ -183,6 +584,137 @@ impl RequestResponseProcess for TerminalFactory {
     }
 }
 
+/// Sends [TerminalEvent::ProcessExited] to a terminal's owner once its
+/// process exits.
+fn report_exit(terminal: &Terminal, runtime: &Runtime, owner: OwnedCapability) {
+    let mut exited = terminal.watch_exit();
+    let post = runtime.post.clone();
+
+    tokio::spawn(async move {
+        while !*exited.borrow() {
+            // fails if the terminal is dropped before its process exits
+            if exited.changed().await.is_err() {
+                return;
+            }
+        }
+
+        let table = Table::new(post);
+        let owner = table.import_owned(owner).unwrap();
+        let owner = table.wrap_handle(owner).unwrap();
+        let data = serde_json::to_vec(&TerminalEvent::ProcessExited).unwrap();
+
+        if let Err(err) = owner.send(&data, &[]).await {
+            debug!("Failed to report terminal exit: {:?}", err);
+        }
+    });
+}
+
+/// The environment variables that guests may set with
+/// [FactoryRequest::SpawnShell].
+///
+/// Variables like `LD_PRELOAD`, `PATH`, or `BASH_ENV` change which code a
+/// program loads or runs, so only ones describing the terminal and locale
+/// are allowed.
+const GUEST_ENV_VARS: &[&str] = &[
+    "COLORTERM",
+    "LANG",
+    "LANGUAGE",
+    "LC_ALL",
+    "LC_COLLATE",
+    "LC_CTYPE",
+    "LC_MESSAGES",
+    "LC_MONETARY",
+    "LC_NUMERIC",
+    "LC_TIME",
+    "TERM",
+];
+
+/// A command that guests may run, for
+/// [TerminalPluginConfig::allowed_commands].
+///
+/// A plain string allows running a program without arguments. A table
+/// allows running it with exactly the given arguments.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AllowedCommand {
+    Program(String),
+    WithArgs { program: String, args: Vec<String> },
+}
+
+impl AllowedCommand {
+    /// Returns true if this entry allows running a program with the given
+    /// arguments.
+    pub fn allows(&self, program: &str, args: &[String]) -> bool {
+        match self {
+            AllowedCommand::Program(allowed) => allowed == program && args.is_empty(),
+            AllowedCommand::WithArgs {
+                program: allowed,
+                args: allowed_args,
+            } => allowed == program && allowed_args == args,
+        }
+    }
+}
+
+/// Checks a command against an allowlist of commands, if there is one.
+fn is_allowed(allowed: Option<&[AllowedCommand]>, program: &str, args: &[String]) -> bool {
+    match allowed {
+        Some(allowed) => allowed.iter().any(|allowed| allowed.allows(program, args)),
+        None => true,
+    }
+}
+
+/// Checks the command and environment of a [FactoryRequest::SpawnShell].
+fn check_spawn(allowed: Option<&[AllowedCommand]>, spawn: &SpawnShell) -> Result<(), FactoryError> {
+    if !is_allowed(allowed, &spawn.command, &spawn.args) {
+        return Err(FactoryError::CommandNotAllowed(spawn.command.clone()));
+    }
+
+    let mut keys: Vec<_> = spawn.env.keys().collect();
+    keys.sort();
+
+    match keys
+        .into_iter()
+        .find(|key| !GUEST_ENV_VARS.contains(&key.as_str()))
+    {
+        Some(key) => Err(FactoryError::EnvNotAllowed(key.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Picks the command that [FactoryRequest::CreateTerminal] runs.
+///
+/// The default shell gives as much access as any other command, so with an
+/// allowlist, it's resolved up front and must be allowed too. Without one,
+/// `None` leaves resolving it to the terminal.
+fn default_command(
+    allowed: Option<&[AllowedCommand]>,
+    default_shell: impl FnOnce() -> std::io::Result<ShellCommand>,
+) -> Result<Option<ShellCommand>, FactoryError> {
+    if allowed.is_none() {
+        return Ok(None);
+    }
+
+    let shell = default_shell().map_err(|err| FactoryError::SpawnFailed(err.to_string()))?;
+    if !is_allowed(allowed, &shell.program, &shell.args) {
+        return Err(FactoryError::CommandNotAllowed(shell.program));
+    }
+
+    Ok(Some(shell))
+}
+
+impl TerminalFactory {
+    /// Loads a set of fonts from lumps and gives them to every terminal.
+    async fn set_font(&mut self, runtime: &Runtime, lumps: TerminalFonts) -> Result<(), String> {
+        let fonts = font::load_fonts(&runtime.asset_store, &lumps)
+            .await
+            .map_err(|err| format!("{:#}", err))?;
+
+        self.fonts = fonts.clone();
+        let _ = self.new_fonts_tx.send(fonts);
+        Ok(())
+    }
+}
+
 impl ServiceRunner for TerminalFactory {
     const NAME: &'static str = "hearth.terminal.TerminalFactory";
 
@@ -196,11 +728,98 @@ impl ServiceRunner for TerminalFactory {
     }
 }
 
+/// Config for the terminal plugin, loaded from the `terminal` table.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TerminalPluginConfig {
+    /// The name of a built-in theme to use as the base palette.
+    ///
+    /// See [palette::THEME_NAMES] for the available themes.
+    pub theme: Option<String>,
+
+    /// Individual colors overriding the theme, as `#RRGGBB` hex strings.
+    ///
+    /// Keys are `foreground`, `background`, `cursor`, or one of
+    /// [palette::ANSI_NAMES].
+    pub colors: HashMap<String, String>,
+
+    /// The maximum size of text extracted by [TerminalUpdate::GetText], in
+    /// bytes. Longer text is truncated.
+    pub max_text_bytes: usize,
+
+    /// Paths to font files to search, in order, for glyphs missing from the
+    /// built-in font, such as emoji or CJK characters.
+    pub fallback_fonts: Vec<PathBuf>,
+
+    /// Whether anything blinks. Disabling this keeps blinking cursors
+    /// steadily shown.
+    pub blinking: bool,
+
+    /// How long blinking things stay shown or hidden, in milliseconds.
+    pub blink_interval_ms: u64,
+
+    /// If set, the terminal pass clears the scene to this color, as a
+    /// `#RRGGBB` hex string, and draws terminals in isolation. By default,
+    /// terminals are drawn over the scene.
+    pub clear_color: Option<String>,
+
+    /// The commands that guests may run with [FactoryRequest::SpawnShell],
+    /// matched exactly against the requested program and its arguments. If
+    /// unset, any command may be run.
+    ///
+    /// [FactoryRequest::CreateTerminal] runs the default shell without
+    /// arguments, which must also be listed when this is set.
+    ///
+    /// Whether or not this is set, guests may only set the environment
+    /// variables in [GUEST_ENV_VARS].
+    pub allowed_commands: Option<Vec<AllowedCommand>>,
+
+    /// How long a terminal's grid must stay the same size before its
+    /// process is resized, in milliseconds. Keeps drag-resizing from
+    /// flooding processes with size changes.
+    pub resize_debounce_ms: u64,
+}
+
+impl Default for TerminalPluginConfig {
+    fn default() -> Self {
+        Self {
+            theme: None,
+            colors: HashMap::new(),
+            max_text_bytes: 1024 * 1024,
+            fallback_fonts: Vec::new(),
+            blinking: true,
+            blink_interval_ms: 530,
+            clear_color: None,
+            allowed_commands: None,
+            resize_debounce_ms: 50,
+        }
+    }
+}
+
 #[derive(Default)]
-pub struct TerminalPlugin {}
+pub struct TerminalPlugin {
+    ime_positions: Option<UnboundedSender<Vec2>>,
+}
+
+impl TerminalPlugin {
+    /// Sends the focused terminal's on-screen cursor position, in pixels
+    /// from the top-left corner of the window, to place the host's IME
+    /// candidate window.
+    pub fn with_ime_positions(mut self, tx: UnboundedSender<Vec2>) -> Self {
+        self.ime_positions = Some(tx);
+        self
+    }
+}
 
 impl Plugin for TerminalPlugin {
     fn build(&mut self, builder: &mut RuntimeBuilder) {
+        let config: TerminalPluginConfig = builder.load_config("terminal").unwrap_or_else(|err| {
+            debug!("Using default terminal config: {}", err);
+            TerminalPluginConfig::default()
+        });
+
+        let palette = palette::palette_from_config(config.theme.as_deref(), &config.colors);
+
         let rend3 = builder
             .get_plugin_mut::<Rend3Plugin>()
             .expect("rend3 plugin was not found");
@@ -214,24 +833,172 @@ impl Plugin for TerminalPlugin {
         };
 
         let fonts = ttf_srcs.map(|src| {
-            let face = owned_ttf_parser::OwnedFace::from_vec(src, 0).unwrap();
-
-            let face_atlas = FaceAtlas::new(
-                face,
-                &rend3.renderer.device,
-                rend3.renderer.queue.to_owned(),
-            );
-
-            Arc::new(face_atlas)
+            let queue = rend3.renderer.queue.to_owned();
+            FaceAtlas::from_data(src, rend3.renderer.device.clone(), queue).map(Arc::new)
         });
 
+        let fonts = match fonts.transpose() {
+            Ok(fonts) => fonts,
+            Err(err) => {
+                error!("Failed to load terminal fonts: {}", err);
+                return;
+            }
+        };
+
         let (new_terminals_tx, new_terminals) = unbounded_channel();
+        let (new_fonts_tx, new_fonts) = unbounded_channel();
+
+        let blink_interval = Duration::from_millis(config.blink_interval_ms);
+        let blink = BlinkPhase::new(config.blinking.then_some(blink_interval));
+        let stats = Arc::new(DrawStats::default());
+        let mut routine =
+            TerminalRoutine::new(rend3, new_terminals, new_fonts, blink, stats.clone());
+
+        // fallbacks and loaded fonts follow the routine across device resets
+        let atlas_device = routine.atlas_device().clone();
+        let fallbacks = config
+            .fallback_fonts
+            .iter()
+            .filter_map(|path| {
+                let name = path.display().to_string();
+                let src = std::fs::read(path)
+                    .map_err(|err| warn!("Failed to read fallback font {}: {}", name, err))
+                    .ok()?;
+
+                let fallback = FallbackFace::new(name.clone(), src, atlas_device.clone());
+
+                if fallback.is_none() {
+                    warn!("Failed to parse fallback font {}", name);
+                }
+
+                fallback
+            })
+            .collect();
+
+        if let Some(hex) = config.clear_color.as_ref() {
+            match palette::parse_hex(hex) {
+                Some(color) => routine.set_clear(Some(draw::linear_clear_color(color))),
+                None => warn!("Invalid terminal clear color: {:?}", hex),
+            }
+        }
 
-        rend3.add_routine(TerminalRoutine::new(rend3, new_terminals));
+        if let Some(tx) = self.ime_positions.take() {
+            routine.set_ime_positions(tx);
+        }
+
+        rend3.add_routine(routine);
+        builder.add_asset_loader(FontLoader::new(atlas_device));
 
         builder.add_plugin(TerminalFactory {
             fonts,
+            fallbacks,
+            palette,
+            max_text_bytes: config.max_text_bytes,
+            resize_delay: Duration::from_millis(config.resize_debounce_ms),
+            allowed_commands: config.allowed_commands,
             new_terminals_tx,
+            new_fonts_tx,
+            stats,
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh() -> std::io::Result<ShellCommand> {
+        Ok(ShellCommand::new("/bin/sh"))
+    }
+
+    fn program(program: &str) -> AllowedCommand {
+        AllowedCommand::Program(program.to_string())
+    }
+
+    fn spawn(command: &str, args: &[&str], env: &[&str]) -> SpawnShell {
+        SpawnShell {
+            state: TerminalState {
+                position: Default::default(),
+                orientation: Default::default(),
+                half_size: Default::default(),
+                opacity: 1.0,
+                padding: Default::default(),
+                units_per_em: 1.0,
+                colors: Default::default(),
+            },
+            command: command.to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            env: env
+                .iter()
+                .map(|key| (key.to_string(), "1".to_string()))
+                .collect(),
+            cols: 0,
+            rows: 0,
+            fonts: None,
+        }
+    }
+
+    #[test]
+    fn allowed_args_must_match() {
+        let allowed = vec![
+            program("/usr/bin/htop"),
+            AllowedCommand::WithArgs {
+                program: "/bin/sh".to_string(),
+                args: vec!["-l".to_string()],
+            },
+        ];
+
+        let allowed = Some(allowed.as_slice());
+        assert!(check_spawn(allowed, &spawn("/usr/bin/htop", &[], &[])).is_ok());
+        assert!(check_spawn(allowed, &spawn("/bin/sh", &["-l"], &[])).is_ok());
+
+        for (command, args) in [
+            ("/usr/bin/htop", &["-d", "10"][..]),
+            ("/bin/sh", &[]),
+            ("/bin/sh", &["-c", "id"]),
+        ] {
+            let err = check_spawn(allowed, &spawn(command, args, &[])).unwrap_err();
+            assert!(matches!(err, FactoryError::CommandNotAllowed(_)));
+        }
+    }
+
+    #[test]
+    fn only_safe_env_vars_are_allowed() {
+        let ok = spawn("/bin/sh", &[], &["TERM", "LANG"]);
+        assert!(check_spawn(None, &ok).is_ok());
+
+        for key in ["LD_PRELOAD", "PATH", "BASH_ENV", "-i", "TERM=x"] {
+            let err = check_spawn(None, &spawn("/bin/sh", &[], &[key])).unwrap_err();
+            assert!(matches!(err, FactoryError::EnvNotAllowed(k) if k == key));
+        }
+    }
+
+    #[test]
+    fn allowlist_accepts_plain_strings() {
+        let config: TerminalPluginConfig = toml::from_str(
+            r#"allowed_commands = ["/bin/sh", { program = "htop", args = ["-t"] }]"#,
+        )
+        .unwrap();
+
+        let allowed = config.allowed_commands.unwrap();
+        assert_eq!(allowed[0], program("/bin/sh"));
+        assert!(allowed[1].allows("htop", &["-t".to_string()]));
+    }
+
+    #[test]
+    fn default_shell_is_lazy_without_allowlist() {
+        let command = default_command(None, || panic!("shell resolved")).unwrap();
+        assert!(command.is_none());
+    }
+
+    #[test]
+    fn default_shell_must_be_allowed() {
+        let allowed = vec![program("/usr/bin/htop")];
+        let err = default_command(Some(&allowed), sh).unwrap_err();
+        assert!(matches!(err, FactoryError::CommandNotAllowed(program) if program == "/bin/sh"));
+
+        let allowed = vec![program("/bin/sh")];
+        let command = default_command(Some(&allowed), sh).unwrap().unwrap();
+        assert_eq!(command.program, "/bin/sh");
+    }
+}
@@ -0,0 +1,248 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashMap;
+
+use alacritty_terminal::{
+    ansi::NamedColor,
+    term::color::{Colors, Rgb},
+};
+use hearth_runtime::tracing::warn;
+use hearth_schema::{terminal::TerminalPalette, Color};
+
+/// The config names of the 16 ANSI colors, in palette order.
+pub const ANSI_NAMES: [&str; 16] = [
+    "black",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "bright_black",
+    "bright_red",
+    "bright_green",
+    "bright_yellow",
+    "bright_blue",
+    "bright_magenta",
+    "bright_cyan",
+    "bright_white",
+];
+
+/// The names of the built-in themes, selectable with [named_theme].
+pub const THEME_NAMES: [&str; 3] = ["tomorrow-night", "solarized-dark", "gruvbox-dark"];
+
+/// Helper function to build a complete palette from `0xRRGGBB` values.
+fn theme(foreground: u32, background: u32, cursor: u32, ansi: [u32; 16]) -> TerminalPalette {
+    let c = |rgb: u32| Some(Color(0xff000000 | rgb));
+
+    TerminalPalette {
+        foreground: c(foreground),
+        background: c(background),
+        cursor: c(cursor),
+        ansi: ansi.map(c),
+    }
+}
+
+/// The default palette that unset colors fall back to. Identical to the
+/// "tomorrow-night" theme, which is alacritty's default.
+pub fn default_palette() -> TerminalPalette {
+    theme(
+        0xc5c8c6,
+        0x1d1f21,
+        0xc5c8c6,
+        [
+            0x1d1f21, 0xcc6666, 0xb5bd68, 0xf0c674, 0x81a2be, 0xb294bb, 0x8abeb7, 0xc5c8c6,
+            0x666666, 0xd54e53, 0xb9ca4a, 0xe7c547, 0x7aa6da, 0xc397d8, 0x70c0b1, 0xeaeaea,
+        ],
+    )
+}
+
+/// Looks up a built-in theme by name.
+pub fn named_theme(name: &str) -> Option<TerminalPalette> {
+    match name {
+        "tomorrow-night" => Some(default_palette()),
+        "solarized-dark" => Some(theme(
+            0x839496,
+            0x002b36,
+            0x93a1a1,
+            [
+                0x073642, 0xdc322f, 0x859900, 0xb58900, 0x268bd2, 0xd33682, 0x2aa198, 0xeee8d5,
+                0x002b36, 0xcb4b16, 0x586e75, 0x657b83, 0x839496, 0x6c71c4, 0x93a1a1, 0xfdf6e3,
+            ],
+        )),
+        "gruvbox-dark" => Some(theme(
+            0xebdbb2,
+            0x282828,
+            0xebdbb2,
+            [
+                0x282828, 0xcc241d, 0x98971a, 0xd79921, 0x458588, 0xb16286, 0x689d6a, 0xa89984,
+                0x928374, 0xfb4934, 0xb8bb26, 0xfabd2f, 0x83a598, 0xd3869b, 0x8ec07c, 0xebdbb2,
+            ],
+        )),
+        _ => None,
+    }
+}
+
+/// Parses a `#RRGGBB` hex string into an opaque color.
+pub fn parse_hex(hex: &str) -> Option<Color> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+
+    if digits.len() != 6 {
+        return None;
+    }
+
+    u32::from_str_radix(digits, 16)
+        .ok()
+        .map(|rgb| Color(0xff000000 | rgb))
+}
+
+/// Returns a palette with every unset color in `palette` taken from
+/// `fallback`.
+pub fn merge(palette: &TerminalPalette, fallback: &TerminalPalette) -> TerminalPalette {
+    let mut ansi = palette.ansi;
+    for (color, fallback) in ansi.iter_mut().zip(fallback.ansi) {
+        *color = color.or(fallback);
+    }
+
+    TerminalPalette {
+        foreground: palette.foreground.or(fallback.foreground),
+        background: palette.background.or(fallback.background),
+        cursor: palette.cursor.or(fallback.cursor),
+        ansi,
+    }
+}
+
+/// Resolves the plugin's base palette from a theme name and a table of
+/// individual color overrides, both taken from the config file.
+///
+/// Unknown themes, color names, and malformed colors are logged and ignored.
+pub fn palette_from_config(
+    theme: Option<&str>,
+    colors: &HashMap<String, String>,
+) -> TerminalPalette {
+    let base = match theme.map(|name| (name, named_theme(name))) {
+        None => default_palette(),
+        Some((_, Some(theme))) => theme,
+        Some((name, None)) => {
+            warn!("Unknown terminal theme {:?}; using default", name);
+            default_palette()
+        }
+    };
+
+    let mut palette = TerminalPalette::default();
+
+    for (name, hex) in colors.iter() {
+        let Some(color) = parse_hex(hex) else {
+            warn!("Invalid terminal color {}: {:?}", name, hex);
+            continue;
+        };
+
+        let slot = match name.as_str() {
+            "foreground" => &mut palette.foreground,
+            "background" => &mut palette.background,
+            "cursor" => &mut palette.cursor,
+            name => match ANSI_NAMES.iter().position(|ansi| *ansi == name) {
+                Some(index) => &mut palette.ansi[index],
+                None => {
+                    warn!("Unknown terminal color name {:?}", name);
+                    continue;
+                }
+            },
+        };
+
+        *slot = Some(color);
+    }
+
+    merge(&palette, &base)
+}
+
+/// Looks up a palette color by its alacritty color index.
+pub fn get_color(palette: &TerminalPalette, index: usize) -> Option<Color> {
+    match index {
+        index if index < 16 => palette.ansi[index],
+        index if index == NamedColor::Foreground as usize => palette.foreground,
+        index if index == NamedColor::Background as usize => palette.background,
+        index if index == NamedColor::Cursor as usize => palette.cursor,
+        _ => None,
+    }
+}
+
+/// Converts a [Color] to alacritty's RGB type, discarding alpha.
+pub fn color_to_rgb(color: Color) -> Rgb {
+    let (_a, r, g, b) = color.to_argb();
+    Rgb { r, g, b }
+}
+
+/// Writes a palette's colors into an alacritty color list.
+pub fn apply_palette(palette: &TerminalPalette, colors: &mut Colors) {
+    for (index, color) in palette.ansi.iter().enumerate() {
+        colors[index] = color.map(color_to_rgb);
+    }
+
+    colors[NamedColor::Foreground] = palette.foreground.map(color_to_rgb);
+    colors[NamedColor::Background] = palette.background.map(color_to_rgb);
+    colors[NamedColor::Cursor] = palette.cursor.map(color_to_rgb);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_colors() {
+        assert_eq!(parse_hex("#ff8000"), Some(Color(0xffff8000)));
+        assert_eq!(parse_hex("00ff80"), Some(Color(0xff00ff80)));
+        assert_eq!(parse_hex("#fff"), None);
+        assert_eq!(parse_hex("#gggggg"), None);
+    }
+
+    #[test]
+    fn builtin_themes_are_complete() {
+        for name in THEME_NAMES {
+            let theme = named_theme(name).unwrap();
+            assert_eq!(merge(&TerminalPalette::default(), &theme), theme);
+            assert!(theme.foreground.is_some());
+            assert!(theme.ansi.iter().all(Option::is_some));
+        }
+    }
+
+    #[test]
+    fn config_overrides_theme() {
+        let colors = HashMap::from([
+            ("red".to_string(), "#123456".to_string()),
+            ("cursor".to_string(), "#abcdef".to_string()),
+            ("not_a_color".to_string(), "#000000".to_string()),
+            ("blue".to_string(), "nonsense".to_string()),
+        ]);
+
+        let palette = palette_from_config(Some("gruvbox-dark"), &colors);
+        let gruvbox = named_theme("gruvbox-dark").unwrap();
+        assert_eq!(palette.ansi[1], Some(Color(0xff123456)));
+        assert_eq!(palette.cursor, Some(Color(0xffabcdef)));
+        assert_eq!(palette.ansi[4], gruvbox.ansi[4]);
+        assert_eq!(palette.foreground, gruvbox.foreground);
+    }
+
+    #[test]
+    fn unknown_theme_falls_back() {
+        let palette = palette_from_config(Some("nope"), &HashMap::new());
+        assert_eq!(palette, default_palette());
+    }
+}
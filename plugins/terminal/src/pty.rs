@@ -0,0 +1,377 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    sync::{mpsc::Sender, Arc},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use alacritty_terminal::{
+    ansi::Processor,
+    config::{Config, Program, PtyConfig},
+    event::{Event, EventListener},
+    event_loop::{EventLoop, Msg, State},
+    sync::FairMutex,
+    term::SizeInfo,
+    tty::Pty,
+    Term,
+};
+use glam::UVec2;
+use mio_extras::channel::Sender as MioSender;
+
+use crate::worker::Debouncer;
+
+/// Forwards a terminal's events to a channel.
+pub struct Listener {
+    sender: Sender<Event>,
+}
+
+impl Listener {
+    pub fn new(sender: Sender<Event>) -> Self {
+        Self { sender }
+    }
+}
+
+impl EventListener for Listener {
+    fn send_event(&self, event: Event) {
+        // the receiver is gone once its terminal has been dropped
+        let _ = self.sender.send(event);
+    }
+}
+
+/// A program to run in a pseudoterminal.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShellCommand {
+    /// The program to run, either a path or a name to look up in `PATH`.
+    pub program: String,
+
+    /// The program's arguments.
+    pub args: Vec<String>,
+
+    /// Environment variables set for the program on top of the host's.
+    pub env: HashMap<String, String>,
+}
+
+impl ShellCommand {
+    /// Creates a command that runs a program without arguments.
+    pub fn new(program: impl Into<String>) -> Self {
+        Self {
+            program: program.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Gets the platform's default shell.
+    ///
+    /// Fails if the variable naming the shell isn't set.
+    pub fn default_shell() -> std::io::Result<Self> {
+        let var = if cfg!(windows) { "COMSPEC" } else { "SHELL" };
+
+        match std::env::var(var) {
+            Ok(program) => Ok(Self::new(program)),
+            Err(err) => Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("couldn't get the system shell from `{}`: {}", var, err),
+            )),
+        }
+    }
+
+    /// Converts this command to an alacritty program.
+    ///
+    /// alacritty can't set a child's environment, so on Unix, variables are
+    /// set by running the program through `env`. The variables follow `--`
+    /// so that none of them can be mistaken for an option to `env`.
+    fn to_program(&self) -> Program {
+        if self.env.is_empty() || cfg!(windows) {
+            return Program::WithArgs {
+                program: self.program.clone(),
+                args: self.args.clone(),
+            };
+        }
+
+        let mut env: Vec<_> = self.env.iter().collect();
+        env.sort();
+
+        let args = std::iter::once("--".to_string())
+            .chain(
+                env.into_iter()
+                    .map(|(key, value)| format!("{}={}", key, value)),
+            )
+            .chain(std::iter::once(self.program.clone()))
+            .chain(self.args.iter().cloned())
+            .collect();
+
+        Program::WithArgs {
+            program: "env".to_string(),
+            args,
+        }
+    }
+}
+
+/// Creates alacritty's size info for a grid of cells.
+pub fn size_info(grid_size: UVec2) -> SizeInfo {
+    SizeInfo::new(
+        grid_size.x as f32,
+        grid_size.y as f32,
+        1.0,
+        1.0,
+        0.0,
+        0.0,
+        false,
+    )
+}
+
+/// A child process attached to an alacritty [Term] through a
+/// pseudoterminal.
+///
+/// The child's output is parsed into the term on a background thread.
+/// Events from the term, including [Event::Exit] once the child exits, are
+/// sent to the given listener channel.
+pub struct PtyProcess {
+    term: Arc<FairMutex<Term<Listener>>>,
+    channel: FairMutex<MioSender<Msg>>,
+    resizer: Debouncer<UVec2>,
+    _event_loop: JoinHandle<(EventLoop<Pty, Listener>, State)>,
+}
+
+impl PtyProcess {
+    /// Spawns a command in a new pseudoterminal with a grid of the given
+    /// size.
+    ///
+    /// Resizes are passed on to the child once no new size has been
+    /// requested for `resize_delay`.
+    pub fn spawn(
+        command: &ShellCommand,
+        grid_size: UVec2,
+        resize_delay: Duration,
+        events: Sender<Event>,
+    ) -> std::io::Result<Self> {
+        let size_info = size_info(grid_size);
+
+        let config = Config {
+            pty_config: PtyConfig {
+                shell: Some(command.to_program()),
+                working_directory: None,
+                hold: false,
+            },
+            ..Default::default()
+        };
+
+        // setup environment variables
+        alacritty_terminal::tty::setup_env(&config);
+
+        let term = Term::new(&config, size_info, Listener::new(events.clone()));
+        let term = Arc::new(FairMutex::new(term));
+
+        let pty = alacritty_terminal::tty::new(&config.pty_config, &size_info, None)?;
+        let event_loop = EventLoop::new(term.clone(), Listener::new(events), pty, false, false);
+        let channel = event_loop.channel();
+
+        let resize_channel = channel.clone();
+        let resizer = Debouncer::new("pty resizer", resize_delay, move |grid_size| {
+            let _ = resize_channel.send(Msg::Resize(size_info(grid_size)));
+        });
+
+        Ok(Self {
+            term,
+            channel: FairMutex::new(channel),
+            resizer,
+            _event_loop: event_loop.spawn(),
+        })
+    }
+
+    /// Gets the term that the child's output is parsed into.
+    pub fn term(&self) -> &Arc<FairMutex<Term<Listener>>> {
+        &self.term
+    }
+
+    /// Writes bytes to the child's input.
+    pub fn send_input(&self, bytes: Vec<u8>) {
+        // fails if the child has exited, in which case there's no one to
+        // read the input anyways
+        let _ = self.channel.lock().send(Msg::Input(Cow::Owned(bytes)));
+    }
+
+    /// Resizes both the pseudoterminal and the term.
+    ///
+    /// The term is resized immediately so that it's always drawn at its
+    /// current size. Resizing the pseudoterminal is debounced so that
+    /// continuous resizing doesn't flood the child with size changes, and
+    /// only the last size is passed on.
+    pub fn resize(&self, grid_size: UVec2) {
+        self.term.lock().resize(size_info(grid_size));
+        self.resizer.submit(grid_size);
+    }
+
+    /// Writes text directly to the term, as if the child had output it.
+    ///
+    /// Used to show messages from the host, like exit banners.
+    pub fn write_to_term(&self, text: &str) {
+        write_text(&mut self.term.lock(), text);
+    }
+}
+
+/// Parses text, including escape sequences, into a term.
+fn write_text<T: EventListener>(term: &mut Term<T>, text: &str) {
+    let mut parser = Processor::new();
+    for byte in text.bytes() {
+        parser.advance(term, byte);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::mpsc::channel, time::Instant};
+
+    use alacritty_terminal::{
+        grid::Dimensions,
+        index::{Column, Line},
+    };
+
+    use super::*;
+    use crate::terminal::line_to_string;
+
+    fn screen_text(pty: &PtyProcess) -> String {
+        let term = pty.term().lock();
+        let grid = term.grid();
+        (0..grid.screen_lines() as i32)
+            .map(|line| {
+                let row = &grid[Line(line)];
+                line_to_string((0..grid.columns()).map(|col| &row[Column(col)])).0
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn wait_for(timeout: Duration, mut cond: impl FnMut() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if cond() {
+                return true;
+            }
+
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        false
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn echo_output_reaches_grid() {
+        // keep the child alive briefly so that its output is always read
+        // before its exit is handled
+        let command = ShellCommand {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), "echo hello; sleep 0.5".to_string()],
+            env: HashMap::new(),
+        };
+
+        let (events_tx, events) = channel();
+        let pty =
+            PtyProcess::spawn(&command, UVec2::new(40, 10), Duration::ZERO, events_tx).unwrap();
+
+        let shown = wait_for(Duration::from_secs(5), || {
+            screen_text(&pty).lines().any(|line| line == "hello")
+        });
+
+        assert!(shown, "screen was {:?}", screen_text(&pty));
+
+        let exited = wait_for(Duration::from_secs(5), || {
+            events.try_iter().any(|event| matches!(event, Event::Exit))
+        });
+
+        assert!(exited, "child exit was not reported");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn env_is_passed_to_child() {
+        let command = ShellCommand {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), "echo $GREETING; sleep 0.5".to_string()],
+            env: [("GREETING".to_string(), "howdy".to_string())].into(),
+        };
+
+        let (events_tx, _events) = channel();
+        let pty =
+            PtyProcess::spawn(&command, UVec2::new(40, 10), Duration::ZERO, events_tx).unwrap();
+
+        let shown = wait_for(Duration::from_secs(5), || {
+            screen_text(&pty).lines().any(|line| line == "howdy")
+        });
+
+        assert!(shown, "screen was {:?}", screen_text(&pty));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn resizes_are_debounced() {
+        // prints the size of the pseudoterminal whenever it changes
+        let script = "trap 'stty size' WINCH; echo ready; for i in $(seq 50); do sleep 0.1; done";
+        let command = ShellCommand {
+            program: "sh".to_string(),
+            args: vec!["-c".to_string(), script.to_string()],
+            env: HashMap::new(),
+        };
+
+        let (events_tx, _events) = channel();
+        let delay = Duration::from_millis(100);
+        let pty = PtyProcess::spawn(&command, UVec2::new(40, 10), delay, events_tx).unwrap();
+
+        let ready = wait_for(Duration::from_secs(5), || {
+            screen_text(&pty).lines().any(|line| line == "ready")
+        });
+
+        assert!(ready, "screen was {:?}", screen_text(&pty));
+
+        // the term follows every resize right away
+        for cols in 41..60 {
+            pty.resize(UVec2::new(cols, 10));
+            assert_eq!(pty.term().lock().grid().columns(), cols as usize);
+        }
+
+        // but the child only sees the last size
+        let resized = wait_for(Duration::from_secs(5), || {
+            screen_text(&pty).lines().any(|line| line == "10 59")
+        });
+
+        let screen = screen_text(&pty);
+        assert!(resized, "screen was {:?}", screen);
+        let sizes = screen
+            .lines()
+            .filter(|line| line.starts_with("10 "))
+            .count();
+        assert_eq!(sizes, 1, "screen was {:?}", screen);
+    }
+
+    #[test]
+    fn banner_is_written_to_term() {
+        let (events_tx, _events) = channel();
+        let size_info = size_info(UVec2::new(20, 4));
+        let mut term = Term::new(&Config::default(), size_info, Listener::new(events_tx));
+        write_text(&mut term, "\x1b[7m[exited]\x1b[0m");
+
+        let row = &term.grid()[Line(0)];
+        let (text, _) = line_to_string((0..term.grid().columns()).map(|col| &row[Column(col)]));
+        assert_eq!(text, "[exited]");
+    }
+}
@@ -36,13 +36,22 @@ struct GlyphVertexOut {
     [[builtin(position)]] clip_position: vec4<f32>;
     [[location(0)]] tex_coords: vec2<f32>;
     [[location(1)]] color: vec4<f32>;
+    [[location(2), interpolate(flat)]] terminal: u32;
 };
 
 struct CameraUniform {
     mvp: mat4x4<f32>;
+    outline_color: vec4<f32>;
+    px_range: f32;
+    outline_width: f32;
 };
 
-[[group(0), binding(0)]] var<uniform> camera: CameraUniform;
+// each terminal's camera, indexed by the terminal's instance index
+struct Cameras {
+    cameras: array<CameraUniform>;
+};
+
+[[group(0), binding(0)]] var<storage, read> cameras: Cameras;
 
 [[group(1), binding(0)]] var t_msdf: texture_2d<f32>;
 [[group(1), binding(1)]] var s_msdf: sampler;
@@ -55,7 +64,8 @@ fn srgb_to_linear(l: vec3<f32>) -> vec3<f32> {
 }
 
 [[stage(vertex)]]
-fn solid_vs(in: SolidVertexIn) -> SolidVertexOut {
+fn solid_vs(in: SolidVertexIn, [[builtin(instance_index)]] terminal: u32) -> SolidVertexOut {
+    let camera = cameras.cameras[terminal];
     var out: SolidVertexOut;
     out.clip_position = camera.mvp * vec4<f32>(in.position, 0.0, 1.0);
     out.color = vec4<f32>(srgb_to_linear(in.color.rgb), in.color.a);
@@ -68,30 +78,58 @@ fn solid_fs(frag: SolidVertexOut) -> [[location(0)]] vec4<f32> {
 }
 
 [[stage(vertex)]]
-fn glyph_vs(in: GlyphVertexIn, [[builtin(vertex_index)]] in_vertex_index: u32) -> GlyphVertexOut {
+fn glyph_vs(in: GlyphVertexIn, [[builtin(instance_index)]] terminal: u32) -> GlyphVertexOut {
+    let camera = cameras.cameras[terminal];
     var out: GlyphVertexOut;
     out.clip_position = camera.mvp * vec4<f32>(in.position, 0.0, 1.0);
     out.tex_coords = in.tex_coords;
     out.color = vec4<f32>(srgb_to_linear(in.color.rgb), in.color.a);
+    out.terminal = terminal;
     return out;
 }
 
-fn screen_px_range(tex_coords: vec2<f32>) -> f32 {
-    let msdf_range = 8.0;
-    let unit_range = vec2<f32>(msdf_range) / vec2<f32>(textureDimensions(t_msdf, 0));
-    let screen_tex_size = vec2<f32>(1.0) / fwidth(tex_coords);
-    return max(0.5 * dot(unit_range, screen_tex_size), 1.0);
-}
-
 fn median(r: f32, g: f32, b: f32) -> f32 {
     return max(min(r, g), min(max(r, g), b));
 }
 
 [[stage(fragment)]]
 fn glyph_fs(frag: GlyphVertexOut) -> [[location(0)]] vec4<f32> {
+    let camera = cameras.cameras[frag.terminal];
     let msd = textureSample(t_msdf, s_msdf, frag.tex_coords);
     let sd = median(msd.r, msd.g, msd.b);
-    let dist = screen_px_range(frag.tex_coords) * (sd - 0.5);
-    let alpha = clamp(dist + 0.5, 0.0, 1.0);
-    return vec4<f32>(frag.color.rgb, alpha);
+
+    // the edge is antialiased over one screen pixel
+    let edge_width = 0.5 / camera.px_range;
+    let fill = smoothstep(0.5 - edge_width, 0.5 + edge_width, sd);
+
+    if (camera.outline_width <= 0.0) {
+        return vec4<f32>(frag.color.rgb, fill);
+    }
+
+    let outer = 0.5 - camera.outline_width;
+    let outline = smoothstep(outer - edge_width, outer + edge_width, sd);
+    let outline_color = srgb_to_linear(camera.outline_color.rgb);
+    let color = mix(outline_color, frag.color.rgb, fill);
+    let alpha = max(fill, outline * camera.outline_color.a);
+    return vec4<f32>(color, alpha);
+}
+
+[[stage(fragment)]]
+fn color_glyph_fs(frag: GlyphVertexOut) -> [[location(0)]] vec4<f32> {
+    // color glyphs keep their own colors, but not the vertex color's alpha
+    let color = textureSample(t_msdf, s_msdf, frag.tex_coords);
+    return vec4<f32>(color.rgb, color.a * frag.color.a);
+}
+
+[[stage(vertex)]]
+fn clear_vs([[builtin(vertex_index)]] index: u32) -> [[builtin(position)]] vec4<f32> {
+    // a single triangle covering the whole screen, on the far plane
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+[[stage(fragment)]]
+fn clear_fs() -> [[location(0)]] vec4<f32> {
+    // the color comes from the blend constant
+    return vec4<f32>(1.0);
 }
@@ -18,80 +18,78 @@
 
 use std::{
     sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{channel, Sender},
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc::channel,
         Arc,
     },
-    thread::JoinHandle,
+    time::Duration,
 };
 
 use alacritty_terminal::{
     ansi::{Color, CursorShape, NamedColor},
-    config::PtyConfig,
     event::{Event, EventListener},
-    event_loop::{EventLoop, Msg, State},
-    grid::Indexed,
+    grid::{Dimensions, Scroll},
+    index::{Column, Line, Point},
     sync::FairMutex,
     term::{
         cell::{Cell, Flags},
         color::{Colors, Rgb, COUNT},
-        RenderableContent, RenderableCursor,
     },
-    tty::Pty,
     Term,
 };
 use glam::{vec2, IVec2, Mat4, UVec2, Vec2};
-use hearth_schema::terminal::TerminalState;
-use mio_extras::channel::Sender as MioSender;
+use hearth_runtime::tokio::sync::watch;
+use hearth_schema::{
+    terminal::{
+        CellPosition, TerminalChrome, TerminalDepthMode, TerminalHotspot, TerminalOutline,
+        TerminalPalette, TerminalSizingMode, TerminalState, TerminalText, TextRange,
+    },
+    window::Ime,
+};
 use owned_ttf_parser::AsFaceRef;
+use unicode_width::UnicodeWidthChar;
 
 use crate::{
-    draw::{GlyphVertex, SolidVertex, TerminalDrawState},
-    text::{FaceAtlas, FontSet, FontStyle},
+    color::ColorGlyph,
+    draw::{GlyphVertex, MeshData, SolidVertex, TerminalDrawState},
+    hotspot::{find_hotspots, Hotspot},
+    palette,
+    pty::{PtyProcess, ShellCommand},
+    text::{FaceAtlas, FallbackFace, FontSet, FontStyle},
+    worker::CoalescingWorker,
 };
 
-pub struct Listener {
-    sender: Sender<Event>,
-}
-
-impl Listener {
-    pub fn new(sender: Sender<Event>) -> Self {
-        Self { sender }
-    }
-}
-
-impl EventListener for Listener {
-    fn send_event(&self, event: Event) {
-        self.sender.send(event).unwrap();
-    }
-}
+/// Written to a held terminal once its command exits.
+const EXIT_BANNER: &str = "\r\n\x1b[0;7m[process exited]\x1b[0m\r\n";
 
 /// Configuration for the initialization of a terminal.
 #[derive(Clone)]
 pub struct TerminalConfig {
     pub fonts: FontSet<Arc<FaceAtlas>>,
 
+    /// Faces to search, in order, for glyphs missing from `fonts`.
+    pub fallbacks: Vec<Arc<FallbackFace>>,
+
     /// The command that this terminal will run.
     ///
     /// Defaults to a platform-specific shell.
-    pub command: Option<String>,
-}
+    pub command: Option<ShellCommand>,
 
-impl TerminalConfig {
-    fn unwrap_command(&self) -> String {
-        match self.command.to_owned() {
-            Some(command) => command,
-            None => match std::env::consts::OS {
-                "dragonfly" | "freebsd" | "haiku" | "linux" | "macos" | "netbsd" | "openbsd"
-                | "redox" | "solaris" | "unix" => {
-                    std::env::var("SHELL").expect("Couldn't get system shell: `$SHELL` not set. ")
-                }
-                "windows" => std::env::var("COMSPEC")
-                    .expect("Couldn't get system shell: `%COMSPEC%` not set. "),
-                _ => todo!("OS {} is unrecognized", std::env::consts::OS),
-            },
-        }
-    }
+    /// The base color palette. Colors left unset by a terminal's own palette
+    /// fall back to this one.
+    pub palette: TerminalPalette,
+
+    /// A fixed grid size in cells. If unset, the grid is sized to fit the
+    /// terminal's state.
+    pub grid_size: Option<UVec2>,
+
+    /// If true, the terminal stays open with an exit banner after its
+    /// command exits instead of quitting.
+    pub hold: bool,
+
+    /// How long the grid must stay the same size before the terminal's
+    /// process is resized.
+    pub resize_delay: Duration,
 }
 
 #[derive(Clone)]
@@ -145,90 +143,204 @@ impl From<Arc<FaceAtlas>> for FaceWithMetrics {
     }
 }
 
-/// Private terminal mutable state.
-struct TerminalInner {
-    grid_size: UVec2,
-    state: TerminalState,
-}
-
-/// A CPU-side wrapper around terminal functionality.
-pub struct Terminal {
-    term: Arc<FairMutex<Term<Listener>>>,
-    _term_loop: JoinHandle<(EventLoop<Pty, Listener>, State)>,
-    term_channel: FairMutex<MioSender<Msg>>,
-    should_quit: AtomicBool,
-    inner: FairMutex<TerminalInner>,
+/// A terminal's faces and the cell layout derived from them.
+#[derive(Clone)]
+struct FontLayout {
     fonts: FontSet<FaceWithMetrics>,
     font_baselines: FontSet<f32>,
     cell_size: Vec2,
 }
 
-impl Terminal {
-    pub fn new(config: TerminalConfig, initial_state: TerminalState) -> Arc<Self> {
-        let fonts = config.fonts.clone().map(FaceWithMetrics::from);
+impl FontLayout {
+    fn new(fonts: FontSet<Arc<FaceAtlas>>) -> Self {
+        let fonts = fonts.map(FaceWithMetrics::from);
         let cell_size = Vec2::new(fonts.regular.width, fonts.regular.height);
         let font_baselines = fonts
             .as_ref()
             .map(|font| (cell_size.y - font.height) / 2.0 + font.ascender);
 
-        let available = (initial_state.half_size - initial_state.padding) * 2.0;
-        let grid_size = (available / cell_size / initial_state.units_per_em)
+        Self {
+            fonts,
+            font_baselines,
+            cell_size,
+        }
+    }
+
+    /// Computes the number of cells that fit in a terminal's state.
+    fn grid_size(&self, state: &TerminalState) -> UVec2 {
+        let available = (state.half_size - state.padding) * 2.0;
+        (available / self.cell_size / state.units_per_em)
             .ceil()
-            .as_uvec2();
-
-        let size_info = alacritty_terminal::term::SizeInfo::new(
-            grid_size.x as f32,
-            grid_size.y as f32,
-            1.0,
-            1.0,
-            0.0,
-            0.0,
-            false,
-        );
+            .as_uvec2()
+    }
+}
 
-        let (sender, term_events) = channel();
+/// Adjusts a terminal's state so that a fixed grid of cells fits in it.
+fn fit_to_grid(cell_size: Vec2, state: &mut TerminalState, grid: UVec2, mode: TerminalSizingMode) {
+    let grid_extent = grid.as_vec2() * cell_size;
 
-        let command = config.unwrap_command();
-        let shell = alacritty_terminal::config::Program::Just(command);
+    match mode {
+        TerminalSizingMode::FixedSize => {
+            let available = (state.half_size - state.padding) * 2.0;
+            let scale = available / grid_extent;
+            state.units_per_em = scale.min_element().max(f32::EPSILON);
+        }
+        TerminalSizingMode::Grow => {
+            state.half_size = state.padding + grid_extent * state.units_per_em / 2.0;
+        }
+    }
+}
 
-        let term_config = alacritty_terminal::config::Config {
-            pty_config: PtyConfig {
-                shell: Some(shell),
-                working_directory: None,
-                hold: false,
-            },
-            ..Default::default()
-        };
+/// Maps a point in a terminal's local space to the column and row of the
+/// visible cell under it, if there is one.
+fn local_to_cell(
+    cell_size: Vec2,
+    units_per_em: f32,
+    grid_size: UVec2,
+    local: Vec2,
+) -> Option<UVec2> {
+    let mut pos = local / (cell_size * units_per_em);
+    pos.y = -pos.y;
+
+    let pos = (pos + grid_size.as_vec2() / 2.0).floor();
+    if pos.cmplt(Vec2::ZERO).any() || pos.cmpge(grid_size.as_vec2()).any() {
+        return None;
+    }
 
-        // setup environment variables
-        alacritty_terminal::tty::setup_env(&term_config);
+    Some(pos.as_uvec2())
+}
 
-        let term_listener = Listener::new(sender.clone());
+/// Converts a point in a term's grid to a guest-facing cell position.
+fn to_cell_position(point: Point, history_size: usize) -> CellPosition {
+    CellPosition {
+        line: (point.line.0 + history_size as i32).max(0) as u32,
+        column: point.column.0 as u32,
+    }
+}
 
-        let term = Term::new(&term_config, size_info, term_listener);
-        let term = FairMutex::new(term);
-        let term = Arc::new(term);
+/// The hotspots of a terminal's visible screen.
+struct HotspotCache {
+    /// The content generation and view that the hotspots were found in.
+    key: (u64, usize, usize, usize),
+    hotspots: Arc<Vec<Hotspot>>,
+}
 
-        let pty = alacritty_terminal::tty::new(&term_config.pty_config, &size_info, None).unwrap();
+/// Text being composed with an input method editor.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Preedit {
+    /// The uncommitted text.
+    pub text: String,
 
-        let term_listener = Listener::new(sender);
-        let term_loop = EventLoop::new(term.clone(), term_listener, pty, false, false);
-        let term_channel = term_loop.channel();
+    /// The byte range of the IME's cursor within `text`, if it's shown.
+    pub cursor: Option<(usize, usize)>,
+}
+
+/// Updates a terminal's preedit text with an IME event. Returns the text to
+/// write to the terminal's input, if any was committed.
+fn apply_ime(preedit: &mut Option<Preedit>, ime: Ime) -> Option<String> {
+    match ime {
+        Ime::Enabled => None,
+        Ime::Preedit { text, cursor } => {
+            *preedit = (!text.is_empty()).then_some(Preedit { text, cursor });
+            None
+        }
+        Ime::Commit(text) => {
+            *preedit = None;
+            Some(text)
+        }
+        Ime::Disabled => {
+            *preedit = None;
+            None
+        }
+    }
+}
+
+/// Private terminal mutable state.
+struct TerminalInner {
+    grid_size: UVec2,
+    fixed_grid: Option<UVec2>,
+    sizing_mode: TerminalSizingMode,
+    state: TerminalState,
+    palette: TerminalPalette,
+    outline: Option<TerminalOutline>,
+    glyph_opacity: f32,
+    depth_mode: TerminalDepthMode,
+    layout: FontLayout,
+    preedit: Option<Preedit>,
+    focused: Option<bool>,
+    pointer: Option<Vec2>,
+    chrome: TerminalChrome,
+}
+
+/// A CPU-side wrapper around terminal functionality.
+pub struct Terminal {
+    pty: PtyProcess,
+    should_quit: AtomicBool,
+    hold: bool,
+    exited: watch::Sender<bool>,
+    content_generation: AtomicU64,
+    hotspots: FairMutex<Option<HotspotCache>>,
+    inner: FairMutex<TerminalInner>,
+    fallbacks: Vec<Arc<FallbackFace>>,
+    base_palette: TerminalPalette,
+    mesher: CoalescingWorker<MeshJob, TerminalDrawState>,
+}
+
+impl Terminal {
+    /// Spawns this terminal's command and creates the terminal around it.
+    pub fn new(
+        config: TerminalConfig,
+        mut initial_state: TerminalState,
+    ) -> std::io::Result<Arc<Self>> {
+        let layout = FontLayout::new(config.fonts.clone());
+        let sizing_mode = TerminalSizingMode::default();
+        let grid_size = match config.grid_size {
+            Some(grid_size) => {
+                fit_to_grid(layout.cell_size, &mut initial_state, grid_size, sizing_mode);
+                grid_size
+            }
+            None => layout.grid_size(&initial_state),
+        };
+
+        let (sender, term_events) = channel();
+        let command = match config.command.clone() {
+            Some(command) => command,
+            None => ShellCommand::default_shell()?,
+        };
+        let pty = PtyProcess::spawn(&command, grid_size, config.resize_delay, sender)?;
 
         let inner = TerminalInner {
             grid_size,
+            fixed_grid: config.grid_size,
+            sizing_mode,
             state: initial_state,
+            palette: config.palette.clone(),
+            outline: None,
+            glyph_opacity: 1.0,
+            depth_mode: TerminalDepthMode::default(),
+            layout,
+            preedit: None,
+            focused: None,
+            pointer: None,
+            chrome: TerminalChrome::default(),
         };
 
+        let fallbacks = config.fallbacks.clone();
+        let mesher = CoalescingWorker::new("terminal mesher", move |job: MeshJob| {
+            job.build(fallbacks.clone())
+        });
+
         let term = Self {
-            fonts,
-            term,
-            _term_loop: term_loop.spawn(),
-            term_channel: FairMutex::new(term_channel),
+            fallbacks: config.fallbacks,
+            mesher,
+            pty,
             should_quit: AtomicBool::new(false),
+            hold: config.hold,
+            exited: watch::channel(false).0,
+            content_generation: AtomicU64::new(0),
+            hotspots: FairMutex::new(None),
             inner: FairMutex::new(inner),
-            cell_size,
-            font_baselines,
+            base_palette: config.palette,
         };
 
         let term = Arc::new(term);
@@ -240,66 +352,308 @@ impl Terminal {
             }
         });
 
-        term
+        Ok(term)
     }
 
     pub fn get_fonts(&self) -> FontSet<Arc<FaceAtlas>> {
-        self.fonts.as_ref().map(|font| font.atlas.to_owned())
+        let inner = self.inner.lock();
+        inner
+            .layout
+            .fonts
+            .as_ref()
+            .map(|font| font.atlas.to_owned())
+    }
+
+    /// Replaces this terminal's fonts, resizing its grid to fit the new cell
+    /// size.
+    pub fn set_fonts(&self, fonts: FontSet<Arc<FaceAtlas>>) {
+        let mut inner = self.inner.lock();
+        inner.layout = FontLayout::new(fonts);
+        self.resize(&mut inner);
+    }
+
+    pub fn get_fallbacks(&self) -> &[Arc<FallbackFace>] {
+        &self.fallbacks
     }
 
     pub fn update(&self, state: TerminalState) {
         let mut inner = self.inner.lock();
+        inner.state = state;
+        self.resize(&mut inner);
+    }
 
-        let available = (state.half_size - state.padding) * 2.0;
-        let grid_size = (available / self.cell_size / state.units_per_em)
-            .ceil()
-            .as_uvec2();
+    /// Resizes the terminal's grid to fit its current state and fonts, or
+    /// fits its state to its fixed grid size.
+    fn resize(&self, inner: &mut TerminalInner) {
+        let grid_size = match inner.fixed_grid {
+            Some(grid_size) => {
+                let cell_size = inner.layout.cell_size;
+                fit_to_grid(cell_size, &mut inner.state, grid_size, inner.sizing_mode);
+                grid_size
+            }
+            None => inner.layout.grid_size(&inner.state),
+        };
 
         if inner.grid_size != grid_size {
             inner.grid_size = grid_size;
+            self.pty.resize(grid_size);
+        }
+    }
 
-            let size_info = alacritty_terminal::term::SizeInfo::new(
-                grid_size.x as f32,
-                grid_size.y as f32,
-                1.0,
-                1.0,
-                0.0,
-                0.0,
-                false,
-            );
+    /// Fixes this terminal's grid to a size in cells, or sizes it to fit
+    /// its state again if `None`.
+    ///
+    /// While the grid is fixed, `mode` decides how the terminal's quad fits
+    /// it. The process is resized once the size stops changing.
+    pub fn set_grid_size(&self, grid_size: Option<UVec2>, mode: TerminalSizingMode) {
+        let mut inner = self.inner.lock();
+        inner.fixed_grid = grid_size.map(|size| size.max(UVec2::ONE));
+        inner.sizing_mode = mode;
+        self.resize(&mut inner);
+    }
+
+    /// Scrolls this terminal's view through its scrollback history.
+    ///
+    /// The display offset is clamped to the length of the history.
+    pub fn scroll(&self, scroll: Scroll) {
+        self.pty.term().lock().scroll_display(scroll);
+    }
+
+    /// Extracts the text of a range of lines, stopping once the text would
+    /// exceed `max_bytes`.
+    pub fn get_text(&self, range: TextRange, max_bytes: usize) -> TerminalText {
+        let term = self.pty.term().lock();
+        let grid = term.grid();
+        let history = grid.history_size() as i64;
+        let screen = grid.screen_lines() as i64;
+        let offset = grid.display_offset() as i64;
+
+        // convert the range to alacritty's line numbering, where 0 is the top
+        // of the live screen and scrollback lines are negative
+        let (start, end) = match range {
+            TextRange::Screen => (-offset, screen - offset),
+            TextRange::Scrollback => (-history, screen),
+            TextRange::Lines { start, end } => (start as i64 - history, end as i64 - history),
+        };
 
-            self.term_channel
-                .lock()
-                .send(Msg::Resize(size_info))
-                .unwrap();
+        let start = start.clamp(-history, screen);
+        let end = end.clamp(start, screen);
 
-            self.term.lock().resize(size_info);
+        let mut text = String::new();
+        let mut truncated = false;
+        for line in start..end {
+            let row = &grid[Line(line as i32)];
+            let cells = (0..grid.columns()).map(|col| &row[Column(col)]);
+            let (mut line_text, wrapped) = line_to_string(cells);
+
+            // soft-wrapped lines continue onto the next line
+            if !wrapped && line + 1 < end {
+                line_text.push('\n');
+            }
+
+            if !append_capped(&mut text, &line_text, max_bytes) {
+                truncated = true;
+                break;
+            }
         }
 
-        inner.state = state;
+        TerminalText { text, truncated }
+    }
+
+    /// Replaces this terminal's palette. Unset colors fall back to the base
+    /// palette from the [TerminalConfig].
+    pub fn set_palette(&self, palette: TerminalPalette) {
+        self.inner.lock().palette = palette::merge(&palette, &self.base_palette);
     }
 
-    pub fn update_draw_state(&self, draw: &mut TerminalDrawState) {
+    /// Replaces this terminal's palette with a built-in theme. Returns false
+    /// if the theme does not exist.
+    pub fn set_theme(&self, name: &str) -> bool {
+        let Some(theme) = palette::named_theme(name) else {
+            return false;
+        };
+
+        self.inner.lock().palette = theme;
+        true
+    }
+
+    /// Sets or clears the outline drawn around this terminal's glyphs.
+    pub fn set_outline(&self, outline: Option<TerminalOutline>) {
+        self.inner.lock().outline = outline;
+    }
+
+    /// Sets how this terminal is depth-tested.
+    pub fn set_depth_mode(&self, depth_mode: TerminalDepthMode) {
+        self.inner.lock().depth_mode = depth_mode;
+    }
+
+    /// Sets the opacity of this terminal's default background and of its
+    /// glyphs. Both are clamped to 0.0 to 1.0.
+    pub fn set_opacity(&self, background: f32, glyphs: f32) {
+        let mut inner = self.inner.lock();
+        inner.state.opacity = background.clamp(0.0, 1.0);
+        inner.glyph_opacity = glyphs.clamp(0.0, 1.0);
+    }
+
+    /// Handles an input method editor event. Commits are written to the
+    /// terminal's input and preedit text is kept to be drawn at the cursor.
+    pub fn on_ime(&self, ime: Ime) {
+        let mut inner = self.inner.lock();
+        let committed = apply_ime(&mut inner.preedit, ime);
+        drop(inner); // get off the mutex
+
+        if let Some(text) = committed {
+            self.send_input(&text);
+        }
+    }
+
+    /// Sets whether this terminal has input focus. The focused terminal's
+    /// cursor position is reported in its draw state for IME placement.
+    ///
+    /// Until this is first called, the terminal's focus is unknown and its
+    /// cursor is drawn as-is. Afterwards, a block cursor is drawn hollow
+    /// while the terminal is unfocused.
+    pub fn set_focused(&self, focused: bool) {
+        self.inner.lock().focused = Some(focused);
+    }
+
+    /// Replaces the decorations drawn around this terminal.
+    pub fn set_chrome(&self, chrome: TerminalChrome) {
+        self.inner.lock().chrome = chrome;
+    }
+
+    /// Sets the title drawn in this terminal's title bar.
+    pub fn set_title(&self, title: String) {
+        self.inner.lock().chrome.title = title;
+    }
+
+    /// Moves the pointer over this terminal, in local space, or takes it
+    /// off. The hotspot under the pointer is underlined.
+    pub fn set_pointer(&self, pointer: Option<Vec2>) {
+        self.inner.lock().pointer = pointer;
+    }
+
+    /// Finds the visible cell at a point in this terminal's local space.
+    pub fn cell_at(&self, local: Vec2) -> Option<CellPosition> {
         let inner = self.inner.lock();
-        let grid_size = inner.grid_size;
-        let state = inner.state.clone();
+        let cell_size = inner.layout.cell_size;
+        let units_per_em = inner.state.units_per_em;
+        let cell = local_to_cell(cell_size, units_per_em, inner.grid_size, local)?;
         drop(inner); // get off the mutex
 
-        let font_baselines = self.font_baselines.clone();
-        let mut canvas = TerminalCanvas::new(
-            self.fonts.clone(),
-            state,
-            grid_size,
-            self.cell_size,
-            font_baselines,
-        );
+        // the term's grid may lag behind while a resize is debounced
+        let term = self.pty.term().lock();
+        let grid = term.grid();
+        if cell.x as usize >= grid.columns() || cell.y as usize >= grid.screen_lines() {
+            return None;
+        }
 
-        let term = self.term.lock();
-        let content = term.renderable_content();
-        canvas.update_from_content(content);
-        drop(term); // get off the mutex
+        let line = Line(cell.y as i32 - grid.display_offset() as i32);
+        let point = Point::new(line, Column(cell.x as usize));
+        Some(to_cell_position(point, grid.history_size()))
+    }
+
+    /// Gets the URLs and paths on the visible screen.
+    pub fn hotspots(&self) -> Vec<TerminalHotspot> {
+        let (hotspots, history_size) = self.screen_hotspots();
+        hotspots
+            .iter()
+            .map(|hotspot| TerminalHotspot {
+                start: to_cell_position(hotspot.start, history_size),
+                end: to_cell_position(hotspot.end, history_size),
+                text: hotspot.text.clone(),
+                kind: hotspot.kind,
+            })
+            .collect()
+    }
+
+    /// Gets the hotspots of the visible screen along with the history size
+    /// they were found with.
+    ///
+    /// Hotspots are only searched for again once the terminal's content or
+    /// view has changed.
+    fn screen_hotspots(&self) -> (Arc<Vec<Hotspot>>, usize) {
+        let term = self.pty.term().lock();
+        let grid = term.grid();
+        let offset = grid.display_offset();
+        let screen = grid.screen_lines();
+        let history_size = grid.history_size();
+        let generation = self.content_generation.load(Ordering::Acquire);
+        let key = (generation, offset, screen, grid.columns());
+
+        let mut cache = self.hotspots.lock();
+        if let Some(cache) = cache.as_ref().filter(|cache| cache.key == key) {
+            return (cache.hotspots.clone(), history_size);
+        }
+
+        let start = -(offset as i32);
+        let lines = (start..start + screen as i32).map(|line| {
+            let row = &grid[Line(line)];
+            let cells = (0..grid.columns()).map(move |col| &row[Column(col)]);
+            (Line(line), cells)
+        });
+
+        let hotspots = Arc::new(find_hotspots(lines));
+        *cache = Some(HotspotCache {
+            key,
+            hotspots: hotspots.clone(),
+        });
+
+        (hotspots, history_size)
+    }
+
+    /// Snapshots this terminal's current contents and queues a new draw
+    /// state to be built from them in the background.
+    ///
+    /// The snapshot only briefly locks the terminal. If an older snapshot
+    /// is still waiting to be built, it's replaced by this one.
+    ///
+    /// `blink_on` is the shared blink phase. Blinking cursors are hidden
+    /// while it's off.
+    pub fn request_draw_state(&self, blink_on: bool) {
+        let mut cells = CellBuffer::from_term(&self.pty.term().lock());
+
+        let inner = self.inner.lock();
+        let hovered = inner.pointer.and_then(|local| {
+            let units_per_em = inner.state.units_per_em;
+            local_to_cell(inner.layout.cell_size, units_per_em, inner.grid_size, local)
+        });
+
+        if let Some(cell) = hovered {
+            let line = Line(cell.y as i32 - cells.display_offset as i32);
+            let point = Point::new(line, Column(cell.x as usize));
+            let (hotspots, _) = self.screen_hotspots();
+            if let Some(hotspot) = hotspots.iter().find(|hotspot| hotspot.contains(point)) {
+                cells
+                    .cells
+                    .iter_mut()
+                    .filter(|cell| hotspot.contains(cell.point))
+                    .for_each(|cell| cell.flags.insert(Flags::UNDERLINE));
+            }
+        }
 
-        canvas.apply_to_state(draw);
+        let job = MeshJob {
+            cells,
+            layout: inner.layout.clone(),
+            state: inner.state.clone(),
+            palette: inner.palette.clone(),
+            grid_size: inner.grid_size,
+            outline: inner.outline,
+            depth_mode: inner.depth_mode,
+            glyph_opacity: inner.glyph_opacity,
+            preedit: inner.preedit.clone(),
+            focused: inner.focused,
+            chrome: inner.chrome.clone(),
+            blink_on,
+        };
+        drop(inner); // get off the mutex
+
+        self.mesher.submit(job);
+    }
+
+    /// Takes the newest draw state built since the last call, if any.
+    pub fn take_draw_state(&self) -> Option<TerminalDrawState> {
+        self.mesher.take()
     }
 
     pub fn quit(&self) {
@@ -311,63 +665,219 @@ impl Terminal {
     }
 
     pub fn send_input(&self, input: &str) {
-        let bytes = input.as_bytes();
-        let cow = std::borrow::Cow::Owned(bytes.to_owned());
-        self.term_channel.lock().send(Msg::Input(cow)).unwrap();
+        self.pty.send_input(input.as_bytes().to_vec());
+    }
+
+    /// Writes raw bytes to this terminal's input.
+    pub fn send_input_bytes(&self, bytes: Vec<u8>) {
+        self.pty.send_input(bytes);
+    }
+
+    /// Returns true once this terminal's command has exited.
+    pub fn has_exited(&self) -> bool {
+        *self.exited.borrow()
+    }
+
+    /// Subscribes to whether this terminal's command has exited.
+    pub fn watch_exit(&self) -> watch::Receiver<bool> {
+        self.exited.subscribe()
     }
 
     fn on_event(&self, event: Event) {
         match event {
             Event::ColorRequest(index, format) => {
-                let color = self
-                    .inner
-                    .lock()
+                let inner = self.inner.lock();
+                let color = inner
                     .state
                     .colors
                     .get(&index)
-                    .map(|color| {
-                        let (_, r, g, b) = color.to_argb();
-                        Rgb { r, g, b }
-                    })
+                    .copied()
+                    .or_else(|| palette::get_color(&inner.palette, index))
+                    .map(palette::color_to_rgb)
                     .unwrap_or(Rgb {
                         r: 0xff,
                         g: 0xff,
                         b: 0xff,
                     });
 
+                drop(inner); // get off the mutex
                 self.send_input(&format(color));
             }
             Event::PtyWrite(text) => self.send_input(&text),
-            Event::Exit => self.should_quit.store(true, Ordering::Relaxed),
+            Event::Wakeup => {
+                self.content_generation.fetch_add(1, Ordering::Release);
+            }
+            Event::Exit => {
+                if self.hold {
+                    self.pty.write_to_term(EXIT_BANNER);
+                } else {
+                    self.should_quit.store(true, Ordering::Relaxed);
+                }
+
+                self.exited.send_replace(true);
+            }
             _ => {}
         }
     }
 }
 
+/// A cell copied out of a terminal's grid.
+#[derive(Clone, Copy, Debug)]
+pub struct BufferedCell {
+    pub point: Point,
+    pub c: char,
+    pub fg: Color,
+    pub bg: Color,
+    pub flags: Flags,
+}
+
+/// A plain copy of the parts of a terminal's content that are drawn.
+///
+/// Copying the content is much quicker than building meshes from it, so
+/// snapshots keep the terminal locked for as little time as possible.
+#[derive(Clone, Debug)]
+pub struct CellBuffer {
+    /// The visible cells.
+    pub cells: Vec<BufferedCell>,
+
+    /// Colors that the program running in the terminal has changed.
+    pub colors: Vec<Option<Rgb>>,
+
+    pub cursor_shape: CursorShape,
+    pub cursor_point: Point,
+    pub cursor_blinking: bool,
+    pub display_offset: usize,
+    pub history_size: usize,
+}
+
+impl CellBuffer {
+    /// Copies the renderable content of a terminal.
+    pub fn from_term<T: EventListener>(term: &Term<T>) -> Self {
+        let cursor_blinking = term.cursor_style().blinking;
+        let history_size = term.grid().history_size();
+        let content = term.renderable_content();
+
+        let cells = content
+            .display_iter
+            .map(|cell| BufferedCell {
+                point: cell.point,
+                c: cell.c,
+                fg: cell.fg,
+                bg: cell.bg,
+                flags: cell.flags,
+            })
+            .collect();
+
+        Self {
+            cells,
+            colors: (0..COUNT).map(|index| content.colors[index]).collect(),
+            cursor_shape: content.cursor.shape,
+            cursor_point: content.cursor.point,
+            cursor_blinking,
+            display_offset: content.display_offset,
+            history_size,
+        }
+    }
+}
+
+/// A snapshot of everything needed to build a terminal's draw state.
+struct MeshJob {
+    cells: CellBuffer,
+    layout: FontLayout,
+    state: TerminalState,
+    palette: TerminalPalette,
+    grid_size: UVec2,
+    outline: Option<TerminalOutline>,
+    depth_mode: TerminalDepthMode,
+    glyph_opacity: f32,
+    preedit: Option<Preedit>,
+    focused: Option<bool>,
+    chrome: TerminalChrome,
+    blink_on: bool,
+}
+
+impl MeshJob {
+    /// Builds the draw state for this snapshot.
+    fn build(self, fallbacks: Vec<Arc<FallbackFace>>) -> TerminalDrawState {
+        let mut canvas = TerminalCanvas::new(
+            self.layout.fonts,
+            fallbacks,
+            self.state,
+            &self.palette,
+            self.grid_size,
+            self.layout.cell_size,
+            self.layout.font_baselines,
+            self.glyph_opacity,
+        );
+
+        canvas.cursor_visible = self.blink_on || !self.cells.cursor_blinking;
+        canvas.preedit = self.preedit;
+        canvas.focused = self.focused;
+        canvas.update_from_cells(&self.cells);
+        canvas.draw_scrollbar(self.cells.display_offset, self.cells.history_size);
+        canvas.draw_chrome(&self.chrome);
+
+        let mut draw = TerminalDrawState::default();
+        canvas.apply_to_state(&mut draw);
+        draw.outline = self.outline;
+        draw.depth_mode = self.depth_mode;
+        draw
+    }
+}
+
+/// The face that a glyph is drawn from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GlyphFace {
+    /// One of the terminal's primary faces.
+    Style(FontStyle),
+
+    /// A fallback face, by index into the terminal's fallbacks.
+    Fallback(usize),
+
+    /// A color glyph from a fallback face, drawn as an image spanning
+    /// `columns` cells.
+    Color { fallback: usize, columns: u32 },
+}
+
 /// An in-progress terminal draw state.
 pub struct TerminalCanvas {
     fonts: FontSet<FaceWithMetrics>,
+    fallbacks: Vec<Arc<FallbackFace>>,
     bg_vertices: Vec<SolidVertex>,
     bg_indices: Vec<u32>,
     overlay_vertices: Vec<SolidVertex>,
     overlay_indices: Vec<u32>,
-    glyphs: Vec<(Vec2, FontStyle, u16, u32)>,
+    chrome_vertices: Vec<SolidVertex>,
+    chrome_indices: Vec<u32>,
+    glyphs: Vec<(Vec2, GlyphFace, u16, u32)>,
     state: TerminalState,
     colors: Colors,
     grid_size: UVec2,
     cell_size: Vec2,
     font_baselines: FontSet<f32>,
+    glyph_opacity: f32,
+    cursor_visible: bool,
+    display_offset: i32,
+    preedit: Option<Preedit>,
+
+    /// Whether the terminal has input focus, or `None` if it's unknown.
+    focused: Option<bool>,
+    ime_anchor: Option<Vec2>,
 }
 
 impl TerminalCanvas {
     pub fn new(
         fonts: FontSet<FaceWithMetrics>,
+        fallbacks: Vec<Arc<FallbackFace>>,
         state: TerminalState,
+        palette: &TerminalPalette,
         grid_size: UVec2,
         cell_size: Vec2,
         font_baselines: FontSet<f32>,
+        glyph_opacity: f32,
     ) -> Self {
         let mut colors = Colors::default();
+        palette::apply_palette(palette, &mut colors);
 
         for (index, color) in state.colors.iter() {
             let (_a, r, g, b) = color.to_argb();
@@ -376,60 +886,164 @@ impl TerminalCanvas {
 
         Self {
             fonts,
+            fallbacks,
             bg_vertices: Vec::new(),
             bg_indices: Vec::new(),
             overlay_vertices: Vec::new(),
             overlay_indices: Vec::new(),
+            chrome_vertices: Vec::new(),
+            chrome_indices: Vec::new(),
             glyphs: Vec::new(),
             state,
             colors,
             grid_size,
             cell_size,
             font_baselines,
+            glyph_opacity,
+            cursor_visible: true,
+            display_offset: 0,
+            preedit: None,
+            focused: None,
+            ime_anchor: None,
         }
     }
 
-    pub fn update_from_content(&mut self, content: RenderableContent) {
+    pub fn update_from_cells(&mut self, cells: &CellBuffer) {
         self.draw_padding();
+        self.display_offset = cells.display_offset as i32;
+
+        for (index, color) in cells.colors.iter().enumerate() {
+            if let Some(color) = color {
+                self.colors[index] = Some(*color);
+            }
+        }
+
+        // preedit text is drawn in place of the cells it covers
+        let preedit = self.preedit.take();
+        let covered = preedit
+            .as_ref()
+            .map(|preedit| self.preedit_columns(preedit, cells.cursor_point));
+
+        for cell in cells.cells.iter() {
+            let is_covered = covered.as_ref().map_or(false, |columns| {
+                cell.point.line == cells.cursor_point.line
+                    && columns.contains(&(cell.point.column.0 as i32))
+            });
+
+            if !is_covered {
+                self.draw_cell(cell);
+            }
+        }
 
-        for index in 0..COUNT {
-            if let Some(color) = content.colors[index] {
-                self.colors[index] = Some(color);
+        match preedit.as_ref() {
+            Some(preedit) => self.draw_preedit(preedit, cells.cursor_point),
+            None if self.cursor_visible => {
+                let shape = match cells.cursor_shape {
+                    CursorShape::Block if self.focused == Some(false) => CursorShape::HollowBlock,
+                    shape => shape,
+                };
+
+                self.draw_cursor(shape, cells.cursor_point);
             }
+            None => {}
         }
 
-        for cell in content.display_iter {
-            self.draw_cell(cell);
+        if self.focused == Some(true) {
+            // IME candidate windows are placed below the cursor
+            let col = cells.cursor_point.column.0 as i32;
+            let row = cells.cursor_point.line.0 + self.display_offset;
+            self.ime_anchor = Some(self.grid_to_pos(col, row + 1));
         }
 
-        self.draw_cursor(content.cursor);
+        self.preedit = preedit;
     }
 
-    pub fn apply_to_state(&self, state: &mut TerminalDrawState) {
+    pub fn apply_to_state(self, state: &mut TerminalDrawState) {
         let mut touched = FontSet::<Vec<u16>>::default();
-        let mut glyph_meshes = FontSet::<(Vec<GlyphVertex>, Vec<u32>)>::default();
+        let mut glyph_meshes = FontSet::<MeshData<GlyphVertex>>::default();
+
+        let fallback_atlases: Vec<_> = self
+            .fallbacks
+            .iter()
+            .map(|fallback| fallback.get_loaded_atlas())
+            .collect();
+
+        let mut fallback_touched = vec![Vec::<u16>::new(); self.fallbacks.len()];
+        let mut fallback_meshes = vec![MeshData::<GlyphVertex>::default(); self.fallbacks.len()];
+        let mut color_atlases = vec![None; self.fallbacks.len()];
+        let mut color_meshes = vec![MeshData::<GlyphVertex>::default(); self.fallbacks.len()];
+
+        // drawn in place of color glyphs that can't be rasterized
+        let replacement = self
+            .fonts
+            .regular
+            .atlas
+            .face
+            .as_face_ref()
+            .glyph_index(char::REPLACEMENT_CHARACTER)
+            .map(|id| id.0);
+
+        for (offset, face, glyph, color) in self.glyphs.iter().copied() {
+            // fallback glyphs are aligned to the regular face's baseline
+            let (atlas, mesh, touched_glyphs, style, glyph) = match face {
+                GlyphFace::Style(style) => (
+                    &self.fonts.get(style).atlas,
+                    glyph_meshes.get_mut(style),
+                    touched.get_mut(style),
+                    style,
+                    glyph,
+                ),
+                GlyphFace::Fallback(index) => match fallback_atlases[index].as_ref() {
+                    Some(atlas) => (
+                        atlas,
+                        &mut fallback_meshes[index],
+                        &mut fallback_touched[index],
+                        FontStyle::Regular,
+                        glyph,
+                    ),
+                    None => continue,
+                },
+                GlyphFace::Color { fallback, columns } => {
+                    match self.fallbacks[fallback].get_color_glyph(glyph) {
+                        Some((atlas, color_glyph)) => {
+                            let mesh = &mut color_meshes[fallback];
+                            self.push_color_glyph(mesh, offset, columns, color_glyph, color);
+                            color_atlases[fallback] = Some(atlas);
+                            continue;
+                        }
+                        None => match replacement {
+                            Some(replacement) => (
+                                &self.fonts.regular.atlas,
+                                glyph_meshes.get_mut(FontStyle::Regular),
+                                touched.get_mut(FontStyle::Regular),
+                                FontStyle::Regular,
+                                replacement,
+                            ),
+                            None => continue,
+                        },
+                    }
+                }
+            };
 
-        for (offset, style, glyph, color) in self.glyphs.iter().copied() {
-            let (vertices, indices) = &mut glyph_meshes.get_mut(style);
             let baseline = *self.font_baselines.get(style) * self.state.units_per_em;
             let offset = offset + Vec2::new(0.0, -baseline);
 
-            let index = vertices.len() as u32;
-            let atlas = &self.fonts.get(style).atlas.atlas;
-            let bitmap = match atlas.glyphs[glyph as usize].as_ref() {
+            let index = mesh.vertices.len() as u32;
+            let bitmap = match atlas.atlas.glyphs[glyph as usize].as_ref() {
                 Some(b) => b,
                 None => continue,
             };
 
-            touched.get_mut(style).push(glyph);
+            touched_glyphs.push(glyph);
 
-            vertices.extend(bitmap.vertices.iter().map(|v| GlyphVertex {
-                position: v.position * self.state.units_per_em + offset,
-                tex_coords: v.tex_coords,
-                color,
-            }));
+            mesh.vertices
+                .extend(bitmap.vertices.iter().map(|v| GlyphVertex {
+                    position: v.position * self.state.units_per_em + offset,
+                    tex_coords: v.tex_coords,
+                    color,
+                }));
 
-            indices.extend_from_slice(&[
+            mesh.indices.extend_from_slice(&[
                 index,
                 index + 1,
                 index + 2,
@@ -439,40 +1053,82 @@ impl TerminalCanvas {
             ]);
         }
 
+        // each atlas places its glyphs in one batch so that glyphs used by
+        // this frame can't evict each other
+        let glyph_meshes = self.fonts.as_ref().zip(touched.zip(glyph_meshes)).map(
+            |(font, (touched, mut mesh))| {
+                let generation = font.atlas.place(&touched, &mut mesh.vertices);
+                (generation, mesh)
+            },
+        );
+
+        let fallback_meshes: Vec<_> = fallback_atlases
+            .iter()
+            .zip(fallback_touched)
+            .zip(fallback_meshes)
+            .map(|((atlas, touched), mut mesh)| {
+                let generation = atlas
+                    .as_ref()
+                    .map(|atlas| atlas.place(&touched, &mut mesh.vertices))
+                    .unwrap_or_default();
+
+                (generation, mesh)
+            })
+            .collect();
+
+        state.glyphs.clear();
+
         self.fonts
             .as_ref()
-            .zip(touched)
-            .for_each(|(font, touched)| {
-                font.atlas.touch(&touched);
+            .zip(glyph_meshes.as_ref())
+            .for_each(|(font, (generation, mesh))| {
+                state.add_glyphs(&font.atlas, *generation, mesh)
             });
 
-        state
-            .glyph_meshes
-            .as_mut()
-            .zip(glyph_meshes)
-            .for_each(|(mesh, (vertices, indices))| {
-                mesh.update(&state.device, &state.queue, &vertices, &indices)
-            });
+        for (atlas, (generation, mesh)) in fallback_atlases.iter().zip(fallback_meshes.iter()) {
+            if let Some(atlas) = atlas {
+                state.add_glyphs(atlas, *generation, mesh);
+            }
+        }
 
-        state.bg_mesh.update(
-            &state.device,
-            &state.queue,
-            &self.bg_vertices,
-            &self.bg_indices,
-        );
+        state.color_glyphs.clear();
 
-        state.overlay_mesh.update(
-            &state.device,
-            &state.queue,
-            &self.overlay_vertices,
-            &self.overlay_indices,
-        );
+        for (atlas, mesh) in color_atlases.iter().zip(color_meshes.iter()) {
+            if let Some(atlas) = atlas {
+                state.add_color_glyphs(atlas, mesh);
+            }
+        }
+
+        state.bg = MeshData {
+            vertices: self.bg_vertices,
+            indices: self.bg_indices,
+        };
+
+        state.overlay = MeshData {
+            vertices: self.overlay_vertices,
+            indices: self.overlay_indices,
+        };
+
+        state.chrome = MeshData {
+            vertices: self.chrome_vertices,
+            indices: self.chrome_indices,
+        };
+
+        // all faces are rasterized at the same scale, so the regular face's
+        // range is used for every glyph
+        state.em_range = self.fonts.regular.atlas.em_range();
 
         state.model =
             Mat4::from_translation(self.state.position) * Mat4::from_quat(self.state.orientation);
+        state.units_per_em = self.state.units_per_em;
+        state.ime_anchor = self.ime_anchor;
     }
 
     pub fn draw_padding(&mut self) {
+        if self.is_background_transparent() {
+            return;
+        }
+
         let tl = -self.state.half_size;
         let br = self.state.half_size;
         let inset = br - self.grid_to_pos(self.grid_size.x as i32, 0);
@@ -480,16 +1136,21 @@ impl TerminalCanvas {
         self.draw_hollow_rect(tl, br, inset, color);
     }
 
-    pub fn draw_cell(&mut self, cell: Indexed<&Cell>) {
+    pub fn draw_cell(&mut self, cell: &BufferedCell) {
         if cell.flags.contains(Flags::HIDDEN) {
             return;
         }
 
         let col = cell.point.column.0 as i32;
-        let row = cell.point.line.0;
+        let row = cell.point.line.0 + self.display_offset;
         let mut fg = cell.fg;
         let mut bg = cell.bg;
 
+        // without a real bold face, show bold text in bright colors instead
+        if cell.flags.contains(Flags::BOLD) && !self.has_bold_face() {
+            fg = bold_as_bright(fg);
+        }
+
         let is_full_block = cell.c == '▀';
 
         if cell.flags.contains(Flags::INVERSE) ^ is_full_block {
@@ -499,13 +1160,13 @@ impl TerminalCanvas {
         let tl = self.grid_to_pos(col, row);
         let br = self.grid_to_pos(col + 1, row + 1);
 
-        let bg = if bg == Color::Named(NamedColor::Background) {
-            self.get_background_color()
-        } else {
-            self.color_to_u32(bg)
-        };
-
-        self.draw_solid_rect(tl, br, bg);
+        if bg != Color::Named(NamedColor::Background) {
+            let bg = self.color_to_u32(bg);
+            self.draw_solid_rect(tl, br, bg);
+        } else if !self.is_background_transparent() {
+            let bg = self.get_background_color();
+            self.draw_solid_rect(tl, br, bg);
+        }
 
         // skip foreground rendering if the entire cell is occupied
         if is_full_block {
@@ -514,11 +1175,20 @@ impl TerminalCanvas {
 
         let style = FontStyle::from_cell_flags(cell.flags);
         let font = self.fonts.get(style);
-        let fg = self.color_to_u32(fg);
+
+        let fg = if cell.flags.contains(Flags::DIM) {
+            dim_color(self.color_to_rgb(fg), self.color_to_rgb(bg))
+        } else {
+            self.color_to_rgb(fg)
+        };
+
+        let fg = with_opacity(rgb_to_u32(fg), self.glyph_opacity);
 
         let face = font.atlas.face.as_face_ref();
         if let Some(glyph) = face.glyph_index(cell.c) {
-            self.glyphs.push((tl, style, glyph.0, fg));
+            self.glyphs.push((tl, GlyphFace::Style(style), glyph.0, fg));
+        } else if let Some((face, glyph)) = self.find_fallback(cell.c) {
+            self.glyphs.push((tl, face, glyph, fg));
         }
 
         let baseline = *self.font_baselines.get(style) * self.state.units_per_em;
@@ -543,13 +1213,94 @@ impl TerminalCanvas {
         }
     }
 
-    pub fn draw_cursor(&mut self, cursor: RenderableCursor) {
+    /// Adds a quad for a color glyph to a mesh.
+    ///
+    /// The glyph is scaled to the height of a cell, keeping its aspect ratio,
+    /// and centered within the cells that it spans.
+    fn push_color_glyph(
+        &self,
+        mesh: &mut MeshData<GlyphVertex>,
+        tl: Vec2,
+        columns: u32,
+        glyph: ColorGlyph,
+        color: u32,
+    ) {
+        let cell_size = self.cell_size * self.state.units_per_em;
+        let span = cell_size.x * columns as f32;
+        let height = cell_size.y;
+        let width = (height * glyph.aspect).min(span);
+        let height = width / glyph.aspect;
+
+        let left = tl.x + (span - width) / 2.0;
+        let top = tl.y - (cell_size.y - height) / 2.0;
+        let bottom = top - height;
+        let right = left + width;
+
+        let index = mesh.vertices.len() as u32;
+        let corners = [
+            (vec2(left, bottom), vec2(glyph.tex_min.x, glyph.tex_max.y)),
+            (vec2(right, bottom), glyph.tex_max),
+            (vec2(left, top), glyph.tex_min),
+            (vec2(right, top), vec2(glyph.tex_max.x, glyph.tex_min.y)),
+        ];
+
+        mesh.vertices
+            .extend(corners.iter().map(|(position, tex_coords)| GlyphVertex {
+                position: *position,
+                tex_coords: *tex_coords,
+                color,
+            }));
+
+        mesh.indices.extend_from_slice(&[
+            index,
+            index + 1,
+            index + 2,
+            index + 2,
+            index + 1,
+            index + 3,
+        ]);
+    }
+
+    /// Searches the fallback faces, in order, for a glyph for a character.
+    ///
+    /// Color glyphs are drawn from the fallback's color atlas instead of its
+    /// MSDF atlas. Returns `None` if the first fallback with the glyph is
+    /// still loading its atlas, so that the glyph doesn't change faces once
+    /// it's loaded.
+    pub fn find_fallback(&self, c: char) -> Option<(GlyphFace, u16)> {
+        for (index, fallback) in self.fallbacks.iter().enumerate() {
+            if let Some(glyph) = fallback.glyph_index(c) {
+                if fallback.is_color_glyph(glyph) {
+                    let columns = c.width().unwrap_or(1).max(1) as u32;
+                    let face = GlyphFace::Color {
+                        fallback: index,
+                        columns,
+                    };
+
+                    return Some((face, glyph));
+                }
+
+                fallback.get_atlas()?;
+                return Some((GlyphFace::Fallback(index), glyph));
+            }
+        }
+
+        None
+    }
+
+    pub fn draw_cursor(&mut self, shape: CursorShape, point: Point) {
         let cursor_color = Color::Named(NamedColor::Foreground);
         let cursor_color = self.color_to_u32(cursor_color);
-        let col = cursor.point.column.0 as i32;
-        let row = cursor.point.line.0;
+        let col = point.column.0 as i32;
+        let row = point.line.0 + self.display_offset;
+
+        // skip the cursor if it's been scrolled out of view
+        if row >= self.grid_size.y as i32 {
+            return;
+        }
+
         let line_width = 0.1 * self.state.units_per_em;
-        match cursor.shape {
+        match shape {
             CursorShape::Hidden => {}
             CursorShape::Block => {
                 let tl = self.grid_to_pos(col, row);
@@ -576,35 +1327,210 @@ impl TerminalCanvas {
         }
     }
 
-    pub fn draw_solid_rect(&mut self, tl: Vec2, br: Vec2, color: u32) {
-        let index = self.bg_vertices.len() as u32;
-        self.bg_vertices.extend_from_slice(&[
-            SolidVertex {
-                position: tl,
-                color,
-            },
-            SolidVertex {
-                position: Vec2::new(br.x, tl.y),
-                color,
-            },
-            SolidVertex {
-                position: Vec2::new(tl.x, br.y),
+    /// Computes the range of columns that preedit text covers when drawn at
+    /// a cursor, clipped to the width of the grid.
+    pub fn preedit_columns(&self, preedit: &Preedit, point: Point) -> std::ops::Range<i32> {
+        let start = point.column.0 as i32;
+        let width = text_columns(&preedit.text) as i32;
+        start..(start + width).min(self.grid_size.x as i32)
+    }
+
+    /// Draws preedit text over the cells at a cursor, underlined, with the
+    /// IME's cursor as a beam.
+    pub fn draw_preedit(&mut self, preedit: &Preedit, point: Point) {
+        let row = point.line.0 + self.display_offset;
+        if row >= self.grid_size.y as i32 {
+            return;
+        }
+
+        let fg = self.color_to_rgb(Color::Named(NamedColor::Foreground));
+        let fg = with_opacity(rgb_to_u32(fg), self.glyph_opacity);
+        let font = self.fonts.get(FontStyle::Regular);
+        let baseline = self.font_baselines.regular * self.state.units_per_em;
+        let underline_pos = font.underline_pos * self.state.units_per_em - baseline;
+        let underline_width = font.underline_width * self.state.units_per_em;
+
+        let line_width = 0.1 * self.state.units_per_em;
+        let cursor = preedit.cursor.map(|(start, _end)| start);
+        let mut cursor_col = None;
+        let mut col = point.column.0 as i32;
+
+        for (index, c) in preedit.text.char_indices() {
+            if cursor == Some(index) {
+                cursor_col = Some(col);
+            }
+
+            let width = c.width().unwrap_or(0) as i32;
+            if width == 0 {
+                continue;
+            }
+
+            if col + width > self.grid_size.x as i32 {
+                break;
+            }
+
+            let tl = self.grid_to_pos(col, row);
+            let br = self.grid_to_pos(col + width, row + 1);
+
+            if !self.is_background_transparent() {
+                let bg = self.get_background_color();
+                self.draw_solid_rect(tl, br, bg);
+            }
+
+            let face = self.fonts.regular.atlas.face.as_face_ref();
+            if let Some(glyph) = face.glyph_index(c) {
+                let face = GlyphFace::Style(FontStyle::Regular);
+                self.glyphs.push((tl, face, glyph.0, fg));
+            } else if let Some((face, glyph)) = self.find_fallback(c) {
+                self.glyphs.push((tl, face, glyph, fg));
+            }
+
+            // the underline is drawn over the glyphs to mark the composition
+            let cy = tl.y + underline_pos;
+            push_rect(
+                &mut self.overlay_vertices,
+                &mut self.overlay_indices,
+                vec2(tl.x, cy),
+                vec2(br.x, cy + underline_width),
+                fg,
+            );
+
+            col += width;
+        }
+
+        if cursor == Some(preedit.text.len()) {
+            cursor_col = Some(col);
+        }
+
+        if let Some(col) = cursor_col {
+            let tl = self.grid_to_pos(col, row);
+            let br = self.grid_to_pos(col + 1, row + 1);
+            let br = vec2(tl.x + line_width, br.y);
+            push_rect(
+                &mut self.overlay_vertices,
+                &mut self.overlay_indices,
+                tl,
+                br,
+                fg,
+            );
+        }
+    }
+
+    /// Draws a scroll position indicator on the right edge of the grid if
+    /// the view is scrolled into the scrollback history.
+    pub fn draw_scrollbar(&mut self, display_offset: usize, history_size: usize) {
+        if display_offset == 0 {
+            return;
+        }
+
+        let (top, bottom) = scrollbar_thumb(display_offset, history_size, self.grid_size.y);
+        let grid_tl = self.grid_to_pos(0, 0);
+        let grid_br = self.grid_to_pos(self.grid_size.x as i32, self.grid_size.y as i32);
+        let width = 0.25 * self.cell_size.x * self.state.units_per_em;
+        let height = grid_br.y - grid_tl.y;
+        let tl = vec2(grid_br.x - width, grid_tl.y + height * top);
+        let br = vec2(grid_br.x, grid_tl.y + height * bottom);
+
+        // draw the thumb as a translucent foreground color
+        let fg = self.color_to_u32(Color::Named(NamedColor::Foreground));
+        let color = (fg & 0x00ffffff) | 0x80000000;
+
+        push_rect(
+            &mut self.overlay_vertices,
+            &mut self.overlay_indices,
+            tl,
+            br,
+            color,
+        );
+    }
+
+    /// Draws a terminal's title bar, border, and drop shadow outside of its
+    /// quad. The title bar extends the top of the quad by one row.
+    pub fn draw_chrome(&mut self, chrome: &TerminalChrome) {
+        let half_size = self.state.half_size;
+        let cell_size = self.cell_size * self.state.units_per_em;
+        let bar_height = if chrome.title_bar { cell_size.y } else { 0.0 };
+        let border = match chrome.border {
+            true => chrome.border_width.max(0.0) * self.state.units_per_em,
+            false => 0.0,
+        };
+
+        let frame_tl = vec2(-half_size.x, half_size.y + bar_height);
+        let frame_br = vec2(half_size.x, -half_size.y);
+        let outer_tl = frame_tl + vec2(-border, border);
+        let outer_br = frame_br + vec2(border, -border);
+
+        let color = schema_color_to_u32(match self.focused {
+            Some(true) => chrome.focused_color,
+            _ => chrome.unfocused_color,
+        });
+
+        // the shadow is drawn first so that everything else covers it
+        if chrome.shadow {
+            let offset = chrome.shadow_offset * self.state.units_per_em;
+            let shadow = schema_color_to_u32(chrome.shadow_color);
+            self.draw_chrome_rect(outer_tl + offset, outer_br + offset, shadow);
+        }
+
+        if border > 0.0 {
+            self.draw_chrome_rect(outer_tl, vec2(frame_tl.x, outer_br.y), color);
+            self.draw_chrome_rect(vec2(frame_br.x, outer_tl.y), outer_br, color);
+            self.draw_chrome_rect(
+                vec2(frame_tl.x, outer_tl.y),
+                vec2(frame_br.x, frame_tl.y),
                 color,
-            },
-            SolidVertex {
-                position: br,
+            );
+            self.draw_chrome_rect(
+                vec2(frame_tl.x, frame_br.y),
+                vec2(frame_br.x, outer_br.y),
                 color,
-            },
-        ]);
+            );
+        }
 
-        self.bg_indices.extend_from_slice(&[
-            index,
-            index + 1,
-            index + 2,
-            index + 2,
-            index + 1,
-            index + 3,
-        ]);
+        if !chrome.title_bar {
+            return;
+        }
+
+        self.draw_chrome_rect(frame_tl, vec2(frame_br.x, half_size.y), color);
+
+        let columns = (half_size.x * 2.0 / cell_size.x).floor().max(0.0) as usize;
+        let title = ellipsize(&chrome.title, columns);
+        let title_width = text_columns(&title) as f32 * cell_size.x;
+        let title_color = schema_color_to_u32(chrome.title_color);
+        let mut x = -title_width / 2.0;
+
+        for c in title.chars() {
+            let width = c.width().unwrap_or(0);
+            if width == 0 {
+                continue;
+            }
+
+            let tl = vec2(x, frame_tl.y);
+            let face = self.fonts.regular.atlas.face.as_face_ref();
+            if let Some(glyph) = face.glyph_index(c) {
+                let face = GlyphFace::Style(FontStyle::Regular);
+                self.glyphs.push((tl, face, glyph.0, title_color));
+            } else if let Some((face, glyph)) = self.find_fallback(c) {
+                self.glyphs.push((tl, face, glyph, title_color));
+            }
+
+            x += width as f32 * cell_size.x;
+        }
+    }
+
+    /// Draws a rectangle into the chrome layer, behind the background.
+    fn draw_chrome_rect(&mut self, tl: Vec2, br: Vec2, color: u32) {
+        push_rect(
+            &mut self.chrome_vertices,
+            &mut self.chrome_indices,
+            tl,
+            br,
+            color,
+        );
+    }
+
+    pub fn draw_solid_rect(&mut self, tl: Vec2, br: Vec2, color: u32) {
+        push_rect(&mut self.bg_vertices, &mut self.bg_indices, tl, br, color);
     }
 
     /// `border` can be positive for inset or negative for outset.
@@ -628,11 +1554,15 @@ impl TerminalCanvas {
 
     pub fn color_to_rgb(&self, color: Color) -> Rgb {
         match color {
-            Color::Named(name) => self.colors[name].unwrap_or(Rgb {
-                r: 0xff,
-                g: 0x00,
-                b: 0xff,
-            }),
+            // dim and bright variants fall back to their base colors
+            Color::Named(name) => self.colors[name]
+                .or(self.colors[name.to_bright()])
+                .or(self.colors[name.to_dim()])
+                .unwrap_or(Rgb {
+                    r: 0xff,
+                    g: 0x00,
+                    b: 0xff,
+                }),
             Color::Spec(rgb) => rgb,
             Color::Indexed(index) => {
                 if let Some(color) = self.colors[index as usize] {
@@ -674,14 +1604,455 @@ impl TerminalCanvas {
     }
 
     pub fn color_to_u32(&self, color: Color) -> u32 {
-        let rgb = self.color_to_rgb(color);
-        0xff000000 | ((rgb.b as u32) << 16) | ((rgb.g as u32) << 8) | (rgb.r as u32)
+        rgb_to_u32(self.color_to_rgb(color))
+    }
+
+    /// Returns true if the bold face is distinct from the regular one.
+    pub fn has_bold_face(&self) -> bool {
+        !Arc::ptr_eq(&self.fonts.bold.atlas, &self.fonts.regular.atlas)
     }
 
     pub fn get_background_color(&self) -> u32 {
         let bg = Color::Named(NamedColor::Background);
-        let base = self.color_to_u32(bg);
-        let alpha = (self.state.opacity * 255.0) as u8;
-        ((alpha as u32) << 24) | (base & 0x00ffffff)
+        with_opacity(self.color_to_u32(bg), self.state.opacity)
+    }
+
+    /// Returns true if the default background is fully transparent, in which
+    /// case its quads are skipped entirely.
+    pub fn is_background_transparent(&self) -> bool {
+        self.state.opacity <= 0.0
+    }
+}
+
+/// Packs an opaque color into the vertex color format.
+pub fn rgb_to_u32(rgb: Rgb) -> u32 {
+    0xff000000 | ((rgb.b as u32) << 16) | ((rgb.g as u32) << 8) | (rgb.r as u32)
+}
+
+/// Packs a protocol color, including its alpha, into the vertex color
+/// format.
+pub fn schema_color_to_u32(color: hearth_schema::Color) -> u32 {
+    let (a, r, g, b) = color.to_argb();
+    ((a as u32) << 24) | ((b as u32) << 16) | ((g as u32) << 8) | (r as u32)
+}
+
+/// Dims a foreground color by moving it a third of the way towards the
+/// background color.
+pub fn dim_color(fg: Rgb, bg: Rgb) -> Rgb {
+    let dim = |fg: u8, bg: u8| ((fg as u16 * 2 + bg as u16) / 3) as u8;
+
+    Rgb {
+        r: dim(fg.r, bg.r),
+        g: dim(fg.g, bg.g),
+        b: dim(fg.b, bg.b),
+    }
+}
+
+/// Maps the 8 normal ANSI colors to their bright variants. Other colors are
+/// unchanged.
+pub fn bold_as_bright(color: Color) -> Color {
+    match color {
+        Color::Named(name) if (name as usize) < 8 => Color::Named(name.to_bright()),
+        Color::Indexed(index) if index < 8 => Color::Indexed(index + 8),
+        color => color,
+    }
+}
+
+/// Scales the alpha of a packed color by an opacity from 0.0 to 1.0.
+pub fn with_opacity(color: u32, opacity: f32) -> u32 {
+    let alpha = (color >> 24) as f32 * opacity.clamp(0.0, 1.0);
+    ((alpha.round() as u32) << 24) | (color & 0x00ffffff)
+}
+
+/// Helper function to append a rectangle's geometry to a solid mesh.
+fn push_rect(
+    vertices: &mut Vec<SolidVertex>,
+    indices: &mut Vec<u32>,
+    tl: Vec2,
+    br: Vec2,
+    color: u32,
+) {
+    let index = vertices.len() as u32;
+    vertices.extend_from_slice(&[
+        SolidVertex {
+            position: tl,
+            color,
+        },
+        SolidVertex {
+            position: Vec2::new(br.x, tl.y),
+            color,
+        },
+        SolidVertex {
+            position: Vec2::new(tl.x, br.y),
+            color,
+        },
+        SolidVertex {
+            position: br,
+            color,
+        },
+    ]);
+
+    indices.extend_from_slice(&[index, index + 1, index + 2, index + 2, index + 1, index + 3]);
+}
+
+/// Counts the number of cells that a string takes up. Wide characters, like
+/// most CJK characters, take up two cells.
+pub fn text_columns(text: &str) -> usize {
+    text.chars().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// Shortens text to fit in a number of columns, ending it with an ellipsis
+/// if it's cut short.
+pub fn ellipsize(text: &str, columns: usize) -> String {
+    if text_columns(text) <= columns {
+        return text.to_string();
+    }
+
+    if columns == 0 {
+        return String::new();
+    }
+
+    // leave a column for the ellipsis
+    let mut shortened = String::new();
+    let mut used = 0;
+    for c in text.chars() {
+        let width = c.width().unwrap_or(0);
+        if used + width >= columns {
+            break;
+        }
+
+        used += width;
+        shortened.push(c);
+    }
+
+    shortened.push('…');
+    shortened
+}
+
+/// Reconstructs the text of a row of cells.
+///
+/// Wide character spacers are skipped and zero-width characters are kept.
+/// Returns the text and whether the row soft-wraps onto the next row, in
+/// which case trailing whitespace is kept.
+pub fn line_to_string<'a>(cells: impl IntoIterator<Item = &'a Cell>) -> (String, bool) {
+    let mut text = String::new();
+    let mut wrapped = false;
+
+    for cell in cells {
+        wrapped = cell.flags.contains(Flags::WRAPLINE);
+
+        let spacer = Flags::WIDE_CHAR_SPACER | Flags::LEADING_WIDE_CHAR_SPACER;
+        if cell.flags.intersects(spacer) {
+            continue;
+        }
+
+        text.push(cell.c);
+        text.extend(cell.zerowidth().into_iter().flatten());
+    }
+
+    if !wrapped {
+        text.truncate(text.trim_end().len());
+    }
+
+    (text, wrapped)
+}
+
+/// Appends as much of `src` to `dst` as fits within `max_bytes`, cutting at
+/// a character boundary. Returns false if `src` did not fit entirely.
+pub fn append_capped(dst: &mut String, src: &str, max_bytes: usize) -> bool {
+    let available = max_bytes.saturating_sub(dst.len());
+    if src.len() <= available {
+        dst.push_str(src);
+        return true;
+    }
+
+    let mut end = available;
+    while !src.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    dst.push_str(&src[..end]);
+    false
+}
+
+/// Computes the top and bottom of a scrollbar thumb as fractions of the
+/// scrollbar's height, given the display offset and the scrollback history
+/// and screen sizes in lines.
+pub fn scrollbar_thumb(
+    display_offset: usize,
+    history_size: usize,
+    screen_lines: u32,
+) -> (f32, f32) {
+    let total = (history_size + screen_lines as usize).max(1) as f32;
+    let offset = display_offset.min(history_size);
+    let top = (history_size - offset) as f32 / total;
+    let bottom = top + screen_lines as f32 / total;
+    (top, bottom.min(1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cells(text: &str) -> Vec<Cell> {
+        text.chars()
+            .zip(std::iter::repeat(Cell::default()))
+            .map(|(c, mut cell)| {
+                cell.c = c;
+                cell
+            })
+            .collect()
+    }
+
+    fn test_state() -> TerminalState {
+        TerminalState {
+            position: Default::default(),
+            orientation: Default::default(),
+            half_size: Vec2::new(1.2, 0.9),
+            opacity: 1.0,
+            padding: Vec2::splat(0.1),
+            units_per_em: 0.04,
+            colors: Default::default(),
+        }
+    }
+
+    #[test]
+    fn local_points_map_to_cells() {
+        let cell_size = Vec2::new(0.5, 1.0);
+        let grid = UVec2::new(10, 4);
+        let map = |x, y| local_to_cell(cell_size, 0.1, grid, Vec2::new(x, y));
+
+        // the grid is centered with +Y up, so the top-left cell is at -X +Y
+        assert_eq!(map(-0.24, 0.19), Some(UVec2::new(0, 0)));
+        assert_eq!(map(0.24, -0.19), Some(UVec2::new(9, 3)));
+        assert_eq!(map(0.01, 0.01), Some(UVec2::new(5, 1)));
+        assert_eq!(map(0.26, 0.0), None);
+        assert_eq!(map(0.0, -0.21), None);
+    }
+
+    #[test]
+    fn fixed_size_scales_cells() {
+        let cell_size = Vec2::new(0.5, 1.2);
+        let mut state = test_state();
+        let grid = UVec2::new(120, 20);
+        fit_to_grid(cell_size, &mut state, grid, TerminalSizingMode::FixedSize);
+
+        assert_eq!(state.half_size, Vec2::new(1.2, 0.9));
+
+        // the grid fits without overflowing either axis
+        let used = grid.as_vec2() * cell_size * state.units_per_em;
+        let available = (state.half_size - state.padding) * 2.0;
+        assert!(used.x <= available.x + 1e-5 && used.y <= available.y + 1e-5);
+        assert!((used - available).abs().min_element() < 1e-5);
+    }
+
+    #[test]
+    fn grow_keeps_cell_size() {
+        let cell_size = Vec2::new(0.5, 1.2);
+        let mut state = test_state();
+        let grid = UVec2::new(80, 24);
+        fit_to_grid(cell_size, &mut state, grid, TerminalSizingMode::Grow);
+
+        assert_eq!(state.units_per_em, 0.04);
+        let available = (state.half_size - state.padding) * 2.0;
+        let cells = available / cell_size / state.units_per_em;
+        assert!((cells - grid.as_vec2()).abs().max_element() < 1e-3);
+    }
+
+    #[test]
+    fn snapshots_skip_stale_meshing() {
+        let (sender, _events) = channel();
+        let size = alacritty_terminal::term::SizeInfo::new(200.0, 60.0, 1.0, 1.0, 0.0, 0.0, false);
+        let config = alacritty_terminal::config::Config::default();
+        let mut term = Term::new(&config, size, crate::pty::Listener::new(sender));
+
+        // stands in for a slow mesher, which only finishes when released
+        let (started_tx, started) = channel();
+        let (release, release_rx) = channel::<()>();
+        let mesher = CoalescingWorker::new("test mesher", move |cells: CellBuffer| {
+            let c = cells.cells[0].c;
+            started_tx.send(c).unwrap();
+            release_rx.recv().unwrap();
+            c
+        });
+
+        let mut snapshot = |c| {
+            term.grid_mut()[Line(0)][Column(0)].c = c;
+            mesher.submit(CellBuffer::from_term(&term));
+        };
+
+        // submitting never waits for the busy mesher
+        snapshot('a');
+        assert_eq!(started.recv().unwrap(), 'a');
+        snapshot('b');
+        snapshot('c');
+        assert_eq!(mesher.take(), None);
+
+        // snapshots replaced while the mesher was busy are skipped
+        release.send(()).unwrap();
+        assert_eq!(started.recv().unwrap(), 'c');
+        assert_eq!(mesher.take(), Some('a'));
+        assert_eq!(mesher.take(), None);
+
+        release.send(()).unwrap();
+        let meshed = loop {
+            match mesher.take() {
+                Some(meshed) => break meshed,
+                None => std::thread::yield_now(),
+            }
+        };
+
+        assert_eq!(meshed, 'c');
+        assert!(started.try_recv().is_err());
+    }
+
+    #[test]
+    fn line_trims_trailing_whitespace() {
+        assert_eq!(
+            line_to_string(&cells("ls -la   ")),
+            ("ls -la".into(), false)
+        );
+    }
+
+    #[test]
+    fn line_skips_wide_spacers() {
+        let mut row = cells("漢 x ");
+        row[1].flags.insert(Flags::WIDE_CHAR_SPACER);
+        row[0].flags.insert(Flags::WIDE_CHAR);
+        assert_eq!(line_to_string(&row), ("漢x".into(), false));
+    }
+
+    #[test]
+    fn wrapped_line_keeps_whitespace() {
+        let mut row = cells("abc  ");
+        row[4].flags.insert(Flags::WRAPLINE);
+        assert_eq!(line_to_string(&row), ("abc  ".into(), true));
+    }
+
+    #[test]
+    fn ime_commits_replace_preedit() {
+        let mut preedit = None;
+        let compose = |text: &str| Ime::Preedit {
+            text: text.to_string(),
+            cursor: Some((text.len(), text.len())),
+        };
+
+        assert_eq!(apply_ime(&mut preedit, Ime::Enabled), None);
+        assert_eq!(apply_ime(&mut preedit, compose("に")), None);
+        assert_eq!(apply_ime(&mut preedit, compose("にほ")), None);
+        assert_eq!(
+            preedit,
+            Some(Preedit {
+                text: "にほ".to_string(),
+                cursor: Some((6, 6)),
+            })
+        );
+
+        let committed = apply_ime(&mut preedit, Ime::Commit("日本".to_string()));
+        assert_eq!(committed.as_deref(), Some("日本"));
+        assert_eq!(preedit, None);
+
+        // an empty preedit clears the composition without committing it
+        apply_ime(&mut preedit, compose("ご"));
+        assert_eq!(apply_ime(&mut preedit, compose("")), None);
+        assert_eq!(preedit, None);
+
+        apply_ime(&mut preedit, compose("ご"));
+        assert_eq!(apply_ime(&mut preedit, Ime::Disabled), None);
+        assert_eq!(preedit, None);
+    }
+
+    #[test]
+    fn wide_characters_take_two_columns() {
+        assert_eq!(text_columns("abc"), 3);
+        assert_eq!(text_columns("日本語"), 6);
+        assert_eq!(text_columns("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn append_capped_cuts_at_char_boundary() {
+        let mut text = "ab".to_string();
+        assert!(append_capped(&mut text, "cd", 4));
+        assert!(!append_capped(&mut text, "é", 5));
+        assert_eq!(text, "abcd");
+
+        let mut text = String::new();
+        assert!(!append_capped(&mut text, "aé", 2));
+        assert_eq!(text, "a");
+    }
+
+    #[test]
+    fn scrollbar_thumb_positions() {
+        assert_eq!(scrollbar_thumb(0, 30, 10), (0.75, 1.0));
+        assert_eq!(scrollbar_thumb(30, 30, 10), (0.0, 0.25));
+        assert_eq!(scrollbar_thumb(15, 30, 10), (0.375, 0.625));
+        assert_eq!(scrollbar_thumb(100, 30, 10), (0.0, 0.25));
+        assert_eq!(scrollbar_thumb(0, 0, 0), (0.0, 0.0));
+    }
+
+    #[test]
+    fn dim_moves_towards_background() {
+        let fg = Rgb {
+            r: 0xff,
+            g: 0x30,
+            b: 0x00,
+        };
+
+        let bg = Rgb {
+            r: 0x00,
+            g: 0x30,
+            b: 0xff,
+        };
+
+        let dimmed = dim_color(fg, bg);
+        assert_eq!((dimmed.r, dimmed.g, dimmed.b), (0xaa, 0x30, 0x55));
+    }
+
+    #[test]
+    fn bold_brightens_base_colors() {
+        let red = Color::Named(NamedColor::Red);
+        let bright_red = Color::Named(NamedColor::BrightRed);
+        assert_eq!(bold_as_bright(red), bright_red);
+        assert_eq!(bold_as_bright(bright_red), bright_red);
+        assert_eq!(bold_as_bright(Color::Indexed(3)), Color::Indexed(11));
+        assert_eq!(bold_as_bright(Color::Indexed(100)), Color::Indexed(100));
+
+        let foreground = Color::Named(NamedColor::Foreground);
+        assert_eq!(bold_as_bright(foreground), foreground);
+    }
+
+    #[test]
+    fn opacity_scales_alpha() {
+        assert_eq!(with_opacity(0xff102030, 1.0), 0xff102030);
+        assert_eq!(with_opacity(0xff102030, 0.5), 0x80102030);
+        assert_eq!(with_opacity(0x80102030, 0.5), 0x40102030);
+        assert_eq!(with_opacity(0xff102030, 0.0), 0x00102030);
+        assert_eq!(with_opacity(0xff102030, 2.0), 0xff102030);
+    }
+
+    #[test]
+    fn ellipsize_fits() {
+        assert_eq!(ellipsize("hello", 5), "hello");
+        assert_eq!(ellipsize("hello", 10), "hello");
+    }
+
+    #[test]
+    fn ellipsize_truncates() {
+        assert_eq!(ellipsize("hello world", 6), "hello…");
+        assert_eq!(ellipsize("hello", 0), "");
+    }
+
+    #[test]
+    fn ellipsize_wide_chars() {
+        // a wide character that would overlap the ellipsis is dropped
+        let title = ellipsize("日本語", 4);
+        assert_eq!(title, "日…");
+        assert!(text_columns(&title) <= 4);
+    }
+
+    #[test]
+    fn schema_colors_keep_alpha() {
+        let color = hearth_schema::Color(0x80112233);
+        assert_eq!(schema_color_to_u32(color), 0x80332211);
     }
 }
@@ -17,14 +17,76 @@
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
 use std::{
-    collections::HashSet,
-    sync::{Arc, Mutex},
+    collections::HashMap,
+    fmt::{Display, Formatter, Result as FmtResult},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
 };
 
 use alacritty_terminal::term::cell::Flags;
 use font_mud::glyph_atlas::GlyphAtlas;
-use hearth_rend3::wgpu::{util::DeviceExt, *};
-use owned_ttf_parser::{AsFaceRef, OwnedFace};
+use glam::{vec2, UVec2, Vec2};
+use hearth_rend3::wgpu::*;
+use hearth_runtime::tracing::{debug, error};
+use owned_ttf_parser::{
+    gpos::{PairAdjustment, PositioningSubtable},
+    AsFaceRef, Face, FaceParsingError, GlyphId, OwnedFace, Rect, Tag,
+};
+
+use crate::color::{self, ColorAtlas, ColorGlyph};
+use crate::draw::GlyphVertex;
+
+/// An error from loading a font face or its glyph atlas.
+#[derive(Debug)]
+pub enum FontError {
+    /// The font data could not be parsed.
+    Parse(FaceParsingError),
+
+    /// The font is a variable font, which glyph atlases can't be generated
+    /// from.
+    Variable,
+
+    /// The glyph atlas could not be generated.
+    AtlasGeneration(String),
+
+    /// The face's largest glyph doesn't fit in the device's maximum texture
+    /// size.
+    AtlasTooLarge { width: u32, height: u32, max: u32 },
+
+    /// Every glyph in a glyph atlas texture of the device's maximum size is
+    /// in use, so no more can be added.
+    AtlasFull { max: u32 },
+}
+
+impl Display for FontError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        match self {
+            FontError::Parse(err) => write!(f, "failed to parse font: {}", err),
+            FontError::Variable => write!(f, "variable fonts are not supported"),
+            FontError::AtlasGeneration(err) => write!(f, "failed to generate glyph atlas: {}", err),
+            FontError::AtlasTooLarge { width, height, max } => write!(
+                f,
+                "glyphs ({}x{}) exceed the maximum texture size of {}",
+                width, height, max
+            ),
+            FontError::AtlasFull { max } => write!(
+                f,
+                "glyph atlas is full at the maximum texture size of {}",
+                max
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FontError {}
+
+impl From<FaceParsingError> for FontError {
+    fn from(err: FaceParsingError) -> Self {
+        FontError::Parse(err)
+    }
+}
 
 /// A kind of font used by a terminal.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -60,6 +122,18 @@ pub struct FontSet<T> {
     pub bold_italic: T,
 }
 
+impl<T, E> FontSet<Result<T, E>> {
+    /// Returns the first error in this set, or a set of all successes.
+    pub fn transpose(self) -> Result<FontSet<T>, E> {
+        Ok(FontSet {
+            regular: self.regular?,
+            italic: self.italic?,
+            bold: self.bold?,
+            bold_italic: self.bold_italic?,
+        })
+    }
+}
+
 impl<T> FontSet<T> {
     pub fn map<O>(self, f: impl Fn(T) -> O) -> FontSet<O> {
         FontSet {
@@ -123,82 +197,1012 @@ impl<T> FontSet<T> {
     }
 }
 
+/// The distance range of MSDF glyph bitmaps, in atlas texels.
+///
+/// This must match the range that font-mud generates its atlases with.
+pub const MSDF_RANGE: f32 = 8.0;
+
+/// The atlas resolution assumed for atlases without any glyph quads to
+/// measure, in texels per em.
+const DEFAULT_TEXELS_PER_EM: f32 = 64.0;
+
+/// The width and height that a [FaceAtlas]'s texture starts at, in texels.
+const INITIAL_ATLAS_SIZE: u32 = 512;
+
+/// The empty space between glyphs in a [FaceAtlas], in texels, so that
+/// filtering doesn't bleed neighboring glyphs into each other.
+const GLYPH_PADDING: u32 = 1;
+
+/// The device and queue that font atlases are created on.
+///
+/// Clones share the same device, so replacing it after a device reset moves
+/// every holder onto the new device together.
+#[derive(Clone)]
+pub struct AtlasDevice {
+    inner: Arc<RwLock<(Arc<Device>, Arc<Queue>)>>,
+}
+
+impl AtlasDevice {
+    pub fn new(device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        Self {
+            inner: Arc::new(RwLock::new((device, queue))),
+        }
+    }
+
+    /// Gets the current device and queue.
+    pub fn get(&self) -> (Arc<Device>, Arc<Queue>) {
+        self.inner.read().unwrap().clone()
+    }
+
+    /// Replaces the device and queue for every clone of this handle.
+    pub fn replace(&self, device: Arc<Device>, queue: Arc<Queue>) {
+        *self.inner.write().unwrap() = (device, queue);
+    }
+}
+
+/// The GPU texture of a [FaceAtlas] and the device it's on.
+struct AtlasTexture {
+    texture: Texture,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+}
+
+/// A glyph's place in a [GlyphCache].
+struct GlyphSlot {
+    /// The texel position of the glyph's cell.
+    origin: UVec2,
+
+    /// The batch that last used this glyph.
+    last_used: u64,
+}
+
+/// A cell newly given to a glyph by [GlyphCache::insert].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct NewSlot {
+    /// The texel position of the cell.
+    origin: UVec2,
+
+    /// Whether the texture had to grow to make room.
+    grew: bool,
+
+    /// Whether another glyph was evicted to make room.
+    evicted: bool,
+}
+
+/// Tracks which glyphs are in a [FaceAtlas]'s texture and where.
+///
+/// The texture is divided into equal cells that fit the face's largest
+/// glyph. Once every cell is taken, the texture doubles in size up to a
+/// maximum, and after that the least recently used glyph is evicted.
+struct GlyphCache {
+    /// The size of a cell, including padding, in texels.
+    cell: UVec2,
+
+    /// The current width and height of the texture.
+    size: u32,
+
+    /// The largest width and height that the texture may grow to.
+    max_size: u32,
+
+    /// The origins of the cells without a glyph, in reverse order of use.
+    free: Vec<UVec2>,
+
+    /// Every glyph in the texture.
+    slots: HashMap<u16, GlyphSlot>,
+
+    /// The current batch. Glyphs used in it are never evicted.
+    batch: u64,
+
+    /// Set once a glyph didn't fit, so that's only logged once.
+    full: bool,
+}
+
+impl GlyphCache {
+    /// Creates an empty cache for glyphs up to `glyph_size` texels large.
+    ///
+    /// Returns `None` if a single glyph doesn't fit in `max_size`.
+    fn new(glyph_size: UVec2, max_size: u32) -> Option<Self> {
+        let cell = glyph_size + UVec2::splat(GLYPH_PADDING);
+        if cell.max_element() > max_size {
+            return None;
+        }
+
+        let mut size = INITIAL_ATLAS_SIZE.min(max_size);
+        while size < cell.max_element() {
+            size = (size * 2).min(max_size);
+        }
+
+        let mut cache = Self {
+            cell,
+            size,
+            max_size,
+            free: Vec::new(),
+            slots: HashMap::new(),
+            batch: 0,
+            full: false,
+        };
+
+        cache.free = cache.new_cells(0, size);
+        Some(cache)
+    }
+
+    /// Lists the cells that a texture growing from `old` to `new` texels
+    /// wide gains, in reverse order of use.
+    fn new_cells(&self, old: u32, new: u32) -> Vec<UVec2> {
+        let (old_cols, old_rows) = (old / self.cell.x, old / self.cell.y);
+        let (cols, rows) = (new / self.cell.x, new / self.cell.y);
+
+        let mut cells: Vec<UVec2> = (0..rows)
+            .flat_map(|row| (0..cols).map(move |col| UVec2::new(col, row)))
+            .filter(|cell| cell.x >= old_cols || cell.y >= old_rows)
+            .map(|cell| cell * self.cell)
+            .collect();
+
+        cells.reverse();
+        cells
+    }
+
+    /// Starts a new batch of glyphs, which can't evict each other.
+    fn begin(&mut self) {
+        self.batch += 1;
+    }
+
+    /// Looks up a glyph's cell, marking it as used by the current batch.
+    fn get(&mut self, glyph: u16) -> Option<UVec2> {
+        let slot = self.slots.get_mut(&glyph)?;
+        slot.last_used = self.batch;
+        Some(slot.origin)
+    }
+
+    /// Gives a glyph that isn't in the cache a cell, growing the texture or
+    /// evicting the least recently used glyph to make room.
+    ///
+    /// Returns `None` if every cell is used by the current batch.
+    fn insert(&mut self, glyph: u16) -> Option<NewSlot> {
+        let mut grew = false;
+        let mut evicted = false;
+
+        if self.free.is_empty() && self.size < self.max_size {
+            let size = (self.size * 2).min(self.max_size);
+            self.free = self.new_cells(self.size, size);
+            self.size = size;
+            grew = true;
+        }
+
+        if self.free.is_empty() {
+            let (lru, _) = self
+                .slots
+                .iter()
+                .filter(|(_, slot)| slot.last_used < self.batch)
+                .min_by_key(|(_, slot)| slot.last_used)?;
+
+            let lru = *lru;
+            let slot = self.slots.remove(&lru).unwrap();
+            self.free.push(slot.origin);
+            evicted = true;
+        }
+
+        let origin = self.free.pop()?;
+        let slot = GlyphSlot {
+            origin,
+            last_used: self.batch,
+        };
+
+        self.slots.insert(glyph, slot);
+
+        Some(NewSlot {
+            origin,
+            grew,
+            evicted,
+        })
+    }
+
+    /// Removes every glyph, keeping the texture's size.
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.free = self.new_cells(0, self.size);
+        self.full = false;
+    }
+}
+
 /// A font face and its MSDF glyph atlas.
+///
+/// Glyph bitmaps are generated and uploaded to the GPU the first time that
+/// they're used. The texture starts small and grows as glyphs are added, up
+/// to the device's maximum texture size, after which the least recently
+/// used glyphs are evicted. Moving glyphs invalidates texture coordinates
+/// placed before, so every change is counted by [Self::generation].
 pub struct FaceAtlas {
     pub face: OwnedFace,
+
+    /// The layout and shape of every glyph. Texture coordinates in this
+    /// are relative to font-mud's packing of the whole face, and are
+    /// converted to this atlas's texture by [Self::place].
     pub atlas: GlyphAtlas,
-    pub texture: Texture,
-    pub queue: Arc<Queue>,
-    pub touched: Mutex<HashSet<u16>>,
+
+    /// The resolution of this atlas's glyph bitmaps, in texels per em.
+    pub texels_per_em: f32,
+
+    cache: Mutex<GlyphCache>,
+
+    /// Incremented whenever glyphs move or the texture is replaced.
+    generation: AtomicU64,
+
+    /// Replaced when this atlas grows or is moved to a new device.
+    gpu: RwLock<AtlasTexture>,
 }
 
 impl FaceAtlas {
     /// Create a new atlas from a face. Note that this takes time to complete.
-    pub fn new(face: OwnedFace, device: &Device, queue: Arc<Queue>) -> Self {
-        let (atlas, _errors) = GlyphAtlas::new(face.as_face_ref()).unwrap();
+    ///
+    /// Fails if the atlas can't be generated or if the face's largest glyph
+    /// doesn't fit in a texture on the given device.
+    pub fn new(face: OwnedFace, device: Arc<Device>, queue: Arc<Queue>) -> Result<Self, FontError> {
+        let (atlas, _errors) = GlyphAtlas::new(face.as_face_ref())
+            .map_err(|err| FontError::AtlasGeneration(format!("{:?}", err)))?;
 
-        let size = Extent3d {
-            width: atlas.width,
-            height: atlas.height,
-            depth_or_array_layers: 1,
-        };
+        let glyph_size = atlas
+            .glyphs
+            .iter()
+            .flatten()
+            .map(|glyph| UVec2::new(glyph.size.x, glyph.size.y))
+            .fold(UVec2::ONE, UVec2::max);
 
-        let texture = device.create_texture_with_data(
-            &queue,
-            &TextureDescriptor {
-                label: Some("AlacrittyRoutine::glyph_texture"),
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: TextureDimension::D2,
-                format: TextureFormat::Rgba8Unorm,
-                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            },
-            &vec![0u8; (atlas.width * atlas.height * 4) as usize],
-        );
+        let max = device.limits().max_texture_dimension_2d;
+        let cache = GlyphCache::new(glyph_size, max).ok_or(FontError::AtlasTooLarge {
+            width: glyph_size.x,
+            height: glyph_size.y,
+            max,
+        })?;
 
-        Self {
+        let texture = Self::create_texture(&device, cache.size);
+        let texels_per_em = atlas_texels_per_em(&atlas).unwrap_or(DEFAULT_TEXELS_PER_EM);
+
+        Ok(Self {
             face,
             atlas,
+            texels_per_em,
+            cache: Mutex::new(cache),
+            generation: AtomicU64::new(0),
+            gpu: RwLock::new(AtlasTexture {
+                texture,
+                device,
+                queue,
+            }),
+        })
+    }
+
+    /// Creates a blank texture for glyph bitmaps.
+    fn create_texture(device: &Device, size: u32) -> Texture {
+        device.create_texture(&TextureDescriptor {
+            label: Some("AlacrittyRoutine::glyph_texture"),
+            size: Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC,
+        })
+    }
+
+    /// Creates a view of this atlas's texture for binding, along with the
+    /// [generation](Self::generation) of its contents.
+    pub fn view(&self) -> (TextureView, u64) {
+        let gpu = self.gpu.read().unwrap();
+        let view = gpu.texture.create_view(&Default::default());
+        (view, self.generation())
+    }
+
+    /// Counts the changes to this atlas that move glyphs or replace its
+    /// texture.
+    ///
+    /// Texture coordinates placed in an older generation may point at the
+    /// wrong glyph or the wrong texture and must be placed again.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Tests if a glyph is currently in this atlas's texture.
+    pub fn is_resident(&self, glyph: u16) -> bool {
+        self.cache.lock().unwrap().slots.contains_key(&glyph)
+    }
+
+    /// Tests if this atlas's texture is on the given queue's device.
+    pub fn is_on(&self, queue: &Arc<Queue>) -> bool {
+        Arc::ptr_eq(&self.gpu.read().unwrap().queue, queue)
+    }
+
+    /// Moves this atlas to an [AtlasDevice]'s current device if it's on
+    /// another one, such as a device that has been lost.
+    ///
+    /// The glyph bitmaps are kept on the CPU, so the new texture starts
+    /// blank and glyphs are uploaded again the next time they're placed.
+    pub fn migrate(&self, device: &AtlasDevice) {
+        let (device, queue) = device.get();
+        if self.is_on(&queue) {
+            return;
+        }
+
+        let mut cache = self.cache.lock().unwrap();
+        cache.clear();
+
+        let texture = Self::create_texture(&device, cache.size);
+        let mut gpu = self.gpu.write().unwrap();
+        *gpu = AtlasTexture {
             texture,
+            device,
             queue,
-            touched: Default::default(),
-        }
-    }
-
-    /// Generate and upload a glyph bitmap for each glyph that hasn't already been.
-    pub fn touch(&self, glyphs: &[u16]) {
-        let mut touched = self.touched.lock().unwrap();
-        for glyph in glyphs {
-            if touched.insert(*glyph) {
-                let glyph = self.atlas.glyphs.get(*glyph as usize);
-                let Some(Some(glyph)) = glyph else { continue };
-                let bitmap = glyph.shape.generate();
-
-                self.queue.write_texture(
-                    ImageCopyTexture {
-                        texture: &self.texture,
-                        mip_level: 0,
-                        origin: Origin3d {
-                            x: glyph.position.x,
-                            y: glyph.position.y,
-                            z: 0,
-                        },
-                        aspect: TextureAspect::All,
-                    },
-                    bitmap.data_bytes(),
-                    ImageDataLayout {
-                        offset: 0,
-                        bytes_per_row: std::num::NonZeroU32::new(glyph.size.x * 4),
-                        rows_per_image: std::num::NonZeroU32::new(glyph.size.y),
-                    },
-                    Extent3d {
-                        width: glyph.size.x,
-                        height: glyph.size.y,
-                        depth_or_array_layers: 1,
+        };
+
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// The distance range of this atlas's glyphs, in ems.
+    pub fn em_range(&self) -> f32 {
+        MSDF_RANGE / self.texels_per_em
+    }
+
+    /// Looks up a glyph's metrics. See [glyph_metrics].
+    pub fn glyph_metrics(&self, glyph: u16) -> Option<GlyphMetrics> {
+        glyph_metrics(self.face.as_face_ref(), glyph)
+    }
+
+    /// Looks up the kerning between two glyphs. See [kerning].
+    pub fn kerning(&self, left: u16, right: u16) -> i16 {
+        kerning(self.face.as_face_ref(), left, right)
+    }
+
+    /// Lays out a string of text. See [layout_text].
+    pub fn layout(&self, text: &str) -> Vec<PositionedGlyph> {
+        layout_text(self.face.as_face_ref(), text)
+    }
+
+    /// Lays out a string of text into glyph quads ready to draw with this
+    /// atlas, scaled from ems by `scale`, and places every glyph used.
+    ///
+    /// Unlike the terminal's fixed-cell path, glyphs are spaced using their
+    /// advances and kerning, so this works for proportional fonts.
+    ///
+    /// Also returns the [generation](Self::generation) that the quads'
+    /// texture coordinates are valid in.
+    pub fn layout_quads(
+        &self,
+        text: &str,
+        scale: f32,
+        color: u32,
+    ) -> (Vec<GlyphVertex>, Vec<u32>, u64) {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut placed = Vec::new();
+
+        for positioned in self.layout(text) {
+            let Some(Some(bitmap)) = self.atlas.glyphs.get(positioned.glyph as usize) else {
+                continue;
+            };
+
+            let index = vertices.len() as u32;
+            placed.push(positioned.glyph);
+
+            vertices.extend(bitmap.vertices.iter().map(|v| GlyphVertex {
+                position: (v.position + positioned.position) * scale,
+                tex_coords: v.tex_coords,
+                color,
+            }));
+
+            indices.extend_from_slice(&[
+                index,
+                index + 1,
+                index + 2,
+                index + 2,
+                index + 1,
+                index + 3,
+            ]);
+        }
+
+        let generation = self.place(&placed, &mut vertices);
+        (vertices, indices, generation)
+    }
+
+    /// Parses a face from font data and creates its atlas.
+    pub fn from_data(
+        src: Vec<u8>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+    ) -> Result<Self, FontError> {
+        let face = OwnedFace::from_vec(src, 0)?;
+        Self::new(face, device, queue)
+    }
+
+    /// Uploads glyphs to this atlas's texture if they aren't already there.
+    ///
+    /// Returns the [generation](Self::generation) that the glyphs are in.
+    pub fn touch(&self, glyphs: &[u16]) -> u64 {
+        let mut cache = self.cache.lock().unwrap();
+        self.upload_all(&mut cache, glyphs);
+        self.generation()
+    }
+
+    /// Uploads glyphs like [Self::touch], then points the texture
+    /// coordinates of their quads at them.
+    ///
+    /// `vertices` holds a quad of four vertices for each glyph in `glyphs`,
+    /// in the same order, with texture coordinates from [Self::atlas]. The
+    /// quads of glyphs that don't fit in the texture are collapsed so that
+    /// they aren't drawn.
+    ///
+    /// Returns the [generation](Self::generation) that the texture
+    /// coordinates are valid in.
+    pub fn place(&self, glyphs: &[u16], vertices: &mut [GlyphVertex]) -> u64 {
+        let mut cache = self.cache.lock().unwrap();
+        self.upload_all(&mut cache, glyphs);
+
+        let packed_size = vec2(self.atlas.width as f32, self.atlas.height as f32);
+        let size = cache.size as f32;
+
+        for (glyph, quad) in glyphs.iter().zip(vertices.chunks_mut(4)) {
+            let bitmap = self.atlas.glyphs.get(*glyph as usize);
+            let (Some(origin), Some(Some(bitmap))) = (cache.get(*glyph), bitmap) else {
+                let position = quad[0].position;
+                quad.iter_mut().for_each(|v| v.position = position);
+                continue;
+            };
+
+            let packed = vec2(bitmap.position.x as f32, bitmap.position.y as f32);
+            for v in quad.iter_mut() {
+                let texel = v.tex_coords * packed_size - packed;
+                v.tex_coords = (origin.as_vec2() + texel) / size;
+            }
+        }
+
+        self.generation()
+    }
+
+    /// Makes sure that every glyph in a batch is in the texture.
+    fn upload_all(&self, cache: &mut GlyphCache, glyphs: &[u16]) {
+        cache.begin();
+
+        // mark every glyph that's already resident first so that the rest
+        // of the batch can't evict them
+        let missing: Vec<u16> = glyphs
+            .iter()
+            .copied()
+            .filter(|glyph| cache.get(*glyph).is_none())
+            .collect();
+
+        for glyph in missing {
+            if cache.get(glyph).is_some() {
+                continue; // repeated in this batch
+            }
+
+            let Some(Some(bitmap)) = self.atlas.glyphs.get(glyph as usize) else {
+                continue;
+            };
+
+            let old_size = cache.size;
+            let Some(slot) = cache.insert(glyph) else {
+                if !cache.full {
+                    let max = cache.max_size;
+                    error!("{}", FontError::AtlasFull { max });
+                    cache.full = true;
+                }
+
+                continue;
+            };
+
+            if slot.grew {
+                debug!("Growing glyph atlas to {}x{}", cache.size, cache.size);
+                self.grow(old_size, cache.size);
+            } else if slot.evicted {
+                self.generation.fetch_add(1, Ordering::AcqRel);
+            }
+
+            let data = bitmap.shape.generate();
+            let size = UVec2::new(bitmap.size.x, bitmap.size.y);
+            self.upload(slot.origin, size, data.data_bytes());
+        }
+    }
+
+    /// Replaces the texture with a larger one, copying the old contents
+    /// over on the GPU.
+    fn grow(&self, old_size: u32, size: u32) {
+        let mut gpu = self.gpu.write().unwrap();
+        let texture = Self::create_texture(&gpu.device, size);
+
+        let mut encoder = gpu
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("FaceAtlas::grow"),
+            });
+
+        encoder.copy_texture_to_texture(
+            ImageCopyTexture {
+                texture: &gpu.texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: old_size,
+                height: old_size,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        // uploads queued for the old texture happen before this copy
+        gpu.queue.submit(Some(encoder.finish()));
+        gpu.texture = texture;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+    }
+
+    /// Uploads a glyph bitmap into the texture.
+    fn upload(&self, origin: UVec2, size: UVec2, data: &[u8]) {
+        let gpu = self.gpu.read().unwrap();
+        gpu.queue.write_texture(
+            ImageCopyTexture {
+                texture: &gpu.texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: origin.x,
+                    y: origin.y,
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            data,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: std::num::NonZeroU32::new(size.x * 4),
+                rows_per_image: std::num::NonZeroU32::new(size.y),
+            },
+            Extent3d {
+                width: size.x,
+                height: size.y,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+}
+
+/// The loading state of a [FallbackFace]'s atlas.
+enum FallbackAtlas {
+    Unloaded,
+    Loading,
+    Loaded(Arc<FaceAtlas>),
+    Failed,
+}
+
+/// A fallback font face for glyphs missing from a terminal's primary faces.
+///
+/// Generating a [FaceAtlas] is expensive, so a fallback's atlas is only
+/// generated in the background once a glyph is first needed from it.
+pub struct FallbackFace {
+    /// A human-readable name for this face, used in logs.
+    pub name: String,
+
+    /// The raw font data, kept around to build the atlas from.
+    src: Arc<Vec<u8>>,
+
+    /// The parsed face, used for cheap glyph lookups.
+    face: OwnedFace,
+
+    device: AtlasDevice,
+    atlas: Mutex<FallbackAtlas>,
+
+    /// The atlas of this face's color glyphs, created once one is needed.
+    color_atlas: Mutex<Option<Arc<ColorAtlas>>>,
+
+    /// Whether each glyph looked up so far is a color glyph, since testing
+    /// it means parsing the face's color and bitmap tables.
+    color_glyphs: Mutex<HashMap<u16, bool>>,
+}
+
+impl FallbackFace {
+    /// Parses a fallback face from font data. Returns `None` if the font data
+    /// is invalid.
+    pub fn new(name: String, src: Vec<u8>, device: AtlasDevice) -> Option<Arc<Self>> {
+        let face = OwnedFace::from_vec(src.clone(), 0).ok()?;
+
+        Some(Arc::new(Self {
+            name,
+            src: Arc::new(src),
+            face,
+            device,
+            atlas: Mutex::new(FallbackAtlas::Unloaded),
+            color_atlas: Mutex::new(None),
+            color_glyphs: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Looks up the glyph for a character in this face.
+    pub fn glyph_index(&self, c: char) -> Option<u16> {
+        self.face.as_face_ref().glyph_index(c).map(|id| id.0)
+    }
+
+    /// Tests if a glyph in this face is drawn in color. See
+    /// [color::is_color_glyph].
+    ///
+    /// The result is cached, since this is checked for every cell drawn
+    /// with this face.
+    pub fn is_color_glyph(&self, glyph: u16) -> bool {
+        *self
+            .color_glyphs
+            .lock()
+            .unwrap()
+            .entry(glyph)
+            .or_insert_with(|| color::is_color_glyph(self.face.as_face_ref(), glyph))
+    }
+
+    /// Looks up a color glyph in this face's color atlas, rasterizing it
+    /// the first time. See [ColorAtlas::get].
+    ///
+    /// The color atlas is recreated if the device has changed since it was
+    /// created, since rasterizing glyphs again is cheap.
+    pub fn get_color_glyph(&self, glyph: u16) -> Option<(Arc<ColorAtlas>, ColorGlyph)> {
+        let (device, queue) = self.device.get();
+        let mut color_atlas = self.color_atlas.lock().unwrap();
+        let atlas = match color_atlas.as_ref() {
+            Some(atlas) if atlas.is_on(&queue) => atlas.to_owned(),
+            _ => {
+                debug!("Creating color glyph atlas for {}", self.name);
+                let atlas = Arc::new(ColorAtlas::new(&device, queue));
+                *color_atlas = Some(atlas.to_owned());
+                atlas
+            }
+        };
+
+        drop(color_atlas);
+
+        let glyph = atlas.get(self.face.as_face_ref(), glyph)?;
+        Some((atlas, glyph))
+    }
+
+    /// Retrieves this face's atlas if it has been generated.
+    ///
+    /// Starts generating the atlas in the background on the first call.
+    pub fn get_atlas(self: &Arc<Self>) -> Option<Arc<FaceAtlas>> {
+        let mut atlas = self.atlas.lock().unwrap();
+        match &*atlas {
+            FallbackAtlas::Loaded(atlas) => {
+                atlas.migrate(&self.device);
+                return Some(atlas.to_owned());
+            }
+            FallbackAtlas::Loading | FallbackAtlas::Failed => return None,
+            FallbackAtlas::Unloaded => *atlas = FallbackAtlas::Loading,
+        }
+
+        debug!("Generating fallback font atlas for {}", self.name);
+        let fallback = self.to_owned();
+        std::thread::spawn(move || {
+            let src = fallback.src.as_ref().to_owned();
+            let (device, queue) = fallback.device.get();
+            let result = FaceAtlas::from_data(src, device, queue);
+
+            *fallback.atlas.lock().unwrap() = match result {
+                Ok(atlas) => FallbackAtlas::Loaded(Arc::new(atlas)),
+                Err(err) => {
+                    error!("Failed to load fallback font {}: {}", fallback.name, err);
+                    FallbackAtlas::Failed
+                }
+            };
+        });
+
+        None
+    }
+
+    /// Retrieves this face's atlas without starting to generate it.
+    pub fn get_loaded_atlas(&self) -> Option<Arc<FaceAtlas>> {
+        match &*self.atlas.lock().unwrap() {
+            FallbackAtlas::Loaded(atlas) => {
+                atlas.migrate(&self.device);
+                Some(atlas.to_owned())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The shaping-related metrics of a single glyph, in font units.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GlyphMetrics {
+    /// The horizontal distance to advance the pen after this glyph.
+    pub advance: u16,
+
+    /// The horizontal distance from the pen position to the glyph's left
+    /// edge.
+    pub left_side_bearing: i16,
+
+    /// The glyph's outline bounding box. `None` for glyphs with no outline,
+    /// like spaces.
+    pub bounding_box: Option<Rect>,
+}
+
+/// A glyph positioned by [layout_text].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PositionedGlyph {
+    /// The glyph's ID in its face.
+    pub glyph: u16,
+
+    /// The position of the glyph's origin on the baseline, in ems. Lines
+    /// advance downwards in -Y.
+    pub position: Vec2,
+}
+
+/// Looks up a glyph's metrics in a face.
+pub fn glyph_metrics(face: &Face, glyph: u16) -> Option<GlyphMetrics> {
+    let id = GlyphId(glyph);
+
+    Some(GlyphMetrics {
+        advance: face.glyph_hor_advance(id)?,
+        left_side_bearing: face.glyph_hor_side_bearing(id).unwrap_or(0),
+        bounding_box: face.glyph_bounding_box(id),
+    })
+}
+
+/// Looks up the horizontal kerning adjustment between two glyphs in font
+/// units.
+///
+/// Pair adjustments from the GPOS table's `kern` feature take precedence,
+/// followed by the legacy `kern` table. Returns 0 if the pair isn't kerned.
+pub fn kerning(face: &Face, left: u16, right: u16) -> i16 {
+    let (left, right) = (GlyphId(left), GlyphId(right));
+
+    gpos_kerning(face, left, right)
+        .or_else(|| {
+            face.tables()
+                .kern?
+                .subtables
+                .into_iter()
+                .filter(|subtable| subtable.horizontal && !subtable.variable)
+                .find_map(|subtable| subtable.glyphs_kerning(left, right))
+        })
+        .unwrap_or(0)
+}
+
+/// Helper function to look up a pair adjustment in GPOS's `kern` feature.
+fn gpos_kerning(face: &Face, left: GlyphId, right: GlyphId) -> Option<i16> {
+    let gpos = face.tables().gpos?;
+    let kern = Tag::from_bytes(b"kern");
+
+    for feature in gpos.features.into_iter().filter(|f| f.tag == kern) {
+        for index in feature.lookup_indices {
+            let Some(lookup) = gpos.lookups.get(index) else {
+                continue;
+            };
+
+            for subtable in lookup.subtables.into_iter::<PositioningSubtable>() {
+                let PositioningSubtable::Pair(pair) = subtable else {
+                    continue;
+                };
+
+                let values = match pair {
+                    PairAdjustment::Format1 { coverage, sets } => coverage
+                        .get(left)
+                        .and_then(|index| sets.get(index))
+                        .and_then(|set| set.get(right)),
+                    PairAdjustment::Format2 {
+                        coverage,
+                        classes,
+                        matrix,
+                    } => match coverage.contains(left) {
+                        true => matrix.get((classes.0.get(left), classes.1.get(right))),
+                        false => None,
                     },
-                );
+                };
+
+                if let Some((first, _second)) = values {
+                    return Some(first.x_advance);
+                }
             }
         }
     }
+
+    None
+}
+
+/// Measures the resolution of an atlas in texels per em from the first glyph
+/// quad with a nonzero width.
+fn atlas_texels_per_em(atlas: &GlyphAtlas) -> Option<f32> {
+    let atlas_size = vec2(atlas.width as f32, atlas.height as f32);
+    atlas.glyphs.iter().flatten().find_map(|bitmap| {
+        let first = bitmap.vertices.first()?;
+        let last = bitmap.vertices.last()?;
+        let ems = (last.position - first.position).abs();
+        let texels = (last.tex_coords - first.tex_coords).abs() * atlas_size;
+        (ems.x > 0.0).then(|| texels.x / ems.x)
+    })
+}
+
+/// Lays out a string of text into positioned glyphs using glyph advances
+/// and kerning. Newlines start a new line one line height down.
+///
+/// Characters missing from the face are laid out with the face's missing
+/// glyph (ID 0).
+pub fn layout_text(face: &Face, text: &str) -> Vec<PositionedGlyph> {
+    let units_per_em = face.units_per_em() as f32;
+    let line_height = (face.height() as f32 + face.line_gap() as f32) / units_per_em;
+
+    let mut glyphs = Vec::new();
+    let mut pen = Vec2::ZERO;
+    let mut last = None;
+
+    for c in text.chars() {
+        if c == '\n' {
+            pen = vec2(0.0, pen.y - line_height);
+            last = None;
+            continue;
+        }
+
+        let glyph = face.glyph_index(c).map(|id| id.0).unwrap_or(0);
+
+        if let Some(last) = last {
+            pen.x += kerning(face, last, glyph) as f32 / units_per_em;
+        }
+
+        glyphs.push(PositionedGlyph {
+            glyph,
+            position: pen,
+        });
+
+        let advance = face.glyph_hor_advance(GlyphId(glyph)).unwrap_or(0);
+        pen.x += advance as f32 / units_per_em;
+        last = Some(glyph);
+    }
+
+    glyphs
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    const MONONOKI: &[u8] = include_bytes!("../../../resources/mononoki/mononoki-Regular.ttf");
+
+    fn glyph(face: &Face, c: char) -> u16 {
+        face.glyph_index(c).unwrap().0
+    }
+
+    #[test]
+    fn metrics_match_ttf_parser() {
+        let face = Face::parse(MONONOKI, 0).unwrap();
+
+        for c in ['A', 'V', 'i', 'W'] {
+            let id = face.glyph_index(c).unwrap();
+            let metrics = glyph_metrics(&face, id.0).unwrap();
+            assert_eq!(Some(metrics.advance), face.glyph_hor_advance(id));
+            assert_eq!(metrics.bounding_box, face.glyph_bounding_box(id));
+        }
+    }
+
+    #[test]
+    fn layout_advances_by_metrics() {
+        let face = Face::parse(MONONOKI, 0).unwrap();
+        let units_per_em = face.units_per_em() as f32;
+
+        for (left, right) in [('A', 'V'), ('i', 'W')] {
+            let (l, r) = (glyph(&face, left), glyph(&face, right));
+            let text = format!("{}{}", left, right);
+            let glyphs = layout_text(&face, &text);
+
+            let advance = face.glyph_hor_advance(GlyphId(l)).unwrap() as f32;
+            let expected = (advance + kerning(&face, l, r) as f32) / units_per_em;
+
+            assert_eq!(glyphs.len(), 2);
+            assert_eq!(glyphs[0].position, Vec2::ZERO);
+            assert_eq!(glyphs[1].position, vec2(expected, 0.0));
+        }
+    }
+
+    #[test]
+    fn monospace_face_has_uniform_advances() {
+        let face = Face::parse(MONONOKI, 0).unwrap();
+        let narrow = glyph_metrics(&face, glyph(&face, 'i')).unwrap();
+        let wide = glyph_metrics(&face, glyph(&face, 'W')).unwrap();
+        assert_eq!(narrow.advance, wide.advance);
+    }
+
+    #[test]
+    fn layout_breaks_lines() {
+        let face = Face::parse(MONONOKI, 0).unwrap();
+        let glyphs = layout_text(&face, "a\nb");
+        assert_eq!(glyphs.len(), 2);
+        assert_eq!(glyphs[1].position.x, 0.0);
+        assert!(glyphs[1].position.y < 0.0);
+    }
+
+    #[test]
+    fn glyph_cache_fills_cells_before_growing() {
+        // 100x100 cells in a 400x400 texture
+        let mut cache = GlyphCache::new(UVec2::splat(99), 400).unwrap();
+        assert_eq!(cache.size, 400);
+        cache.begin();
+
+        let mut origins = HashSet::new();
+        for glyph in 0..16 {
+            let slot = cache.insert(glyph).unwrap();
+            assert!(!slot.grew && !slot.evicted);
+            assert!(slot.origin.max_element() < 400);
+            assert!(origins.insert(slot.origin));
+        }
+
+        assert_eq!(cache.get(3), Some(cache.slots[&3].origin));
+        assert_eq!(cache.get(16), None);
+    }
+
+    #[test]
+    fn glyph_cache_grows_to_max_size() {
+        let mut cache = GlyphCache::new(UVec2::splat(99), 800).unwrap();
+        assert_eq!(cache.size, INITIAL_ATLAS_SIZE);
+        cache.begin();
+
+        // 512 / 100 = 5 cells to a side
+        let mut origins = HashSet::new();
+        for glyph in 0..25 {
+            let slot = cache.insert(glyph).unwrap();
+            assert!(!slot.grew);
+            assert!(origins.insert(slot.origin));
+        }
+
+        let slot = cache.insert(25).unwrap();
+        assert!(slot.grew && !slot.evicted);
+        assert_eq!(cache.size, 800);
+        assert!(origins.insert(slot.origin));
+
+        // 800 / 100 = 8 cells to a side, without reusing the old cells
+        for glyph in 26..64 {
+            let slot = cache.insert(glyph).unwrap();
+            assert!(!slot.grew);
+            assert!(slot.origin.max_element() < 800);
+            assert!(origins.insert(slot.origin));
+        }
+
+        assert_eq!(cache.insert(64), None);
+    }
+
+    #[test]
+    fn glyph_cache_evicts_least_recently_used() {
+        let mut cache = GlyphCache::new(UVec2::splat(99), 400).unwrap();
+        for glyph in 0..16 {
+            cache.begin();
+            cache.insert(glyph).unwrap();
+        }
+
+        cache.begin();
+        let kept = cache.get(0).unwrap();
+        let slot = cache.insert(16).unwrap();
+        assert!(slot.evicted && !slot.grew);
+        assert_ne!(slot.origin, kept);
+        assert_eq!(cache.get(1), None);
+        assert_eq!(cache.get(0), Some(kept));
+
+        // glyphs can't evict others from the same batch
+        for glyph in 17..31 {
+            cache.insert(glyph).unwrap();
+        }
+
+        assert_eq!(cache.insert(31), None);
+    }
+
+    #[test]
+    fn glyph_cache_rejects_oversized_glyphs() {
+        assert!(GlyphCache::new(UVec2::new(400, 10), 400).is_none());
+
+        // the texture starts large enough for at least one glyph
+        let cache = GlyphCache::new(UVec2::splat(999), 2048).unwrap();
+        assert_eq!(cache.size, 1024);
+        assert_eq!(cache.free.len(), 1);
+    }
 }
@@ -0,0 +1,213 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    sync::{
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Runs jobs on a background thread, keeping only the newest result.
+///
+/// Jobs that are replaced by a newer job before the thread gets to them are
+/// skipped, so a slow job never builds up a backlog.
+pub struct CoalescingWorker<J, O> {
+    jobs: Mutex<Sender<J>>,
+    output: Arc<Mutex<Option<O>>>,
+}
+
+impl<J: Send + 'static, O: Send + 'static> CoalescingWorker<J, O> {
+    /// Spawns a worker thread that runs `work` on submitted jobs.
+    ///
+    /// The thread exits once the worker is dropped.
+    pub fn new(name: &str, mut work: impl FnMut(J) -> O + Send + 'static) -> Self {
+        let (jobs, jobs_rx) = channel::<J>();
+        let output = Arc::new(Mutex::new(None));
+        let thread_output = output.clone();
+
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while let Ok(mut job) = jobs_rx.recv() {
+                    // skip to the newest job
+                    while let Ok(newer) = jobs_rx.try_recv() {
+                        job = newer;
+                    }
+
+                    let result = work(job);
+                    *thread_output.lock().unwrap() = Some(result);
+                }
+            })
+            .expect("failed to spawn worker thread");
+
+        Self {
+            jobs: Mutex::new(jobs),
+            output,
+        }
+    }
+
+    /// Queues a job, replacing any queued jobs that haven't started yet.
+    pub fn submit(&self, job: J) {
+        // the thread only exits once the sender is dropped
+        let _ = self.jobs.lock().unwrap().send(job);
+    }
+
+    /// Takes the result of the newest finished job, if there's a new one
+    /// since the last call.
+    pub fn take(&self) -> Option<O> {
+        self.output.lock().unwrap().take()
+    }
+}
+
+/// Runs a callback on a background thread once submitted values stop
+/// changing for a while, with only the newest value.
+///
+/// A value still pending when the debouncer is dropped is run immediately.
+pub struct Debouncer<T> {
+    values: Mutex<Sender<T>>,
+}
+
+impl<T: Send + 'static> Debouncer<T> {
+    /// Spawns a thread that runs `f` on values that haven't been replaced by
+    /// a newer value within `delay`.
+    pub fn new(name: &str, delay: Duration, mut f: impl FnMut(T) + Send + 'static) -> Self {
+        let (values, values_rx) = channel::<T>();
+
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                while let Ok(mut value) = values_rx.recv() {
+                    loop {
+                        match values_rx.recv_timeout(delay) {
+                            Ok(newer) => value = newer,
+                            Err(RecvTimeoutError::Timeout) => break,
+                            Err(RecvTimeoutError::Disconnected) => {
+                                f(value);
+                                return;
+                            }
+                        }
+                    }
+
+                    f(value);
+                }
+            })
+            .expect("failed to spawn debouncer thread");
+
+        Self {
+            values: Mutex::new(values),
+        }
+    }
+
+    /// Submits a value, restarting the delay.
+    pub fn submit(&self, value: T) {
+        // the thread only exits once the sender is dropped
+        let _ = self.values.lock().unwrap().send(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// Polls a worker until it has a result or a second has passed.
+    fn wait_for<J: Send + 'static, O: Send + 'static>(worker: &CoalescingWorker<J, O>) -> O {
+        let start = Instant::now();
+        loop {
+            if let Some(output) = worker.take() {
+                return output;
+            }
+
+            assert!(start.elapsed() < Duration::from_secs(1), "worker timed out");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn runs_jobs() {
+        let worker = CoalescingWorker::new("test worker", |job: u32| job * 2);
+        worker.submit(21);
+        assert_eq!(wait_for(&worker), 42);
+        assert_eq!(worker.take(), None);
+    }
+
+    #[test]
+    fn stale_jobs_are_skipped() {
+        let worker = CoalescingWorker::new("test worker", |job: u32| {
+            std::thread::sleep(Duration::from_millis(20));
+            job
+        });
+
+        let mut results = Vec::new();
+        for job in 0..50 {
+            worker.submit(job);
+            results.extend(worker.take());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // the newest job always finishes last
+        while results.last() != Some(&49) {
+            results.push(wait_for(&worker));
+        }
+
+        assert!(results.len() < 25, "{} jobs ran", results.len());
+        assert!(results.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn debouncer_keeps_newest_value() {
+        let (tx, rx) = channel();
+        let debouncer = Debouncer::new("test debouncer", Duration::from_secs(60), move |v| {
+            tx.send(v).unwrap();
+        });
+
+        // every value is replaced well within the delay
+        for value in 0..20u32 {
+            debouncer.submit(value);
+        }
+
+        drop(debouncer);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![19]);
+    }
+
+    #[test]
+    fn debouncer_runs_after_delay() {
+        let (tx, rx) = channel();
+        let debouncer = Debouncer::new("test debouncer", Duration::from_millis(10), move |v| {
+            tx.send(v).unwrap();
+        });
+
+        debouncer.submit(7u32);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(7));
+    }
+
+    #[test]
+    fn debouncer_flushes_on_drop() {
+        let (tx, rx) = channel();
+        let debouncer = Debouncer::new("test debouncer", Duration::from_secs(60), move |v| {
+            tx.send(v).unwrap();
+        });
+
+        debouncer.submit(7u32);
+        drop(debouncer);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(7));
+    }
+}
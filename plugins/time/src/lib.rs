@@ -32,9 +32,13 @@ use hearth_runtime::{
     },
 };
 
+/// Scheduled and periodic message delivery.
+pub mod scheduler;
+
 /// A plugin that provides timing services to guests.
 ///
-/// Adds the [SleepService], [TimerFactory], and [StopwatchFactory] services.
+/// Adds the [SleepService], [TimerFactory], [StopwatchFactory], and
+/// [scheduler::TimerService] services.
 #[derive(Default)]
 pub struct TimePlugin;
 
@@ -43,7 +47,8 @@ impl Plugin for TimePlugin {
         builder
             .add_plugin(SleepService)
             .add_plugin(TimerFactory)
-            .add_plugin(StopwatchFactory);
+            .add_plugin(StopwatchFactory)
+            .add_plugin(scheduler::TimerService);
     }
 }
 
@@ -0,0 +1,405 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::sync::Arc;
+
+use hearth_runtime::{
+    async_trait, cargo_process_metadata,
+    flue::{CapabilityHandle, CapabilityRef, OwnedTableSignal, Permissions, Table},
+    hearth_schema::timer::*,
+    process::{Process, ProcessMetadata},
+    runtime::Runtime,
+    tokio::{
+        self,
+        time::{Duration, Instant},
+    },
+    tracing::debug,
+    utils::{ProcessRunner, ServiceRunner},
+};
+
+/// Delivers messages to capabilities after a delay or periodically.
+///
+/// All timers are driven by this single process from one deadline queue, so
+/// idle timers only cost their queue entries.
+pub struct TimerService;
+
+#[async_trait]
+impl ProcessRunner for TimerService {
+    async fn run(mut self, label: String, _runtime: Arc<Runtime>, ctx: &Process) {
+        let table = ctx.borrow_table();
+        let mut timers = TimerQueue::default();
+
+        loop {
+            let next = timers.next_deadline();
+            let recv = ctx.borrow_parent().recv_owned();
+
+            let signal = tokio::select! {
+                _ = tokio::time::sleep_until(next.unwrap_or_else(Instant::now)), if next.is_some() => {
+                    timers.fire(table, Instant::now()).await;
+                    continue;
+                }
+                signal = recv => signal,
+            };
+
+            match signal {
+                Some(OwnedTableSignal::Message { data, caps }) => {
+                    let Some(reply) = caps.first() else {
+                        debug!("Request to {:?} has no reply address", label);
+                        continue;
+                    };
+
+                    let response = match serde_json::from_slice(&data) {
+                        Ok(request) => timers.on_request(table, ctx, request, caps.get(1)),
+                        Err(err) => {
+                            debug!("{:?} failed to parse request: {:?}", label, err);
+                            continue;
+                        }
+                    };
+
+                    let data = serde_json::to_vec(&response).unwrap();
+                    if let Err(err) = reply.send(&data, &[]).await {
+                        debug!("{:?} reply error: {:?}", label, err);
+                    }
+                }
+                Some(OwnedTableSignal::Down { handle }) => {
+                    let key = key_of(&handle);
+                    timers.cancel_target(table, key);
+                    table.dec_ref(key).unwrap();
+                }
+                None => break,
+            }
+        }
+
+        timers.cancel_all(table);
+    }
+}
+
+impl ServiceRunner for TimerService {
+    const NAME: &'static str = TIMER_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        cargo_process_metadata!()
+    }
+}
+
+/// Gets a permissionless handle identifying a capability's route.
+///
+/// The returned handle holds a reference that must be manually freed.
+fn key_of(cap: &CapabilityRef) -> CapabilityHandle {
+    cap.demote(Permissions::empty()).unwrap().into_handle()
+}
+
+/// A single scheduled delivery.
+struct Timer {
+    /// A permissionless handle identifying the target.
+    key: CapabilityHandle,
+
+    /// A send-only handle to the target.
+    target: CapabilityHandle,
+
+    payload: Vec<u8>,
+    deadline: Instant,
+    period: Option<Duration>,
+}
+
+/// The set of active timers, ordered by deadline.
+#[derive(Default)]
+struct TimerQueue {
+    timers: HashMap<u64, Timer>,
+
+    /// A min-heap of deadlines and timer IDs. Entries for cancelled or
+    /// rescheduled timers are left in place and skipped when popped.
+    deadlines: BinaryHeap<Reverse<(Instant, u64)>>,
+
+    next_id: u64,
+}
+
+impl TimerQueue {
+    fn on_request(
+        &mut self,
+        table: &Table,
+        ctx: &Process,
+        request: TimerRequest,
+        target: Option<&CapabilityRef>,
+    ) -> TimerResponse {
+        let target = target.ok_or(TimerError::MissingTarget)?;
+
+        let (delay, period, payload) = match request {
+            TimerRequest::SendAfter { millis, payload } => {
+                (Duration::from_millis(millis), None, payload)
+            }
+            TimerRequest::SendInterval {
+                period_millis: 0, ..
+            } => return Err(TimerError::ZeroPeriod),
+            TimerRequest::SendInterval {
+                period_millis,
+                payload,
+            } => {
+                let period = Duration::from_millis(period_millis);
+                (period, Some(period), payload)
+            }
+            TimerRequest::Cancel { id } => {
+                let key = key_of(target);
+                let result = self.cancel(table, id, key);
+                table.dec_ref(key).unwrap();
+                return result;
+            }
+        };
+
+        if !target.get_permissions().contains(Permissions::SEND) {
+            return Err(TimerError::TargetNotSendable);
+        }
+
+        if delay > Duration::from_millis(MAX_TIMER_MILLIS) {
+            return Err(TimerError::TooLong);
+        }
+
+        let deadline = Instant::now()
+            .checked_add(delay)
+            .ok_or(TimerError::TooLong)?;
+
+        if target.get_permissions().contains(Permissions::MONITOR) {
+            target.monitor(ctx.borrow_parent()).unwrap();
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let timer = Timer {
+            key: key_of(target),
+            target: target.demote(Permissions::SEND).unwrap().into_handle(),
+            payload,
+            deadline,
+            period,
+        };
+
+        self.deadlines.push(Reverse((timer.deadline, id)));
+        self.timers.insert(id, timer);
+        Ok(id)
+    }
+
+    /// Gets the earliest deadline of any timer.
+    fn next_deadline(&mut self) -> Option<Instant> {
+        // discard stale entries so that we don't wake up for nothing
+        while let Some(Reverse((deadline, id))) = self.deadlines.peek() {
+            match self.timers.get(id) {
+                Some(timer) if timer.deadline == *deadline => return Some(*deadline),
+                _ => {
+                    self.deadlines.pop();
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Delivers every timer whose deadline has passed, in deadline order.
+    async fn fire(&mut self, table: &Table, now: Instant) {
+        while let Some(deadline) = self.next_deadline() {
+            if deadline > now {
+                break;
+            }
+
+            let Reverse((_, id)) = self.deadlines.pop().unwrap();
+            let timer = self.timers.get_mut(&id).unwrap();
+
+            if let Err(err) = table.send(timer.target, &timer.payload, &[]).await {
+                debug!("Timer {} delivery error: {:?}", id, err);
+                self.remove(table, id);
+                continue;
+            }
+
+            let Some(period) = timer.period else {
+                self.remove(table, id);
+                continue;
+            };
+
+            // skip any ticks that were missed entirely
+            let mut next = timer.deadline.checked_add(period);
+            while let Some(deadline) = next.filter(|deadline| *deadline <= now) {
+                next = deadline.checked_add(period);
+            }
+
+            let Some(next) = next else {
+                debug!("Timer {} can't be scheduled any further", id);
+                self.remove(table, id);
+                continue;
+            };
+
+            timer.deadline = next;
+            self.deadlines.push(Reverse((next, id)));
+        }
+    }
+
+    /// Cancels a timer if its target matches `key`.
+    fn cancel(&mut self, table: &Table, id: u64, key: CapabilityHandle) -> TimerResponse {
+        match self.timers.get(&id) {
+            Some(timer) if timer.key == key => {
+                self.remove(table, id);
+                Ok(id)
+            }
+            _ => Err(TimerError::NotFound),
+        }
+    }
+
+    /// Cancels every timer targeting `key`.
+    fn cancel_target(&mut self, table: &Table, key: CapabilityHandle) {
+        let ids: Vec<u64> = self
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.key == key)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in ids {
+            self.remove(table, id);
+        }
+    }
+
+    fn cancel_all(&mut self, table: &Table) {
+        let ids: Vec<u64> = self.timers.keys().copied().collect();
+        for id in ids {
+            self.remove(table, id);
+        }
+    }
+
+    fn remove(&mut self, table: &Table, id: u64) {
+        if let Some(timer) = self.timers.remove(&id) {
+            table.dec_ref(timer.key).unwrap();
+            table.dec_ref(timer.target).unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_runtime::testing::{TestMailbox, TestRuntime, TestRuntimeBuilder};
+
+    fn runtime() -> TestRuntime {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(TimerService);
+        builder.build()
+    }
+
+    fn request(
+        runtime: &TestRuntime,
+        target: &TestMailbox,
+        request: TimerRequest,
+    ) -> TimerResponse {
+        let service = runtime.get_service(TIMER_SERVICE).unwrap();
+        let target = target.capability(Permissions::SEND | Permissions::MONITOR);
+        runtime.request(&service, &request, &[&target]).0
+    }
+
+    fn send_after(millis: u64, payload: &str) -> TimerRequest {
+        TimerRequest::SendAfter {
+            millis,
+            payload: serde_json::to_vec(payload).unwrap(),
+        }
+    }
+
+    #[test]
+    fn fires_in_deadline_order() {
+        let runtime = runtime();
+        let target = runtime.mailbox();
+
+        let start = runtime.block_on(async { Instant::now() });
+        for (millis, payload) in [(300, "third"), (100, "first"), (200, "second")] {
+            request(&runtime, &target, send_after(millis, payload)).unwrap();
+        }
+
+        for expected in ["first", "second", "third"] {
+            assert_eq!(target.recv_json::<String>().0, expected);
+        }
+
+        let elapsed = runtime.block_on(async { start.elapsed() });
+        assert!(elapsed >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn rejects_unrepresentable_delays() {
+        let runtime = runtime();
+        let target = runtime.mailbox();
+
+        let response = request(&runtime, &target, send_after(u64::MAX, "never"));
+        assert_eq!(response, Err(TimerError::TooLong));
+
+        let interval = TimerRequest::SendInterval {
+            period_millis: u64::MAX,
+            payload: serde_json::to_vec("never").unwrap(),
+        };
+
+        let response = request(&runtime, &target, interval);
+        assert_eq!(response, Err(TimerError::TooLong));
+    }
+
+    #[test]
+    fn interval_repeats_until_cancelled() {
+        let runtime = runtime();
+        let target = runtime.mailbox();
+
+        let interval = TimerRequest::SendInterval {
+            period_millis: 100,
+            payload: serde_json::to_vec("tick").unwrap(),
+        };
+
+        let id = request(&runtime, &target, interval).unwrap();
+        for _ in 0..3 {
+            assert_eq!(target.recv_json::<String>().0, "tick");
+        }
+
+        let cancel = TimerRequest::Cancel { id };
+        assert_eq!(request(&runtime, &target, cancel), Ok(id));
+
+        // the next message must be this one, not another tick
+        request(&runtime, &target, send_after(1000, "done")).unwrap();
+        assert_eq!(target.recv_json::<String>().0, "done");
+    }
+
+    #[test]
+    fn cancel_requires_target() {
+        let runtime = runtime();
+        let target = runtime.mailbox();
+        let other = runtime.mailbox();
+
+        let id = request(&runtime, &target, send_after(100, "mine")).unwrap();
+        let cancel = TimerRequest::Cancel { id };
+        assert_eq!(request(&runtime, &other, cancel), Err(TimerError::NotFound));
+        assert_eq!(target.recv_json::<String>().0, "mine");
+    }
+
+    #[test]
+    fn zero_period_is_refused() {
+        let runtime = runtime();
+        let target = runtime.mailbox();
+
+        let interval = TimerRequest::SendInterval {
+            period_millis: 0,
+            payload: vec![],
+        };
+
+        assert_eq!(
+            request(&runtime, &target, interval),
+            Err(TimerError::ZeroPeriod)
+        );
+    }
+}
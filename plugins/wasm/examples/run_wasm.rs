@@ -9,14 +9,14 @@ use tracing::info;
 
 #[tokio::main]
 async fn main() {
-    hearth_runtime::init_logging();
+    hearth_runtime::init_logging(&Default::default());
 
     let wasm_path = std::env::args()
         .nth(1)
         .expect("expected path to .wasm file");
     let wasm_data = std::fs::read(wasm_path).unwrap();
 
-    let config = RuntimeConfig {};
+    let config = RuntimeConfig::default();
 
     let config_path = hearth_runtime::get_config_path();
     let config_file = hearth_runtime::load_config(&config_path).unwrap();
@@ -28,6 +28,9 @@ async fn main() {
     let spawn_info = WasmSpawnInfo {
         lump: wasm_lump,
         entrypoint: None,
+        limits: Default::default(),
+        keep_awake: false,
+        allow: None,
     };
 
     let meta = cargo_process_metadata!();
@@ -0,0 +1,277 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Process groups for Wasm guests.
+
+use std::sync::Arc;
+
+use hearth_macros::impl_wasm_linker;
+use hearth_runtime::anyhow::Result;
+use hearth_runtime::flue::{MailboxGroup, Permissions, Table};
+use hearth_runtime::group::{GroupHandle, ProcessGroup};
+use hearth_runtime::process::{Process, ProcessMetadata};
+use hearth_runtime::runtime::Runtime;
+use hearth_runtime::{async_trait, cargo_process_metadata, hearth_schema, tokio, utils::*};
+use hearth_schema::group::*;
+use tracing::{debug, error, Instrument};
+use wasmtime::{Caller, Linker};
+
+use crate::{GetAbi, WasmLinker, WasmProcessSpawner};
+
+/// Serves a [ProcessGroup] to guests. Accepts [GroupRequest].
+///
+/// The controller that owns a group tears it down when it exits.
+pub struct GroupController {
+    group: Arc<ProcessGroup>,
+    spawner: WasmProcessSpawner,
+    _handle: Option<GroupHandle>,
+}
+
+impl GroupController {
+    /// Creates a controller that owns a group.
+    pub fn owner(handle: GroupHandle, spawner: WasmProcessSpawner) -> Self {
+        Self {
+            group: handle.group().clone(),
+            spawner,
+            _handle: Some(handle),
+        }
+    }
+
+    /// Creates a controller for a group that it doesn't own.
+    pub fn shared(group: Arc<ProcessGroup>, spawner: WasmProcessSpawner) -> Self {
+        Self {
+            group,
+            spawner,
+            _handle: None,
+        }
+    }
+
+    /// Creates metadata for a controller of a group.
+    fn metadata(group: &ProcessGroup) -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.name = Some(format!("GroupController {}", group.id()));
+        meta.description = Some("Controls a process group. Accepts GroupRequest.".to_string());
+        meta
+    }
+}
+
+#[async_trait]
+impl RequestResponseProcess for GroupController {
+    type Request = GroupRequest;
+    type Response = GroupResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, GroupRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match &request.data {
+            GroupRequest::Spawn(_) | GroupRequest::Registry if self.group.is_torn_down() => {
+                GroupError::TornDown.into()
+            }
+            GroupRequest::Spawn(info) => {
+                let group = Some(self.group.clone());
+                let result = self
+                    .spawner
+                    .spawn_lump_in(
+                        request.runtime,
+                        request.process,
+                        info,
+                        request.cap_args,
+                        group,
+                    )
+                    .await;
+
+                match result {
+                    Ok(spawned) => ResponseInfo {
+                        data: Ok(GroupSuccess::Spawned),
+                        caps: vec![spawned.process, spawned.endpoint],
+                    },
+                    Err(err) => {
+                        error!("Wasm group spawning error: {:?}", err);
+                        GroupError::SpawnFailed(format!("{:#}", err)).into()
+                    }
+                }
+            }
+            GroupRequest::List => Ok(GroupSuccess::Members(self.group.members())).into(),
+            GroupRequest::KillAll => {
+                let requester = self.spawner.requester(request.process);
+                let killed = self.group.kill_all(requester);
+                Ok(GroupSuccess::Killed(killed.len())).into()
+            }
+            GroupRequest::Registry => {
+                let table = request.process.borrow_table();
+                let parent = request
+                    .runtime
+                    .registry
+                    .borrow_parent()
+                    .export_to(Permissions::SEND, table)
+                    .unwrap()
+                    .to_owned();
+
+                let requester = self.spawner.requester(request.process);
+                let view = self.group.registry_view(parent).with_requester(requester);
+                let mut meta = cargo_process_metadata!();
+                meta.name = Some(format!("group {} registry", self.group.id()));
+                meta.description =
+                    Some("A process group's registry. Accepts RegistryRequest.".to_string());
+
+                let view = request.spawn(meta, view);
+                let view = view
+                    .demote(Permissions::SEND | Permissions::MONITOR)
+                    .unwrap();
+
+                ResponseInfo {
+                    data: Ok(GroupSuccess::Registry),
+                    caps: vec![view],
+                }
+            }
+        }
+    }
+}
+
+/// Implements the `hearth::group` ABI module.
+pub struct GroupAbi {
+    runtime: Arc<Runtime>,
+    process: Arc<Process>,
+    spawner: WasmProcessSpawner,
+}
+
+#[impl_wasm_linker(module = "hearth::group")]
+impl GroupAbi {
+    /// Creates a new process group and returns a capability to its owning
+    /// controller in this process's table.
+    ///
+    /// The group is nested in this process's group, if it has one. It's torn
+    /// down when the controller is killed or when this process exits.
+    fn create(&self) -> Result<u32> {
+        let factory = &self.runtime.process_factory;
+        let current = self.process.borrow_info().group.clone();
+        let handle = factory.create_group(current.as_deref());
+        let meta = GroupController::metadata(&handle);
+        let runner = GroupController::owner(handle, self.spawner.clone());
+
+        // the controller joins this process's group, so tearing that group
+        // down tears the new one down too
+        let controller = factory.spawn_child(meta, &self.process, None);
+        self.kill_on_exit(&controller);
+
+        let perms = Permissions::SEND | Permissions::MONITOR | Permissions::KILL;
+        Ok(self.run(controller, runner, perms))
+    }
+
+    /// Returns a capability to a spawner for this process's children, which
+    /// accepts [WasmSpawnerRequest](hearth_schema::wasm::WasmSpawnerRequest).
+    ///
+    /// Unlike the shared spawner service, this spawner knows who it spawns
+    /// for: its spawns are audited as requested by this process and may only
+    /// grant this process's ABI policy. It's killed when this process exits.
+    fn spawner(&self) -> Result<u32> {
+        let pid = self.process.borrow_info().pid;
+        let mut meta = WasmProcessSpawner::get_process_metadata();
+        meta.name = Some(format!("spawner for PID {}", pid));
+
+        let factory = &self.runtime.process_factory;
+        let spawner = factory.spawn_child(meta, &self.process, None);
+        self.kill_on_exit(&spawner);
+
+        let runner = self.spawner.clone();
+        Ok(self.run(spawner, runner, Permissions::SEND | Permissions::MONITOR))
+    }
+
+    /// Returns a capability to a controller of this process's group, or
+    /// `u32::MAX` (or `0xFFFFFFFF`) if this process isn't in a group.
+    ///
+    /// The capability can't tear the group down.
+    fn current(&self) -> Result<u32> {
+        let Some(group) = self.process.borrow_info().group.clone() else {
+            return Ok(u32::MAX);
+        };
+
+        let meta = GroupController::metadata(&group);
+        let runner = GroupController::shared(group, self.spawner.clone());
+        let requester = self.process.borrow_info().audit_actor();
+        let factory = &self.runtime.process_factory;
+        let controller = factory.spawn_for(meta, requester, None);
+        Ok(self.run(controller, runner, Permissions::SEND | Permissions::MONITOR))
+    }
+}
+
+impl GroupAbi {
+    pub fn new(runtime: Arc<Runtime>, process: Arc<Process>, spawner: WasmProcessSpawner) -> Self {
+        Self {
+            runtime,
+            process,
+            spawner,
+        }
+    }
+
+    /// Runs a helper process for this process, such as a group controller,
+    /// and returns a capability to it in this process's table.
+    fn run(
+        &self,
+        helper: Process,
+        runner: impl ProcessRunner + 'static,
+        perms: Permissions,
+    ) -> u32 {
+        let cap = helper
+            .borrow_parent()
+            .export_to(perms, self.process.borrow_table())
+            .unwrap()
+            .into_handle()
+            .0;
+
+        let label = helper.borrow_info().meta.name.clone();
+        let label = label.unwrap_or_else(|| "<no name>".to_string());
+        let runtime = self.runtime.clone();
+        let span = helper.span();
+        tokio::spawn(
+            async move {
+                runner.run(label, runtime, &helper).await;
+            }
+            .instrument(span),
+        );
+
+        cap.try_into().unwrap()
+    }
+
+    /// Kills a helper process once this process exits.
+    fn kill_on_exit(&self, helper: &Process) {
+        let table = Table::new(self.runtime.post.clone());
+        let perms = Permissions::MONITOR;
+        let owner = self.process.borrow_parent().export_to(perms, &table);
+        let owner = owner.unwrap().into_handle();
+        let perms = Permissions::KILL;
+        let helper = helper.borrow_parent().export_to(perms, &table);
+        let helper = helper.unwrap().into_handle();
+
+        tokio::spawn(async move {
+            let group = MailboxGroup::new(&table);
+            let mailbox = group.create_mailbox().unwrap();
+            let owner = table.wrap_handle(owner).unwrap();
+            owner.monitor(&mailbox).unwrap();
+            drop(owner);
+
+            // nothing else can reach this mailbox, so any signal is the down
+            mailbox.recv(|_| ()).await;
+            debug!("process group owner exited; killing its helper");
+
+            // fails if the helper has already been killed
+            let _ = table.kill(helper);
+        });
+    }
+}
@@ -0,0 +1,231 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Hibernation of idle Wasm processes.
+//!
+//! When [WasmConfig::hibernate_after] is set, a process that waits on its
+//! parent mailbox for that long with no other mailboxes open is sent a
+//! [HibernationMessage::Hibernate] on its parent mailbox. If the process
+//! agrees to hibernate, its store and instance are dropped. Its table and
+//! parent mailbox are kept, so its capabilities and registry entries stay
+//! valid.
+//!
+//! The next signal to a hibernated process's parent mailbox wakes it: its
+//! module is instantiated again and runs from its entrypoint, and the signal
+//! is the first that it receives. If it saved its state to a lump before
+//! hibernating, a [HibernationMessage::Wake] with that lump comes first.
+//!
+//! Hibernation is opt-in: only guests that set [GuestMetadata::hibernates]
+//! in their embedded metadata are asked, and processes spawned with
+//! [WasmSpawnInfo::keep_awake] are never asked at all. A process that waits
+//! on its parent mailbox again without replying is never asked again.
+//!
+//! [WasmConfig::hibernate_after]: crate::limits::WasmConfig::hibernate_after
+//! [GuestMetadata::hibernates]: hearth_schema::wasm::GuestMetadata::hibernates
+//! [WasmSpawnInfo::keep_awake]: hearth_schema::wasm::WasmSpawnInfo::keep_awake
+
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hearth_runtime::process::ProcessMetadata;
+use hearth_runtime::{async_trait, hearth_schema};
+use hearth_runtime::{cargo_process_metadata, utils::*};
+use hearth_schema::wasm::*;
+use hearth_schema::LumpId;
+
+/// The error that a process's host calls return to unwind it when it's
+/// ready to hibernate.
+#[derive(Debug)]
+pub struct Hibernating {
+    /// The lump that the process saved its state to, if any.
+    pub state: Option<LumpId>,
+}
+
+impl Display for Hibernating {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "process is hibernating")
+    }
+}
+
+impl std::error::Error for Hibernating {}
+
+/// What to do with a process after asking it to hibernate.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decision {
+    /// Hibernate the process with the lump that it saved its state to.
+    Hibernate(Option<LumpId>),
+
+    /// Keep the process awake and ask it again after another idle period.
+    StayAwake,
+
+    /// The process doesn't understand hibernation, so never hibernate it.
+    Never,
+}
+
+impl Decision {
+    /// Decides what to do with a process from the data of its reply to
+    /// [HibernationMessage::Hibernate], or `None` if it didn't reply.
+    pub fn from_reply(reply: Option<&[u8]>) -> Self {
+        // guests that don't know about hibernation ignore the request, and
+        // would lose their state if they were hibernated anyway
+        let Some(reply) = reply else {
+            return Decision::Never;
+        };
+
+        match serde_json::from_slice(reply) {
+            Ok(HibernationMessage::Hibernated(state)) => Decision::Hibernate(state),
+            Ok(HibernationMessage::Refused) => Decision::StayAwake,
+            _ => Decision::Never,
+        }
+    }
+}
+
+/// Counters for the hibernation of every process spawned by a spawner.
+#[derive(Debug, Default)]
+pub struct HibernationCounters {
+    hibernated: AtomicU64,
+    wakes: AtomicU64,
+    total_wake_us: AtomicU64,
+    max_wake_us: AtomicU64,
+}
+
+impl HibernationCounters {
+    /// Records that a process was hibernated.
+    pub fn hibernated(&self) {
+        self.hibernated.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a hibernated process exited without waking.
+    pub fn exited(&self) {
+        self.hibernated.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Records that a hibernated process was woken and how long it took.
+    pub fn woke(&self, latency: Duration) {
+        let latency = latency.as_micros().try_into().unwrap_or(u64::MAX);
+        self.hibernated.fetch_sub(1, Ordering::Relaxed);
+        self.wakes.fetch_add(1, Ordering::Relaxed);
+        self.total_wake_us.fetch_add(latency, Ordering::Relaxed);
+        self.max_wake_us.fetch_max(latency, Ordering::Relaxed);
+    }
+
+    /// Gets the current counters.
+    pub fn get(&self) -> HibernationStats {
+        let wakes = self.wakes.load(Ordering::Relaxed);
+        let total_wake_us = self.total_wake_us.load(Ordering::Relaxed);
+
+        HibernationStats {
+            hibernated: self.hibernated.load(Ordering::Relaxed),
+            wakes,
+            mean_wake_latency_us: total_wake_us.checked_div(wakes).unwrap_or(0),
+            max_wake_latency_us: self.max_wake_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reports the hibernation of Wasm processes. Accepts
+/// [HibernationStatsRequest].
+pub struct HibernationStatsService {
+    pub(crate) counters: Arc<HibernationCounters>,
+}
+
+#[async_trait]
+impl RequestResponseProcess for HibernationStatsService {
+    type Request = HibernationStatsRequest;
+    type Response = HibernationStatsResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, HibernationStatsRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match request.data {
+            HibernationStatsRequest::Get => self.counters.get().into(),
+        }
+    }
+}
+
+impl ServiceRunner for HibernationStatsService {
+    const NAME: &'static str = HIBERNATION_STATS_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Reports the hibernation of idle Wasm processes. Accepts HibernationStatsRequest."
+                .to_string(),
+        );
+
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replies_decide_hibernation() {
+        let reply = |message: HibernationMessage| serde_json::to_vec(&message).unwrap();
+        let state = LumpId([1; 32]);
+
+        assert_eq!(Decision::from_reply(None), Decision::Never);
+
+        assert_eq!(
+            Decision::from_reply(Some(&reply(HibernationMessage::Hibernated(Some(state))))),
+            Decision::Hibernate(Some(state))
+        );
+
+        assert_eq!(
+            Decision::from_reply(Some(&reply(HibernationMessage::Refused))),
+            Decision::StayAwake
+        );
+
+        // echoing the request back or replying to it as a request are both
+        // signs that the guest doesn't know about hibernation
+        assert_eq!(
+            Decision::from_reply(Some(&reply(HibernationMessage::Hibernate))),
+            Decision::Never
+        );
+
+        assert_eq!(
+            Decision::from_reply(Some(b"{\"Err\":\"bad\"}")),
+            Decision::Never
+        );
+    }
+
+    #[test]
+    fn counters_track_wakes() {
+        let counters = HibernationCounters::default();
+        counters.hibernated();
+        counters.hibernated();
+        counters.woke(Duration::from_micros(100));
+        counters.woke(Duration::from_micros(300));
+        counters.hibernated();
+
+        assert_eq!(
+            counters.get(),
+            HibernationStats {
+                hibernated: 1,
+                wakes: 2,
+                mean_wake_latency_us: 200,
+                max_wake_latency_us: 300,
+            }
+        );
+    }
+}
@@ -16,24 +16,50 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with Hearth. If not, see <https://www.gnu.org/licenses/>.
 
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use group::GroupAbi;
 use hearth_macros::impl_wasm_linker;
 use hearth_runtime::anyhow::{anyhow, bail, Context, Result};
 use hearth_runtime::asset::{AssetLoader, AssetStore};
 use hearth_runtime::flue::{
     CapabilityHandle, CapabilityRef, Mailbox, MailboxGroup, Permissions, Table, TableSignal,
 };
+use hearth_runtime::group::ProcessGroup;
 use hearth_runtime::lump::{bytes::Bytes, LumpStoreImpl};
-use hearth_runtime::process::{Process, ProcessLogEvent, ProcessMetadata};
+use hearth_runtime::process::{Process, ProcessId, ProcessLogEvent, ProcessMetadata};
 use hearth_runtime::runtime::{Plugin, Runtime, RuntimeBuilder};
+use hearth_runtime::tokio::sync::oneshot;
 use hearth_runtime::{async_trait, hearth_schema};
 use hearth_runtime::{cargo_process_metadata, tokio, utils::*};
-use hearth_schema::wasm::WasmSpawnInfo;
+use hearth_schema::abi::HearthError;
+use hearth_schema::audit::{AuditActor, AuditEventKind};
+use hearth_schema::lump::{LumpMetadata, LumpOrigin};
+use hearth_schema::wasm::{
+    ExitReason, GuestMetadata, HibernationMessage, LinkRequest, WasmLimits, WasmSpawnError,
+    WasmSpawnInfo, WasmSpawnResponse, WasmSpawnerRequest, ABI_VERSION, METADATA_SECTION,
+};
 use hearth_schema::{LumpId, SignalKind};
+use hibernate::{Decision, Hibernating, HibernationCounters, HibernationStatsService};
+use limits::{ProcessLimiter, WasmConfig};
+use policy::AbiPolicy;
 use slab::Slab;
-use tracing::{error, warn};
-use wasmtime::{Caller, Config, Engine, Instance, Linker, Module, Store, UpdateDeadline};
+use tracing::{debug, error, warn, Instrument};
+use wasmtime::{
+    Caller, Config, Engine, Instance, InstancePre, Linker, Module, Store, UpdateDeadline,
+};
+
+pub mod group;
+pub mod hibernate;
+pub mod limits;
+pub mod link;
+pub mod migrate;
+pub mod policy;
+pub mod remote;
+pub mod upload;
 
 /// An interface to attempt to acquire a Wasm ABI by type.
 pub trait GetAbi<T>
@@ -53,6 +79,53 @@ pub trait WasmLinker<T: GetAbi<Self>>: Sized {
     fn add_to_linker(linker: &mut Linker<T>);
 }
 
+/// Declares a trait with the Wasm-level function signatures of an ABI module
+/// definition from [hearth_schema::abi].
+///
+/// Passing the trait to [impl_wasm_linker] as an ABI's `signatures`
+/// implements it for that ABI, so the ABI fails to compile unless its
+/// functions exactly match the definition that guests import.
+macro_rules! abi_signatures {
+    (
+        [$name:ident]
+        module = $module:literal;
+        $($(#[$meta:meta])* fn $fn_name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*
+    ) => {
+        #[doc = concat!("The function signatures of the `", $module, "` ABI module.")]
+        pub trait $name {
+            const MODULE: &'static str = $module;
+
+            $($(#[$meta])* fn $fn_name($($arg: $ty),*) $(-> $ret)?;)*
+        }
+    };
+}
+
+hearth_schema::lump_abi!(abi_signatures, LumpSignatures);
+
+/// The recoverable result of a fallible ABI function.
+///
+/// ABI functions that return `Result<AbiResult<T>>` trap on the outer error
+/// and return the inner one to the guest, encoded by [EncodeAbiResult].
+pub type AbiResult<T = ()> = std::result::Result<T, HearthError>;
+
+/// Encodes an [AbiResult] as the `i32` that its function returns to guests.
+pub trait EncodeAbiResult {
+    /// Encodes this result with [HearthError::encode].
+    fn encode(self) -> i32;
+}
+
+impl EncodeAbiResult for AbiResult<()> {
+    fn encode(self) -> i32 {
+        HearthError::encode(self.map(|()| 0))
+    }
+}
+
+impl EncodeAbiResult for AbiResult<u32> {
+    fn encode(self) -> i32 {
+        HearthError::encode(self)
+    }
+}
+
 /// A utility type for safely accessing and interpreting a Wasm guest's memory.
 pub struct GuestMemory<'a> {
     pub bytes: &'a mut [u8],
@@ -160,11 +233,11 @@ impl LogAbi {
             .try_into()
             .map_err(|_| anyhow!("invalid log level constant {}", level))?;
 
-        let event = ProcessLogEvent {
+        let event = ProcessLogEvent::new(
             level,
-            module: memory.get_str(module_ptr, module_len)?.to_string(),
-            content: memory.get_str(content_ptr, content_len)?.to_string(),
-        };
+            memory.get_str(module_ptr, module_len)?,
+            memory.get_str(content_ptr, content_len)?,
+        );
 
         self.process.borrow_info().log_tx.send(event)?;
 
@@ -192,7 +265,7 @@ pub struct LumpAbi {
     pub this_lump: LumpId,
 }
 
-#[impl_wasm_linker(module = "hearth::lump")]
+#[impl_wasm_linker(signatures = "LumpSignatures")]
 impl LumpAbi {
     /// Retrieves the [LumpId] of the WebAssembly module lump of the currently
     /// running process. Writes the result into the guest memory at the given
@@ -205,57 +278,72 @@ impl LumpAbi {
 
     /// Load a lump from its [LumpId], retrieved from guest memory via pointer.
     ///
-    /// Fails if the lump is not found in the lump store.
-    async fn load_by_id(&mut self, memory: GuestMemory<'_>, id_ptr: u32) -> Result<u32> {
+    /// Returns [HearthError::NotFound] if the lump is not found in the lump
+    /// store.
+    async fn load_by_id(&mut self, memory: GuestMemory<'_>, id_ptr: u32) -> Result<AbiResult<u32>> {
         let id: LumpId = *memory.get_memory_ref(id_ptr)?;
-        let bytes = self
-            .lump_store
-            .get_lump(&id)
-            .await
-            .ok_or_else(|| anyhow!("couldn't find {:?} in lump store", id))?;
-        Ok(self.lump_handles.insert(LocalLump { id, bytes }) as u32)
+        let Some(bytes) = self.lump_store.get_lump(&id).await else {
+            let err = format!("couldn't find {} in lump store", id);
+            return Ok(Err(HearthError::NotFound(err)));
+        };
+
+        Ok(Ok(self.lump_handles.insert(LocalLump { id, bytes }) as u32))
     }
 
     /// Loads a lump from guest memory.
     async fn load(&mut self, memory: GuestMemory<'_>, data_ptr: u32, data_len: u32) -> Result<u32> {
         let bytes: Bytes = memory.get_slice(data_ptr, data_len)?.to_vec().into();
-        let id = self.lump_store.add_lump(bytes.clone()).await;
+        let metadata = LumpMetadata {
+            origin: Some(LumpOrigin::Guest),
+            ..Default::default()
+        };
+
+        let id = self.lump_store.add_lump_with(bytes.clone(), metadata).await;
         let lump = LocalLump { id, bytes };
         let handle = self.lump_handles.insert(lump) as u32;
         Ok(handle)
     }
 
     /// Writes the [LumpId] of a loaded lump to guest memory via pointer.
-    fn get_id(&self, memory: GuestMemory<'_>, handle: u32, id_ptr: u32) -> Result<()> {
-        let lump = self.get_lump(handle)?;
+    fn get_id(&self, memory: GuestMemory<'_>, handle: u32, id_ptr: u32) -> Result<AbiResult> {
+        let lump = match self.get_lump(handle) {
+            Ok(lump) => lump,
+            Err(err) => return Ok(Err(err)),
+        };
+
         let id: &mut LumpId = memory.get_memory_ref(id_ptr)?;
         *id = lump.id;
-        Ok(())
+        Ok(Ok(()))
     }
 
     /// Gets the length of a loaded lump by handle.
-    fn get_len(&self, handle: u32) -> Result<u32> {
-        self.get_lump(handle).map(|lump| lump.bytes.len() as u32)
+    fn get_len(&self, handle: u32) -> Result<AbiResult<u32>> {
+        Ok(self.get_lump(handle).map(|lump| lump.bytes.len() as u32))
     }
 
     /// Copies the data of a loaded lump into guest memory by handle.
     ///
     /// The length required to copy the lump into guest memory can be accessed
     /// using [Self::get_len].
-    fn get_data(&self, memory: GuestMemory<'_>, handle: u32, data_ptr: u32) -> Result<()> {
-        let lump = self.get_lump(handle)?;
+    fn get_data(&self, memory: GuestMemory<'_>, handle: u32, data_ptr: u32) -> Result<AbiResult> {
+        let lump = match self.get_lump(handle) {
+            Ok(lump) => lump,
+            Err(err) => return Ok(Err(err)),
+        };
+
         let data_len = lump.bytes.len() as u32;
         let dst = memory.get_slice(data_ptr, data_len)?;
         dst.copy_from_slice(&lump.bytes);
-        Ok(())
+        Ok(Ok(()))
     }
 
     /// Unloads a lump by handle.
-    fn free(&mut self, handle: u32) -> Result<()> {
-        self.lump_handles
+    fn free(&mut self, handle: u32) -> Result<AbiResult> {
+        Ok(self
+            .lump_handles
             .try_remove(handle as usize)
             .map(|_| ())
-            .ok_or_else(|| anyhow!("lump handle {} is invalid", handle))
+            .ok_or_else(|| invalid_lump_handle(handle)))
     }
 }
 
@@ -269,13 +357,17 @@ impl LumpAbi {
     }
 
     /// Helper function to get a lump reference from a handle.
-    fn get_lump(&self, handle: u32) -> Result<&LocalLump> {
+    fn get_lump(&self, handle: u32) -> AbiResult<&LocalLump> {
         self.lump_handles
             .get(handle as usize)
-            .ok_or_else(|| anyhow!("lump handle {} is invalid", handle))
+            .ok_or_else(|| invalid_lump_handle(handle))
     }
 }
 
+fn invalid_lump_handle(handle: u32) -> HearthError {
+    HearthError::InvalidHandle(format!("lump handle {} is invalid", handle))
+}
+
 /// Implements the `hearth::table` ABI module.
 pub struct TableAbi {
     process: Arc<Process>,
@@ -333,10 +425,24 @@ impl TableAbi {
     /// Fails if the desired permissions are not a subset of the original's.
     fn demote(&self, handle: u32, perms: u32) -> Result<u32> {
         let perms = Permissions::from_bits(perms).context("unknown permission bits set")?;
+        let cap = CapabilityHandle(handle as usize);
+
+        let current = self
+            .as_ref()
+            .get_permissions(cap)
+            .with_context(|| format!("demote({handle})"))?;
+
+        if !current.contains(perms) {
+            bail!(
+                "demote({handle}): {:?} is not a subset of {:?}",
+                perms,
+                current
+            );
+        }
 
         let handle = self
             .as_ref()
-            .demote(CapabilityHandle(handle as usize), perms)
+            .demote(cap, perms)
             .with_context(|| format!("demote({handle})"))?;
 
         Ok(handle.0.try_into().unwrap())
@@ -364,12 +470,18 @@ impl TableAbi {
             .iter()
             .map(|cap| CapabilityHandle(*cap as usize))
             .collect();
-        self.process
-            .borrow_table()
+        let table = self.process.borrow_table();
+        table
             .send(CapabilityHandle(handle as usize), data, &caps)
             .await
             .with_context(|| format!("send({handle})"))?;
 
+        let tap = &self.process.borrow_info().tap;
+        if tap.is_recording() {
+            let caps: Vec<_> = caps.iter().map(|cap| cap.0).collect();
+            tap.record_outbound(table, handle as usize, data, &caps);
+        }
+
         Ok(())
     }
 
@@ -381,6 +493,8 @@ impl TableAbi {
             .kill(CapabilityHandle(handle as usize))
             .with_context(|| format!("kill({handle})"))?;
 
+        self.process.borrow_info().audit_kill(None);
+
         Ok(())
     }
 }
@@ -410,6 +524,10 @@ impl<'a> From<TableSignal<'a>> for Signal {
 struct MailboxArena<'a> {
     group: &'a MailboxGroup<'a>,
     mbs: Slab<Mailbox<'a>>,
+
+    /// The mailbox for the reply to a [HibernationMessage::Hibernate], if
+    /// the process has been asked to hibernate.
+    hook: Option<Mailbox<'a>>,
 }
 
 impl<'a> MailboxArena<'a> {
@@ -433,6 +551,13 @@ pub struct MailboxAbi {
     process: Arc<Process>,
     signals: Slab<Signal>,
 
+    /// Signals to receive on the parent mailbox before any others.
+    parked: VecDeque<Signal>,
+
+    /// How long the parent mailbox has to be idle before the process is
+    /// hibernated, or `None` if it's never hibernated.
+    hibernate_after: Option<Duration>,
+
     #[borrows(process)]
     #[covariant]
     arena: MailboxArena<'this>,
@@ -466,8 +591,12 @@ impl MailboxAbi {
     fn make_capability(&self, handle: u32, perms: u32) -> Result<u32> {
         let mb = self.get_mb(handle)?;
         let perms = Permissions::from_bits(perms).context("unknown permission bits set")?;
-        let cap = mb.export(perms).unwrap();
-        Ok(cap.into_handle().0.try_into().unwrap())
+        let cap = mb.export(perms).unwrap().into_handle().0;
+        self.borrow_process()
+            .borrow_info()
+            .tap
+            .note_export(cap, handle);
+        Ok(cap.try_into().unwrap())
     }
 
     /// Monitors a capability by its handle in this process's table. When the
@@ -484,15 +613,64 @@ impl MailboxAbi {
         Ok(())
     }
 
+    /// Links a mailbox to a process by the process's link endpoint
+    /// capability.
+    ///
+    /// When the process exits, the mailbox receives a message containing a
+    /// JSON-serialized `ProcessDown` with the process's ID and exit reason.
+    /// If the process has already exited, the message is sent immediately.
+    /// Linking the same mailbox to the same endpoint again has no effect.
+    async fn link(&self, mailbox: u32, endpoint: u32) -> Result<()> {
+        self.send_link_request(mailbox, endpoint, true)
+            .await
+            .with_context(|| format!("link(mailbox = {}, endpoint = {})", mailbox, endpoint))
+    }
+
+    /// Removes a link made with [Self::link].
+    async fn unlink(&self, mailbox: u32, endpoint: u32) -> Result<()> {
+        self.send_link_request(mailbox, endpoint, false)
+            .await
+            .with_context(|| format!("unlink(mailbox = {}, endpoint = {})", mailbox, endpoint))
+    }
+
     /// Waits for a signal to be received by a mailbox.
     async fn recv(&mut self, handle: u32) -> Result<u32> {
-        let mb = self.get_mb(handle)?;
+        let signal = match self.take_parked(handle) {
+            Some(signal) => signal,
+            None if handle == 0 => self.recv_parent().await?,
+            None => self
+                .get_mb(handle)?
+                .recv(|signal| Signal::from(signal))
+                .await
+                .context("process has been killed")?,
+        };
 
-        let signal = mb
-            .recv(|signal| Signal::from(signal))
-            .await
-            .context("process has been killed")?;
+        self.tap_signal(handle, &signal);
+        let handle = self.with_signals_mut(|signals| signals.insert(signal));
+
+        Ok(handle.try_into().unwrap())
+    }
+
+    /// Waits up to `timeout_ms` milliseconds for a signal to be received by a
+    /// mailbox.
+    ///
+    /// Returns `u32::MAX` (or `0xFFFFFFFF`) if the timeout passed first.
+    /// Otherwise, returns the handle to the received signal.
+    async fn recv_timeout(&mut self, handle: u32, timeout_ms: u64) -> Result<u32> {
+        let signal = match self.take_parked(handle) {
+            Some(signal) => signal,
+            None => {
+                let mb = self.get_mb(handle)?;
+                let timeout = Duration::from_millis(timeout_ms);
+
+                match recv_timeout(mb, timeout, |signal| Signal::from(signal)).await {
+                    Ok(signal) => signal.context("process has been killed")?,
+                    Err(Timeout) => return Ok(u32::MAX),
+                }
+            }
+        };
 
+        self.tap_signal(handle, &signal);
         let handle = self.with_signals_mut(|signals| signals.insert(signal));
 
         Ok(handle.try_into().unwrap())
@@ -503,14 +681,17 @@ impl MailboxAbi {
     /// Returns `u32::MAX` (or `0xFFFFFFFF`) if the mailbox's queue is empty.
     /// Otherwise, returns the handle to the received signal.
     fn try_recv(&mut self, handle: u32) -> Result<u32> {
-        let mb = self.get_mb(handle)?;
-
-        let signal = mb
-            .try_recv(|signal| Signal::from(signal))
-            .context("process has been killed")?;
+        let signal = match self.take_parked(handle) {
+            Some(signal) => Some(signal),
+            None => self
+                .get_mb(handle)?
+                .try_recv(|signal| Signal::from(signal))
+                .context("process has been killed")?,
+        };
 
         match signal {
             Some(signal) => {
+                self.tap_signal(handle, &signal);
                 let handle = self.with_signals_mut(|signals| signals.insert(signal));
                 Ok(handle.try_into().unwrap())
             }
@@ -533,7 +714,15 @@ impl MailboxAbi {
         handles_ptr: u32,
         handles_len: u32,
     ) -> Result<u64> {
-        let handles = memory.get_memory_slice(handles_ptr, handles_len)?;
+        let handles: &[u32] = memory.get_memory_slice(handles_ptr, handles_len)?;
+
+        if let Some(index) = handles.iter().position(|handle| *handle == 0) {
+            if let Some(signal) = self.take_parked(0) {
+                self.tap_signal(0, &signal);
+                let handle = self.with_signals_mut(|signals| signals.insert(signal));
+                return Ok(((index as u64) << 32) | (handle as u64));
+            }
+        }
 
         let mbs = handles
             .iter()
@@ -545,6 +734,7 @@ impl MailboxAbi {
 
         let (signal, index, _) = futures_util::future::select_all(mbs).await;
         let signal = signal.context("process has been killed")?;
+        self.tap_signal(handles[index], &signal);
         let handle = self.with_signals_mut(|signals| signals.insert(signal));
         let result = ((index as u64) << 32) | (handle as u64);
         Ok(result)
@@ -630,6 +820,156 @@ impl MailboxAbi {
 }
 
 impl MailboxAbi {
+    /// Helper function to send a link or unlink request for a mailbox to a
+    /// link endpoint.
+    ///
+    /// Links are identified by this process's ID and the mailbox's handle, so
+    /// that unlinking doesn't need to track anything guest-side.
+    async fn send_link_request(&self, mailbox: u32, endpoint: u32, link: bool) -> Result<()> {
+        let process = self.borrow_process();
+        let pid = process.borrow_info().pid as u64;
+        let id = (pid << 32) | mailbox as u64;
+        let endpoint = CapabilityHandle(endpoint as usize);
+        let table = process.borrow_table();
+
+        if !link {
+            let data = serde_json::to_vec(&LinkRequest::Unlink { id })?;
+            table.send(endpoint, &data, &[]).await?;
+            return Ok(());
+        }
+
+        let mb = self.get_mb(mailbox)?;
+        let cap = mb.export(Permissions::SEND)?.into_handle();
+        let data = serde_json::to_vec(&LinkRequest::Link { id })?;
+        let result = table.send(endpoint, &data, &[cap]).await;
+        table.dec_ref(cap)?;
+        result?;
+        Ok(())
+    }
+
+    /// Helper function to take the next parked signal for a mailbox.
+    ///
+    /// Only the parent mailbox has parked signals.
+    fn take_parked(&mut self, handle: u32) -> Option<Signal> {
+        if handle != 0 {
+            return None;
+        }
+
+        self.with_parked_mut(|parked| parked.pop_front())
+    }
+
+    /// Helper function to wait for a signal on the parent mailbox, asking the
+    /// process to hibernate if it stays idle.
+    ///
+    /// Fails with [Hibernating] once the process is ready to hibernate. See
+    /// the [hibernate] module for details.
+    async fn recv_parent(&mut self) -> Result<Signal> {
+        let idle = match self.borrow_hibernate_after() {
+            // only hibernate when no other mailbox could receive a signal
+            Some(idle) if self.with_arena(|arena| arena.mbs.is_empty()) => *idle,
+            _ => {
+                return self
+                    .borrow_process()
+                    .borrow_parent()
+                    .recv(|signal| Signal::from(signal))
+                    .await
+                    .context("process has been killed");
+            }
+        };
+
+        // the process is waiting again after being asked to hibernate
+        if self.with_arena(|arena| arena.hook.is_some()) {
+            let signal = self
+                .borrow_process()
+                .borrow_parent()
+                .try_recv(|signal| Signal::from(signal))
+                .context("process has been killed")?;
+
+            let reply = self.with_arena_mut(|arena| {
+                let hook = arena.hook.take().unwrap();
+                hook.try_recv(|signal| Signal::from(signal))
+                    .context("process has been killed")
+            })?;
+
+            // stay awake if something arrived in the meantime
+            if let Some(signal) = signal {
+                return Ok(signal);
+            }
+
+            let decision = match reply {
+                Some(Signal::Message { data, caps }) => {
+                    let table = self.borrow_process().borrow_table();
+                    for cap in caps {
+                        let _ = table.dec_ref(CapabilityHandle(cap as usize));
+                    }
+
+                    Decision::from_reply(Some(&data))
+                }
+                Some(Signal::Down { .. }) => Decision::Never,
+                None => Decision::from_reply(None),
+            };
+
+            match decision {
+                Decision::Hibernate(state) => return Err(Hibernating { state }.into()),
+                Decision::StayAwake => {}
+                Decision::Never => {
+                    // stop asking a process that doesn't know about hibernation
+                    self.with_hibernate_after_mut(|idle| *idle = None);
+
+                    return self
+                        .borrow_process()
+                        .borrow_parent()
+                        .recv(|signal| Signal::from(signal))
+                        .await
+                        .context("process has been killed");
+                }
+            }
+        }
+
+        let parent = self.borrow_process().borrow_parent();
+        let signal = recv_timeout(parent, idle, |signal| Signal::from(signal)).await;
+
+        match signal {
+            Ok(signal) => signal.context("process has been killed"),
+            Err(Timeout) => self.ask_to_hibernate(),
+        }
+    }
+
+    /// Helper function to create a mailbox for the reply to a
+    /// [HibernationMessage::Hibernate] and return the message with a
+    /// capability to it.
+    fn ask_to_hibernate(&mut self) -> Result<Signal> {
+        let reply = self.with_arena_mut(|arena| -> Result<u32> {
+            let hook = arena
+                .group
+                .create_mailbox()
+                .context("process has been killed")?;
+
+            let cap = hook.export(Permissions::SEND)?.into_handle();
+            arena.hook = Some(hook);
+            Ok(cap.0.try_into().unwrap())
+        })?;
+
+        Ok(Signal::Message {
+            data: serde_json::to_vec(&HibernationMessage::Hibernate)?,
+            caps: vec![reply],
+        })
+    }
+
+    /// Helper function to report a received signal to the process's message
+    /// tap.
+    fn tap_signal(&self, mailbox: u32, signal: &Signal) {
+        let process = self.borrow_process();
+        let tap = &process.borrow_info().tap;
+
+        if let Signal::Message { data, caps } = signal {
+            if tap.is_recording() {
+                let caps: Vec<_> = caps.iter().map(|cap| *cap as usize).collect();
+                tap.record_inbound(process.borrow_table(), mailbox, data, &caps);
+            }
+        }
+    }
+
     /// Helper function to get a reference to a mailbox by its handle.
     ///
     /// Fails if the handle is invalid.
@@ -719,6 +1059,56 @@ impl MetadataAbi {
     }
 }
 
+/// The features that a host reports through [AbiQuery::has_feature].
+///
+/// Each ABI module's name (such as `hearth::mailbox`) is recorded as a
+/// feature when it's added to a linker, so the set reflects the ABI modules
+/// that this runtime actually provides.
+#[derive(Clone, Debug, Default)]
+pub struct AbiFeatures {
+    features: HashSet<String>,
+}
+
+impl AbiFeatures {
+    /// Records that a feature is available.
+    pub fn insert(&mut self, feature: &str) {
+        self.features.insert(feature.to_string());
+    }
+
+    /// Tests if a feature is available.
+    pub fn contains(&self, feature: &str) -> bool {
+        self.features.contains(feature)
+    }
+
+    /// Iterates over every available feature.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.features.iter().map(String::as_str)
+    }
+}
+
+/// Implements the `hearth::abi` ABI module, which guests use to check the
+/// host's ABI version and which optional features it provides.
+///
+/// Unlike the other ABIs, this is available in every phase of execution.
+#[derive(Clone, Debug)]
+pub struct AbiQuery {
+    pub features: Arc<AbiFeatures>,
+}
+
+#[impl_wasm_linker(module = "hearth::abi")]
+impl AbiQuery {
+    /// Returns the host's [ABI_VERSION].
+    fn version(&self) -> Result<u32> {
+        Ok(ABI_VERSION)
+    }
+
+    /// Returns 1 if the host provides the named feature and 0 if it doesn't.
+    fn has_feature(&self, memory: GuestMemory<'_>, ptr: u32, len: u32) -> Result<u32> {
+        let name = memory.get_str(ptr, len)?;
+        Ok(self.features.contains(name) as u32)
+    }
+}
+
 /// Encapsulates an instance of each guest ABI data structure.
 ///
 /// Each variant is only accessible during a specific phase of a process's
@@ -729,16 +1119,23 @@ pub enum ProcessData {
     ///
     /// Before the process is spawned into the runtime, it may export
     /// user-facing metadata through [MetadataAbi].
-    Metadata { metadata: MetadataAbi },
+    Metadata {
+        abi: AbiQuery,
+        limiter: ProcessLimiter,
+        metadata: MetadataAbi,
+    },
 
     /// The **running phase** of process execution.
     ///
     /// Provides full access to a process's ABIs post-spawn.
     Running {
+        abi: AbiQuery,
+        limiter: ProcessLimiter,
         log: LogAbi,
         lump: LumpAbi,
         table: TableAbi,
         mailbox: MailboxAbi,
+        group: GroupAbi,
     },
 }
 
@@ -746,7 +1143,16 @@ impl GetAbi<MetadataAbi> for ProcessData {
     fn get_abi(&mut self) -> Result<&mut MetadataAbi> {
         match self {
             Self::Running { .. } => bail!("process is running"),
-            Self::Metadata { metadata } => Ok(metadata),
+            Self::Metadata { metadata, .. } => Ok(metadata),
+        }
+    }
+}
+
+impl GetAbi<AbiQuery> for ProcessData {
+    fn get_abi(&mut self) -> Result<&mut AbiQuery> {
+        match self {
+            Self::Metadata { abi, .. } => Ok(abi),
+            Self::Running { abi, .. } => Ok(abi),
         }
     }
 }
@@ -768,18 +1174,31 @@ impl_running_get_abi!(ProcessData, LogAbi, log);
 impl_running_get_abi!(ProcessData, LumpAbi, lump);
 impl_running_get_abi!(ProcessData, TableAbi, table);
 impl_running_get_abi!(ProcessData, MailboxAbi, mailbox);
+impl_running_get_abi!(ProcessData, GroupAbi, group);
 
 impl ProcessData {
-    pub fn new_metadata() -> Self {
+    pub fn new_metadata(features: Arc<AbiFeatures>, limiter: ProcessLimiter) -> Self {
         Self::Metadata {
+            abi: AbiQuery { features },
+            limiter,
             metadata: Default::default(),
         }
     }
 
-    pub fn new_running(runtime: &Runtime, process: Process, this_lump: LumpId) -> Self {
-        let process = Arc::new(process);
+    pub fn new_running(
+        runtime: &Arc<Runtime>,
+        spawner: WasmProcessSpawner,
+        process: Arc<Process>,
+        this_lump: LumpId,
+        features: Arc<AbiFeatures>,
+        mut limiter: ProcessLimiter,
+        hibernate_after: Option<Duration>,
+    ) -> Self {
+        limiter.set_process(process.clone());
 
         Self::Running {
+            abi: AbiQuery { features },
+            limiter,
             log: LogAbi {
                 process: process.clone(),
             },
@@ -787,20 +1206,49 @@ impl ProcessData {
             table: TableAbi {
                 process: process.clone(),
             },
-            mailbox: MailboxAbi::new(process, Slab::new(), |process| MailboxArena {
-                group: process.borrow_group(),
-                mbs: Slab::new(),
-            }),
+            mailbox: MailboxAbi::new(
+                process.clone(),
+                Slab::new(),
+                VecDeque::new(),
+                hibernate_after,
+                |process| MailboxArena {
+                    group: process.borrow_group(),
+                    mbs: Slab::new(),
+                    hook: None,
+                },
+            ),
+            group: GroupAbi::new(runtime.clone(), process, spawner),
+        }
+    }
+
+    /// Gets this process's resource limiter.
+    pub fn limiter(&mut self) -> &mut ProcessLimiter {
+        match self {
+            Self::Metadata { limiter, .. } => limiter,
+            Self::Running { limiter, .. } => limiter,
         }
     }
 
-    /// Adds all module ABIs to the given linker.
-    pub fn add_to_linker(linker: &mut Linker<Self>) {
+    /// Adds every ABI module to a linker and returns the resulting features.
+    pub fn add_to_linker(linker: &mut Linker<Self>) -> AbiFeatures {
+        let mut features = AbiFeatures::default();
+
+        AbiQuery::add_to_linker(linker);
+        features.insert(AbiQuery::MODULE);
         LogAbi::add_to_linker(linker);
+        features.insert(LogAbi::MODULE);
         LumpAbi::add_to_linker(linker);
+        features.insert(LumpAbi::MODULE);
         TableAbi::add_to_linker(linker);
+        features.insert(TableAbi::MODULE);
         MailboxAbi::add_to_linker(linker);
+        features.insert(MailboxAbi::MODULE);
         MetadataAbi::add_to_linker(linker);
+        features.insert(MetadataAbi::MODULE);
+        GroupAbi::add_to_linker(linker);
+        features.insert(GroupAbi::MODULE);
+
+        features
     }
 }
 
@@ -809,31 +1257,64 @@ struct WasmProcess {
     exports_metadata: bool,
     instance: Instance,
     this_lump: LumpId,
+    features: Arc<AbiFeatures>,
+    engine: Engine,
+    module: Arc<InstancePre<ProcessData>>,
+    limits: WasmLimits,
+
+    /// How long the process has to be idle before it's hibernated, or `None`
+    /// if it's never hibernated.
+    hibernate_after: Option<Duration>,
+
+    /// Signals for the parent mailbox to receive first once running.
+    parked: VecDeque<Signal>,
 }
 
 impl WasmProcess {
     pub async fn new(
         engine: &Engine,
-        linker: &Linker<ProcessData>,
-        module: &Module,
+        module: &Arc<InstancePre<ProcessData>>,
         this_lump: LumpId,
+        features: Arc<AbiFeatures>,
+        limits: &WasmLimits,
+        hibernate_after: Option<Duration>,
     ) -> Result<Self> {
-        let data = ProcessData::new_metadata();
-        let mut store = Store::new(engine, data);
-
-        let instance = linker
-            .instantiate_async(&mut store, module)
-            .await
-            .context("instantiating Wasm instance")?;
+        let (store, instance) = Self::instantiate(engine, module, &features, limits).await?;
 
         Ok(Self {
             store,
             exports_metadata: false,
             instance,
             this_lump,
+            features,
+            engine: engine.clone(),
+            module: module.clone(),
+            limits: limits.clone(),
+            hibernate_after,
+            parked: VecDeque::new(),
         })
     }
 
+    /// Creates a new store and instantiates a module in it.
+    async fn instantiate(
+        engine: &Engine,
+        module: &InstancePre<ProcessData>,
+        features: &Arc<AbiFeatures>,
+        limits: &WasmLimits,
+    ) -> Result<(Store<ProcessData>, Instance)> {
+        let limiter = ProcessLimiter::new(limits);
+        let data = ProcessData::new_metadata(features.clone(), limiter);
+        let mut store = Store::new(engine, data);
+        store.limiter(|data| data.limiter());
+
+        let instance = module
+            .instantiate_async(&mut store)
+            .await
+            .context("instantiating Wasm instance")?;
+
+        Ok((store, instance))
+    }
+
     /// Executes the process's `_hearth_metadata` function and returns the
     /// result.
     pub async fn get_metadata(&mut self) -> Result<ProcessMetadata> {
@@ -855,15 +1336,25 @@ impl WasmProcess {
         }
 
         // retrieve the written metadata from the store's process data
-        let ProcessData::Metadata { metadata } = self.store.data() else {
+        let ProcessData::Metadata { metadata, .. } = self.store.data() else {
             bail!("process metadata unavailable");
         };
 
         Ok(metadata.meta.to_owned())
     }
 
-    /// Executes a Wasm process.
-    async fn run(mut self, runtime: Arc<Runtime>, ctx: Process, entrypoint: Option<u32>) {
+    /// Executes a Wasm process, then sends its exit reason to its link
+    /// endpoint.
+    ///
+    /// The process is hibernated whenever it's ready to. See [hibernate].
+    async fn run(
+        mut self,
+        runtime: Arc<Runtime>,
+        spawner: WasmProcessSpawner,
+        ctx: Process,
+        entrypoint: Option<u32>,
+        exit: oneshot::Sender<ExitReason>,
+    ) {
         // grab the PID for logging
         let pid = ctx.borrow_info().pid;
 
@@ -875,8 +1366,79 @@ impl WasmProcess {
             );
         }
 
-        // switch the process ABIs to running
-        *self.store.data_mut() = ProcessData::new_running(runtime.as_ref(), ctx, self.this_lump);
+        let process = Arc::new(ctx);
+
+        let result = loop {
+            self.start_running(&runtime, &spawner, &process);
+
+            // call inner execution behavior
+            let result = self.run_inner(entrypoint).await;
+
+            let state = match &result {
+                Err(err) => match err.downcast_ref::<Hibernating>() {
+                    Some(hibernating) => hibernating.state,
+                    None => break result,
+                },
+                Ok(()) => break result,
+            };
+
+            if let Err(err) = self.hibernate(&process, state, &spawner.hibernation).await {
+                break Err(err);
+            }
+        };
+
+        // handle the process's errors
+        let result = result.with_context(|| format!("PID {}", pid));
+        let killed = process.borrow_group().poll_dead();
+        let breach = self.store.data_mut().limiter().breach().map(str::to_owned);
+
+        let reason = match (result, breach) {
+            (Ok(()), _) => ExitReason::Finished,
+            (Err(_), _) if killed => ExitReason::Killed,
+            // most guests trap soon after an allocation is refused
+            (Err(err), Some(breach)) => {
+                error!("{:?}", err);
+                ExitReason::LimitExceeded(breach)
+            }
+            (Err(err), None) => {
+                error!("{:?}", err);
+                ExitReason::Trapped(format!("{:#}", err))
+            }
+        };
+
+        let _ = exit.send(reason);
+    }
+
+    /// Switches the process's ABIs to running and starts preemptively
+    /// timeslicing it.
+    fn start_running(
+        &mut self,
+        runtime: &Arc<Runtime>,
+        spawner: &WasmProcessSpawner,
+        process: &Arc<Process>,
+    ) {
+        // keep the limiter's state
+        let limiter = std::mem::replace(
+            self.store.data_mut().limiter(),
+            ProcessLimiter::new(&Default::default()),
+        );
+
+        let mut data = ProcessData::new_running(
+            runtime,
+            spawner.clone(),
+            process.clone(),
+            self.this_lump,
+            self.features.clone(),
+            limiter,
+            self.hibernate_after,
+        );
+
+        if let ProcessData::Running { mailbox, .. } = &mut data {
+            let parked = std::mem::take(&mut self.parked);
+            mailbox.with_parked_mut(|queue| queue.extend(parked));
+        }
+
+        *self.store.data_mut() = data;
 
         // while executing the main function, preemptively timeslice until killed
         self.store.epoch_deadline_callback(move |store| {
@@ -890,18 +1452,64 @@ impl WasmProcess {
 
             Ok(UpdateDeadline::Yield(1))
         });
+    }
 
-        // call inner execution behavior and handle its errors
-        match self
-            .run_inner(entrypoint)
-            .await
-            .with_context(|| format!("PID {}", pid))
-        {
-            Ok(()) => {}
+    /// Drops the process's instance until its parent mailbox receives a
+    /// signal, then instantiates its module again to receive it.
+    ///
+    /// `state` is the lump that the process saved its state to, if any.
+    async fn hibernate(
+        &mut self,
+        process: &Process,
+        state: Option<LumpId>,
+        counters: &HibernationCounters,
+    ) -> Result<()> {
+        let pid = process.borrow_info().pid;
+        debug!("Hibernating PID {}", pid);
+
+        // drop the store and its instance, keeping an empty store in their place
+        let limiter = ProcessLimiter::new(&Default::default());
+        let data = ProcessData::new_metadata(self.features.clone(), limiter);
+        self.store = Store::new(&self.engine, data);
+        counters.hibernated();
+
+        let signal = process
+            .borrow_parent()
+            .recv(|signal| Signal::from(signal))
+            .await;
+
+        let Some(signal) = signal else {
+            counters.exited();
+            bail!("process has been killed");
+        };
+
+        let start = Instant::now();
+        let instantiated =
+            Self::instantiate(&self.engine, &self.module, &self.features, &self.limits).await;
+
+        let (store, instance) = match instantiated {
+            Ok(instantiated) => instantiated,
             Err(err) => {
-                error!("{:?}", err);
+                counters.exited();
+                return Err(err).context("waking process");
             }
+        };
+
+        self.store = store;
+        self.instance = instance;
+
+        if let Some(state) = state {
+            self.parked.push_back(Signal::Message {
+                data: serde_json::to_vec(&HibernationMessage::Wake(state))?,
+                caps: vec![],
+            });
         }
+
+        self.parked.push_back(signal);
+        counters.woke(start.elapsed());
+        debug!("Woke PID {}", pid);
+
+        Ok(())
     }
 
     /// Performs the actual process execution using easy error handling.
@@ -948,30 +1556,101 @@ impl WasmProcess {
     }
 }
 
+#[derive(Clone)]
 pub struct WasmProcessSpawner {
     engine: Arc<Engine>,
-    linker: Arc<Linker<ProcessData>>,
+    features: Arc<AbiFeatures>,
+    config: Arc<WasmConfig>,
+    hibernation: Arc<HibernationCounters>,
+
+    /// The ABI policy of processes whose spawn requests don't set one.
+    baseline: Arc<AbiPolicy>,
+
+    /// The broadest ABI policy that this spawner may grant. The spawner
+    /// service is limited to the baseline.
+    ceiling: Arc<AbiPolicy>,
+
+    /// The guest that this spawner spawns children for, if it's bound to
+    /// one. Spawns are audited as requested by it.
+    requester: Option<AuditActor>,
+
+    /// The group of the guest that this spawner is bound to, if it's in one.
+    /// Children join it unless they're spawned into another group.
+    group: Option<Arc<ProcessGroup>>,
+}
+
+/// A process spawned by a [WasmProcessSpawner].
+pub struct Spawned<'a> {
+    /// The new process's PID.
+    pub pid: ProcessId,
+
+    /// A capability to the new process.
+    pub process: CapabilityRef<'a>,
+
+    /// A capability to the new process's [link::LinkEndpoint].
+    pub endpoint: CapabilityRef<'a>,
 }
 
 #[async_trait]
 impl RequestResponseProcess for WasmProcessSpawner {
-    type Request = WasmSpawnInfo;
-    type Response = ();
+    type Request = WasmSpawnerRequest;
+    type Response = WasmSpawnResponse;
 
     async fn on_request<'a>(
         &'a mut self,
-        request: &mut RequestInfo<'a, WasmSpawnInfo>,
+        request: &mut RequestInfo<'a, WasmSpawnerRequest>,
     ) -> ResponseInfo<'a, Self::Response> {
-        ResponseInfo {
-            data: (),
-            caps: match self.spawn(request).await {
-                // spawned successfully; return cap
-                Ok(child) => vec![child],
-                // error occurred. log and no cap
-                Err(err) => {
-                    error!("Wasm spawning error: {:?}", err);
-                    vec![]
+        let (info, service) = match &request.data {
+            WasmSpawnerRequest::Spawn(info) => (info, None),
+            WasmSpawnerRequest::SpawnService { spawn, service } => (spawn, Some(service)),
+            WasmSpawnerRequest::WarmUp { warm_up } => {
+                let result = self.load_module(request.runtime, warm_up).await;
+
+                if let Err(err) = result.as_ref() {
+                    error!("Wasm warm-up error: {:?}", err);
                 }
+
+                return ResponseInfo {
+                    data: result
+                        .map(|_| ())
+                        .map_err(|err| WasmSpawnError::Failed(format!("{:#}", err))),
+                    caps: vec![],
+                };
+            }
+        };
+
+        let result = self
+            .spawn_lump(request.runtime, request.process, info, request.cap_args)
+            .await
+            .map_err(|err| {
+                error!("Wasm spawning error: {:?}", err);
+                WasmSpawnError::Failed(format!("{:#}", err))
+            });
+
+        let result = match (result, service) {
+            (Ok(spawned), Some(service)) => {
+                let registry = request.cap_args.first();
+                self.register_spawned(request, registry, service, &spawned)
+                    .await
+                    .map(|_| spawned)
+                    .map_err(|err| {
+                        error!("Wasm service registration error: {:?}", err);
+                        WasmSpawnError::Unregistered(format!("{:#}", err))
+                    })
+            }
+            (result, _) => result,
+        };
+
+        match result {
+            // spawned successfully; return the child and its link endpoint
+            Ok(spawned) => ResponseInfo {
+                data: Ok(()),
+                caps: vec![spawned.process, spawned.endpoint],
+            },
+            // error occurred. report it to the requester
+            Err(err) => ResponseInfo {
+                data: Err(err),
+                caps: vec![],
             },
         }
     }
@@ -983,78 +1662,349 @@ impl ServiceRunner for WasmProcessSpawner {
     fn get_process_metadata() -> ProcessMetadata {
         let mut meta = cargo_process_metadata!();
         meta.description =
-            Some("The native WebAssembly process spawner. Accepts WasmSpawnInfo.".to_string());
+            Some("The native WebAssembly process spawner. Accepts WasmSpawnerRequest.".to_string());
 
         meta
     }
 }
 
 impl WasmProcessSpawner {
-    pub async fn spawn<'a>(
-        &'a mut self,
-        request: &mut RequestInfo<'a, WasmSpawnInfo>,
-    ) -> Result<CapabilityRef<'a>> {
-        // load the WebAssembly module from the asset store
-        let module = request
-            .runtime
-            .asset_store
-            .load_asset::<WasmModuleLoader>(&request.data.lump)
-            .await
-            .context("loading Wasm module")?;
+    /// Registers a newly-spawned process in a registry, killing it if it
+    /// can't be registered.
+    ///
+    /// Uses the runtime's registry if none is given.
+    async fn register_spawned(
+        &self,
+        request: &RequestInfo<'_, WasmSpawnerRequest>,
+        registry: Option<&CapabilityRef<'_>>,
+        name: &str,
+        spawned: &Spawned<'_>,
+    ) -> Result<()> {
+        let process = request.process;
+        let registry = match registry {
+            Some(registry) => registry.clone(),
+            None => request
+                .runtime
+                .registry
+                .borrow_parent()
+                .export_to(Permissions::SEND, process.borrow_table())?,
+        };
 
-        // instantiate a new WasmProcess
-        let mut process = WasmProcess::new(&self.engine, &self.linker, &module, request.data.lump)
-            .await
-            .context("initializing process")?;
+        let child = &spawned.process;
+        let result = migrate::register(process, &registry, name, child).await;
 
-        // retrieve the process's metadata
-        let meta = process
-            .get_metadata()
-            .await
-            .context("retrieving process metadata")?;
+        if result.is_err() {
+            if let Err(err) = child.kill() {
+                warn!("failed to kill unregistered process: {:?}", err);
+            }
 
-        // spawn a new local process
-        let child = request.runtime.process_factory.spawn(meta);
+            let audit = request.runtime.process_factory.audit_log();
+            audit.record(AuditEventKind::Kill {
+                requester: self.requester(process),
+                target: Some(spawned.pid as u64),
+            });
+        } else {
+            let factory = &request.runtime.process_factory;
+            factory.set_service(name.to_string(), spawned.pid);
+
+            let audit = factory.audit_log();
+            audit.record(AuditEventKind::Register {
+                name: name.to_string(),
+                pid: Some(spawned.pid as u64),
+                requester: self.requester(process),
+            });
+        }
 
-        // import a capability to its parent mailbox
-        let child_cap = child
-            .borrow_parent()
-            .export_to(Permissions::all(), request.process.borrow_table())
-            .unwrap();
+        result.with_context(|| format!("registering {:?}", name))
+    }
 
-        // send the child the initial capabilities from the request
-        child_cap
-            .send(&[], request.cap_args.iter().collect::<Vec<_>>().as_slice())
+    /// Loads a linked Wasm module from a lump, compiling and linking it if
+    /// it isn't already cached.
+    ///
+    /// Fails if the module needs a newer host ABI. Returns the module and
+    /// its embedded metadata, if it has any.
+    pub async fn load_module(
+        &self,
+        runtime: &Runtime,
+        lump: &LumpId,
+    ) -> Result<(Arc<InstancePre<ProcessData>>, Option<GuestMetadata>)> {
+        // check the module's embedded metadata before doing anything with it
+        let data = runtime
+            .lump_store
+            .get_lump(lump)
+            .await
+            .context("loading Wasm module lump")?;
+
+        let guest_meta = read_guest_metadata(&data)?;
+
+        if let Some(guest_meta) = guest_meta.as_ref() {
+            if guest_meta.abi_version > ABI_VERSION {
+                bail!(
+                    "{} {} requires host ABI version {}, but this host provides version {}",
+                    guest_meta.name,
+                    guest_meta.version,
+                    guest_meta.abi_version,
+                    ABI_VERSION
+                );
+            }
+        }
+
+        // load the linked module from the asset store
+        let module = runtime
+            .asset_store
+            .load_asset::<WasmModuleLoader>(lump)
+            .await
+            .context("loading Wasm module")?;
+
+        Ok((module, guest_meta))
+    }
+
+    /// Gets how long a process spawned with the given info and module
+    /// metadata has to be idle before it's hibernated, or `None` if it's
+    /// never hibernated.
+    fn hibernate_after(
+        &self,
+        info: &WasmSpawnInfo,
+        guest_meta: Option<&GuestMetadata>,
+    ) -> Option<Duration> {
+        let opted_in = guest_meta.map_or(false, |meta| meta.hibernates);
+        if info.keep_awake || !opted_in {
+            return None;
+        }
+
+        self.config.hibernate_after.map(Duration::from_secs)
+    }
+
+    /// Creates a spawner for the children of a process with the given ABI
+    /// policy, which can only grant subsets of it.
+    ///
+    /// The children's spawns are audited as requested by the process, and
+    /// they inherit its group.
+    fn for_children(&self, process: &Process, policy: AbiPolicy) -> Self {
+        let info = process.borrow_info();
+        Self {
+            ceiling: Arc::new(policy),
+            requester: Some(info.audit_actor()),
+            group: info.group.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Gets who spawns by this spawner are attributed to when it runs in
+    /// `process`.
+    pub fn requester(&self, process: &Process) -> AuditActor {
+        self.requester
+            .clone()
+            .unwrap_or_else(|| process.borrow_info().audit_actor())
+    }
+
+    /// Spawns a Wasm process from a lump in the local lump store.
+    ///
+    /// `cap_args` are sent to the new process as its initial capabilities.
+    /// The returned capabilities are in `process`'s table. The process joins
+    /// the group of the guest that this spawner is bound to, if it's in one.
+    pub async fn spawn_lump<'a>(
+        &self,
+        runtime: &Arc<Runtime>,
+        process: &'a Process,
+        info: &WasmSpawnInfo,
+        cap_args: &[CapabilityRef<'_>],
+    ) -> Result<Spawned<'a>> {
+        self.spawn_lump_in(runtime, process, info, cap_args, None)
+            .await
+    }
+
+    /// Spawns a Wasm process from a lump like [Self::spawn_lump], adding it
+    /// to `group` if one is given or to this spawner's group otherwise.
+    pub async fn spawn_lump_in<'a>(
+        &self,
+        runtime: &Arc<Runtime>,
+        process: &'a Process,
+        info: &WasmSpawnInfo,
+        cap_args: &[CapabilityRef<'_>],
+        group: Option<Arc<ProcessGroup>>,
+    ) -> Result<Spawned<'a>> {
+        let (module, guest_meta) = self.load_module(runtime, &info.lump).await?;
+
+        // refuse modules that import host calls that they aren't allowed
+        let requested = info.allow.as_deref();
+        let features = &self.features;
+        let policy = AbiPolicy::resolve(requested, &self.baseline, &self.ceiling, features)?;
+        policy.check(module.module())?;
+        let features = Arc::new(policy.restrict(features));
+
+        // instantiate a new WasmProcess
+        let mut wasm = WasmProcess::new(
+            &self.engine,
+            &module,
+            info.lump,
+            features,
+            &self.config.resolve(&info.limits),
+            self.hibernate_after(info, guest_meta.as_ref()),
+        )
+        .await
+        .context("initializing process")?;
+
+        // retrieve the process's metadata
+        let mut meta = wasm
+            .get_metadata()
+            .await
+            .context("retrieving process metadata")?;
+
+        // fill in whatever the guest didn't export with its embedded metadata
+        if let Some(guest_meta) = guest_meta {
+            meta.name.get_or_insert(guest_meta.name);
+            meta.version.get_or_insert(guest_meta.version);
+
+            if meta.description.is_none() {
+                meta.description = guest_meta.description;
+            }
+        }
+
+        // spawn a new local process on behalf of the requesting process
+        let requester = self.requester(process);
+        let factory = &runtime.process_factory;
+        let child = match group.or_else(|| self.group.clone()) {
+            Some(group) => factory.spawn_in_group(meta, requester, Some(info.lump), &group),
+            None => factory.spawn_for(meta, requester, Some(info.lump)),
+        };
+
+        // import a capability to its parent mailbox
+        let child_cap = child
+            .borrow_parent()
+            .export_to(Permissions::all(), process.borrow_table())
+            .unwrap();
+
+        // send the child the initial capabilities from the request
+        child_cap
+            .send(&[], cap_args.iter().collect::<Vec<_>>().as_slice())
             .await
             .unwrap();
 
         // flush the child's mailbox to import the initial capabilities
-        child.borrow_parent().recv(|_| ()).await.unwrap();
+        let initial_caps = child
+            .borrow_parent()
+            .recv(|signal| match signal {
+                TableSignal::Message { caps, .. } => caps.iter().map(|cap| cap.0).collect(),
+                TableSignal::Down { .. } => Vec::new(),
+            })
+            .await
+            .unwrap();
+
+        child
+            .borrow_info()
+            .tap
+            .record_spawn(child.borrow_table(), info.lump, &initial_caps);
+
+        // spawn the child's link endpoint
+        let pid = child.borrow_info().pid;
+        let (endpoint, exit_tx) = link::LinkEndpoint::new(pid);
+        let endpoint_process = runtime
+            .process_factory
+            .spawn(link::LinkEndpoint::metadata(pid));
+
+        let endpoint_cap = endpoint_process
+            .borrow_parent()
+            .export_to(
+                Permissions::SEND | Permissions::MONITOR,
+                process.borrow_table(),
+            )
+            .unwrap();
+
+        // let the endpoint exit once its holder and the child are both gone
+        process
+            .borrow_parent()
+            .export_to(Permissions::MONITOR, endpoint_process.borrow_table())
+            .unwrap()
+            .monitor(endpoint_process.borrow_parent())
+            .unwrap();
+
+        let label = format!("link endpoint for PID {}", pid);
+        let endpoint_runtime = runtime.clone();
+        let span = endpoint_process.span();
+        tokio::spawn(
+            async move {
+                endpoint
+                    .run(label, endpoint_runtime, &endpoint_process)
+                    .await;
+            }
+            .instrument(span),
+        );
 
         // run the process
-        let runtime = request.runtime.clone();
-        tokio::spawn(process.run(runtime, child, request.data.entrypoint));
+        let span = child.span();
+        let spawner = self.for_children(&child, policy);
+        let run = wasm.run(runtime.clone(), spawner, child, info.entrypoint, exit_tx);
+        tokio::spawn(run.instrument(span));
+
+        // return the child's caps
+        Ok(Spawned {
+            pid,
+            process: child_cap,
+            endpoint: endpoint_cap,
+        })
+    }
+}
+
+/// Reads the [GuestMetadata] embedded in a module's [METADATA_SECTION].
+///
+/// Returns `None` if the module has no metadata section.
+pub fn read_guest_metadata(data: &[u8]) -> Result<Option<GuestMetadata>> {
+    // modules may be given as WebAssembly text for convenience
+    let data = wat::parse_bytes(data).context("parsing Wasm module")?;
+
+    for payload in wasmparser::Parser::new(0).parse_all(&data) {
+        let payload = payload.context("parsing Wasm module")?;
+
+        if let wasmparser::Payload::CustomSection(reader) = payload {
+            if reader.name() == METADATA_SECTION {
+                let meta = GuestMetadata::decode(reader.data())
+                    .context("malformed Wasm metadata section")?;
 
-        // return the child's cap
-        Ok(child_cap)
+                return Ok(Some(meta));
+            }
+        }
     }
+
+    Ok(None)
 }
 
+/// Compiles Wasm modules and links them against the host ABI.
+///
+/// Loaded modules are cached by the asset store, so each module is only
+/// compiled and linked once no matter how many times it's spawned.
 pub struct WasmModuleLoader {
     engine: Arc<Engine>,
+    linker: Arc<Linker<ProcessData>>,
+    link_count: Arc<AtomicUsize>,
 }
 
 #[async_trait]
 impl AssetLoader for WasmModuleLoader {
-    type Asset = Module;
+    type Asset = InstancePre<ProcessData>;
+
+    async fn load_asset(&self, _store: &AssetStore, data: &[u8]) -> Result<Self::Asset> {
+        let module = Module::new(&self.engine, data)?;
+
+        let linked = self
+            .linker
+            .instantiate_pre(&module)
+            .context("linking Wasm module")?;
 
-    async fn load_asset(&self, _store: &AssetStore, data: &[u8]) -> Result<Module> {
-        Module::new(&self.engine, data)
+        self.link_count.fetch_add(1, Ordering::Relaxed);
+        Ok(linked)
     }
 }
 
 pub struct WasmPlugin {
     engine: Arc<Engine>,
+
+    /// The host ABI linker, shared by every module this plugin loads.
+    linker: Arc<Linker<ProcessData>>,
+    features: Arc<AbiFeatures>,
+
+    /// How many modules have been compiled and linked by the loader.
+    link_count: Arc<AtomicUsize>,
 }
 
 impl Default for WasmPlugin {
@@ -1065,25 +2015,63 @@ impl Default for WasmPlugin {
         config.memory_init_cow(true);
 
         let engine = Engine::new(&config).unwrap();
+        let mut linker = Linker::new(&engine);
+        let features = ProcessData::add_to_linker(&mut linker);
 
         Self {
             engine: Arc::new(engine),
+            linker: Arc::new(linker),
+            features: Arc::new(features),
+            link_count: Default::default(),
         }
     }
 }
 
 impl Plugin for WasmPlugin {
     fn build(&mut self, builder: &mut RuntimeBuilder) {
-        let mut linker = Linker::new(&self.engine);
-        ProcessData::add_to_linker(&mut linker);
+        let config = builder
+            .load_config::<WasmConfig>("wasm")
+            .unwrap_or_else(|err| {
+                debug!("Using default Wasm config: {}", err);
+                WasmConfig::default()
+            });
+
+        if config.allow.is_none() {
+            warn!("wasm.allow is unset; guests may only import hearth::abi");
+        }
 
-        builder.add_plugin(WasmProcessSpawner {
+        let hibernation = Arc::new(HibernationCounters::default());
+        let baseline = AbiPolicy::from_config(config.allow.as_deref(), &self.features)
+            .unwrap_or_else(|err| panic!("invalid wasm.allow config: {:#}", err));
+
+        // requests to the spawner service are anonymous, so it can't grant
+        // more than the baseline
+        let baseline = Arc::new(baseline);
+
+        let spawner = WasmProcessSpawner {
             engine: self.engine.to_owned(),
-            linker: Arc::new(linker),
+            features: self.features.to_owned(),
+            config: Arc::new(config),
+            hibernation: hibernation.clone(),
+            baseline: baseline.clone(),
+            ceiling: baseline,
+            requester: None,
+            group: None,
+        };
+
+        builder.add_plugin(spawner.clone());
+        builder.add_plugin(remote::RemoteSpawner { spawner });
+        builder.add_plugin(remote::LumpSource);
+        builder.add_plugin(upload::LumpUploader::default());
+        builder.add_plugin(migrate::Migrator);
+        builder.add_plugin(HibernationStatsService {
+            counters: hibernation,
         });
 
         builder.add_asset_loader(WasmModuleLoader {
             engine: self.engine.to_owned(),
+            linker: self.linker.to_owned(),
+            link_count: self.link_count.to_owned(),
         });
     }
 
@@ -1103,6 +2091,23 @@ impl Plugin for WasmPlugin {
 mod tests {
     use super::*;
 
+    use hearth_runtime::testing::{TestRuntime, TestRuntimeBuilder};
+    use hearth_schema::wasm::{
+        HibernationStats, HibernationStatsRequest, ProcessDown, HIBERNATION_STATS_SERVICE,
+    };
+
+    /// Creates a test runtime builder with `config`, letting guests import
+    /// every host ABI module.
+    fn allow_all(mut config: toml::Table) -> TestRuntimeBuilder {
+        let wasm = config
+            .entry("wasm")
+            .or_insert_with(|| toml::Table::new().into());
+
+        let allow = toml::Value::Array(vec!["*".into()]);
+        wasm.as_table_mut().unwrap().insert("allow".into(), allow);
+        TestRuntimeBuilder::with_config(config)
+    }
+
     #[test]
     fn link() {
         let mut config = Config::new();
@@ -1111,4 +2116,952 @@ mod tests {
         let mut linker = Linker::new(&engine);
         ProcessData::add_to_linker(&mut linker);
     }
+
+    /// Lists a WebAssembly text import for each function of an ABI module
+    /// definition from [hearth_schema::abi].
+    macro_rules! wat_imports {
+        (
+            []
+            module = $module:literal;
+            $($(#[$meta:meta])* fn $name:ident($($arg:ident: $ty:ty),* $(,)?) $(-> $ret:ty)?;)*
+        ) => {
+            vec![$({
+                let params: Vec<_> = [$(stringify!($ty)),*].into_iter().map(wat_type).collect();
+                let results: Vec<_> = [$(stringify!($ret))?].into_iter().map(wat_type).collect();
+                format!(
+                    r#"(import "{}" "{}" (func (param {}) (result {})))"#,
+                    $module,
+                    stringify!($name),
+                    params.join(" "),
+                    results.join(" "),
+                )
+            }),*]
+        };
+    }
+
+    fn wat_type(ty: &str) -> &'static str {
+        match ty {
+            "u32" | "i32" => "i32",
+            "u64" | "i64" => "i64",
+            _ => panic!("unsupported ABI type {}", ty),
+        }
+    }
+
+    #[test]
+    fn lump_abi_links_definition() {
+        let imports: Vec<String> = hearth_schema::lump_abi!(wat_imports);
+        let module = format!("(module {})", imports.join(" "));
+
+        let mut config = Config::new();
+        config.async_support(true);
+        let engine = Engine::new(&config).unwrap();
+        let module = Module::new(&engine, module).unwrap();
+        let mut linker = Linker::new(&engine);
+        ProcessData::add_to_linker(&mut linker);
+        linker.instantiate_pre(&module).unwrap();
+    }
+
+    fn link_exit_reason(module: &str) -> ExitReason {
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+        runtime_exit_reason(&runtime, module)
+    }
+
+    fn runtime_exit_reason(runtime: &TestRuntime, module: &str) -> ExitReason {
+        let spawned = runtime.spawn_wasm(module.to_string());
+        let mailbox = runtime.mailbox();
+        let notify = mailbox.capability(Permissions::SEND);
+        let request = LinkRequest::Link { id: 0 };
+        runtime.send(&spawned.endpoint, &request, &[&notify]);
+
+        let (down, _) = mailbox.recv_json::<ProcessDown>();
+        down.reason
+    }
+
+    /// Makes a module in WebAssembly text with a metadata section declaring
+    /// the given ABI version.
+    fn module_with_abi(abi_version: u32) -> String {
+        use hearth_schema::wasm::{encode_metadata, encoded_metadata_len};
+
+        const FIELDS: [&str; 3] = ["abi-test", "1.2.3", ""];
+        let data: [u8; encoded_metadata_len(FIELDS)] = encode_metadata(abi_version, FIELDS, false);
+        let escaped: String = data.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+
+        format!(
+            r#"(module (@custom "{}" "{}") (func (export "run")))"#,
+            METADATA_SECTION, escaped
+        )
+    }
+
+    #[test]
+    fn read_metadata_section() {
+        let module = module_with_abi(ABI_VERSION);
+        let meta = read_guest_metadata(module.as_bytes()).unwrap().unwrap();
+        assert_eq!(meta.name, "abi-test");
+        assert_eq!(meta.version, "1.2.3");
+        assert_eq!(meta.abi_version, ABI_VERSION);
+        assert_eq!(meta.description, None);
+
+        let bare = read_guest_metadata(br#"(module (func (export "run")))"#).unwrap();
+        assert_eq!(bare, None);
+    }
+
+    #[test]
+    fn newer_abi_is_rejected() {
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let spawn = |module: String| {
+            let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+
+            let info = WasmSpawnInfo {
+                lump,
+                entrypoint: None,
+                limits: Default::default(),
+                keep_awake: false,
+                allow: None,
+            };
+
+            runtime.request::<_, WasmSpawnResponse>(&spawner, &info, &[&runtime.registry()])
+        };
+
+        let (result, caps) = spawn(module_with_abi(ABI_VERSION));
+        assert_eq!(result, Ok(()));
+        assert_eq!(caps.len(), 2);
+
+        let (result, caps) = spawn(module_with_abi(ABI_VERSION + 1));
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("requires host ABI version"), "{}", err);
+        assert!(caps.is_empty());
+    }
+
+    #[test]
+    fn denied_imports_fail_to_spawn() {
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let module = r#"
+            (module
+                (import "hearth::group" "create" (func $create (result i32)))
+                (func (export "run")))
+        "#;
+
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+
+        let spawn = |allow: Option<Vec<&str>>| {
+            let info = WasmSpawnInfo {
+                lump,
+                entrypoint: None,
+                limits: Default::default(),
+                keep_awake: false,
+                allow: allow.map(|allow| allow.iter().map(|module| module.to_string()).collect()),
+            };
+
+            runtime.request::<_, WasmSpawnResponse>(&spawner, &info, &[&runtime.registry()])
+        };
+
+        let (result, caps) = spawn(Some(vec!["hearth::log", "hearth::mailbox"]));
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("hearth::group"), "{}", err);
+        assert!(caps.is_empty());
+
+        let (result, _caps) = spawn(Some(vec!["hearth::nonexistent"]));
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("unknown ABI module"), "{}", err);
+
+        let (result, caps) = spawn(Some(vec!["hearth::group"]));
+        assert_eq!(result, Ok(()));
+        assert_eq!(caps.len(), 2);
+
+        let (result, _caps) = spawn(None);
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test]
+    fn spawner_service_is_limited_to_baseline() {
+        let config = toml::from_str("[wasm]\nallow = [\"hearth::log\"]\n").unwrap();
+        let mut builder = TestRuntimeBuilder::with_config(config);
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let module = r#"
+            (module
+                (import "hearth::group" "create" (func $create (result i32)))
+                (func (export "run")))
+        "#;
+
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+
+        let spawn = |allow: Option<Vec<String>>| {
+            let info = WasmSpawnInfo {
+                lump,
+                entrypoint: None,
+                limits: Default::default(),
+                keep_awake: false,
+                allow,
+            };
+
+            runtime.request::<_, WasmSpawnResponse>(&spawner, &info, &[&runtime.registry()])
+        };
+
+        let (result, caps) = spawn(Some(vec!["hearth::group".to_string()]));
+        let err = result.unwrap_err().to_string();
+        assert!(err.contains("can't be granted"), "{}", err);
+        assert!(caps.is_empty());
+
+        let (result, _caps) = spawn(None);
+        assert!(result.unwrap_err().to_string().contains("hearth::group"));
+    }
+
+    #[test]
+    fn unconfigured_guests_only_get_abi_queries() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let module = r#"
+            (module
+                (import "hearth::log" "log" (func $log (param i32 i32 i32 i32 i32)))
+                (func (export "run")))
+        "#;
+
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+
+        let info = WasmSpawnInfo {
+            lump,
+            entrypoint: None,
+            limits: Default::default(),
+            keep_awake: false,
+            allow: None,
+        };
+
+        let (result, caps) =
+            runtime.request::<_, WasmSpawnResponse>(&spawner, &info, &[&runtime.registry()]);
+        assert!(result.unwrap_err().to_string().contains("hearth::log"));
+        assert!(caps.is_empty());
+    }
+
+    #[test]
+    fn spawns_reuse_linked_modules() {
+        let plugin = WasmPlugin::default();
+        let link_count = plugin.link_count.clone();
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(plugin);
+        let runtime = builder.build();
+
+        let module = r#"(module (func (export "run")))"#;
+
+        for _ in 0..16 {
+            runtime.spawn_wasm(module);
+        }
+
+        // only the first spawn compiles and links the module
+        assert_eq!(link_count.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn warm_up_links_ahead_of_spawns() {
+        let plugin = WasmPlugin::default();
+        let link_count = plugin.link_count.clone();
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(plugin);
+        let runtime = builder.build();
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let module = r#"(module (func (export "run")))"#;
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+        let request = WasmSpawnerRequest::WarmUp { warm_up: lump };
+        let (result, caps) = runtime.request::<_, WasmSpawnResponse>(&spawner, &request, &[]);
+        assert_eq!(result, Ok(()));
+        assert!(caps.is_empty());
+        assert_eq!(link_count.load(Ordering::Relaxed), 1);
+
+        runtime.spawn_wasm(module);
+        assert_eq!(link_count.load(Ordering::Relaxed), 1);
+
+        let missing = WasmSpawnerRequest::WarmUp {
+            warm_up: LumpId([0; 32]),
+        };
+
+        let (result, _) = runtime.request::<_, WasmSpawnResponse>(&spawner, &missing, &[]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn spawn_service_reports_refused_registration() {
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let module = r#"(module (func (export "run")))"#;
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+
+        let request = WasmSpawnerRequest::SpawnService {
+            spawn: WasmSpawnInfo {
+                lump,
+                entrypoint: None,
+                limits: Default::default(),
+                keep_awake: false,
+                allow: None,
+            },
+            service: "hearth.Test".to_string(),
+        };
+
+        // the runtime's own registry is immutable
+        let (result, caps) = runtime.request::<_, WasmSpawnResponse>(&spawner, &request, &[]);
+        let err = result.unwrap_err();
+        assert!(matches!(err, WasmSpawnError::Unregistered(_)), "{}", err);
+        assert!(caps.is_empty());
+    }
+
+    #[test]
+    fn bound_spawner_attributes_spawns() {
+        use hearth_schema::audit::{AuditActor, AuditEventKind};
+
+        // sends its bound spawner to the first capability it receives
+        let module = r#"
+            (module
+                (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+                (import "hearth::mailbox" "get_message_caps"
+                    (func $get_message_caps (param i32 i32)))
+                (import "hearth::group" "spawner" (func $spawner (result i32)))
+                (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (call $get_message_caps (call $recv (i32.const 0)) (i32.const 0))
+                    (i32.store (i32.const 8) (call $spawner))
+                    (call $send (i32.load (i32.const 0))
+                        (i32.const 0) (i32.const 0) (i32.const 8) (i32.const 1))
+                    ;; the spawner is killed once this process exits
+                    (drop (call $recv (i32.const 0)))))
+        "#;
+
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+        let guest = runtime.spawn_wasm(module);
+
+        let mailbox = runtime.mailbox();
+        let reply = mailbox.capability(Permissions::SEND);
+        runtime.send(&guest.process, &(), &[&reply]);
+        let (_data, caps) = mailbox.recv();
+        let spawner = caps.into_iter().next().unwrap();
+
+        let child = r#"(module (func (export "run")))"#;
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(child.into()));
+
+        let info = WasmSpawnInfo {
+            lump,
+            entrypoint: None,
+            limits: Default::default(),
+            keep_awake: false,
+            allow: None,
+        };
+
+        let (result, _caps) =
+            runtime.request::<_, WasmSpawnResponse>(&spawner, &info, &[&runtime.registry()]);
+        result.unwrap();
+        runtime.settle();
+
+        let spawns: Vec<_> = runtime
+            .runtime()
+            .process_factory
+            .audit_log()
+            .query(&Default::default(), None)
+            .into_iter()
+            .filter_map(|event| match event.kind {
+                AuditEventKind::Spawn {
+                    pid,
+                    requester,
+                    lump,
+                    ..
+                } => Some((pid, requester, lump)),
+                _ => None,
+            })
+            .collect();
+
+        // lumps are content-addressed, so this finds the guest's lump
+        let guest_lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+        let guest_pid = spawns
+            .iter()
+            .find(|(_, _, spawned)| *spawned == Some(guest_lump))
+            .map(|(pid, _, _)| *pid);
+
+        let (_, requester, _) = spawns
+            .iter()
+            .find(|(_, _, spawned)| *spawned == Some(lump))
+            .unwrap();
+
+        assert!(
+            matches!(requester, AuditActor::Process { pid, .. } if Some(*pid) == guest_pid),
+            "{:?}",
+            requester
+        );
+    }
+
+    #[test]
+    fn guest_children_inherit_group() {
+        use hearth_schema::group::{GroupRequest, GroupResponse, GroupSuccess};
+
+        // sends a new group's controller and its bound spawner to the first
+        // capability it receives, then waits to be killed
+        let module = r#"
+            (module
+                (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+                (import "hearth::mailbox" "get_message_caps"
+                    (func $get_message_caps (param i32 i32)))
+                (import "hearth::group" "create" (func $create (result i32)))
+                (import "hearth::group" "spawner" (func $spawner (result i32)))
+                (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (call $get_message_caps (call $recv (i32.const 0)) (i32.const 0))
+                    (i32.store (i32.const 8) (call $create))
+                    (i32.store (i32.const 12) (call $spawner))
+                    (call $send (i32.load (i32.const 0))
+                        (i32.const 0) (i32.const 0) (i32.const 8) (i32.const 2))
+                    (drop (call $recv (i32.const 0)))))
+        "#;
+
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+        let owner = runtime.spawn_wasm(module);
+
+        let mailbox = runtime.mailbox();
+        let reply = mailbox.capability(Permissions::SEND);
+        runtime.send(&owner.process, &(), &[&reply]);
+        let (_data, caps) = mailbox.recv();
+        let controller = &caps[0];
+
+        // spawn a copy of the guest into the group and get its spawner
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+        let info = WasmSpawnInfo {
+            lump,
+            entrypoint: None,
+            limits: Default::default(),
+            keep_awake: false,
+            allow: None,
+        };
+
+        let request = GroupRequest::Spawn(info.clone());
+        let (result, member) = runtime.request::<_, GroupResponse>(controller, &request, &[]);
+        assert!(matches!(result, Ok(GroupSuccess::Spawned)));
+        runtime.send(&member[0], &(), &[&reply]);
+        let (_data, member_caps) = mailbox.recv();
+
+        // a child that the member spawns joins the member's group
+        let spawner = &member_caps[1];
+        let (result, _child) =
+            runtime.request::<_, WasmSpawnResponse>(spawner, &info, &[&runtime.registry()]);
+        result.unwrap();
+
+        let (result, _) = runtime.request::<_, GroupResponse>(controller, &GroupRequest::List, &[]);
+        match result {
+            Ok(GroupSuccess::Members(members)) => assert_eq!(members.len(), 2),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn abi_version_and_features() {
+        let module = format!(
+            r#"
+            (module
+                (import "hearth::abi" "version" (func $version (result i32)))
+                (import "hearth::abi" "has_feature"
+                    (func $has_feature (param i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "hearth::stub")
+                (data (i32.const 16) "hearth::mailbox")
+                (func (export "run")
+                    (if (i32.ne (call $version) (i32.const {}))
+                        (then unreachable))
+                    ;; the stub feature is missing, so take the fallback path
+                    (if (call $has_feature (i32.const 0) (i32.const 12))
+                        (then unreachable))
+                    (if (i32.eqz (call $has_feature (i32.const 16) (i32.const 15)))
+                        (then unreachable))))
+            "#,
+            ABI_VERSION
+        );
+
+        assert_eq!(link_exit_reason(&module), ExitReason::Finished);
+    }
+
+    #[test]
+    fn lump_errors_are_returned() {
+        // the zeroed lump ID at address 0 isn't in the lump store
+        let module = format!(
+            r#"
+            (module
+                (import "hearth::lump" "load_by_id" (func $load_by_id (param i32) (result i32)))
+                (import "hearth::lump" "get_len" (func $get_len (param i32) (result i32)))
+                (import "hearth::lump" "free" (func $free (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (if (i32.ne (call $load_by_id (i32.const 0)) (i32.const {not_found}))
+                        (then unreachable))
+                    (if (i32.ne (call $get_len (i32.const 1000)) (i32.const {invalid_handle}))
+                        (then unreachable))
+                    (if (i32.ne (call $free (i32.const 1000)) (i32.const {invalid_handle}))
+                        (then unreachable))))
+            "#,
+            not_found = -HearthError::NOT_FOUND,
+            invalid_handle = -HearthError::INVALID_HANDLE,
+        );
+
+        assert_eq!(link_exit_reason(&module), ExitReason::Finished);
+    }
+
+    #[test]
+    fn lump_out_of_bounds_traps() {
+        let module = r#"
+            (module
+                (import "hearth::lump" "load_by_id" (func $load_by_id (param i32) (result i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (drop (call $load_by_id (i32.const 65530)))))
+        "#;
+
+        let reason = link_exit_reason(module);
+        assert!(matches!(reason, ExitReason::Trapped(_)), "{:?}", reason);
+    }
+
+    #[test]
+    fn link_finished() {
+        let reason = link_exit_reason(r#"(module (func (export "run")))"#);
+        assert_eq!(reason, ExitReason::Finished);
+    }
+
+    #[test]
+    fn link_trapped() {
+        let reason = link_exit_reason(r#"(module (func (export "run") unreachable))"#);
+        assert!(matches!(reason, ExitReason::Trapped(_)), "{:?}", reason);
+    }
+
+    /// Reads this process's resident set size in bytes.
+    #[cfg(target_os = "linux")]
+    fn resident_memory() -> usize {
+        let statm = std::fs::read_to_string("/proc/self/statm").unwrap();
+        let pages: usize = statm.split_whitespace().nth(1).unwrap().parse().unwrap();
+        pages * 4096
+    }
+
+    #[test]
+    fn memory_limit_contains_guest() {
+        const LIMIT: u64 = 16 * 1024 * 1024;
+
+        let mut config = toml::Table::new();
+        let mut wasm = toml::Table::new();
+        wasm.insert("max_memory".into(), (LIMIT as i64).into());
+        config.insert("wasm".into(), wasm.into());
+
+        let mut builder = allow_all(config);
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        // grow a page at a time, touching each new page, until refused
+        let module = r#"
+            (module
+                (memory 1)
+                (func (export "run") (local $page i32)
+                    (loop $grow
+                        (local.set $page (memory.grow (i32.const 1)))
+                        (if (i32.eq (local.get $page) (i32.const -1))
+                            (then unreachable))
+                        (i32.store (i32.mul (local.get $page) (i32.const 65536))
+                            (i32.const 1))
+                        (br $grow))))
+        "#;
+
+        #[cfg(target_os = "linux")]
+        let before = resident_memory();
+
+        let reason = runtime_exit_reason(&runtime, module);
+        assert!(
+            matches!(reason, ExitReason::LimitExceeded(_)),
+            "{:?}",
+            reason
+        );
+
+        #[cfg(target_os = "linux")]
+        {
+            let grown = resident_memory().saturating_sub(before);
+            assert!(grown < 4 * LIMIT as usize, "RSS grew by {} bytes", grown);
+        }
+
+        runtime.assert_logged("refused to grow memory");
+    }
+
+    /// Makes a metadata section for a guest that may or may not opt in to
+    /// hibernation.
+    fn hibernation_metadata(hibernates: bool) -> String {
+        use hearth_schema::wasm::{encode_metadata, encoded_metadata_len};
+
+        const FIELDS: [&str; 3] = ["sleepy-test", "1.0.0", ""];
+        let data: [u8; encoded_metadata_len(FIELDS)] =
+            encode_metadata(ABI_VERSION, FIELDS, hibernates);
+        let escaped: String = data.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+        format!(r#"(@custom "{}" "{}")"#, METADATA_SECTION, escaped)
+    }
+
+    /// Makes a guest that touches 4 MiB of memory, then replies to every
+    /// message on its parent mailbox by agreeing to hibernate. `hibernates`
+    /// sets whether its metadata opts in to hibernation.
+    fn sleepy_module(hibernates: bool) -> String {
+        format!(
+            r#"
+            (module
+                {}
+                (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+                (import "hearth::mailbox" "get_message_caps"
+                    (func $get_message_caps (param i32 i32)))
+                (import "hearth::mailbox" "destroy_signal" (func $destroy_signal (param i32)))
+                (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+                (memory (export "memory") 64)
+                (data (i32.const 16) "{{\"Hibernated\":null}}")
+                (func (export "run") (local $signal i32)
+                    (memory.fill (i32.const 64) (i32.const 1) (i32.const 4194240))
+                    (loop $serve
+                        (local.set $signal (call $recv (i32.const 0)))
+                        (call $get_message_caps (local.get $signal) (i32.const 0))
+                        (call $send (i32.load (i32.const 0))
+                            (i32.const 16) (i32.const 19) (i32.const 0) (i32.const 0))
+                        (call $destroy_signal (local.get $signal))
+                        (br $serve))))
+            "#,
+            hibernation_metadata(hibernates)
+        )
+    }
+
+    /// How long guests in [hibernating_runtime] have to be idle, in seconds.
+    const HIBERNATE_AFTER: u64 = 60;
+
+    fn hibernating_runtime() -> TestRuntime {
+        let config = format!("[wasm]\nhibernate_after = {}\n", HIBERNATE_AFTER);
+        let mut builder = allow_all(toml::from_str(&config).unwrap());
+        builder.add_plugin(WasmPlugin::default());
+        builder.build()
+    }
+
+    /// Waits until guests spawned by [hibernating_runtime] have hibernated.
+    fn wait_to_hibernate(runtime: &TestRuntime) {
+        let idle = Duration::from_secs(HIBERNATE_AFTER + 1);
+        runtime.block_on(tokio::time::sleep(idle));
+    }
+
+    fn hibernation_stats(runtime: &TestRuntime) -> HibernationStats {
+        let service = runtime.get_service(HIBERNATION_STATS_SERVICE).unwrap();
+        runtime
+            .request(&service, &HibernationStatsRequest::Get, &[])
+            .0
+    }
+
+    #[test]
+    fn idle_guests_hibernate() {
+        const GUESTS: usize = 32;
+
+        let runtime = hibernating_runtime();
+        let _guests: Vec<_> = (0..GUESTS)
+            .map(|_| runtime.spawn_wasm(sleepy_module(true)))
+            .collect();
+
+        runtime.settle();
+
+        #[cfg(target_os = "linux")]
+        let awake = resident_memory();
+
+        wait_to_hibernate(&runtime);
+        assert_eq!(hibernation_stats(&runtime).hibernated, GUESTS as u64);
+
+        #[cfg(target_os = "linux")]
+        {
+            // each guest touched 4 MiB
+            let freed = awake.saturating_sub(resident_memory());
+            let expected = GUESTS * 2 * 1024 * 1024;
+            assert!(freed > expected, "RSS only dropped by {} bytes", freed);
+        }
+    }
+
+    #[test]
+    fn hibernated_guest_wakes() {
+        let runtime = hibernating_runtime();
+        let guest = runtime.spawn_wasm(sleepy_module(true));
+        wait_to_hibernate(&runtime);
+        assert_eq!(hibernation_stats(&runtime).hibernated, 1);
+
+        let reply = runtime.mailbox();
+        let reply_cap = reply.capability(Permissions::SEND);
+        runtime.send(&guest.process, &(), &[&reply_cap]);
+
+        let (message, _) = reply.recv_json::<HibernationMessage>();
+        assert_eq!(message, HibernationMessage::Hibernated(None));
+
+        let stats = hibernation_stats(&runtime);
+        assert_eq!(stats.hibernated, 0);
+        assert_eq!(stats.wakes, 1);
+    }
+
+    #[test]
+    fn guests_opt_in_to_hibernation() {
+        // the guest would agree to hibernate if it were asked
+        let runtime = hibernating_runtime();
+        let _guest = runtime.spawn_wasm(sleepy_module(false));
+        wait_to_hibernate(&runtime);
+        assert_eq!(hibernation_stats(&runtime).hibernated, 0);
+    }
+
+    #[test]
+    fn silent_guests_stay_awake() {
+        // waits on its parent mailbox forever without replying to anything
+        let module = format!(
+            r#"
+            (module
+                {}
+                (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+                (import "hearth::mailbox" "destroy_signal" (func $destroy_signal (param i32)))
+                (func (export "run")
+                    (loop $wait
+                        (call $destroy_signal (call $recv (i32.const 0)))
+                        (br $wait))))
+            "#,
+            hibernation_metadata(true)
+        );
+
+        let runtime = hibernating_runtime();
+        let _guest = runtime.spawn_wasm(module);
+        wait_to_hibernate(&runtime);
+        assert_eq!(hibernation_stats(&runtime).hibernated, 0);
+    }
+
+    #[test]
+    fn keep_awake_opts_out() {
+        let runtime = hibernating_runtime();
+        let module = sleepy_module(true);
+        let lump = runtime.block_on(runtime.runtime().lump_store.add_lump(module.into()));
+
+        let info = WasmSpawnInfo {
+            lump,
+            entrypoint: None,
+            limits: Default::default(),
+            keep_awake: true,
+            allow: None,
+        };
+
+        let spawner = runtime
+            .get_service("hearth.wasm.WasmProcessSpawner")
+            .unwrap();
+
+        let (result, _caps) =
+            runtime.request::<_, WasmSpawnResponse>(&spawner, &info, &[&runtime.registry()]);
+
+        assert_eq!(result, Ok(()));
+        wait_to_hibernate(&runtime);
+        assert_eq!(hibernation_stats(&runtime).hibernated, 0);
+    }
+
+    #[test]
+    fn recv_timeout_expires() {
+        let module = r#"
+            (module
+                (import "hearth::mailbox" "create" (func $create (result i32)))
+                (import "hearth::mailbox" "recv_timeout"
+                    (func $recv_timeout (param i32 i64) (result i32)))
+                (func (export "run")
+                    (if (i32.ne (call $recv_timeout (call $create) (i64.const 5000))
+                                (i32.const -1))
+                        (then unreachable))))
+        "#;
+
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+
+        // time is paused in test runtimes, so the timeout elapses instantly
+        let start = runtime.block_on(async { tokio::time::Instant::now() });
+        assert_eq!(runtime_exit_reason(&runtime, module), ExitReason::Finished);
+        let elapsed = runtime.block_on(async move { start.elapsed() });
+        assert!(elapsed >= Duration::from_secs(5), "{:?}", elapsed);
+    }
+
+    /// Spawns a guest that demotes the second capability of the first
+    /// message it receives to `perms` and sends the result to the first.
+    ///
+    /// Returns the demoted capability's permissions, or `None` if the guest
+    /// exited without replying.
+    fn attenuate(given: Permissions, perms: Permissions) -> Option<Permissions> {
+        let module = format!(
+            r#"
+            (module
+                (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+                (import "hearth::mailbox" "get_message_caps"
+                    (func $get_message_caps (param i32 i32)))
+                (import "hearth::table" "demote" (func $demote (param i32 i32) (result i32)))
+                (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (call $get_message_caps (call $recv (i32.const 0)) (i32.const 0))
+                    (i32.store (i32.const 8)
+                        (call $demote (i32.load (i32.const 4)) (i32.const {})))
+                    (call $send (i32.load (i32.const 0))
+                        (i32.const 0) (i32.const 0) (i32.const 8) (i32.const 1))))
+            "#,
+            perms.bits()
+        );
+
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+        let spawned = runtime.spawn_wasm(module);
+
+        // replies and link notifications share a mailbox; only replies have caps
+        let mailbox = runtime.mailbox();
+        let reply = mailbox.capability(Permissions::SEND);
+        runtime.send(&spawned.endpoint, &LinkRequest::Link { id: 0 }, &[&reply]);
+
+        let target = runtime.mailbox();
+        let target = target.capability(given);
+        runtime.send(&spawned.process, &(), &[&reply, &target]);
+
+        let (_data, caps) = mailbox.recv();
+        caps.first().map(|cap| cap.get_permissions())
+    }
+
+    /// Makes a module named `echo-test` that sends the data of the first
+    /// message it receives to the first capability in it.
+    fn echo_module() -> String {
+        use hearth_schema::wasm::{encode_metadata, encoded_metadata_len};
+
+        const FIELDS: [&str; 3] = ["echo-test", "1.0.0", ""];
+        let data: [u8; encoded_metadata_len(FIELDS)] = encode_metadata(ABI_VERSION, FIELDS, false);
+        let escaped: String = data.iter().map(|byte| format!("\\{:02x}", byte)).collect();
+
+        format!(
+            r#"
+            (module
+                (@custom "{}" "{}")
+                (import "hearth::mailbox" "recv" (func $recv (param i32) (result i32)))
+                (import "hearth::mailbox" "get_message_data_len"
+                    (func $data_len (param i32) (result i32)))
+                (import "hearth::mailbox" "get_message_data"
+                    (func $get_message_data (param i32 i32)))
+                (import "hearth::mailbox" "get_message_caps"
+                    (func $get_message_caps (param i32 i32)))
+                (import "hearth::table" "send" (func $send (param i32 i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (func (export "run")
+                    (local $signal i32)
+                    (local.set $signal (call $recv (i32.const 0)))
+                    (call $get_message_caps (local.get $signal) (i32.const 0))
+                    (call $get_message_data (local.get $signal) (i32.const 16))
+                    (call $send (i32.load (i32.const 0))
+                        (i32.const 16) (call $data_len (local.get $signal))
+                        (i32.const 0) (i32.const 0))))
+            "#,
+            METADATA_SECTION, escaped
+        )
+    }
+
+    #[test]
+    fn recorded_messages_replay() {
+        use hearth_runtime::tap::read_recording;
+        use hearth_runtime::testing::ReplayOptions;
+        use hearth_schema::tap::TapRecord;
+
+        let dir = std::env::temp_dir().join(format!("hearth-replay-{}", std::process::id()));
+        let config = format!(
+            "[message_tap]\nprocesses = [\"echo-test\"]\ndir = {:?}\n",
+            dir
+        );
+
+        let mut builder = allow_all(toml::from_str(&config).unwrap());
+        builder.add_plugin(WasmPlugin::default());
+        let runtime = builder.build();
+        let spawned = runtime.spawn_wasm(echo_module());
+
+        let links = runtime.mailbox();
+        let notify = links.capability(Permissions::SEND);
+        runtime.send(&spawned.endpoint, &LinkRequest::Link { id: 0 }, &[&notify]);
+
+        let reply = runtime.mailbox();
+        let reply_cap = reply.capability(Permissions::SEND);
+        let message = serde_json::json!({ "timestamp": 1, "text": "hello" });
+        runtime.send(&spawned.process, &message, &[&reply_cap]);
+
+        let (echoed, _) = reply.recv_json::<serde_json::Value>();
+        assert_eq!(echoed, message);
+
+        let (down, _) = links.recv_json::<ProcessDown>();
+        runtime.settle();
+
+        let path = dir.join(format!("echo-test-{}.jsonl", down.pid));
+        let mut records = read_recording(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(records[0], TapRecord::Start { .. }));
+        assert!(matches!(records.last(), Some(TapRecord::Stop { .. })));
+
+        let mut builder = allow_all(Default::default());
+        builder.add_plugin(WasmPlugin::default());
+        let replayer = builder.build();
+
+        let options = ReplayOptions::default();
+        let report = replayer.replay(echo_module(), &records, &options);
+        report.assert_matches();
+        assert_eq!(report.delivered, 1);
+        assert_eq!(report.sends.len(), 1);
+
+        // timestamps are ignored, but other changes are not
+        for record in records.iter_mut() {
+            if let TapRecord::Outbound { data, .. } = record {
+                *data = br#"{"timestamp":2,"text":"goodbye"}"#.to_vec();
+            }
+        }
+
+        let report = replayer.replay(echo_module(), &records, &options);
+        assert_eq!(report.mismatches.len(), 1);
+        assert!(report.mismatches[0].to_string().contains("goodbye"));
+    }
+
+    #[test]
+    fn demote_attenuates() {
+        let given = Permissions::SEND | Permissions::MONITOR;
+        assert_eq!(attenuate(given, Permissions::SEND), Some(Permissions::SEND));
+    }
+
+    #[test]
+    fn demote_to_superset_fails() {
+        let given = Permissions::SEND;
+        assert_eq!(
+            attenuate(given, Permissions::SEND | Permissions::KILL),
+            None
+        );
+    }
 }
@@ -0,0 +1,213 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Per-process resource limits for Wasm guests.
+//!
+//! Every Wasm store gets a [ProcessLimiter] so that a guest growing its
+//! memory or tables without bound is refused at the Wasm level instead of
+//! exhausting the host.
+
+use std::sync::Arc;
+
+use hearth_runtime::anyhow::Result;
+use hearth_runtime::hearth_schema::{wasm::WasmLimits, ProcessLogLevel};
+use hearth_runtime::process::{Process, ProcessLogEvent};
+use serde::Deserialize;
+use tracing::warn;
+use wasmtime::{ResourceLimiter, StoreLimits, StoreLimitsBuilder};
+
+/// Configuration for the Wasm plugin.
+///
+/// Loaded from the `wasm` table of the config file. The limits here are the
+/// defaults for every process and the maximums that spawn requests can ask
+/// for.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WasmConfig {
+    /// The maximum size of each linear memory in bytes.
+    pub max_memory: u64,
+
+    /// The maximum number of elements in each table.
+    pub max_table_elements: u32,
+
+    /// How many seconds a process has to wait idly on its parent mailbox
+    /// before it's hibernated. Processes are never hibernated if unset.
+    ///
+    /// See [crate::hibernate] for what hibernation means for guests.
+    pub hibernate_after: Option<u64>,
+
+    /// The host ABI modules that processes may import if their spawn
+    /// requests don't list any, including the init system, and the most that
+    /// the spawner service may grant. `"*"` allows every module. Only
+    /// `hearth::abi` is allowed if unset.
+    ///
+    /// See [crate::policy] for how spawns are restricted.
+    pub allow: Option<Vec<String>>,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        Self {
+            max_memory: 256 * 1024 * 1024,
+            max_table_elements: 100_000,
+            hibernate_after: None,
+            allow: None,
+        }
+    }
+}
+
+impl WasmConfig {
+    /// Resolves the limits requested for a process against this config.
+    ///
+    /// Unset limits use the configured ones and requested limits are
+    /// clamped to them.
+    pub fn resolve(&self, requested: &WasmLimits) -> WasmLimits {
+        let max_memory = requested
+            .max_memory
+            .map_or(self.max_memory, |max| max.min(self.max_memory));
+
+        let max_table_elements = requested
+            .max_table_elements
+            .map_or(self.max_table_elements, |max| {
+                max.min(self.max_table_elements)
+            });
+
+        WasmLimits {
+            max_memory: Some(max_memory),
+            max_table_elements: Some(max_table_elements),
+        }
+    }
+}
+
+/// A [ResourceLimiter] for a single Wasm process that remembers when one of
+/// its limits was hit.
+pub struct ProcessLimiter {
+    limits: StoreLimits,
+    max_memory: usize,
+    max_table_elements: u32,
+    breach: Option<String>,
+    process: Option<Arc<Process>>,
+}
+
+impl ProcessLimiter {
+    /// Creates a limiter from resolved limits. Unset limits are unlimited.
+    pub fn new(limits: &WasmLimits) -> Self {
+        let max_memory = limits
+            .max_memory
+            .map_or(usize::MAX, |max| max.try_into().unwrap_or(usize::MAX));
+
+        let max_table_elements = limits.max_table_elements.unwrap_or(u32::MAX);
+
+        Self {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(max_memory)
+                .table_elements(max_table_elements)
+                .build(),
+            max_memory,
+            max_table_elements,
+            breach: None,
+            process: None,
+        }
+    }
+
+    /// Sets the process that limit breaches are logged to.
+    pub fn set_process(&mut self, process: Arc<Process>) {
+        self.process = Some(process);
+    }
+
+    /// Returns a description of the first limit that was hit, if any.
+    pub fn breach(&self) -> Option<&str> {
+        self.breach.as_deref()
+    }
+
+    fn on_breach(&mut self, description: String) {
+        match self.process.as_ref() {
+            Some(process) => {
+                let info = process.borrow_info();
+                warn!("PID {}: {}", info.pid, description);
+
+                let event =
+                    ProcessLogEvent::new(ProcessLogLevel::Warning, "hearth::wasm", &description);
+                let _ = info.log_tx.send(event);
+            }
+            None => warn!("Wasm instance: {}", description),
+        }
+
+        self.breach.get_or_insert(description);
+    }
+}
+
+impl ResourceLimiter for ProcessLimiter {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+
+        if !allowed && desired > self.max_memory {
+            self.on_breach(format!(
+                "refused to grow memory to {} bytes (limit is {} bytes)",
+                desired, self.max_memory
+            ));
+        }
+
+        Ok(allowed)
+    }
+
+    fn table_growing(&mut self, current: u32, desired: u32, maximum: Option<u32>) -> Result<bool> {
+        let allowed = self.limits.table_growing(current, desired, maximum)?;
+
+        if !allowed && desired > self.max_table_elements {
+            self.on_breach(format!(
+                "refused to grow table to {} elements (limit is {} elements)",
+                desired, self.max_table_elements
+            ));
+        }
+
+        Ok(allowed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_clamped() {
+        let config = WasmConfig {
+            max_memory: 1024,
+            max_table_elements: 16,
+            hibernate_after: None,
+            allow: None,
+        };
+
+        let resolved = config.resolve(&WasmLimits::default());
+        assert_eq!(resolved.max_memory, Some(1024));
+        assert_eq!(resolved.max_table_elements, Some(16));
+
+        let resolved = config.resolve(&WasmLimits {
+            max_memory: Some(512),
+            max_table_elements: Some(64),
+        });
+
+        assert_eq!(resolved.max_memory, Some(512));
+        assert_eq!(resolved.max_table_elements, Some(16));
+    }
+}
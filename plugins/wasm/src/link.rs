@@ -0,0 +1,222 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Link endpoints, which notify linked mailboxes when a Wasm process exits.
+//!
+//! Each Wasm process gets a link endpoint when it's spawned. The endpoint is
+//! a separate host process so that it outlives the process it describes,
+//! which lets links made after the process has exited still be answered
+//! with its exit reason.
+//!
+//! An endpoint monitors the process that it was handed to. Once both that
+//! process and the process the endpoint describes are gone, nothing is left
+//! to link, so the endpoint exits.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hearth_runtime::flue::{CapabilityRef, TableSignal};
+use hearth_runtime::process::{Process, ProcessId, ProcessMetadata};
+use hearth_runtime::runtime::Runtime;
+use hearth_runtime::tokio::sync::oneshot;
+use hearth_runtime::{async_trait, cargo_process_metadata, hearth_schema, tokio, utils::*};
+use hearth_schema::wasm::{ExitReason, LinkRequest, ProcessDown};
+use tracing::debug;
+
+/// Serves [LinkRequest] messages for a single Wasm process.
+pub struct LinkEndpoint {
+    pid: ProcessId,
+    exit: oneshot::Receiver<ExitReason>,
+}
+
+impl LinkEndpoint {
+    /// Creates an endpoint for a process. The process sends its exit reason
+    /// on the returned sender when it exits; if the sender is dropped
+    /// instead, the process is considered killed.
+    pub fn new(pid: ProcessId) -> (Self, oneshot::Sender<ExitReason>) {
+        let (exit_tx, exit) = oneshot::channel();
+        (Self { pid, exit }, exit_tx)
+    }
+
+    /// Gets the process metadata for the endpoint of the given process.
+    pub fn metadata(pid: ProcessId) -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.name = Some(format!("Link endpoint for PID {}", pid));
+        meta.description = Some(
+            "Notifies linked mailboxes when a Wasm process exits. Accepts LinkRequest.".to_string(),
+        );
+        meta
+    }
+
+    /// Sends a [ProcessDown] message to a linked mailbox.
+    async fn notify(&self, cap: &CapabilityRef<'_>, reason: &ExitReason) {
+        let down = ProcessDown {
+            pid: self.pid as u64,
+            reason: reason.clone(),
+        };
+
+        let data = serde_json::to_vec(&down).unwrap();
+        if let Err(err) = cap.send(&data, &[]).await {
+            debug!("failed to notify link of PID {}: {:?}", self.pid, err);
+        }
+    }
+}
+
+#[async_trait]
+impl ProcessRunner for LinkEndpoint {
+    async fn run(mut self, _label: String, _runtime: Arc<Runtime>, ctx: &Process) {
+        let table = ctx.borrow_table();
+        let mut links: HashMap<u64, CapabilityRef<'_>> = HashMap::new();
+        let mut exited: Option<ExitReason> = None;
+        let mut holder_down = false;
+
+        loop {
+            if holder_down && exited.is_some() {
+                debug!("link endpoint for PID {} is no longer needed", self.pid);
+                break;
+            }
+
+            let recv = ctx.borrow_parent().recv(|signal| match signal {
+                TableSignal::Message { data, caps } => Some((data.to_vec(), caps.first().copied())),
+                TableSignal::Down { .. } => None,
+            });
+
+            let signal = tokio::select! {
+                reason = &mut self.exit, if exited.is_none() => {
+                    let reason = reason.unwrap_or(ExitReason::Killed);
+                    debug!("PID {} exited: {:?}", self.pid, reason);
+
+                    for (_id, cap) in links.drain() {
+                        self.notify(&cap, &reason).await;
+                    }
+
+                    exited = Some(reason);
+                    continue;
+                }
+                signal = recv => signal,
+            };
+
+            let (data, cap) = match signal {
+                // the endpoint itself was killed
+                None => break,
+                // the only down signal is from the endpoint's holder
+                Some(None) => {
+                    holder_down = true;
+                    continue;
+                }
+                Some(Some(message)) => message,
+            };
+
+            let request: LinkRequest = match serde_json::from_slice(&data) {
+                Ok(request) => request,
+                Err(err) => {
+                    debug!("invalid link request: {:?}", err);
+                    continue;
+                }
+            };
+
+            match request {
+                LinkRequest::Link { id } => {
+                    let Some(cap) = cap.and_then(|cap| table.wrap_handle(cap).ok()) else {
+                        debug!("link request is missing a capability to notify");
+                        continue;
+                    };
+
+                    match exited.as_ref() {
+                        Some(reason) => self.notify(&cap, reason).await,
+                        None => {
+                            links.insert(id, cap);
+                        }
+                    }
+                }
+                LinkRequest::Unlink { id } => {
+                    links.remove(&id);
+                }
+                LinkRequest::GetPid => {
+                    let Some(cap) = cap.and_then(|cap| table.wrap_handle(cap).ok()) else {
+                        debug!("PID request is missing a reply address");
+                        continue;
+                    };
+
+                    let data = serde_json::to_vec(&(self.pid as u64)).unwrap();
+                    if let Err(err) = cap.send(&data, &[]).await {
+                        debug!("failed to reply with PID {}: {:?}", self.pid, err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_runtime::flue::Permissions;
+    use hearth_runtime::testing::TestRuntimeBuilder;
+
+    #[test]
+    fn exits_once_holder_and_process_are_gone() {
+        let runtime = TestRuntimeBuilder::new().build();
+        let factory = &runtime.runtime().process_factory;
+
+        let (holder, endpoint_process) = runtime.block_on(async {
+            let holder = factory.spawn(Default::default());
+            let endpoint_process = factory.spawn(LinkEndpoint::metadata(1));
+            (holder, endpoint_process)
+        });
+
+        holder
+            .borrow_parent()
+            .export_to(Permissions::MONITOR, endpoint_process.borrow_table())
+            .unwrap()
+            .monitor(endpoint_process.borrow_parent())
+            .unwrap();
+
+        let endpoint_cap = endpoint_process
+            .borrow_parent()
+            .export_to(Permissions::SEND, runtime.process().borrow_table())
+            .unwrap();
+
+        let (endpoint, exit_tx) = LinkEndpoint::new(1);
+        let label = "link endpoint".to_string();
+        let endpoint_runtime = runtime.runtime().clone();
+        let task = runtime.block_on(async move {
+            tokio::spawn(async move {
+                endpoint
+                    .run(label, endpoint_runtime, &endpoint_process)
+                    .await;
+            })
+        });
+
+        exit_tx.send(ExitReason::Finished).unwrap();
+        runtime.settle();
+        assert!(!task.is_finished());
+
+        // links made after the exit are still answered while the holder lives
+        let mailbox = runtime.mailbox();
+        let notify = mailbox.capability(Permissions::SEND);
+        runtime.send(&endpoint_cap, &LinkRequest::Link { id: 0 }, &[&notify]);
+        let (down, _) = mailbox.recv_json::<ProcessDown>();
+        assert_eq!(down.reason, ExitReason::Finished);
+
+        drop(holder);
+        runtime.settle();
+        assert!(task.is_finished());
+    }
+}
@@ -0,0 +1,447 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Cooperative migration of Wasm processes between peers.
+
+use std::time::Duration;
+
+use hearth_runtime::anyhow::{anyhow, bail, Context, Result};
+use hearth_runtime::flue::{CapabilityRef, Permissions};
+use hearth_runtime::process::{Process, ProcessMetadata};
+use hearth_runtime::{async_trait, hearth_schema};
+use hearth_runtime::{cargo_process_metadata, utils::*};
+use hearth_schema::registry::{RegistryRequest, RegistryResponse};
+use hearth_schema::wasm::*;
+use hearth_schema::LumpId;
+use tracing::{debug, warn};
+
+use crate::remote::get_service;
+
+/// How long a migrating process has to answer each [MigrationMessage].
+///
+/// Spawning the new process is bounded by the remote spawner's own request
+/// timeout instead, since it may include transferring lumps.
+pub const MIGRATION_STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends a [MigrationMessage] to a process and waits for its reply.
+async fn exchange<'a>(
+    process: &'a Process,
+    target: &CapabilityRef<'a>,
+    message: MigrationMessage,
+    args: &[&CapabilityRef<'_>],
+) -> Result<MigrationMessage> {
+    let target =
+        RequestResponse::<MigrationMessage, MigrationMessage>::new(process, target.clone())
+            .with_timeout(MIGRATION_STEP_TIMEOUT);
+
+    let (reply, _caps) = target.request(&message, args).await?;
+
+    match reply {
+        MigrationMessage::Failed(err) => bail!("process failed to migrate: {}", err),
+        reply => Ok(reply),
+    }
+}
+
+/// Sends a [MigrationMessage] to a process without waiting for a reply.
+async fn notify(
+    target: &CapabilityRef<'_>,
+    message: MigrationMessage,
+    args: &[&CapabilityRef<'_>],
+) -> Result<()> {
+    let data = serde_json::to_vec(&message).unwrap();
+    target.send(&data, args).await?;
+    Ok(())
+}
+
+/// Registers a process under a name in a registry.
+pub(crate) async fn register(
+    process: &Process,
+    registry: &CapabilityRef<'_>,
+    name: &str,
+    service: &CapabilityRef<'_>,
+) -> Result<()> {
+    let registry =
+        RequestResponse::<RegistryRequest, RegistryResponse>::new(process, registry.clone());
+
+    let request = RegistryRequest::Register {
+        name: name.to_string(),
+    };
+
+    let (response, _caps) = registry.request(&request, &[service]).await?;
+
+    match response {
+        RegistryResponse::Register(Some(_)) => Ok(()),
+        RegistryResponse::Register(None) => bail!("registry refused {:?}", name),
+        other => bail!("unexpected registry response: {:?}", other),
+    }
+}
+
+/// Moves running Wasm processes to other peers. Accepts [MigrateRequest].
+pub struct Migrator;
+
+#[async_trait]
+impl RequestResponseProcess for Migrator {
+    type Request = MigrateRequest;
+    type Response = MigrateResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, MigrateRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match self.migrate(request).await {
+            Ok(caps) => ResponseInfo { data: Ok(()), caps },
+            Err(err) => {
+                debug!("migration error: {:?}", err);
+                ResponseInfo {
+                    data: Err(format!("{:#}", err)),
+                    caps: vec![],
+                }
+            }
+        }
+    }
+}
+
+impl ServiceRunner for Migrator {
+    const NAME: &'static str = MIGRATOR_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Migrates Wasm processes to other peers. Accepts MigrateRequest.".to_string());
+
+        meta
+    }
+}
+
+impl Migrator {
+    async fn migrate<'a>(
+        &mut self,
+        request: &RequestInfo<'a, MigrateRequest>,
+    ) -> Result<Vec<CapabilityRef<'a>>> {
+        let process = request.process;
+        let info = &request.data;
+
+        let original = request
+            .cap_args
+            .first()
+            .context("missing the process to migrate")?;
+
+        if !original.get_permissions().contains(Permissions::KILL) {
+            bail!("migrating a process requires the kill permission");
+        }
+
+        let args = request.cap_args.get(1..).unwrap_or_default();
+        if !info.services.is_empty() && args.is_empty() {
+            bail!("re-registering services requires a registry");
+        }
+
+        // once this succeeds, the original holds back its messages until it
+        // is told to forward or resume
+        let state =
+            match exchange(process, original, MigrationMessage::Snapshot, &[original]).await? {
+                MigrationMessage::State(state) => state,
+                other => bail!("expected a state snapshot, got {:?}", other),
+            };
+
+        debug!("migrating process with state {} to {:?}", state, info.peer);
+
+        let caps = match spawn_restored(request, state).await {
+            Ok(caps) => caps,
+            Err(err) => {
+                if let Err(err) = notify(original, MigrationMessage::Resume, &[original]).await {
+                    warn!("failed to resume process after failed migration: {:?}", err);
+                }
+
+                return Err(err);
+            }
+        };
+
+        let child = &caps[0];
+        let forwarded = exchange(
+            process,
+            original,
+            MigrationMessage::Forward,
+            &[original, child],
+        )
+        .await;
+
+        // the original may have already forwarded some messages, so there's
+        // no safe way to resume it after this point; keep the new process
+        match forwarded {
+            Ok(MigrationMessage::Forwarded) => {}
+            Ok(other) => warn!("expected forwarding to finish, got {:?}", other),
+            Err(err) => warn!("process did not confirm forwarding: {:?}", err),
+        }
+
+        if let Some(registry) = args.first() {
+            for name in info.services.iter() {
+                if let Err(err) = register(process, registry, name, child).await {
+                    warn!(
+                        "failed to re-register migrated service {:?}: {:?}",
+                        name, err
+                    );
+                }
+            }
+        }
+
+        original
+            .kill()
+            .context("failed to kill the original process")?;
+
+        Ok(caps)
+    }
+}
+
+/// Spawns a migrating process on the destination peer and restores its state.
+///
+/// The new process is killed if it fails to restore.
+async fn spawn_restored<'a>(
+    request: &RequestInfo<'a, MigrateRequest>,
+    state: LumpId,
+) -> Result<Vec<CapabilityRef<'a>>> {
+    let runtime = request.runtime;
+    let process = request.process;
+    let info = &request.data;
+
+    let spawner = get_service(runtime, process, REMOTE_SPAWNER_SERVICE).await?;
+    let spawner = RequestResponse::<RemoteSpawnInfo, RemoteSpawnResponse>::new(process, spawner);
+
+    let spawn = RemoteSpawnInfo {
+        peer: Some(info.peer.clone()),
+        spawn: info.spawn.clone(),
+        lumps: vec![state],
+    };
+
+    // the remote spawner replaces its first argument with its own lump
+    // source, so any capability works as a placeholder here
+    let placeholder = &request.cap_args[0];
+    let mut args = vec![placeholder];
+    args.extend(request.cap_args.get(1..).unwrap_or_default().iter());
+
+    let (response, caps) = spawner.request(&spawn, &args).await?;
+    response.map_err(|err| anyhow!("failed to spawn on {:?}: {}", info.peer, err))?;
+
+    let child = caps.first().context("remote spawner returned no process")?;
+
+    // the new process only restores from senders that can prove they may
+    // kill it, so that nobody else can overwrite its state
+    match exchange(process, child, MigrationMessage::Restore(state), &[child]).await {
+        Ok(MigrationMessage::Ready) => Ok(caps),
+        result => {
+            if let Err(err) = child.kill() {
+                warn!("failed to kill unrestored process: {:?}", err);
+            }
+
+            match result {
+                Ok(other) => bail!("expected the process to be ready, got {:?}", other),
+                Err(err) => Err(err.context("restoring state")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use hearth_runtime::flue::TableSignal;
+    use hearth_runtime::lump::lump_id;
+    use hearth_runtime::testing::{TestRuntime, TestRuntimeBuilder};
+
+    /// Stands in for the remote spawner by returning its last argument as
+    /// the spawned process.
+    struct EchoSpawner;
+
+    #[async_trait]
+    impl RequestResponseProcess for EchoSpawner {
+        type Request = RemoteSpawnInfo;
+        type Response = RemoteSpawnResponse;
+
+        async fn on_request<'a>(
+            &'a mut self,
+            request: &mut RequestInfo<'a, RemoteSpawnInfo>,
+        ) -> ResponseInfo<'a, Self::Response> {
+            ResponseInfo {
+                data: Ok(()),
+                caps: request.cap_args.last().cloned().into_iter().collect(),
+            }
+        }
+    }
+
+    fn test_runtime() -> TestRuntime {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_service(REMOTE_SPAWNER_SERVICE, EchoSpawner);
+        builder.add_service(MIGRATOR_SERVICE, Migrator);
+        builder.build()
+    }
+
+    fn request() -> MigrateRequest {
+        MigrateRequest {
+            peer: "peer".to_string(),
+            spawn: WasmSpawnInfo {
+                lump: lump_id(b"module"),
+                entrypoint: None,
+                limits: Default::default(),
+                keep_awake: false,
+                allow: None,
+            },
+            services: vec![],
+        }
+    }
+
+    /// Waits for a migration message to a process and returns it along with
+    /// its capabilities.
+    fn recv_migration<'a>(
+        runtime: &TestRuntime,
+        process: &'a Process,
+    ) -> (MigrationMessage, Vec<CapabilityRef<'a>>) {
+        let (data, caps) = runtime
+            .block_on(process.borrow_parent().recv(|signal| match signal {
+                TableSignal::Message { data, caps } => Some((data.to_vec(), caps)),
+                TableSignal::Down { .. } => None,
+            }))
+            .flatten()
+            .expect("process went down");
+
+        let table = process.borrow_table();
+        let caps = caps
+            .into_iter()
+            .map(|handle| table.wrap_handle(handle).unwrap())
+            .collect();
+
+        (serde_json::from_slice(&data).unwrap(), caps)
+    }
+
+    fn reply(runtime: &TestRuntime, caps: &[CapabilityRef<'_>], message: MigrationMessage) {
+        runtime.send(&caps[0], &message, &[]);
+    }
+
+    #[test]
+    fn migrates_process() {
+        let runtime = test_runtime();
+        let migrator = runtime.get_service(MIGRATOR_SERVICE).unwrap();
+        let factory = &runtime.runtime().process_factory;
+        let table = runtime.process().borrow_table();
+        let original = factory.spawn(ProcessMetadata::default());
+        let original_cap = original
+            .borrow_parent()
+            .export_to(Permissions::all(), table)
+            .unwrap();
+
+        let child = factory.spawn(ProcessMetadata::default());
+        let child_cap = child
+            .borrow_parent()
+            .export_to(Permissions::all(), table)
+            .unwrap();
+
+        let monitor = runtime.mailbox();
+        monitor.monitor(&original_cap);
+
+        let replies = runtime.mailbox();
+        let reply_cap = replies.capability(Permissions::SEND);
+        let caps = [&reply_cap, &original_cap, &child_cap];
+        runtime.send(&migrator, &request(), &caps);
+
+        let state = lump_id(b"state");
+        let (message, caps) = recv_migration(&runtime, &original);
+        assert_eq!(message, MigrationMessage::Snapshot);
+        reply(&runtime, &caps, MigrationMessage::State(state));
+
+        let (message, caps) = recv_migration(&runtime, &child);
+        assert_eq!(message, MigrationMessage::Restore(state));
+        assert!(caps[1].get_permissions().contains(Permissions::KILL));
+        reply(&runtime, &caps, MigrationMessage::Ready);
+
+        let (message, caps) = recv_migration(&runtime, &original);
+        assert_eq!(message, MigrationMessage::Forward);
+        assert_eq!(caps.len(), 3);
+        reply(&runtime, &caps, MigrationMessage::Forwarded);
+
+        let (response, caps) = replies.recv_json::<MigrateResponse>();
+        assert_eq!(response, Ok(()));
+        assert_eq!(caps.len(), 1);
+        monitor.recv_down();
+    }
+
+    #[test]
+    fn failed_restore_resumes_original() {
+        let runtime = test_runtime();
+        let migrator = runtime.get_service(MIGRATOR_SERVICE).unwrap();
+        let factory = &runtime.runtime().process_factory;
+        let table = runtime.process().borrow_table();
+        let original = factory.spawn(ProcessMetadata::default());
+        let original_cap = original
+            .borrow_parent()
+            .export_to(Permissions::all(), table)
+            .unwrap();
+
+        let child = factory.spawn(ProcessMetadata::default());
+        let child_cap = child
+            .borrow_parent()
+            .export_to(Permissions::all(), table)
+            .unwrap();
+
+        let replies = runtime.mailbox();
+        let reply_cap = replies.capability(Permissions::SEND);
+        let caps = [&reply_cap, &original_cap, &child_cap];
+        runtime.send(&migrator, &request(), &caps);
+
+        let (_, caps) = recv_migration(&runtime, &original);
+        reply(&runtime, &caps, MigrationMessage::State(lump_id(b"state")));
+
+        let (_, caps) = recv_migration(&runtime, &child);
+        let err = "invalid saved state".to_string();
+        reply(&runtime, &caps, MigrationMessage::Failed(err));
+
+        let (message, _) = recv_migration(&runtime, &original);
+        assert_eq!(message, MigrationMessage::Resume);
+
+        let (response, _) = replies.recv_json::<MigrateResponse>();
+        assert!(response.unwrap_err().contains("invalid saved state"));
+    }
+
+    #[test]
+    fn migrating_requires_kill_permission() {
+        let runtime = test_runtime();
+        let migrator = runtime.get_service(MIGRATOR_SERVICE).unwrap();
+        let original = runtime.mailbox();
+        let original_cap = original.capability(Permissions::SEND);
+
+        let (response, _) =
+            runtime.request::<_, MigrateResponse>(&migrator, &request(), &[&original_cap]);
+
+        assert!(response.unwrap_err().contains("kill permission"));
+        assert!(original.try_recv().is_none());
+    }
+
+    #[test]
+    fn registering_services_requires_registry() {
+        let runtime = test_runtime();
+        let migrator = runtime.get_service(MIGRATOR_SERVICE).unwrap();
+        let original = runtime.mailbox();
+        let original_cap = original.capability(Permissions::SEND | Permissions::KILL);
+
+        let mut request = request();
+        request.services.push("service".to_string());
+        let (response, _) =
+            runtime.request::<_, MigrateResponse>(&migrator, &request, &[&original_cap]);
+
+        assert!(response.unwrap_err().contains("requires a registry"));
+        assert!(original.try_recv().is_none());
+    }
+}
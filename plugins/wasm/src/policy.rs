@@ -0,0 +1,238 @@
+// Copyright (c) 2023 the Hearth contributors
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Policies of which host ABI modules a Wasm process may import.
+//!
+//! Every spawn resolves an [AbiPolicy] from [WasmSpawnInfo::allow], or from
+//! [WasmConfig::allow] if the request doesn't set one. A module that imports
+//! a function from a denied ABI module fails to spawn, so a denied host call
+//! can't be reached at all instead of trapping when it's made. Processes
+//! also only see their allowed modules through `hearth::abi`'s
+//! `has_feature`.
+//!
+//! A process can only grant policies that are a subset of its own: the
+//! spawners behind the `hearth::group` ABI, including the one returned by
+//! its `spawner` function, are limited to their guest's policy. Requests to
+//! the spawner service don't say who sent them, so the service can grant at
+//! most the configured baseline. A process that was narrowed below the
+//! baseline can still reach it through the service if it's handed a registry
+//! that has it, so parents that narrow their children should also hide
+//! `hearth.wasm.WasmProcessSpawner` from the registry they pass down. Only
+//! native host code may grant more than the baseline.
+//!
+//! The baseline is deny-by-default: if [WasmConfig::allow] is unset, guests
+//! may only import `hearth::abi`.
+//!
+//! [WasmSpawnInfo::allow]: hearth_schema::wasm::WasmSpawnInfo::allow
+//! [WasmConfig::allow]: crate::limits::WasmConfig::allow
+
+use std::collections::BTreeSet;
+
+use hearth_runtime::anyhow::{bail, Result};
+use wasmtime::Module;
+
+use crate::{AbiFeatures, AbiQuery};
+
+/// The set of host ABI modules that a process may import.
+///
+/// `hearth::abi` is always allowed so that guests can query what they may
+/// use.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AbiPolicy {
+    modules: BTreeSet<String>,
+}
+
+impl AbiPolicy {
+    /// Creates a policy that allows the given modules.
+    pub fn new(modules: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            modules: modules.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// Creates a policy that allows every module that provides a feature.
+    pub fn all(features: &AbiFeatures) -> Self {
+        Self::new(features.iter())
+    }
+
+    /// Creates the baseline policy from [WasmConfig::allow].
+    ///
+    /// Nothing but `hearth::abi` is allowed if `allow` is unset, and `"*"`
+    /// allows every module. Fails if a listed module doesn't exist.
+    pub fn from_config(allow: Option<&[String]>, features: &AbiFeatures) -> Result<Self> {
+        let Some(allow) = allow else {
+            return Ok(Self::default());
+        };
+
+        if allow.iter().any(|module| module == "*") {
+            return Ok(Self::all(features));
+        }
+
+        for module in allow {
+            if !features.contains(module) {
+                bail!("unknown ABI module {:?}", module);
+            }
+        }
+
+        Ok(Self::new(allow))
+    }
+
+    /// Resolves the policy of a new process.
+    ///
+    /// `requested` is the spawn request's list of allowed modules, if it has
+    /// one. Otherwise, the process gets the modules of `baseline` that
+    /// `ceiling` allows. Fails if a requested module doesn't exist or isn't
+    /// allowed by `ceiling`.
+    pub fn resolve(
+        requested: Option<&[String]>,
+        baseline: &AbiPolicy,
+        ceiling: &AbiPolicy,
+        features: &AbiFeatures,
+    ) -> Result<Self> {
+        let Some(requested) = requested else {
+            return Ok(baseline.intersection(ceiling));
+        };
+
+        for module in requested {
+            if !features.contains(module) {
+                bail!("unknown ABI module {:?}", module);
+            }
+
+            if !ceiling.allows(module) {
+                bail!("ABI module {:?} can't be granted by this spawner", module);
+            }
+        }
+
+        Ok(Self::new(requested))
+    }
+
+    /// Tests if this policy allows a module.
+    pub fn allows(&self, module: &str) -> bool {
+        module == AbiQuery::MODULE || self.modules.contains(module)
+    }
+
+    /// Creates a policy of the modules that both policies allow.
+    pub fn intersection(&self, other: &AbiPolicy) -> Self {
+        Self::new(self.modules.intersection(&other.modules).cloned())
+    }
+
+    /// Narrows a set of features to the modules that this policy allows.
+    pub fn restrict(&self, features: &AbiFeatures) -> AbiFeatures {
+        let mut restricted = AbiFeatures::default();
+
+        for feature in features.iter().filter(|feature| self.allows(feature)) {
+            restricted.insert(feature);
+        }
+
+        restricted
+    }
+
+    /// Fails if a module imports anything from a module that this policy
+    /// denies.
+    pub fn check(&self, module: &Module) -> Result<()> {
+        for import in module.imports() {
+            if !self.allows(import.module()) {
+                bail!(
+                    "module imports {:?} from {}, which its ABI policy denies",
+                    import.name(),
+                    import.module()
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn features() -> AbiFeatures {
+        let mut features = AbiFeatures::default();
+
+        for module in [
+            "hearth::abi",
+            "hearth::log",
+            "hearth::mailbox",
+            "hearth::group",
+        ] {
+            features.insert(module);
+        }
+
+        features
+    }
+
+    #[test]
+    fn requests_are_limited_by_ceiling() {
+        let features = features();
+        let baseline = AbiPolicy::all(&features);
+        let ceiling = AbiPolicy::new(["hearth::log", "hearth::mailbox"]);
+        let resolve = |requested: &[&str]| {
+            let requested: Vec<_> = requested.iter().map(|module| module.to_string()).collect();
+            AbiPolicy::resolve(Some(&requested), &baseline, &ceiling, &features)
+        };
+
+        assert_eq!(
+            resolve(&["hearth::log"]).unwrap(),
+            AbiPolicy::new(["hearth::log"])
+        );
+
+        assert!(resolve(&["hearth::group"]).is_err());
+        assert!(resolve(&["hearth::nonexistent"]).is_err());
+
+        let default = AbiPolicy::resolve(None, &baseline, &ceiling, &features).unwrap();
+        assert_eq!(default, ceiling);
+    }
+
+    #[test]
+    fn config_is_deny_by_default() {
+        let features = features();
+        let from_config = |allow: Option<&[&str]>| {
+            let allow: Option<Vec<_>> =
+                allow.map(|allow| allow.iter().map(|module| module.to_string()).collect());
+            AbiPolicy::from_config(allow.as_deref(), &features)
+        };
+
+        assert_eq!(from_config(None).unwrap(), AbiPolicy::default());
+
+        assert_eq!(
+            from_config(Some(&["*"])).unwrap(),
+            AbiPolicy::all(&features)
+        );
+
+        assert_eq!(
+            from_config(Some(&["hearth::log"])).unwrap(),
+            AbiPolicy::new(["hearth::log"])
+        );
+
+        let err = from_config(Some(&["hearth::logs"])).unwrap_err();
+        assert!(err.to_string().contains("hearth::logs"), "{}", err);
+    }
+
+    #[test]
+    fn abi_queries_are_always_allowed() {
+        let policy = AbiPolicy::default();
+        assert!(policy.allows("hearth::abi"));
+        assert!(!policy.allows("hearth::log"));
+
+        let restricted = policy.restrict(&features());
+        assert!(restricted.contains("hearth::abi"));
+        assert!(!restricted.contains("hearth::mailbox"));
+    }
+}
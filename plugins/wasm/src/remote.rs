@@ -0,0 +1,486 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Services for spawning Wasm processes across peers.
+
+use std::time::Duration;
+
+use hearth_runtime::anyhow::{anyhow, bail, Context, Error, Result};
+use hearth_runtime::flue::{CapabilityRef, Permissions};
+use hearth_runtime::lump::bytes::Bytes;
+use hearth_runtime::process::{Process, ProcessMetadata};
+use hearth_runtime::runtime::Runtime;
+use hearth_runtime::{async_trait, hearth_schema};
+use hearth_runtime::{cargo_process_metadata, utils::*};
+use hearth_schema::lump::{LumpMetadata, LumpMismatch, LumpOrigin};
+use hearth_schema::registry::{RegistryRequest, RegistryResponse};
+use hearth_schema::wasm::*;
+use hearth_schema::LumpId;
+use tracing::{debug, warn};
+
+use crate::WasmProcessSpawner;
+
+/// The number of times a failed chunk request is retried before giving up.
+const CHUNK_RETRIES: usize = 3;
+
+/// How long to wait before the first retry of a failed chunk request. Each
+/// further retry waits this much longer.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+/// Looks up a service by name in the runtime's registry.
+pub(crate) async fn get_service<'a>(
+    runtime: &Runtime,
+    process: &'a Process,
+    name: &str,
+) -> Result<CapabilityRef<'a>> {
+    let registry = runtime
+        .registry
+        .borrow_parent()
+        .export_to(Permissions::SEND, process.borrow_table())?;
+
+    let registry = RequestResponse::<RegistryRequest, RegistryResponse>::new(process, registry);
+
+    let request = RegistryRequest::Get {
+        name: name.to_string(),
+    };
+
+    let (response, mut caps) = registry.request(&request, &[]).await?;
+
+    match response {
+        RegistryResponse::Get(true) if !caps.is_empty() => Ok(caps.remove(0)),
+        RegistryResponse::Get(_) => bail!("service {:?} is unavailable", name),
+        other => bail!("unexpected registry response: {:?}", other),
+    }
+}
+
+/// Requests a single chunk of a lump from a lump source.
+async fn request_chunk(
+    process: &Process,
+    source: &CapabilityRef<'_>,
+    lump: LumpId,
+    offset: u64,
+) -> Result<LumpChunk> {
+    let source =
+        RequestResponse::<LumpChunkRequest, LumpChunkResponse>::new(process, source.clone());
+
+    let request = LumpChunkRequest {
+        lump,
+        offset,
+        len: LUMP_CHUNK_SIZE,
+    };
+
+    let (response, _caps) = source.request(&request, &[]).await?;
+    response.map_err(|err| anyhow!("lump source error: {}", err))
+}
+
+/// Fetches a whole lump from a lump source in chunks, along with its
+/// metadata.
+///
+/// Failed chunks are retried from the last received offset after a delay, so
+/// an interrupted transfer doesn't need to start over. Lumps larger than
+/// [MAX_LUMP_SIZE] are refused before any more than their first chunk is
+/// received. The data isn't verified against `lump`; see
+/// [fetch_verified_lump].
+pub async fn fetch_lump(
+    process: &Process,
+    source: &CapabilityRef<'_>,
+    lump: LumpId,
+) -> Result<(Bytes, LumpMetadata)> {
+    let mut data = Vec::new();
+    let mut metadata = LumpMetadata::default();
+    let mut size = None;
+    let mut failures = 0;
+
+    while size.map_or(true, |size| (data.len() as u64) < size) {
+        let offset = data.len() as u64;
+        let chunk = match request_chunk(process, source, lump, offset).await {
+            Ok(chunk) => chunk,
+            Err(err) if failures < CHUNK_RETRIES => {
+                failures += 1;
+                warn!("retrying lump {} chunk at {}: {:?}", lump, offset, err);
+                tokio::time::sleep(RETRY_DELAY * failures as u32).await;
+                continue;
+            }
+            Err(err) => return Err(err.context(format!("fetching lump {}", lump))),
+        };
+
+        if chunk.size > MAX_LUMP_SIZE {
+            bail!(
+                "lump {} is {} bytes, over the limit of {}",
+                lump,
+                chunk.size,
+                MAX_LUMP_SIZE
+            );
+        }
+
+        if *size.get_or_insert(chunk.size) != chunk.size {
+            bail!("lump {} changed size during transfer", lump);
+        }
+
+        if chunk.data.is_empty() && offset < chunk.size {
+            bail!("lump source sent an empty chunk at {}", offset);
+        }
+
+        if offset + chunk.data.len() as u64 > chunk.size {
+            bail!("lump source sent more data than the lump's size");
+        }
+
+        if offset == 0 {
+            metadata = chunk.metadata;
+        }
+
+        failures = 0;
+        data.extend_from_slice(&chunk.data);
+    }
+
+    Ok((data.into(), metadata))
+}
+
+/// Fetches a lump from a lump source and adds it to the local lump store.
+///
+/// Fails with a [LumpMismatch] if the fetched data doesn't have the
+/// requested ID, in which case nothing is stored.
+pub async fn fetch_verified_lump(
+    runtime: &Runtime,
+    process: &Process,
+    source: &CapabilityRef<'_>,
+    lump: LumpId,
+) -> Result<()> {
+    let (data, mut metadata) = fetch_lump(process, source, lump).await?;
+    metadata.origin = Some(LumpOrigin::Remote);
+
+    runtime
+        .lump_store
+        .add_verified_lump(lump, data, metadata)
+        .await?;
+
+    Ok(())
+}
+
+/// Serves lumps from the local lump store in chunks. Accepts
+/// [LumpChunkRequest].
+pub struct LumpSource;
+
+#[async_trait]
+impl RequestResponseProcess for LumpSource {
+    type Request = LumpChunkRequest;
+    type Response = LumpChunkResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, LumpChunkRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let lumps = &request.runtime.lump_store;
+        let id = &request.data.lump;
+        let lump = lumps.get_lump(id).await;
+        let info = lumps.get_lump_info(id).await;
+
+        let response: LumpChunkResponse = match lump.zip(info) {
+            None => Err(format!("lump {} not found", request.data.lump)),
+            Some((lump, info)) => {
+                let size = lump.len() as u64;
+                let start = request.data.offset.min(size) as usize;
+                let len = request.data.len.min(LUMP_CHUNK_SIZE) as usize;
+                let end = (start + len).min(lump.len());
+
+                Ok(LumpChunk {
+                    size,
+                    data: lump[start..end].to_vec(),
+                    metadata: info.metadata,
+                })
+            }
+        };
+
+        response.into()
+    }
+}
+
+impl ServiceRunner for LumpSource {
+    const NAME: &'static str = LUMP_SOURCE_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Serves local lumps in chunks. Accepts LumpChunkRequest.".to_string());
+
+        meta
+    }
+}
+
+/// Spawns Wasm processes on behalf of remote peers, fetching their module
+/// lumps from the requester if needed. Accepts [RemoteSpawnInfo].
+pub struct RemoteSpawner {
+    pub(crate) spawner: WasmProcessSpawner,
+}
+
+#[async_trait]
+impl RequestResponseProcess for RemoteSpawner {
+    type Request = RemoteSpawnInfo;
+    type Response = RemoteSpawnResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, RemoteSpawnInfo>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        match self.spawn(request).await {
+            Ok(caps) => ResponseInfo { data: Ok(()), caps },
+            Err(err) => {
+                debug!("remote spawn error: {:?}", err);
+
+                // tell the requester if it served a corrupted lump
+                match err.downcast_ref::<LumpMismatch>() {
+                    Some(mismatch) => RemoteSpawnError::LumpMismatch(*mismatch).into(),
+                    None => RemoteSpawnError::Failed(format!("{:#}", err)).into(),
+                }
+            }
+        }
+    }
+}
+
+impl ServiceRunner for RemoteSpawner {
+    const NAME: &'static str = REMOTE_SPAWNER_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description =
+            Some("Spawns Wasm processes for remote peers. Accepts RemoteSpawnInfo.".to_string());
+
+        meta
+    }
+}
+
+impl RemoteSpawner {
+    async fn spawn<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, RemoteSpawnInfo>,
+    ) -> Result<Vec<CapabilityRef<'a>>> {
+        let runtime = request.runtime;
+        let process = request.process;
+        let info = &request.data;
+
+        // forward requests for other peers, attaching our own lump source
+        if let Some(peer) = info.peer.as_ref() {
+            let remote = get_service(runtime, process, peer).await?;
+            let source = get_service(runtime, process, LUMP_SOURCE_SERVICE).await?;
+
+            let forwarded = RemoteSpawnInfo {
+                peer: None,
+                spawn: info.spawn.clone(),
+                lumps: info.lumps.clone(),
+            };
+
+            let remote =
+                RequestResponse::<RemoteSpawnInfo, RemoteSpawnResponse>::new(process, remote);
+
+            let mut caps = vec![&source];
+            caps.extend(request.cap_args.get(1..).unwrap_or_default().iter());
+            let (response, caps) = remote.request(&forwarded, &caps).await?;
+            response.map_err(|err| match err {
+                // pass mismatches on so that the original requester sees them
+                RemoteSpawnError::LumpMismatch(mismatch) => Error::new(mismatch),
+                err => anyhow!("peer {:?} failed to spawn: {}", peer, err),
+            })?;
+
+            if caps.is_empty() {
+                bail!("peer {:?} did not return the spawned process", peer);
+            }
+
+            return Ok(caps);
+        }
+
+        for lump in std::iter::once(&info.spawn.lump).chain(info.lumps.iter()) {
+            let lump = *lump;
+            if runtime.lump_store.get_lump(&lump).await.is_some() {
+                continue;
+            }
+
+            let source = request
+                .cap_args
+                .first()
+                .context("lump is missing and no lump source was given")?;
+
+            debug!("fetching lump {} for remote spawn", lump);
+            fetch_verified_lump(runtime, process, source, lump).await?;
+        }
+
+        let cap_args = request.cap_args.get(1..).unwrap_or_default();
+        let spawned = self
+            .spawner
+            .spawn_lump(runtime, process, &info.spawn, cap_args)
+            .await?;
+
+        Ok(vec![spawned.process, spawned.endpoint])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{Arc, Mutex};
+
+    use hearth_runtime::lump::lump_id;
+    use hearth_runtime::testing::TestRuntimeBuilder;
+    use tokio::time::Instant;
+
+    /// Serves a lump like [LumpSource] but corrupts its second chunk.
+    struct CorruptSource(Vec<u8>);
+
+    #[async_trait]
+    impl RequestResponseProcess for CorruptSource {
+        type Request = LumpChunkRequest;
+        type Response = LumpChunkResponse;
+
+        async fn on_request<'a>(
+            &'a mut self,
+            request: &mut RequestInfo<'a, LumpChunkRequest>,
+        ) -> ResponseInfo<'a, Self::Response> {
+            let start = request.data.offset as usize;
+            let end = (start + LUMP_CHUNK_SIZE as usize).min(self.0.len());
+            let mut data = self.0[start..end].to_vec();
+
+            if start > 0 {
+                data[0] ^= 0xff;
+            }
+
+            Ok(LumpChunk {
+                size: self.0.len() as u64,
+                data,
+                metadata: Default::default(),
+            })
+            .into()
+        }
+    }
+
+    /// Serves a lump like [LumpSource] but fails the first request for
+    /// every chunk, recording when each request arrived.
+    struct FlakySource {
+        data: Vec<u8>,
+        requests: Arc<Mutex<Vec<(u64, Instant)>>>,
+    }
+
+    #[async_trait]
+    impl RequestResponseProcess for FlakySource {
+        type Request = LumpChunkRequest;
+        type Response = LumpChunkResponse;
+
+        async fn on_request<'a>(
+            &'a mut self,
+            request: &mut RequestInfo<'a, LumpChunkRequest>,
+        ) -> ResponseInfo<'a, Self::Response> {
+            let offset = request.data.offset;
+            let mut requests = self.requests.lock().unwrap();
+            let first = !requests.iter().any(|(other, _)| *other == offset);
+            requests.push((offset, Instant::now()));
+
+            if first {
+                return Err("try again".to_string()).into();
+            }
+
+            let start = offset as usize;
+            let end = (start + LUMP_CHUNK_SIZE as usize).min(self.data.len());
+
+            Ok(LumpChunk {
+                size: self.data.len() as u64,
+                data: self.data[start..end].to_vec(),
+                metadata: Default::default(),
+            })
+            .into()
+        }
+    }
+
+    /// Claims to serve a lump larger than [MAX_LUMP_SIZE].
+    struct HugeSource;
+
+    #[async_trait]
+    impl RequestResponseProcess for HugeSource {
+        type Request = LumpChunkRequest;
+        type Response = LumpChunkResponse;
+
+        async fn on_request<'a>(
+            &'a mut self,
+            _request: &mut RequestInfo<'a, LumpChunkRequest>,
+        ) -> ResponseInfo<'a, Self::Response> {
+            Ok(LumpChunk {
+                size: MAX_LUMP_SIZE + 1,
+                data: vec![0; LUMP_CHUNK_SIZE as usize],
+                metadata: Default::default(),
+            })
+            .into()
+        }
+    }
+
+    #[test]
+    fn failed_chunks_are_retried_after_delay() {
+        let data: Vec<u8> = (0..LUMP_CHUNK_SIZE * 2).map(|i| i as u8).collect();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let source = FlakySource {
+            data: data.clone(),
+            requests: requests.clone(),
+        };
+
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_service("FlakySource", source);
+        let runtime = builder.build();
+        let source = runtime.get_service("FlakySource").unwrap();
+
+        let lump = lump_id(&data);
+        let fetch = fetch_lump(runtime.process(), &source, lump);
+        let (fetched, _) = runtime.block_on(fetch).unwrap();
+        assert_eq!(&fetched[..], &data[..]);
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+
+        for retry in requests.chunks(2) {
+            assert_eq!(retry[0].0, retry[1].0);
+            assert!(retry[1].1 - retry[0].1 >= RETRY_DELAY);
+        }
+    }
+
+    #[test]
+    fn oversized_lumps_are_refused() {
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_service("HugeSource", HugeSource);
+        let runtime = builder.build();
+        let source = runtime.get_service("HugeSource").unwrap();
+
+        let lump = lump_id(b"");
+        let result = runtime.block_on(fetch_lump(runtime.process(), &source, lump));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn corrupted_chunks_are_rejected() {
+        let data: Vec<u8> = (0..LUMP_CHUNK_SIZE * 2).map(|i| i as u8).collect();
+        let lump = lump_id(&data);
+
+        let mut builder = TestRuntimeBuilder::new();
+        builder.add_service("CorruptSource", CorruptSource(data));
+        let runtime = builder.build();
+        let source = runtime.get_service("CorruptSource").unwrap();
+
+        let inner = runtime.runtime();
+        let result = runtime.block_on(fetch_verified_lump(inner, runtime.process(), &source, lump));
+
+        let err = result.unwrap_err();
+        let mismatch = err.downcast_ref::<LumpMismatch>().unwrap();
+        assert_eq!(mismatch.expected, lump);
+        assert!(runtime.block_on(inner.lump_store.get_lump(&lump)).is_none());
+    }
+}
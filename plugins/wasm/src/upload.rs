@@ -0,0 +1,283 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Chunked uploads of lumps into the local lump store.
+
+use std::collections::BTreeMap;
+
+use hearth_runtime::process::ProcessMetadata;
+use hearth_runtime::{async_trait, hearth_schema};
+use hearth_runtime::{cargo_process_metadata, utils::*};
+use hearth_schema::lump::{LumpMetadata, LumpOrigin};
+use hearth_schema::wasm::*;
+use hearth_schema::LumpId;
+use tracing::debug;
+
+/// The maximum number of unfinished uploads. Starting another upload past
+/// this drops the oldest one.
+pub const MAX_PENDING_UPLOADS: usize = 16;
+
+/// An upload that hasn't been finished yet.
+pub struct Upload {
+    /// The total size of the upload.
+    pub size: u64,
+
+    /// The data received so far.
+    pub data: Vec<u8>,
+
+    /// The ID that the uploaded data must have, if any.
+    pub expected: Option<LumpId>,
+
+    /// The metadata to store with the lump.
+    pub metadata: LumpMetadata,
+}
+
+/// The uploads that have been started but not finished, by ID.
+#[derive(Default)]
+pub struct PendingUploads {
+    uploads: BTreeMap<u64, Upload>,
+    next_id: u64,
+}
+
+impl PendingUploads {
+    /// Starts a new upload of `size` bytes and returns its ID.
+    ///
+    /// Fails if `size` is over [MAX_LUMP_SIZE].
+    pub fn begin(
+        &mut self,
+        size: u64,
+        expected: Option<LumpId>,
+        metadata: LumpMetadata,
+    ) -> Result<u64, String> {
+        if size > MAX_LUMP_SIZE {
+            return Err(format!("lumps may be at most {} bytes", MAX_LUMP_SIZE));
+        }
+
+        // abandoned uploads would otherwise be kept forever
+        if self.uploads.len() >= MAX_PENDING_UPLOADS {
+            self.uploads.pop_first();
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let upload = Upload {
+            size,
+            data: Vec::new(),
+            expected,
+            metadata,
+        };
+
+        self.uploads.insert(id, upload);
+        Ok(id)
+    }
+
+    /// Appends a chunk to an upload. Returns the number of bytes received
+    /// so far.
+    pub fn append(&mut self, upload: u64, offset: u64, data: &[u8]) -> Result<u64, String> {
+        let pending = self.get(upload)?;
+
+        if data.len() > LUMP_CHUNK_SIZE as usize {
+            return Err(format!("chunks may be at most {} bytes", LUMP_CHUNK_SIZE));
+        }
+
+        let received = pending.data.len() as u64;
+        if offset != received {
+            return Err(format!(
+                "expected a chunk at offset {}, got {}",
+                received, offset
+            ));
+        }
+
+        if received + data.len() as u64 > pending.size {
+            return Err(format!("upload exceeds its size of {}", pending.size));
+        }
+
+        pending.data.extend_from_slice(data);
+        Ok(pending.data.len() as u64)
+    }
+
+    /// Ends a complete upload and returns it.
+    ///
+    /// Incomplete uploads are kept so that they can still be finished.
+    pub fn finish(&mut self, upload: u64) -> Result<Upload, String> {
+        let pending = self.get(upload)?;
+        let received = pending.data.len() as u64;
+
+        if received != pending.size {
+            return Err(format!(
+                "upload is incomplete: received {} of {} bytes",
+                received, pending.size
+            ));
+        }
+
+        Ok(self.uploads.remove(&upload).unwrap())
+    }
+
+    fn get(&mut self, upload: u64) -> Result<&mut Upload, String> {
+        self.uploads
+            .get_mut(&upload)
+            .ok_or_else(|| format!("no pending upload with ID {}", upload))
+    }
+}
+
+/// Adds lumps to the local lump store from chunked uploads. Accepts
+/// [LumpUploadRequest].
+#[derive(Default)]
+pub struct LumpUploader {
+    uploads: PendingUploads,
+}
+
+#[async_trait]
+impl RequestResponseProcess for LumpUploader {
+    type Request = LumpUploadRequest;
+    type Response = LumpUploadResponse;
+
+    async fn on_request<'a>(
+        &'a mut self,
+        request: &mut RequestInfo<'a, LumpUploadRequest>,
+    ) -> ResponseInfo<'a, Self::Response> {
+        let response = match &request.data {
+            LumpUploadRequest::Begin {
+                size,
+                expected,
+                metadata,
+            } => match self.uploads.begin(*size, *expected, metadata.clone()) {
+                Ok(upload) => {
+                    debug!("started upload {} of {} bytes", upload, size);
+                    Ok(LumpUploadSuccess::Started { upload })
+                }
+                Err(err) => Err(LumpUploadError::Invalid(err)),
+            },
+            LumpUploadRequest::Chunk {
+                upload,
+                offset,
+                data,
+            } => self
+                .uploads
+                .append(*upload, *offset, data)
+                .map(|received| LumpUploadSuccess::Received { received })
+                .map_err(LumpUploadError::Invalid),
+            LumpUploadRequest::Finish { upload } => match self.uploads.finish(*upload) {
+                Ok(finished) => self.store(request, *upload, finished).await,
+                Err(err) => Err(LumpUploadError::Invalid(err)),
+            },
+        };
+
+        response.into()
+    }
+}
+
+impl LumpUploader {
+    /// Adds a finished upload to the lump store, verifying its ID if one
+    /// was expected.
+    async fn store(
+        &self,
+        request: &RequestInfo<'_, LumpUploadRequest>,
+        upload: u64,
+        finished: Upload,
+    ) -> LumpUploadResponse {
+        let lumps = &request.runtime.lump_store;
+        let mut metadata = finished.metadata;
+        // clients can't vouch for where their data came from
+        metadata.origin = Some(LumpOrigin::Upload);
+        let data = finished.data.into();
+
+        let lump = match finished.expected {
+            Some(expected) => {
+                lumps
+                    .add_verified_lump(expected, data, metadata)
+                    .await
+                    .map_err(LumpUploadError::Mismatch)?;
+
+                expected
+            }
+            None => lumps.add_lump_with(data, metadata).await,
+        };
+
+        debug!("finished upload {} as lump {}", upload, lump);
+        Ok(LumpUploadSuccess::Finished(lump))
+    }
+}
+
+impl ServiceRunner for LumpUploader {
+    const NAME: &'static str = LUMP_UPLOADER_SERVICE;
+
+    fn get_process_metadata() -> ProcessMetadata {
+        let mut meta = cargo_process_metadata!();
+        meta.description = Some(
+            "Adds lumps to the local lump store from chunked uploads. Accepts LumpUploadRequest."
+                .to_string(),
+        );
+
+        meta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks_are_appended_in_order() {
+        let mut uploads = PendingUploads::default();
+        let upload = uploads.begin(6, None, Default::default()).unwrap();
+
+        assert_eq!(uploads.append(upload, 0, b"abc"), Ok(3));
+        assert!(uploads.append(upload, 0, b"abc").is_err());
+        assert!(uploads.finish(upload).is_err());
+        assert_eq!(uploads.append(upload, 3, b"def"), Ok(6));
+        let finished = uploads.finish(upload).map(|upload| upload.data);
+        assert_eq!(finished, Ok(b"abcdef".to_vec()));
+        assert!(uploads.finish(upload).is_err());
+    }
+
+    #[test]
+    fn uploads_cannot_exceed_their_size() {
+        let mut uploads = PendingUploads::default();
+        let upload = uploads.begin(2, None, Default::default()).unwrap();
+        assert!(uploads.append(upload, 0, b"abc").is_err());
+
+        let upload = uploads.begin(MAX_LUMP_SIZE, None, Default::default());
+        let upload = upload.unwrap();
+        let chunk = vec![0; LUMP_CHUNK_SIZE as usize + 1];
+        assert!(uploads.append(upload, 0, &chunk).is_err());
+    }
+
+    #[test]
+    fn oversized_uploads_are_rejected() {
+        let mut uploads = PendingUploads::default();
+        let size = MAX_LUMP_SIZE + 1;
+        assert!(uploads.begin(size, None, Default::default()).is_err());
+        assert!(uploads.begin(u64::MAX, None, Default::default()).is_err());
+    }
+
+    #[test]
+    fn oldest_uploads_are_dropped() {
+        let mut uploads = PendingUploads::default();
+        let first = uploads.begin(0, None, Default::default()).unwrap();
+
+        for _ in 0..MAX_PENDING_UPLOADS {
+            uploads.begin(0, None, Default::default()).unwrap();
+        }
+
+        assert!(uploads.finish(first).is_err());
+        let second = uploads.finish(first + 1).map(|upload| upload.data);
+        assert_eq!(second, Ok(vec![]));
+    }
+}
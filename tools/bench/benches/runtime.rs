@@ -0,0 +1,213 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmarks for capability tables, local messaging, and the registry.
+
+use std::time::Instant;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use hearth_bench::*;
+use hearth_runtime::{
+    flue::{MailboxGroup, Permissions, PostOffice, Table, TableSignal},
+    hearth_schema::registry::RegistryRequest,
+    process::Process,
+    tokio,
+};
+
+/// Creates and frees a capability on a table shared by many threads.
+///
+/// Each thread has its own mailbox group, so the only shared state is the
+/// table itself.
+fn table_churn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("table_churn");
+    group.throughput(Throughput::Elements(1));
+
+    for threads in CONTENTION {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                let table = Table::new(PostOffice::new());
+
+                b.iter_custom(|iters| {
+                    let per_thread = (iters / threads as u64).max(1);
+                    let start = Instant::now();
+
+                    std::thread::scope(|scope| {
+                        for _ in 0..threads {
+                            scope.spawn(|| {
+                                let group = MailboxGroup::new(&table);
+                                for _ in 0..per_thread {
+                                    let mailbox = group.create_mailbox().unwrap();
+                                    black_box(mailbox.export(Permissions::SEND).unwrap());
+                                }
+                            });
+                        }
+                    });
+
+                    start.elapsed()
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+/// Clones and frees capabilities that already exist in a table.
+fn capability(c: &mut Criterion) {
+    let table = Table::new(PostOffice::new());
+    let group = MailboxGroup::new(&table);
+    let mailbox = group.create_mailbox().unwrap();
+    let handle = mailbox.export(Permissions::SEND).unwrap().into_handle();
+    let cap = mailbox
+        .export(Permissions::SEND | Permissions::KILL)
+        .unwrap();
+
+    let mut group = c.benchmark_group("capability");
+
+    group.bench_function("inc_dec_ref", |b| {
+        b.iter(|| {
+            table.inc_ref(handle).unwrap();
+            table.dec_ref(handle).unwrap();
+        })
+    });
+
+    group.bench_function("demote", |b| {
+        b.iter(|| black_box(cap.demote(Permissions::SEND).unwrap()))
+    });
+
+    group.finish();
+    table.dec_ref(handle).unwrap();
+}
+
+/// Sends messages of different sizes from one process to another.
+///
+/// Each message is received before the next is sent, so this measures the
+/// whole trip through the post office.
+fn send(c: &mut Criterion) {
+    let bench = BenchRuntime::new(2);
+    let sender = bench.spawn("sender");
+    let receiver = bench.spawn("receiver");
+    let mailbox = receiver.borrow_group().create_mailbox().unwrap();
+    let cap = mailbox
+        .export_to(Permissions::SEND, sender.borrow_table())
+        .unwrap();
+
+    let mut group = c.benchmark_group("send");
+
+    for size in MESSAGE_SIZES {
+        let data = message(size);
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &data, |b, data| {
+            b.iter_custom(|iters| {
+                bench.tokio.block_on(async {
+                    let start = Instant::now();
+
+                    for _ in 0..iters {
+                        cap.send(data, &[]).await.unwrap();
+                        mailbox.recv(|_| ()).await.unwrap();
+                    }
+
+                    start.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Looks up a service in the registry from many tasks at once.
+fn registry_get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("registry_get");
+    group.throughput(Throughput::Elements(1));
+
+    for tasks in CONTENTION {
+        let bench = BenchRuntime::new(tasks);
+
+        group.bench_with_input(BenchmarkId::from_parameter(tasks), &tasks, |b, &tasks| {
+            b.iter_custom(|iters| {
+                let per_task = (iters / tasks as u64).max(1);
+
+                let clients: Vec<_> = (0..tasks).map(|_| bench.spawn("registry client")).collect();
+
+                bench.tokio.block_on(async {
+                    let start = Instant::now();
+
+                    let handles: Vec<_> = clients
+                        .into_iter()
+                        .map(|client| {
+                            let registry = bench.runtime.registry.clone();
+                            tokio::spawn(async move {
+                                get_repeatedly(&registry, &client, per_task).await
+                            })
+                        })
+                        .collect();
+
+                    for handle in handles {
+                        handle.await.unwrap();
+                    }
+
+                    start.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+/// Gets [IDLE_SERVICE] from the registry `count` times on behalf of `client`.
+async fn get_repeatedly(registry: &Process, client: &Process, count: u64) {
+    let table = client.borrow_table();
+    let registry = registry
+        .borrow_parent()
+        .export_to(Permissions::SEND, table)
+        .unwrap();
+
+    let reply = client.borrow_group().create_mailbox().unwrap();
+    let reply_cap = reply.export(Permissions::SEND).unwrap();
+
+    let request = RegistryRequest::Get {
+        name: IDLE_SERVICE.to_string(),
+    };
+
+    let request = serde_json::to_vec(&request).unwrap();
+
+    for _ in 0..count {
+        registry.send(&request, &[&reply_cap]).await.unwrap();
+
+        let caps = reply
+            .recv(|signal| match signal {
+                TableSignal::Message { caps, .. } => caps,
+                other => panic!("expected registry response, got {:?}", other),
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(caps.len(), 1, "{} is not registered", IDLE_SERVICE);
+
+        for cap in caps {
+            table.dec_ref(cap).unwrap();
+        }
+    }
+}
+
+criterion_group!(benches, table_churn, capability, send, registry_get);
+criterion_main!(benches);
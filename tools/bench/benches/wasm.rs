@@ -0,0 +1,62 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Benchmarks for spawning Wasm processes.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use hearth_bench::TRIVIAL_MODULE;
+use hearth_runtime::testing::TestRuntimeBuilder;
+use hearth_wasm::WasmPlugin;
+
+/// Spawns a trivial module end to end.
+///
+/// This covers the whole spawn request: storing the lump, loading and
+/// linking the module, instantiating it, and spawning its process.
+fn spawn(c: &mut Criterion) {
+    let mut builder = TestRuntimeBuilder::new();
+    builder.add_plugin(WasmPlugin::default());
+    let runtime = builder.build();
+
+    // warm up with one spawn so that one-time setup isn't measured
+    runtime.spawn_wasm(TRIVIAL_MODULE);
+
+    c.bench_function("wasm_spawn", |b| {
+        b.iter(|| runtime.spawn_wasm(TRIVIAL_MODULE))
+    });
+}
+
+/// Spawns a trivial module on a fresh runtime.
+///
+/// Unlike [spawn], every iteration has to compile and link the module, so
+/// the difference between the two is what the linked module cache saves.
+fn spawn_cold(c: &mut Criterion) {
+    c.bench_function("wasm_spawn_cold", |b| {
+        b.iter_batched_ref(
+            || {
+                let mut builder = TestRuntimeBuilder::new();
+                builder.add_plugin(WasmPlugin::default());
+                builder.build()
+            },
+            |runtime| runtime.spawn_wasm(TRIVIAL_MODULE),
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+criterion_group!(benches, spawn, spawn_cold);
+criterion_main!(benches);
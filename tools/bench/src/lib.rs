@@ -0,0 +1,117 @@
+// Copyright (c) 2023 the Hearth contributors.
+// SPDX-License-Identifier: AGPL-3.0-or-later
+//
+// This file is part of Hearth.
+//
+// Hearth is free software: you can redistribute it and/or modify it under the
+// terms of the GNU Affero General Public License as published by the Free
+// Software Foundation, either version 3 of the License, or (at your option)
+// any later version.
+//
+// Hearth is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS
+// FOR A PARTICULAR PURPOSE. See the GNU Affero General Public License for more
+// details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with Hearth. If not, see <https://www.gnu.org/licenses/>.
+
+//! Shared setup for Hearth's benchmark suite.
+//!
+//! Every fixture here is deterministic so that two runs on the same machine
+//! are comparable. The benchmarks need neither a GPU nor a network and run
+//! with `cargo bench -p hearth-bench`.
+//!
+//! To compare a change against a baseline, save the baseline first, then
+//! measure the change against it:
+//!
+//! ```sh
+//! git checkout main
+//! cargo bench -p hearth-bench -- --save-baseline main
+//! git checkout my-branch
+//! cargo bench -p hearth-bench -- --baseline main
+//! ```
+
+use std::sync::Arc;
+
+use hearth_runtime::{
+    async_trait,
+    process::{Process, ProcessMetadata},
+    runtime::{Runtime, RuntimeBuilder, RuntimeConfig},
+    tokio,
+    utils::ProcessRunner,
+};
+
+/// The name of the idle service registered by [BenchRuntime].
+pub const IDLE_SERVICE: &str = "hearth.bench.Idle";
+
+/// The sizes of the messages sent by the messaging benchmarks.
+pub const MESSAGE_SIZES: [usize; 2] = [16, 64 * 1024];
+
+/// The numbers of threads or tasks contending in the contention benchmarks.
+pub const CONTENTION: [usize; 3] = [1, 4, 16];
+
+/// A Wasm module that exports an empty `run` function.
+pub const TRIVIAL_MODULE: &str = r#"(module (func (export "run")))"#;
+
+/// Creates a message of the given length with deterministic contents.
+pub fn message(len: usize) -> Vec<u8> {
+    (0..len).map(|idx| idx as u8).collect()
+}
+
+/// Creates process metadata with only a name.
+pub fn metadata(name: &str) -> ProcessMetadata {
+    let mut meta = ProcessMetadata::default();
+    meta.name = Some(name.to_string());
+    meta
+}
+
+/// A runtime on a multi-threaded Tokio runtime, for benchmarks that measure
+/// contention between threads.
+///
+/// The runtime has a single service named [IDLE_SERVICE] and no plugins.
+pub struct BenchRuntime {
+    pub runtime: Arc<Runtime>,
+
+    // dropped last so that the runtime may still spawn tasks on drop
+    pub tokio: tokio::runtime::Runtime,
+}
+
+impl BenchRuntime {
+    /// Starts a new runtime with the given number of Tokio worker threads.
+    pub fn new(workers: usize) -> Self {
+        let tokio = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(workers)
+            .enable_all()
+            .build()
+            .expect("failed to create benchmark Tokio runtime");
+
+        let mut builder = RuntimeBuilder::new(Default::default());
+
+        {
+            // spawning processes spawns tasks, so enter the Tokio runtime first
+            let _guard = tokio.enter();
+            builder.add_service(IDLE_SERVICE.to_string(), metadata(IDLE_SERVICE), Idle);
+        }
+
+        let runtime = tokio.block_on(builder.run(RuntimeConfig::default()));
+
+        Self { runtime, tokio }
+    }
+
+    /// Spawns a host process with the given name.
+    pub fn spawn(&self, name: &str) -> Process {
+        let _guard = self.tokio.enter();
+        self.runtime.process_factory.spawn(metadata(name))
+    }
+}
+
+/// A service that does nothing until it is killed.
+struct Idle;
+
+#[async_trait]
+impl ProcessRunner for Idle {
+    async fn run(self, _label: String, _runtime: Arc<Runtime>, ctx: &Process) {
+        while ctx.borrow_parent().recv(|_| ()).await.is_some() {}
+    }
+}